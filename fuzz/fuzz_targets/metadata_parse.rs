@@ -0,0 +1,8 @@
+//! Throw arbitrary bytes at the metadata parser.
+//!
+//! `cargo +nightly fuzz run metadata_parse` from the top of the tree.
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+	freebsd_rustdate::fuzz::metadata(data);
+});
@@ -40,6 +40,13 @@ pub(crate) fn workdir(conf: &Config) -> Result<(), String>
 	// libc::access is a thing, but it's a little gross...   assume we're
 	// root for now and worry about when we're not later.
 
+	if looks_like_sysroot(wd)
+	{
+		eprintln!("WARNING: workdir {} looks like a system root (it has \
+				bin/, etc/, and libexec/).  Are -b and -w swapped?",
+				wd.display());
+	}
+
 	Ok(())
 }
 
@@ -54,10 +61,58 @@ pub(crate) fn basedir(conf: &Config) -> Result<(), String>
 
 	// x-ref workdir() about perms
 
+	if looks_like_workdir(bd)
+	{
+		eprintln!("WARNING: basedir {} looks like a workdir (it has \
+				downloaded files in files/).  Are -b and -w swapped?",
+				bd.display());
+	}
+
 	Ok(())
 }
 
 
+/// Does a dir look like somebody's workdir?  That is, has a files/ with
+/// hash-named .gz's in it.  Only looks at the first few entries; a real
+/// one can have a whole lot.
+pub(crate) fn looks_like_workdir(dir: &std::path::Path) -> bool
+{
+	let ishash = |n: &str| n.strip_suffix(".gz")
+			.map_or(false, |h| h.len() == 64
+				&& h.chars().all(|c| c.is_ascii_hexdigit()));
+	let rd = match std::fs::read_dir(dir.join("files")) {
+		Ok(rd) => rd,
+		Err(_) => return false,
+	};
+	rd.take(32).filter_map(|e| e.ok())
+			.any(|e| e.file_name().to_str().map_or(false, ishash))
+}
+
+
+/// Does a dir look like a system root?
+pub(crate) fn looks_like_sysroot(dir: &std::path::Path) -> bool
+{
+	["bin", "etc", "libexec"].iter().all(|d| dir.join(d).is_dir())
+}
+
+
+/// Can we write in a dir?  The above mostly assume we're root; this is
+/// for when we want to actually know.
+pub(crate) fn writable(dir: &std::path::Path) -> Result<(), String>
+{
+	use std::os::unix::ffi::OsStrExt as _;
+	let cdir = std::ffi::CString::new(dir.as_os_str().as_bytes())
+			.map_err(|e| format!("Bad path {}: {e}", dir.display()))?;
+
+	// SAFETY: valid C string
+	match unsafe { libc::access(cdir.as_ptr(), libc::W_OK) } {
+		0 => Ok(()),
+		_ => Err(format!("Can't write to {}: {}", dir.display(),
+				std::io::Error::last_os_error())),
+	}
+}
+
+
 
 /*
  * There's a version check in freebsd-update.sh, that won't let you run
@@ -66,19 +121,98 @@ pub(crate) fn basedir(conf: &Config) -> Result<(), String>
  * boy is there a lot of uncertainty about details, but...  we'll do as
  * well as we can, by pretending from the kernel version we got from
  * freebsd-version.
+ *
+ * Binary updates only exist for releases (and the ALPHA/BETA/RC builds
+ * on the way to them); a -STABLE or -CURRENT box, or a custom build,
+ * has nothing on the server to update from.
  */
 pub(crate) fn version(vers: &crate::info::Version) -> Result<(), String>
 {
-	let rstr = r##"
-Cannot upgrade from a version that is not a release
-(including alpha, beta and release candidates).
-"##;
-	match vers.kernel.reltype.as_ref() {
-		// Common case
-		"RELEASE" => Ok(()),
-		s if s.starts_with("ALPHA") => Ok(()),
-		s if s.starts_with("BETA")  => Ok(()),
-		s if s.starts_with("RC")    => Ok(()),
-		_ => Err(rstr.to_string()),
+	let kv = vers.kernel();
+	match is_release(kv.reltype()) {
+		true  => Ok(()),
+		false => Err(format!("freebsd-rustdate can only update RELEASE \
+				systems; this system reports {kv}.  Binary updates only \
+				exist for releases (and their ALPHA, BETA, and RC builds); \
+				anything else needs to be updated from source.")),
+	}
+}
+
+/// Is a release type one there are binary updates for?
+fn is_release(reltype: &str) -> bool
+{
+	// Has to be a number (or nothing) after the pre-release ones;
+	// "RC1" is, "RCfoo" is somebody's own build.
+	let numbered = |pre: &str| reltype.strip_prefix(pre)
+			.map_or(false, |n| n.bytes().all(|b| b.is_ascii_digit()));
+	reltype == "RELEASE" || ["ALPHA", "BETA", "RC"].into_iter().any(numbered)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::fs::{create_dir_all, write};
+
+	#[test]
+	fn looks_like()
+	{
+		use super::{looks_like_workdir as wd, looks_like_sysroot as sr};
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+
+		// Nothing yet
+		assert!(!wd(dir));
+		assert!(!sr(dir));
+
+		// files/ with random stuff isn't enough
+		let files = dir.join("files");
+		create_dir_all(&files).unwrap();
+		write(files.join("notes.gz"), b"").unwrap();
+		write(files.join(format!("{}.txt", "a".repeat(64))), b"").unwrap();
+		assert!(!wd(dir));
+
+		// A hash-named one is
+		write(files.join(format!("{}.gz", "0123abcd".repeat(8))), b"")
+				.unwrap();
+		assert!(wd(dir));
+
+		// Need all of bin, etc, and libexec, as dirs
+		create_dir_all(dir.join("bin")).unwrap();
+		create_dir_all(dir.join("etc")).unwrap();
+		assert!(!sr(dir));
+		write(dir.join("libexec"), b"").unwrap();
+		assert!(!sr(dir));
+		std::fs::remove_file(dir.join("libexec")).unwrap();
+		create_dir_all(dir.join("libexec")).unwrap();
+		assert!(sr(dir));
+	}
+
+	#[test]
+	fn version()
+	{
+		let check = |s: &str| {
+			super::version(&crate::info::version::fake(s).unwrap())
+		};
+
+		for ok in ["14.2-RELEASE", "14.2-RELEASE-p3", "15.0-ALPHA3",
+				"14.3-BETA2-p1", "14.3-RC1"]
+		{ assert_eq!(check(ok), Ok(()), "{ok}"); }
+
+		for bad in ["14.2-STABLE", "15.0-CURRENT", "13.4-PRERELEASE",
+				"14.2-STABLE-mybuild", "14.3-RCfoo"]
+		{
+			let err = check(bad).unwrap_err();
+			let want = format!("freebsd-rustdate can only update RELEASE \
+					systems; this system reports {bad}.");
+			assert!(err.starts_with(&want), "{err}");
+		}
+
+		// It's the kernel that counts
+		let vers: crate::info::Version = "14.2-STABLE\n14.2-RELEASE-p1\n"
+				.parse().unwrap();
+		assert!(super::version(&vers).unwrap_err()
+				.contains("reports 14.2-STABLE."));
 	}
 }
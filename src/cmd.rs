@@ -3,11 +3,26 @@ pub(crate) mod fetch;
 pub(crate) mod cron;
 pub(crate) mod show_install;
 pub(crate) mod show_merges;
+pub(crate) mod progress;
 pub(crate) mod resolve_merges;
+pub(crate) mod export_pending;
+pub(crate) mod import_pending;
 pub(crate) mod upgrade;
 pub(crate) mod clean;
 pub(crate) mod install;
 pub(crate) mod check_fetch;
 pub(crate) mod check_sys;
+pub(crate) mod audit;
+pub(crate) mod why;
+pub(crate) mod patch_contents;
 pub(crate) mod extract;
+pub(crate) mod fix_links;
+pub(crate) mod config_check;
+pub(crate) mod doctor;
+pub(crate) mod completions;
+pub(crate) mod capabilities;
+pub(crate) mod merge_file;
 pub(crate) mod dump_metadata;
+pub(crate) mod hash_bench;
+pub(crate) mod io_bench;
+pub(crate) mod selftest;
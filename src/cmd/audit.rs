@@ -0,0 +1,305 @@
+//! $0 audit
+use crate::command::CmdArg;
+use crate::util::plural;
+use crate::util::hash::Sha256HashBuf;
+
+use std::io::{stdout, Write as _};
+
+
+/// One link in the chain we check.
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Check
+{
+	/// What we checked
+	name: &'static str,
+
+	/// Did it pass?
+	ok: bool,
+
+	/// Summary of what we found
+	detail: String,
+
+	/// Individual problems, if any
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	problems: Vec<String>,
+}
+
+/// The whole report
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Report
+{
+	/// When we ran
+	time: String,
+
+	/// What version the audited metadata is for
+	version: String,
+
+	/// Where we got the tag (or "cached")
+	tag_source: String,
+
+	/// Any warnings that don't make the audit fail
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	warnings: Vec<String>,
+
+	/// The individual checks
+	checks: Vec<Check>,
+
+	/// Overall result
+	pass: bool,
+}
+
+
+/// Command: $0 audit
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	// Setup dirs
+	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?;
+
+	// Split up
+	let CmdArg { clargs, config, version } = carg;
+
+	// Extract our own args
+	let args = match clargs.command {
+		crate::command::FrCmds::Audit(a) => a,
+		_ => unreachable!("I'm an audit, why does it think I'm not??"),
+	};
+
+	// Text output as we go, unless we're doing JSON.
+	let say = |s: &str| {
+		if !args.json { print!("{s}"); stdout().flush().ok(); }
+	};
+
+	// What have we got to audit?
+	let mut state = match rtdirs.state_load_raw()? {
+		Some(s) => s,
+		None => anyhow::bail!("No state to load; no fetch/upgrade has been run?"),
+	};
+	if let Some(n) = state.keyprint_check(&config.keyprint)
+	{ eprintln!("{n}"); }
+	let mdidx = match &state.meta_idx {
+		Some(m) => m,
+		None => anyhow::bail!("No saved metadata to audit; run fetch first."),
+	};
+	let vers = state.meta_idx_vers.as_ref().unwrap_or(&version.kernel);
+	let cached = crate::server::SignedFiles::load(&rtdirs.signed_dir());
+
+	let mut warnings = Vec::new();
+	let mut checks = Vec::new();
+
+
+	/*
+	 * First link: the signed tag.  Verify the cached one; if we can get
+	 * a fresh one from the server, see if it agrees.
+	 */
+	say("Verifying signed tag...  ");
+	let mut tag_source = "cached".to_string();
+	let tag = match &cached {
+		Err(e) => {
+			checks.push(Check { name: "signed tag", ok: false,
+					detail: format!("no saved signed tag: {e}"),
+					problems: vec![] });
+			None
+		},
+		Ok(sf) => {
+			use crate::server::verify_tag;
+			match verify_tag(&sf.key, &sf.tag, &config.keyprint, vers) {
+				Ok(kt) => Some(kt),
+				Err(e) => {
+					checks.push(Check { name: "signed tag", ok: false,
+							detail: format!("saved tag doesn't verify: {e}"),
+							problems: vec![] });
+					None
+				},
+			}
+		},
+	};
+
+	if let Some(kt) = &tag
+	{
+		let mut detail = format!("signature OK with configured keyprint, \
+				tINDEX {}", kt.tidx());
+
+		if args.offline
+		{
+			warnings.push("offline: using the signed tag saved at fetch \
+					time, not re-fetched from the server".to_string());
+		}
+		else
+		{
+			use crate::server::Server;
+			let hint = state.server_hint(&config.servername,
+					config.server_cache_ttl);
+			let srv = Server::find_inner(&config.servername, vers,
+					&config.keyprint, hint, config.prefer_server.as_deref(),
+					true);
+			match srv
+			{
+				Ok(srv) => {
+					tag_source = srv.name().to_string();
+					match srv.keytag_tidx() == Some(kt.tidx()) {
+						true => detail.push_str("; matches server's current tag"),
+						false => warnings.push(format!("server {} now offers \
+								a newer tag; audited against the signed tag \
+								saved at fetch time", srv.name())),
+					}
+				},
+				Err(e) => {
+					warnings.push(format!("couldn't re-fetch tag ({e}); \
+							using the signed tag saved at fetch time"));
+				},
+			}
+		}
+
+		checks.push(Check { name: "signed tag", ok: true, detail,
+				problems: vec![] });
+	}
+	say(if tag.is_some() { "OK.\n" } else { "FAILED.\n" });
+
+
+	/*
+	 * Second link: the tINDEX the tag signs, and that our saved
+	 * metadata index matches it.
+	 */
+	say("Verifying metadata index...  ");
+	let tidx_ok = match (&tag, &cached) {
+		(Some(kt), Ok(sf)) => {
+			use crate::util::hash;
+			use crate::metadata::MetadataIdx;
+			let res = hash::check_sha256(&sf.tidx, kt.tidx(), "tINDEX")
+					.map_err(|e| e.to_string())
+					.and_then(|_| MetadataIdx::parse(&sf.tidx)
+							.map_err(|e| e.to_string()));
+			match res
+			{
+				Ok(signed) => {
+					let bad = mdidx.mismatches(&signed);
+					let ok = bad.is_empty();
+					let detail = match ok {
+						true  => "saved metadata index matches signed tINDEX"
+								.to_string(),
+						false => "saved metadata index doesn't match signed \
+								tINDEX".to_string(),
+					};
+					let problems = bad.iter()
+							.map(|t| format!("INDEX-{} hash differs",
+									t.to_uppercase()))
+							.collect();
+					checks.push(Check { name: "metadata index", ok, detail,
+							problems });
+					ok
+				},
+				Err(e) => {
+					checks.push(Check { name: "metadata index", ok: false,
+							detail: e, problems: vec![] });
+					false
+				},
+			}
+		},
+		_ => {
+			checks.push(Check { name: "metadata index", ok: false,
+					detail: "no verified tag to check against".to_string(),
+					problems: vec![] });
+			false
+		},
+	};
+	say(if tidx_ok { "OK.\n" } else { "FAILED.\n" });
+
+
+	/*
+	 * Third link: the INDEX files themselves.
+	 */
+	say("Verifying metadata files...  ");
+	{
+		let hashes = mdidx.get_matching(&["all", "new", "old"]);
+		let problems = hash_problems(&rtdirs,
+				hashes.into_iter().map(|h| h.to_buf()));
+		let ok = problems.is_empty();
+		let detail = match ok {
+			true  => "all metadata files present and match".to_string(),
+			false => format!("{} bad metadata file{}", problems.len(),
+					plural(problems.len())),
+		};
+		say(if ok { "OK.\n" } else { "FAILED.\n" });
+		checks.push(Check { name: "metadata files", ok, detail, problems });
+	}
+
+
+	/*
+	 * And finally, anything a pending install is going to use.
+	 */
+	if let Some(manifest) = &state.manifest
+	{
+		let mut hashes: Vec<_> = manifest.install_hashes().into_iter()
+				.collect();
+		hashes.sort_unstable();
+
+		let nh = hashes.len();
+		say(&format!("Verifying {nh} file{} for pending {}...  ",
+				plural(nh), manifest.mtype()));
+		let problems = hash_problems(&rtdirs,
+				hashes.into_iter().map(|h| h.to_buf()));
+		let ok = problems.is_empty();
+		let detail = match ok {
+			true  => format!("all {nh} files present and match"),
+			false => format!("{} of {nh} files missing or bad",
+					problems.len()),
+		};
+		say(if ok { "OK.\n" } else { "FAILED.\n" });
+		checks.push(Check { name: "pending install files", ok, detail,
+				problems });
+	}
+
+
+	// Roll it up
+	let pass = checks.iter().all(|c| c.ok);
+	let time = chrono::Local::now().to_rfc3339();
+	let report = Report { time, version: vers.to_string(), tag_source,
+			warnings, checks, pass };
+
+	match args.json
+	{
+		true  => println!("{}", serde_json::to_string_pretty(&report)?),
+		false => show_report(&report),
+	}
+
+	Ok(if pass { 0 } else { 1 })
+}
+
+
+
+/// Check a bunch of hashfiles, returning descriptions of what's wrong.
+fn hash_problems(rtdirs: &crate::core::RtDirs,
+		hashes: impl Iterator<Item = Sha256HashBuf>) -> Vec<String>
+{
+	hashes.filter_map(|h| {
+		let hf = rtdirs.hashfile(&h);
+		if !hf.is_file() { return Some(format!("{}: missing", hf.display())); }
+		match rtdirs.check_hashfile(&h) {
+			Ok(()) => None,
+			Err(e) => Some(format!("{}: {e}", hf.display())),
+		}
+	}).collect()
+}
+
+
+/// Print out the text report
+fn show_report(report: &Report)
+{
+	println!("\nAudit of update files for {}, {}", report.version,
+			report.time);
+	println!("Signed tag source: {}", report.tag_source);
+	for w in &report.warnings { println!("WARNING: {w}"); }
+	println!("");
+
+	for c in &report.checks
+	{
+		let res = if c.ok { "PASS" } else { "FAIL" };
+		println!("  [{res}] {}: {}", c.name, c.detail);
+		for p in &c.problems { println!("           {p}"); }
+	}
+
+	println!("\nOverall: {}", if report.pass { "PASS" } else { "FAIL" });
+}
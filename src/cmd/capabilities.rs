@@ -0,0 +1,259 @@
+//! $0 capabilities
+use crate::command::FrArgs;
+
+
+/// Named features, for other tools to check for rather than digging
+/// through --help.  Once a name is in here, it keeps meaning the same
+/// thing for as long as the feature's around; don't rename or reuse
+/// them.
+const FEATURES: &[&str] = &[
+	"audit-json",          // audit --json
+	"audit-offline",       // audit --offline
+	"completions",         // completions --shell
+	"export-pending",      // export-pending/import-pending
+	"install-busy-files",  // install --busy-files
+	"install-keep-going",  // install --keep-going
+	"install-require-flags", // install --require-flags
+	"install-resume",      // install --reboot/--enable-resume
+	"ownership-manifest",  // install/extract --ownership-manifest
+	"prefer-server",       // --prefer-server, PreferServer
+	"progress-json",       // progress --json
+	"release-shorthand",   // -r 14.2, 14.2-release, etc
+	"sessions",            // --record-session/--replay-session
+];
+
+
+/// Command: $0 capabilities
+///
+/// Writes the report to stdout as JSON.
+pub(crate) fn run()
+{
+	let caps = Caps::load();
+	let out = serde_json::to_string_pretty(&caps)
+			.expect("Just strings and bools, can't fail");
+	println!("{out}");
+}
+
+
+
+/*
+ * The report.  The commands and their args come straight out of clap,
+ * so they're never out of date.
+ */
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Caps
+{
+	name: &'static str,
+	version: &'static str,
+
+	/// x-ref crate::state::STATE_SCHEMA
+	state_schema: u32,
+
+	features: Vec<&'static str>,
+
+	/// Args before the command
+	global_args: Vec<Arg>,
+
+	commands: Vec<Cmd>,
+}
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Cmd
+{
+	name: String,
+	aliases: Vec<String>,
+
+	/// Dev/internal bits that aren't in --help
+	hidden: bool,
+
+	args: Vec<Arg>,
+}
+
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Arg
+{
+	id: String,
+	long: Option<String>,
+	short: Option<char>,
+	positional: bool,
+
+	/// What sort of value it takes; "none" for plain switches
+	value_type: &'static str,
+
+	/// For "choice", what the choices are
+	values: Vec<String>,
+
+	/// Can be given more than once (or take several values)
+	multiple: bool,
+	required: bool,
+	hidden: bool,
+}
+
+
+impl Caps
+{
+	fn load() -> Self
+	{
+		use clap::CommandFactory as _;
+		let mut top = FrArgs::command();
+		top.build();
+
+		// clap's own --help (and help command) are everywhere, and
+		// nobody needs telling.
+		let args = |c: &clap::Command| -> Vec<Arg> {
+			c.get_arguments().filter(|a| a.get_id() != "help")
+					.map(Arg::from).collect()
+		};
+
+		let commands = top.get_subcommands()
+				.filter(|c| c.get_name() != "help")
+				.map(|c| Cmd {
+					name: c.get_name().to_string(),
+					aliases: c.get_all_aliases().map(|a| a.to_string())
+							.collect(),
+					hidden: c.is_hide_set(),
+					args: args(c),
+				})
+				.collect();
+
+		Self {
+			name: env!("CARGO_PKG_NAME"),
+			version: crate::VERSION,
+			state_schema: crate::state::STATE_SCHEMA,
+			features: FEATURES.to_vec(),
+			global_args: args(&top),
+			commands,
+		}
+	}
+}
+
+
+impl From<&clap::Arg> for Arg
+{
+	fn from(a: &clap::Arg) -> Self
+	{
+		let values: Vec<String> = a.get_possible_values().iter()
+				.filter(|v| !v.is_hide_set())
+				.map(|v| v.get_name().to_string()).collect();
+		let multiple = matches!(a.get_action(), clap::ArgAction::Append)
+				|| a.get_num_args().is_some_and(|n| n.max_values() > 1);
+
+		Self {
+			id: a.get_id().to_string(),
+			long: a.get_long().map(|l| l.to_string()),
+			short: a.get_short(),
+			positional: a.is_positional(),
+			value_type: value_type(a, !values.is_empty()),
+			values,
+			multiple,
+			required: a.is_required_set(),
+			hidden: a.is_hide_set(),
+		}
+	}
+}
+
+
+/// A name for what an arg takes, out of the type clap parses it into.
+/// Anything it doesn't know comes out "unknown"; the test catches that,
+/// so new kinds of args get a name here.
+fn value_type(a: &clap::Arg, choice: bool) -> &'static str
+{
+	use std::any::TypeId;
+
+	if !a.get_action().takes_values() { return "none"; }
+	if choice { return "choice"; }
+
+	let types = [
+		(TypeId::of::<String>(),                 "string"),
+		(TypeId::of::<std::ffi::OsString>(),     "string"),
+		(TypeId::of::<std::path::PathBuf>(),     "path"),
+		(TypeId::of::<crate::info::AVersion>(),  "release"),
+		(TypeId::of::<regex_lite::Regex>(),      "regex"),
+		(TypeId::of::<crate::components::Component>(), "component"),
+		(TypeId::of::<u8>(),    "integer"),
+		(TypeId::of::<u32>(),   "integer"),
+		(TypeId::of::<u64>(),   "integer"),
+		(TypeId::of::<usize>(), "integer"),
+	];
+	let tid = a.get_value_parser().type_id();
+	types.iter().find(|(t, _)| tid == *t).map(|(_, n)| *n)
+			.unwrap_or("unknown")
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use serde_json::Value;
+
+	fn caps() -> Value
+	{
+		let caps = super::Caps::load();
+		let out = serde_json::to_string_pretty(&caps).unwrap();
+		serde_json::from_str(&out).expect("should be valid JSON")
+	}
+
+	#[test]
+	fn every_command()
+	{
+		use clap::CommandFactory as _;
+
+		let caps = caps();
+		let names: Vec<&str> = caps["commands"].as_array().unwrap().iter()
+				.map(|c| c["name"].as_str().unwrap()).collect();
+
+		// Everything clap knows about is in there, hidden ones too.
+		let cmd = crate::command::FrArgs::command();
+		for c in cmd.get_subcommands()
+		{
+			assert!(names.contains(&c.get_name()), "{} missing from {names:?}",
+					c.get_name());
+		}
+		assert!(names.contains(&"capabilities"));
+		assert!(!names.contains(&"help"));
+
+		assert_eq!(caps["version"], crate::VERSION);
+		assert_eq!(caps["state_schema"], crate::state::STATE_SCHEMA);
+		let feats = caps["features"].as_array().unwrap();
+		assert!(feats.iter().any(|f| f == "sessions"));
+	}
+
+	#[test]
+	fn args()
+	{
+		let caps = caps();
+		let cmd = |n: &str| caps["commands"].as_array().unwrap().iter()
+				.find(|c| c["name"] == n).unwrap().clone();
+		let arg = |c: &Value, id: &str| c["args"].as_array().unwrap().iter()
+				.find(|a| a["id"] == id).unwrap_or_else(|| panic!("no {id}"))
+				.clone();
+
+		let up = cmd("upgrade");
+		let rel = arg(&up, "release");
+		assert_eq!(rel["value_type"], "release");
+		assert_eq!(rel["short"], "r");
+		assert_eq!(rel["long"], "release");
+
+		let inst = cmd("install");
+		assert_eq!(arg(&inst, "keep_going")["value_type"], "none");
+
+		let comp = arg(&cmd("completions"), "shell");
+		assert_eq!(comp["value_type"], "choice");
+		assert!(comp["values"].as_array().unwrap().iter().any(|v| v == "zsh"));
+
+		let globs = caps["global_args"].as_array().unwrap();
+		let conf = globs.iter().find(|a| a["id"] == "config").unwrap();
+		assert_eq!(conf["value_type"], "path");
+		assert!(globs.iter().all(|a| a["id"] != "help"));
+
+		// Nothing we don't have a name for
+		let all = globs.iter().chain(caps["commands"].as_array().unwrap()
+				.iter().flat_map(|c| c["args"].as_array().unwrap().iter()));
+		for a in all
+		{ assert_ne!(a["value_type"], "unknown", "{a}"); }
+	}
+}
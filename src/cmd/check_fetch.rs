@@ -19,6 +19,14 @@ pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
 	};
 	let quiet = args.quiet > 0;
 
+	// There's never anything for us on a non-release, but this is just
+	// looking, so say so (when asked to talk) rather than failing.
+	if let Err(e) = crate::check::version(&version)
+	{
+		if !quiet { println!("{e}\nNothing to check."); }
+		return Ok(0);
+	}
+
 
 	// Delay?
 	if args.cron
@@ -36,8 +44,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
 
 
 	// Locate server to get the keytag stuff
-	let server = crate::server::Server::find_inner(&config.servername,
-			&version.kernel, &config.keyprint, quiet)?;
+	let rtdirs = crate::core::RtDirs::init(config.basedir(),
+			config.workdir())?;
+	let server = crate::server::Server::find_cached_rt(&config,
+			&version.kernel, &rtdirs, quiet)?;
 
 	// We're kinda fetch-y, so if we got something, it matches our
 	// version; the only difference can be the patch.
@@ -84,12 +94,6 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 	check!(servername);
 	check!(keyprint);
 
-	// Should only run on releases (temporarily knocked off for dev)
-	match crate::check::version(&carg.version) {
-		Ok(_) => (),
-		Err(_e) => (), // errs.push(e),
-	};
-
 
 	match errs.len() {
 		0 => Ok(()),
@@ -1,6 +1,7 @@
 //! $0 check-sys
 use std::collections::{HashSet, HashMap};
 use std::io::{stdout, Write as _};
+use std::path::Path;
 
 use crate::command::CmdArg;
 
@@ -14,7 +15,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	// Setting up various dirs
 	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
-			&carg.config.workdir())?;
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
 
 	// No state for this cmd
 
@@ -35,12 +37,21 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// that filtering later in the process anyway.
 	crate::metadata::set_ugid_cmp(true);
 
+	// Not being root means there's stuff we can't look at; x-ref
+	// unchecked().
+	let unpriv = crate::util::euid() != 0;
+
 	// Do the "finalize components" thing, which pulls src outta the list
-	// if we don't seem to have src installed.
-	config.finalize_components();
+	// if we don't seem to have src installed, or it's a git checkout.
+	if let Some(n) = config.finalize_components() { println!("{n}"); }
 
 	// Show our starting point
 	println!("Currently running {version}.");
+	if unpriv
+	{
+		println!("Not running as root: flags won't be compared, and files \
+				that can't be read will be counted, not compared.");
+	}
 
 	// Extract args
 	let args = match clargs.command {
@@ -49,72 +60,43 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	};
 
 
-	// Find the server
-	let mut server = crate::server::Server::find(&config.servername,
-			&version.kernel, &config.keyprint)?;
-
-	// Set copy of dirnames the server object accesses internally
-	server.set_filesdir(rtdirs.files().to_path_buf());
-
-
-
 	/*
 	 * Load the metadata
 	 */
-	// Load up the metadata index stuff
-	print!("Loading metadata index...");
-	stdout().flush()?;
-	let mdidx = server.get_metadata_idx()?;
-	println!("   OK.");
-
-	// All we need here is the INDEX-ALL
-	let metadatas = &["all"];
-
-	// Get the one we need
-	print!("Getting all metadata files...  ");
-	stdout().flush()?;
-	let metamiss = {
-		let fd = rtdirs.files();
-		let missing = mdidx.not_in_dir(fd, metadatas);
-		missing
-	};
-	match metamiss.len()
-	{
-		0 => println!("All present."),
-		_ => {
-			println!("{} missing.", metamiss.len());
-
-			// So grab 'em.
-			println!("Fetching...");
-			let files = metamiss.into_iter().collect();
-			server.fetch_metafiles(files)?;
-			println!("Done.");
+	// That's either from the server, or from dist sets if we were given
+	// them.  For the former, we keep the server around to say what
+	// patchlevel we compared to.
+	let mut server = None;
+	let (mut all, ignored, dsets) = match &args.dist_dir {
+		Some(dd) => {
+			let dm = crate::core::dist::acquire(dd, &rtdirs, &config)?;
+			(dm.all, dm.ignored, Some(dm.sets))
 		},
-	};
-
-	// Check all the metafiles hashes
-	print!("Checking metadata file hashes...  ");
-	stdout().flush()?;
-	let hres = {
-		let fd = rtdirs.files();
-		let td = rtdirs.tmp();
-		mdidx.check_hashes(fd, td, metadatas)
-	};
-	match hres {
-		Ok(_) => println!("   OK."),
-		Err(e) => {
-			println!("   Errors found.\n{}", e.join("\n"));
-			bail!("Invalid metafiles, bailing.");
+		None => {
+			// Find the server
+			let server = server.insert(crate::server::Server::find_cached_rt(
+					&config, &version.kernel, &rtdirs, false)?);
+
+			// Set copy of dirnames the server object accesses internally
+			server.set_filesdir(rtdirs.files().to_path_buf());
+
+			// All we need here is the INDEX-ALL.  Unlike fetch/upgrade, we
+			// don't bother with anything from the last run's index.
+			use crate::core::mdfetch;
+			let metadatas = &["all"];
+			let mut acq = mdfetch::acquire(server, &rtdirs, &config,
+					metadatas, metadatas, None,
+					&mut mdfetch::default_printer())?;
+			(acq.take("all"), acq.ignored, None)
 		},
 	};
 
-
-	// Parse out the metadata
-	print!("Parsing metadata files...  ");
-	stdout().flush()?;
-	let mut all = mdidx.parse_one_full("all", rtdirs.tmp(), &config)?;
-	print!(" all");
-	println!("   OK.");
+	// Report on what IgnorePaths did, if asked.
+	if let Some(si) = &args.show_ignored
+	{
+		let full = si.full_file(config.workdir());
+		ignored.show(full.as_deref())?;
+	}
 
 
 	// Handle path in/exclusions, if there are any.
@@ -166,18 +148,27 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 	println!("{} paths to scan", scanpaths.len());
 	use crate::core::scan;
-	let mut cur = scan::scan_inner(config.basedir().to_path_buf(),
-			scanpaths, do_hashes)?;
-	{
-		// Just for kicks, give details
-		let ndir  = cur.dirs.len();
-		let nfile = cur.files.len();
-		let nsl   = cur.symlinks.len();
-		let nhl   = cur.hardlinks.len();
-		let nmiss = cur.dashes.len();
-		println!("Found {ndir} dirs, {nfile} files, {nsl} symlinks, \
-				{nhl} hardlinks, and {nmiss} missing files.");
-	}
+	let basedir = config.basedir().to_path_buf();
+	let scanned = match unpriv {
+		true  => scan::scan_unpriv(basedir, scanpaths, do_hashes)?,
+		false => scan::Scanned {
+			md: scan::scan_inner(basedir, scanpaths, do_hashes)?,
+			unreadable: Vec::new(),
+			annotated: Vec::new(),
+		},
+	};
+	let scan::Scanned { md: mut cur, unreadable, .. } = scanned;
+	let unreadable: HashSet<&std::path::Path> = unreadable.iter()
+			.map(|p| p.as_path()).collect();
+	let show_unreadable = || {
+		let n = unreadable.len();
+		if n > 0
+		{
+			println!("\n{n} file{} could not be read (run as root for full \
+					coverage).", crate::util::plural(n));
+		}
+	};
+	println!("{}", scan::found_desc(&cur, &all.parts()));
 
 
 	// Filter components.
@@ -244,14 +235,18 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	// If there's nothing left in all, everything's the same.
 	let relstr = || -> String {
-		use crate::info::version::mk_str;
-		mk_str(&version.kernel.release, &version.kernel.reltype,
-				server.keytag_patchnum())
+		match (&server, &args.dist_dir) {
+			(_, Some(dd)) => format!("the distribution sets in {}",
+					dd.display()),
+			(s, None) => version.with_patch(s.as_ref()
+					.and_then(|s| s.keytag_patchnum())).to_string(),
+		}
 	};
 	if all.empty()
 	{
 		let rstr = relstr();
 		println!("\nNo differences found vs. {rstr}.");
+		show_unreadable();
 
 		return Ok(());
 	}
@@ -272,10 +267,22 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		}
 	};
 
+	// And some we just can't tell, and so shouldn't cry wolf over.
+	let mut nunchecked: HashMap<String, u32> = HashMap::new();
+	let mut should_skip = |t: &str, p: &std::path::Path| -> bool {
+		if should_ignore(t) { return true; }
+		match unchecked(t, unpriv, unreadable.contains(p)) {
+			true => { *nunchecked.entry(t.to_string()).or_default() += 1; true },
+			false => false,
+		}
+	};
+
 	// Rack up what they are
 	let allpaths = all.allpaths_hashset_nodash();
 	let len = allpaths.len();
 	let mut diffs: HashMap<&std::path::Path, Vec<String>> = HashMap::with_capacity(len);
+	let mut typeconf: HashSet<&std::path::Path> = HashSet::new();
+	let mut nflagdiffs = 0;
 	for p in allpaths
 	{
 		let mut add = |s| { diffs.entry(p).or_default().push(s); };
@@ -286,7 +293,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let my = match cur.get_path(p) {
 			Some(m) => m,
 			None => {
-				if !should_ignore("missing")
+				if !should_skip("missing", p)
 				{ add(format!("doesn't exist on your system")); }
 				continue;
 			},
@@ -297,8 +304,11 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let mtype = my.ftype();
 		if utype != mtype
 		{
-			if !should_ignore("type")
+			if !should_skip("type", p)
 			{
+				// Something missing is just missing, but something else
+				// in the way is a conflict.
+				if mtype != "dashline" { typeconf.insert(p); }
 				add(match mtype
 				{
 					"dashline" => format!("is missing but should be a {utype}"),
@@ -319,11 +329,23 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		};
 		if let Some(diffs) = diffs
 		{
+			// Derived db's get regenerated locally, so their hashes never
+			// match upstream's; that's its own (ignorable) thing.
+			let derived = crate::core::derived::lookup(p);
 			diffs.into_iter().for_each(|d| {
 				// We may be doing some filtering down of what types of
 				// differences we care about.
-				if !should_ignore(d.dtype())
-				{ add(d.to_string()); }
+				let dtype = match (d.dtype(), derived) {
+					("hash", Some(_)) if do_hashes => "derived",
+					(t, _) => t,
+				};
+				if should_skip(dtype, p) { return; }
+				if dtype == "flags" { nflagdiffs += 1; }
+				match derived {
+					Some(dv) if dtype == "derived" => add(format!("{d} \
+							(expected; regenerated from {})", dv.source)),
+					_ => add(d.to_string()),
+				}
 			});
 		}
 	}
@@ -354,6 +376,15 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		println!("Ignored differing {}.\n", istrs.join(", "));
 	}
 
+	// Things we couldn't check get mentioned the same way, minus the
+	// hashes and missing'ness of unreadable files, which get counted
+	// below.
+	let nflags = nunchecked.get("flags").copied().unwrap_or(0);
+	if nflags > 0
+	{
+		println!("Didn't compare flags({nflags}), since that needs root.\n");
+	}
+
 	// Now the remaining details
 	match diffs.len()
 	{
@@ -367,11 +398,130 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			}
 		},
 	}
+	if nflagdiffs > 0 { flags_lost_note(&rtdirs); }
+	show_unreadable();
+
+
+	/*
+	 * Put things back, if asked.
+	 */
+	if !args.fix { return Ok(()); }
+
+	// What can be fixed is what differs, plus with --force, what we
+	// couldn't tell.  Derived db's are for regenerating, not extracting.
+	let mut fixable: HashSet<&Path> = diffs.keys().copied().collect();
+	if args.force
+	{
+		fixable.extend(all.allpaths_hashset_nodash().into_iter()
+				.filter(|p| unreadable.contains(p)));
+	}
+	fixable.retain(|p| crate::core::derived::lookup(p).is_none());
+	let dry = args.dry_run;
+	let confirm_type = |p: &Path| -> Result<bool, anyhow::Error> {
+		let what = diffs.get(p).map(|d| d.join("; ")).unwrap_or_default();
+		match dry {
+			true => {
+				println!("DRY RUN: would ask about {} ({what})", p.display());
+				Ok(false)
+			},
+			false => {
+				let q = format!("{} {what}; replace it?", p.display());
+				Ok(crate::util::confirm(&q)?)
+			},
+		}
+	};
+	println!("");
+	let fixes = fix_pick(fixable, &typeconf, confirm_type)?;
+
+	let nfix = fixes.len();
+	if nfix == 0
+	{
+		println!("Nothing to fix.");
+		return Ok(());
+	}
+	let npp = crate::util::plural(nfix);
+	if !dry && !args.yes
+			&& !crate::util::confirm(&format!("Extract {nfix} path{npp} \
+					from upstream?"))?
+	{
+		println!("Not fixing anything.");
+		return Ok(());
+	}
+	println!("Fixing {nfix} path{npp}.");
+
+	// Everything we need is already loaded up, so just trim it down to
+	// what we're putting back.
+	let mut fixmd = all.clone();
+	fixmd.keep_paths(&fixes);
+	fixmd.dashes = HashSet::new();
+
+	use crate::core::extract;
+	let src = match (&dsets, server) {
+		(Some(sets), _) => extract::Source::Dist(sets),
+		(None, Some(s)) => extract::Source::Server(Box::new(move || Ok(s))),
+		(None, None) => unreachable!("Have to have gotten metadata somewhere"),
+	};
+	extract::from_metadata(fixmd, &rtdirs, config.basedir(), src, dry,
+			args.ownership_manifest.as_deref())?;
+	if !dry { println!("\nDone."); }
 
 	Ok(())
 }
 
 
+/// Pick which of the differing paths --fix will put back.  Anything
+/// that's the wrong type on the system only gets replaced if ask says so,
+/// one at a time.
+fn fix_pick<'a>(fixable: HashSet<&'a Path>, typeconf: &HashSet<&Path>,
+		mut ask: impl FnMut(&Path) -> Result<bool, anyhow::Error>)
+		-> Result<HashSet<&'a Path>, anyhow::Error>
+{
+	let mut ret = HashSet::with_capacity(fixable.len());
+	let mut fixable: Vec<_> = fixable.into_iter().collect();
+	fixable.sort_unstable();
+	for p in fixable
+	{
+		if typeconf.contains(p) && !ask(p)? { continue; }
+		ret.insert(p);
+	}
+	Ok(ret)
+}
+
+
+/// If the last install went into somewhere that couldn't keep file flags,
+/// that'd be why they differ now; say so.  A state we can't read just
+/// means no note.
+fn flags_lost_note(rtdirs: &crate::core::RtDirs)
+{
+	let Ok(state) = rtdirs.state_load_brief() else { return };
+	if let Some(n) = flags_lost_desc(&state.history) { println!("{n}"); }
+}
+
+fn flags_lost_desc(history: &[crate::state::HistoryEntry]) -> Option<String>
+{
+	let he = history.last()?;
+	let fl = he.flags_lost.as_ref()?;
+	Some(format!("\nNote: when the {} to {} was installed, {}; the flags \
+			differing above may be why.", he.mtype, he.to, fl.describe()))
+}
+
+
+/// Is a difference of this type something we can't actually vouch for
+/// as non-root?  Flags may need privilege to see at all (depending on
+/// the filesystem), and a file we couldn't read has no hash to compare,
+/// and may not have been lstat-able at all (so looks missing).
+/// Everything else (owner, mode, etc) we can see fine, and so report.
+fn unchecked(dtype: &str, unpriv: bool, unreadable: bool) -> bool
+{
+	if !unpriv { return false; }
+	match dtype {
+		"flags" => true,
+		"hash" | "derived" | "missing" => unreadable,
+		_ => false,
+	}
+}
+
+
 /// Do some checks of our config/etc
 fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 {
@@ -392,10 +542,10 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 	check!(workdir);
 	check!(basedir);
 
-	// Should only run on releases (temporarily knocked off for dev)
+	// Should only run on releases
 	match crate::check::version(&carg.version) {
 		Ok(_) => (),
-		Err(_e) => (), // errs.push(e),
+		Err(e) => errs.push(e),
 	};
 
 
@@ -409,3 +559,84 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 		},
 	}
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn fix_pick()
+	{
+		use std::collections::HashSet;
+		use std::path::Path;
+
+		let p = Path::new;
+		let fixable = HashSet::from([p("/a"), p("/b"), p("/c"), p("/d")]);
+		let typeconf = HashSet::from([p("/b"), p("/d")]);
+
+		// Only the type conflicts get asked about, each on its own
+		let mut asked = Vec::new();
+		let got = super::fix_pick(fixable, &typeconf, |q| {
+			asked.push(q.to_path_buf());
+			Ok(q == p("/d"))
+		}).unwrap();
+		assert_eq!(asked, [p("/b"), p("/d")]);
+		assert_eq!(got, HashSet::from([p("/a"), p("/c"), p("/d")]));
+	}
+
+	#[test]
+	fn unchecked()
+	{
+		use super::unchecked;
+
+		// As root, everything counts
+		for t in ["flags", "hash", "missing", "uid", "mode", "type"]
+		{
+			assert!(!unchecked(t, false, false), "{t}");
+			assert!(!unchecked(t, false, true), "{t}");
+		}
+
+		// Flags never do as non-root
+		assert!(unchecked("flags", true, false));
+		assert!(unchecked("flags", true, true));
+
+		// Readable files compare like normal
+		for t in ["hash", "missing", "uid", "gid", "mode", "type", "target"]
+		{ assert!(!unchecked(t, true, false), "{t}"); }
+
+		// Unreadable ones, we can't say anything about the contents, or
+		// whether they're really there.  But what we did see, we trust.
+		assert!(unchecked("hash", true, true));
+		assert!(unchecked("derived", true, true));
+		assert!(!unchecked("derived", true, false));
+		assert!(unchecked("missing", true, true));
+		for t in ["uid", "gid", "mode", "type"]
+		{ assert!(!unchecked(t, true, true), "{t}"); }
+	}
+
+	#[test]
+	fn flags_lost_desc()
+	{
+		use crate::state::HistoryEntry;
+		use crate::core::fsprobe::FlagsLost;
+
+		let he = |to: &str, lost: bool| HistoryEntry {
+				mtype: "upgrade".to_string(),
+				from: "14.1-RELEASE".to_string(), to: to.to_string(),
+				when: 0, audit: None, deferred: Vec::new(), counts: None,
+				flags_lost: lost.then(|| FlagsLost {
+					fstype: "tmpfs".to_string(), flags: 0x00020000,
+					paths: 7 }) };
+
+		// Only the last one matters; it's what's there now.
+		assert_eq!(super::flags_lost_desc(&[]), None);
+		assert_eq!(super::flags_lost_desc(&[he("14.2-RELEASE", true),
+				he("14.2-RELEASE-p1", false)]), None);
+		let n = super::flags_lost_desc(&[he("14.2-RELEASE", true)]).unwrap();
+		assert_eq!(n, "\nNote: when the upgrade to 14.2-RELEASE was \
+				installed, the basedir's filesystem (tmpfs) doesn't keep \
+				schg flags, so 7 paths that should have them won't; the \
+				flags differing above may be why.");
+	}
+}
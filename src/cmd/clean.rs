@@ -1,5 +1,8 @@
 //! #0 clean
+use std::path::{Path, PathBuf};
+
 use crate::command::CmdArg;
+use crate::state::Manifest;
 
 pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 {
@@ -8,7 +11,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			&carg.config.workdir())?;
 
 	// Split up
-	let CmdArg { clargs, config: _, version: _ } = carg;
+	let CmdArg { clargs, config, version: _ } = carg;
 
 	// Extract our own args
 	let args = match clargs.command {
@@ -17,7 +20,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	};
 
 	// Maybe we have state
-	let state = rtdirs.state_load_raw()?;
+	let mut state = rtdirs.state_load_raw()?;
+	let dry = args.dry_run;
 
 
 	// Currently everything clean might do is behind --options, so
@@ -26,22 +30,89 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let mut did = false;
 
 
+	// Tempfiles from installs that died partway.  Before --pending, so
+	// we still know where to look.
+	if args.install_residue
+	{
+		did = true;
+		use crate::core::install::residue;
+
+		let dirs = match &state {
+			Some(st) => residue::dirs(st.manifest.as_ref(),
+					&st.last_install_dirs),
+			None => Vec::new(),
+		};
+		let res = residue::sweep(config.basedir(), &dirs, residue::MIN_AGE,
+				dry);
+		match res.found.is_empty() {
+			true  => println!("No install residue found."),
+			false => res.describe(dry).iter().for_each(|l| println!("{l}")),
+		}
+	}
+
+
+	// Old copies of what --pending threw away.  Before --pending, so
+	// doing both leaves the one it's about to make.
+	if args.discarded
+	{
+		did = true;
+		let old = discarded(&rtdirs.discarded_dir())?;
+		let num = old.len();
+		let desc = format!("{num} discarded pending update{}",
+				crate::util::plural(num));
+		match (num, dry) {
+			(0, _) => println!("No discarded pending updates kept."),
+			(_, true) => println!("Would remove {desc}."),
+			(_, false) => {
+				for f in &old { std::fs::remove_file(f)?; }
+				println!("Removed {desc}.");
+			},
+		}
+	}
+
+
 	// Clean up pending state; i.e., forget about a fetch/upgrade we did.
 	if args.pending
 	{
 		did = true;  // didit
 
 		// Clear out the manifest if there is one
-		if let Some(mut st) = state
+		if let Some(st) = &mut state
 		{
-			match st.manifest
+			// Don't strand somebody halfway through an upgrade without
+			// saying so loudly.
+			if let Some(m) = &st.manifest
+			{
+				let force = args.i_know_kernel_is_installed;
+				if let Some(why) = blocker(m, !st.recovery.is_empty())
+				{
+					match force {
+						true  => eprintln!("{}\n", why[0]),
+						false => anyhow::bail!("{}", why.join("\n")),
+					}
+				}
+			}
+
+			match st.manifest.as_ref().map(|m| m.mtype())
 			{
 				None => println!("No pending updates to clear."),
-				Some(m) => {
-					let mt = m.mtype();
+				Some(mt) if dry => println!("Would clear pending {mt}."),
+				Some(mt) => {
+					// Keep a copy of what it was, so it's not a mystery
+					// later.
+					let m = st.manifest.as_ref().unwrap();
+					let bak = backup(&rtdirs.discarded_dir(), m,
+							chrono::Utc::now())?;
+
+					// Remember where it might have left residue, if it got
+					// as far as installing anything.
+					use crate::core::install::residue;
+					st.last_install_dirs = residue::dirs(st.manifest.as_ref(),
+							&st.last_install_dirs);
 					st.manifest = None;
-					rtdirs.state_save(&st)?;
-					println!("Pending {mt} cleared.");
+					rtdirs.state_save(st)?;
+					println!("Pending {mt} cleared (a copy is in {}).",
+							bak.display());
 				},
 			}
 		}
@@ -52,6 +123,35 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 
 
+	// Clean out the files dir, except what a pending install needs.  If
+	// we're clearing pending too, that's nothing, even when we're only
+	// pretending to.
+	if args.files
+	{
+		did = true;
+		use crate::util::{human_bytes, plural};
+
+		let keep = match (&state, args.pending) {
+			(Some(st), false) => st.manifest.as_ref()
+					.map(|m| m.install_hashes()).unwrap_or_default(),
+			_ => Default::default(),
+		};
+		let unneeded = rtdirs.unneeded_files(&keep)?;
+		let num = unneeded.len();
+		let bytes: u64 = unneeded.iter().map(|(_, b)| b).sum();
+		let (num, bytes) = (format!("{num} file{}", plural(num)),
+				human_bytes(bytes));
+		match dry {
+			true => println!("Would remove {num} from the files dir, \
+					freeing {bytes}."),
+			false => {
+				for (f, _) in &unneeded { std::fs::remove_file(f)?; }
+				println!("Removed {num} from the files dir, freeing {bytes}.");
+			},
+		}
+	}
+
+
 
 	// If we didn't [potentially] do something, that's presumably not
 	// what the user really wanted, so mention it...
@@ -64,3 +164,146 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	Ok(())
 }
+
+
+/// Why a pending update shouldn't just be thrown away, if it shouldn't:
+/// once an upgrade's started installing, the manifest is the only record
+/// of where the system is.  The first line says what's wrong; the rest
+/// what to do instead.
+fn blocker(m: &Manifest, recovery: bool) -> Option<Vec<String>>
+{
+	let u = match m {
+		Manifest::Fetch(_) => return None,
+		Manifest::Upgrade(u) => u,
+	};
+	let vers = m.version();
+	let what = match (u.kernel, u.world) {
+		(false, _) => return None,
+		(true, false) => "its new kernel is already installed, but world \
+				isn't.  Discarding it would leave the new kernel with the \
+				old world, and nothing recording what was in flight",
+		(true, true) => "its kernel and world are already installed, and \
+				only removing the old shared libs is left.  Discarding it \
+				would lose track of those",
+	};
+
+	let cmdname = crate::util::cmdname();
+	let mut ret = vec![format!("The pending upgrade to {vers} is partly \
+			installed: {what}.")];
+	ret.push(format!("  - `{cmdname} install` picks up where it left off \
+			and finishes it."));
+	if recovery
+	{
+		ret.push(format!("  - `{cmdname} show-install` lists what earlier \
+				installs left to roll back to."));
+	}
+	ret.push(format!("  - `{cmdname} clean --pending \
+			--i-know-kernel-is-installed` discards it anyway."));
+	Some(ret)
+}
+
+
+/// Save a compressed copy of a manifest we're about to throw away.
+fn backup(dir: &Path, m: &Manifest, now: chrono::DateTime<chrono::Utc>)
+		-> Result<PathBuf, anyhow::Error>
+{
+	std::fs::create_dir_all(dir)?;
+	let fname = format!("{}-{}.json.gz", now.format("%Y%m%dT%H%M%SZ"),
+			m.mtype());
+	let path = dir.join(fname);
+	crate::util::compress::write_json_gz(&path, m)?;
+	Ok(path)
+}
+
+
+/// The discarded manifests backup() has saved.
+fn discarded(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error>
+{
+	let rd = match std::fs::read_dir(dir) {
+		Ok(rd) => rd,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound
+				=> return Ok(Vec::new()),
+		Err(e) => return Err(e),
+	};
+	let mut ret = Vec::new();
+	for de in rd
+	{
+		let p = de?.path();
+		let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
+		if name.ends_with(".json.gz") { ret.push(p); }
+	}
+	ret.sort_unstable();
+	Ok(ret)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashMap;
+	use crate::metadata::Metadata;
+	use crate::state::Manifest;
+
+	fn vers() -> crate::info::AVersion { "14.2-RELEASE".parse().unwrap() }
+
+	fn upgrade(kernel: bool, world: bool) -> Manifest
+	{
+		let mut m = Manifest::new_upgrade(Metadata::default(),
+				Metadata::default(), vers(), HashMap::new(), HashMap::new());
+		if let Manifest::Upgrade(u) = &mut m
+		{
+			u.kernel = kernel;
+			u.world = world;
+		}
+		m
+	}
+
+	#[test]
+	fn blocker()
+	{
+		use super::blocker;
+
+		// Nothing's been touched yet, so away it can go
+		let fetch = Manifest::new_fetch(Metadata::default(),
+				Metadata::default(), vers());
+		assert_eq!(blocker(&fetch, false), None);
+		assert_eq!(blocker(&upgrade(false, false), false), None);
+
+		// Partway, not so much
+		let kern = blocker(&upgrade(true, false), false).unwrap();
+		assert!(kern[0].contains("new kernel is already installed"), "{kern:?}");
+		assert!(kern.iter().any(|l| l.contains("--i-know-kernel-is-installed")));
+		assert!(!kern.iter().any(|l| l.contains("roll back")));
+
+		let world = blocker(&upgrade(true, true), true).unwrap();
+		assert!(world[0].contains("old shared libs"), "{world:?}");
+		assert!(world.iter().any(|l| l.contains("roll back")));
+	}
+
+	#[test]
+	fn backup()
+	{
+		use std::io::Read as _;
+
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path().join("discarded");
+		assert!(super::discarded(&dir).unwrap().is_empty());
+
+		let now = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+		let m = upgrade(true, false);
+		let p = super::backup(&dir, &m, now).unwrap();
+		assert_eq!(p.file_name().unwrap(), "20231114T221320Z-upgrade.json.gz");
+
+		// It's all there
+		let mut json = String::new();
+		flate2::read::GzDecoder::new(std::fs::File::open(&p).unwrap())
+				.read_to_string(&mut json).unwrap();
+		let back: Manifest = serde_json::from_str(&json).unwrap();
+		assert_eq!(back.state(), m.state());
+
+		// And findable for cleaning out, without anything else in there
+		std::fs::write(dir.join("README"), "x").unwrap();
+		assert_eq!(super::discarded(&dir).unwrap(), [p]);
+	}
+}
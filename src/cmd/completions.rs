@@ -0,0 +1,598 @@
+//! $0 completions, and the __complete helper the scripts call back into
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::command::{FrArgs, CompletionShell, CompleteWhat};
+use crate::info::AVersion;
+
+
+/// What we call ourselves in the scripts
+const PROG: &str = "freebsd-rustdate";
+
+
+/// Command: $0 completions
+///
+/// Writes the script to stdout.  It's all generated from the clap
+/// definitions, so new commands and flags show up without anybody
+/// remembering to touch this.
+pub(crate) fn script(shell: CompletionShell)
+{
+	let spec = Spec::load();
+	let out = match shell {
+		CompletionShell::Sh   => sh(&spec),
+		CompletionShell::Bash => bash(&spec),
+		CompletionShell::Zsh  => zsh(&spec),
+		CompletionShell::Fish => fish(&spec),
+	};
+	print!("{out}");
+}
+
+
+/// Command: $0 __complete
+///
+/// Candidates, one per line.  This gets run on every tab, so it only
+/// looks at what's here locally, and any trouble just means fewer (or
+/// no) candidates rather than errors splattered over somebody's prompt.
+pub(crate) fn complete(clargs: &FrArgs, what: CompleteWhat)
+{
+	let cands = match what {
+		CompleteWhat::ReleaseCandidates => local_releases(clargs),
+	};
+	for c in cands { println!("{c}"); }
+}
+
+
+
+/*
+ * What there is to complete, out of clap.
+ */
+#[derive(Debug)]
+struct Spec
+{
+	/// Flags before the command
+	globals: Vec<Flag>,
+
+	/// And the commands
+	cmds: Vec<Cmd>,
+}
+
+#[derive(Debug)]
+struct Cmd
+{
+	name: String,
+	about: String,
+	flags: Vec<Flag>,
+}
+
+#[derive(Debug)]
+struct Flag
+{
+	short: Option<char>,
+	long: Option<String>,
+	about: String,
+
+	/// Does it take a value?
+	value: bool,
+
+	/// Is the value a release name?
+	release: bool,
+}
+
+impl Flag
+{
+	/// All the ways of writing it; -r, --release
+	fn forms(&self) -> Vec<String>
+	{
+		let mut ret = Vec::new();
+		if let Some(s) = self.short { ret.push(format!("-{s}")); }
+		if let Some(l) = &self.long { ret.push(format!("--{l}")); }
+		ret
+	}
+}
+
+impl Spec
+{
+	fn load() -> Self
+	{
+		use clap::CommandFactory as _;
+		let mut top = FrArgs::command();
+		top.build();
+
+		let flags = |c: &clap::Command| -> Vec<Flag> {
+			c.get_arguments()
+				.filter(|a| !a.is_positional() && !a.is_hide_set())
+				.map(|a| Flag {
+					short: a.get_short(),
+					long: a.get_long().map(|l| l.to_string()),
+					about: a.get_help().map(|h| h.to_string())
+							.unwrap_or_default(),
+					value: a.get_action().takes_values(),
+					release: a.get_id() == "release",
+				})
+				.collect()
+		};
+
+		let globals = flags(&top);
+		let cmds = top.get_subcommands()
+				.filter(|c| !c.is_hide_set())
+				.map(|c| Cmd {
+					name: c.get_name().to_string(),
+					about: c.get_about().map(|a| a.to_string())
+							.unwrap_or_default(),
+					flags: flags(c),
+				})
+				.collect();
+		Self { globals, cmds }
+	}
+
+	/// (command, flag) for every flag, including the globals with an
+	/// empty command, so the scripts can match on "$cmd $prev".
+	fn pairs(&self) -> impl Iterator<Item = (&str, &Flag)>
+	{
+		let glob = self.globals.iter().map(|f| ("", f));
+		let cmds = self.cmds.iter()
+				.flat_map(|c| c.flags.iter().map(|f| (c.name.as_str(), f)));
+		glob.chain(cmds)
+	}
+
+	/// Case patterns for "$cmd $prev" where prev is a flag wanting a
+	/// release, or any other value.
+	fn prev_patterns(&self, release: bool) -> String
+	{
+		let pats: Vec<String> = self.pairs()
+				.filter(|(_, f)| f.value && f.release == release)
+				.flat_map(|(c, f)| f.forms().into_iter()
+					.map(move |fl| format!("\"{c} {fl}\"")))
+				.collect();
+		pats.join("|")
+	}
+
+	/// Case pattern for the flags before the command that take a value,
+	/// for skipping over when looking for the command.
+	fn global_valued(&self) -> String
+	{
+		let pats: Vec<String> = self.globals.iter().filter(|f| f.value)
+				.flat_map(|f| f.forms()).collect();
+		pats.join("|")
+	}
+
+	/// Every way of writing each flag, space separated
+	fn flag_words(flags: &[Flag]) -> String
+	{
+		let words: Vec<String> = flags.iter().flat_map(|f| f.forms())
+				.collect();
+		words.join(" ")
+	}
+
+	fn cmd_words(&self) -> String
+	{
+		let words: Vec<&str> = self.cmds.iter().map(|c| c.name.as_str())
+				.collect();
+		words.join(" ")
+	}
+
+	/// `case "$cmd" in` arms setting a var to each command's flags.
+	fn flag_arms(&self, var: &str, indent: &str) -> String
+	{
+		let mut ret = String::new();
+		let _ = writeln!(ret, "{indent}\"\") {var}=\"{}\" ;;",
+				Self::flag_words(&self.globals));
+		for c in &self.cmds
+		{
+			let _ = writeln!(ret, "{indent}{}) {var}=\"{}\" ;;", c.name,
+					Self::flag_words(&c.flags));
+		}
+		ret
+	}
+}
+
+
+/// The shell function name; can't have dashes everywhere.
+fn func() -> String { format!("_{}", PROG.replace('-', "_")) }
+
+/// How the scripts ask us for release names
+fn release_cmd() -> String
+{ format!("{PROG} __complete release-candidates 2>/dev/null") }
+
+/// Single-quoted, for sh-ish shells
+fn squote(s: &str) -> String { format!("'{}'", s.replace('\'', r"'\''")) }
+
+/// First line only; some of the abouts run on.
+fn short(s: &str) -> &str { s.lines().next().unwrap_or("").trim() }
+
+
+
+/*
+ * The scripts
+ */
+fn sh(spec: &Spec) -> String
+{
+	let mut s = String::new();
+	let _ = write!(s, r#"#!/bin/sh
+# Completions for {PROG}.
+#
+# /bin/sh doesn't do programmable completion itself; this prints the
+# candidates for the word being completed, for anything that can run a
+# command to get them.  For bash, that's
+#
+#   complete -C /path/to/this/script -o default {PROG}
+#
+# It's called as "script command cur prev", with $COMP_LINE holding the
+# line up to the cursor.
+
+cur="$2"
+prev="$3"
+
+# Find the command, skipping the global flags (and their values)
+set -- ${{COMP_LINE%"$cur"}}
+[ $# -gt 0 ] && shift
+cmd=""
+while [ $# -gt 0 ]; do
+	case "$1" in
+"#);
+	let gv = spec.global_valued();
+	if !gv.is_empty()
+	{ let _ = writeln!(s, "\t\t{gv}) [ $# -gt 1 ] && shift ;;"); }
+	let _ = write!(s, r#"		-*) ;;
+		*) cmd="$1"; break ;;
+	esac
+	shift
+done
+
+words=""
+case "$cmd $prev" in
+"#);
+	let rel = spec.prev_patterns(true);
+	if !rel.is_empty()
+	{ let _ = writeln!(s, "\t{rel})\n\t\twords=$({}) ;;", release_cmd()); }
+	let val = spec.prev_patterns(false);
+	if !val.is_empty()
+	{ let _ = writeln!(s, "\t{val})\n\t\texit 0 ;;"); }
+	let _ = write!(s, r#"	*)
+		case "$cur" in
+			-*)
+				case "$cmd" in
+{}				esac ;;
+			*)
+				[ -z "$cmd" ] && words="{}" ;;
+		esac ;;
+esac
+
+for w in $words; do
+	case "$w" in
+		"$cur"*) echo "$w" ;;
+	esac
+done
+exit 0
+"#, spec.flag_arms("words", "\t\t\t\t\t"), spec.cmd_words());
+	s
+}
+
+
+fn bash(spec: &Spec) -> String
+{
+	let f = func();
+	let mut s = String::new();
+	let _ = write!(s, r#"# bash completion for {PROG}
+
+{f}()
+{{
+	local cur prev cmd words i
+	cur="${{COMP_WORDS[COMP_CWORD]}}"
+	prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+
+	# Find the command, skipping the global flags (and their values)
+	cmd=""
+	i=1
+	while [ $i -lt $COMP_CWORD ]; do
+		case "${{COMP_WORDS[i]}}" in
+"#);
+	let gv = spec.global_valued();
+	if !gv.is_empty()
+	{ let _ = writeln!(s, "\t\t\t{gv}) i=$((i+1)) ;;"); }
+	let _ = write!(s, r#"			-*) ;;
+			*) cmd="${{COMP_WORDS[i]}}"; break ;;
+		esac
+		i=$((i+1))
+	done
+
+	case "$cmd $prev" in
+"#);
+	let rel = spec.prev_patterns(true);
+	if !rel.is_empty()
+	{
+		let _ = writeln!(s, "\t\t{rel})\n\t\t\tCOMPREPLY=( $(compgen -W \
+				\"$({})\" -- \"$cur\") )\n\t\t\treturn ;;", release_cmd());
+	}
+	let val = spec.prev_patterns(false);
+	if !val.is_empty()
+	{ let _ = writeln!(s, "\t\t{val})\n\t\t\treturn ;;"); }
+	let _ = write!(s, r#"	esac
+
+	words=""
+	case "$cur" in
+		-*)
+			case "$cmd" in
+{}			esac ;;
+		*)
+			[ -z "$cmd" ] && words="{}" ;;
+	esac
+	COMPREPLY=( $(compgen -W "$words" -- "$cur") )
+}}
+complete -F {f} -o default {PROG}
+"#, spec.flag_arms("words", "\t\t\t\t"), spec.cmd_words());
+	s
+}
+
+
+fn zsh(spec: &Spec) -> String
+{
+	let f = func();
+	let mut s = String::new();
+	let _ = write!(s, r#"#compdef {PROG}
+
+{f}()
+{{
+	local -a cmds
+	local cmd opts i
+	cmds=(
+"#);
+	for c in &spec.cmds
+	{
+		let d = format!("{}:{}", c.name, short(&c.about));
+		let _ = writeln!(s, "\t\t{}", squote(&d));
+	}
+	let _ = write!(s, r#"	)
+
+	# Find the command, skipping the global flags (and their values)
+	cmd=""
+	for (( i = 2; i < CURRENT; i++ )); do
+		case "${{words[i]}}" in
+"#);
+	let gv = spec.global_valued();
+	if !gv.is_empty()
+	{ let _ = writeln!(s, "\t\t\t{gv}) (( i++ )) ;;"); }
+	let _ = write!(s, r#"			-*) ;;
+			*) cmd="${{words[i]}}"; break ;;
+		esac
+	done
+
+	case "$cmd ${{words[CURRENT-1]}}" in
+"#);
+	let rel = spec.prev_patterns(true);
+	if !rel.is_empty()
+	{
+		let _ = writeln!(s, "\t\t{rel})\n\t\t\tcompadd -- \
+				${{(f)\"$({})\"}}\n\t\t\treturn ;;", release_cmd());
+	}
+	let val = spec.prev_patterns(false);
+	if !val.is_empty()
+	{ let _ = writeln!(s, "\t\t{val})\n\t\t\t_files\n\t\t\treturn ;;"); }
+	let _ = write!(s, r#"	esac
+
+	if [[ "$PREFIX" == -* ]]; then
+		case "$cmd" in
+{}		esac
+		compadd -- ${{=opts}}
+	elif [[ -z "$cmd" ]]; then
+		_describe -t commands '{PROG} command' cmds
+	else
+		_files
+	fi
+}}
+
+if [ "$funcstack[1]" = "_{PROG}" ]; then
+	{f} "$@"
+else
+	compdef {f} {PROG}
+fi
+"#, spec.flag_arms("opts", "\t\t\t"));
+	s
+}
+
+
+fn fish(spec: &Spec) -> String
+{
+	// Single-quoted for fish, which only escapes \ and ' in there.
+	let fq = |s: &str| format!("'{}'",
+			s.replace('\\', r"\\").replace('\'', r"\'"));
+
+	let mut s = format!("# fish completion for {PROG}\n\n");
+	let line = |s: &mut String, cond: &str, f: &Flag| {
+		let mut l = format!("complete -c {PROG} -n {}", fq(cond));
+		if let Some(c) = f.short { let _ = write!(l, " -s {c}"); }
+		if let Some(n) = &f.long { let _ = write!(l, " -l {n}"); }
+		match (f.value, f.release) {
+			(true, true) => { let _ = write!(l, " -x -a {}",
+					fq(&format!("({})", release_cmd()))); },
+			(true, false) => l.push_str(" -r"),
+			(false, _)    => (),
+		}
+		if !f.about.is_empty()
+		{ let _ = write!(l, " -d {}", fq(short(&f.about))); }
+		let _ = writeln!(s, "{l}");
+	};
+
+	for f in &spec.globals { line(&mut s, "__fish_use_subcommand", f); }
+	for c in &spec.cmds
+	{
+		let _ = writeln!(s, "complete -c {PROG} -n __fish_use_subcommand \
+				-f -a {} -d {}", c.name, fq(short(&c.about)));
+	}
+	for c in &spec.cmds
+	{
+		let cond = format!("__fish_seen_subcommand_from {}", c.name);
+		for f in &c.flags { line(&mut s, &cond, f); }
+	}
+	s
+}
+
+
+
+/*
+ * Release names
+ */
+
+/// Release names to offer for --release, from what we know locally:
+/// the running version, and whatever fetch/upgrade have seen.
+fn local_releases(clargs: &FrArgs) -> Vec<String>
+{
+	use crate::config;
+
+	// A broken or missing config shouldn't stop us; the defaults are
+	// probably where things are anyway.
+	let config = config::load_config_file(&clargs.config, clargs)
+			.unwrap_or_else(|_| config::with_args(Default::default(), clargs));
+	let vers = match &clargs.fakeversion {
+		Some(x) => crate::info::version::fake(x),
+		None    => crate::info::version::get(config.basedir()),
+	};
+	let vers = match vers {
+		Ok(v) => v,
+		Err(_) => return Vec::new(),
+	};
+
+	let sdir = crate::core::rtdirs::state_dir_for(config.basedir(),
+			config.workdir());
+	release_candidates(vers.max(), &seen_versions(&sdir))
+}
+
+
+/// Versions the state in a dir knows about.  This just reads; no
+/// locking, and no making or fixing anything.
+fn seen_versions(dir: &Path) -> Vec<AVersion>
+{
+	let state = match dir.is_dir() {
+		true  => crate::state::load_brief_from_dir(dir).ok(),
+		false => None,
+	};
+	let state = match state {
+		Some(s) => s,
+		None => return Vec::new(),
+	};
+
+	let mut ret = Vec::new();
+	ret.extend(state.meta_idx_vers.clone());
+	ret.extend(state.manifest_brief.as_ref().map(|b| b.version.clone()));
+	ret
+}
+
+
+/// Releases it'd make sense to upgrade to from cur: the next minor and
+/// major, the rest of the cycle if we're on a BETA/RC, and anything
+/// we've seen that's newer.
+fn release_candidates(cur: &AVersion, seen: &[AVersion]) -> Vec<String>
+{
+	let mk = |release: String, reltype: &str| AVersion { release,
+			reltype: reltype.to_string(), patch: None };
+	let cur = AVersion { patch: None, ..cur.clone() };
+	let mut ret = Vec::new();
+
+	let nums: Vec<u32> = cur.release.split('.')
+			.filter_map(|n| n.parse().ok()).collect();
+	if let [maj, min] = nums[..]
+	{
+		ret.push(mk(format!("{maj}.{}", min + 1), "RELEASE"));
+		ret.push(mk(format!("{}.0", maj + 1), "RELEASE"));
+	}
+
+	let rc = cur.reltype.strip_prefix("RC")
+			.and_then(|n| n.parse::<u32>().ok());
+	let next = match rc {
+		Some(n) => Some(format!("RC{}", n + 1)),
+		None if cur.reltype.starts_with("BETA") => Some("RC1".to_string()),
+		None => None,
+	};
+	if let Some(next) = next
+	{
+		ret.push(mk(cur.release.clone(), &next));
+		ret.push(mk(cur.release.clone(), "RELEASE"));
+	}
+
+	ret.extend(seen.iter().map(|v| AVersion { patch: None, ..v.clone() })
+			.filter(|v| *v > cur));
+	ret.sort();
+	ret.dedup();
+	ret.iter().map(|v| v.to_string()).collect()
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use crate::info::AVersion;
+
+	fn av(s: &str) -> AVersion { s.parse().unwrap() }
+
+	#[test]
+	fn release_candidates()
+	{
+		use super::release_candidates as rc;
+
+		assert_eq!(rc(&av("14.1-RELEASE-p6"), &[]),
+				["14.2-RELEASE", "15.0-RELEASE"]);
+		assert_eq!(rc(&av("14.2-RC2"), &[]),
+				["14.2-RC3", "14.2-RELEASE", "14.3-RELEASE", "15.0-RELEASE"]);
+		assert_eq!(rc(&av("15.0-BETA3"), &[]),
+				["15.0-RC1", "15.0-RELEASE", "15.1-RELEASE", "16.0-RELEASE"]);
+
+		// Seen ones come in if they're newer, without their patch
+		// level, and only once.
+		let seen = [av("14.1-RELEASE-p5"), av("14.3-RELEASE-p1"),
+				av("14.2-RELEASE")];
+		assert_eq!(rc(&av("14.1-RELEASE-p6"), &seen),
+				["14.2-RELEASE", "14.3-RELEASE", "15.0-RELEASE"]);
+	}
+
+	#[test]
+	fn seen_versions()
+	{
+		let td = tempfile::tempdir().unwrap();
+		assert!(super::seen_versions(&td.path().join("nope")).is_empty());
+
+		let mut state = crate::state::State::default();
+		state.meta_idx_vers = Some(av("14.2-RELEASE-p1"));
+		crate::state::save_to_dir(td.path(), &state).unwrap();
+		assert_eq!(super::seen_versions(td.path()),
+				[av("14.2-RELEASE-p1")]);
+	}
+
+	#[test]
+	fn spec()
+	{
+		let spec = super::Spec::load();
+		let names: Vec<&str> = spec.cmds.iter().map(|c| c.name.as_str())
+				.collect();
+		assert!(names.contains(&"upgrade"), "{names:?}");
+		assert!(!names.contains(&"__complete"), "{names:?}");
+		assert!(!names.contains(&"dump-metadata"), "{names:?}");
+
+		let clean = spec.cmds.iter().find(|c| c.name == "clean").unwrap();
+		let words = super::Spec::flag_words(&clean.flags);
+		assert!(words.contains("--pending"), "{words}");
+		assert!(words.contains("--discarded"), "{words}");
+
+		// Both --release's get release names
+		let rel = spec.prev_patterns(true);
+		assert!(rel.contains("\"upgrade -r\""), "{rel}");
+		assert!(rel.contains("\"patch-contents --release\""), "{rel}");
+		assert!(spec.global_valued().contains("--config"));
+	}
+
+	#[test]
+	fn scripts()
+	{
+		use super::*;
+		let spec = Spec::load();
+		for s in [sh(&spec), bash(&spec), zsh(&spec), fish(&spec)]
+		{
+			assert!(s.contains("__complete release-candidates"), "{s}");
+			assert!(s.contains("upgrade"), "{s}");
+			assert!(s.contains("show-install"), "{s}");
+			assert!(!s.contains("privsep-worker"), "{s}");
+		}
+		assert!(bash(&spec).contains("complete -F _freebsd_rustdate"));
+		assert!(zsh(&spec).starts_with("#compdef freebsd-rustdate\n"));
+	}
+}
@@ -0,0 +1,104 @@
+//! $0 config-check
+use crate::command::FrArgs;
+use crate::util::plural;
+
+
+/// Command: $0 config-check
+///
+/// Unlike everything else, this runs before (well, instead of) the
+/// normal config loading, since a config that won't load is exactly
+/// what we want to be able to look at.
+pub(crate) fn run(clargs: &FrArgs) -> Result<u8, anyhow::Error>
+{
+	let args = match &clargs.command {
+		crate::command::FrCmds::ConfigCheck(a) => a,
+		_ => unreachable!("I'm a config-check, why does it think I'm not??"),
+	};
+
+	let cfile = &clargs.config;
+	println!("Checking {}.", cfile.display());
+	let conf = match std::fs::read(cfile) {
+		Ok(c) => c,
+		Err(e) => {
+			println!("\nCan't read config file: {e}");
+			return Ok(1);
+		},
+	};
+
+	// Everything the parser found with the file itself
+	let (config, probs) = crate::config::parse(&conf);
+	let strs = |err: bool| -> Vec<String> {
+		probs.iter().filter(|p| p.is_error() == err).map(|p| p.to_string())
+				.collect()
+	};
+	let mut errs = strs(true);
+	let warns = strs(false);
+
+	// And then the things about the values, after any command-line
+	// overrides.
+	let config = crate::config::with_args(config, clargs);
+	macro_rules! check {
+		( $fld:ident) => {
+			match crate::check::$fld(&config) {
+				Ok(_) => (),
+				Err(e) => errs.push(e),
+			}
+		};
+	}
+	check!(keyprint);
+	check!(servername);
+	let wdok = crate::check::workdir(&config);
+	let bdok = crate::check::basedir(&config);
+
+	// Only worth looking at perms if they're there at all
+	for (ok, dir) in [(wdok, config.workdir()), (bdok, config.basedir())]
+	{
+		match ok {
+			Ok(_) => {
+				if let Err(e) = crate::check::writable(dir) { errs.push(e); }
+			},
+			Err(e) => errs.push(e),
+		}
+	}
+
+	// Optionally, see if the servername actually finds us any servers
+	if args.network && config.servername.len() > 0
+	{
+		let sname = &config.servername;
+		match crate::server::lookup::servers(sname, false) {
+			Ok(s) if s.is_empty() => errs.push(format!("No servers found \
+					for {sname}")),
+			Ok(s) => println!("{sname} resolves to {} server{}.", s.len(),
+					plural(s.len())),
+			Err(e) => errs.push(format!("Can't look up servers for \
+					{sname}: {e}")),
+		}
+	}
+
+
+	// OK, what have we got?
+	let (nerr, nwarn) = (errs.len(), warns.len());
+	if nerr == 0 && nwarn == 0
+	{
+		println!("No problems found.");
+		return Ok(0);
+	}
+
+	if nerr > 0
+	{
+		println!("\nErrors:");
+		errs.iter().for_each(|e| println!("  {e}"));
+	}
+	if nwarn > 0
+	{
+		println!("\nWarnings:");
+		warns.iter().for_each(|w| println!("  {w}"));
+	}
+	println!("\n{nerr} error{}, {nwarn} warning{}.", plural(nerr),
+			plural(nwarn));
+
+	Ok(match nerr {
+		0 => 0,
+		_ => 1,
+	})
+}
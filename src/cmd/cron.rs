@@ -50,16 +50,27 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// output.  In practice, I'm just gonna re-exec ourself as fetch and
 	// capture outputs.  That seems simpler.
 
-	// Sleep somewhere in the arena of an hour
-	if !crargs.immediately
+	// Sleep somewhere in the jitter window; an hour, unless somebody
+	// with a lot of machines hitting one mirror said otherwise.
+	let jitter = match crargs.immediately {
+		true  => 0,
+		false => crargs.jitter.unwrap_or(carg.config.cron_jitter),
+	};
+	if jitter > 0
 	{
 		use rand::{Rng, SeedableRng};
 		let mut rng = rand_pcg::Pcg64::from_entropy();
-		let sleep = rng.gen_range(0..3600) as u64;
+		let sleep = rng.gen_range(0..jitter);
 		let dur = std::time::Duration::from_secs(sleep);
 		std::thread::sleep(dur);
 	}
 
+	// If the last run is still going (slow mirror, say), wait our turn
+	// rather than piling on top of it.
+	use crate::core::lock::BasedirLock;
+	let wait = std::time::Duration::from_secs(carg.config.cron_lock_wait);
+	let _lock = BasedirLock::take(&rtdirs.lock_file(), wait)?;
+
 
 	// Exec
 	let myself = crate::util::argv_0().expect("Can't figure argv[0], bailing");
@@ -91,7 +102,20 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// it turned up something to do.
 	// x-ref this output in fetch::run().
 	let noup = "\nNo updates needed to update system to";
-	if foutstr.contains(noup)
+	let updates = !foutstr.contains(noup);
+
+	// Either way, it worked, so leave a note for anybody monitoring
+	// that we're still at it.
+	let state = rtdirs.state_load_brief()?;
+	let brief = state.manifest_brief;
+	let when = chrono::Utc::now().timestamp();
+	let mut marker = Marker::new(when, &carg.version.to_string(), updates,
+			brief.as_ref());
+	marker.bytes = state.bytes;
+	marker.servers = state.server_stats;
+	marker.write(&rtdirs.cron_marker())?;
+
+	if !updates
 	{
 		// Was nothing to do, exit cleanly.
 		return Ok(())
@@ -123,6 +147,64 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 
 
+/// What we leave in RtDirs::cron_marker() after every run that got
+/// through fetch OK, so monitoring can tell when runs stop happening.
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+struct Marker
+{
+	/// When it finished (unix time)
+	when: i64,
+
+	/// What we were running
+	version: String,
+
+	/// Whether fetch found something to install
+	updates: bool,
+
+	/// A line about it for humans
+	summary: String,
+
+	/// What the pending install will write out, if there is one
+	install_bytes: Option<u64>,
+
+	/// Everything fetch and upgrade have downloaded and stored so far
+	bytes: crate::util::bytecount::Bytes,
+
+	/// How each server did this time
+	servers: Vec<crate::core::pool::fetch::HostStats>,
+}
+
+impl Marker
+{
+	fn new(when: i64, version: &str, updates: bool,
+			brief: Option<&crate::state::ManifestBrief>) -> Self
+	{
+		let summary = match (updates, brief) {
+			(false, _) => "no updates needed".to_string(),
+			(true, None) => "updates pending".to_string(),
+			(true, Some(b)) => format!("pending {} to {}: {} added, {} \
+					removed, {} updated", b.mtype, b.version, b.added,
+					b.removed, b.updated),
+		};
+		let version = version.to_string();
+		let install_bytes = brief.and_then(|b| b.install_bytes);
+		Self { when, version, updates, summary, install_bytes,
+				bytes: Default::default(), servers: Vec::new() }
+	}
+
+	/// Write it out, replacing the last one all at once.
+	fn write(&self, file: &std::path::Path) -> Result<(), std::io::Error>
+	{
+		let json = serde_json::to_vec_pretty(self)?;
+		let tmpf = file.with_extension("json.tmp");
+		std::fs::write(&tmpf, json)?;
+		std::fs::rename(&tmpf, file)
+	}
+}
+
+
+
 /// Do some checks of our config/etc
 fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 {
@@ -143,10 +225,10 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 	check!(workdir);
 	check!(basedir);
 
-	// Should only run on releases (temporarily knocked off for dev)
+	// Should only run on releases
 	match crate::check::version(&carg.version) {
 		Ok(_) => (),
-		Err(_e) => (), // errs.push(e),
+		Err(e) => errs.push(e),
 	};
 
 
@@ -160,3 +242,59 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 		},
 	}
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::Marker;
+
+	#[test]
+	fn marker()
+	{
+		use crate::metadata::{Metadata, MetaFile};
+		use crate::state::Manifest;
+
+		let td = tempfile::tempdir().unwrap();
+		let mfile = td.path().join("cron-last.json");
+		let read = || -> serde_json::Value {
+			let s = std::fs::read_to_string(&mfile).unwrap();
+			serde_json::from_str(&s).unwrap()
+		};
+
+		// Nothing to do
+		let m = Marker::new(1700000000, "14.2-RELEASE-p1", false, None);
+		m.write(&mfile).unwrap();
+		let j = read();
+		assert_eq!(j["when"], 1700000000);
+		assert_eq!(j["version"], "14.2-RELEASE-p1");
+		assert_eq!(j["updates"], false);
+		assert_eq!(j["summary"], "no updates needed");
+		assert!(j["install_bytes"].is_null());
+		assert_eq!(j["bytes"]["downloaded"], 0);
+		assert!(j["servers"].as_array().unwrap().is_empty());
+
+		// Something pending replaces it
+		let mut new = Metadata::default();
+		let f = MetaFile { path: "/bin/x".into(), ..Default::default() };
+		new.files.insert("/bin/x".into(), f);
+		let mani = Manifest::new_fetch(Metadata::default(), new,
+				"14.2-RELEASE-p2".parse().unwrap());
+		let brief = mani.brief();
+		let mut m = Marker::new(1700003600, "14.2-RELEASE-p1", true,
+				Some(&brief));
+		m.servers = vec![crate::core::pool::fetch::HostStats {
+				host: "update1.example.org".into(), requests: 4, bytes: 9000,
+				errors: 1, total_ms: 200, mean_ms: 50 }];
+		m.write(&mfile).unwrap();
+		let j = read();
+		assert_eq!(j["when"], 1700003600);
+		assert_eq!(j["updates"], true);
+		assert_eq!(j["summary"], "pending fetch to 14.2-RELEASE-p2: 1 \
+				added, 0 removed, 0 updated");
+		assert_eq!(j["servers"][0]["host"], "update1.example.org");
+		assert_eq!(j["servers"][0]["mean_ms"], 50);
+		assert!(!td.path().join("cron-last.json.tmp").exists());
+	}
+}
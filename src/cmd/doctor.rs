@@ -0,0 +1,431 @@
+//! $0 doctor
+//!
+//! Looking for the states people tend to get stuck in, and getting them
+//! back out.  Each of these is something that's been walked through by
+//! hand enough times to be worth doing here instead.
+use std::path::PathBuf;
+
+use crate::command::CmdArg;
+use crate::core::RtDirs;
+use crate::info::version::AVersion;
+use crate::util::plural;
+
+
+/// Something we found.
+#[derive(Debug, PartialEq)]
+enum Problem
+{
+	/// The lockfile's held; something's running right now
+	LockHeld(Option<u32>),
+
+	/// The lockfile names a process that's gone
+	StaleLock(u32),
+
+	/// The statefile won't load; and whether the backup does
+	BadState { err: String, bak: bool },
+
+	/// The statefile's fine, but the pending manifest won't load
+	BadManifest(String),
+
+	/// Metadata files in files/ that don't match their names
+	BadMetafiles(Vec<PathBuf>),
+
+	/// The pending fetch/upgrade was made on something other than what's
+	/// running now
+	StalePending { mtype: &'static str, from: AVersion, running: AVersion },
+
+	/// An upgrade partway through installing
+	UpgradeMidway { state: &'static str, next: String },
+}
+
+impl Problem
+{
+	/// What's wrong
+	fn describe(&self) -> String
+	{
+		match self {
+			Self::LockHeld(pid) => {
+				let who = pid.map_or(String::new(), |p| format!(" (pid {p})"));
+				format!("Another run{who} is going right now; anything else \
+						can wait for it.")
+			},
+			Self::StaleLock(pid) => format!("The lockfile was left by pid \
+					{pid}, which isn't running any more.  That's harmless; \
+					the lock itself went with it."),
+			Self::BadState { err, .. } => format!("The statefile won't \
+					load: {err}"),
+			Self::BadManifest(err) => format!("The pending update won't \
+					load: {err}"),
+			Self::BadMetafiles(fs) => format!("{} metadata file{} don't \
+					match their hash:\n{}", fs.len(), plural(fs.len()),
+					fs.iter().map(|f| format!("  {}", f.display()))
+					.collect::<Vec<_>>().join("\n")),
+			Self::StalePending { mtype, from, running } => format!("The \
+					pending {mtype} was made on {from}, but the system is \
+					running {running} now; installing it would likely put \
+					older files over newer ones."),
+			Self::UpgradeMidway { state, next } => format!("An upgrade is \
+					partway installed ({state}).\nNext: {next}"),
+		}
+	}
+
+	/// What we'd do about it, if there's anything to do here.
+	fn fix(&self) -> Option<String>
+	{
+		match self {
+			Self::BadState { bak: true, .. } => Some("Restore the \
+					statefile (and pending manifest) from before the last \
+					save".to_string()),
+			Self::BadMetafiles(fs) => Some(format!("Delete the bad file{}, \
+					so the next fetch gets them again", plural(fs.len()))),
+			Self::StalePending { mtype, .. } => Some(format!("Clear the \
+					pending {mtype} (like `clean --pending`)")),
+			Self::LockHeld(_) | Self::StaleLock(_)
+					| Self::BadState { bak: false, .. }
+					| Self::BadManifest(_) | Self::UpgradeMidway { .. }
+					=> None,
+		}
+	}
+
+	/// Do the fix.
+	fn apply(&self, rtdirs: &RtDirs) -> Result<(), anyhow::Error>
+	{
+		match self {
+			Self::BadState { bak: true, .. } => {
+				crate::state::restore_backup(rtdirs.state())?;
+			},
+			Self::BadMetafiles(fs) => {
+				for f in fs { std::fs::remove_file(f)?; }
+			},
+			Self::StalePending { .. } => {
+				if let Some(mut st) = rtdirs.state_load_raw()?
+				{
+					st.manifest = None;
+					rtdirs.state_save(&st)?;
+				}
+			},
+			_ => unreachable!("Nothing to apply for {self:?}"),
+		}
+		Ok(())
+	}
+}
+
+
+/// What to do next, partway through installing an upgrade.
+fn next_step(world: bool, rebooted: bool, cmdname: &str) -> String
+{
+	match (world, rebooted) {
+		(false, false) => format!("reboot into the new kernel, then run \
+				`{cmdname} install` to install world."),
+		(false, true) => format!("run `{cmdname} install` to install \
+				world."),
+		(true, _) => format!("run `{cmdname} install` once more to finish \
+				up (removing old shared libs)."),
+	}
+}
+
+
+/// Look for everything we know about.  running is what the system's
+/// running (Version::max()), and kernel is the running kernel.
+fn diagnose(rtdirs: &RtDirs, running: &AVersion, kernel: &AVersion,
+		cmdname: &str) -> Result<Vec<Problem>, anyhow::Error>
+{
+	use crate::core::lock::{self, LockStatus as LS};
+	use crate::state::{self, Manifest, StateLoadErr as SLE};
+
+	let mut ret = Vec::new();
+
+	match lock::status(&rtdirs.lock_file())? {
+		LS::Held(pid) => ret.push(Problem::LockHeld(pid)),
+		LS::Free(Some(pid)) if !lock::pid_alive(pid)
+				=> ret.push(Problem::StaleLock(pid)),
+		_ => (),
+	}
+
+	// If the statefile's broken, there's no looking any further.
+	match state::check_statefile(rtdirs.state()) {
+		Ok(_) | Err(SLE::None) => (),
+		Err(e) => {
+			let bak = state::backup_loads(rtdirs.state());
+			ret.push(Problem::BadState { err: e.to_string(), bak });
+			return Ok(ret);
+		},
+	}
+	let st = match rtdirs.state_load_raw() {
+		Ok(Some(st)) => st,
+		Ok(None) => return Ok(ret),
+		Err(e) => {
+			ret.push(Problem::BadManifest(e.to_string()));
+			return Ok(ret);
+		},
+	};
+
+	if let Some(idx) = &st.meta_idx
+	{
+		let bad: Vec<PathBuf> = idx.get_matching(&["all", "new", "old"])
+				.into_iter().map(|h| h.to_buf())
+				.filter(|h| rtdirs.hashfile(h).is_file()
+						&& rtdirs.check_hashfile(h).is_err())
+				.map(|h| rtdirs.hashfile(&h)).collect();
+		if !bad.is_empty() { ret.push(Problem::BadMetafiles(bad)); }
+	}
+
+	if let Some(m) = &st.manifest
+	{
+		if let Some(r) = m.source_mismatch(running)
+		{
+			let from = m.from().expect("mismatch means we know").clone();
+			ret.push(Problem::StalePending { mtype: m.mtype(), from,
+					running: r.clone() });
+		}
+		else if let Manifest::Upgrade(u) = m
+		{
+			let to = m.version();
+			let rebooted = kernel.release == to.release
+					&& kernel.reltype == to.reltype;
+			if u.kernel
+			{
+				ret.push(Problem::UpgradeMidway { state: m.state(),
+						next: next_step(u.world, rebooted, cmdname) });
+			}
+		}
+	}
+
+	Ok(ret)
+}
+
+
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	let rtdirs = RtDirs::init(carg.config.basedir(), carg.config.workdir())?;
+
+	let CmdArg { clargs, config: _, version } = carg;
+	let args = match clargs.command {
+		crate::command::FrCmds::Doctor(a) => a,
+		_ => unreachable!("I'm a doctor, why does it think I'm not??"),
+	};
+	let cmdname = crate::util::cmdname();
+
+	println!("Currently running {version}.");
+	let probs = diagnose(&rtdirs, version.max(), &version.kernel, &cmdname)?;
+	if probs.is_empty()
+	{
+		println!("Nothing wrong that I know how to look for.");
+		return Ok(0);
+	}
+
+	let mut left = 0;
+	for p in &probs
+	{
+		println!("\n{}", p.describe());
+		let fix = match p.fix() {
+			Some(f) => f,
+			None => { left += 1; continue; },
+		};
+		if args.dry_run
+		{
+			println!("Would fix: {fix}.");
+			left += 1;
+			continue;
+		}
+		if !args.yes && !crate::util::confirm(&format!("{fix}?"))?
+		{
+			println!("Left alone.");
+			left += 1;
+			continue;
+		}
+		match p.apply(&rtdirs) {
+			Ok(_) => println!("Fixed."),
+			Err(e) => { println!("Couldn't: {e}"); left += 1; },
+		}
+	}
+
+	// Midway through an upgrade isn't something wrong, just something
+	// to know, so it doesn't count; likewise who has or had the lock.
+	let info = probs.iter().filter(|p| matches!(p,
+			Problem::UpgradeMidway { .. } | Problem::LockHeld(_)
+			| Problem::StaleLock(_))).count();
+	Ok(match left > info { true => 1, false => 0 })
+}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::{diagnose, Problem, RtDirs};
+	use crate::info::version::AVersion;
+	use crate::metadata::Metadata;
+	use crate::state::{State, Manifest};
+	use std::path::PathBuf;
+
+	fn av(s: &str) -> AVersion { s.parse().unwrap() }
+
+	/// The statefile, to mangle
+	fn statefile(rtdirs: &RtDirs) -> PathBuf
+	{
+		rtdirs.state().join("freebsd_rustdate_state.json")
+	}
+
+	/// A workdir with an rtdirs in it
+	fn setup() -> (tempfile::TempDir, tempfile::TempDir, RtDirs)
+	{
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+		(base, wd, rtdirs)
+	}
+
+	fn diag(rtdirs: &RtDirs, running: &str) -> Vec<Problem>
+	{
+		let r = av(running);
+		diagnose(rtdirs, &r, &r, "fr").unwrap()
+	}
+
+	/// Diagnose, fix everything fixable, and diagnose again
+	fn fix_all(rtdirs: &RtDirs, running: &str) -> Vec<Problem>
+	{
+		for p in diag(rtdirs, running)
+		{
+			if p.fix().is_some() { p.apply(rtdirs).unwrap(); }
+		}
+		diag(rtdirs, running)
+	}
+
+	#[test]
+	fn healthy()
+	{
+		let (_b, _w, rtdirs) = setup();
+		assert_eq!(diag(&rtdirs, "14.2-RELEASE"), []);
+
+		let st = State::default();
+		rtdirs.state_save(&st).unwrap();
+		assert_eq!(diag(&rtdirs, "14.2-RELEASE"), []);
+	}
+
+	#[test]
+	fn stale_lock()
+	{
+		let (_b, _w, rtdirs) = setup();
+		let mut child = std::process::Command::new("true").spawn().unwrap();
+		let dead = child.id();
+		child.wait().unwrap();
+		std::fs::write(rtdirs.lock_file(), format!("{dead}\n")).unwrap();
+
+		// Just said; the file's harmless, so it's left be
+		assert_eq!(diag(&rtdirs, "14.2-RELEASE"), [Problem::StaleLock(dead)]);
+		assert_eq!(Problem::StaleLock(dead).fix(), None);
+		assert_eq!(fix_all(&rtdirs, "14.2-RELEASE"),
+				[Problem::StaleLock(dead)]);
+		assert!(rtdirs.lock_file().exists());
+
+		// A live one isn't touched
+		use crate::core::lock::BasedirLock;
+		let _l = BasedirLock::take(&rtdirs.lock_file(),
+				std::time::Duration::ZERO).unwrap();
+		let me = std::process::id();
+		assert_eq!(fix_all(&rtdirs, "14.2-RELEASE"),
+				[Problem::LockHeld(Some(me))]);
+	}
+
+	#[test]
+	fn bad_state()
+	{
+		let (_b, _w, rtdirs) = setup();
+		let mut st = State::default();
+		rtdirs.state_save(&st).unwrap();
+
+		// No backup yet; nothing to do but say so
+		std::fs::write(statefile(&rtdirs), b"{").unwrap();
+		let probs = fix_all(&rtdirs, "14.2-RELEASE");
+		assert!(matches!(probs[..], [Problem::BadState { bak: false, .. }]),
+				"{probs:?}");
+
+		// With one, go back to it
+		rtdirs.state_save(&st).unwrap();
+		st.kept_libs = vec!["/lib/x.so.1".into()];
+		rtdirs.state_save(&st).unwrap();
+		std::fs::write(statefile(&rtdirs), b"{").unwrap();
+		let probs = diag(&rtdirs, "14.2-RELEASE");
+		assert!(matches!(probs[..], [Problem::BadState { bak: true, .. }]),
+				"{probs:?}");
+		assert_eq!(fix_all(&rtdirs, "14.2-RELEASE"), []);
+		assert!(rtdirs.state_load_raw().unwrap().unwrap().kept_libs
+				.is_empty());
+	}
+
+	#[test]
+	fn bad_metafiles()
+	{
+		use crate::metadata::MetadataIdx;
+		use crate::util::compress::{gz_bytes, write_hashfile};
+
+		let (_b, _wd, rtdirs) = setup();
+
+		// One that's right, one that isn't
+		let gh = write_hashfile(rtdirs.files(), b"good\n");
+		let bh = "0".repeat(64);
+		let idx = MetadataIdx::parse(format!("INDEX-ALL|{gh}\n\
+				INDEX-NEW|{bh}\n").as_bytes()).unwrap();
+		let hs = idx.get_matching(&["all", "new"]);
+		let (gf, bf) = (rtdirs.hashfile(&hs[0].to_buf()),
+				rtdirs.hashfile(&hs[1].to_buf()));
+		std::fs::write(&bf, gz_bytes(b"good\n")).unwrap();
+
+		let st = State { meta_idx: Some(idx), ..Default::default() };
+		rtdirs.state_save(&st).unwrap();
+		assert_eq!(diag(&rtdirs, "14.2-RELEASE"),
+				[Problem::BadMetafiles(vec![bf.clone()])]);
+		assert_eq!(fix_all(&rtdirs, "14.2-RELEASE"), []);
+		assert!(gf.is_file());
+		assert!(!bf.exists());
+	}
+
+	#[test]
+	fn pending()
+	{
+		let (_b, _w, rtdirs) = setup();
+
+		// A fetch from some other version
+		let mut m = Manifest::new_fetch(Metadata::default(),
+				Metadata::default(), av("14.1-RELEASE-p7"));
+		m.set_from(av("14.1-RELEASE-p6"));
+		let st = State { manifest: Some(m), ..Default::default() };
+		rtdirs.state_save(&st).unwrap();
+		assert_eq!(diag(&rtdirs, "14.1-RELEASE-p6"), []);
+		let probs = diag(&rtdirs, "14.2-RELEASE");
+		assert!(matches!(probs[..], [Problem::StalePending { .. }]),
+				"{probs:?}");
+		assert_eq!(fix_all(&rtdirs, "14.2-RELEASE"), []);
+		assert!(rtdirs.state_load_raw().unwrap().unwrap().manifest.is_none());
+
+		// An upgrade with the kernel in
+		let mut m = Manifest::new_upgrade(Metadata::default(),
+				Metadata::default(), av("14.2-RELEASE"),
+				Default::default(), Default::default());
+		m.set_from(av("14.1-RELEASE-p6"));
+		if let Manifest::Upgrade(u) = &mut m { u.kernel = true; }
+		let st = State { manifest: Some(m), ..Default::default() };
+		rtdirs.state_save(&st).unwrap();
+
+		// Not rebooted yet, then rebooted
+		let r = av("14.1-RELEASE-p6");
+		let probs = diagnose(&rtdirs, &r, &r, "fr").unwrap();
+		match &probs[..] {
+			[Problem::UpgradeMidway { next, .. }] => assert!(
+					next.starts_with("reboot"), "{next}"),
+			_ => panic!("{probs:?}"),
+		}
+		let k = av("14.2-RELEASE");
+		let probs = diagnose(&rtdirs, &k, &k, "fr").unwrap();
+		match &probs[..] {
+			[Problem::UpgradeMidway { next, .. }] => assert_eq!(next,
+					"run `fr install` to install world."),
+			_ => panic!("{probs:?}"),
+		}
+
+		// Nothing to fix there, just something to know
+		assert_eq!(fix_all(&rtdirs, "14.2-RELEASE").len(), 1);
+	}
+}
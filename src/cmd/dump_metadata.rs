@@ -9,7 +9,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 {
 	// Setup dirs
 	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
-			&carg.config.workdir())?;
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
 
 	// Split up and extract our bits
 	let CmdArg { clargs, config, version: _ } = carg;
@@ -23,61 +24,48 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// Find server to get info from
 	let version = &dmargs.version;
 	println!("Loading info for {version}.");
-	let mut server = crate::server::Server::find(&config.servername,
-			&version, &config.keyprint)?;
+	let mut server = crate::server::Server::find_cached_rt(&config,
+			&version, &rtdirs, false)?;
 	server.set_filesdir(rtdirs.files().to_path_buf());
-	let metadatas = &["all", "old", "new"];
-
-	print!("Loading metadata index for {version}...");
-	stdout().flush()?;
-	let mdidx = server.get_metadata_idx()?;
-	println!("   OK.");
-
-	print!("Getting metadata files for {version}...  ");
-	stdout().flush()?;
-	let metamiss = mdidx.not_in_dir(rtdirs.files(), metadatas);
-	match metamiss.len()
-	{
-		0 => println!("All present."),
-		_ => {
-			println!("{} missing.", metamiss.len());
-
-			// So grab 'em.
-			println!("Fetching...");
-			let files = metamiss.into_iter().collect();
-			server.fetch_metafiles(files)?;
-			println!("Done.");
-		},
-	};
-
-	print!("Checking metadata file hashes...  ");
-	stdout().flush()?;
-	let hres = {
-		let fd = rtdirs.files();
-		let td = rtdirs.tmp();
-		mdidx.check_hashes(fd, td, metadatas)
-	};
-	match hres {
-		Ok(_) => println!("   OK."),
-		Err(e) => {
-			println!("   Errors found.\n{}", e.join("\n"));
-			anyhow::bail!("Invalid metafiles, bailing.");
-		},
+	let metadatas: Vec<&str> = dmargs.which.iter().map(|w| w.as_ref())
+			.collect();
+	let metadatas = &metadatas[..];
+
+	let mdidx = {
+		use crate::core::mdfetch;
+		let mut report = mdfetch::printer(
+				&format!("metadata index for {version}"),
+				&format!("metadata files for {version}"));
+		mdfetch::fetch_files(&mut server, &rtdirs, metadatas, None,
+				&mut report)?
 	};
 
 
 	// And now save out the files.
+	use crate::command::DumpMetadataFormat as DMF;
 	let tmpdir = rtdirs.tmp();
 	let outdir = &dmargs.dir;
-	print!("Writing out metadata files to {}...\n    ", outdir.display());
+	let ext = match dmargs.format {
+		DMF::Raw => "".to_string(),
+		f => format!(".{f}"),
+	};
+	print!("Writing out {} metadata files to {}...\n    ", dmargs.format,
+			outdir.display());
 	stdout().flush()?;
 	for md in metadatas
 	{
 		let infile = mdidx.one_tmpfile(tmpdir, md).unwrap();
-		let outfname = format!("fupd-md-index-{md}");
+		let outfname = format!("fupd-md-index-{md}{ext}");
 		let outfile = outdir.join(&outfname);
 
-		std::fs::copy(&infile, &outfile)?;
+		match dmargs.format
+		{
+			DMF::Raw => { std::fs::copy(&infile, &outfile)?; },
+			fmt => {
+				let flat = flatten_one(&mdidx, tmpdir, md, &dmargs.component)?;
+				write_flat(&outfile, fmt, &flat)?;
+			},
+		}
 		print!(" {outfname}");
 		stdout().flush()?;
 	}
@@ -86,3 +74,55 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	println!("\n\nDone.");
 	Ok(())
 }
+
+
+
+/// Parse out one metadata file, and flatten it down.
+fn flatten_one(mdidx: &crate::metadata::MetadataIdx, tmpdir: &std::path::Path,
+		which: &str, comps: &[crate::components::Component])
+		-> Result<Vec<crate::metadata::FlatLine>, anyhow::Error>
+{
+	let mut mdg = match mdidx.parse_one(tmpdir, which) {
+		Ok(m) => m,
+		Err(e) => {
+			println!("");
+			eprintln!("Errors parsing {which}:");
+			e.iter().for_each(|e| eprintln!("  {e}"));
+			anyhow::bail!("Invalid metadata file, bailing.");
+		},
+	};
+
+	if !comps.is_empty()
+	{
+		let keep = comps.iter().cloned().collect();
+		mdg.keep_components(&keep);
+	}
+
+	Ok(mdg.flatten())
+}
+
+
+/// Write out flattened metadata in the requested format.
+fn write_flat(file: &std::path::Path, fmt: crate::command::DumpMetadataFormat,
+		flat: &[crate::metadata::FlatLine]) -> Result<(), anyhow::Error>
+{
+	use crate::command::DumpMetadataFormat as DMF;
+	use crate::metadata::FlatLine;
+
+	let fh = std::fs::File::create(file)?;
+	let mut fh = std::io::BufWriter::new(fh);
+	match fmt
+	{
+		DMF::Json => {
+			serde_json::to_writer_pretty(&mut fh, flat)?;
+			writeln!(fh, "")?;
+		},
+		DMF::Csv => {
+			writeln!(fh, "{}", FlatLine::CSV_HEADER)?;
+			for l in flat { writeln!(fh, "{}", l.csv_row())?; }
+		},
+		DMF::Raw => unreachable!("raw doesn't get flattened"),
+	}
+	fh.flush()?;
+	Ok(())
+}
@@ -0,0 +1,120 @@
+//! $0 export-pending
+use crate::command::CmdArg;
+use crate::core::RtDirs;
+use crate::core::bundle;
+use crate::state::Manifest;
+use crate::util::hash::Sha256HashBuf;
+
+use anyhow::bail;
+
+
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	// Setup dirs
+	let rtdirs = RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?;
+
+	// Split up
+	let CmdArg { clargs, config, version } = carg;
+
+	// Extract our own args
+	let args = match &clargs.command {
+		crate::command::FrCmds::ExportPending(a) => a,
+		_ => unreachable!("I'm an export-pending, why does it think I'm not??"),
+	};
+
+	let mut state = match rtdirs.state_load_raw()? {
+		Some(s) => s,
+		None => bail!("No state to load; no fetch/upgrade has been run?"),
+	};
+
+	// The bundle says it's all under our KeyPrint, so don't go passing
+	// along an index that wasn't.
+	if let Some(n) = state.keyprint_check(&config.keyprint)
+	{ println!("{n}"); }
+	let manifest = match &state.manifest {
+		Some(m) => m,
+		None => {
+			println!("No install pending.");
+			return Ok(1);
+		},
+	};
+
+	// Upgrades need to be ready to go from the start.  Resolving merges
+	// takes files that aren't going in the bundle, and a half-installed
+	// one only makes sense on the system it's half-installed on.
+	let cmdname = crate::util::cmdname();
+	if let Manifest::Upgrade(mup) = manifest
+	{
+		if mup.merge_conflict.len() > 0
+		{
+			bail!("Pending upgrade has unresolved merge conflicts; run \
+					`{cmdname} resolve-merges` first.");
+		}
+		if mup.kernel
+		{ bail!("Pending upgrade is already partly installed here."); }
+
+		// The other side redoes merges to check them, which it can't do
+		// with one done by hand.
+		if mup.merge_clean.values().any(|c| c.resolved)
+		{
+			bail!("Pending upgrade has merges resolved by hand, which \
+					can't be checked on the other side.");
+		}
+	}
+
+	// What install needs (and the merge inputs, to redo them), plus the
+	// metadata files we got it from.
+	let mut hashes: Vec<Sha256HashBuf> = manifest.cache_hashes().iter()
+			.map(|h| h.to_buf()).collect();
+	hashes.sort_unstable();
+	let missing = hashes.iter().filter(|h| !rtdirs.hashfile(h).is_file())
+			.count();
+	if missing > 0
+	{
+		bail!("{missing} file{} needed for the pending install missing \
+				from {}; try re-running the fetch/upgrade.",
+				crate::util::plural(missing), rtdirs.files().display());
+	}
+	if let Some(idx) = &state.meta_idx
+	{
+		// The one the manifest gets checked against has to go.
+		let which = bundle::signed_which(manifest);
+		let need = idx.get_matching(&[which]);
+		if need.iter().any(|h| !rtdirs.hashfile(&h.to_buf()).is_file())
+		{
+			bail!("Metadata file INDEX-{} missing from {}; try re-running \
+					the fetch/upgrade.", which.to_uppercase(),
+					rtdirs.files().display());
+		}
+
+		let mdhashes = idx.get_matching(&["all", "new", "old"]).into_iter()
+				.map(|h| h.to_buf())
+				.filter(|h| rtdirs.hashfile(h).is_file());
+		hashes.extend(mdhashes);
+	}
+
+	let arch = crate::info::kernel::arch()?;
+	let mut hdr = bundle::Header::new(manifest, version.clone(),
+			config.keyprint.clone(), arch, hashes.len());
+	hdr.meta_idx = state.meta_idx.clone();
+	hdr.meta_idx_vers = state.meta_idx_vers.clone();
+
+	// The other side only takes our word for any of it if the signed
+	// bits it came from go along, so make sure they still hang together
+	// before going to the trouble.
+	let signed = crate::server::SignedFiles::load(&rtdirs.signed_dir())
+			.map_err(|e| anyhow::anyhow!("No saved signed tag ({e}); \
+					try re-running the fetch/upgrade."))?;
+	if let Err(e) = bundle::verify_signed(&hdr, &signed, &config.keyprint)
+	{ bail!("{e}; try re-running the fetch/upgrade."); }
+
+	println!("Exporting pending {} from {version} to {} ({} files)...",
+			hdr.mtype, hdr.to, hashes.len());
+	bundle::export(&args.output, args.zstd, &hdr, manifest, &signed,
+			&hashes, &rtdirs)?;
+	println!("Wrote {}.  Run `{cmdname} import-pending` with it on the \
+			target system.", args.output.display());
+
+	Ok(0)
+}
@@ -1,9 +1,15 @@
 //! $0 extract
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::{stdout, Write as _};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::command::CmdArg;
+use crate::config::Config;
+use crate::core::RtDirs;
+use crate::info::version::{Version, AVersion};
+use crate::metadata::MetadataIdx;
+use crate::server::Server;
+use crate::state::State;
 
 use anyhow::bail;
 
@@ -14,10 +20,9 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	check(&carg)?;
 
 	// Setting up various dirs
-	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
-			&carg.config.workdir())?;
-
-	// No state for this cmd
+	let rtdirs = RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
 
 	// I'm gonna need to know my command name in a few places, so just
 	// pre-figure it...
@@ -78,72 +83,105 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// Show our starting point
 	println!("Currently running {version}.");
 
-	// Find the server
-	let mut server = crate::server::Server::find(&config.servername,
-			&version.kernel, &config.keyprint)?;
+	// Going back to an earlier patch level?
+	let at = args.at_patch.map(|p| AVersion { patch: (p > 0).then_some(p),
+			..version.kernel.clone() });
+	if let Some(vers) = &at { println!("Extracting from {vers}."); }
 
-	// Set copy of dirnames the server object accesses internally
-	server.set_filesdir(rtdirs.files().to_path_buf());
+	// All we need here is the INDEX-ALL
+	let metadatas = &["all"];
 
+	// We may not need to talk to the server at all, if we've already got
+	// the metadata for this version downloaded, so we only find it when
+	// we need it.
+	let mut server = None;
 
 
 	/*
 	 * Load the metadata
 	 */
-	// Load up the metadata index stuff
-	print!("Loading metadata index...");
-	stdout().flush()?;
-	let mdidx = server.get_metadata_idx()?;
-	println!("   OK.");
-
-	// All we need here is the INDEX-ALL
-	let metadatas = &["all"];
-
-	// Get the one we need
-	print!("Getting all metadata files...  ");
-	stdout().flush()?;
-	let metamiss = {
-		let fd = rtdirs.files();
-		let missing = mdidx.not_in_dir(fd, metadatas);
-		missing
+	// We just want the saved index, not any pending manifest
+	let mut state = rtdirs.state_load_brief()?;
+	if let Some(n) = state.keyprint_check(&config.keyprint)
+	{ println!("{n}"); }
+
+	// If we're working from dist sets, that's all we need.
+	let dist = match &args.dist_dir {
+		Some(dd) => Some(crate::core::dist::acquire(dd, &rtdirs, &config)?),
+		None => None,
+	};
+	let local = match args.refresh_metadata || dist.is_some()
+			|| args.at_patch.is_some() {
+		true  => None,
+		false => local_mdidx(&state, &rtdirs, &version.kernel, metadatas),
 	};
-	match metamiss.len()
-	{
-		0 => println!("All present."),
-		_ => {
-			println!("{} missing.", metamiss.len());
 
-			// So grab 'em.
-			println!("Fetching...");
-			let files = metamiss.into_iter().collect();
-			server.fetch_metafiles(files)?;
-			println!("Done.");
+	let mdidx = match local {
+		_ if dist.is_some() => None,
+		Some(idx) => {
+			println!("Using already downloaded metadata.");
+			Some(idx)
 		},
-	};
+		None => {
+			let server = server.insert(find_server(&config, &version,
+					&rtdirs, &mut state)?);
+
+			// An earlier patch level is its own thing; don't go
+			// caching it as what we're running.
+			use crate::core::mdfetch;
+			let mdidx = match &at {
+				Some(vers) => {
+					let idx = mdfetch::idx_at(server, &rtdirs,
+							&config.keyprint, vers)?;
+					let mut src = mdfetch::WithIdx { src: server, idx };
+					mdfetch::fetch_files(&mut src, &rtdirs, metadatas, None,
+							&mut mdfetch::default_printer())
+							.map_err(|e| gone(e, &format!("{vers} metadata \
+								files")))?
+				},
+				None => {
+					let mdidx = mdfetch::fetch_files(server, &rtdirs,
+							metadatas, None,
+							&mut mdfetch::default_printer())?;
+
+					// Stash it for next time.  If what's saved is from
+					// this same index, we just add to it; otherwise this
+					// replaces it, along with the signed bits for audit.
+					let vers = version.kernel.clone();
+					let idx = mdidx.clone_matching(metadatas);
+					let same = state.meta_idx_vers.as_ref() == Some(&vers)
+							&& state.meta_idx.as_ref()
+								.is_some_and(|mi| mi.mismatches(&mdidx).is_empty());
+					match (same, &mut state.meta_idx) {
+						(true, Some(mi)) => mi.update_from(&idx),
+						_ => {
+							state.meta_idx = Some(idx);
+							state.meta_idx_vers = Some(vers);
+							state.meta_idx_keyprint = Some(config.keyprint.clone());
+							server.signed_files()?.save(&rtdirs.signed_dir())?;
+						},
+					}
+					rtdirs.state_save(&state)?;
+					mdidx
+				},
+			};
 
-	// Check all the metafiles hashes
-	print!("Checking metadata file hashes...  ");
-	stdout().flush()?;
-	let hres = {
-		let fd = rtdirs.files();
-		let td = rtdirs.tmp();
-		mdidx.check_hashes(fd, td, metadatas)
-	};
-	match hres {
-		Ok(_) => println!("   OK."),
-		Err(e) => {
-			println!("   Errors found.\n{}", e.join("\n"));
-			bail!("Invalid metafiles, bailing.");
+			Some(mdidx)
 		},
 	};
 
 
 	// Parse out the metadata
-	print!("Parsing metadata files...  ");
-	stdout().flush()?;
-	let mut all = mdidx.parse_one_full("all", rtdirs.tmp(), &config)?;
-	print!(" all");
-	println!("   OK.");
+	let (mut all, dsets) = match (mdidx, dist) {
+		(Some(mdidx), _) => {
+			use crate::core::mdfetch;
+			let mut acq = mdfetch::parse(mdidx, &rtdirs, &config, metadatas,
+					&mut mdfetch::default_printer())?;
+			(acq.take("all"), None)
+		},
+		(None, Some(dm)) => (dm.all, Some(dm.sets)),
+		(None, None) => unreachable!("Have to have gotten metadata somewhere"),
+	};
 
 
 	// Unlike most other commands, we're only conditionally trimming
@@ -187,7 +225,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		println!("\nFiltering components...");
 
 		// The src thing
-		config.finalize_components();
+		if let Some(n) = config.finalize_components() { println!("{n}"); }
 
 		// Now compare to the scan
 		let curpaths = cur.allpaths_hashset_nodash();
@@ -267,6 +305,23 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		return Ok(());
 	}
 
+	// A slightly-off regex can match a whole lot more than intended, so
+	// make sure that's really what's wanted before we go overwriting
+	// things.
+	if !dry && args.limit > 0 && npaths > args.limit
+	{
+		println!("\n{npaths} paths matched, more than the limit of {}:",
+				args.limit);
+		for l in scope_sample(all.allpaths(), 5) { println!("  {l}"); }
+
+		if !args.yes_really && !crate::util::confirm("Proceed?")?
+		{
+			eprintln!("\nNot extracting.  Check your paths with --dry-run, \
+					or use --yes-really or a larger --limit.");
+			bail!("too many paths matched");
+		}
+	}
+
 
 
 	/*
@@ -311,65 +366,136 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 
 	/*
-	 * Fetch necessary hashes
+	 * And the rest is the same as check-sys --fix.
 	 */
-	let mut needhashes = all.hashes_no_hash_dir(rtdirs.files());
-	if dry
-	{
-		if let Some(nh) = needhashes
-		{
-			let nh = nh.len();
-			println!("DRY RUN: {nh} file{} would need to be downloaded.",
-					plural(nh));
-			needhashes = None;
+	use crate::core::extract;
+	let src = match &dsets {
+		Some(sets) => extract::Source::Dist(sets),
+		None => {
+			let (cfg, vers, rtd) = (&config, &version, &rtdirs);
+			let st = &mut state;
+			extract::Source::Server(Box::new(move || match server {
+				Some(s) => Ok(s),
+				None => find_server(cfg, vers, rtd, st),
+			}))
+		},
+	};
+	let hashpaths = at.as_ref().map(|_| hash_paths(&all));
+	extract::from_metadata(all, &rtdirs, config.basedir(), src, dry,
+			args.ownership_manifest.as_deref()).map_err(|e| {
+		match (&at, &hashpaths) {
+			(Some(vers), Some(hp)) => gone_files(e, vers, hp),
+			_ => e,
 		}
-	}
-	if let Some(nh) = needhashes
+	})?;
+	if dry { return Ok(()); }
+
+	println!("\nDone.");
+	Ok(())
+}
+
+
+/// A sample of a big set of paths for sanity-checking: the first and
+/// last `each` of them, sorted.
+fn scope_sample(mut paths: Vec<&Path>, each: usize) -> Vec<String>
+{
+	paths.sort_unstable();
+	let show = |ps: &[&Path]| -> Vec<String> {
+		ps.iter().map(|p| p.display().to_string()).collect()
+	};
+
+	let n = paths.len();
+	if n <= each * 2 { return show(&paths); }
+
+	let mut ret = show(&paths[..each]);
+	ret.push(format!("... ({} more) ...", n - each * 2));
+	ret.extend(show(&paths[n - each..]));
+	ret
+}
+
+
+/// What .gz files on the server have the contents for what paths.
+fn hash_paths(all: &crate::metadata::Metadata)
+		-> HashMap<String, Vec<PathBuf>>
+{
+	let mut ret: HashMap<String, Vec<PathBuf>> = HashMap::new();
+	for f in all.files.values()
 	{
-		// All encapsulated up, just build the control with the dirs and
-		// kick it off.
-		use crate::core::pool::hashcheck as hcp;
-		use crate::core::hashfetch as hf;
-
-		// It's a little layer-violate-y that we expose this up here, but
-		// we need to get all this info in anyway, so might as well
-		// package it up.
-		let tmpdir = rtdirs.tmp().to_path_buf();
-		let filesdir = rtdirs.files().to_path_buf();
-		let keep = false;
-		let ctrl = hcp::Control { tmpdir, filesdir, keep };
-
-		hf::get(&server, nh, ctrl)?;
+		ret.entry(format!("{}.gz", f.sha256.to_buf())).or_default()
+				.push(f.path.clone());
 	}
-	else
-	{
-		println!("All data files present.");
+	ret.values_mut().for_each(|ps| ps.sort_unstable());
+	ret
+}
+
+
+/// If fetching what failed because the server doesn't have some files
+/// (anymore), say which.
+fn gone(e: anyhow::Error, what: &str) -> anyhow::Error
+{
+	let nf = crate::core::pool::fetch::not_found(&e);
+	match nf.len() {
+		0 => e,
+		_ => anyhow::anyhow!("The server no longer has these {what}: {}",
+				nf.join(", ")),
 	}
-	println!("");
+}
 
 
-	/*
-	 * Now we know everything about what we want to do.  If we're dry
-	 * running, just say it.  Else, do it.
-	 */
+/// Same for file contents, but by path, which is a lot more use.
+fn gone_files(e: anyhow::Error, vers: &AVersion,
+		hashpaths: &HashMap<String, Vec<PathBuf>>) -> anyhow::Error
+{
+	let nf = crate::core::pool::fetch::not_found(&e);
+	if nf.is_empty() { return e; }
+
+	let mut lines: Vec<String> = nf.iter().flat_map(|h| {
+		match hashpaths.get(h) {
+			Some(ps) => ps.iter().map(|p| format!("  {} ({h})",
+					p.display())).collect(),
+			None => vec![format!("  ({h})")],
+		}
+	}).collect();
+	lines.sort_unstable();
+	anyhow::anyhow!("The server no longer has the {vers} contents of:\n{}",
+			lines.join("\n"))
+}
 
-	if dry
-	{
-		println!("DRY RUN: Would install the following:");
-		let mut paths = all.allpaths();
-		paths.sort_unstable();
-		for p in paths { println!("  {}", p.display()); }
-		return Ok(());
-	}
 
-	// Reuse bits from install
-	use crate::core::install;
-	println!("Installing files");
-	let isplit = all.into_split_types();
-	install::split(isplit, &rtdirs, config.basedir(), false)?;
+/// Find a server to talk to.
+fn find_server(config: &Config, version: &Version, rtdirs: &RtDirs,
+		state: &mut State)
+		-> Result<Server, anyhow::Error>
+{
+	let mut server = Server::find_cached(config, &version.kernel, state,
+			false)?;
+	rtdirs.state_save(state)?;
 
-	println!("\nDone.");
-	Ok(())
+	// Set copy of dirnames the server object accesses internally
+	server.set_filesdir(rtdirs.files().to_path_buf());
+	server.set_partialdir(rtdirs.partial_dir());
+	Ok(server)
+}
+
+
+/// Try to find usable metadata for our version that we've already got
+/// downloaded, from the index an earlier run saved.  If there's anything
+/// missing or wrong about it, we just say "no", and the caller can go to
+/// the server.
+fn local_mdidx(state: &State, rtdirs: &RtDirs, vers: &AVersion,
+		which: &[&str]) -> Option<MetadataIdx>
+{
+	if state.meta_idx_vers.as_ref() != Some(vers) { return None; }
+	let idx = state.meta_idx.as_ref()?;
+	if idx.get_matching(which).len() != which.len() { return None; }
+	let cache = rtdirs.mdcache();
+	if idx.not_in_dir(rtdirs.files(), cache, which).len() > 0 { return None; }
+
+	// check_hashes() removes any mismatched files, so a bad one will be
+	// re-fetched when we go to the server.
+	idx.check_hashes(rtdirs.files(), rtdirs.tmp(), cache, which).ok()?;
+
+	Some(idx.clone())
 }
 
 
@@ -393,10 +519,10 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 	check!(workdir);
 	check!(basedir);
 
-	// Should only run on releases (temporarily knocked off for dev)
+	// Should only run on releases
 	match crate::check::version(&carg.version) {
 		Ok(_) => (),
-		Err(_e) => (), // errs.push(e),
+		Err(e) => errs.push(e),
 	};
 
 
@@ -410,3 +536,66 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 		},
 	}
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn scope_sample()
+	{
+		use std::path::Path;
+		use super::scope_sample;
+
+		let names: Vec<_> = (0..20).rev().map(|i| format!("/p/{i:02}"))
+				.collect();
+		let paths: Vec<&Path> = names.iter().map(Path::new).collect();
+
+		// Sorted, first and last few
+		let got = scope_sample(paths.clone(), 2);
+		assert_eq!(got, ["/p/00", "/p/01", "... (16 more) ...", "/p/18",
+				"/p/19"]);
+
+		// Small enough, it's all of them
+		let got = scope_sample(paths[..4].to_vec(), 2);
+		assert_eq!(got, ["/p/16", "/p/17", "/p/18", "/p/19"]);
+	}
+
+	#[test]
+	fn gone_files()
+	{
+		use crate::core::pool::fetch::{GetErr, PoolErrs};
+		use crate::metadata::{Metadata, MetaFile};
+		use crate::util::hash::sha256_reader;
+
+		let file = |p: &str, c: &str| -> (std::path::PathBuf, MetaFile) {
+			(p.into(), MetaFile { path: p.into(),
+					sha256: sha256_reader(&mut c.as_bytes()).unwrap(),
+					..Default::default() })
+		};
+		let md = Metadata { files: [file("/usr/sbin/sshd", "sshd"),
+				file("/bin/ls", "ls"), file("/rescue/ls", "ls")].into(),
+				..Default::default() };
+		let hp = super::hash_paths(&md);
+		let gz = |c: &str| format!("{}.gz", sha256_reader(&mut c.as_bytes())
+				.unwrap().to_buf());
+		assert_eq!(hp[&gz("ls")], ["/bin/ls", "/rescue/ls"]
+				.map(std::path::PathBuf::from));
+
+		// The 404's come back as what paths they'd have been
+		let url = |f: &str| url::Url::parse("http://example.org/f/").unwrap()
+				.join(f).unwrap();
+		let e: anyhow::Error = PoolErrs { errs: vec![
+				GetErr::Status(url(&gz("ls")), 404)] }.into();
+		let vers = "14.2-RELEASE-p3".parse().unwrap();
+		let msg = super::gone_files(e, &vers, &hp).to_string();
+		assert_eq!(msg, format!("The server no longer has the \
+				14.2-RELEASE-p3 contents of:\n  /bin/ls ({h})\n  \
+				/rescue/ls ({h})", h = gz("ls")));
+
+		// Anything else goes through as-is
+		let e = super::gone_files(anyhow::anyhow!("timeout"), &vers, &hp);
+		assert_eq!(e.to_string(), "timeout");
+	}
+}
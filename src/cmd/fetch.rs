@@ -1,8 +1,8 @@
 //! $0 fetch
 use std::collections::HashSet;
-use std::io::{stdout, Write as _};
 
 use crate::command::CmdArg;
+use crate::core::session;
 
 use anyhow::bail;
 
@@ -14,7 +14,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	// Setting up various dirs
 	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
-			&carg.config.workdir())?;
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
 
 	// See what sorta state we're in, and if it's one where we shouldn't
 	// be running fetch.
@@ -35,43 +36,63 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		bail!("upgrade in progress");
 	}
 
+	// A rotated key leaves whatever the old one vouched for unvouched
+	if let Some(n) = state.keyprint_check(&carg.config.keyprint)
+	{ println!("{n}"); }
+
 
 	// OK, bust it up so we can move the bits around individually.
 	let CmdArg { clargs, mut config, version } = carg;
-	let _ = clargs;  // Until we need 'em
+
+	// Extract our own args
+	let args = match clargs.command {
+		crate::command::FrCmds::Fetch(a) => a,
+		_ => unreachable!("I'm a fetch, why does it think I'm not??"),
+	};
+
+	// Any one-off path filters just get added to the config
+	let note = config.add_cli_filters(&args.exclude, &args.include_only);
+	if args.no_keep_modified_metadata { config.keep_modified_metadata = false; }
+
+	// Don't go fighting with pkg over a pkgbase system
+	crate::core::pkgbase::check(config.basedir(), args.allow_pkgbase)?;
 
 	// Do the "finalize components" thing, which pulls src outta the list
-	// if we don't seem to have src installed.
-	config.finalize_components();
+	// if we don't seem to have src installed, or it's a git checkout.
+	if let Some(n) = config.finalize_components() { println!("{n}"); }
 
 	// Fetch will use the INDEX-{NEW,OLD} metadata thingies
 	let metadatas = &["new", "old"];
 
 	// Show our starting point
 	println!("Currently running {version}.");
+	if let Some(pn) = pending_note(&state) { println!("{pn}"); }
+
+	// Keep track of where the time goes, and let anybody watching know
+	// what we're up to.
+	let mut tm = crate::util::timings::Timings::new();
+	let _status = crate::util::status::start(rtdirs.status_file(),
+			"fetch");
 
 
 	/*
 	 * Now we can start the actual fetch process.  First, find a server
 	 * we can talk to.
 	 */
-	let mut server = crate::server::Server::find(&config.servername,
-			&version.kernel, &config.keyprint)?;
+	tm.phase("Finding server");
+	let mut server = crate::server::Server::find_cached(&config,
+			&version.kernel, &mut state, false)?;
+	rtdirs.state_save(&state)?;
 
 	// Set copy of dirnames the server object accesses internally
 	server.set_filesdir(rtdirs.files().to_path_buf());
+	server.set_partialdir(rtdirs.partial_dir());
 
 
 
 	/*
 	 * Next, get metadata from it
 	 */
-	// Load up the metadata index stuff
-	print!("Loading metadata index...");
-	stdout().flush()?;
-	let mdidx = server.get_metadata_idx()?;
-	println!("   OK.");
-
 	// Find and fetch any metadata patches we need.  The logic around
 	// this is pretty thoroughly opaque, I'm not quite clear on what it's
 	// trying to do yet.  It looks like we need to have done updates and
@@ -80,62 +101,29 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// print!("Fetching metadata patches...  TODO\n");
 	// let metapatches = some::long::path::to::figure::out();
 
-	// Find and download any missing metadata files
-	print!("Getting all metadata files...  ");
-	stdout().flush()?;
-	let metamiss = {
-		let fd = rtdirs.files();
-		let mut missing = mdidx.not_in_dir(fd, metadatas);
-		if let Some(md) = state.meta_idx
-		{ missing.extend(md.not_in_dir(fd, metadatas)); }
-		missing
-	};
-	match metamiss.len()
-	{
-		0 => println!("All present."),
-		_ => {
-			println!("{} missing.", metamiss.len());
-
-			// So grab 'em.
-			println!("Fetching...");
-			let files = metamiss.into_iter().collect();
-			server.fetch_metafiles(files)?;
-			println!("Done.");
-		},
-	};
-
-	// Check all the metafiles hashes
-	print!("Checking metadata file hashes...  ");
-	stdout().flush()?;
-	let hres = {
-		let fd = rtdirs.files();
-		let td = rtdirs.tmp();
-		mdidx.check_hashes(fd, td, metadatas)
-	};
-	match hres {
-		Ok(_) => println!("   OK."),
-		Err(e) => {
-			println!("   Errors found.\n{}", e.join("\n"));
-			bail!("Invalid metafiles, bailing.");
-		},
-	};
-
-
 	// f-u.sh has 'sanity checks' of the metafiles.  We do actual full
 	// parses, so they aren't functionally needed.  And parsing is
 	// so fast, there's no useful gain from doing cheaper checks first
 	// either.
 
-
-	// Parse out the metadatas from each
-	print!("Parsing metadata files...  ");
-	stdout().flush()?;
-	let old = mdidx.parse_one_full("old", rtdirs.tmp(), &config)?;
-	print!(" old");
-	let new = mdidx.parse_one_full("new", rtdirs.tmp(), &config)?;
-	print!(" new");
-
-	println!("   OK.");
+	// Load the index, get and check the new/old metadata files (and any
+	// from our last run), and parse 'em out.
+	tm.phase("Fetching metadata");
+	use crate::core::mdfetch;
+	let mut acq = mdfetch::acquire(&mut server, &rtdirs, &config,
+			metadatas, &["old", "new"], state.meta_idx.as_ref(),
+			&mut mdfetch::default_printer())?;
+	let old = acq.take("old");
+	let new = acq.take("new");
+	let ignored = acq.ignored;
+	let mdidx = acq.idx;
+
+	// Report on what IgnorePaths did, if asked.
+	if let Some(si) = &args.show_ignored
+	{
+		let full = si.full_file(config.workdir());
+		ignored.show(full.as_deref())?;
+	}
 
 	// Strip those MetadataGroup's into Metadata's.  Revisit this if we
 	// decide to do the component-heuristic stuff here.
@@ -148,6 +136,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	 * Now scan over the system looking at the files names in our indices
 	 * and seeing what their current status is.
 	 */
+	tm.phase("Inspecting system");
 	print!("Inspecting system...  ");
 	let scanpaths = {
 		let mut paths = HashSet::new();
@@ -169,77 +158,102 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		bail!("No paths to scan");
 	}
 	println!("{} paths to scan", scanpaths.len());
+	session::note(format_args!("scan: {} paths", scanpaths.len()));
 	use crate::core::scan;
 	// our cur = f-u.sh's INDEX-PRESENT
-	let mut cur = scan::scan(config.basedir().to_path_buf(), scanpaths)?;
-	{
-		// Just for kicks, give details
-		let ndir  = cur.dirs.len();
-		let nfile = cur.files.len();
-		let nsl   = cur.symlinks.len();
-		let nhl   = cur.hardlinks.len();
-		let nmiss = cur.dashes.len();
-		println!("Found {ndir} dirs, {nfile} files, {nsl} symlinks, \
-				{nhl} hardlinks, and {nmiss} missing files.");
-	}
+	let annotate = config.annotate_xattr.as_deref()
+			.and_then(crate::util::xattr::AttrName::parse);
+	let scanned = scan::scan_annotated(config.basedir().to_path_buf(),
+			scanpaths, annotate)?;
+	let mut cur = scanned.md;
+	let annotated = scanned.annotated;
+	println!("{}", scan::found_desc(&cur, &[&old, &new]));
 
 
 
 	/*
 	 * Do various filtering
 	 */
-	let modified_files = {
-		// fetch_filter_unmodified_notpresent()
-		use crate::core::filter;
-		let mpret = filter::modified_present(&old, &new, &cur,
-				&config.update_if_unmodified, None, None);
-		// This returns what f-u.sh calls "modifiedfiles"
-		filter::apply_modified_present(mpret, &mut old, &mut new, &mut cur)
-	};
+	tm.phase("Filtering");
+	use crate::core::filter;
+	let filter::LocalChanges { modified: modified_files, skipped_updates,
+			kept_metadata } = filter::local_changes(&mut old, &mut new,
+				&mut cur, &config.update_if_unmodified,
+				config.keep_modified_metadata);
 	match modified_files.len()
 	{
 		nf if nf > 0 => println!("{nf} modified files will be ignored."),
 		_ => (),
 	}
-
-	// AllowAdd and AllowDelete handling would go here
-
-	// Handle KeepModifiedMetadata.  Anything where the current metadata
-	// differs from old, replace new's metadata with our stuff.
-	if config.keep_modified_metadata
+	for l in filter::skipped_updates_desc(&skipped_updates) { println!("{l}"); }
+	session::note(format_args!("filter: {} modified, {} skipped updates, \
+			{} old / {} new / {} present left", modified_files.len(),
+			skipped_updates.len(), old.len(), new.len(), cur.len()));
+
+	// AllowAdd=no we don't do (config refuses it).  AllowDelete=no
+	// means anything new would remove, we keep as it is instead.
+	if !config.allow_delete
 	{
-		let modd = cur.modified_metadata(&old);
-		if !modd.empty()
+		use crate::util::plural;
+		let kept = filter::keep_deletes(&mut new, &cur);
+		let nk = kept.len();
+		if nk > 0
 		{
-			new.replace_metadata_from(&modd);
+			println!("{nk} path{} removed upstream will be kept \
+					(AllowDelete no).", plural(nk));
 		}
 	}
 
-	// Now collate cur/new together, and remove any lines that are
-	// the same between them.  f-u.sh's fetch_filter_uptodate()
+	for l in filter::kept_metadata_desc(&kept_metadata, false)
+	{ println!("{l}"); }
+
+	// Before going any further, make sure what we'd remove makes sense.
+	if let Some(mr) = crate::core::removal::check(&cur, &new,
+			config.max_removal_percent)
 	{
-		let ntmp = new.clone();
-		new.remove_matching(&cur);
-		cur.remove_matching(&ntmp);
+		let pct = config.max_removal_percent;
+		for l in mr.describe(pct) { eprintln!("{l}"); }
+		match args.allow_mass_removal {
+			true  => eprintln!("Going ahead anyway (--allow-mass-removal).\n"),
+			false => {
+				eprintln!("If that's really what you want, rerun with \
+						--allow-mass-removal.");
+				bail!("refusing implausible mass removal");
+			},
+		}
 	}
 
+	// Now collate cur/new together, and remove any lines that are
+	// the same between them.
+	filter::uptodate(&mut new, &mut cur);
+	session::note(format_args!("uptodate: {} new / {} present left",
+			new.len(), cur.len()));
+
 
 	// If there's nothing left in new at this point, that means cur ==
 	// new, so we're already up to date.
-	let relstr = || -> String {
-		use crate::info::version::mk_str;
-		mk_str(&version.kernel.release, &version.kernel.reltype,
-				server.keytag_patchnum())
-	};
+	let rstr = version.with_patch(server.keytag_patchnum()).to_string();
 	if new.empty()
 	{
-		let rstr = relstr();
 		println!("\nNo updates needed to update system to {rstr}");
 		// XXX x-ref noup in cron::run() if you change this string.
 
+		// Anything left pending from an earlier fetch is either what we
+		// already have now or older, so shouldn't hang around for install
+		// to find.
+		let target = version.with_patch(server.keytag_patchnum());
+		if let Some(msg) = settle_pending(&mut state, &target)
+		{ println!("\n{msg}"); }
+
+		// Even finding nothing meant downloading metadata; count it.
+		state.bytes.add(&crate::util::bytecount::get());
+		state.server_stats = server.stats();
+		rtdirs.state_save(&state)?;
+
 		// But give an EOL warning if there is one.
 		if let Some(ew) = server.eol_warning(&version) { println!("\n{ew}"); }
 
+		tm.report(clargs.timings);
 		return Ok(());
 	}
 
@@ -253,11 +267,11 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// of them that are unmodified from old, we may need for patching.
 	// The modified ones don't fall into that, but may be needed for
 	// rollback, so we'll just stash 'em all.
-	if let Some(stashfiles) = cur.files_no_hash_dir(rtdirs.files())
 	{
-		println!("Stashing {} current files.", stashfiles.len());
-		cur.stash_files(&stashfiles, config.basedir().to_path_buf(),
-				rtdirs.tmp().to_path_buf(), rtdirs.files().to_path_buf())?;
+		use std::collections::HashSet;
+		tm.phase("Stashing files");
+		crate::core::stash::stash_current(&mut cur, config.basedir(),
+				rtdirs.tmp(), rtdirs.files(), &HashSet::new())?;
 	}
 
 
@@ -320,6 +334,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// Try getting and applying any we can.
 	if maybe_patches.len() > 0
 	{
+		tm.phase("Fetching patches");
 		println!("Trying to fetch {} patch files.", maybe_patches.len());
 		let pret = server.fetch_patch_files(maybe_patches,
 				rtdirs.tmp().to_path_buf())?;
@@ -344,6 +359,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// pulling out the matching entries from cur), and that we don't have
 	// a <hash>.gz for.
 	let needhashes = new.hashes_no_hash_dir(rtdirs.files());
+	session::note(format_args!("files: {} to fetch",
+			needhashes.as_ref().map_or(0, |nh| nh.len())));
 	if let Some(nh) = needhashes
 	{
 		// All encapsulated up, just build the control with the dirs and
@@ -359,7 +376,18 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let keep = false; // Not currently reprocessing
 		let ctrl = hcp::Control { tmpdir, filesdir, keep };
 
-		hf::get(&server, nh, ctrl)?;
+		// Make some room first, if we're capped, without losing what
+		// this or a pending install needs, and with space for what's
+		// coming.
+		use crate::core::evict;
+		let mut keep = evict::pending(&state);
+		keep.extend(cur.files.values().chain(new.files.values())
+				.map(|f| f.sha256));
+		evict::enforce(rtdirs.files(), config.max_workdir_size, &keep,
+				nh.len())?;
+
+		tm.phase(format!("Fetching {} files", nh.len()));
+		hf::get(&mut server, nh, ctrl)?;
 	}
 	else
 	{
@@ -391,9 +419,26 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let manifest = {
 		// Build version from our current running version, with the patch
 		// from the server's keytag.
-		let mut vers = version.max().clone();
-		vers.patch = server.keytag_patchnum();
-		Manifest::new_fetch(cur, new, vers)
+		let vers = version.with_patch(server.keytag_patchnum());
+		let mut mf = Manifest::new_fetch(cur, new, vers);
+		mf.set_from(version.max().clone());
+		mf.set_keyprint(&config.keyprint);
+		mf.set_note(note);
+		mf.set_skipped_updates(skipped_updates);
+		mf.set_kept_metadata(kept_metadata);
+		if config.preserve_timestamps == crate::config::Timestamps::Upstream
+		{
+			use crate::core::install::upstream_mtime;
+			mf.set_install_mtime(Some(upstream_mtime(mf.version())));
+		}
+		if let Some(attr) = &config.annotate_xattr
+		{ mf.set_annotated(attr, annotated); }
+
+		// Size it up for the summaries; only an estimate, so not worth
+		// failing over.
+		if let Err(e) = mf.size_install(&rtdirs)
+		{ eprintln!("Couldn't size up install: {e}"); }
+		mf
 	};
 
 	// Print out a summary.  We don't display the full list like f-u.sh
@@ -408,6 +453,16 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		println!("\nUpgrade will remove {rem} files, add {add} files, and \
 				update {upd} files.\n\
 				Run `{cmdname} show-install` for details.");
+		let risk = manifest.risks().summary().describe();
+		if !risk.is_empty() { println!(""); }
+		risk.iter().for_each(|l| println!("{l}"));
+
+		println!("");
+		let run = crate::util::bytecount::get();
+		manifest.bytes_summary(&rtdirs, &run).iter()
+				.for_each(|l| println!("{l}"));
+		server.stats().iter()
+				.for_each(|h| println!("From {}.", h.describe()));
 	}
 
 	// Prep it up for saving
@@ -417,22 +472,84 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// 'tINDEX.present'
 	let save_mdidx = mdidx.clone_matching(metadatas);
 	state.meta_idx = Some(save_mdidx);
+	state.meta_idx_vers = Some(version.kernel.clone());
+	state.meta_idx_keyprint = Some(config.keyprint.clone());
+
+	// And the signed bits it came from, for `audit`.  The tag gets kept
+	// by patch level too, for `patch-contents` to find later.
+	let signed = server.signed_files()?;
+	signed.save(&rtdirs.signed_dir())?;
+	signed.archive_tag(&rtdirs.signed_dir(),
+			&version.with_patch(server.keytag_patchnum()))?;
 
 	// OK, save up that state
+	tm.phase("Saving state");
+	state.bytes.add(&crate::util::bytecount::get());
+	state.server_stats = server.stats();
 	rtdirs.state_save(&state)?;
+	tm.end();
+
+	// And get back under MaxWorkdirSize, now that we know just what's
+	// needed.  Everything's saved by now, so falling short just means
+	// being over the cap until the next run.
+	if let Err(e) = crate::core::evict::enforce(rtdirs.files(),
+			config.max_workdir_size, &crate::core::evict::pending(&state), 0)
+	{ eprintln!("Warning: {e}"); }
 
 	// And we're done.  If we get this far, there's something to install,
 	// so remind the user.
-	let rstr = relstr();
 	println!("\nRun `{cmdname} install` to upgrade from {version} to {rstr}.");
 
 	// Also give an EOL warning if there is one.
 	if let Some(ew) = server.eol_warning(&version) { println!("\n{ew}"); }
 
+	tm.report(clargs.timings);
 	Ok(())
 }
 
 
+/// If there's already a pending manifest, mention that we're about to
+/// replace it.
+fn pending_note(state: &crate::state::State) -> Option<String>
+{
+	let m = state.manifest.as_ref()?;
+	Some(format!("Replacing pending {} to {}.", m.mtype(), m.version()))
+}
+
+
+/// When we find nothing to update, see what to do about any manifest
+/// left over from an earlier run.  If it's for where we already are, or
+/// an older patch, it's stale, so clear it out; installing it would just
+/// be a no-op or a downgrade.  Anything else we leave alone, but make
+/// noise about.  Returns what to tell the user, if anything.
+fn settle_pending(state: &mut crate::state::State,
+		target: &crate::info::version::AVersion) -> Option<String>
+{
+	let mani = state.manifest.as_ref()?;
+	let (mt, mv) = (mani.mtype(), mani.version().clone());
+
+	let same_rel = mv.release == target.release && mv.reltype == target.reltype;
+	match same_rel && mv.patch <= target.patch {
+		true => {
+			state.manifest = None;
+			let why = match mv.patch == target.patch {
+				true  => "already installed",
+				false => "obsolete",
+			};
+			Some(format!("Cleared stale pending {mt} to {mv} ({why})."))
+		},
+		false => {
+			let cmdname = crate::util::cmdname();
+			Some(format!("WARNING: a pending {mt} to {mv} from an earlier \
+					run is still around,\n\
+					but the system is already up to date with {target}.\n\
+					Run `{cmdname} clean --pending` if you don't mean to \
+					install it."))
+		},
+	}
+}
+
+
 /// Do some checks of our config/etc
 fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 {
@@ -453,10 +570,10 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 	check!(workdir);
 	check!(basedir);
 
-	// Should only run on releases (temporarily knocked off for dev)
+	// Should only run on releases
 	match crate::check::version(&carg.version) {
 		Ok(_) => (),
-		Err(_e) => (), // errs.push(e),
+		Err(e) => errs.push(e),
 	};
 
 
@@ -470,3 +587,71 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 		},
 	}
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use crate::state::{State, Manifest};
+
+	fn with_pending(vers: &str, upgrade: bool) -> State
+	{
+		use crate::metadata::Metadata;
+		let vers = vers.parse().unwrap();
+		let (md, md2) = (Metadata::default(), Metadata::default());
+		let manifest = match upgrade {
+			false => Manifest::new_fetch(md, md2, vers),
+			true  => Manifest::new_upgrade(md, md2, vers,
+					Default::default(), Default::default()),
+		};
+		State { manifest: Some(manifest), ..Default::default() }
+	}
+
+	#[test]
+	fn pending_note()
+	{
+		use super::pending_note;
+
+		assert_eq!(pending_note(&State::default()), None);
+		let st = with_pending("14.1-RELEASE-p2", false);
+		assert_eq!(pending_note(&st).as_deref(),
+				Some("Replacing pending fetch to 14.1-RELEASE-p2."));
+	}
+
+	#[test]
+	fn settle_pending()
+	{
+		use super::settle_pending;
+		let target = "14.1-RELEASE-p3".parse().unwrap();
+
+		// Nothing pending, nothing to say
+		let mut st = State::default();
+		assert_eq!(settle_pending(&mut st, &target), None);
+
+		// Older patch is obsolete, same one is already done; both go.
+		for (v, why) in [("14.1-RELEASE-p2", "obsolete"),
+				("14.1-RELEASE-p3", "already installed")]
+		{
+			let mut st = with_pending(v, false);
+			let msg = settle_pending(&mut st, &target).unwrap();
+			assert!(st.manifest.is_none(), "{v} cleared");
+			assert!(msg.contains(why), "{v}: {msg}");
+		}
+
+		// Stale upgrades to where we are go too.
+		let mut st = with_pending("14.1-RELEASE", true);
+		settle_pending(&mut st, &target).unwrap();
+		assert!(st.manifest.is_none(), "stale upgrade cleared");
+
+		// Newer than the server's, or a different release, we don't
+		// know enough to toss; just complain.
+		for (v, up) in [("14.1-RELEASE-p4", false), ("14.2-RELEASE", true)]
+		{
+			let mut st = with_pending(v, up);
+			let msg = settle_pending(&mut st, &target).unwrap();
+			assert!(st.manifest.is_some(), "{v} kept");
+			assert!(msg.contains("clean --pending"), "{v}: {msg}");
+		}
+	}
+}
@@ -0,0 +1,384 @@
+//! $0 fix-links
+use std::collections::BTreeMap;
+use std::io::{stdout, Write as _};
+use std::path::Path;
+
+use crate::command::CmdArg;
+use crate::metadata::{Metadata, MetaFile, MetaHardLink};
+use crate::util::plural;
+
+use anyhow::bail;
+
+
+/// What we found looking at one of upstream's hardlinks on the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkState
+{
+	/// Already the same file as its target
+	Ok,
+
+	/// Neither the link nor its target exist; presumably that component
+	/// isn't installed.
+	Absent,
+
+	/// The link doesn't exist
+	Missing,
+
+	/// A separate file with the same contents as the target
+	Dup,
+
+	/// A separate file with different contents; we won't touch it
+	Differs,
+
+	/// Something other than a file is there
+	NotFile,
+
+	/// The target is missing, or isn't what upstream says it should be,
+	/// so we've nothing good to link to.
+	BadTarget,
+}
+
+impl LinkState
+{
+	/// Is this something we can fix?
+	fn fixable(&self) -> bool
+	{ matches!(self, Self::Missing | Self::Dup) }
+
+	fn describe(&self) -> &'static str
+	{
+		match self {
+			Self::Ok        => "ok",
+			Self::Absent    => "not installed",
+			Self::Missing   => "missing",
+			Self::Dup       => "separate copy of the target",
+			Self::Differs   => "separate file with different contents",
+			Self::NotFile   => "not a file",
+			Self::BadTarget => "target missing or modified",
+		}
+	}
+}
+
+
+/// Command: $0 fix-links
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	// Check our various config etc.
+	check(&carg)?;
+
+	// Setting up various dirs
+	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
+
+	let CmdArg { clargs, config, version } = carg;
+
+	// Extract args
+	let args = match clargs.command {
+		crate::command::FrCmds::FixLinks(a) => a,
+		_ => unreachable!("I'm a fix-links, why does it think I'm not??"),
+	};
+
+	println!("Currently running {version}.");
+
+
+	/*
+	 * Load up the INDEX-ALL, same as check-sys, since what we care
+	 * about is what's supposed to be installed now.
+	 */
+	let mut server = crate::server::Server::find_cached_rt(&config,
+			&version.kernel, &rtdirs, false)?;
+	server.set_filesdir(rtdirs.files().to_path_buf());
+
+	use crate::core::mdfetch;
+	let metadatas = &["all"];
+	let mut acq = mdfetch::acquire(&mut server, &rtdirs, &config,
+			metadatas, metadatas, None, &mut mdfetch::default_printer())?;
+	let all = acq.take("all").into_metadata();
+
+	if all.hardlinks.is_empty()
+	{
+		println!("No hardlinks in the upstream metadata; nothing to do.");
+		return Ok(0);
+	}
+
+
+	/*
+	 * Scan the links and their targets.  The scanner already notes which
+	 * of the paths it's given are the same file, so that's all we need
+	 * to tell what's linked up.
+	 */
+	let scanpaths = {
+		let mut paths: Vec<_> = all.hardlinks.values()
+				.flat_map(|l| [l.path.clone(), l.target.clone()])
+				.collect();
+		paths.sort_unstable();
+		paths.dedup();
+		paths
+	};
+	let nlinks = all.hardlinks.len();
+	print!("Inspecting {nlinks} hardlink{} ({} paths)...  ", plural(nlinks),
+			scanpaths.len());
+	stdout().flush()?;
+	let basedir = config.basedir().to_path_buf();
+	let cur = crate::core::scan::scan_inner(basedir.clone(), scanpaths,
+			true)?;
+	println!("Done.");
+
+
+	// Sort 'em out
+	let mut found: BTreeMap<&Path, (&MetaHardLink, LinkState)> = all.hardlinks
+			.iter()
+			.map(|(p, l)| (p.as_path(), (l, classify(l, &all, &cur))))
+			.collect();
+	let count = |st: LinkState| found.values().filter(|(_, s)| *s == st)
+			.count();
+	let nok = count(LinkState::Ok);
+	let nabs = count(LinkState::Absent);
+	let nfix = found.values().filter(|(_, s)| s.fixable()).count();
+
+	print!("{nok} correctly linked");
+	if nabs > 0 { print!(", {nabs} not installed"); }
+	println!(".");
+	found.retain(|_, (_, s)| !matches!(s, LinkState::Ok | LinkState::Absent));
+	if found.is_empty()
+	{
+		println!("No problems found.");
+		return Ok(0);
+	}
+
+	println!("\n{} problem{} found:", found.len(), plural(found.len()));
+	for (p, (l, st)) in &found
+	{
+		println!("  {} -> {}: {}", p.display(), l.target.display(),
+				st.describe());
+	}
+
+	// How much would we get back?  A dup that's got other names (that we
+	// don't know about) won't actually free anything.
+	let reclaim: u64 = found.iter()
+			.filter(|(_, (_, s))| *s == LinkState::Dup)
+			.filter_map(|(p, _)| {
+				use std::os::unix::fs::MetadataExt as _;
+				let m = crate::util::path_join(&basedir, p)
+						.symlink_metadata().ok()?;
+				(m.nlink() == 1).then(|| m.len())
+			})
+			.sum();
+	if reclaim > 0
+	{
+		println!("\nRelinking duplicates would reclaim {}.",
+				crate::util::human_bytes(reclaim));
+	}
+
+	let nbad = found.len() - nfix;
+	if nbad > 0
+	{
+		println!("\n{nbad} of these can't be fixed automatically, and will \
+				be left alone.");
+	}
+
+	if !args.apply
+	{
+		if nfix > 0
+		{
+			println!("\n{nfix} link{} can be fixed; run with --apply to \
+					do so.", plural(nfix));
+		}
+		return Ok(1);
+	}
+	if nfix == 0 { return Ok(1); }
+
+
+	/*
+	 * Go ahead and fix 'em.
+	 */
+	print!("\nFixing {nfix} link{}...  ", plural(nfix));
+	stdout().flush()?;
+	let mut fails = Vec::new();
+	for (p, (l, _)) in found.iter().filter(|(_, (_, s))| s.fixable())
+	{
+		if let Err(e) = relink(&basedir, l)
+		{ fails.push(format!("{}: {e}", p.display())); }
+	}
+	println!("Done.");
+
+	if fails.len() > 0
+	{
+		println!("{} link{} failed:", fails.len(), plural(fails.len()));
+		fails.iter().for_each(|f| println!("  {f}"));
+		return Ok(1);
+	}
+
+	Ok(match nbad {
+		0 => 0,
+		_ => 1,
+	})
+}
+
+
+/// Where a path actually lives; the scanner records the later names of
+/// a file as links to the first one it saw.
+fn root<'a>(cur: &'a Metadata, p: &'a Path) -> &'a Path
+{
+	match cur.hardlinks.get(p) {
+		Some(l) => &l.target,
+		None => p,
+	}
+}
+
+/// The file a path is, if it's a file.
+fn curfile<'a>(cur: &'a Metadata, p: &'a Path) -> Option<&'a MetaFile>
+{
+	cur.files.get(root(cur, p))
+}
+
+
+/// Figure out what's up with one link.
+fn classify(l: &MetaHardLink, all: &Metadata, cur: &Metadata) -> LinkState
+{
+	use LinkState as S;
+
+	if root(cur, &l.path) == root(cur, &l.target) { return S::Ok; }
+
+	let here = |p: &Path| root(cur, p) != p || cur.files.contains_key(p)
+			|| cur.dirs.contains_key(p) || cur.symlinks.contains_key(p);
+	let (lhere, there) = (here(&l.path), here(&l.target));
+	if !lhere && !there { return S::Absent; }
+
+	// Is the target what it should be?
+	let want = match (all.files.get(&l.target), curfile(cur, &l.target)) {
+		(Some(w), Some(t)) if w.sha256 == t.sha256 => w,
+		_ => return S::BadTarget,
+	};
+
+	if !lhere { return S::Missing; }
+	match curfile(cur, &l.path) {
+		Some(f) if f.sha256 == want.sha256 => S::Dup,
+		Some(_) => S::Differs,
+		None => S::NotFile,
+	}
+}
+
+
+/// Make a link right.
+///
+/// install::link() takes care of never leaving the link path missing;
+/// we just have to get any schg flags out of its way (and put the
+/// target's back after).
+fn relink(basedir: &Path, l: &MetaHardLink) -> Result<(), anyhow::Error>
+{
+	use crate::util::{lstat, lchflags, unschg_file, path_join};
+	let schg = libc::SF_IMMUTABLE as u32;
+
+	let tpath = path_join(basedir, &l.target);
+	let lpath = path_join(basedir, &l.path);
+
+	let (tst, _) = lstat(&tpath)?;
+	let tschg = tst.flags & schg != 0;
+	if tschg { unschg_file(&tpath, tst.flags)?; }
+
+	// If there's an old copy there, it's going away, so don't bother
+	// restoring its flags.
+	if let Ok((lst, _)) = lstat(&lpath)
+	{
+		if lst.flags & schg != 0 { unschg_file(&lpath, lst.flags)?; }
+	}
+
+	let res = crate::core::install::link(&lpath, l, basedir);
+	if tschg { lchflags(&tpath, tst.flags.into())?; }
+	res?;
+
+	Ok(())
+}
+
+
+/// Do some checks of our config/etc
+fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
+{
+	let mut errs: Vec<String> = vec![];
+
+	macro_rules! check {
+		( $fld:ident) => {
+			match crate::check::$fld(&carg.config) {
+				Ok(_) => (),
+				Err(e) => errs.push(e),
+			}
+		};
+	}
+
+	// Lot of simple config fields that have common check types
+	check!(servername);
+	check!(keyprint);
+	check!(workdir);
+	check!(basedir);
+
+	match errs.len() {
+		0 => Ok(()),
+		_ => bail!("Cannot run fix-links:\n  - {}", errs.join("\n  - ")),
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+	use crate::metadata::{Metadata, MetaFile, MetaHardLink, MetaSymLink};
+	use super::{classify, LinkState as S};
+
+	fn file(p: &str, h: u8) -> (PathBuf, MetaFile)
+	{
+		let f = MetaFile { path: p.into(), sha256: [h; 32].into(),
+				..Default::default() };
+		(p.into(), f)
+	}
+
+	fn link(p: &str, t: &str) -> (PathBuf, MetaHardLink)
+	{
+		(p.into(), MetaHardLink { path: p.into(), target: t.into() })
+	}
+
+	#[test]
+	fn classify_links()
+	{
+		let mut all = Metadata::default();
+		all.files.extend([file("/bin/a", 1), file("/bin/x", 2)]);
+		all.hardlinks.extend([link("/bin/b", "/bin/a"),
+				link("/bin/c", "/bin/a"), link("/bin/d", "/bin/a"),
+				link("/bin/e", "/bin/a"), link("/bin/y", "/bin/x"),
+				link("/bin/z", "/bin/q")]);
+
+		let mut cur = Metadata::default();
+		// b fine; the scanner may have picked c as the first name
+		cur.files.extend([file("/bin/c", 1), file("/bin/d", 1),
+				file("/bin/e", 9), file("/bin/x", 7)]);
+		cur.hardlinks.extend([link("/bin/a", "/bin/c"),
+				link("/bin/b", "/bin/c")]);
+		cur.dashes.insert("/bin/y".into());
+		cur.dashes.insert("/bin/z".into());
+		cur.dashes.insert("/bin/q".into());
+
+		let get = |cur: &Metadata, p: &str|
+				classify(&all.hardlinks[&PathBuf::from(p)], &all, cur);
+		assert_eq!(get(&cur, "/bin/b"), S::Ok);
+		assert_eq!(get(&cur, "/bin/c"), S::Ok);
+		assert_eq!(get(&cur, "/bin/d"), S::Dup);
+		assert_eq!(get(&cur, "/bin/e"), S::Differs);
+		assert_eq!(get(&cur, "/bin/y"), S::BadTarget);
+		assert_eq!(get(&cur, "/bin/z"), S::Absent);
+
+		// Symlink where the link should be
+		cur.files.remove(&PathBuf::from("/bin/d"));
+		cur.symlinks.insert("/bin/d".into(), MetaSymLink { path: "/bin/d".into(),
+				target: "a".into(), ..Default::default() });
+		assert_eq!(get(&cur, "/bin/d"), S::NotFile);
+
+		// Good target, missing link
+		cur.symlinks.clear();
+		cur.dashes.insert("/bin/d".into());
+		assert_eq!(get(&cur, "/bin/d"), S::Missing);
+		assert!(get(&cur, "/bin/d").fixable());
+		assert!(!get(&cur, "/bin/e").fixable());
+	}
+}
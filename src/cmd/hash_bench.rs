@@ -0,0 +1,84 @@
+//! $0 hash-bench
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::command::CmdArg;
+use crate::util::hash::{self, Sha256Hash, Sha256ReaderErr};
+
+
+/// Command: $0 hash-bench
+pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
+{
+	let args = match carg.clargs.command {
+		crate::command::FrCmds::HashBench(a) => a,
+		_ => unreachable!("I'm a hash-bench, why does it think I'm not??"),
+	};
+
+	let mut files = Vec::new();
+	walk(&args.dir, &mut files)?;
+	files.sort_unstable();
+	let bytes: u64 = files.iter()
+			.filter_map(|f| f.symlink_metadata().ok())
+			.map(|m| m.len()).sum();
+	println!("{} files, {} MB under {}.", files.len(), bytes >> 20,
+			args.dir.display());
+	println!("Hardware SHA256 {}available.", match hash::accel() {
+		true  => "", false => "not ",
+	});
+
+	// The 8k io::copy() is how we used to do it, for comparison.
+	type Method = fn(&Path) -> Result<Sha256Hash, Sha256ReaderErr>;
+	let methods: [(&str, Method); 3] = [
+		("io::copy", |f| hash::sha256_reader(&mut std::fs::File::open(f)?)),
+		("read", |f| hash::sha256_file_with(f, false)),
+		("mmap", |f| hash::sha256_file_with(f, true)),
+	];
+
+	// One untimed go first, so everything's in cache the same for all of
+	// them, and to have something to check against.
+	let want: Vec<_> = files.iter().map(|f| methods[0].1(f).ok()).collect();
+
+	for (name, m) in methods
+	{
+		let mut best = Duration::MAX;
+		for _ in 0..args.rounds.max(1)
+		{
+			let start = Instant::now();
+			for (f, w) in files.iter().zip(&want)
+			{
+				let got = m(f).ok();
+				if got != *w
+				{
+					anyhow::bail!("{name} got a different hash for {}",
+							f.display());
+				}
+			}
+			best = best.min(start.elapsed());
+		}
+		let secs = best.as_secs_f64();
+		let mbps = match secs > 0.0 {
+			true  => (bytes as f64 / (1 << 20) as f64) / secs,
+			false => 0.0,
+		};
+		println!("  {name:<10} {secs:>8.3}s  {mbps:>8.1} MB/s");
+	}
+
+	Ok(())
+}
+
+
+/// All the regular files under a dir, not following symlinks.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), std::io::Error>
+{
+	for ent in std::fs::read_dir(dir)?
+	{
+		let ent = ent?;
+		let ft = ent.file_type()?;
+		match (ft.is_dir(), ft.is_file()) {
+			(true, _) => walk(&ent.path(), files)?,
+			(_, true) => files.push(ent.path()),
+			_ => (),
+		}
+	}
+	Ok(())
+}
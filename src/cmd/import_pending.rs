@@ -0,0 +1,76 @@
+//! $0 import-pending
+use crate::command::CmdArg;
+use crate::core::RtDirs;
+use crate::core::bundle;
+
+use anyhow::bail;
+
+
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	// Setup dirs
+	let rtdirs = RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?;
+
+	// Split up
+	let CmdArg { clargs, config, version } = carg;
+
+	// Extract our own args
+	let args = match &clargs.command {
+		crate::command::FrCmds::ImportPending(a) => a,
+		_ => unreachable!("I'm an import-pending, why does it think I'm not??"),
+	};
+
+	// Don't stomp on something already here
+	let cmdname = crate::util::cmdname();
+	let mut state = rtdirs.state_load()?;
+	if let Some(m) = &state.manifest
+	{
+		bail!("There's already a pending {} here; install it, or \
+				`{cmdname} clean --pending` it first.", m.mtype());
+	}
+
+	println!("Importing {}...", args.file.display());
+	let arch = crate::info::kernel::arch()?;
+	let imp = bundle::import(&args.file, &rtdirs, config.basedir(),
+			&config.keyprint, |h| h.check(&version, &config.keyprint, &arch))?;
+	let bundle::Imported { header, manifest, signed } = imp;
+	println!("Got pending {} from {} to {}; {} files, all checked.",
+			header.mtype, header.from, header.to, header.nfiles);
+	println!("Signed tag verified against the configured KeyPrint, and \
+			the pending {} against its metadata.", header.mtype);
+
+	// Make sure it's all really there, same as install will.
+	let hashes = manifest.install_hashes();
+	{
+		use crate::core::pool::Pool as _;
+		use crate::core::pool::present;
+
+		const MAXMISS: usize = 20;
+		let nhf = hashes.len();
+		let ctrl = present::Control::new(rtdirs.files().to_path_buf(),
+				MAXMISS);
+		let hashes = hashes.into_iter().map(|h| h.into());
+		let pres = present::Present::new(nhf, MAXMISS).run(&ctrl, hashes)?;
+		if !pres.missing.is_empty()
+		{
+			let nm = pres.missing.len();
+			let more = if pres.aborted { " (or more)" } else { "" };
+			bail!("Bundle is missing {nm}{more} file{} the install needs.",
+					crate::util::plural(nm));
+		}
+	}
+
+	// Keep the signed bits like fetch would, for `audit` and friends.
+	signed.save(&rtdirs.signed_dir())?;
+	signed.archive_tag(&rtdirs.signed_dir(), &header.to)?;
+
+	state.manifest = Some(manifest);
+	state.meta_idx = header.meta_idx;
+	state.meta_idx_vers = header.meta_idx_vers;
+	state.meta_idx_keyprint = Some(header.keyprint);
+	rtdirs.state_save(&state)?;
+
+	println!("Run `{cmdname} install` to install it.");
+	Ok(0)
+}
@@ -1,13 +1,13 @@
 //! #0 install
 use crate::command::CmdArg;
 use crate::util::{plural, path_join};
+use crate::util::out::{self, say, sayln};
 use crate::state::Manifest;
 use crate::core::RtDirs;
 use crate::core::install;
 use crate::metadata::MetadataLine;
 use crate::metadata::SplitTypes;
 
-use std::io::{stdout, Write as _};
 use std::collections::HashMap;
 use std::path::{PathBuf, Path};
 
@@ -23,6 +23,17 @@ enum InstRet
 
 	/// Install is complete, clear out
 	Done,
+
+	/// Manifest has updated stuff in it, save, then reboot
+	Reboot,
+
+	/// Install is complete, but these old shared libs were left behind
+	LibsKept(Vec<PathBuf>),
+
+	/// Everything else in this step went in, but these paths (and why)
+	/// didn't (--keep-going); save with just them still pending, and the
+	/// step not done until they are
+	Failed(install::Failed),
 }
 
 
@@ -30,8 +41,11 @@ enum InstRet
 /// Command: $0 install
 ///
 /// Main entry point
-pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
 {
+	// Check our various config etc.
+	check(&carg)?;
+
 	// Setup dirs
 	let rtdirs = RtDirs::init(&carg.config.basedir(),
 			&carg.config.workdir())?;
@@ -40,13 +54,28 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let CmdArg { clargs, config, version } = carg;
 
 	// Extract our own args
-	let args = match clargs.command {
+	let args = match &clargs.command {
 		crate::command::FrCmds::Install(a) => a,
 		_ => unreachable!("I'm a install, why does it think I'm not??"),
 	};
 
+	// Don't go fighting with pkg over a pkgbase system
+	crate::core::pkgbase::check(config.basedir(), args.allow_pkgbase)?;
+
 	// Handle disabling fsync if we asked for that.
 	if args.no_sync { install::set_fsync(false); }
+	install::set_preserve_extras(args.preserve_acls);
+
+	// Just setting up the resume script is its own thing.
+	if args.enable_resume
+	{
+		return enable_resume(&clargs, &rtdirs, &config).map(|()| 0);
+	}
+
+	// If we're here via the resume script, the marker's already gone,
+	// but if somebody's running us by hand, a leftover marker is stale.
+	let marker = rtdirs.resume_marker();
+	if marker.exists() { std::fs::remove_file(&marker)?; }
 
 	// Load up the state and see what's in the manifest
 	let mut state = match rtdirs.state_load_raw()? {
@@ -58,8 +87,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let manifest = match &mut state.manifest {
 		Some(m) => m,
 		None => {
-			println!("No install pending.");
-			return Ok(());
+			sayln!("No install pending.");
+			return Ok(0);
 		},
 	};
 
@@ -68,7 +97,85 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let upvers = manifest.version();
 	let mt = manifest.mtype();
 	let cmdname = crate::util::cmdname();
-	println!("Installing pending {mt} from {version} to {upvers}");
+	sayln!("Installing pending {mt} from {version} to {upvers}");
+
+	// Everything gets the one mtime, if it was made with
+	// PreserveTimestamps upstream.
+	install::set_install_mtime(manifest.install_mtime());
+	if let Some(t) = manifest.install_mtime()
+	{
+		let when = chrono::DateTime::from_timestamp(t, 0)
+				.map(|d| d.to_rfc3339()).unwrap_or_else(|| t.to_string());
+		sayln!("Installed files get mtime {when} (PreserveTimestamps).");
+	}
+
+	// Make sure it's still for this system.  If something else moved it
+	// along since, this is all stale and would be going backward.
+	if let Some(running) = manifest.source_mismatch(version.max())
+	{
+		let from = manifest.from().expect("mismatch means we know");
+		sayln!("The pending {mt} was made on {from}, but the system is now \
+				running {running}.");
+		match args.force {
+			true => sayln!("Installing anyway (--force)."),
+			false => {
+				sayln!("Installing it would likely replace newer files \
+						with older ones.  Run `{cmdname} clean --pending` \
+						and fetch again,\nor use --force if you're sure.");
+				bail!("Pending {mt} is for {from}, not {running}");
+			},
+		}
+	}
+
+	// And that what it says to install was vouched for by the key we
+	// trust now.  Old manifests can't say, and bailing on one partway
+	// through an upgrade would leave things worse off, so those just
+	// get mentioned.
+	let kp: String = config.keyprint.chars().take(16).collect();
+	match manifest.keyprint_mismatch(&config.keyprint) {
+		None => (),
+		Some(None) => {
+			sayln!("The pending {mt} is from before the KeyPrint it was \
+					checked against got recorded, so it can't be matched \
+					up with the configured one ({kp}...).");
+		},
+		Some(Some(was)) => {
+			let was: String = was.chars().take(16).collect();
+			sayln!("The pending {mt} was checked against KeyPrint {was}..., \
+					not the configured {kp}...; maybe the signing key was \
+					rotated.");
+			sayln!("Run `{cmdname} clean --pending` and fetch again.");
+			bail!("Pending {mt} wasn't verified against the configured \
+					KeyPrint");
+		},
+	}
+
+	// Anything in there somebody else manages?
+	if let Some(an) = manifest.annotated()
+	{
+		use crate::util::plural;
+		let n = an.paths.len();
+		sayln!("{n} file{} to replace or remove marked {}.", plural(n),
+				an.attr);
+		if config.protect_annotated && !args.touch_managed
+		{
+			for p in &an.paths { sayln!("  {}", p.display()); }
+			sayln!("ProtectAnnotated is set; use --touch-managed to \
+					install over them anyway.");
+			bail!("Pending {mt} touches {n} {} file{}", an.attr, plural(n));
+		}
+	}
+
+	// Are we installing into a running jail?
+	let jail = match config.basedir() == &"/".as_ref() {
+		true  => None,
+		false => crate::util::jail::find(config.basedir()),
+	};
+	if let Some(j) = &jail
+	{
+		sayln!("  into running jail {j}");
+	}
+	let jail = jail.as_ref();
 
 
 	// Do a quick check; if there are conflicted merges, we're not ready
@@ -79,16 +186,27 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let ncf = mup.merge_conflict.len();
 		if ncf > 0
 		{
-			println!(" {ncf} merge conflict{} unresolved.", plural(ncf));
-			println!("    Run `{cmdname} resolve-merges` to resolve");
+			sayln!(" {ncf} merge conflict{} unresolved.", plural(ncf));
+			sayln!("    Run `{cmdname} resolve-merges` to resolve");
 			bail!("Unresolved merge conflicts");
 		}
 	}
 
 
+	// Clean up after any earlier install that died partway, before this
+	// one gets going.
+	{
+		use install::residue;
+		let dirs = residue::dirs(Some(&*manifest), &state.last_install_dirs);
+		let res = residue::sweep(config.basedir(), &dirs, residue::MIN_AGE,
+				args.dry_run);
+		res.describe(args.dry_run).iter().for_each(|l| sayln!("{l}"));
+	}
+
+
 
 	// Rack up some info out of cur/new that we'll use several times.
-	let exp_hashes;
+	let exp_hashes = manifest.install_hashes();
 	let cn_paths: Vec<_>;
 	{
 		let (cur, new) = match manifest {
@@ -100,14 +218,6 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			},
 		};
 
-		exp_hashes = {
-			use std::collections::HashSet;
-			let mut exp_hashes = HashSet::with_capacity(new.files.len());
-			cur.files.values().for_each(|f| { exp_hashes.insert(f.sha256); });
-			new.files.values().for_each(|f| { exp_hashes.insert(f.sha256); });
-			exp_hashes
-		};
-
 		cn_paths = {
 			let mut paths = cur.allpaths_hashset();
 			new.allpaths_hashset().into_iter().for_each(|p| {
@@ -121,24 +231,63 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	// Check that expected files all exist.
 	// f-u.sh install_verify()
+	//
+	// This can be a lot of files, so run it through a pool, and stop
+	// once we've found enough missing to know things are wrong.  Their
+	// contents get checked too, so bad ones stop us before anything's
+	// installed, rather than partway.
 	let nhf = exp_hashes.len();
-	print!("Checking required files are present...   {nhf} hashfiles...  ");
-	stdout().flush()?;
-	for h in exp_hashes
+	say!("Checking required files...   {nhf} hashfiles...  ");
+	out::flush();
 	{
-		let hb = h.into();
-		if !rtdirs.hashfile(&hb).is_file()
+		use crate::core::pool::Pool as _;
+		use crate::core::pool::present;
+
+		const MAXMISS: usize = 20;
+		let ctrl = present::Control::new(rtdirs.files().to_path_buf(),
+				MAXMISS).verifying();
+		let hashes = exp_hashes.into_iter().map(|h| h.into());
+		let pres = present::Present::new(nhf, MAXMISS).run(&ctrl, hashes)?;
+
+		if !pres.missing.is_empty()
 		{
-			println!("Update files missing -- this should never happen.  \
+			let nm = pres.missing.len();
+			let more = if pres.aborted { " (or more)" } else { "" };
+			sayln!("{nm}{more} missing.");
+			sayln!("Update files missing -- this should never happen.  \
 					Try re-running `{cmdname} {mt}`.");
-			bail!("Internal error -- missing files");
+			let mlist: Vec<_> = pres.missing.iter().map(|h| h.to_string())
+					.collect();
+			bail!("Internal error -- missing files in {}:\n  {}",
+					rtdirs.files().display(), mlist.join("\n  "));
 		}
+
+		// Corrupted since they were fetched.  Clear them out, so the
+		// re-run gets them again.
+		if !pres.bad.is_empty()
+		{
+			let nb = pres.bad.len();
+			let more = if pres.aborted { " (or more)" } else { "" };
+			sayln!("{nb}{more} corrupted.");
+			for (h, e) in &pres.bad
+			{
+				sayln!("  {h}: {e}");
+				let _ = std::fs::remove_file(rtdirs.hashfile(h));
+			}
+			bail!("Update files corrupted; re-run `{cmdname} {mt}` to \
+					get them again.");
+		}
+
+		// And remember what the checked ones looked like, so if any
+		// change before we get to them, we notice.
+		install::set_checked_hashfiles(pres.seen);
 	}
-	println!("Ok.");
+	sayln!("Ok.");
 
 
 
 	// Handle boot envs if we should
+	let mut recovery = Vec::new();
 	'mkbe: {
 		use crate::util::bectl;
 
@@ -162,10 +311,16 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			nstr
 		};
 		let snap = format!("{version}_{ts}");
-		print!("Creating snapshot of existing boot environment: ({snap})...  ");
-		stdout().flush()?;
+		say!("Creating snapshot of existing boot environment: ({snap})...  ");
+		out::flush();
 		bectl::create(&snap)?;
-		println!("Done.");
+		sayln!("Done.");
+
+		// Remember it for later, after making sure it's really there
+		use crate::state::{RecoveryPoint, RecoveryKind};
+		let found = bectl::exists(&snap).unwrap_or(false);
+		recovery.push(RecoveryPoint::new(RecoveryKind::BootEnv, snap,
+				version.to_string(), found));
 	}
 
 
@@ -180,7 +335,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	//
 	// f-u.sh install_unschg()
 	let cnlen = cn_paths.len();
-	println!("Checking file flags ({cnlen} path{} to scan)", plural(cnlen));
+	sayln!("Checking file flags ({cnlen} path{} to scan)", plural(cnlen));
 	let schgs = {
 		use crate::core::scan;
 		let bd = config.basedir().to_path_buf();
@@ -189,7 +344,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let nschg = schgs.len();
 	if nschg == 0
 	{
-		println!("No +schg files found.");
+		sayln!("No +schg files found.");
 	}
 	else
 	{
@@ -200,9 +355,9 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		{
 			match crate::util::euid()
 			{
-				0 => println!("{nschg} +schg file{} found, clearing   (dry run)",
+				0 => sayln!("{nschg} +schg file{} found, clearing   (dry run)",
 						plural(nschg)),
-				_ => println!("{}  (dry run)", nr_msg()),
+				_ => sayln!("{}  (dry run)", nr_msg()),
 			};
 		}
 		else
@@ -213,20 +368,29 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 				anyhow::bail!(nr_msg());
 			}
 
-			print!("{nschg} +schg file{} found.  Clearing flags...",
+			say!("{nschg} +schg file{} found.  Clearing flags...",
 					plural(nschg));
-			stdout().flush()?;
+			out::flush();
 			for f in schgs
 			{
 				use crate::util::unschg_file;
 				let fpath = path_join(config.basedir(), &f.0);
 				unschg_file(&fpath, f.1)?;
 			}
-			println!("Done.");
+			sayln!("Done.");
 		}
 	}
 
 
+	// Will the flags we set actually stick?  On something like tmpfs
+	// they just don't, and nothing along the way would say so.  Not
+	// being root, we're not setting any anyway.
+	let flags_lost = match crate::util::euid() {
+		0 => check_flags(config.basedir(), manifest, args.require_flags)?,
+		_ => None,
+	};
+
+
 
 	/*
 	 * OK, now we can start looking at the individual steps we take.
@@ -243,11 +407,37 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	 * reboot, then install the world, wait for them to deal with
 	 * rebuilding anything, then remove the old .so.*'s.
 	 */
-	println!("Beginning install.\n");
+	let owners = match &args.ownership_manifest {
+		Some(f) => Some(install::OwnerManifest::load_or_new(f)?),
+		None => None,
+	};
+	let mut inst = Installed { owners, lines: HashMap::new(),
+			retained: Vec::new(), version: version.to_string(), recovery,
+			regen: Default::default(), deferred: Vec::new(),
+			failed: Vec::new() };
+
+	let mut tm = crate::util::timings::Timings::new();
+	let _status = match args.dry_run {
+		true  => None,
+		false => Some(crate::util::status::start(rtdirs.status_file(),
+				"install")),
+	};
+	tm.phase("Installing");
+	sayln!("Beginning install.\n");
 	let iret = match manifest {
-		Manifest::Fetch(_)   => fetch(&args, &rtdirs, &config, manifest)?,
-		Manifest::Upgrade(_) => upgrade(&args, &rtdirs, &config, manifest)?,
+		Manifest::Fetch(_)   => fetch(&args, &rtdirs, &config, jail,
+				manifest, &mut inst)?,
+		Manifest::Upgrade(_) => upgrade(&args, &rtdirs, &config, jail,
+				manifest, &mut inst)?,
+	};
+
+	// Anything we kept going past?  Those are all that stay pending, and
+	// the step they're part of waits on them.
+	let failed: Vec<_> = match &iret {
+		InstRet::Failed(f) => f.iter().map(|(p, _)| p.clone()).collect(),
+		_ => Vec::new(),
 	};
+	manifest.set_install_failed(failed);
 
 
 	// XXX f-u.sh has rollback, I'm not doing that right now...
@@ -256,11 +446,172 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// Depending on the result, do the appropriate thing before
 	// returning.  If it's a dry run, the appropriate thing is always
 	// nothing, so...
+	let mut ret = 0;
 	if !args.dry_run
 	{
+		// Make sure what we installed looks like what we meant to
+		let vfull = args.verify_full;
+		let nlines = inst.lines.len();
+		if nlines > 0
+		{
+			tm.phase("Verifying");
+			say!("\nVerifying {nlines} installed path{}{}...  ",
+					plural(nlines), if vfull { " (full)" } else { "" });
+			out::flush();
+			let bad = install::verify(config.basedir(), &inst.lines, vfull)?;
+			match bad.is_empty() {
+				true  => sayln!("OK."),
+				false => {
+					sayln!("FAILED.");
+					verify_report(&bad);
+					ret = VERIFY_FAILED;
+				},
+			}
+		}
+
+		tm.end();
+
+		// What we had to skip
+		if let InstRet::Failed(failed) = &iret
+		{
+			let nf = failed.len();
+			sayln!("\n{nf} path{} failed to install:", plural(nf));
+			for (p, e) in failed { sayln!("  {}: {e}", p.display()); }
+			sayln!("Fix what's wrong and run `{cmdname} install` again to \
+					retry just {}.", if nf == 1 { "it" } else { "those" });
+			ret = INSTALL_FAILED;
+		}
+
+		// Anything that lost ACLs or extattrs along the way?
+		let dropped = install::take_dropped_extras();
+		if !dropped.is_empty()
+		{
+			let nd = dropped.len();
+			sayln!("\nACLs/extended attributes not carried over to {nd} \
+					replaced file{}:", plural(nd));
+			for (p, what) in dropped
+			{ sayln!("  {}: {what}", p.display()); }
+		}
+
+		// And flags that didn't stick; worth saying again down here.
+		if let Some(fl) = &flags_lost
+		{ sayln!("\nWARNING: {}.", fl.describe()); }
+
+		// Dirs we left for their local content; mention any we haven't
+		// before, and remember them all for next time.
+		let mut retained = std::mem::take(&mut inst.retained);
+		retained.sort_unstable();
+		retained.dedup();
+		let newret: Vec<_> = retained.iter()
+				.filter(|p| !state.retained_dirs.contains(p)).collect();
+		if !newret.is_empty()
+		{
+			let nr = newret.len();
+			sayln!("\n{nr} director{} removed upstream kept, since local \
+					files remain in {}:", if nr == 1 { "y" } else { "ies" },
+					if nr == 1 { "it" } else { "them" });
+			for p in newret { sayln!("  {}", p.display()); }
+		}
+		state.retained_dirs.extend(retained);
+		state.retained_dirs.sort_unstable();
+		state.retained_dirs.dedup();
+		state.retained_dirs.retain(|p| path_join(config.basedir(), p).is_dir());
+
+		// What we could roll back to
+		if !inst.recovery.is_empty() { sayln!(""); }
+		for rp in std::mem::take(&mut inst.recovery)
+		{
+			sayln!("To roll back: {}", rp.describe());
+			state.add_recovery(rp);
+		}
+
+		// Helpers we couldn't run here
+		if !inst.deferred.is_empty()
+		{
+			let nd = inst.deferred.len();
+			sayln!("\nWARNING: {nd} post-install command{} not run; run \
+					{} yourself:", plural(nd),
+					if nd == 1 { "it" } else { "them" });
+			for c in &inst.deferred { sayln!("  {c}"); }
+			state.deferred_helpers.append(&mut inst.deferred);
+		}
+
+		// Write out what ownership things should have had
+		if let (Some(om), Some(f)) = (&inst.owners, &args.ownership_manifest)
+		{
+			om.write(f)?;
+			sayln!("Wrote ownership manifest ({} entries) to {}.",
+					om.len(), f.display());
+		}
+
+		// Finishing up in a jail we were asked to restart?
+		let restart = match (&iret, jail) {
+			(InstRet::Done | InstRet::LibsKept(_), Some(j))
+					if args.restart_jail => Some(j),
+			_ => None,
+		};
+
+		// Finishing up?  The manifest's about to go away, so audit the
+		// result (if asked), save the /etc report, and note it all in
+		// the history while we can.
+		if matches!(iret, InstRet::Done | InstRet::LibsKept(_))
+		{
+			let audit = match (&state.manifest, args.verify) {
+				(Some(Manifest::Upgrade(mup)), true) => {
+					tm.phase("Auditing");
+					let unch = match upgrade_unchanged(&state, &rtdirs,
+							&config) {
+						Ok(u) => Some(u),
+						Err(e) => {
+							sayln!("\nCan't tell what the upgrade found \
+									already up to date ({e}); only \
+									auditing what it installed.");
+							None
+						},
+					};
+					let a = closing_audit(config.basedir(), mup,
+							unch.as_ref())?;
+					tm.end();
+					if !a.passed() { ret = VERIFY_FAILED; }
+					Some(a)
+				},
+				_ => None,
+			};
+			save_etc_report(&state, &rtdirs, &config, &version);
+
+			if let Some(m) = &state.manifest
+			{
+				state.last_install_dirs = m.install_dirs();
+
+				use crate::state::HistoryEntry;
+				let he = HistoryEntry { mtype: m.mtype().to_string(),
+						from: version.to_string(),
+						to: m.version().to_string(),
+						when: chrono::Utc::now().timestamp(), audit,
+						deferred: std::mem::take(&mut state.deferred_helpers),
+						counts: Some((&m.brief()).into()),
+						flags_lost: flags_lost.clone() };
+
+				// The path lists, for show-install to look back at.
+				// Not worth failing the install over.
+				use crate::core::history;
+				let hdir = rtdirs.history_dir();
+				let keep = config.install_history_detail as usize;
+				if keep > 0
+				{
+					let det = history::Detail::from_manifest(m);
+					if let Err(e) = history::save(&hdir, &he, &det)
+					{ eprintln!("WARNING: couldn't save install details: {e}"); }
+				}
+				state.add_history(he);
+				if let Err(e) = history::prune(&hdir, &state.history, keep)
+				{ eprintln!("WARNING: couldn't prune old install details: {e}"); }
+			}
+		}
+
 		match iret
 		{
-			InstRet::Save => {
+			InstRet::Save | InstRet::Failed(_) => {
 				// Updated manifest, save it
 				rtdirs.state_save(&state)?;
 			},
@@ -269,29 +620,270 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 				state.manifest = None;
 				rtdirs.state_save(&state)?;
 			},
+			InstRet::LibsKept(libs) => {
+				// Done, but remember what we left lying around, along
+				// with whatever earlier upgrades left that's still there.
+				state.manifest = None;
+				state.kept_libs.extend(libs);
+				state.kept_libs.sort_unstable();
+				state.kept_libs.dedup();
+				state.kept_libs.retain(|p| path_join(config.basedir(), p)
+						.exists());
+				rtdirs.state_save(&state)?;
+			},
+			InstRet::Reboot => {
+				// Save like usual, leave a note for the resume script
+				// (if there is one), and go down.  Unless what we just
+				// installed looks wrong; then rebooting into it is a
+				// bad idea.
+				rtdirs.state_save(&state)?;
+				if ret == VERIFY_FAILED
+				{
+					sayln!("Not rebooting, since verification failed.");
+					return Ok(ret);
+				}
+				if install::resume_enabled(config.basedir())
+				{
+					std::fs::write(&marker, "")?;
+				}
+				else
+				{
+					sayln!("No resume script installed; run \
+							`{cmdname} install` again after the reboot.");
+				}
+				install::reboot()?;
+			},
 			// None -> doesn't exist anymore...  if the subfuncs finish
 			// cleanly, it's because they either did something (so we're
 			// doing something) or it was a dry run (and we already
 			// skipped this).
 		}
+
+		if let Some(j) = restart
+		{
+			say!("Restarting jail {j}...  ");
+			out::flush();
+			match j.restart() {
+				Ok(()) => sayln!("Done."),
+				Err(e) => sayln!("failed\n{e}"),
+			}
+		}
+	}
+	else if args.restart_jail && jail.is_some()
+	{
+		sayln!("Would restart jail when done   (dry run)");
 	}
 
 
-	Ok(())
+	tm.report(clargs.timings);
+	Ok(ret)
+}
+
+
+
+
+/// Make sure the basedir's filesystem keeps the flags the pending install
+/// sets.  If it doesn't, warn (loudly), or with --require-flags, error
+/// out.  Returns what gets lost, for the history.
+fn check_flags(basedir: &Path, manifest: &Manifest, require: bool)
+		-> Result<Option<FlagsLost>, anyhow::Error>
+{
+	use crate::core::fsprobe::{self, FlagCheck};
+
+	// Everything with flags that's going in
+	let sum = manifest.change_summary();
+	let paths: Vec<_> = sum.added.into_iter().chain(sum.updated).collect();
+	let lines = match manifest {
+		Manifest::Fetch(f)   => f.new.get_from_paths(paths),
+		Manifest::Upgrade(u) => u.get_from_paths(paths),
+	};
+	let wanted: Vec<u32> = lines.values().filter_map(|l| l.flags())
+			.filter(|f| *f != 0).collect();
+
+	let fl = match fsprobe::check_flags(basedir, &wanted) {
+		FlagCheck::Ok => return Ok(None),
+		FlagCheck::Unknown(w) if require => bail!("{w}  (--require-flags)"),
+		FlagCheck::Unknown(w) => {
+			sayln!("WARNING: {w}");
+			return Ok(None);
+		},
+		FlagCheck::Lost(fl) => fl,
+	};
+
+	if require
+	{
+		bail!("Not installing, since {} (--require-flags).", fl.describe());
+	}
+	sayln!("\n*** WARNING: {}.", fl.describe());
+	sayln!("*** The install will go ahead without them; anything copied \
+			from here later won't\n*** have them either.  Use \
+			--require-flags to refuse instead.\n");
+	Ok(Some(fl))
 }
 
 
 
+/// Do some checks of our config/etc
+fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
+{
+	let mut errs: Vec<String> = vec![];
+
+	macro_rules! check {
+		( $fld:ident) => {
+			match crate::check::$fld(&carg.config) {
+				Ok(_) => (),
+				Err(e) => errs.push(e),
+			}
+		};
+	}
+
+	check!(workdir);
+	check!(basedir);
+
+	// Should only run on releases
+	match crate::check::version(&carg.version) {
+		Ok(_) => (),
+		Err(e) => errs.push(e),
+	};
+
+
+	match errs.len() {
+		0 => Ok(()),
+		_ => {
+			use anyhow::anyhow;
+			let estr = anyhow!("Cannot run install::\n  - {}",
+					errs.join("\n  - "));
+			Err(estr)
+		},
+	}
+}
+
+
 
 /*
  * Fetch/Upgrade individual variants
  */
 use crate::command::FrCmdInstall;
 use crate::config::Config;
+use crate::util::jail::Jail;
+use crate::core::fsprobe::FlagsLost;
+use install::OwnerManifest;
+
+/// Exit status when the install went through, but what's on disk
+/// doesn't look like what we installed.
+const VERIFY_FAILED: u8 = 2;
+
+/// Exit status when --keep-going got past some paths it couldn't install.
+const INSTALL_FAILED: u8 = 3;
+
+/// Tracking of what we've installed along the way
+#[derive(Debug)]
+struct Installed
+{
+	/// Ownership manifest we're building up, if we are
+	owners: Option<OwnerManifest>,
+
+	/// Everything we installed, for verifying afterward
+	lines: HashMap<PathBuf, MetadataLine>,
+
+	/// Removed-upstream dirs we kept for their local content
+	retained: Vec<PathBuf>,
+
+	/// The version we're installing from
+	version: String,
+
+	/// BE's and kernel backups we've made
+	recovery: Vec<crate::state::RecoveryPoint>,
+
+	/// Derived db's we skipped installing, to regenerate instead
+	regen: std::collections::BTreeSet<crate::core::derived::Regen>,
+
+	/// Post-install helpers we couldn't run, as what to run instead
+	deferred: Vec<String>,
+
+	/// What we kept going past in the step we're on
+	failed: install::Failed,
+}
+
+impl Installed
+{
+	/// Install a batch of stuff, noting what went in, and remembering
+	/// anything that didn't (busy, or kept going past).
+	fn split(&mut self, smd: SplitTypes, rtdirs: &RtDirs, basedir: &Path,
+			args: &FrCmdInstall) -> Result<(), anyhow::Error>
+	{
+		let mut done = smd.clone();
+		let failed = install::split(smd, rtdirs, basedir, args.busy_files,
+				args.keep_going, args.dry_run)?;
+		failed.iter().for_each(|(p, _)| done.remove(p));
+
+		if let Some(om) = self.owners.as_mut() { om.record(&done); }
+		let all = done.dirs.into_iter().chain(done.files)
+				.chain(done.syms).chain(done.hards);
+		self.lines.extend(all);
+		self.failed.extend(failed);
+		Ok(())
+	}
+
+	/// Anything we kept going past, as the step's result.
+	fn take_failed(&mut self) -> Option<InstRet>
+	{
+		match self.failed.is_empty() {
+			true  => None,
+			false => Some(InstRet::Failed(std::mem::take(&mut self.failed))),
+		}
+	}
+
+	/// Pull out any derived files that'll get regenerated from a source
+	/// we're also installing, rather than installed.
+	fn skip_derived(&mut self, lines: &mut HashMap<PathBuf, MetadataLine>)
+	{
+		use crate::core::derived;
+
+		let (skip, regen) = {
+			let paths = lines.keys().map(|p| p.as_path()).collect();
+			let (skip, regen) = derived::skip_installing(&paths);
+			let skip: Vec<_> = skip.into_iter().map(|p| p.to_path_buf())
+					.collect();
+			(skip, regen)
+		};
+		for p in &skip
+		{
+			lines.remove(p);
+			sayln!("Not installing {}; it'll be regenerated.", p.display());
+		}
+		self.regen.extend(regen);
+	}
+
+	/// Run a post-install helper, remembering it if we couldn't.
+	fn helper(&mut self, h: install::Helper, config: &Config)
+			-> Result<(), anyhow::Error>
+	{
+		install::run_helper(h, config.basedir(), config.install_helpers,
+				&mut self.deferred)
+	}
+
+	/// Backup the kernel, and remember where it went.
+	fn backup_kernel(&mut self, basedir: &Path) -> Result<(), anyhow::Error>
+	{
+		let bakdir = match install::backup_kernel(basedir)? {
+			Some(d) => d,
+			None => return Ok(()),
+		};
+
+		use crate::state::{RecoveryPoint, RecoveryKind};
+		let found = path_join(basedir, &bakdir).join("kernel").is_file();
+		let name = bakdir.display().to_string();
+		self.recovery.push(RecoveryPoint::new(RecoveryKind::KernelBackup,
+				name, self.version.clone(), found));
+		Ok(())
+	}
+}
 
 /// Do the install for a 'fetch' invocation
 fn fetch(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
-		manifest: &Manifest)
+		jail: Option<&Jail>, manifest: &Manifest,
+		inst: &mut Installed)
 		-> Result<InstRet, anyhow::Error>
 {
 	let dry = args.dry_run;
@@ -320,8 +912,25 @@ fn fetch(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 	// delete the things in removed.  So let's go through the
 	// added/updated and get the MetadataLine's for 'em.
 	use crate::util::uniq_vecs;
-	let ipaths = uniq_vecs(&mut [added, updated]);
-	let ilines = mf.new.get_from_paths(ipaths);
+	let mut ipaths = uniq_vecs(&mut [added, updated]);
+
+	// If a --keep-going install got through everything but a few paths,
+	// those are all there is left to do.
+	let retry = manifest.install_failed();
+	let removed = match retry.is_empty() {
+		true  => removed,
+		false => {
+			let nr = retry.len();
+			sayln!("Retrying {nr} path{} that failed to install last time.",
+					plural(nr));
+			ipaths.retain(|p| retry.contains(p));
+			Vec::new()
+		},
+	};
+	let mut ilines = mf.new.get_from_paths(ipaths);
+
+	// Derived db's we'll regenerate, not install.
+	inst.skip_derived(&mut ilines);
 
 	// Split out into the different types.
 	let smd = split_metadata(ilines);
@@ -331,14 +940,16 @@ fn fetch(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 	// And now the action
 	//
 
-	// Do the kernel backup first.
-	if !dry { install::backup_kernel(config.basedir())?; }
+	// Do the kernel backup first.  On a retry, what's there now is
+	// already the new one.
+	if !dry && retry.is_empty() { inst.backup_kernel(config.basedir())?; }
 
 	// Install the bits
-	install::split(smd, rtdirs, config.basedir(), dry)?;
+	self_last(&smd, config.basedir());
+	inst.split(smd, rtdirs, config.basedir(), args)?;
 
 	// Delete things that need deleting
-	match handle_removes(&removed, config.basedir(), dry)?
+	match handle_removes(&removed, config.basedir(), dry, inst)?
 	{
 		None => (),
 		Some(fail) => rmdirs_fails_warn(&fail),
@@ -346,15 +957,18 @@ fn fetch(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 
 
 	// kldxref on non-dry
-	if !dry { install::kldxref(config.basedir())?; }
+	if !dry { inst.helper(install::Helper::Kldxref, config)?; }
 
 	// Kick the postworld bits
-	if !dry { post_world(config.basedir())? };
+	post_world(args, config, jail, inst)?;
+
+	// Anything we kept going past is all that's left to do.
+	if let Some(fr) = inst.take_failed() { return Ok(fr); }
 
 
 	// And that's it.  Fetch is a single step, so if we make it this far,
 	// the install is all done (if we did stuff, anyway).
-	println!("\n\nInstall complete{}.",
+	sayln!("\n\nInstall complete{}.",
 			if dry { "   (dry run)" } else { "" });
 	Ok(InstRet::Done)
 }
@@ -363,7 +977,8 @@ fn fetch(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 
 /// Do the install for a 'upgrade' invocation
 fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
-		manifest: &mut Manifest)
+		jail: Option<&Jail>, manifest: &mut Manifest,
+		inst: &mut Installed)
 		-> Result<InstRet, anyhow::Error>
 {
 	// Dry run upgrade is a little trickier, since we have to run all 3
@@ -378,6 +993,10 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 	use crate::state::ManifestSummary;
 	let ManifestSummary { added, removed, updated} = csum;
 
+	// What a --keep-going run before left undone, if anything.  That's
+	// all there is left of whatever step it was on.
+	let mut retry = manifest.install_failed().to_vec();
+
 	// Pull out the ManiUpgrade
 	let mu = match manifest {
 		Manifest::Upgrade(u) => u,
@@ -414,54 +1033,78 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 	{
 		// Kernel means "everything that starts with /boot" by our
 		// meaning, so strip down to those things.
-		println!("Installing kernel...");
+		sayln!("Installing kernel...");
+		crate::util::status::phase("Installing kernel");
+		let retry = std::mem::take(&mut retry);
 
-		// Backup the kernel first
-		if !dry { install::backup_kernel(config.basedir())?; }
+		// Backup the kernel first.  On a retry, what's there now is
+		// already mostly the new one.
+		if !dry && retry.is_empty() { inst.backup_kernel(config.basedir())?; }
 
 		// Filter down our install/remove lists.
-		let klines: HashMap<_, _> = ilines.iter().filter_map(|(p, m)| {
+		let mut klines: HashMap<_, _> = ilines.iter().filter_map(|(p, m)| {
 			match is_kernel_dir(p) {
 				false => None,
 				true  => Some((p.clone(), m.clone())),
 			}
 		}).collect();
 		let kremoved: Vec<_> = removed.iter().filter_map(|p| {
-			match is_kernel_dir(p) {
+			match is_kernel_dir(p) && retry.is_empty() {
 				false => None,
 				true  => Some(p),
 			}
 		}).collect();
+		retry_only(&mut klines, &retry);
 
 		// Now we can smd-ify
 		let smd = split_metadata(klines);
 
 		// Do the install/delete
-		install::split(smd, rtdirs, config.basedir(), dry)?;
-		match handle_removes(&kremoved, config.basedir(), dry)?
+		inst.split(smd, rtdirs, config.basedir(), args)?;
+		match handle_removes(&kremoved, config.basedir(), dry, inst)?
 		{
 			None => (),
 			Some(fail) => rmdirs_fails_warn(&fail),
 		}
 
 		// kldxref on non-dry
-		if !dry { install::kldxref(config.basedir())?; }
+		if !dry { inst.helper(install::Helper::Kldxref, config)?; }
+
+		// Not all of it went in?  Then the kernel's not done, and
+		// neither rebooting into it nor going on to world is a good
+		// idea.
+		if let Some(fr) = inst.take_failed()
+		{
+			sayln!("\nKernel update incomplete; not {}.",
+					match args.reboot {
+						true  => "rebooting",
+						false => "going on to world",
+					});
+			return Ok(fr);
+		}
 
 		// If this wasn't a dry run, and we got here, we're done.  Dry
 		// runs would quietly proceed ahead.
 		mu.kernel = true;
 		if dry
 		{
-			println!("\nKernel updated installed.  (dry run, continuing)\n");
+			sayln!("\nKernel updated installed.  (dry run, continuing)\n");
 		}
 		else
 		{
-			println!("\nKernel updates have been installed.  Please reboot \
+			sayln!("\nKernel updates have been installed.  Please reboot \
 					and run\n`{cmdname} install` again to finish \
 					installing updates.");
+			if args.reboot
+			{
+				match reboot_ok(config, mu) {
+					Ok(()) => return Ok(InstRet::Reboot),
+					Err(e) => sayln!("\nNot rebooting: {e}."),
+				}
+			}
 			match args.all
 			{
-				true => println!("\n  (run with --all, proceeding anyway)\n"),
+				true => sayln!("\n  (run with --all, proceeding anyway)\n"),
 				false => return Ok(InstRet::Save),
 			}
 		}
@@ -471,11 +1114,13 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 	// Need to install the world?
 	if !mu.world
 	{
-		println!("Installing world...");
+		sayln!("Installing world...");
+		crate::util::status::phase("Installing world");
+		let retry = std::mem::take(&mut retry);
 
 		// Well, first of all, world doesn't include the stuff we did in
 		// the kernel dir above.
-		let wlines: HashMap<_, _> = ilines.iter().filter_map(|(p, m)| {
+		let mut wlines: HashMap<_, _> = ilines.iter().filter_map(|(p, m)| {
 			match is_kernel_dir(p) {
 				true  => None,
 				false => Some((p.clone(), m.clone())),
@@ -508,27 +1153,40 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 		// - Filter down the removals to cut out ld-elf and .so's, but go
 		//   ahead and [try to] delete the dirs.
 
-		// So we just install what we worked out, like usual.
+		// So we just install what we worked out, like usual, less any
+		// derived db's we'll regenerate.
+		inst.skip_derived(&mut wlines);
+		retry_only(&mut wlines, &retry);
 		let smd = split_metadata(wlines);
-		install::split(smd, rtdirs, config.basedir(), dry)?;
-
-		// And remove everything that doesn't match ld/.so.  Make a list
-		// of the .so's we'd remove for a message...
-		let linker = install::re_linker_file();
-		let shlib = install::re_so_file();
-		let mut rm_sos = false;
-		wremoved.retain(|p| {
-			let pstr = String::from_utf8_lossy(p.as_os_str().as_encoded_bytes());
-			if linker.is_match(&pstr) { return false; }
-			if shlib.is_match(&pstr)  { rm_sos = true; return false; }
-			true
-		});
-		handle_removes(&wremoved, config.basedir(), dry)?;
-		// Don't bother warning here.
+		self_last(&smd, config.basedir());
+		inst.split(smd, rtdirs, config.basedir(), args)?;
+
+		// And remove everything that doesn't match ld/.so.  The .so's
+		// get recorded in the manifest, so the final step knows just
+		// what it's going to be removing.  A retry already did this.
+		if retry.is_empty()
+		{
+			let linker = install::re_linker_file();
+			let shlib = install::re_so_file();
+			let mut old_libs = Vec::new();
+			wremoved.retain(|p| {
+				let pstr = String::from_utf8_lossy(p.as_os_str().as_encoded_bytes());
+				if linker.is_match(&pstr) { return false; }
+				if shlib.is_match(&pstr)  { old_libs.push(p.to_path_buf()); return false; }
+				true
+			});
+			old_libs.sort_unstable();
+			mu.old_libs = old_libs;
+			handle_removes(&wremoved, config.basedir(), dry, inst)?;
+			// Don't bother warning here.
+		}
 
 
 		// Now do the postworld stuff
-		if !dry { post_world(config.basedir())?; }
+		post_world(args, config, jail, inst)?;
+
+		// World's not done 'till all of it's in.
+		if let Some(fr) = inst.take_failed() { return Ok(fr); }
 
 
 		// OK, world done.  If there are so's to remove, stop here and
@@ -536,14 +1194,14 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 		mu.world = true;
 		if dry
 		{
-			println!("\nWorld updated installed.  (dry run, continuing)\n");
+			sayln!("\nWorld updated installed.  (dry run, continuing)\n");
 		}
 		else
 		{
-			println!("\nWorld update installed.");
-			if rm_sos
+			sayln!("\nWorld update installed.");
+			if !mu.old_libs.is_empty()
 			{
-				println!("\
+				sayln!("\
 					Completing this upgrade requires removing old shared \
 					object files.\n\
 					Please rebuild all installed 3rd party software \
@@ -552,7 +1210,7 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 					updates.");
 				match args.all
 				{
-					true => println!("\n  (run with --all, proceeding anyway)\n"),
+					true => sayln!("\n  (run with --all, proceeding anyway)\n"),
 					false => return Ok(InstRet::Save),
 				}
 			}
@@ -562,8 +1220,64 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 
 	// Now do final cleanup.  f-u.sh does some grepping around to try and
 	// find the things that weren't already cleaned up in the earlier
-	// steps, but screw that, I'll just redo _all_ the deletes.
-	match handle_removes(&removed, config.basedir(), dry)?
+	// steps, but screw that, I'll just redo _all_ the deletes.  Except
+	// for the old libs, which get some extra care.
+	use std::collections::HashSet;
+	let libs = mu.old_libs.clone();
+	let libset: HashSet<_> = libs.iter().collect();
+
+	if args.skip_lib_cleanup
+	{
+		let rest: Vec<_> = removed.iter()
+				.filter(|p| !libset.contains(p)).collect();
+		match handle_removes(&rest, config.basedir(), dry, inst)?
+		{
+			None => (),
+			Some(fail) => rmdirs_fails_warn(&fail),
+		}
+
+		let nl = libs.len();
+		sayln!("\n\nUpgrade complete{}; leaving {nl} old shared lib{} \
+				in place.", if dry { "   (dry run)" } else { "" }, plural(nl));
+		return Ok(InstRet::LibsKept(libs));
+	}
+
+	if !args.force_lib_cleanup
+	{
+		// No procstat (or it can't see in from here) means we can't
+		// tell, so leave the call to the user.
+		let users = match install::lib_users(config.basedir(), &libs) {
+			Ok(u) => u,
+			Err(e) => bail!("Can't tell whether anything is still using \
+					the old shared libs: {e}\nUse --force-lib-cleanup to \
+					remove them anyway, or --skip-lib-cleanup to leave \
+					them in place."),
+		};
+		if !users.is_empty()
+		{
+			let nu = users.len();
+			sayln!("\n{nu} running process{} still using old shared libs:",
+					if nu == 1 { "" } else { "es" });
+			for u in &users
+			{
+				sayln!("  {} ({})", u.pid, u.comm);
+				for l in &u.libs { sayln!("      {}", l.display()); }
+			}
+			match dry
+			{
+				true => sayln!("\n  (dry run, continuing)\n"),
+				false => {
+					sayln!("\nNot removing old shared libs.  Restart or \
+							rebuild the above and run `{cmdname} install` \
+							again,\nor use --force-lib-cleanup or \
+							--skip-lib-cleanup.");
+					return Ok(InstRet::Save);
+				},
+			}
+		}
+	}
+
+	match handle_removes(&removed, config.basedir(), dry, inst)?
 	{
 		None => (),
 		Some(fail) => rmdirs_fails_warn(&fail),
@@ -572,7 +1286,7 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
 
 
 	// I guess we're done.
-	println!("\n\nUpgrade complete{}.",
+	sayln!("\n\nUpgrade complete{}.",
 			if dry { "   (dry run)" } else { "" });
 	Ok(InstRet::Done)
 }
@@ -587,6 +1301,17 @@ fn upgrade(args: &FrCmdInstall, rtdirs: &RtDirs, config: &Config,
  * this file...
  */
 
+/// Cut a step's lines down to just what a --keep-going run before
+/// couldn't install, if that's where we are.
+fn retry_only(lines: &mut HashMap<PathBuf, MetadataLine>, retry: &[PathBuf])
+{
+	if retry.is_empty() { return; }
+	let nr = retry.len();
+	sayln!("Retrying {nr} path{} that failed to install last time.",
+			plural(nr));
+	lines.retain(|p, _| retry.contains(p));
+}
+
 /// Given a set of paths and MetadataLine's, split them out into the
 /// individual install types.
 fn split_metadata(mds: HashMap<PathBuf, MetadataLine>) -> SplitTypes
@@ -596,8 +1321,21 @@ fn split_metadata(mds: HashMap<PathBuf, MetadataLine>) -> SplitTypes
 
 
 
-/// Handle removing files
-fn handle_removes(rms: &[impl AsRef<Path>], basedir: &Path, dry: bool)
+/// Say if we're about to install over what we're running from, and have
+/// those go in last.
+fn self_last(smd: &SplitTypes, basedir: &Path)
+{
+	use install::selfexe;
+	let ours = selfexe::find(smd, basedir);
+	selfexe::describe(&ours).iter().for_each(|l| sayln!("{l}"));
+	install::set_install_last(ours);
+}
+
+
+/// Handle removing files.  Dirs kept for having local stuff in them get
+/// noted in inst.
+fn handle_removes(rms: &[impl AsRef<Path>], basedir: &Path, dry: bool,
+		inst: &mut Installed)
 		-> Result<Option<Vec<PathBuf>>, anyhow::Error>
 {
 	let rmlen = rms.len();
@@ -605,34 +1343,97 @@ fn handle_removes(rms: &[impl AsRef<Path>], basedir: &Path, dry: bool)
 
 	if dry
 	{
-		println!("{rmlen} file{} to remove   (dry run)", plural(rmlen));
+		sayln!("{rmlen} file{} to remove   (dry run)", plural(rmlen));
 		return Ok(None);
 	}
 
 	// Else there's stuff to do.
-	let mut rdirs = Vec::new();
-	print!("Deleting {rmlen} path{}...   ", plural(rmlen));
-	stdout().flush()?;
-
-	// In practice, rms is probably already always sorted, but be safe
-	// and sort ourselves.  And then reverse it; we want to rm
-	// reverse-depth-first, otherwise we find that a dir is not empty and
-	// fail to delete it, before we delete the stuff in it...
-	use itertools::Itertools as _;
-	for p in rms.iter().map(|p| p.as_ref()).sorted_unstable().rev()
-	{
-		let rmp = path_join(basedir, p);
-		if install::rm(&rmp)? { rdirs.push(p); }
+	say!("Deleting {rmlen} path{}...   ", plural(rmlen));
+	out::flush();
+	let res = install::remove(basedir, rms)?;
+	sayln!("Done.");
+
+	inst.retained.extend(res.retained);
+	match res.failed.len() {
+		0 => Ok(None),
+		_ => Ok(Some(res.failed)),
 	}
-	println!("Done.");
+}
 
+/// What an upgrade found already up to date, worked back out from the
+/// target version's metadata that it saved.
+fn upgrade_unchanged(state: &crate::state::State, rtdirs: &RtDirs,
+		config: &Config)
+		-> Result<crate::metadata::Metadata, anyhow::Error>
+{
+	use anyhow::anyhow;
 
-	match rdirs.len() {
-		0 => Ok(None),
-		_ => Ok(Some(rdirs.into_iter().map(|p| p.to_path_buf()).collect())),
+	let (mani, mup) = match &state.manifest {
+		Some(m @ Manifest::Upgrade(u)) => (m, u),
+		_ => bail!("no pending upgrade"),
+	};
+
+	// One-off path filters didn't get kept, so we can't filter the same
+	if mani.note().is_some()
+	{ bail!("it was made with nonstandard path filters"); }
+
+	let to = mani.version();
+	let idx = state.meta_idx.as_ref()
+			.filter(|_| state.meta_idx_vers.as_ref().is_some_and(|v|
+				v.release == to.release && v.reltype == to.reltype))
+			.ok_or_else(|| anyhow!("no saved metadata for {to}"))?;
+	let which = ["all"];
+	idx.check_hashes(rtdirs.files(), rtdirs.tmp(), rtdirs.mdcache(), &which)
+			.map_err(|e| anyhow!("{}", e.iter().map(|e| e.to_string())
+				.collect::<Vec<_>>().join("; ")))?;
+	let (all, _) = idx.parse_one_full("all", rtdirs.tmp(), config)?;
+	Ok(mup.unchanged(all.into_metadata(), &config.update_if_unmodified))
+}
+
+/// Audit a finished upgrade, and say how it went.
+fn closing_audit(basedir: &Path, mup: &crate::state::ManiUpgrade,
+		unchanged: Option<&crate::metadata::Metadata>)
+		-> Result<crate::state::Audit, anyhow::Error>
+{
+	say!("\nAuditing the upgraded system...  ");
+	out::flush();
+	let (checked, bad) = install::audit(basedir, mup, unchanged)?;
+	match bad.is_empty() {
+		true  => sayln!("OK, {checked} path{} match.", plural(checked)),
+		false => {
+			sayln!("FAILED; {} of {checked} path{} don't match.",
+					bad.len(), plural(checked));
+			verify_report(&bad);
+		},
 	}
+
+	let mismatches = bad.into_iter()
+			.map(|m| (m.path, m.what.to_string(), m.detail)).collect();
+	Ok(crate::state::Audit { checked, mismatches })
 }
 
+
+/// Show what verification turned up, grouped by what's wrong.
+fn verify_report(bad: &[install::Mismatch])
+{
+	use std::collections::BTreeMap;
+	let mut bywhat: BTreeMap<&str, Vec<&install::Mismatch>> = BTreeMap::new();
+	for m in bad { bywhat.entry(m.what).or_default().push(m); }
+
+	for (what, ms) in bywhat
+	{
+		let n = ms.len();
+		let (p, have) = (plural(n), if n == 1 { "has" } else { "have" });
+		match what {
+			"missing" => sayln!("{n} path{p} missing after install:"),
+			"type"    => sayln!("{n} path{p} {have} the wrong type:"),
+			_ => sayln!("{n} path{p} {have} {what} differing from expected:"),
+		}
+		for m in ms { sayln!("  {}: {}", m.path.display(), m.detail); }
+	}
+}
+
+
 /// Use the return from handle_removes() to warn.  Can make this smarter
 /// maybe...
 fn rmdirs_fails_warn(dirs: &[impl AsRef<Path>])
@@ -640,35 +1441,126 @@ fn rmdirs_fails_warn(dirs: &[impl AsRef<Path>])
 	let dlen = dirs.len();
 	if dlen > 0
 	{
-		println!("{dlen} director{} not removed:",
+		sayln!("{dlen} director{} not removed:",
 			if dlen > 1 { "ies" } else { "y" });
-		for d in dirs { println!("  {}", d.as_ref().display()); }
+		for d in dirs { sayln!("  {}", d.as_ref().display()); }
 	}
 }
 
 
 
-/// Post-world-install rebuilding stuff.
-fn post_world(basedir: &Path) -> Result<(), anyhow::Error>
+/// Is it safe to reboot after the kernel step?  Returns a description
+/// of why not if it's not.
+fn reboot_ok(config: &Config, mu: &crate::state::ManiUpgrade)
+		-> Result<(), &'static str>
 {
-	let atroot = basedir == &"/".as_ref();
+	if config.basedir() != &"/".as_ref()
+	{ return Err("not installing to /"); }
+	if crate::util::euid() != 0
+	{ return Err("not running as root"); }
+	if !mu.kernel
+	{ return Err("kernel step not complete"); }
+	if mu.world
+	{ return Err("world step already done; unexpected state"); }
+	if mu.merge_conflict.len() > 0
+	{ return Err("unresolved merge conflicts"); }
+	Ok(())
+}
+
+
+/// Install the rc.d script to resume after `install --reboot`.
+fn enable_resume(clargs: &crate::command::FrArgs, rtdirs: &RtDirs,
+		config: &Config) -> Result<(), anyhow::Error>
+{
+	let myself = std::env::current_exe()?;
+	let my_args = clargs.mk_args();
+	let marker = rtdirs.resume_marker();
+
+	let script = install::resume_script(&myself, &my_args, &marker);
+	install::write_resume(config.basedir(), &script)?;
+
+	let dst = path_join(config.basedir(), install::RESUME_SCRIPT);
+	sayln!("Installed resume script at {}.", dst.display());
+	sayln!("A pending install will be resumed on the next boot after \
+			`install --reboot`.");
+	Ok(())
+}
+
+
+
+/// Save the /etc report from a finishing upgrade, for show-merges to
+/// hand out later.  Just a warning if it doesn't work out.
+fn save_etc_report(state: &crate::state::State, rtdirs: &RtDirs,
+		config: &Config, version: &crate::info::Version)
+{
+	use crate::core::merge::report;
+
+	let (mup, upvers) = match &state.manifest {
+		Some(m @ Manifest::Upgrade(u)) => (u, m.version().to_string()),
+		_ => return,
+	};
+	let pats = [&config.merge_changes[..], &config.update_if_unmodified[..]];
+	let saved = rtdirs.etc_report_file();
+	let ret = report::build(mup, &pats, rtdirs, config.basedir(),
+			version.to_string(), upvers).and_then(|r| r.write(&saved));
+	match ret {
+		Ok(()) => sayln!("Saved /etc report for `{} show-merges \
+				--etc-report`.", crate::util::cmdname()),
+		Err(e) => eprintln!("WARNING: couldn't save /etc report: {e}"),
+	}
+}
+
 
-	// Restart sshd if it's running, since some cases where bits of it
-	// are updated behind its back could cause future logins to fail.
-	// (PR263489).  Only if we're working on the root system, of
-	// course...
-	if atroot { install::try_sshd_restart()?; }
 
-	// Rehash SSL certs.  certctl(1) has been around since 12.2; f-u.sh
-	// tries to support systems before that.  I don't.
-	install::rehash_certs(basedir)?;
+/// Post-world-install rebuilding stuff.  On a dry run, this just says
+/// what it'd do about services.
+fn post_world(args: &FrCmdInstall, config: &Config, jail: Option<&Jail>,
+		inst: &mut Installed)
+		-> Result<(), anyhow::Error>
+{
+	let basedir = config.basedir();
+	let atroot = basedir == &"/".as_ref();
 
-	// Rebuild passwd and login class DB
-	install::pwd_mkdb(basedir)?;
-	install::cap_mkdb(basedir)?;
+	// Restart sshd (and whatever else) if it's running and we replaced
+	// its bits, since some cases where bits of it are updated behind
+	// its back could cause future logins to fail (PR263489).  Only if
+	// we're working on the root system or a running jail, of course...
+	// and if the whole jail is getting restarted later, don't bother.
+	use install::services::{self, Target};
+	let target = match (atroot, jail) {
+		(true, _) => Target::Root,
+		(false, Some(_)) if args.restart_jail => Target::JailRestart,
+		(false, Some(_)) => Target::Jail,
+		(false, None) => Target::Elsewhere,
+	};
+	let excluded = |svc: &str| match &args.no_restart_services {
+		// Given with no names means all of 'em
+		Some(v) if v.is_empty() => true,
+		Some(v) if v.iter().any(|s| s == svc) => true,
+		_ => config.no_restart_services.iter().any(|s| s == svc),
+	};
+	let root = crate::util::euid() == 0;
+	let acts = services::plan(inst.lines.keys().map(|p| p.as_path()),
+			target, excluded, root);
+	services::run(&acts, jail, args.dry_run)?;
+	if args.dry_run { return Ok(()); }
+
+	// Rehash SSL certs
+	inst.helper(install::Helper::Certctl, config)?;
+
+	// Rebuild passwd and login class DB, and whatever other derived
+	// db's we skipped installing.
+	use crate::core::derived::Regen;
+	let mut regen = inst.regen.clone();
+	regen.extend(Regen::ALWAYS);
+	for r in regen
+	{
+		install::regenerate(r, basedir, config.install_helpers,
+				&mut inst.deferred)?;
+	}
 
 	// And unconditionally eat the work of rebuilding man indices
-	install::makewhatis(basedir)?;
+	inst.helper(install::Helper::Makewhatis, config)?;
 
 
 	// Guess that's it...
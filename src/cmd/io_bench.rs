@@ -0,0 +1,136 @@
+//! $0 io-bench
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::command::CmdArg;
+use crate::core::pool;
+
+
+/// Command: $0 io-bench
+///
+/// A fake hashcheck: each file gets made up and gzip'd and hashed in
+/// memory (the CPU part), then written out and synced under an IO slot
+/// (the disk part), the way the real pools do it.  Run it against a dir
+/// on a slow disk (or a size-capped tmpfs, with --delay-ms to make it
+/// slow) and compare how the different --jobs-io settings do.
+pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
+{
+	let args = match carg.clargs.command {
+		crate::command::FrCmds::IoBench(a) => a,
+		_ => unreachable!("I'm an io-bench, why does it think I'm not??"),
+	};
+
+	let dir = tempfile::Builder::new().prefix("io-bench.")
+			.tempdir_in(&args.dir)?;
+	let ctrl = Control {
+		dir: dir.path().to_path_buf(),
+		size: args.size << 10,
+		delay: Duration::from_millis(args.delay_ms),
+	};
+
+	let cpu = pool::jobs_cpu();
+	let ios = match args.io.is_empty() {
+		true  => vec![cpu, (cpu / 2).max(1)],
+		false => args.io.clone(),
+	};
+	println!("{} files of {} KB, {cpu} CPU workers, {} rounds each, \
+			writing under {}.", args.files, args.size, args.rounds.max(1),
+			args.dir.display());
+
+	for io in ios
+	{
+		pool::set_jobs_io(io);
+		let mut times = Vec::new();
+		for _ in 0..args.rounds.max(1)
+		{
+			use pool::Pool as _;
+			let start = Instant::now();
+			let bench = Bench { errs: Vec::new() };
+			let errs = bench.run(&ctrl, 0..args.files)?;
+			if let Some(e) = errs.first()
+			{ anyhow::bail!("{} errors, first: {e}", errs.len()); }
+			times.push(start.elapsed().as_secs_f64());
+		}
+
+		let (mean, sd) = mean_sd(&times);
+		let (min, max) = times.iter().fold((f64::MAX, 0.0f64),
+				|(lo, hi), t| (lo.min(*t), hi.max(*t)));
+		println!("  --jobs-io {io:<3}  mean {mean:>7.3}s  stddev \
+				{sd:>6.3}s  range {min:.3}-{max:.3}s");
+	}
+
+	Ok(())
+}
+
+
+/// Mean and (population) standard deviation
+fn mean_sd(v: &[f64]) -> (f64, f64)
+{
+	if v.is_empty() { return (0.0, 0.0); }
+	let n = v.len() as f64;
+	let mean = v.iter().sum::<f64>() / n;
+	let var = v.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+	(mean, var.sqrt())
+}
+
+
+#[derive(Debug, Clone)]
+struct Control
+{
+	dir: PathBuf,
+	size: usize,
+	delay: Duration,
+}
+
+struct Bench
+{
+	errs: Vec<std::io::Error>,
+}
+
+impl pool::Pool for Bench
+{
+	type PoolResult = Vec<std::io::Error>;
+	type Control = Control;
+	type UnitControl = Control;
+	fn mk_unitcontrol(ctrl: &Control) -> Control { ctrl.clone() }
+
+	type WorkRequest = usize;
+	type WorkResult  = ();
+	type WorkErr     = std::io::Error;
+
+	fn work(ctrl: &Control, req: usize) -> Result<(), std::io::Error>
+	{
+		use std::io::Write as _;
+
+		// Something gzip has to work at a bit, but not random enough
+		// to be all CPU.
+		let data: Vec<u8> = (0..ctrl.size)
+				.map(|i| (((i * 7 + req) % 251) ^ (i >> 9)) as u8).collect();
+		let mut gz = flate2::write::GzEncoder::new(Vec::new(),
+				flate2::Compression::default());
+		gz.write_all(&data)?;
+		let gz = gz.finish()?;
+		crate::util::hash::sha256_reader(&mut &gz[..])
+				.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other,
+					e.to_string()))?;
+
+		// And the writing
+		let _slot = pool::io_slot();
+		let path = ctrl.dir.join(format!("{req}.gz"));
+		let mut f = std::fs::File::create(&path)?;
+		f.write_all(&gz)?;
+		f.write_all(&data)?;
+		f.sync_all()?;
+		std::thread::sleep(ctrl.delay);
+		std::fs::remove_file(&path)
+	}
+
+	fn nthreads(&self) -> u32 { pool::jobs_cpu() }
+
+	fn work_result(&mut self, resp: Result<(), std::io::Error>)
+	{
+		if let Err(e) = resp { self.errs.push(e); }
+	}
+
+	fn finalize(self) -> Vec<std::io::Error> { self.errs }
+}
@@ -0,0 +1,155 @@
+//! $0 merge-file
+use std::path::Path;
+
+use crate::command::{CmdArg, FrCmdMergeFile};
+
+
+/// Command: $0 merge-file
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	let CmdArg { clargs, .. } = carg;
+	let args = match clargs.command {
+		crate::command::FrCmds::MergeFile(a) => a,
+		_ => unreachable!("I'm a merge-file, why does it think I'm not??"),
+	};
+
+	Ok(merge_exit(&args))
+}
+
+
+/// Do the merge, and turn the result into an exit status.
+///
+/// Like merge(1) and diff3(1), that's 0 for a clean merge, 1 for
+/// conflicts, 2 for trouble.
+fn merge_exit(args: &FrCmdMergeFile) -> u8
+{
+	use crate::core::merge::MergeError as ME;
+	match merge(args) {
+		Ok(()) => 0,
+		Err(ME::Conflicts) => {
+			eprintln!("Conflicts found in merge.");
+			1
+		},
+		Err(ME::IO(e)) => {
+			eprintln!("{e}");
+			2
+		},
+	}
+}
+
+
+fn merge(args: &FrCmdMergeFile) -> Result<(), crate::core::merge::MergeError>
+{
+	use crate::core::merge::merge_files;
+
+	// Read everything in first, so it's safe to write the output over
+	// one of the inputs (generally, --mine).
+	let read = |f: &Path| std::fs::read(f).map_err(|e| {
+		std::io::Error::new(e.kind(), format!("{}: {e}", f.display()))
+	});
+	let old  = read(&args.old)?;
+	let mine = read(&args.mine)?;
+	let new  = read(&args.new)?;
+
+	// Like merge(1), the files are taken as they are; no normalizing.
+	match &args.output {
+		Some(o) => {
+			let mut of = std::fs::File::create(o).map_err(|e| {
+				std::io::Error::new(e.kind(), format!("{}: {e}", o.display()))
+			})?;
+			merge_files(&old, &mine, &new, false, &mut of)?;
+		},
+		None => {
+			let mut out = std::io::stdout().lock();
+			merge_files(&old, &mine, &new, false, &mut out)?;
+		},
+	}
+	Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::Path;
+	use crate::command::FrCmdMergeFile;
+
+	/// Write out the 3 inputs and merge them, returning the exit status
+	/// and output.
+	fn do_merge(dir: &Path, old: &str, mine: &str, new: &str) -> (u8, String)
+	{
+		let f = |n: &str, c: &str| {
+			let p = dir.join(n);
+			std::fs::write(&p, c).unwrap();
+			p
+		};
+		let args = FrCmdMergeFile {
+			old: f("old", old), mine: f("mine", mine), new: f("new", new),
+			output: Some(dir.join("out")),
+		};
+		let ret = super::merge_exit(&args);
+		let out = std::fs::read_to_string(dir.join("out")).unwrap();
+		(ret, out)
+	}
+
+	const OLD: &str = "# header\nfoo=1\nbar=2\nbaz=3\n";
+
+	#[test]
+	fn clean()
+	{
+		let td = tempfile::tempdir().unwrap();
+
+		// Local change at the top, upstream at the bottom
+		let mine = "# header\nfoo=10\nbar=2\nbaz=3\n";
+		let new  = "# header\nfoo=1\nbar=2\nbaz=3\nquux=4\n";
+		let (ret, out) = do_merge(td.path(), OLD, mine, new);
+		assert_eq!(ret, 0);
+		assert_eq!(out, "# header\nfoo=10\nbar=2\nbaz=3\nquux=4\n");
+	}
+
+	#[test]
+	fn conflict()
+	{
+		let td = tempfile::tempdir().unwrap();
+
+		let mine = "# header\nfoo=1\nbar=mine\nbaz=3\n";
+		let new  = "# header\nfoo=1\nbar=new\nbaz=3\n";
+		let (ret, out) = do_merge(td.path(), OLD, mine, new);
+		assert_eq!(ret, 1);
+		assert!(out.contains("<<<<<<< ours\nbar=mine\n"), "{out}");
+		assert!(out.contains("=======\nbar=new\n>>>>>>> theirs\n"), "{out}");
+	}
+
+	#[test]
+	fn no_base()
+	{
+		let td = tempfile::tempdir().unwrap();
+
+		// Nothing in common to work from, so any difference is a
+		// conflict (this is what upgrade does with files that are new
+		// upstream but already exist locally).
+		let (ret, out) = do_merge(td.path(), "", "mine\n", "new\n");
+		assert_eq!(ret, 1);
+		assert!(out.contains("mine\n") && out.contains("new\n"), "{out}");
+
+		// But if they agree, sure.
+		let (ret, out) = do_merge(td.path(), "", "same\n", "same\n");
+		assert_eq!(ret, 0);
+		assert_eq!(out, "same\n");
+	}
+
+	#[test]
+	fn errors()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let args = FrCmdMergeFile {
+			old: td.path().join("nonexistent"),
+			mine: td.path().join("nonexistent"),
+			new: td.path().join("nonexistent"),
+			output: Some(td.path().join("out")),
+		};
+		assert_eq!(super::merge_exit(&args), 2);
+		assert!(!td.path().join("out").exists(), "no output on error");
+	}
+}
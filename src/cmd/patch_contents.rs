@@ -0,0 +1,114 @@
+//! $0 patch-contents
+use crate::command::CmdArg;
+use crate::core::RtDirs;
+use crate::info::AVersion;
+use crate::metadata::MetadataGroup;
+use crate::server::Server;
+
+
+pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
+{
+	use anyhow::bail;
+
+	// Setting up various dirs
+	let rtdirs = RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
+
+	let CmdArg { clargs, config, version } = carg;
+
+	// Extract args
+	let args = match clargs.command {
+		crate::command::FrCmds::PatchContents(a) => a,
+		_ => unreachable!("I'm a patch-contents, why does it think I'm not??"),
+	};
+
+	// Which release.  If it came with a patch level, that's the one
+	// we're asking about, unless told otherwise.
+	let given = args.release.unwrap_or_else(|| version.kernel.clone());
+	let release = AVersion { patch: None, ..given.clone() };
+
+	let mut server = Server::find_cached_rt(&config, &release, &rtdirs,
+			false)?;
+	server.set_filesdir(rtdirs.files().to_path_buf());
+	let latest = server.keytag_patchnum().unwrap_or(0);
+
+	// And which patch levels
+	let to = args.to.or(args.patch).or(given.patch).unwrap_or(latest);
+	let from = match args.from {
+		Some(f) => f,
+		None if to == 0 => bail!("{release} has nothing before it to \
+				compare with."),
+		None => to - 1,
+	};
+	if from >= to
+	{ bail!("--from ({from}) needs to be before --to ({to})."); }
+	if to > latest
+	{ bail!("The server only has {release} up to -p{latest}."); }
+
+	let at = |p: u32| AVersion { patch: (p > 0).then_some(p),
+			..release.clone() };
+	let (fromv, tov) = (at(from), at(to));
+	println!("Comparing {fromv} to {tov}.");
+
+	let old = load(&mut server, &rtdirs, &config.keyprint, &fromv)?;
+	let new = load(&mut server, &rtdirs, &config.keyprint, &tov)?;
+
+
+	/*
+	 * And what's the difference?
+	 */
+	use crate::core::patchdiff;
+	let d = patchdiff::diff(&old, &new);
+	println!("");
+	if d.is_empty()
+	{
+		println!("No differences between {fromv} and {tov}.");
+		return Ok(());
+	}
+
+	let sections = [
+		("Added",                  &d.added),
+		("Removed",                &d.removed),
+		("Contents changed",       &d.content),
+		("Owner/mode/flags changed", &d.metadata),
+	];
+	for (name, l) in sections
+	{
+		if l.is_empty() { continue; }
+		println!("{name} ({}):", l.len());
+		for c in l.iter()
+		{
+			println!("  {}  [{}]", c.path.display(),
+					c.components.join(", "));
+		}
+		println!("");
+	}
+	println!("{tov}: {} added, {} removed, {} with changed contents, {} \
+			with changed owner/mode/flags.", d.added.len(), d.removed.len(),
+			d.content.len(), d.metadata.len());
+
+	Ok(())
+}
+
+
+/// Get the full upstream metadata (INDEX-ALL, what IDS compares
+/// against) for one patch level of a release.
+fn load(server: &mut Server, rtdirs: &RtDirs, keyprint: &str,
+		vers: &AVersion)
+		-> Result<MetadataGroup, anyhow::Error>
+{
+	use anyhow::anyhow;
+	use crate::core::mdfetch;
+
+	let idx = mdfetch::idx_at(server, rtdirs, keyprint, vers)?;
+	let mut src = mdfetch::WithIdx { src: server, idx };
+	let mut pr = mdfetch::printer(&format!("{vers} metadata index"),
+			&format!("{vers} metadata files"));
+	let idx = mdfetch::fetch_files(&mut src, rtdirs, &["all"], None,
+			&mut pr)?;
+	idx.parse_one(rtdirs.tmp(), "all").map_err(|e| {
+		let es: Vec<_> = e.iter().map(|e| e.to_string()).collect();
+		anyhow!("Parsing {vers} metadata: {}", es.join("; "))
+	})
+}
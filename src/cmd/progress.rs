@@ -0,0 +1,45 @@
+//! $0 progress
+use crate::command::CmdArg;
+
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	// Setup dirs
+	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?;
+
+	// Extract our own args
+	let args = match carg.clargs.command {
+		crate::command::FrCmds::Progress(a) => a,
+		_ => unreachable!("I'm a progress, why does it think I'm not??"),
+	};
+
+	let st = match crate::util::status::load(&rtdirs.status_file())? {
+		Some(s) => s,
+		None => {
+			if !args.json { println!("Nothing running."); }
+			return Ok(1);
+		},
+	};
+	let alive = st.alive();
+
+	if args.json
+	{
+		let mut sj = serde_json::to_value(&st)?;
+		sj["stale"] = (!alive).into();
+		println!("{}", serde_json::to_string_pretty(&sj)?);
+	}
+	else
+	{
+		st.describe().iter().for_each(|l| println!("{l}"));
+		if !alive
+		{
+			println!("\nSTALE: pid {} isn't running; it must have died \
+					without cleaning up.", st.pid);
+		}
+	}
+
+	Ok(match alive {
+		true  => 0,
+		false => 1,
+	})
+}
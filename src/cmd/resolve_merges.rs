@@ -105,7 +105,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	let mut fixed = 0;
 	let mut inline = String::new();
 	let cfmarker = regex_lite::Regex
-			::new(r"(?m)^(?:(?:<{7}|>{7}|\|{7}) .*|========)$")?;
+			::new(r"(?m)^(?:(?:<{7}|>{7}|\|{7}) .*|={7})$")?;
 
 	use itertools::Itertools as _; // .sorted()
 	let cfkeys: Vec<_> = conflicts.keys().sorted().cloned().collect();
@@ -243,7 +243,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let Conflict { old, new, cur, res: _ } = cd;
 
 		let res = cleanhash.to_buf();
-		let clean = Clean { old, new, cur, res };
+		let clean = Clean { old, new, cur, res, resolved: true,
+				normalized: false };
 		mup.merge_clean.insert(f.to_path_buf(), clean);
 
 		// And this one is fixed!
@@ -0,0 +1,303 @@
+//! $0 selftest
+//!
+//! Exercise the local machinery that's bitten people before (compression,
+//! hashing, lstat/lchflags, patching, merging, the statefile, metadata
+//! parsing), without going near the network, and say how it all went in
+//! a form that can just be pasted into a bug report.
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::command::CmdArg;
+use crate::util::{fstype, securelevel};
+
+
+/// How one check came out, short of failing.
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome
+{
+	Pass,
+
+	/// Couldn't meaningfully run it here, and why
+	Skip(String),
+}
+
+
+/// One check: what it's called, and how to run it in a scratch dir.
+type Check = (&'static str, fn(&Path) -> Result<Outcome, anyhow::Error>);
+
+const CHECKS: &[Check] = &[
+	("gzip round-trip", gzip),
+	("sha256 vectors", sha256),
+	("lstat types", lstat),
+	("lchflags set/clear", lchflags),
+	("bspatch", bspatch),
+	("diff3 merge", merge),
+	("statefile round-trip", statefile),
+	("metadata parse", metadata),
+];
+
+
+/// Command: $0 selftest
+pub(crate) fn run(carg: CmdArg) -> Result<u8, anyhow::Error>
+{
+	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?;
+	let CmdArg { config, version, .. } = carg;
+
+	// Where we are
+	println!("freebsd-rustdate {}", env!("CARGO_PKG_VERSION"));
+	println!("System version:  {version}");
+	println!("euid:            {}", crate::util::euid());
+	println!("securelevel:     {}", securelevel()
+			.map_or_else(|e| format!("unknown ({e})"), |l| l.to_string()));
+	for (what, dir) in [("workdir", config.workdir()),
+			("basedir", config.basedir())]
+	{
+		println!("{:<17}{} ({})", format!("{what}:"), dir.display(),
+				fstype(dir).unwrap_or_else(|e| format!("unknown: {e}")));
+	}
+	println!("SHA256 accel:    {}", match crate::util::hash::accel() {
+		true => "yes", false => "no",
+	});
+	println!();
+
+	// And then how things go
+	let scratch = tempfile::Builder::new().prefix("selftest.")
+			.tempdir_in(rtdirs.tmp())?;
+	let mut nfail = 0;
+	for (name, check) in CHECKS
+	{
+		let dir = scratch.path().join(name.replace(['/', ' '], "-"));
+		std::fs::create_dir(&dir)?;
+		let start = Instant::now();
+		let res = check(&dir);
+		let took = ms(start.elapsed());
+		match res {
+			Ok(Outcome::Pass) => println!("  PASS  {name:<22} {took}"),
+			Ok(Outcome::Skip(why)) => println!("  SKIP  {name:<22} {why}"),
+			Err(e) => {
+				nfail += 1;
+				println!("  FAIL  {name:<22} {took}\n        {e:#}");
+			},
+		}
+	}
+
+	println!();
+	match nfail {
+		0 => { println!("All checks passed."); Ok(0) },
+		n => { println!("{n} check{} failed.", crate::util::plural(n)); Ok(1) },
+	}
+}
+
+
+fn ms(d: Duration) -> String
+{
+	format!("({:.1}ms)", d.as_secs_f64() * 1000.0)
+}
+
+
+/*
+ * The checks themselves.  Each gets its own empty dir to work in.
+ */
+
+macro_rules! ensure_eq {
+	($got:expr, $want:expr, $what:literal) => {
+		let (got, want) = ($got, $want);
+		if got != want
+		{
+			anyhow::bail!(concat!($what, ": expected {:?}, got {:?}"),
+					want, got);
+		}
+	};
+}
+
+
+fn gzip(dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	use crate::util::compress;
+
+	let data: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes())
+			.collect();
+	let (src, gz) = (dir.join("data"), dir.join("data.gz"));
+	std::fs::write(&src, &data)?;
+	compress::compress_gz(&src, &gz)?;
+	ensure_eq!(compress::gz_isize(&gz)?, data.len() as u64, "gzip isize");
+	let back = compress::decompress_to_vec(&gz)?;
+	ensure_eq!(back.len(), data.len(), "decompressed length");
+	if back != data { anyhow::bail!("decompressed data differs"); }
+	Ok(Outcome::Pass)
+}
+
+
+fn sha256(dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	use crate::util::hash;
+
+	// FIPS 180-2 vectors, plus empty
+	let vectors: &[(&[u8], &str)] = &[
+		(b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+		(b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+		(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+			"248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+	];
+	let f = dir.join("vec");
+	for (data, want) in vectors
+	{
+		hash::check_sha256(data, want, "vector")?;
+		std::fs::write(&f, data)?;
+		hash::check_sha256_file(&f, want)?;
+	}
+	Ok(Outcome::Pass)
+}
+
+
+fn lstat(dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	use std::os::unix::ffi::OsStrExt as _;
+	use libc::{S_IFMT, S_IFREG, S_IFLNK, S_IFDIR, S_IFIFO};
+
+	std::fs::write(dir.join("file"), b"x")?;
+	std::os::unix::fs::symlink("file", dir.join("link"))?;
+	std::fs::create_dir(dir.join("dir"))?;
+	let fifo = std::ffi::CString::new(dir.join("fifo").as_os_str()
+			.as_bytes())?;
+	// SAFETY: valid C string
+	if unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) } != 0
+	{ Err(std::io::Error::last_os_error())?; }
+
+	for (name, want) in [("file", S_IFREG), ("link", S_IFLNK),
+			("dir", S_IFDIR), ("fifo", S_IFIFO)]
+	{
+		let (st, _) = crate::util::lstat(&dir.join(name))?;
+		if st.mode & S_IFMT != want
+		{
+			anyhow::bail!("{name}: expected type {want:o}, got mode {:o}",
+					st.mode);
+		}
+	}
+	match crate::util::lstat(&dir.join("nope")) {
+		Err(crate::util::LstatErr::Nonexistent(_)) => (),
+		Err(e) => anyhow::bail!("nonexistent: expected Nonexistent, got {e}"),
+		Ok(_) => anyhow::bail!("nonexistent: found something"),
+	}
+	Ok(Outcome::Pass)
+}
+
+
+fn lchflags(dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	if crate::util::euid() != 0
+	{ return Ok(Outcome::Skip("not root".to_string())); }
+
+	// We can set schg above securelevel 0, but not clear it, and then
+	// we'd have a scratch file nobody can remove.
+	match securelevel() {
+		Ok(l) if l > 0 => return Ok(Outcome::Skip(format!("securelevel \
+				{l} would keep us from clearing schg"))),
+		Err(e) => return Ok(Outcome::Skip(format!("can't tell \
+				securelevel: {e}"))),
+		_ => (),
+	}
+
+	let f = dir.join("flagged");
+	std::fs::write(&f, b"x")?;
+	let schg = libc::SF_IMMUTABLE;
+	crate::util::lchflags(&f, schg)?;
+	let (st, _) = crate::util::lstat(&f)?;
+	let set = st.flags as u64 & schg != 0;
+	crate::util::lchflags(&f, 0)?;
+	let (st, _) = crate::util::lstat(&f)?;
+	ensure_eq!(set, true, "schg set");
+	ensure_eq!(st.flags as u64 & schg, 0, "schg cleared");
+	Ok(Outcome::Pass)
+}
+
+
+fn bspatch(dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	let old: Vec<u8> = b"The quick brown fox jumps over the lazy dog.\n"
+			.repeat(50);
+	let mut new = old.clone();
+	new[100..103].copy_from_slice(b"cat");
+	new.extend_from_slice(b"And then some.\n");
+
+	let mut patch = Vec::new();
+	qbsdiff::Bsdiff::new(&old, &new).compare(std::io::Cursor::new(&mut patch))?;
+	let (src, dst, pf) = (dir.join("old"), dir.join("new"), dir.join("patch"));
+	std::fs::write(&src, &old)?;
+	std::fs::write(&pf, &patch)?;
+	crate::util::bspatch::patch(&src, &dst, &pf)?;
+	if std::fs::read(&dst)? != new { anyhow::bail!("patched output differs"); }
+	Ok(Outcome::Pass)
+}
+
+
+fn merge(_dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	use crate::core::merge::{merge_files, Merged};
+
+	let old = b"# config\nfoo=1\nbar=2\nbaz=3\n";
+	let cur = b"# config\nfoo=local\nbar=2\nbaz=3\n";
+	let new = b"# config\nfoo=1\nbar=2\nbaz=upstream\n";
+	let want = b"# config\nfoo=local\nbar=2\nbaz=upstream\n";
+
+	let mut out = Vec::new();
+	let how = merge_files(old, cur, new, false, &mut out)
+			.map_err(|e| anyhow::anyhow!("{e}"))?;
+	ensure_eq!(how, Merged::Clean, "merge result");
+	if out != want { anyhow::bail!("merged output differs"); }
+
+	// And both sides touching the same line has to conflict
+	let new = b"# config\nfoo=upstream\nbar=2\nbaz=3\n";
+	if merge_files(old, cur, new, false, &mut Vec::new()).is_ok()
+	{ anyhow::bail!("conflicting merge didn't conflict"); }
+	Ok(Outcome::Pass)
+}
+
+
+fn statefile(dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	use crate::state::{self, State};
+
+	let kept = vec![std::path::PathBuf::from("/lib/libselftest.so.1")];
+	let mut st = State::default();
+	st.kept_libs = kept.clone();
+	state::save_to_dir(dir, &st)?;
+	let back = state::load_from_dir(dir)?;
+	ensure_eq!(back.kept_libs, kept, "kept_libs");
+	Ok(Outcome::Pass)
+}
+
+
+fn metadata(_dir: &Path) -> Result<Outcome, anyhow::Error>
+{
+	let sample = "\
+world|base|/bin|d|0|0|0755|0||
+world|base|/bin/[|f|0|0|0555|0|3ad985a50b79037b9672cf197fbc67bd54766199e190055101ea7d8c64ca843b|
+world|base|/nonexistent|-|||||
+";
+	let mdg = crate::metadata::parse_reader(&mut sample.as_bytes())
+			.map_err(|es| anyhow::anyhow!("{es:?}"))?;
+	ensure_eq!(mdg.len(), 3, "entries");
+	Ok(Outcome::Pass)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn checks()
+	{
+		// Everything but lchflags can run anywhere
+		for (name, check) in super::CHECKS
+		{
+			let td = tempfile::tempdir().unwrap();
+			let res = check(td.path())
+					.unwrap_or_else(|e| panic!("{name}: {e:#}"));
+			if *name != "lchflags set/clear"
+			{ assert_eq!(res, super::Outcome::Pass, "{name}"); }
+		}
+	}
+}
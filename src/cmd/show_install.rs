@@ -1,5 +1,9 @@
 //! #0 show-install
+use std::path::Path;
+
 use crate::command::CmdArg;
+use crate::metadata::Metadata;
+use crate::util::hash::Sha256Hash;
 
 pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 {
@@ -8,7 +12,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			&carg.config.workdir())?;
 
 	// Split upt
-	let CmdArg { clargs, config: _, version } = carg;
+	let CmdArg { clargs, config, version } = carg;
 
 	// Extract our own args
 	let args = match clargs.command {
@@ -16,8 +20,15 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		_ => unreachable!("I'm a show-install, why does it think I'm not??"),
 	};
 
-	// Load up the state and see what's in the manifest
-	let state = match rtdirs.state_load_raw()? {
+	// Load up the state and see what's in the manifest.  For the plain
+	// summary, the brief is all we need, and that saves loading the
+	// whole manifest, which can be huge.
+	let full = args.verbose.len() > 0;
+	let state = match full {
+		true  => rtdirs.state_load_raw()?,
+		false => rtdirs.state_load_brief_raw()?,
+	};
+	let mut state = match state {
 		Some(s) => s,
 		None => {
 			println!("No state to load; no fetch/upgrade has been run?");
@@ -25,10 +36,37 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			return Ok(());
 		},
 	};
-	let manifest = match state.manifest {
-		Some(m) => m,
-		None => {
+	let mani = state.manifest.take();
+	let brief = match (&mani, state.manifest_brief) {
+		(Some(m), _) => m.brief(),
+		(None, Some(b)) => b,
+		(None, None) => {
 			println!("No install pending.");
+			let kl = state.kept_libs.len();
+			if kl > 0
+			{
+				use crate::util::plural;
+				println!("\n{kl} old shared lib{} left behind by \
+						`install --skip-lib-cleanup`:", plural(kl));
+				for l in &state.kept_libs { println!("  {}", l.display()); }
+			}
+
+			// But right after an install is when people want to know
+			// what it just did.
+			if let Some(he) = state.history.last()
+			{
+				let det = crate::core::history::load(&rtdirs.history_dir(),
+						he);
+				println!("");
+				for l in last_desc(he, det.as_ref(), &args.verbose)
+				{ println!("{l}"); }
+				if args.detail
+				{
+					println!("\n(--detail only works for a pending \
+							install.)");
+				}
+			}
+			show_recovery(&state.recovery);
 			return Ok(());
 		},
 	};
@@ -50,9 +88,14 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		false
 	};
 
+	// Details come from the manifest, which we only have when we're
+	// being verbose.
+	let manifest = || mani.as_ref()
+			.expect("Verbose show-install should have loaded the manifest");
+
 	// Say what we want to do
-	let upvers = manifest.version();
-	let mt = manifest.mtype();
+	let upvers = &brief.version;
+	let mt = &brief.mtype;
 	let cmdname = crate::util::cmdname();
 	if isverb("any")
 	{
@@ -63,18 +106,35 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		println!("Summary of pending {mt} from {version} to {upvers}");
 		println!("    (use `{cmdname} show-install -v` to show full details)");
 	}
+	if let Some(note) = &brief.note
+	{
+		println!("NOTE: this was {note}; paths outside them won't be \
+				updated.");
+	}
+	{
+		use crate::core::filter::skipped_updates_desc;
+		for l in skipped_updates_desc(&brief.skipped_updates)
+		{ println!("{l}"); }
+	}
 
 
 	// Added/removed/updated files
-	let sum = manifest.change_summary();
+	let marked = |p: &std::path::Path| {
+		let an = brief.annotated.as_ref()?;
+		an.paths.iter().any(|ap| ap == p).then_some(an.attr.as_str())
+	};
+	let sum = match full {
+		true  => Some(manifest().change_summary()),
+		false => None,
+	};
+	let nomd = Metadata::default();
 	let steps = [
-		("add",    sum.added),
-		("remove", sum.removed),
-		("update", sum.updated),
+		("add",    brief.added),
+		("remove", brief.removed),
+		("update", brief.updated),
 	];
-	for (act, files) in steps
+	for (act, num) in steps
 	{
-		let num = files.len();
 		match num
 		{
 			0 => {
@@ -88,7 +148,34 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 				if isverb(act)
 				{
 					println!("\n {num} files to {act}:");
-					for f in files { let f = f.display(); println!("  {f}"); }
+					let sum = sum.as_ref().expect("Verbose has a summary");
+					let files = match act {
+						"add"    => &sum.added,
+						"remove" => &sum.removed,
+						_        => &sum.updated,
+					};
+					let detailed = args.detail && act != "remove";
+					let (cur, new) = match detailed {
+						true  => manifest().cur_new(),
+						false => (&nomd, &nomd),
+					};
+					let size = |h: &Sha256Hash| {
+						let hf = rtdirs.hashfile(&h.to_buf());
+						crate::util::compress::gz_isize(&hf).ok()
+					};
+					for f in files
+					{
+						let mark = match marked(f.as_path()) {
+							Some(a) => format!("  [{a}]"),
+							None => String::new(),
+						};
+						let det = match detailed {
+							true  => detail(f, cur, new, act == "update",
+									args.full_hashes, size),
+							false => String::new(),
+						};
+						println!("  {}{det}{mark}", f.display());
+					}
 				}
 				else
 				{
@@ -99,9 +186,25 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 
 
+	// Things something else manages
+	if let Some(an) = &brief.annotated
+	{
+		let n = an.paths.len();
+		println!(" {n} file{} to replace or remove marked {}.",
+				crate::util::plural(n), an.attr);
+	}
+
+
+	// What sort of things those are
+	{
+		let risk = brief.risk.describe();
+		if !risk.is_empty() { println!(""); }
+		risk.iter().for_each(|l| println!(" {l}"));
+	}
+
+
 	// Changed types
-	let tchanges = manifest.type_changes();
-	let nch = tchanges.len();
+	let nch = brief.type_changes;
 	if nch > 0
 	{
 		if isverb("change") { println!(""); }
@@ -109,6 +212,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		if isverb("change")
 		{
 			use itertools::Itertools as _; // .sorted()
+			let tchanges = manifest().type_changes();
 			for p in tchanges.keys().sorted()
 			{
 				let ch = tchanges.get(p).unwrap();
@@ -119,14 +223,36 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 
 
+	// Local metadata kept over upstream's
+	let nkm = brief.kept_metadata;
+	if nkm > 0
+	{
+		match isverb("metadata") {
+			true  => {
+				use crate::core::filter::kept_metadata_desc;
+				println!("");
+				let kept = manifest().kept_metadata();
+				for l in kept_metadata_desc(kept, true) { println!(" {l}"); }
+			},
+			false => println!(" {nkm} path{} keeping local owner/mode/flags \
+					over upstream's.", crate::util::plural(nkm)),
+		}
+	}
+
+
 	// Merged files
 	use crate::state::Manifest;
-	if let Manifest::Upgrade(mup) = &manifest
+	let mup = || match manifest() {
+		Manifest::Upgrade(u) => u,
+		Manifest::Fetch(_) => unreachable!("Brief says it's an upgrade"),
+	};
+	if brief.mtype == "upgrade"
 	{
 		use crate::util::plural;
 
 		if isverb("merge")
 		{
+			let mup = mup();
 			println!("");
 			let clean = &mup.merge_clean;
 			if clean.len() > 0
@@ -156,7 +282,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		}
 		else
 		{
-			let num = mup.num_clean();
+			let num = brief.merge_clean;
 			if num > 0
 			{
 				println!(" {num} clean merge{}; see `{cmdname} show-merges` \
@@ -167,7 +293,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 				println!(" No merged files.");
 			}
 
-			let num = mup.num_conflict();
+			let num = brief.merge_conflict;
 			if num > 0
 			{
 				println!(" {num} outstanding conflicted merge{}; run \
@@ -178,12 +304,320 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 				println!(" No conflicts to resolve.");
 			}
 		}
+
+
+		// Old shared libs, held until the very end
+		let num = brief.old_libs;
+		if num > 0
+		{
+			if isverb("libs")
+			{
+				println!("\n {num} old shared lib{} to remove after world:",
+						plural(num));
+				for l in &mup().old_libs { println!("  {}", l.display()); }
+			}
+			else
+			{
+				println!(" {num} old shared lib{} to remove after world.",
+						plural(num));
+			}
+		}
+
+
+		// How long the install steps might take
+		if let Some(sz) = &brief.sizes
+		{
+			println!("\n Install estimate (not counting the reboot between):");
+			let mbps = config.install_mb_per_sec;
+			sz.describe(mbps).iter().for_each(|l| println!("  {l}"));
+		}
+	}
+
+	// How much it all adds up to
+	if let Some(ib) = brief.install_bytes
+	{
+		use crate::util::human_bytes;
+		println!("\n Install will write {} into the basedir.",
+				human_bytes(ib));
+	}
+	if !state.bytes.is_empty()
+	{
+		println!(" All fetches and upgrades so far: {}.",
+				state.bytes.describe());
 	}
 
 
 	// Now say something about the overall state.
-	let ststr = manifest.state();
+	let ststr = &brief.state;
 	println!("\n{ststr}.");
 
+	show_recovery(&state.recovery);
+
 	Ok(())
 }
+
+
+/// What --detail says about a path: what new has it as, and for updates,
+/// what hash cur had.
+fn detail(p: &Path, cur: &Metadata, new: &Metadata, upd: bool, full: bool,
+		size: impl Fn(&Sha256Hash) -> Option<u64>) -> String
+{
+	let hash = |h: &Sha256Hash| {
+		let hb = h.to_buf();
+		let hs: &str = hb.as_ref();
+		match full {
+			true  => hs.to_string(),
+			false => hs[..16].to_string(),
+		}
+	};
+
+	if let Some(f) = new.files.get(p)
+	{
+		let sz = match size(&f.sha256) {
+			Some(s) => s.to_string(),
+			None    => "?".to_string(),
+		};
+		let mut ret = format!("  file sha256={} size={sz}", hash(&f.sha256));
+		if let Some(cf) = cur.files.get(p).filter(|_| upd)
+		{ ret.push_str(&format!(" was={}", hash(&cf.sha256))); }
+		return ret;
+	}
+	if let Some(d) = new.dirs.get(p)
+	{ return format!("  dir mode={:04o}", d.mode); }
+	if let Some(l) = new.symlinks.get(p)
+	{ return format!("  symlink target={}", l.target.display()); }
+	if let Some(l) = new.hardlinks.get(p)
+	{ return format!("  hardlink target={}", l.target.display()); }
+	String::new()
+}
+
+
+/// What the last finished install did, for when there's nothing pending.
+/// It's all from the history; counts if it has them, and with -v, the
+/// lists if they were kept (x-ref Config::install_history_detail).
+fn last_desc(he: &crate::state::HistoryEntry,
+		det: Option<&crate::core::history::Detail>,
+		verbose: &[crate::command::ShowInstallType]) -> Vec<String>
+{
+	use crate::command::ShowInstallType as SIT;
+	use crate::util::plural;
+
+	let when = match chrono::DateTime::from_timestamp(he.when, 0) {
+		Some(w) => chrono::DateTime::<chrono::Local>::from(w)
+				.format("%Y-%m-%d %H:%M").to_string(),
+		None => "?".to_string(),
+	};
+	let mut ret = vec![format!("Last finished install (not pending; this \
+			already happened), {} from {} to {}, at {when}:", he.mtype,
+			he.from, he.to)];
+	if let Some(fl) = &he.flags_lost
+	{ ret.push(format!(" Flags not kept: {}.", fl.describe())); }
+
+	let counts = match &he.counts {
+		Some(c) => c,
+		None => {
+			ret.push(" (No counts were kept for this install.)".to_string());
+			return ret;
+		},
+	};
+	let want = |t: &SIT| verbose.contains(&SIT::All) || verbose.contains(t);
+	let listed = |t: &SIT| want(t) && det.is_some();
+	let paths = |ret: &mut Vec<String>, ps: &[std::path::PathBuf]| {
+		ret.extend(ps.iter().map(|p| format!("  {}", p.display())));
+	};
+
+	let steps = [
+		(SIT::Add,    "added",   counts.added,   det.map(|d| &d.added)),
+		(SIT::Rm,     "removed", counts.removed, det.map(|d| &d.removed)),
+		(SIT::Update, "updated", counts.updated, det.map(|d| &d.updated)),
+	];
+	for (sit, act, num, list) in steps
+	{
+		match (num, list) {
+			(0, _) => ret.push(format!(" No files {act}.")),
+			(n, Some(l)) if want(&sit) => {
+				ret.push(format!(" {n} file{} {act}:", plural(n)));
+				paths(&mut ret, l);
+			},
+			(n, _) => ret.push(format!(" {n} file{} {act}.", plural(n))),
+		}
+	}
+
+	let n = counts.type_changes;
+	if n > 0
+	{
+		match (listed(&SIT::Change), det) {
+			(true, Some(d)) => {
+				ret.push(format!(" {n} path{} changed type:", plural(n)));
+				ret.extend(d.changed.iter().map(|(p, old, new)| format!("  \
+						{} changed from {old} to {new}", p.display())));
+			},
+			_ => ret.push(format!(" {n} path{} changed type.", plural(n))),
+		}
+	}
+
+	let n = counts.kept_metadata;
+	if n > 0
+	{
+		match (listed(&SIT::Metadata), det) {
+			(true, Some(d)) => {
+				use crate::core::filter::kept_metadata_desc;
+				ret.extend(kept_metadata_desc(&d.kept_metadata, true)
+						.into_iter().map(|l| format!(" {l}")));
+			},
+			_ => ret.push(format!(" {n} path{} kept local owner/mode/flags \
+					over upstream's.", plural(n))),
+		}
+	}
+
+	let n = counts.merged;
+	if n > 0
+	{
+		match (listed(&SIT::Merge), det) {
+			(true, Some(d)) => {
+				ret.push(format!(" {n} merged file{}:", plural(n)));
+				paths(&mut ret, &d.merged);
+			},
+			_ => ret.push(format!(" {n} merged file{}.", plural(n))),
+		}
+	}
+
+	let n = counts.old_libs;
+	if n > 0
+	{
+		match (listed(&SIT::Libs), det) {
+			(true, Some(d)) => {
+				ret.push(format!(" {n} old shared lib{} removed:",
+						plural(n)));
+				paths(&mut ret, &d.old_libs);
+			},
+			_ => ret.push(format!(" {n} old shared lib{} removed.",
+					plural(n))),
+		}
+	}
+
+	if !verbose.is_empty() && det.is_none()
+	{
+		ret.push(" (The path lists for this install weren't kept; \
+				InstallHistoryDetail sets how many installs keep them.)"
+				.to_string());
+	}
+	ret
+}
+
+
+/// Show what earlier installs left around to roll back to.
+fn show_recovery(recovery: &[crate::state::RecoveryPoint])
+{
+	if recovery.is_empty() { return; }
+
+	println!("\nTo roll back (newest first):");
+	for rp in recovery.iter().rev() { println!("  {}", rp.describe()); }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::{Path, PathBuf};
+	use crate::metadata::{Metadata, MetaDir, MetaFile, MetaSymLink};
+	use crate::util::hash::Sha256Hash;
+
+	fn hash(s: &str) -> Sha256Hash
+	{
+		crate::util::hash::sha256_reader(&mut s.as_bytes()).unwrap()
+	}
+
+	fn file(p: &str, h: Sha256Hash) -> (PathBuf, MetaFile)
+	{
+		(p.into(), MetaFile { path: p.into(), sha256: h,
+				..Default::default() })
+	}
+
+	fn sz(_: &Sha256Hash) -> Option<u64> { Some(1234) }
+
+	#[test]
+	fn detail()
+	{
+		let (h1, h2) = (hash("old"), hash("new"));
+
+		let cur = Metadata { files: [file("/bin/sh", h1)].into(),
+				..Default::default() };
+		let mut new = Metadata { files: [file("/bin/sh", h2),
+				file("/bin/new", h2)].into(), ..Default::default() };
+		new.dirs.insert("/usr/share/x".into(), MetaDir {
+				path: "/usr/share/x".into(), mode: 0o755,
+				..Default::default() });
+		new.symlinks.insert("/lib/l".into(), MetaSymLink {
+				path: "/lib/l".into(), target: "/lib/t".into(),
+				..Default::default() });
+
+		let d = |p: &str, upd, full| super::detail(Path::new(p), &cur, &new,
+				upd, full, sz);
+		let (s1, s2) = (h1.to_buf().to_string(), h2.to_buf().to_string());
+
+		assert_eq!(d("/bin/sh", true, false), format!("  file sha256={} \
+				size=1234 was={}", &s2[..16], &s1[..16]));
+		assert_eq!(d("/bin/new", false, true), format!("  file sha256={s2} \
+				size=1234"));
+		assert_eq!(d("/usr/share/x", false, false), "  dir mode=0755");
+		assert_eq!(d("/lib/l", false, false), "  symlink target=/lib/t");
+		assert_eq!(d("/nowhere", false, false), "");
+
+		// No cached file to size up
+		let d = super::detail(Path::new("/bin/new"), &cur, &new, false, false,
+				|_| None);
+		assert!(d.ends_with("size=?"), "{d}");
+	}
+
+
+	#[test]
+	fn last_desc()
+	{
+		use crate::command::ShowInstallType as SIT;
+		use crate::core::history::Detail;
+		use crate::state::{HistoryEntry, InstallCounts};
+
+		let mut he = HistoryEntry { mtype: "fetch".to_string(),
+				from: "14.1-RELEASE-p1".to_string(),
+				to: "14.1-RELEASE-p2".to_string(), when: 1_700_000_000,
+				audit: None, deferred: Vec::new(), counts: None,
+				flags_lost: None };
+		let det = Detail { added: vec!["/bin/new".into()],
+				updated: vec!["/bin/sh".into(), "/lib/libc.so.7".into()],
+				..Default::default() };
+
+		// An old entry with nothing but the versions
+		let d = super::last_desc(&he, None, &[SIT::All]);
+		assert!(d[0].contains("not pending") && d[0].contains("fetch from \
+				14.1-RELEASE-p1 to 14.1-RELEASE-p2"), "{d:?}");
+		assert_eq!(d[1..], [" (No counts were kept for this install.)"]);
+
+		// Counts only; -v can't do any better, and says why
+		he.counts = Some(InstallCounts { added: 1, updated: 2,
+				..Default::default() });
+		let d = super::last_desc(&he, None, &[]);
+		assert_eq!(d[1..], [" 1 file added.", " No files removed.",
+				" 2 files updated."]);
+		let d = super::last_desc(&he, None, &[SIT::Update]);
+		assert_eq!(d[3], " 2 files updated.");
+		assert!(d[4].contains("weren't kept"), "{d:?}");
+
+		// With the lists, -v picks which get shown
+		let d = super::last_desc(&he, Some(&det), &[SIT::Update]);
+		assert_eq!(d[1..], [" 1 file added.", " No files removed.",
+				" 2 files updated:", "  /bin/sh", "  /lib/libc.so.7"]);
+		let d = super::last_desc(&he, Some(&det), &[SIT::All]);
+		assert_eq!(d[1..3], [" 1 file added:", "  /bin/new"]);
+
+		// Flags the basedir didn't keep get called out up top
+		he.flags_lost = Some(crate::core::fsprobe::FlagsLost {
+				fstype: "tmpfs".to_string(), flags: 0x00020000, paths: 12 });
+		let d = super::last_desc(&he, None, &[]);
+		assert_eq!(d[1], " Flags not kept: the basedir's filesystem (tmpfs) \
+				doesn't keep schg flags, so 12 paths that should have them \
+				won't.");
+		assert_eq!(d[2], " 1 file added.");
+	}
+}
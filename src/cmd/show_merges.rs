@@ -8,7 +8,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			&carg.config.workdir())?;
 
 	// Split up
-	let CmdArg { clargs, config: _, version } = carg;
+	let CmdArg { clargs, config, version } = carg;
 
 	// Extract our own args
 	let args = match clargs.command {
@@ -16,6 +16,12 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		_ => unreachable!("I'm a show-merges, why does it think I'm not??"),
 	};
 
+	// The /etc report is its own thing
+	if let Some(rf) = &args.etc_report
+	{
+		return etc_report(rf, &rtdirs, &config, &version);
+	}
+
 	// Load up the state and see what's in the manifest
 	let state = match rtdirs.state_load_raw()? {
 		Some(s) => s,
@@ -64,13 +70,24 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// Detailyize
 	use crate::util::plural;
 	use itertools::Itertools as _; // .sorted()
-	let clean = mup.merge_clean;
+	let mut clean = mup.merge_clean;
+	if args.path.len() > 0
+	{
+		clean.retain(|p, _| {
+			let pstr = p.to_string_lossy();
+			args.path.iter().any(|r| r.is_match(&pstr))
+		});
+	}
 	let num = clean.len();
 
 	println!("{num} merged file{}", plural(num));
 	if args.upstream { println!("  (diffs against new upstream versions)"); }
 	println!("");
 
+	let color = use_color();
+	let comments = &args.comment_prefix;
+	use crate::core::merge;
+
 	for f in clean.keys().sorted()
 	{
 		let cd = clean.get(f).unwrap();
@@ -80,9 +97,26 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		}?;
 		let new  = mf_data(&cd.res)?;
 
+		let trivial = match merge::trivial_changes(&prev, &new, comments) {
+			true  => "  (comment/whitespace changes only)",
+			false => "",
+		};
+		let norm = match cd.normalized {
+			true  => "  (merged after normalizing whitespace)",
+			false => "",
+		};
+
+		// Summary only?
+		if args.stat
+		{
+			let (add, rm) = merge::merge_diffstat(&prev, &new);
+			println!("{}: +{add} -{rm}{trivial}{norm}", f.display());
+			continue;
+		}
+
 		// We assume it's _probably_ valid UTF8, so easy to deal with.
 		// If it's not, we do our best, but warn about it.
-		let dbytes = crate::core::merge::merge_diff(f, &prev, &new);
+		let dbytes = merge::merge_diff(f, &prev, &new);
 		let dstr = String::from_utf8_lossy(&dbytes);
 
 		use std::borrow::Cow::{Owned, Borrowed};
@@ -91,10 +125,16 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			Borrowed(_) => "",
 		};
 
+		let dstr = match color {
+			true  => colorize(&dstr),
+			false => dstr.into_owned(),
+		};
 
-		println!("diff {}{lstr}\n{dstr}\n", f.display());
+		println!("diff {}{trivial}{norm}{lstr}\n{dstr}\n", f.display());
 	}
 
+	if args.stat { println!(""); }
+
 
 	let ncf = mup.merge_conflict.len();
 	if ncf > 0
@@ -108,3 +148,94 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	Ok(())
 }
+
+
+
+/// Write out the /etc report.  From the pending upgrade if there is one,
+/// otherwise whatever install saved at the end of the last one.
+fn etc_report(file: &std::path::Path, rtdirs: &crate::core::RtDirs,
+		config: &crate::config::Config, version: &crate::info::Version)
+		-> Result<(), anyhow::Error>
+{
+	use crate::state::Manifest;
+	use crate::core::merge::report;
+
+	let manifest = rtdirs.state_load_raw()?.and_then(|s| s.manifest);
+	let (mup, upvers) = match &manifest {
+		Some(m @ Manifest::Upgrade(u)) => (u, m.version().to_string()),
+		_ => {
+			let saved = rtdirs.etc_report_file();
+			if !saved.is_file()
+			{
+				anyhow::bail!("No pending upgrade, and no report saved \
+						from installing one.");
+			}
+			std::fs::copy(&saved, file)?;
+			println!("No pending upgrade; copied the report saved by the \
+					last upgrade install to {}.", file.display());
+			return Ok(());
+		},
+	};
+
+	let pats = [&config.merge_changes[..], &config.update_if_unmodified[..]];
+	let rep = report::build(mup, &pats, rtdirs, config.basedir(),
+			version.to_string(), upvers)?;
+	rep.write(file)?;
+
+	let nf = rep.files.len();
+	println!("Wrote report on {nf} file{} to {}.", crate::util::plural(nf),
+			file.display());
+	for (class, n) in rep.counts()
+	{
+		println!("  {n:5} {}", class.desc());
+	}
+
+	Ok(())
+}
+
+
+
+/// Should we colorize output?  Only to a terminal, and only if the user
+/// hasn't asked us not to (https://no-color.org/).
+fn use_color() -> bool
+{
+	use std::io::IsTerminal as _;
+
+	let nocolor = std::env::var_os("NO_COLOR")
+			.map(|v| !v.is_empty()).unwrap_or(false);
+	!nocolor && std::io::stdout().is_terminal()
+}
+
+
+/// Colorize a unified diff.  Simple ANSI escapes; headers bold, hunk
+/// markers cyan, removals red, additions green.
+fn colorize(diff: &str) -> String
+{
+	const BOLD:  &str = "\x1b[1m";
+	const CYAN:  &str = "\x1b[36m";
+	const RED:   &str = "\x1b[31m";
+	const GREEN: &str = "\x1b[32m";
+	const RESET: &str = "\x1b[0m";
+
+	let mut ret = String::with_capacity(diff.len() * 2);
+	for l in diff.split_inclusive('\n')
+	{
+		let (body, nl) = match l.strip_suffix('\n') {
+			Some(b) => (b, "\n"),
+			None    => (l, ""),
+		};
+		let col = if body.starts_with("+++") || body.starts_with("---")
+			{ BOLD }
+			else if body.starts_with("@@") { CYAN }
+			else if body.starts_with('-')  { RED }
+			else if body.starts_with('+')  { GREEN }
+			else { "" };
+		match col {
+			"" => ret.push_str(l),
+			_  => { ret.push_str(col); ret.push_str(body);
+					ret.push_str(RESET); ret.push_str(nl); },
+		}
+	}
+
+	ret
+}
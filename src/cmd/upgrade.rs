@@ -1,6 +1,5 @@
 //! $0 upgrade
-use std::collections::{HashSet, HashMap};
-use std::io::{stdout, Write as _};
+use std::collections::{BTreeMap, HashSet, HashMap};
 use std::path::PathBuf;
 
 use crate::command::CmdArg;
@@ -17,7 +16,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	// Setting up various dirs
 	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
-			&carg.config.workdir())?;
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
 
 	// See what sorta state we're in, and if it's one where we shouldn't
 	// be running fetch.
@@ -38,6 +38,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		bail!("upgrade in progress");
 	}
 
+	// A rotated key leaves whatever the old one vouched for unvouched
+	if let Some(n) = state.keyprint_check(&carg.config.keyprint)
+	{ println!("{n}"); }
+
 
 	// OK, bust it up so we can move the bits around individually.
 	let CmdArg { clargs, mut config, version } = carg;
@@ -48,13 +52,26 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		_ => unreachable!("I'm an upgrade, why does it think I'm not??"),
 	};
 
+	// Any one-off path filters just get added to the config
+	let note = config.add_cli_filters(&upargs.exclude, &upargs.include_only);
+	if upargs.no_keep_modified_metadata { config.keep_modified_metadata = false; }
+
+	// Don't go fighting with pkg over a pkgbase system
+	crate::core::pkgbase::check(config.basedir(), upargs.allow_pkgbase)?;
+
 	// Do the "finalize components" thing, which pulls src outta the list
-	// if we don't seem to have src installed.
-	config.finalize_components();
+	// if we don't seem to have src installed, or it's a git checkout.
+	if let Some(n) = config.finalize_components() { println!("{n}"); }
 
 	// Show our starting point
 	println!("Currently running {version}.");
 
+	// Keep track of where the time goes, and let anybody watching know
+	// what we're up to.
+	let mut tm = crate::util::timings::Timings::new();
+	let _status = crate::util::status::start(rtdirs.status_file(),
+			"upgrade");
+
 
 
 	/*
@@ -63,70 +80,33 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	 * First, we load up the old/all indices for the currently running
 	 * version.
 	 */
+	tm.phase("Fetching metadata");
 	println!("Loading info for {version}.");
-	let mut server = crate::server::Server::find(&config.servername,
-			&version.kernel, &config.keyprint)?;
+	let mut server = crate::server::Server::find_cached(&config,
+			&version.kernel, &mut state, false)?;
+	rtdirs.state_save(&state)?;
 	server.set_filesdir(rtdirs.files().to_path_buf());
+	server.set_partialdir(rtdirs.partial_dir());
 	let metadatas = &["all", "old"];
 
-	print!("Loading metadata index for {version}...");
-	stdout().flush()?;
-	let mdidx = server.get_metadata_idx()?;
-	println!("   OK.");
-
-	print!("Getting metadata files for {version}...  ");
-	stdout().flush()?;
-	let metamiss = {
-		let fd = rtdirs.files();
-		let mut missing = mdidx.not_in_dir(fd, metadatas);
+	use crate::core::mdfetch;
+	let mut acq = {
+		let idxl = format!("metadata index for {version}");
+		let filesl = format!("metadata files for {version}");
 		// Include in the old stuff from our saved state in case we need
 		// 'em.  Though do we??
-		if let Some(md) = state.meta_idx
-		{ missing.extend(md.not_in_dir(fd, metadatas)); }
-		missing
+		mdfetch::acquire(&mut server, &rtdirs, &config, metadatas,
+				&["old", "all"], state.meta_idx.as_ref(),
+				&mut mdfetch::printer(&idxl, &filesl))?
 	};
-	match metamiss.len()
-	{
-		0 => println!("All present."),
-		_ => {
-			println!("{} missing.", metamiss.len());
-
-			// So grab 'em.
-			println!("Fetching...");
-			let files = metamiss.into_iter().collect();
-			server.fetch_metafiles(files)?;
-			println!("Done.");
-		},
-	};
-
-	print!("Checking metadata file hashes...  ");
-	stdout().flush()?;
-	let hres = {
-		let fd = rtdirs.files();
-		let td = rtdirs.tmp();
-		mdidx.check_hashes(fd, td, metadatas)
-	};
-	match hres {
-		Ok(_) => println!("   OK."),
-		Err(e) => {
-			println!("   Errors found.\n{}", e.join("\n"));
-			bail!("Invalid metafiles, bailing.");
-		},
-	};
-
-	print!("Parsing metadata files...  ");
-	stdout().flush()?;
-	let mut cv_old = mdidx.parse_one_full("old", rtdirs.tmp(), &config)?;
-	print!(" old");
-	stdout().flush()?;
-	let mut cv_all = mdidx.parse_one_full("all", rtdirs.tmp(), &config)?;
-	print!(" all");
-	stdout().flush()?;
-	println!("   OK.");
+	let mut cv_old = acq.take("old");
+	let mut cv_all = acq.take("all");
+	let mut ignored = acq.ignored;
 
 
 	// Based on that "old" all file, scan our current system to find out
 	// the state of things.
+	tm.phase("Inspecting system");
 	print!("Inspecting system...  ");
 	let scanpaths = {
 		let paths = cv_all.allpaths();
@@ -143,17 +123,13 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 	println!("{} paths to scan", scanpaths.len());
 	use crate::core::scan;
-	let mut cur = scan::scan(config.basedir().to_path_buf(), scanpaths)?;
-	{
-		// Just for kicks, give details
-		let ndir  = cur.dirs.len();
-		let nfile = cur.files.len();
-		let nsl   = cur.symlinks.len();
-		let nhl   = cur.hardlinks.len();
-		let nmiss = cur.dashes.len();
-		println!("Found {ndir} dirs, {nfile} files, {nsl} symlinks, \
-				{nhl} hardlinks, and {nmiss} missing files.");
-	}
+	let annotate = config.annotate_xattr.as_deref()
+			.and_then(crate::util::xattr::AttrName::parse);
+	let scanned = scan::scan_annotated(config.basedir().to_path_buf(),
+			scanpaths, annotate.clone())?;
+	let mut cur = scanned.md;
+	let mut annotated = scanned.annotated;
+	println!("{}", scan::found_desc(&cur, &cv_all.parts()));
 
 
 	// For upgrade, from the current version's INDEX-ALL, we're
@@ -196,7 +172,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		config.components = keepcomps;
 	}
 
-	// Don't need the component layer anymore
+	// Hang onto what we've got by component, to see what goes away in
+	// the new version; otherwise, don't need the component layer
+	// anymore.
+	let installed = cv_all.clone();
 	let cv_all = cv_all.into_metadata();
 	let cv_old = cv_old.into_metadata();
 
@@ -217,7 +196,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			bail!("Bad programmer, no cookie!");
 		}
 	}
-	let old_server = server;
+	let mut old_server = server;
 
 
 
@@ -226,55 +205,45 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	/*
 	 * Now we do stuff based on the version we're trying to upgrade to.
 	 */
+	tm.phase("Fetching metadata");
 	println!("\nLoading info for {}.", upargs.release);
-	let mut server = crate::server::Server::find(&config.servername,
-			&upargs.release, &config.keyprint)?;
+	let mut server = crate::server::Server::find_cached(&config,
+			&upargs.release, &mut state, false)?;
+	server.share_stats(&old_server);
+	rtdirs.state_save(&state)?;
 	server.set_filesdir(rtdirs.files().to_path_buf());
+	server.set_partialdir(rtdirs.partial_dir());
 
 	// Only metadata we need from this one is the 'all'.
 	let metadatas = &["all"];
-	print!("Loading metadata index...");
-	stdout().flush()?;
-	let mdidx = server.get_metadata_idx()?;
-	println!("   OK.");
-
-	print!("Getting metadata files...  ");
-	stdout().flush()?;
-	let metamiss = mdidx.not_in_dir(rtdirs.files(), metadatas);
-	match metamiss.len()
+	let mut acq = mdfetch::acquire(&mut server, &rtdirs, &config,
+			metadatas, metadatas, None,
+			&mut mdfetch::printer("metadata index", "metadata files"))?;
+	let mut all = acq.take("all");
+	ignored.merge(acq.ignored);
+	let mdidx = acq.idx;
+
+	// Report on what IgnorePaths did, if asked.
+	if let Some(si) = &upargs.show_ignored
 	{
-		0 => println!("All present."),
-		_ => {
-			println!("{} missing.", metamiss.len());
-
-			// So grab 'em.
-			println!("Fetching...");
-			let files = metamiss.into_iter().collect();
-			server.fetch_metafiles(files)?;
-			println!("Done.");
-		},
-	};
+		let full = si.full_file(config.workdir());
+		ignored.show(full.as_deref())?;
+	}
 
-	print!("Checking metadata file hashes...  ");
-	stdout().flush()?;
-	let hres = {
-		let fd = rtdirs.files();
-		let td = rtdirs.tmp();
-		mdidx.check_hashes(fd, td, metadatas)
-	};
-	match hres {
-		Ok(_) => println!("   OK."),
-		Err(e) => {
-			println!("   Errors found.\n{}", e.join("\n"));
-			bail!("Invalid metafiles, bailing.");
-		},
-	};
-	print!("Parsing metadata files...  ");
-	stdout().flush()?;
-	let mut all = mdidx.parse_one_full("all", rtdirs.tmp(), &config)?;
-	print!(" all");
-	stdout().flush()?;
-	println!("   OK.");
+	// Components come and go between releases.  Anything of one we
+	// have that's not in the new one anywhere gets removed along with
+	// everything else missing from new, since the scan covered all of
+	// old; but say so, rather than leaving it buried in a big removal
+	// count.  With AllowDelete=no, they stay like anything else would
+	// (x-ref keep_deletes() below).  Whatever took over the rest of its
+	// paths, we need to be installing, or they'd go too.
+	for v in installed.vanished_components(&all)
+	{
+		println!("{}", v.describe(&upargs.release, config.allow_delete)
+				.join("\n"));
+		config.components.extend(v.moved.into_keys());
+	}
+	drop(installed);
 
 	// But prune down to the components we're worrying about, then dump
 	// the component level.
@@ -284,13 +253,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 	// f-u.sh will replace a non-GENERIC kernel with a GENERIC one, which
 	// seems not great.  But, we'll go with it...
-	//
-	// OTOH, if <basedir> isn't /, then the running kernel doesn't really
-	// tell us a darn thing about what should be in the system image
-	// we're working on, so just forget it.
-	let kconf = crate::info::kernel::conf().unwrap();
 	let isroot = config.basedir().to_str() == Some("/");
-	if isroot && kconf != "GENERIC"
+	let faked = clargs.fakeversion.is_some();
+	if let Some(kconf) = custom_kernel(isroot, faked,
+			crate::info::kernel::conf)?
 	{
 		println!("\n    WARNING  --  WARNING  --  WARNING");
 		println!("This system is running a {kconf} kernel, which is not\n\
@@ -324,20 +290,15 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 				.filter(|p| !curpaths.contains(p))
 				.map(|p| p.to_path_buf()).collect()
 	};
+	tm.phase("Inspecting system");
 	if scanpaths.len() > 0
 	{
 		println!("{} new paths to scan", scanpaths.len());
-		let ncur = scan::scan(config.basedir().to_path_buf(), scanpaths)?;
-		{
-			// Just for kicks, give details
-			let ndir  = ncur.dirs.len();
-			let nfile = ncur.files.len();
-			let nsl   = ncur.symlinks.len();
-			let nhl   = ncur.hardlinks.len();
-			let nmiss = ncur.dashes.len();
-			println!("Found {ndir} dirs, {nfile} files, {nsl} symlinks, \
-					{nhl} hardlinks, and {nmiss} missing files.");
-		}
+		let nscanned = scan::scan_annotated(config.basedir().to_path_buf(),
+				scanpaths, annotate)?;
+		annotated.extend(nscanned.annotated);
+		let ncur = nscanned.md;
+		println!("{}", scan::found_desc(&ncur, &[&new]));
 		cur.extend(ncur);
 	}
 
@@ -358,6 +319,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// info 'cuz we're putting it in hashes, so this is kinda
 	// best-effort...  I guess we should go with the compromise of "only
 	// remove things where all 3 match".
+	//
+	// What we drop here (and below) as already being right, install
+	// --verify works back out from the metadata; x-ref
+	// ManiUpgrade::unchanged().
 	{
 		let ncmatches = new.find_matching(&cur);
 		let mut matches = new.find_matching(&old);
@@ -368,8 +333,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 
 
-	// Handle MergeChanges
-	let mut to_merge = HashMap::new();
+	// Handle MergeChanges.  This is a BTreeMap so we always go through
+	// the merges in the same order; two runs over the same inputs should
+	// come out with the same manifest.
+	let mut to_merge = BTreeMap::new();
 	let dontmerge = merge::dont_merge();
 	if config.merge_changes.len() > 0
 	{
@@ -389,38 +356,43 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			// bother.
 			if dontmerge.contains(p) { return; }
 
-			// While f-u.sh does full comparisons and so will include
-			// e.g. permission-related mismatches, I'm gonna ignore that
-			// and just go with hashes.
-			let of = tm_old.files.get(p);
-			let nf = tm_new.files.get(p);
-
-			// Hang on, if this isn't in new, WTF are we doing here??
-			// And if it's not in old, we can't merge anything anyway...
-			let of = match of { Some(f) => f, None => return, };
-			let nf = match nf { Some(f) => f, None => return, };
-
-			// If cur matches either hash, we're done
-			let ch = f.sha256;
-			let oh = of.sha256;
-			let nh = nf.sha256;
-			if ch == oh || ch == nh { return; }
-
-			// How about the veryold case?  Maybe that triggers in some
-			// cases.  When we're "behind" on the patches on our current
-			// version, perhaps.
-			if let Some(vof) = tm_vold.files.get(p)
+			// The veryold case maybe triggers when we're "behind" on the
+			// patches on our current version.
+			let base = merge::merge_base(f, tm_old.files.get(p),
+					tm_new.files.get(p), tm_vold.files.get(p));
+			if let Some(of) = base { to_merge.insert(p.clone(), of.clone()); }
+		});
+	}
+
+	// Files that are new upstream, but that we already have our own
+	// version of.  Those would just get "added" over ours, since nothing
+	// above sees them as modified (there's no old to be modified from).
+	// So treat them like merges from an empty base; that'll pretty much
+	// always conflict, which puts the decision in front of the user via
+	// resolve-merges.
+	{
+		let pats = [&config.merge_changes[..], &config.update_if_unmodified[..]];
+		let colls = merge::new_local_collisions(&old, &cur, &new, &pats);
+		let nc = colls.len();
+		if nc > 0
+		{
+			println!("{nc} file{} new in {} already exist{} locally with \
+					different contents:", plural(nc), upargs.release,
+					if nc == 1 { "s" } else { "" });
+			colls.iter().for_each(|p| println!("    {}", p.display()));
+			println!("These will be staged as merge conflicts rather than \
+					replaced.");
+
+			let ehash = merge::empty_base(rtdirs.files())?;
+			for p in colls
 			{
-				if vof.sha256 == ch { return; }
+				let nf = new.files.get(&p).unwrap();
+				let base = MetaFile { sha256: ehash, ..nf.clone() };
+				to_merge.insert(p, base);
 			}
-
-			// So it's changed, and we have to try merging.  We don't
-			// really _know_ what version of the file the user started
-			// from, but guess it's the entry from old and we'll run with
-			// it.
-			to_merge.insert(p.clone(), of.clone());
-		});
+		}
 	}
+
 	let to_merge = to_merge;  // Dump mut JIC
 	match to_merge.len()
 	{
@@ -433,30 +405,75 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	/*
 	 * Do various filtering
 	 */
-	// Handle UpdateIfUnmodified
-	let modified_files = {
-		use crate::core::filter;
+	tm.phase("Filtering");
+	// Handle UpdateIfUnmodified.  Hang onto the hashes of the files
+	// we're skipping, so we can tell the user later what they're
+	// missing out on.
+	use crate::core::filter;
+	let (modified_files, skipped, skipped_updates) = {
 		let ignore: HashSet<_> = to_merge.keys().map(|p| p.as_ref()).collect();
 		let mpret = filter::modified_present(&old, &new, &cur,
 				&config.update_if_unmodified, Some(&ignore), Some(&cv_old));
-		filter::apply_modified_present(mpret, &mut old, &mut new, &mut cur)
+		let sk = mpret.skipped_updates(&old, &new);
+		let skipped: HashMap<PathBuf, merge::Skipped> = sk.iter()
+				.filter_map(|s| {
+					let cur = cur.files.get(&s.path)?.sha256.to_buf();
+					let new = new.files.get(&s.path)?.sha256.to_buf();
+					Some((s.path.clone(), merge::Skipped { cur, new,
+							security: s.security }))
+				}).collect();
+		let mf = filter::apply_modified_present(mpret, &mut old, &mut new,
+				&mut cur);
+		(mf, skipped, sk)
 	};
 	match modified_files.len()
 	{
 		nf if nf > 0 => println!("{nf} modified files will be ignored."),
 		_ => (),
 	}
+	for l in filter::skipped_updates_desc(&skipped_updates) { println!("{l}"); }
 
-	// AllowAdd and AllowDelete handling would go here
+	// AllowAdd=no we don't do (config refuses it).  AllowDelete=no
+	// means anything new would remove, we keep as it is instead.
+	if !config.allow_delete
+	{
+		use crate::util::plural;
+		let kept = filter::keep_deletes(&mut new, &cur);
+		let nk = kept.len();
+		if nk > 0
+		{
+			println!("{nk} path{} removed upstream will be kept \
+					(AllowDelete no).", plural(nk));
+		}
+	}
 
 	// Handle KeepModifiedMetadata.  Anything where the current metadata
 	// differs from old, replace new's metadata with our stuff.
+	let mut kept_metadata = Vec::new();
 	if config.keep_modified_metadata
 	{
 		let modd = cur.modified_metadata(&old);
 		if !modd.empty()
 		{
-			new.replace_metadata_from(&modd);
+			kept_metadata = new.replace_metadata_from(&modd);
+		}
+	}
+	for l in filter::kept_metadata_desc(&kept_metadata, false)
+	{ println!("{l}"); }
+
+	// Before going any further, make sure what we'd remove makes sense.
+	if let Some(mr) = crate::core::removal::check(&cur, &new,
+			config.max_removal_percent)
+	{
+		let pct = config.max_removal_percent;
+		for l in mr.describe(pct) { eprintln!("{l}"); }
+		match upargs.allow_mass_removal {
+			true  => eprintln!("Going ahead anyway (--allow-mass-removal).\n"),
+			false => {
+				eprintln!("If that's really what you want, rerun with \
+						--allow-mass-removal.");
+				bail!("refusing implausible mass removal");
+			},
 		}
 	}
 
@@ -475,14 +492,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// weren't, we have bombed out well before here), and there's no
 	// changes, something went very very wrong, so this is definitely
 	// error-y.
-	let relstr = || -> String {
-		use crate::info::version::mk_str;
-		mk_str(&upargs.release.release, &upargs.release.reltype,
-				server.keytag_patchnum())
-	};
+	let rstr = crate::info::version::mk_str(&upargs.release.release,
+			&upargs.release.reltype, server.keytag_patchnum());
 	if cur.empty()
 	{
-		let rstr = relstr();
 		println!("No updates needed update system to {rstr}.\n\
 				I'm an upgrade, so that can't be right, right??");
 		bail!("Should have found upgrades to do!");
@@ -508,6 +521,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}).collect();
 	if mhashes.len() > 0
 	{
+		tm.phase("Fetching old files for merging");
 		println!("Trying to fetch {} old files for merging", mhashes.len());
 
 		// In a sense, this feels like it should be best-effort; ideally
@@ -523,18 +537,21 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let keep = true;
 		let ctrl = hcp::Control { tmpdir, filesdir, keep };
 
-		hf::get(&old_server, mhashes, ctrl)?;
+		hf::get(&mut old_server, mhashes, ctrl)?;
 	}
 
 	// Be sure any of our cur files are stashed up in the filesdir.  Any
 	// of them that are unmodified from old, we may need for patching.
 	// The modified ones don't fall into that, but may be needed for
 	// rollback, so we'll just stash 'em all.
-	if let Some(stashfiles) = cur.files_no_hash_dir(rtdirs.files())
+	//
+	// The ones we're about to merge, we can't do without.
 	{
-		println!("Stashing {} current files.", stashfiles.len());
-		cur.stash_files(&stashfiles, config.basedir().to_path_buf(),
-				rtdirs.tmp().to_path_buf(), rtdirs.files().to_path_buf())?;
+		tm.phase("Stashing files");
+		let required: HashSet<_> = to_merge.keys().map(|p| p.as_path())
+				.collect();
+		crate::core::stash::stash_current(&mut cur, config.basedir(),
+				rtdirs.tmp(), rtdirs.files(), &required)?;
 	}
 
 
@@ -589,6 +606,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	// Won't trigger here currently; x-ref above
 	if maybe_patches.len() > 0
 	{
+		tm.phase("Fetching patches");
 		println!("Trying to fetch {} patch files.", maybe_patches.len());
 		let pret = server.fetch_patch_files(maybe_patches,
 				rtdirs.tmp().to_path_buf())?;
@@ -628,13 +646,50 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let keep = true;
 		let ctrl = hcp::Control { tmpdir, filesdir, keep };
 
-		hf::get(&server, nh, ctrl)?;
+		// Make some room first, if we're capped, without losing what
+		// this or a pending install needs, and with space for what's
+		// coming.
+		use crate::core::evict;
+		let mut keep = evict::pending(&state);
+		keep.extend(cur.files.values().chain(new.files.values())
+				.chain(to_merge.values()).map(|f| f.sha256));
+		evict::enforce(rtdirs.files(), config.max_workdir_size, &keep,
+				nh.len())?;
+
+		tm.phase(format!("Fetching {} files", nh.len()));
+		hf::get(&mut server, nh, ctrl)?;
 	}
 	else
 	{
 		println!("All files present.");
 	}
 
+	// The upstream versions of the files UpdateIfUnmodified skipped
+	// aren't needed for installing, but they're what show-merges
+	// --etc-report compares against.  Nice to have, not worth failing
+	// over.
+	let mut skiphashes: Vec<_> = skipped.values().map(|s| s.new)
+			.filter(|h| !rtdirs.files().join(format!("{h}.gz")).is_file())
+			.collect();
+	skiphashes.sort_unstable();
+	if skiphashes.len() > 0
+	{
+		use crate::core::pool::hashcheck as hcp;
+		use crate::core::hashfetch as hf;
+
+		let tmpdir = rtdirs.tmp().to_path_buf();
+		let filesdir = rtdirs.files().to_path_buf();
+		let keep = true;
+		let ctrl = hcp::Control { tmpdir, filesdir, keep };
+
+		tm.phase("Fetching skipped files");
+		if let Err(e) = hf::get(&mut server, skiphashes, ctrl)
+		{
+			eprintln!("Couldn't fetch upstream versions of skipped \
+					files: {e}");
+		}
+	}
+
 
 
 	/*
@@ -653,6 +708,7 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	if tmlen > 0
 	{
 		use std::fs;
+		tm.phase("Merging");
 		println!("Trying to merge {tmlen} file{}.", plural(tmlen));
 
 		// Prep up a place to do the merges
@@ -679,7 +735,9 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		//
 		// 2) File doesn't exist in old.  In that case, "merge" is
 		//    defined as "just take the new", so...  also not very
-		//    merge-y.
+		//    merge-y.  Unless we already had our own version, in
+		//    which case we've put it in with an empty old, and it'll
+		//    wind up as a conflict.
 		//
 		// Those two cases are already handled by us up above when we
 		// build to_merge; it already won't have entries for anything
@@ -700,9 +758,10 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		//    which case we _show_ the user, but don't provide any way to
 		//    override, or they fail, in which case we dump them in
 		//    $EDITOR in a diff3-style file to fix.
-		for (path, of) in to_merge
+		for (i, (path, of)) in to_merge.into_iter().enumerate()
 		{
-			use merge::{merge_files, MergeError};
+			crate::util::status::items(i as u64, Some(tmlen as u64));
+			use merge::{merge_files, MergeError, Merged};
 			use crate::util::compress;
 			use crate::util::hash::sha256_file;
 
@@ -731,13 +790,16 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			// If it went OK, put it in our clean list and move on.  If
 			// it got an IO error, just bomb out.
 			let mut outf = tempfile::NamedTempFile::new_in(rtdirs.tmp())?;
-			let mret = merge_files(&oldb, &curb, &newb, outf.as_file_mut());
-			let isok = match mret {
-				Ok(_) => true,
+			// Normalizing only changes what we install; cur was stashed
+			// as-is, so it's still there to roll back to.
+			let mret = merge_files(&oldb, &curb, &newb,
+					config.merge_normalize, outf.as_file_mut());
+			let (isok, normalized) = match mret {
+				Ok(m) => (true, m == Merged::Normalized),
 				Err(e) => match e {
 					// IO errors we bomb, Conflicts we continue on
 					MergeError::IO(ioe) => return Err(ioe)?,
-					MergeError::Conflicts => false,
+					MergeError::Conflicts => (false, false),
 				},
 			};
 
@@ -758,7 +820,8 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 			{
 				true => {
 					let res = nhb;
-					let cm = merge::Clean { old, new, cur, res };
+					let cm = merge::Clean { old, new, cur, res,
+							resolved: false, normalized };
 					merges_clean.insert(pbuf, cm);
 				},
 				false => {
@@ -775,6 +838,13 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		{
 			println!("{oklen} file{} merged cleanly.\n\
 					Run `{cmdname} show-merges` to review.", plural(oklen));
+			let nnorm = merges_clean.values().filter(|c| c.normalized)
+					.count();
+			if nnorm > 0
+			{
+				println!("({nnorm} of those only after normalizing trailing \
+						whitespace and final newlines.)");
+			}
 		}
 		if cflen > 0
 		{
@@ -805,7 +875,35 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		// the server keytag.
 		let mut vers = upargs.release.clone();
 		vers.patch = server.keytag_patchnum();
-		Manifest::new_upgrade(cur, new, vers, merges_clean, merges_conflict)
+		let mut mu = Manifest::new_upgrade(cur, new, vers, merges_clean,
+				merges_conflict);
+		mu.set_from(version.max().clone());
+		mu.set_keyprint(&config.keyprint);
+		mu.set_note(note);
+		mu.set_kept_metadata(kept_metadata);
+		if config.preserve_timestamps == crate::config::Timestamps::Upstream
+		{
+			use crate::core::install::upstream_mtime;
+			mu.set_install_mtime(Some(upstream_mtime(mu.version())));
+		}
+		if let Some(attr) = &config.annotate_xattr
+		{ mu.set_annotated(attr, annotated); }
+
+		// Size up the install steps, for estimating.  Everything should
+		// be in the files dir by now, but it's only an estimate, so not
+		// worth failing over.
+		if let Manifest::Upgrade(u) = &mut mu
+		{
+			u.skipped = skipped;
+			u.sizes = match u.step_sizes(&rtdirs) {
+				Ok(sz) => Some(sz),
+				Err(e) => {
+					eprintln!("Couldn't size up install steps: {e}");
+					None
+				},
+			};
+		}
+		mu
 	};
 
 
@@ -819,6 +917,26 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 		let upd = sum.updated .len();
 		println!("\nUpgrade will remove {rem} files, add {add} files, and \
 				update {upd} files.");
+
+		let sizes = match &manifest {
+			Manifest::Upgrade(u) => u.sizes,
+			Manifest::Fetch(_) => None,
+		};
+		if let Some(sz) = sizes
+		{
+			println!("Install estimate (not counting the reboot between):");
+			let mbps = config.install_mb_per_sec;
+			sz.describe(mbps).iter().for_each(|l| println!("  {l}"));
+		}
+
+		let risk = manifest.risks().summary().describe();
+		risk.iter().for_each(|l| println!("{l}"));
+
+		let run = crate::util::bytecount::get();
+		manifest.bytes_summary(&rtdirs, &run).iter()
+				.for_each(|l| println!("{l}"));
+		server.stats().iter()
+				.for_each(|h| println!("From {}.", h.describe()));
 	}
 
 
@@ -826,7 +944,24 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	state.manifest = Some(manifest);
 	let save_mdidx = mdidx.clone_matching(metadatas);
 	state.meta_idx = Some(save_mdidx);
+	state.meta_idx_vers = Some(upargs.release.clone());
+	state.meta_idx_keyprint = Some(config.keyprint.clone());
+	tm.phase("Saving state");
+	let signed = server.signed_files()?;
+	signed.save(&rtdirs.signed_dir())?;
+	signed.archive_tag(&rtdirs.signed_dir(), &crate::info::AVersion {
+			patch: server.keytag_patchnum(), ..upargs.release.clone() })?;
+	state.bytes.add(&crate::util::bytecount::get());
+	state.server_stats = server.stats();
 	rtdirs.state_save(&state)?;
+	tm.end();
+
+	// And get back under MaxWorkdirSize, now that we know just what's
+	// needed.  Everything's saved by now, so falling short just means
+	// being over the cap until the next run.
+	if let Err(e) = crate::core::evict::enforce(rtdirs.files(),
+			config.max_workdir_size, &crate::core::evict::pending(&state), 0)
+	{ eprintln!("Warning: {e}"); }
 
 
 	// Remind the user if there are conflicts to resolve.  Otherwise just
@@ -842,7 +977,6 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 	}
 	else
 	{
-		let rstr = relstr();
 		println!("\nRun `{cmdname} install` to upgrade from {version} to \
 				{rstr}.");
 	}
@@ -852,10 +986,30 @@ pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
 
 
 	// And that's it...
+	tm.report(clargs.timings);
 	Ok(())
 }
 
 
+/// Are we running a custom (non-GENERIC) kernel that upgrading will
+/// replace?  Returns its config name if so.
+///
+/// If <basedir> isn't /, or we're pretending to be some other version,
+/// then the running kernel doesn't really tell us a darn thing about the
+/// system we're working on, so we don't even ask.
+fn custom_kernel(isroot: bool, faked: bool,
+		kconf: impl FnOnce() -> Result<String, anyhow::Error>)
+		-> Result<Option<String>, anyhow::Error>
+{
+	if !isroot || faked { return Ok(None); }
+	let kc = kconf()?;
+	match kc == "GENERIC" {
+		true  => Ok(None),
+		false => Ok(Some(kc)),
+	}
+}
+
+
 /// Do some checks of our config/etc
 fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 {
@@ -876,10 +1030,10 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 	check!(workdir);
 	check!(basedir);
 
-	// Should only run on releases (temporarily knocked off for dev)
+	// Should only run on releases
 	match crate::check::version(&carg.version) {
 		Ok(_) => (),
-		Err(_e) => (), // errs.push(e),
+		Err(e) => errs.push(e),
 	};
 
 	// Nonsensical to upgrade to ourself
@@ -913,3 +1067,30 @@ fn check(carg: &CmdArg) -> Result<(), anyhow::Error>
 		},
 	}
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn custom_kernel()
+	{
+		use super::custom_kernel;
+
+		let never = || -> Result<String, anyhow::Error> {
+			panic!("shouldn't be looking at the running kernel")
+		};
+		let kc = |k: &'static str| move || Ok(k.to_string());
+
+		// Faked versions and other basedirs never ask the host.
+		assert_eq!(custom_kernel(true, true, never).unwrap(), None);
+		assert_eq!(custom_kernel(false, false, never).unwrap(), None);
+		assert_eq!(custom_kernel(false, true, never).unwrap(), None);
+
+		// Otherwise, GENERIC is fine, others get called out.
+		assert_eq!(custom_kernel(true, false, kc("GENERIC")).unwrap(), None);
+		assert_eq!(custom_kernel(true, false, kc("MYKERN")).unwrap(),
+				Some("MYKERN".to_string()));
+	}
+}
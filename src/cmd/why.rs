@@ -0,0 +1,122 @@
+//! $0 why
+use crate::command::CmdArg;
+use crate::metadata::MetadataLine;
+
+
+pub(crate) fn run(carg: CmdArg) -> Result<(), anyhow::Error>
+{
+	// Setting up various dirs
+	let rtdirs = crate::core::RtDirs::init(&carg.config.basedir(),
+			&carg.config.workdir())?
+			.with_mdcache(carg.config.metadata_cache)?;
+
+	let CmdArg { clargs, mut config, version } = carg;
+
+	// Extract args
+	let args = match clargs.command {
+		crate::command::FrCmds::Why(a) => a,
+		_ => unreachable!("I'm a why, why does it think I'm not??"),
+	};
+	let path = args.path.as_path();
+	if !path.is_absolute()
+	{ anyhow::bail!("Need an absolute path, not {}", path.display()); }
+
+	// Same component setup fetch would do
+	if let Some(n) = config.finalize_components() { println!("{n}"); }
+
+	println!("Currently running {version}.");
+
+
+	/*
+	 * Get the metadata the same way fetch does, but parse it out raw;
+	 * the filtering is part of what we're explaining.
+	 */
+	let mut server = crate::server::Server::find_cached_rt(&config,
+			&version.kernel, &rtdirs, false)?;
+	server.set_filesdir(rtdirs.files().to_path_buf());
+
+	use crate::core::mdfetch;
+	let metadatas = &["new", "old"];
+	let mdidx = mdfetch::fetch_files(&mut server, &rtdirs, metadatas, None,
+			&mut mdfetch::default_printer())?;
+	let parse = |which| {
+		mdidx.parse_one(rtdirs.tmp(), which).map_err(|e| {
+			let es: Vec<_> = e.iter().map(|e| e.to_string()).collect();
+			anyhow::anyhow!("Parsing {which} metadata: {}", es.join("; "))
+		})
+	};
+	let old = parse("old")?;
+	let new = parse("new")?;
+
+	// And just the one path on the system
+	let cur = crate::core::scan::scan(config.basedir().to_path_buf(),
+			vec![path.to_path_buf()])?;
+
+
+	/*
+	 * Now what's it all mean?
+	 */
+	use crate::core::why;
+	let ex = why::explain(path, old, new, cur, &config, args.upgrade);
+
+	println!("\n{}:", path.display());
+	match ex.components.is_empty() {
+		true  => println!("  Components: none"),
+		false => {
+			let cs: Vec<_> = ex.components.iter().map(|(c, used)| {
+				match used {
+					true  => c.clone(),
+					false => format!("{c} (not in Components)"),
+				}
+			}).collect();
+			println!("  Components: {}", cs.join(", "));
+		},
+	}
+	for (set, pat) in &ex.patterns
+	{ println!("  Matches {set} `{pat}`"); }
+	println!("  Old: {}", show_line(ex.old.as_ref()));
+	println!("  New: {}", show_line(ex.new.as_ref()));
+	println!("  Cur: {}", show_line(ex.cur.as_ref()));
+	if ex.metadata_kept
+	{ println!("  Local owner/mode/flags kept (KeepModifiedMetadata)"); }
+
+	let what = if args.upgrade { "Upgrade" } else { "Fetch" };
+	println!("\n{what}: {}; {}.", ex.disposition.desc(), ex.rule);
+
+
+	// If there's something pending, it may have its own ideas (e.g., an
+	// upgrade merging things).
+	let state = rtdirs.state_load()?;
+	if let Some(m) = &state.manifest
+	{
+		let what = why::pending(m, path);
+		let what = what.as_deref().unwrap_or("doesn't touch it");
+		println!("Pending {} to {}: {what}.", m.mtype(), m.version());
+	}
+
+	Ok(())
+}
+
+
+/// Show the interesting bits of a line.
+fn show_line(l: Option<&MetadataLine>) -> String
+{
+	use crate::metadata::MetadataLine as ML;
+
+	let perms = |uid: u32, gid: u32, mode: u32, flags: u32| {
+		format!("{uid}:{gid} mode {mode:o} flags {flags:x}")
+	};
+	match l {
+		None => "(none)".to_string(),
+		Some(ML::File(f)) => format!("file {} {}", f.sha256.to_buf(),
+				perms(f.uid, f.gid, f.mode, f.flags)),
+		Some(ML::Dir(d)) => format!("directory {}",
+				perms(d.uid, d.gid, d.mode, d.flags)),
+		Some(ML::SymLink(s)) => format!("symlink -> {} {}",
+				s.target.display(),
+				perms(s.uid, s.gid, s.mode, s.flags)),
+		Some(ML::HardLink(h)) => format!("hardlink to {}",
+				h.target.display()),
+		Some(ML::Dash(_)) => "not present".to_string(),
+	}
+}
@@ -6,7 +6,10 @@ mod line;
 pub(crate) use line::FrArgs;
 pub(crate) use line::FrCmds;
 pub(crate) use line::{ShowInstallType, CheckSysIgnore};
-pub(crate) use line::FrCmdInstall;
+pub(crate) use line::DumpMetadataFormat;
+pub(crate) use line::{FrCmdInstall, FrCmdMergeFile};
+pub(crate) use line::BusyFiles;
+pub(crate) use line::{CompletionShell, CompleteWhat};
 pub use line::parse;
 
 
@@ -57,8 +60,46 @@ pub fn run(clargs: FrArgs) -> Result<ExitCode, anyhow::Error>
 {
 	use crate::*;
 
+	// Checking the config has to happen without loading it the normal
+	// way, since that bails on the first problem.
+	if let line::FrCmds::ConfigCheck(_) = &clargs.command
+	{
+		init(&clargs)?;
+		let ret = cmd::config_check::run(&clargs)?;
+		return Ok(MyExit::from(ret).into());
+	}
+
+	// The fetch worker has already given up the privs to read the
+	// config, or much of anything else; it gets all it needs on stdin.
+	if let line::FrCmds::PrivsepWorker = &clargs.command
+	{
+		init(&clargs)?;
+		crate::core::privsep::worker_main()?;
+		return Ok(MyExit::Ok.into());
+	}
+
+	// Completions shouldn't need a working config (or much of anything)
+	// to get the script out, and the helpers make do without one.
+	// Likewise saying what we can do.
+	match &clargs.command {
+		line::FrCmds::Completions(a) => {
+			cmd::completions::script(a.shell);
+			return Ok(MyExit::Ok.into());
+		},
+		line::FrCmds::Complete(a) => {
+			cmd::completions::complete(&clargs, a.what);
+			return Ok(MyExit::Ok.into());
+		},
+		line::FrCmds::Capabilities => {
+			cmd::capabilities::run();
+			return Ok(MyExit::Ok.into());
+		},
+		_ => (),
+	}
+
 	// Load up config
 	let config = config::load_config_file(&clargs.config, &clargs)?;
+	crate::core::privsep::set_user(&config.fetch_user);
 
 	// Any early initalization
 	init(&clargs)?;
@@ -69,6 +110,8 @@ pub fn run(clargs: FrArgs) -> Result<ExitCode, anyhow::Error>
 		None => crate::info::version::get(config.basedir())?,
 	};
 
+	crate::core::session::note(format_args!("version {}", version.kernel));
+
 	let carg = CmdArg { clargs, config, version };
 
 	use line::FrCmds as FC;
@@ -79,19 +122,36 @@ pub fn run(clargs: FrArgs) -> Result<ExitCode, anyhow::Error>
 		FC::Upgrade{..} => cmd::upgrade::run(carg)?.into(),
 		FC::Install{..} => cmd::install::run(carg)?.into(),
 		FC::Extract{..} => cmd::extract::run(carg)?.into(),
+		FC::FixLinks{..} => cmd::fix_links::run(carg)?.into(),
 		FC::CheckSys{..} => cmd::check_sys::run(carg)?.into(),
 		FC::CheckFetch{..} => cmd::check_fetch::run(carg)?.into(),
+		FC::Audit{..} => cmd::audit::run(carg)?.into(),
+		FC::Why{..} => cmd::why::run(carg)?.into(),
+		FC::PatchContents{..} => cmd::patch_contents::run(carg)?.into(),
 
 		// Show
 		FC::ShowInstall{..} => cmd::show_install::run(carg)?.into(),
 		FC::ShowMerges{..}  => cmd::show_merges::run(carg)?.into(),
+		FC::Progress{..}    => cmd::progress::run(carg)?.into(),
 
 		// Misc
 		FC::Clean{..} => cmd::clean::run(carg)?.into(),
 		FC::ResolveMerges{..} => cmd::resolve_merges::run(carg)?.into(),
+		FC::ExportPending{..} => cmd::export_pending::run(carg)?.into(),
+		FC::ImportPending{..} => cmd::import_pending::run(carg)?.into(),
+		FC::MergeFile{..} => cmd::merge_file::run(carg)?.into(),
+		FC::ConfigCheck{..} => unreachable!("Handled before config load"),
+		FC::Doctor{..} => cmd::doctor::run(carg)?.into(),
+		FC::Completions{..} => unreachable!("Handled before config load"),
+		FC::Capabilities => unreachable!("Handled before config load"),
 
 		// Dev
 		FC::DumpMetadata{..} => cmd::dump_metadata::run(carg)?.into(),
+		FC::HashBench{..} => cmd::hash_bench::run(carg)?.into(),
+		FC::IoBench{..} => cmd::io_bench::run(carg)?.into(),
+		FC::Selftest => cmd::selftest::run(carg)?.into(),
+		FC::PrivsepWorker => unreachable!("Handled before config load"),
+		FC::Complete{..} => unreachable!("Handled before config load"),
 
 		// Fake
 		#[cfg(test)]
@@ -105,7 +165,7 @@ pub fn run(clargs: FrArgs) -> Result<ExitCode, anyhow::Error>
 
 
 /// Do any initalization we care about
-pub fn init(_clargs: &FrArgs) -> Result<(), anyhow::Error>
+pub fn init(clargs: &FrArgs) -> Result<(), anyhow::Error>
 {
 	// Init cached euid; we don't change perms during the run, so...
 	crate::util::set_euid();
@@ -113,5 +173,94 @@ pub fn init(_clargs: &FrArgs) -> Result<(), anyhow::Error>
 	// Setup u/gid comparison flag
 	crate::metadata::init_ugid_cmp();
 
+	// Trusting the workdir's perms or not
+	crate::core::rtdirs::set_insecure_ok(clargs.insecure_workdir);
+
+	// Whether server lookups can go to a public resolver
+	crate::server::lookup::set_fallback_dns(clargs.fallback_dns);
+
+	// How to hash big files
+	crate::util::hash::set_mmap(clargs.hash_mmap);
+
+	// Recording (or replaying) what the server says
+	crate::core::session::init(clargs.record_session.as_deref(),
+			clargs.replay_session.as_deref())?;
+
 	Ok(())
 }
+
+
+/// Commands that just show stuff can die quietly if whoever's reading
+/// goes away (e.g., `| head`).  Anything doing real work keeps the
+/// default of ignoring it, and gets EPIPE errors instead.
+///
+/// This is process-wide, so it's main()'s call, not run()'s; tests run
+/// commands in-process, and the rest of them still want EPIPE.
+pub fn set_sigpipe(clargs: &FrArgs)
+{
+	use line::FrCmds as FC;
+	match clargs.command {
+		FC::ShowInstall{..} | FC::ShowMerges{..} | FC::CheckSys{..}
+				| FC::Progress{..} | FC::Why{..} | FC::PatchContents{..}
+				=> crate::util::out::sigpipe_default(),
+		_ => (),
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn not_release()
+	{
+		use clap::Parser as _;
+		use super::FrArgs;
+
+		let td = tempfile::tempdir().unwrap();
+		let (wd, bd) = (td.path().join("work"), td.path().join("base"));
+		std::fs::create_dir(&wd).unwrap();
+		std::fs::create_dir(&bd).unwrap();
+		let cfile = td.path().join("freebsd-update.conf");
+		std::fs::write(&cfile, format!("ServerName update.example.org\n\
+				KeyPrint {}\nWorkDir {}\nBaseDir {}\n", "0a".repeat(32),
+				wd.display(), bd.display())).unwrap();
+
+		let run = |vers: &str, cmd: &[&str]| {
+			let mut line = vec!["freebsd-rustdate", "-c",
+					cfile.to_str().unwrap(), "--as-version", vers];
+			line.extend_from_slice(cmd);
+			super::run(FrArgs::try_parse_from(line).unwrap())
+		};
+
+		// Everything that'd change the system turns it away the same way
+		let cmds: &[&[&str]] = &[&["fetch"], &["cron"], &["install"],
+				&["extract"], &["check-sys"], &["upgrade", "-r", "15.0-RELEASE"]];
+		for vers in ["14.2-STABLE", "15.0-CURRENT", "14.2-STABLE-mybuild"]
+		{
+			let want = format!("freebsd-rustdate can only update RELEASE \
+					systems; this system reports {vers}.");
+			for cmd in cmds
+			{
+				let err = run(vers, cmd).unwrap_err().to_string();
+				assert!(err.starts_with(&format!("Cannot run {}::", cmd[0])),
+						"{cmd:?}: {err}");
+				assert!(err.contains(&want), "{cmd:?}: {err}");
+			}
+
+			// check-fetch just says so
+			assert!(run(vers, &["check-fetch", "-q"]).is_ok());
+		}
+
+		// Releases typed short still come out the same as in full, so
+		// upgrading to where we already are is still caught.
+		for rel in ["14.2", "14.2-release", "FreeBSD 14.2-RELEASE-p5"]
+		{
+			let err = run("14.2-RELEASE-p1", &["upgrade", "-r", rel])
+					.unwrap_err().to_string();
+			assert!(err.contains("Cannot upgrade from 14.2-RELEASE to \
+					itself."), "{rel}: {err}");
+		}
+	}
+}
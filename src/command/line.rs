@@ -23,13 +23,19 @@ pub struct FrArgs
 	#[arg(short, long, default_value="/etc/freebsd-update.conf")]
 	pub(crate) config: PathBuf,
 
-	/// Act as if we're running a given version.
+	/// Act as if we're running a given version (e.g., 14.1-RELEASE-p3).
 	///
 	/// This probably has limited uses.  You would probably only need to
 	/// specify it if we can't figure out the version normally, which we
 	/// usually can.  Even with a `--basedir`, if we can run that
 	/// basedir's `freebsd-version`, we should get the right answer.
-	#[arg(id="as-version", long)]
+	///
+	/// If given, this wins over anything we'd detect, from the basedir or
+	/// otherwise, for everything version-related: which server metadata
+	/// we use, what we say we're updating from and to, and what gets
+	/// recorded.  It also means we don't look at the running kernel's
+	/// config, since it isn't the one we're pretending to run.
+	#[arg(id="as-version", long, value_name = "VERSION")]
 	pub(crate) fakeversion: Option<String>,
 
 	/// How many CPU-bound threads to run in parallel
@@ -54,6 +60,56 @@ pub struct FrArgs
 	#[arg(short='J', long)]
 	pub(crate) jobs_net: Option<u32>,
 
+	/// How many workers can be writing heavily at once
+	/// (default the -j value, but at least 4).
+	///
+	/// Stashing, checking, and patching files are as much writing as
+	/// they are CPU work, so raising `-j` for faster hashing also means
+	/// that many streams of writes into the workdir.  On a slow disk,
+	/// set this lower than `-j`, and the extra threads will only add
+	/// hashing and (de)compressing.
+	#[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+	pub(crate) jobs_io: Option<u32>,
+
+	/// Show how long each step took at the end of fetch/upgrade/install.
+	///
+	/// This is always shown for runs that take more than a few minutes.
+	#[arg(long)]
+	pub(crate) timings: bool,
+
+	/// Use the workdir even if others could write to it.
+	///
+	/// Normally we refuse to run if the workdir (or the files and state
+	/// dirs in it) isn't owned by us (or root), or is writable by
+	/// group or other, since whoever can write there can swap out what
+	/// gets installed.  Only use this if you're really sure.
+	#[arg(long)]
+	pub(crate) insecure_workdir: bool,
+
+	/// Hash big files via mmap instead of reading them.
+	///
+	/// This is usually faster, but a file being truncated while we're
+	/// hashing it kills the whole run (with SIGBUS), so it's not the
+	/// default.
+	#[arg(long)]
+	pub(crate) hash_mmap: bool,
+
+	/// Save what the server sends us into DIR, to reproduce this run.
+	///
+	/// Along with the responses themselves (keys, tags, metadata, and a
+	/// sample of the files), this keeps a log of the requests made, and
+	/// notes on what got decided along the way (components kept, how
+	/// many paths made it through each step).  It's for sending to
+	/// whoever's looking into a problem with your update, along with
+	/// your config and what version you're running.
+	#[arg(long, value_name = "DIR", conflicts_with = "replay_session")]
+	pub(crate) record_session: Option<PathBuf>,
+
+	/// Play back a --record-session DIR instead of talking to servers.
+	#[arg(long, value_name = "DIR")]
+	#[clap(hide(true))]
+	pub(crate) replay_session: Option<PathBuf>,
+
 
 	// Some config file params can be overriden on the command line
 
@@ -86,6 +142,35 @@ pub struct FrArgs
 	/// HTTP cache).
 	#[arg(id="server", short, long)]
 	pub(crate) servername: Option<String>,
+
+	/// Don't use the cached last-good server.
+	///
+	/// Normally we try the server that worked last time first, if it
+	/// was recent enough (see `ServerCacheTTL`).  This forces doing the
+	/// full server discovery instead.
+	#[arg(long)]
+	pub(crate) no_server_cache: bool,
+
+	/// Try this server first, when it's working.
+	///
+	/// Unlike `--server`, this doesn't replace the list of servers
+	/// found for `ServerName`; it just goes ahead of them (even if it's
+	/// not in the list), and if it's down or not offering the right
+	/// update, the rest get their usual turn.  Handy for a mirror you
+	/// know to be good.  Overrides `PreferServer` in the config.
+	#[arg(long, value_name="HOST")]
+	pub(crate) prefer_server: Option<String>,
+
+	/// Use a public DNS resolver if there's no other way to look up
+	/// servers.
+	///
+	/// Server discovery normally uses the nameservers in
+	/// /etc/resolv.conf, or failing that, whatever's answering on
+	/// 127.0.0.1.  If neither is there (say, early in boot, with
+	/// resolvconf's file not written yet), this allows falling back to
+	/// Quad9 (9.9.9.9) rather than giving up.
+	#[arg(long)]
+	pub(crate) fallback_dns: bool,
 }
 
 
@@ -114,6 +199,11 @@ pub(crate) enum FrCmds
 	/// cronjob.  It produces no direct output unless it hits an error.
 	/// If it finds pending updates that need to be installed, it sends
 	/// the output to the `MailTo` config param.
+	///
+	/// Each successful run leaves a `cron-last.json` in the statedir,
+	/// with when it ran and what it found, for monitoring to check on.
+	/// If a previous run is still going, it waits up to `CronLockWait`
+	/// seconds for it to finish.
 	Cron(FrCmdCron),
 
 	/// Fetch upgrades to a new version.
@@ -141,7 +231,10 @@ pub(crate) enum FrCmds
 	/// of one (or multiple) of those, like the list of the actual
 	/// path[s] that will be touched.
 	///
-	/// If no install is pending, it will just tell you that.
+	/// If no install is pending, it will tell you that, and then what
+	/// the last finished install did.  That comes from the history, so
+	/// `-v` can only list paths for the last few installs (see
+	/// InstallHistoryDetail); older ones just have counts.
 	ShowInstall(FrCmdShowInstall),
 
 	/// Show merged files from a pending upgrade.
@@ -162,6 +255,17 @@ pub(crate) enum FrCmds
 	/// resolve them.
 	ShowMerges(FrCmdShowMerges),
 
+	/// Show what a running fetch/upgrade/install is up to.
+	///
+	/// While they run, those commands keep a small JSON status file in
+	/// the state dir, with the phase they're in, how far along it they
+	/// are, and when it was last updated.  This shows it, or with
+	/// `--json`, just dumps it for other tools.  If the command that
+	/// wrote it isn't running anymore, it's called out as stale.
+	///
+	/// Exits non-zero if nothing's running.
+	Progress(FrCmdProgress),
+
 	/// Resolve conflicted merges for a pending upgrade.
 	///
 	/// In the case of a cross-version `upgrade`, locally changed config
@@ -178,12 +282,38 @@ pub(crate) enum FrCmds
 	/// there are pending conflicts to be resolved.
 	ResolveMerges(FrCmdResolveMerges),
 
+	/// Bundle up a pending install to carry to another system.
+	///
+	/// For systems that can't reach the update server themselves: run
+	/// the `fetch` or `upgrade` on one that can (with the same version,
+	/// config, and components), then this packs the pending install and
+	/// exactly the files needed to install it into a single tar, to be
+	/// loaded with `import-pending` on the other end.  With `--zstd`,
+	/// it's compressed with zstd(1).
+	///
+	/// A pending upgrade has to have any merge conflicts resolved, and
+	/// not be partly installed already.
+	ExportPending(FrCmdExportPending),
+
+	/// Load a pending install made with `export-pending`.
+	///
+	/// Every file in the bundle is checked against its hash, the pending
+	/// install is set up for this system, and the files it needs are
+	/// checked to all be present, so `install` can then run without
+	/// talking to anything.  This refuses bundles for another version,
+	/// KeyPrint, or architecture than what's here, and won't replace a
+	/// pending install that's already here; `clean --pending` that
+	/// first.
+	ImportPending(FrCmdImportPending),
+
 	/// Clean up stuff (not all stuff included).
 	///
-	/// This requires an argument for what to clean.  Currently pending
-	/// update/upgrade info is the only implemented feature.  It might be
-	/// nice if this could clean up old downloaded/cached files in a
-	/// smart way someday...
+	/// This requires an argument for what to clean: pending
+	/// update/upgrade info with `--pending`, downloaded and stashed files
+	/// in the files dir that a pending install doesn't need with
+	/// `--files`, and/or tempfiles left in the basedir by an install that
+	/// died partway with `--install-residue`.  `--dry-run` just says
+	/// what would go.
 	Clean(FrCmdClean),
 
 	/// Check current system state against upstream expectation.
@@ -212,6 +342,12 @@ pub(crate) enum FrCmds
 	/// upstream, so you'd probably want `-i uid,gid,flags` to always be
 	/// given.
 	///
+	/// Run as a regular user against `/`, you get what could be checked
+	/// without privilege.  Owners, modes, and types are still compared,
+	/// but flags aren't, and files that can't be read (or even looked
+	/// at) aren't compared at all; they're just counted, so run as root
+	/// for full coverage.
+	///
 	/// Also notable is the SHA256 hashes.  If you ignore those
 	/// differences (`-i hash`), then this command doesn't need to SHA256
 	/// your whole system to do the checks, so it will run a lot faster.
@@ -240,6 +376,43 @@ pub(crate) enum FrCmds
 	/// patch waiting.
 	CheckFetch(FrCmdCheckFetch),
 
+	/// Re-verify downloaded update files against upstream signatures.
+	///
+	/// This walks the chain from the signed tag, through the metadata
+	/// index and metadata files we've saved, down to the files needed
+	/// by any pending install, and checks each link.  No update files
+	/// are downloaded; only the small signed tag is re-fetched from the
+	/// server, and with `--offline`, not even that.
+	///
+	/// Exits non-zero if any check fails.
+	Audit(FrCmdAudit),
+
+	/// Explain what fetch would do with a path, and why.
+	///
+	/// Working out why some file did or didn't get updated means
+	/// untangling Components, IgnorePaths, UpdateIfUnmodified,
+	/// KeepModifiedMetadata, and what's actually on the system.  This
+	/// walks a single path through those the same way `fetch` does, and
+	/// shows which components it's in, which config patterns match it,
+	/// its old/new/current metadata, and what happens to it and which
+	/// rule decided that.  If there's an install pending, it also says
+	/// what that will do with it.
+	Why(FrCmdWhy),
+
+	/// Show what a patch level changed, file by file.
+	///
+	/// This fetches the upstream metadata for two patch levels of a
+	/// release (by default, the latest and the one before it) and lists
+	/// the paths added, removed, or changed between them, with the
+	/// components they're in.  Nothing about the local system is looked
+	/// at.
+	///
+	/// The server only offers a signed tag for its latest patch level,
+	/// so earlier ones need a tag saved by an earlier `fetch` or
+	/// `upgrade` here.  And servers don't keep old metadata around
+	/// forever; if the one needed is gone, you'll be told so.
+	PatchContents(FrCmdPatchContents),
+
 	/// Extract a file or subtree exactly from upstream.
 	///
 	/// Calling this with a path or several paths (possibly expressed as
@@ -268,6 +441,105 @@ pub(crate) enum FrCmds
 	/// to be _extremely_ cautious about pulling out `--force`.
 	Extract(FrCmdExtract),
 
+	/// Check (and optionally fix) hardlinks across the installed system.
+	///
+	/// Upstream ships a number of files as several hardlinked names.
+	/// Things like restoring from backups, or copying trees around, can
+	/// leave those as separate copies, or lose some of the names.  This
+	/// goes through all the hardlinks in the upstream metadata, and
+	/// reports any that aren't actually the same file as their target,
+	/// along with how much space relinking the copies would get back.
+	///
+	/// With `--apply`, missing links are recreated, and separate copies
+	/// are replaced with links, as long as their contents match the
+	/// target.  Anything else is reported and left alone.  A path being
+	/// fixed is never left missing, even if something fails partway.
+	///
+	/// Exits non-zero if any problems are left.
+	FixLinks(FrCmdFixLinks),
+
+	/// 3-way merge a set of files, the same way upgrade does.
+	///
+	/// This runs the same merge that `upgrade` uses for files matching
+	/// `MergeChanges`, on any arbitrary files you give it.  e.g., if
+	/// you've restored a config from backup, and want to re-apply the
+	/// upstream changes to it (with the old and new versions extracted
+	/// from somewhere).
+	///
+	/// The result goes to stdout, or to the file given with `-o`.  If
+	/// there are conflicts, the output has diff3-style conflict markers
+	/// in it.  Exits 0 for a clean merge, 1 if there were conflicts, and
+	/// 2 on errors.
+	MergeFile(FrCmdMergeFile),
+
+	/// Check the config file for problems.
+	///
+	/// This reports everything wrong with the config at once, with line
+	/// numbers where it can, rather than falling over on the first thing
+	/// when some other command runs.  That includes unknown (e.g.,
+	/// misspelled) params, and whether WorkDir and BaseDir exist and are
+	/// writable.  Exits non-zero if there are any errors; warnings alone
+	/// don't count.
+	ConfigCheck(FrCmdConfigCheck),
+
+	/// Look for common stuck states, and offer to fix them.
+	///
+	/// Checks for the things that usually need sorting out by hand
+	/// after something went wrong partway:
+	///
+	///   - a lockfile left behind by a run that's gone
+	///   - an upgrade partway installed, and what to run next
+	///   - a statefile that won't load (restored from its backup)
+	///   - metadata files in files/ that don't match their hash
+	///   - a pending fetch/upgrade made on another version than what's
+	///     running now (cleared, like `clean --pending`)
+	///
+	/// Each fix is asked about first.  Exits non-zero if anything's left
+	/// wrong afterward.
+	///
+	/// Examples:
+	///
+	///   See what's up without changing anything:
+	///     freebsd-rustdate doctor --dry-run
+	///
+	///   Fix everything it can, without asking (e.g. from a script):
+	///     freebsd-rustdate doctor --yes
+	///
+	///   Against a jail or other basedir:
+	///     freebsd-rustdate -b /jails/www -w /var/db/fu-www doctor
+	#[command(verbatim_doc_comment)]
+	Doctor(FrCmdDoctor),
+
+	/// Print a shell completion script.
+	///
+	/// Covers the commands and their flags, and fills in release names
+	/// for `upgrade -r` (and `patch-contents -r`) from the running
+	/// version and what's been fetched before, without asking the
+	/// server.  For bash, zsh, and fish, source the output (or drop it
+	/// where the shell looks for completions).  /bin/sh has no
+	/// programmable completion, so the sh version is a standalone
+	/// script that prints candidates for the words it's given; bash can
+	/// use it with `complete -C`, and anything else that can run a
+	/// command for its candidates can too.
+	///
+	/// Examples:
+	///
+	///   freebsd-rustdate completions --shell zsh \
+	///       > /usr/local/share/zsh/site-functions/_freebsd-rustdate
+	///   freebsd-rustdate completions --shell bash \
+	///       > /usr/local/share/bash-completion/completions/freebsd-rustdate
+	#[command(verbatim_doc_comment)]
+	Completions(FrCmdCompletions),
+
+	/// Describe what this version can do, as JSON.
+	///
+	/// For scripts and orchestration to check before using something,
+	/// rather than picking apart `--help`.  It has the version, the
+	/// statefile schema version, every command with its args (what they
+	/// take, and the choices where there are some), and a list of named
+	/// features that won't change meaning.
+	Capabilities,
+
 	/// Dump out metadata info for a version.  (DEV)
 	///
 	/// This is of no interest to anybody who's not working on
@@ -276,6 +548,48 @@ pub(crate) enum FrCmds
 	/// extracting them somewhere, so you can manually poke at things.
 	#[clap(hide(true))]
 	DumpMetadata(FrCmdDumpMetadata),
+
+	/// Time hashing the files under a dir.  (DEV)
+	///
+	/// Hashes everything under the dir each of the ways we know how,
+	/// and says how fast each was, to see whether changes to the hashing
+	/// code help or hurt.
+	#[clap(hide(true))]
+	HashBench(FrCmdHashBench),
+
+	/// Time a fake hashcheck under different --jobs-io.  (DEV)
+	///
+	/// Makes up files, gzips and hashes them on -j workers, and writes
+	/// them into a dir under a heavy IO slot, like the stash/hashcheck/
+	/// patch pools do.  Point it at a slow disk, or a size-capped tmpfs
+	/// with --delay-ms, to see how limiting the writers changes the
+	/// time and how much it bounces around.
+	#[clap(hide(true))]
+	IoBench(FrCmdIoBench),
+
+	/// Check the local machinery works, without the network.  (DEV)
+	///
+	/// Runs through compression, hashing, lstat/lchflags, patching,
+	/// merging, the statefile, and metadata parsing in a scratch dir,
+	/// and reports how each went along with some info about the system.
+	/// Mostly useful to paste into bug reports.
+	#[clap(hide(true))]
+	Selftest,
+
+	/// The unprivileged side of fetching as FetchUser.  (INTERNAL)
+	///
+	/// Gets run by us, not by people; it reads a job on stdin, and
+	/// writes what happened to stdout.
+	#[clap(hide(true))]
+	PrivsepWorker,
+
+	/// Candidates for the completion scripts to offer.  (INTERNAL)
+	///
+	/// What `completions` scripts call back into; newline-separated, and
+	/// only from what's here locally.
+	#[clap(hide(true))]
+	#[command(name = "__complete")]
+	Complete(FrCmdComplete),
 }
 
 
@@ -294,11 +608,88 @@ pub(crate) struct FrCmdFetch
 	#[arg(long)]
 	pub(crate) as_cron: bool,
 
+	/// Report paths ignored via the `IgnorePaths` config.
+	///
+	/// Shows how many paths each pattern matched, and calls out patterns
+	/// that matched nothing.  With `full`, the full path lists are also
+	/// written to `ignored-paths.txt` in the workdir.
+	#[arg(long, value_name = "LEVEL", num_args = 0..=1,
+			default_missing_value = "counts")]
+	pub(crate) show_ignored: Option<ShowIgnored>,
+
+	/// Don't touch paths matching this regex (can be specified multiple
+	/// times).
+	///
+	/// This works like `IgnorePaths` in the config, but just for this
+	/// run.  The pending install remembers it was computed this way, and
+	/// `show-install` will say so.
+	#[arg(short = 'x', long, value_name = "REGEX")]
+	pub(crate) exclude: Vec<regex_lite::Regex>,
+
+	/// Only update paths matching this regex (can be specified multiple
+	/// times).
+	///
+	/// Applied after `IgnorePaths` and `--exclude`.  As with
+	/// `--exclude`, this is just for this run, and `show-install` will
+	/// mention it.
+	#[arg(long, value_name = "REGEX")]
+	pub(crate) include_only: Vec<regex_lite::Regex>,
+
+	/// Take upstream's owner/mode/flags this run, even on paths where
+	/// they've been changed locally.
+	///
+	/// Overrides `KeepModifiedMetadata` in the config, just for this
+	/// run.
+	#[arg(long)]
+	pub(crate) no_keep_modified_metadata: bool,
+
+	/// Go ahead even if this would remove an implausible number of
+	/// paths.
+	///
+	/// Normally, removing more than `MaxRemovalPercent` of the paths
+	/// we're tracking, or everything in a dir like /usr/bin, is taken as
+	/// a sign of truncated metadata, and refused.
+	#[arg(long)]
+	pub(crate) allow_mass_removal: bool,
+
+	/// Go ahead even if the basedir's base system is managed by pkgbase.
+	///
+	/// Normally, if base is installed as FreeBSD-* packages, we refuse
+	/// to touch it, since pkg and we would be fighting over the same
+	/// files.
+	#[arg(long)]
+	pub(crate) allow_pkgbase: bool,
+
 	// XXX IF we grow more here, we presumably need to add them to
 	// FrCmdCron too, and adjust the cron::run() func to copy them over
 	// when it re-execs.
 }
 
+/// How much detail to show about ignored paths
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(clap::ValueEnum)]
+pub(crate) enum ShowIgnored
+{
+	/// Per-pattern counts
+	Counts,
+
+	/// Counts, plus full path lists written to a file
+	Full,
+}
+
+impl ShowIgnored
+{
+	/// Where the full lists go, if we're writing them.
+	pub(crate) fn full_file(&self, workdir: &std::path::Path)
+			-> Option<PathBuf>
+	{
+		match self {
+			Self::Counts => None,
+			Self::Full   => Some(workdir.join("ignored-paths.txt")),
+		}
+	}
+}
+
 /// Cron args
 #[derive(Debug)]
 #[derive(Parser)]
@@ -308,6 +699,13 @@ pub(crate) struct FrCmdCron
 	#[clap(hide(true))]
 	#[arg(long)]
 	pub(crate) immediately: bool,
+
+	/// Sleep a random time up to this many seconds before fetching
+	/// (0-14400).  Overrides the CronJitter config, which defaults to an
+	/// hour.
+	#[arg(long, value_name = "SECONDS",
+			value_parser = crate::config::parse_cron_jitter)]
+	pub(crate) jitter: Option<u64>,
 }
 
 /// Upgrade args
@@ -315,9 +713,61 @@ pub(crate) struct FrCmdCron
 #[derive(Parser)]
 pub(crate) struct FrCmdUpgrade
 {
-	/// Release to upgrade to (e.g., 13.2-RELEASE)
-	#[arg(short, long)]
+	/// Release to upgrade to (e.g., 13.2-RELEASE, or just 13.2)
+	#[arg(short, long, value_parser = crate::info::version::parse_release_arg)]
 	pub(crate) release: crate::info::version::AVersion,
+
+	/// Report paths ignored via the `IgnorePaths` config.
+	///
+	/// Shows how many paths each pattern matched, and calls out patterns
+	/// that matched nothing.  With `full`, the full path lists are also
+	/// written to `ignored-paths.txt` in the workdir.
+	#[arg(long, value_name = "LEVEL", num_args = 0..=1,
+			default_missing_value = "counts")]
+	pub(crate) show_ignored: Option<ShowIgnored>,
+
+	/// Don't touch paths matching this regex (can be specified multiple
+	/// times).
+	///
+	/// This works like `IgnorePaths` in the config, but just for this
+	/// run.  The pending install remembers it was computed this way, and
+	/// `show-install` will say so.
+	#[arg(short = 'x', long, value_name = "REGEX")]
+	pub(crate) exclude: Vec<regex_lite::Regex>,
+
+	/// Only update paths matching this regex (can be specified multiple
+	/// times).
+	///
+	/// Applied after `IgnorePaths` and `--exclude`.  As with
+	/// `--exclude`, this is just for this run, and `show-install` will
+	/// mention it.
+	#[arg(long, value_name = "REGEX")]
+	pub(crate) include_only: Vec<regex_lite::Regex>,
+
+	/// Take upstream's owner/mode/flags this run, even on paths where
+	/// they've been changed locally.
+	///
+	/// Overrides `KeepModifiedMetadata` in the config, just for this
+	/// run.
+	#[arg(long)]
+	pub(crate) no_keep_modified_metadata: bool,
+
+	/// Go ahead even if this would remove an implausible number of
+	/// paths.
+	///
+	/// Normally, removing more than `MaxRemovalPercent` of the paths
+	/// we're tracking, or everything in a dir like /usr/bin, is taken as
+	/// a sign of truncated metadata, and refused.
+	#[arg(long)]
+	pub(crate) allow_mass_removal: bool,
+
+	/// Go ahead even if the basedir's base system is managed by pkgbase.
+	///
+	/// Normally, if base is installed as FreeBSD-* packages, we refuse
+	/// to touch it, since pkg and we would be fighting over the same
+	/// files.
+	#[arg(long)]
+	pub(crate) allow_pkgbase: bool,
 }
 
 /// Install args
@@ -351,6 +801,172 @@ pub(crate) struct FrCmdInstall
 	/// process.
 	#[arg(short='s', long)]
 	pub(crate) no_sync: bool,
+
+	/// Carry on past paths that fail to install, and list them at the end.
+	///
+	/// Normally the first path that can't be installed stops everything.
+	/// With this, it's noted and skipped, the rest goes in, and the
+	/// install fails at the end with the list.  Only those stay pending,
+	/// so running `install` again (once you've fixed what was wrong)
+	/// retries just them.  A full or read-only filesystem still stops
+	/// things right away.
+	#[arg(long)]
+	pub(crate) keep_going: bool,
+
+	/// Reboot after installing the kernel step of an Upgrade.
+	///
+	/// Only applies when installing to `/` as root, and the kernel step
+	/// completes successfully with the world step still pending.  A
+	/// reboot will be scheduled via shutdown(8) a minute out.  Combine
+	/// with `--enable-resume` to have the world step run automatically
+	/// on the next boot.
+	#[arg(short='r', long, conflicts_with_all = ["all", "dry_run"])]
+	pub(crate) reboot: bool,
+
+	/// Install an rc.d script to resume the install after `--reboot`.
+	///
+	/// This writes a one-shot rc.d script that re-runs `install` on boot
+	/// if the previous run rebooted via `--reboot`, then exits without
+	/// installing anything.
+	#[arg(long, exclusive = true)]
+	pub(crate) enable_resume: bool,
+
+	/// Remove old shared libs even if running processes still use them.
+	///
+	/// Normally the final step of an Upgrade checks whether any running
+	/// process has the old libs mapped, and refuses to remove them if
+	/// so.
+	#[arg(long, conflicts_with = "skip_lib_cleanup")]
+	pub(crate) force_lib_cleanup: bool,
+
+	/// Finish an Upgrade without removing the old shared libs.
+	///
+	/// They'll be left in place and remembered in the state, so you can
+	/// clean them up by hand once nothing needs them.
+	#[arg(long)]
+	pub(crate) skip_lib_cleanup: bool,
+
+	/// Install even if the system isn't running the version the pending
+	/// fetch/upgrade was made from.
+	///
+	/// If the system's been upgraded some other way since (say, by
+	/// activating a different boot environment), installing the pending
+	/// changes would generally put older files over newer ones, so
+	/// that's refused unless this is given.
+	#[arg(long)]
+	pub(crate) force: bool,
+
+	/// Replace files marked with the `AnnotateXattr` extattr anyway.
+	///
+	/// Only needed with `ProtectAnnotated yes`; otherwise marked files
+	/// are just counted.
+	#[arg(long)]
+	pub(crate) touch_managed: bool,
+
+	/// Go ahead even if the basedir's base system is managed by pkgbase.
+	///
+	/// Normally, if base is installed as FreeBSD-* packages, we refuse
+	/// to touch it, since pkg and we would be fighting over the same
+	/// files.
+	#[arg(long)]
+	pub(crate) allow_pkgbase: bool,
+
+	/// Restart the jail when done, if installing into a running jail.
+	///
+	/// If the basedir is the root of a running jail, the whole jail is
+	/// restarted (via `service jail restart`) after the install is
+	/// complete, instead of restarting individual services in it.
+	#[arg(long)]
+	pub(crate) restart_jail: bool,
+
+	/// Don't automatically restart these services.
+	///
+	/// Services whose bits get replaced (like sshd) are normally
+	/// restarted after the world install.  Those named here (or all of
+	/// them, if none are named) are left alone, and listed as needing a
+	/// manual restart instead.  Adds to the `NoRestartServices` config.
+	#[arg(long, value_name = "SVC", value_delimiter = ',', num_args = 0..)]
+	pub(crate) no_restart_services: Option<Vec<String>>,
+
+	/// Also re-hash installed files when verifying the install.
+	///
+	/// After installing, everything installed is checked against the
+	/// expected ownership, mode, and flags, and any differences are
+	/// reported (with an exit status of 2).  This also checks file
+	/// contents, which is a lot slower.
+	#[arg(long)]
+	pub(crate) verify_full: bool,
+
+	/// Audit the whole result after the final step of an upgrade.
+	///
+	/// Once the last step is installed, every path the upgrade covered
+	/// (what it installed, and what it found already up to date) is
+	/// checked on disk against the new release, contents included.
+	/// Differences don't undo anything, but they're reported (with an
+	/// exit status of 2), and recorded with the install.
+	#[arg(long)]
+	pub(crate) verify: bool,
+
+	/// Write the intended ownership of installed files to an mtree file.
+	///
+	/// When installing as non-root (e.g., building an image in a dir
+	/// you own), ownership and flags can't be set.  This records what
+	/// they should be, for each installed path (and any parent dirs
+	/// created along the way), in mtree(5) format suitable for
+	/// `mtree -U` or `tar @spec`.  An existing file is added to, so the
+	/// kernel and world steps of an Upgrade accumulate into one.
+	#[arg(long, value_name = "FILE")]
+	pub(crate) ownership_manifest: Option<std::path::PathBuf>,
+
+	/// Copy ACLs and extended attributes onto files being replaced.
+	///
+	/// Installing a file puts a whole new file in place, so anything on
+	/// the old one that isn't ownership/mode/flags would be lost.  By
+	/// default we copy over any non-trivial ACL and extattrs (best
+	/// effort); either way, any files that lost them are listed at the
+	/// end of the install.
+	#[arg(long, value_name = "BOOL", default_value_t = true)]
+	#[arg(action = clap::ArgAction::Set)]
+	pub(crate) preserve_acls: bool,
+
+	/// Refuse to install if the basedir's filesystem won't keep file
+	/// flags.
+	///
+	/// Before installing (as root), we try setting the flags the install
+	/// wants on a scratch file in the basedir.  Some filesystems (tmpfs,
+	/// NFS) don't keep some or all of them, and the install would
+	/// otherwise go through with a warning and the flags just missing.
+	/// This makes that (or not being able to tell) an error instead.
+	#[arg(long)]
+	pub(crate) require_flags: bool,
+
+	/// What to do about files another process has open for writing.
+	///
+	/// Replacing a file something has open for writing (a log, a db a
+	/// daemon is rebuilding) leaves it writing to the old, unlinked
+	/// file, and what it writes is lost.  So files under /etc and /var
+	/// are checked first (with procstat(1)).  `retry` holds busy ones
+	/// until everything else is in and checks again, skipping any still
+	/// busy; `skip` skips them right away; `force` doesn't check.
+	/// Skipped files stay pending, for running `install` again later.
+	#[arg(long, value_enum, value_name = "WHAT", default_value_t)]
+	pub(crate) busy_files: BusyFiles,
+}
+
+/// What install does about files open for writing
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[derive(clap::ValueEnum)]
+pub(crate) enum BusyFiles
+{
+	/// Try them again at the end, then skip them
+	#[default]
+	Retry,
+
+	/// Skip them
+	Skip,
+
+	/// Replace them anyway
+	Force,
 }
 
 /// ShowInstall verbose types
@@ -379,6 +995,12 @@ pub(crate) enum ShowInstallType
 
 	/// Merged files
 	Merge,
+
+	/// Old shared libs to remove at the end of an Upgrade
+	Libs,
+
+	/// Where local owner/mode/flags were kept over upstream's
+	Metadata,
 }
 
 /// ShowInstall args
@@ -391,6 +1013,19 @@ pub(crate) struct FrCmdShowInstall
 	pub(crate) verbose: Vec<ShowInstallType>,
 	// XXX Don't seem to be able to talk clap into accepting "-v with no
 	// arg -> ::All", sigh.
+
+	/// In the add/update lists, show what each path will be.
+	///
+	/// Files get their new sha256 and (uncompressed) size, and updated
+	/// ones the hash they had before too; dirs get their mode, and
+	/// links their target.  Still one line per path, sorted, so it
+	/// diffs well between hosts.
+	#[arg(long, requires = "verbose")]
+	pub(crate) detail: bool,
+
+	/// Show whole hashes with --detail, instead of the first 16 digits.
+	#[arg(long, requires = "detail")]
+	pub(crate) full_hashes: bool,
 }
 
 /// ShowMerges args
@@ -405,6 +1040,33 @@ pub(crate) struct FrCmdShowMerges
 	/// system".
 	#[arg(short, long)]
 	pub(crate) upstream: bool,
+
+	/// Only show a summary of lines added/removed for each file.
+	#[arg(long)]
+	pub(crate) stat: bool,
+
+	/// Only show merges for matching paths (regex).
+	#[arg(short, long)]
+	pub(crate) path: Vec<regex_lite::Regex>,
+
+	/// Line prefixes treated as comments.
+	///
+	/// Files whose only changes are in whitespace or comment lines are
+	/// noted as such, since they're often not worth much review.
+	#[arg(long, value_delimiter = ',', default_values = ["#", ";"])]
+	pub(crate) comment_prefix: Vec<String>,
+
+	/// Write a report of /etc differences from the new release to FILE.
+	///
+	/// Instead of showing merges, this goes through everything under
+	/// MergeChanges and UpdateIfUnmodified, classifies it (merged,
+	/// conflict resolved or not, local kept, upstream taken, or just
+	/// metadata kept), and writes it out as JSON, with diffs against the
+	/// pristine new version.  Works any time there's a pending upgrade;
+	/// once install finishes it, the report it saved on the way out is
+	/// used instead.
+	#[arg(long, value_name = "FILE")]
+	pub(crate) etc_report: Option<PathBuf>,
 }
 
 /// ResolveMerges args
@@ -420,18 +1082,175 @@ pub(crate) struct FrCmdResolveMerges
 	pub(crate) exit: bool,
 }
 
+/// MergeFile args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdMergeFile
+{
+	/// The common ancestor; e.g., the old upstream version.
+	#[arg(long, value_name = "FILE")]
+	pub(crate) old: PathBuf,
+
+	/// Your locally modified version.
+	#[arg(long, value_name = "FILE")]
+	pub(crate) mine: PathBuf,
+
+	/// The new upstream version.
+	#[arg(long, value_name = "FILE")]
+	pub(crate) new: PathBuf,
+
+	/// Write the result here instead of stdout.
+	#[arg(short, long, value_name = "FILE")]
+	pub(crate) output: Option<PathBuf>,
+}
+
 /// DumpMetadata args
 #[derive(Debug)]
 #[derive(Parser)]
 pub(crate) struct FrCmdDumpMetadata
 {
 	/// Version to dump metadata for (e.g., 13.2-RELEASE)
-	#[arg(short, long)]
+	#[arg(short, long, value_parser = crate::info::version::parse_release_arg)]
 	pub(crate) version: crate::info::version::AVersion,
 
 	/// Directory to save the files into (must exist)
 	#[arg(short, long)]
 	pub(crate) dir: PathBuf,
+
+	/// Output format.
+	///
+	/// `raw` is the decompressed INDEX files as the server has them.
+	/// `json` and `csv` are parsed out, one entry per path, sorted by
+	/// path so they diff cleanly between versions.
+	#[arg(short, long, value_enum, default_value_t)]
+	pub(crate) format: DumpMetadataFormat,
+
+	/// Which metadata files to dump (can be specified multiple times).
+	#[arg(short, long, value_delimiter = ',', num_args = 1..,
+			default_values = ["all", "old", "new"])]
+	pub(crate) which: Vec<DumpMetadataWhich>,
+
+	/// Only dump entries for the given component[s] (e.g., `world`,
+	/// `kernel/generic`).  Ignored for `raw`.
+	#[arg(short, long)]
+	pub(crate) component: Vec<crate::components::Component>,
+}
+
+/// HashBench args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdHashBench
+{
+	/// Directory to hash everything under
+	pub(crate) dir: PathBuf,
+
+	/// How many times to go over it with each method
+	#[arg(short, long, default_value_t = 3)]
+	pub(crate) rounds: u32,
+}
+
+/// IoBench args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdIoBench
+{
+	/// Directory to write under (in a tempdir that gets cleaned up)
+	pub(crate) dir: PathBuf,
+
+	/// How many files to make each round
+	#[arg(short = 'n', long, default_value_t = 200)]
+	pub(crate) files: usize,
+
+	/// How big each one is, in KB
+	#[arg(short, long, default_value_t = 1024)]
+	pub(crate) size: usize,
+
+	/// Extra sleep with each write, to fake a slow disk
+	#[arg(long, value_name = "MS", default_value_t = 0)]
+	pub(crate) delay_ms: u64,
+
+	/// How many rounds to time each --jobs-io for
+	#[arg(short, long, default_value_t = 5)]
+	pub(crate) rounds: u32,
+
+	/// --jobs-io values to try (default -j, and half that)
+	#[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+	pub(crate) io: Vec<u32>,
+}
+
+/// Completions args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdCompletions
+{
+	/// Which shell to write it for
+	#[arg(short, long, value_enum)]
+	pub(crate) shell: CompletionShell,
+}
+
+/// Shells we can write completions for
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(clap::ValueEnum)]
+pub(crate) enum CompletionShell
+{
+	Sh,
+	Bash,
+	Zsh,
+	Fish,
+}
+
+/// __complete args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdComplete
+{
+	/// What to list
+	#[arg(value_enum)]
+	pub(crate) what: CompleteWhat,
+}
+
+/// What __complete knows how to list
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(clap::ValueEnum)]
+pub(crate) enum CompleteWhat
+{
+	/// Releases it'd make sense to upgrade to
+	ReleaseCandidates,
+}
+
+/// DumpMetadata output formats
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[derive(clap::ValueEnum)]
+#[derive(strum::Display, strum::AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum DumpMetadataFormat
+{
+	/// The INDEX files as they come
+	#[default]
+	Raw,
+
+	/// Parsed out into a JSON array
+	Json,
+
+	/// Parsed out into a CSV table
+	Csv,
+}
+
+/// DumpMetadata files
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(clap::ValueEnum)]
+#[derive(strum::Display, strum::AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub(crate) enum DumpMetadataWhich
+{
+	/// INDEX-ALL
+	All,
+
+	/// INDEX-OLD
+	Old,
+
+	/// INDEX-NEW
+	New,
 }
 
 /// Clean args
@@ -443,6 +1262,58 @@ pub(crate) struct FrCmdClean
 	/// a previous `fetch` or `upgrade`).
 	#[arg(short, long)]
 	pub(crate) pending: bool,
+
+	/// Discard a pending upgrade even though it's partly installed.
+	///
+	/// Once an upgrade's kernel (or world) is in, `--pending` won't
+	/// throw it away, since that leaves the system half upgraded with
+	/// nothing recording what was in flight.  Finishing it with
+	/// `install` is almost always better.
+	#[arg(long, requires = "pending")]
+	pub(crate) i_know_kernel_is_installed: bool,
+
+	/// Remove the copies of discarded pending updates that `--pending`
+	/// keeps in the state dir.
+	#[arg(long)]
+	pub(crate) discarded: bool,
+
+	/// Clean out files in the files dir that aren't needed for a pending
+	/// install (or all of them, if nothing's pending).
+	#[arg(short, long)]
+	pub(crate) files: bool,
+
+	/// Clean out tempfiles an install that died partway left behind, in
+	/// the dirs the pending (or last) install touched.  install does
+	/// this itself before starting, too.
+	#[arg(long)]
+	pub(crate) install_residue: bool,
+
+	/// Don't clean anything, just say what would go.
+	#[arg(short='n', long)]
+	pub(crate) dry_run: bool,
+}
+
+/// ExportPending args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdExportPending
+{
+	/// File to write the bundle to.
+	#[arg(short, long, value_name = "FILE")]
+	pub(crate) output: std::path::PathBuf,
+
+	/// Compress it with zstd.
+	#[arg(long)]
+	pub(crate) zstd: bool,
+}
+
+/// ImportPending args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdImportPending
+{
+	/// The bundle, from `export-pending`.
+	pub(crate) file: std::path::PathBuf,
 }
 
 /// CheckSys diff-ignore types
@@ -477,6 +1348,10 @@ pub(crate) enum CheckSysIgnore
 
 	/// Mismatched file types
 	Type,
+
+	/// Hashes of derived db's (pwd.db and the like), which never match
+	/// after being regenerated
+	Derived,
 }
 
 /// CheckSys args
@@ -500,6 +1375,139 @@ pub(crate) struct FrCmdCheckSys
 	/// Exclude paths (regex)
 	#[arg(short = 'x', long)]
 	pub(crate) exclude: Vec<regex_lite::Regex>,
+
+	/// Report paths ignored via the `IDSIgnorePaths` config.
+	///
+	/// Shows how many paths each pattern matched, and calls out patterns
+	/// that matched nothing.  With `full`, the full path lists are also
+	/// written to `ignored-paths.txt` in the workdir.
+	#[arg(long, value_name = "LEVEL", num_args = 0..=1,
+			default_missing_value = "counts")]
+	pub(crate) show_ignored: Option<ShowIgnored>,
+
+	/// Compare against release distribution sets instead of the server.
+	///
+	/// Reads base.txz, kernel.txz, etc. from the given dir (e.g., the
+	/// release media), and uses their contents as the expected system,
+	/// without talking to the update server at all.  This only knows the
+	/// RELEASE contents, so anything patched since will differ.
+	#[arg(long, value_name = "DIR")]
+	pub(crate) dist_dir: Option<std::path::PathBuf>,
+
+	/// Put differing paths back the way upstream has them.
+	///
+	/// After the comparison, offers to extract the upstream versions of
+	/// exactly the paths that differ, like `extract` would, but without
+	/// scanning or loading the metadata all over again.  Paths that are
+	/// the wrong type on the system (e.g., a directory where a file
+	/// should be) are each asked about separately, even with --yes.
+	#[arg(long)]
+	pub(crate) fix: bool,
+
+	/// With --fix, go ahead without asking.
+	#[arg(short, long, requires = "fix")]
+	pub(crate) yes: bool,
+
+	/// With --fix, just say what we'd do, don't actually do it.
+	#[arg(short='n', long, requires = "fix")]
+	pub(crate) dry_run: bool,
+
+	/// With --fix, also extract paths we couldn't check.
+	///
+	/// Like `extract --force`, this overwrites things that may well
+	/// already be right; here, that's files we couldn't read to compare
+	/// when not running as root.
+	#[arg(short, long, requires = "fix")]
+	pub(crate) force: bool,
+
+	/// With --fix, record the ownership of what's extracted.
+	///
+	/// As with `extract --ownership-manifest`.
+	#[arg(long, value_name = "FILE", requires = "fix")]
+	pub(crate) ownership_manifest: Option<std::path::PathBuf>,
+}
+
+/// Progress args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdProgress
+{
+	/// Output the raw status as JSON.
+	#[arg(long)]
+	pub(crate) json: bool,
+}
+
+/// ConfigCheck args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdConfigCheck
+{
+	/// Also look up ServerName, to be sure it finds some servers.
+	#[arg(long)]
+	pub(crate) network: bool,
+}
+
+/// Doctor args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdDoctor
+{
+	/// Fix everything fixable, without asking.
+	#[arg(short, long, conflicts_with = "dry_run")]
+	pub(crate) yes: bool,
+
+	/// Just say what we'd fix.
+	#[arg(short='n', long)]
+	pub(crate) dry_run: bool,
+}
+
+/// FixLinks args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdFixLinks
+{
+	/// Actually fix things, rather than just reporting.
+	#[arg(long)]
+	pub(crate) apply: bool,
+}
+
+/// Why args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdWhy
+{
+	/// Explain it the way an upgrade would go, where MergeChanges merges
+	/// locally modified files, rather than fetch.  The running version's
+	/// metadata stands in for the target release's.
+	#[arg(long)]
+	pub(crate) upgrade: bool,
+
+	/// The path to explain.
+	pub(crate) path: std::path::PathBuf,
+}
+
+/// PatchContents args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdPatchContents
+{
+	/// Release to look at (e.g., 14.2-RELEASE).  Defaults to what
+	/// we're running.
+	#[arg(short, long, value_parser = crate::info::version::parse_release_arg)]
+	pub(crate) release: Option<crate::info::version::AVersion>,
+
+	/// Show what this patch level changed from the one before.  Defaults
+	/// to the server's latest.
+	#[arg(short, long, conflicts_with_all = ["from", "to"])]
+	pub(crate) patch: Option<u32>,
+
+	/// Compare from this patch level (default: the one before --to).
+	#[arg(long)]
+	pub(crate) from: Option<u32>,
+
+	/// Compare to this patch level (default: the server's latest).
+	#[arg(long)]
+	pub(crate) to: Option<u32>,
 }
 
 /// CheckFetch args
@@ -523,6 +1531,21 @@ pub(crate) struct FrCmdCheckFetch
 	pub(crate) cron: bool,
 }
 
+/// Audit args
+#[derive(Debug)]
+#[derive(Parser)]
+pub(crate) struct FrCmdAudit
+{
+	/// Output the report as JSON.
+	#[arg(long)]
+	pub(crate) json: bool,
+
+	/// Don't touch the network; verify against the signed tag saved
+	/// when the metadata was fetched.
+	#[arg(long)]
+	pub(crate) offline: bool,
+}
+
 /// Extract args
 #[derive(Debug)]
 #[derive(Parser)]
@@ -554,6 +1577,60 @@ pub(crate) struct FrCmdExtract
 	#[arg(short, long)]
 	pub(crate) force: bool,
 
+	/// Always load the metadata from the server.
+	///
+	/// By default, if we already have the metadata for the running
+	/// version downloaded, we use it without asking the server.
+	#[arg(long)]
+	pub(crate) refresh_metadata: bool,
+
+	/// Extract from release distribution sets instead of the server.
+	///
+	/// Reads base.txz, kernel.txz, etc. from the given dir (e.g., the
+	/// release media) for both the metadata and file contents, without
+	/// talking to the update server at all.  You get what the RELEASE
+	/// shipped, without any patches since.
+	#[arg(long, value_name = "DIR", conflicts_with = "refresh_metadata")]
+	pub(crate) dist_dir: Option<std::path::PathBuf>,
+
+	/// Extract paths as they were at an earlier patch level.
+	///
+	/// Uses the metadata for -pN of the running release instead of the
+	/// server's latest (0 is the release itself).  The server only
+	/// offers a signed tag for its latest, so anything older needs the
+	/// one an earlier `fetch` or `upgrade` here saved, and the server
+	/// has to still carry that patch level's index and files.
+	///
+	/// With `--dry-run`, this shows what'd go back.  Without it, this is
+	/// an emergency downgrade: the older files really get installed,
+	/// undoing whatever the later patches fixed (security fixes
+	/// included), and nothing keeps track of them being older; to
+	/// everything else they're just locally modified.  Add `--force` to put
+	/// them in place even where they look the same.  Only do this for a
+	/// file a patch broke, and move forward again as soon as you can.
+	#[arg(long, value_name = "N", conflicts_with = "dist_dir")]
+	pub(crate) at_patch: Option<u32>,
+
+	/// Write the intended ownership of extracted files to an mtree file.
+	///
+	/// As with `install --ownership-manifest`; an existing file is added
+	/// to.
+	#[arg(long, value_name = "FILE")]
+	pub(crate) ownership_manifest: Option<std::path::PathBuf>,
+
+	/// Most paths to extract without confirmation.
+	///
+	/// If more than this many paths match, we show how many and a sample
+	/// of them, and stop before fetching or writing anything, unless you
+	/// confirm (on a tty) or give `--yes-really`.  0 means no limit.
+	/// Doesn't apply to `--dry-run`.
+	#[arg(long, value_name = "N", default_value_t = 500)]
+	pub(crate) limit: usize,
+
+	/// Go ahead even if more than `--limit` paths match.
+	#[arg(long)]
+	pub(crate) yes_really: bool,
+
 	/// Some number of path[s] to work with.
 	///
 	/// If `-x` is given, these are treated as regular expressions.
@@ -580,14 +1657,31 @@ impl std::fmt::Display for FrCmds
 			Self::Install{..} => f.write_str("install"),
 			Self::Clean{..}   => f.write_str("show-install"),
 			Self::Extract{..} => f.write_str("extract"),
+			Self::FixLinks{..} => f.write_str("fix-links"),
 			Self::CheckSys{..}    => f.write_str("check-sys"),
 			Self::CheckFetch{..}  => f.write_str("check-fetch"),
+			Self::Audit{..}       => f.write_str("audit"),
+			Self::Why{..}         => f.write_str("why"),
+			Self::PatchContents{..} => f.write_str("patch-contents"),
 			Self::ShowMerges{..}  => f.write_str("show-merges"),
 			Self::ShowInstall{..} => f.write_str("show-install"),
+			Self::Progress{..}    => f.write_str("progress"),
 			Self::ResolveMerges{..} => f.write_str("resolve-merges"),
+			Self::ExportPending{..} => f.write_str("export-pending"),
+			Self::ImportPending{..} => f.write_str("import-pending"),
+			Self::MergeFile{..}   => f.write_str("merge-file"),
+			Self::ConfigCheck{..} => f.write_str("config-check"),
+			Self::Doctor{..}      => f.write_str("doctor"),
+			Self::Completions{..} => f.write_str("completions"),
+			Self::Capabilities    => f.write_str("capabilities"),
 
 			// More dev/debug-ish stuff
 			Self::DumpMetadata{..} => f.write_str("dump-metadata"),
+			Self::HashBench{..}    => f.write_str("hash-bench"),
+			Self::IoBench{..}      => f.write_str("io-bench"),
+			Self::Selftest         => f.write_str("selftest"),
+			Self::PrivsepWorker    => f.write_str("privsep-worker"),
+			Self::Complete{..}     => f.write_str("__complete"),
 
 			// Shouldn't really be possible
 			#[cfg(test)]
@@ -620,8 +1714,20 @@ impl FrArgs
 		{ ret.push(format!("--jobs-cpu={v}")); }
 		if let Some(v) = &self.jobs_net
 		{ ret.push(format!("--jobs-net={v}")); }
+		if let Some(v) = &self.jobs_io
+		{ ret.push(format!("--jobs-io={v}")); }
 		if let Some(v) = &self.servername
 		{ ret.push(format!("--server={v}")); }
+		if self.no_server_cache
+		{ ret.push("--no-server-cache".to_string()); }
+		if let Some(v) = &self.prefer_server
+		{ ret.push(format!("--prefer-server={v}")); }
+		if self.fallback_dns
+		{ ret.push("--fallback-dns".to_string()); }
+		if self.timings
+		{ ret.push("--timings".to_string()); }
+		if self.insecure_workdir
+		{ ret.push("--insecure-workdir".to_string()); }
 
 		// There are paths, so assume they can str-ify like we did with
 		// config.
@@ -629,6 +1735,10 @@ impl FrArgs
 		{ ret.push(format!("--basedir={}", p.to_str().unwrap())); }
 		if let Some(p) = &self.workdir
 		{ ret.push(format!("--workdir={}", p.to_str().unwrap())); }
+		if let Some(p) = &self.record_session
+		{ ret.push(format!("--record-session={}", p.to_str().unwrap())); }
+		if let Some(p) = &self.replay_session
+		{ ret.push(format!("--replay-session={}", p.to_str().unwrap())); }
 
 		ret
 	}
@@ -641,7 +1751,8 @@ pub fn parse() -> FrArgs
 	let ret = FrArgs::parse();
 
 	// Setup the parallelism bits from the parse
-	crate::core::pool::init_jobs(&ret.jobs_net, &ret.jobs_cpu);
+	crate::core::pool::init_jobs(&ret.jobs_net, &ret.jobs_cpu,
+			&ret.jobs_io);
 
 	ret
 }
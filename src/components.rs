@@ -8,8 +8,8 @@
 
 /// Various component-related structs
 mod structs;
-pub(crate) use structs::Component;
-pub(crate) use structs::{BaseComponent, BaseSubComponent};
+pub use structs::Component;
+pub use structs::{BaseComponent, BaseSubComponent};
 
 
 
@@ -21,7 +21,17 @@ impl Component
 	/// that if you specify just a component, that covers all the
 	/// subcomponents, but if you specify a sub, it matches only that one.
 	/// So give us a method to do that check.
-	pub(crate) fn contains(&self, other: &Self) -> bool
+	///
+	/// ```
+	/// use freebsd_rustdate::components::Component;
+	///
+	/// let world: Component = "world".parse().unwrap();
+	/// let lib32: Component = "world/lib32".parse().unwrap();
+	/// assert!(world.contains(&lib32));
+	/// assert!(lib32.contains(&lib32));
+	/// assert!(!lib32.contains(&world));
+	/// ```
+	pub fn contains(&self, other: &Self) -> bool
 	{
 		if self.comp != other.comp { return false }
 		if self.subcomp.is_none()  { return true  }
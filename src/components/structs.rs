@@ -2,10 +2,14 @@
 
 
 /// Top-level component
+///
+/// Parses from, and displays as, the names in the config ("kernel",
+/// "src", "world").
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(strum::Display, strum::EnumString, strum::AsRefStr)]
 // #[derive(strum::EnumIs)]
-pub(crate) enum BaseComponent
+#[non_exhaustive]
+pub enum BaseComponent
 {
 	#[strum(serialize = "kernel")]
 	Kernel,
@@ -19,10 +23,13 @@ pub(crate) enum BaseComponent
 /// A subcomponent.  Not all combinations of this and Component make
 /// sense, but I'm not gonna try overmodelling too much until I have a
 /// good reason to...
+///
+/// The list will probably grow, as upstream adds them.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(strum::Display, strum::EnumString, strum::AsRefStr)]
 // #[derive(strum::EnumIs)]
-pub(crate) enum BaseSubComponent
+#[non_exhaustive]
+pub enum BaseSubComponent
 {
 	// kernel choices
 	#[strum(serialize = "generic")]
@@ -58,8 +65,20 @@ pub(crate) enum BaseSubComponent
 
 /// A component entry of some sort, including both the Component and the
 /// Subcomponent
+///
+/// These look like "world" or "world/lib32" in the config.
+///
+/// ```
+/// use freebsd_rustdate::components::{Component, BaseComponent};
+///
+/// let c: Component = "world/lib32".parse().unwrap();
+/// assert_eq!(c.comp(), BaseComponent::World);
+/// assert_eq!(c.subcomp().unwrap().to_string(), "lib32");
+/// assert_eq!(c.to_string(), "world/lib32");
+/// assert!("world/nope".parse::<Component>().is_err());
+/// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) struct Component
+pub struct Component
 {
 	/// The component
 	pub(crate) comp: BaseComponent,
@@ -68,6 +87,15 @@ pub(crate) struct Component
 	pub(crate) subcomp: Option<BaseSubComponent>,
 }
 
+impl Component
+{
+	/// The component
+	pub fn comp(&self) -> BaseComponent { self.comp }
+
+	/// The subcomponent, if there is one
+	pub fn subcomp(&self) -> Option<BaseSubComponent> { self.subcomp }
+}
+
 impl std::fmt::Display for Component
 {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error>
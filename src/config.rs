@@ -35,6 +35,11 @@ pub struct Config
 	/// Paths to Ignore in IDS mode.  For us, that's check-sys.
 	pub(crate) ids_ignore_paths: Vec<Regex>,
 
+	/// Only deal with paths matching these, after IgnorePaths.  This
+	/// isn't a config file setting; it comes from `--include-only` on
+	/// fetch/upgrade.
+	pub(crate) include_only: Vec<Regex>,
+
 	/// Paths to update only if the user hasn't changed them from our
 	/// best guess at the upstream contents.
 	pub(crate) update_if_unmodified: Vec<Regex>,
@@ -42,13 +47,95 @@ pub struct Config
 	/// Merge changes to matching files
 	pub(crate) merge_changes: Vec<Regex>,
 
+	/// When a merge conflicts, retry with trailing whitespace and final
+	/// newlines normalized (`MergeNormalize whitespace`, vs. `none`).
+	#[derivative(Default(value="true"))]
+	pub(crate) merge_normalize: bool,
+
 	/// Keep modifications to metadata (perms, owner, flags, etc)
 	#[derivative(Default(value="true"))]
 	pub(crate) keep_modified_metadata: bool,
 
+	/// Remove files upstream doesn't have anymore (`AllowDelete`)
+	#[derivative(Default(value="true"))]
+	pub(crate) allow_delete: bool,
+
+	/// Update src even when /usr/src is a git checkout, if src is
+	/// explicitly in Components.
+	pub(crate) manage_git_src: bool,
+
+	/// Take upstream metadata for paths outside where base lives (e.g.
+	/// /usr/local), for self-built servers that do that on purpose.
+	pub(crate) allow_unexpected_prefixes: bool,
+
 	/// Notification email address for `cron` command.
 	pub(crate) mailto: Option<String>,
 
+	/// Up to how long (in seconds) `cron` sleeps before fetching, to
+	/// spread out a bunch of machines all kicked off at the same time.
+	#[derivative(Default(value="3600"))]
+	pub(crate) cron_jitter: u64,
+
+	/// How long (in seconds) `cron` waits for a previous run that's still
+	/// going to finish, rather than running over the top of it.  0 means
+	/// give up right away.
+	pub(crate) cron_lock_wait: u64,
+
+	/// How long (in seconds) to trust the cached last-good server before
+	/// doing full server discovery again.  0 disables the cache.
+	#[derivative(Default(value="3600"))]
+	pub(crate) server_cache_ttl: u64,
+
+	/// A host to try ahead of everything else, when it's working (like
+	/// a mirror that's known to be good and close by).  Unlike
+	/// ServerName, the rest are still there if it isn't.
+	pub(crate) prefer_server: Option<String>,
+
+	/// Keep decompressed metadata files around between runs (in
+	/// `workdir/cache/`).  Ones unused for a month get cleared out.
+	pub(crate) metadata_cache: bool,
+
+	/// Rough install speed (in MB/s of decompressed files), for
+	/// guessing how long an upgrade's install steps will take.
+	#[derivative(Default(value="50"))]
+	pub(crate) install_mb_per_sec: u32,
+
+	/// How many of the most recent finished installs keep their full
+	/// path lists around for show-install; the rest only keep counts.
+	#[derivative(Default(value="3"))]
+	pub(crate) install_history_detail: u32,
+
+	/// Cap on the files dir (where nearly all of workdir's space goes),
+	/// in bytes; least recently used files get evicted to stay under
+	/// it.
+	pub(crate) max_workdir_size: Option<u64>,
+
+	/// Refuse a fetch/upgrade that'd remove more than this percent of
+	/// the paths currently tracked, without `--allow-mass-removal`.
+	#[derivative(Default(value="20"))]
+	pub(crate) max_removal_percent: u8,
+
+	/// Services not to automatically restart after installing
+	pub(crate) no_restart_services: Vec<String>,
+
+	/// An extattr (e.g. `user.managed_by`) marking files something else
+	/// manages; fetch/upgrade note which files they'd replace have it.
+	pub(crate) annotate_xattr: Option<String>,
+
+	/// Refuse to install over AnnotateXattr-marked files without
+	/// `install --touch-managed`.
+	pub(crate) protect_annotated: bool,
+
+	/// How to run post-install helpers (kldxref, pwd_mkdb, etc) when
+	/// the basedir isn't /.
+	pub(crate) install_helpers: HelperMode,
+
+	/// Who to fetch and check files as, when we're running as root.
+	pub(crate) fetch_user: FetchUser,
+
+	/// What mtime installed files get.
+	pub(crate) preserve_timestamps: Timestamps,
+
 
 	/// What dir we're working from
 	#[derivative(Default(value="\"/\".into()"))]
@@ -74,42 +161,233 @@ impl Config
 {
 	// Some fields kept hidden so we can make sure they don't change from
 	// under us, so we can cache derived bits.
-	pub(crate) fn basedir(&self) -> &std::path::Path { &self.basedir }
-	pub(crate) fn workdir(&self) -> &std::path::Path { &self.workdir }
+	pub fn basedir(&self) -> &std::path::Path { &self.basedir }
+	pub fn workdir(&self) -> &std::path::Path { &self.workdir }
+
+	/// Parse a config file's contents, the same way we would.  Anything
+	/// that'd keep us from using it comes back as
+	/// ConfigErr::Problems; warnings don't.
+	///
+	/// ```
+	/// use freebsd_rustdate::config::{Config, ConfigErr, ProblemKind};
+	///
+	/// let conf = Config::load(b"ServerName update.example.org\n").unwrap();
+	/// assert_eq!(conf.servername(), "update.example.org");
+	///
+	/// let err = Config::load(b"MailTo root\nCreateBootEnv maybe\n")
+	///         .unwrap_err();
+	/// match err {
+	///     ConfigErr::Problems(ps) => {
+	///         assert_eq!(ps[0].line(), Some(2));
+	///         assert_eq!(ps[0].kind(), ProblemKind::Syntax);
+	///     },
+	///     e => panic!("{e}"),
+	/// }
+	/// ```
+	pub fn load(conf: &[u8]) -> Result<Self, ConfigErr> { load(conf) }
+
+	/// Trusted keyprint
+	pub fn keyprint(&self) -> &str { &self.keyprint }
+
+	/// Server to look for updates on
+	pub fn servername(&self) -> &str { &self.servername }
+
+	/// Components to update
+	pub fn components(&self) -> &HashSet<Component> { &self.components }
 
 
 	/// "Finalize" components.  This is a kinda one-off hack to remove
 	/// the src component if the system doesn't seem to have src
 	/// installed.  And it doesn't seem to consider "src/src" if that's
 	/// given explicitly either.  A little hinky, but hey...
-	pub(crate) fn finalize_components(&mut self)
+	///
+	/// Also pulls src (all of it) if /usr/src is a git checkout, unless
+	/// ManageGitSrc says otherwise; that returns a notice to show.
+	/// Since components are filtered when the metadata gets parsed, src
+	/// then never gets scanned or hashed at all.
+	pub(crate) fn finalize_components(&mut self) -> Option<String>
 	{
+		use crate::components::BaseComponent as BC;
+
+		// Somebody's managing src with git; stay out of their way,
+		// unless they asked for it explicitly.
+		let gitdir = self.basedir.join("usr/src/.git");
+		if gitdir.exists()
+		{
+			let has_src = self.components.iter().any(|c| c.comp == BC::Src);
+			if has_src && self.manage_git_src { return None; }
+			self.components.retain(|c| c.comp != BC::Src);
+			return has_src.then(|| format!("{} is a git checkout; leaving \
+					the src component alone (set ManageGitSrc to update it \
+					anyway).", gitdir.display()));
+		}
+
 		// So if src _is_ apparently there, there's nothing to do
 		let checkfile = self.basedir.join("usr/src/COPYRIGHT");
-		if checkfile.is_file() { return; }
+		if checkfile.is_file() { return None; }
 		let src_comp = "src".parse().unwrap();
 		self.components.retain(|c| c != &src_comp);
+		None
 	}
+
+
+	/// Add one-off path filters from the command line (fetch/upgrade's
+	/// `--exclude` and `--include-only`).  Returns a note about them to
+	/// stash in the manifest, if there were any.
+	pub(crate) fn add_cli_filters(&mut self, exclude: &[Regex],
+			include_only: &[Regex]) -> Option<String>
+	{
+		self.ignore_paths.extend(exclude.iter().cloned());
+		self.include_only.extend(include_only.iter().cloned());
+
+		let args: Vec<_> = exclude.iter().map(|r| format!("--exclude {r}"))
+				.chain(include_only.iter()
+					.map(|r| format!("--include-only {r}")))
+				.collect();
+		match args.len() {
+			0 => None,
+			_ => Some(format!("computed with nonstandard path filters: {}",
+					args.join(" "))),
+		}
+	}
+}
+
+
+/// How post-install helpers get run against the basedir
+/// (`InstallHelpers`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum HelperMode
+{
+	/// chroot in and run the basedir's own if we can, else the host's
+	#[default]
+	Auto,
+
+	/// Only ever the basedir's own
+	Chroot,
+
+	/// Only ever the host's
+	Host,
+
+	/// Don't; just say what to run
+	Skip,
+}
+
+
+/// Who fetching and hash checking runs as, when we're root
+/// (`FetchUser`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) enum FetchUser
+{
+	/// _fbsdupdate if it exists, else nobody
+	#[default]
+	Auto,
+
+	/// This one
+	Named(String),
+
+	/// Don't drop privileges at all
+	Off,
+}
+
+
+/// What mtime installed files and symlinks end up with
+/// (`PreserveTimestamps`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Timestamps
+{
+	/// Whenever we installed them
+	#[default]
+	Now,
+
+	/// One per patch level, worked out from the release; x-ref
+	/// core::install::upstream_mtime()
+	Upstream,
 }
 
 
 /// Problems loading config
 #[derive(Debug)]
 #[derive(Error)]
+#[non_exhaustive]
 pub enum ConfigErr
 {
 	/// File I/O error of some sort
 	#[error("Config file I/O error: {0}")]
 	IO(#[from] std::io::Error),
 
-	/// Syntax error in the config file
-	#[error("Config file syntax error: {0}")]
-	Syntax(String),
+	/// Things wrong with the contents of the config file
+	#[error("Config file errors:\n  {}", problem_list(.0))]
+	Problems(Vec<ConfigProblem>),
+}
+
+
+/// Something wrong (or just suspicious) in a config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem
+{
+	/// Which line it's on (1-based), if it's about a particular line
+	pub(crate) line: Option<usize>,
+
+	/// What sort of problem
+	pub(crate) kind: ProblemKind,
+
+	/// What's wrong
+	pub(crate) msg: String,
+}
+
+/// Sorts of ConfigProblem's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProblemKind
+{
+	/// Can't parse it
+	Syntax,
 
 	/// Explicitly unsupported config params, because I have limited
 	/// scope.
-	#[error("Unsupported config: {0}")]
-	Unsupported(String),
+	Unsupported,
+
+	/// Not fatal, but probably not what you meant
+	Warning,
+}
+
+impl ConfigProblem
+{
+	fn new(line: usize, kind: ProblemKind, msg: String) -> Self
+	{ Self { line: Some(line), kind, msg } }
+
+	/// Does this keep us from using the config?
+	pub fn is_error(&self) -> bool
+	{ self.kind != ProblemKind::Warning }
+
+	/// Which line it's on (1-based), if it's about a particular line
+	pub fn line(&self) -> Option<usize> { self.line }
+
+	/// What sort of problem
+	pub fn kind(&self) -> ProblemKind { self.kind }
+
+	/// What's wrong
+	pub fn msg(&self) -> &str { &self.msg }
+}
+
+impl std::fmt::Display for ConfigProblem
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+	{
+		if let Some(l) = self.line { write!(f, "line {l}: ")?; }
+		match self.kind {
+			ProblemKind::Syntax      => (),
+			ProblemKind::Unsupported => f.write_str("Unsupported config: ")?,
+			ProblemKind::Warning     => f.write_str("warning: ")?,
+		}
+		f.write_str(&self.msg)
+	}
+}
+
+fn problem_list(probs: &[ConfigProblem]) -> String
+{
+	let strs: Vec<_> = probs.iter().map(|p| p.to_string()).collect();
+	strs.join("\n  ")
 }
 
 
@@ -129,9 +407,17 @@ pub(crate) fn load_config(conf: &[u8], clargs: &crate::command::FrArgs)
 		-> Result<Config, ConfigErr>
 {
 	// Load from the file
-	let mut conf = load(&conf)?;
+	let conf = load(&conf)?;
 
 	// And override from CL args as appropriate
+	Ok(with_args(conf, clargs))
+}
+
+
+/// Apply the overrides from command-line args to a config.
+pub(crate) fn with_args(mut conf: Config, clargs: &crate::command::FrArgs)
+		-> Config
+{
 	macro_rules! or {
 		( $fld:ident ) => {
 			conf.$fld = match &clargs.$fld {
@@ -149,9 +435,12 @@ pub(crate) fn load_config(conf: &[u8], clargs: &crate::command::FrArgs)
 	or!(basedir);
 	or!(workdir);
 	or!(servername);
+	conf.prefer_server = clargs.prefer_server.clone().or(conf.prefer_server);
 
+	if clargs.no_server_cache { conf.server_cache_ttl = 0; }
 
-	Ok(conf)
+
+	conf
 }
 
 
@@ -163,13 +452,76 @@ pub(crate) fn load_config(conf: &[u8], clargs: &crate::command::FrArgs)
 // }
 
 
-/// Parse out a string of the config
+/// Parse out a string of the config, erroring if there's anything wrong
+/// with it.
 fn load(conf: &[u8]) -> Result<Config, ConfigErr>
+{
+	let (config, probs) = parse(conf);
+	let errs: Vec<_> = probs.into_iter().filter(|p| p.is_error()).collect();
+	match errs.len() {
+		0 => Ok(config),
+		_ => Err(ConfigErr::Problems(errs)),
+	}
+}
+
+
+/// Params we know about, for spotting typos.  That's what we handle,
+/// plus what freebsd-update.sh does that we quietly don't.
+const KNOWN_PARAMS: &[&str] = &["KeyPrint", "ServerName", "Components",
+		"IgnorePaths", "IDSIgnorePaths", "UpdateIfUnmodified",
+		"MergeChanges", "MergeNormalize", "BaseDir", "WorkDir", "CreateBootEnv", "BootEnvRoot",
+		"KeepModifiedMetadata", "ManageGitSrc", "AllowUnexpectedPrefixes", "MailTo", "MetadataCache", "ServerCacheTTL", "PreferServer",
+		"InstallMBPerSec", "MaxRemovalPercent", "AnnotateXattr", "ProtectAnnotated", "NoRestartServices", "CronJitter", "CronLockWait", "InstallHelpers", "FetchUser",
+		"PreserveTimestamps", "MaxWorkdirSize", "InstallHistoryDetail",
+		"AllowAdd", "AllowDelete", "StrictComponents", "BackupKernel",
+		"BackupKernelDir", "BackupKernelSymbolFiles"];
+
+/// The longest `cron` will sleep before fetching; 4 hours.
+pub(crate) const CRON_JITTER_MAX: u64 = 4 * 60 * 60;
+
+/// Parse a size, in bytes, with an optional K/M/G/T (binary) suffix;
+/// e.g., MaxWorkdirSize's "1500M".
+pub(crate) fn parse_size(s: &str) -> Result<u64, String>
+{
+	let s = s.trim();
+	let s = s.strip_suffix(['B', 'b']).unwrap_or(s);
+	let (num, shift) = match s.char_indices().last() {
+		Some((i, 'K' | 'k')) => (&s[..i], 10),
+		Some((i, 'M' | 'm')) => (&s[..i], 20),
+		Some((i, 'G' | 'g')) => (&s[..i], 30),
+		Some((i, 'T' | 't')) => (&s[..i], 40),
+		_ => (s, 0),
+	};
+	let num: u64 = num.parse().map_err(|e| format!("{e}"))?;
+	num.checked_mul(1 << shift).ok_or_else(|| "too big".to_string())
+}
+
+/// Parse a cron jitter window, in seconds.  Shared by the CronJitter
+/// config and `cron --jitter`.
+pub(crate) fn parse_cron_jitter(s: &str) -> Result<u64, String>
+{
+	let secs: u64 = s.trim().parse().map_err(|e| format!("{e}"))?;
+	match secs > CRON_JITTER_MAX {
+		true  => Err(format!("more than {CRON_JITTER_MAX} seconds")),
+		false => Ok(secs),
+	}
+}
+
+
+/// Parse out a string of the config, collecting up everything wrong with
+/// it rather than stopping at the first thing.
+pub(crate) fn parse(conf: &[u8]) -> (Config, Vec<ConfigProblem>)
 {
 	let mut config = Config::default();
+	let mut probs = Vec::new();
 
-	for inline in conf.split(|c| *c == b'\n')
+	for (lnum, inline) in conf.split(|c| *c == b'\n').enumerate()
 	{
+		let lnum = lnum + 1;
+		let mut prob = |kind, msg| probs.push(ConfigProblem::new(lnum, kind,
+				msg));
+		use ProblemKind as PK;
+
 		// Discard any parts past a comment
 		let line = match inline.splitn(2, |c| *c == b'#').next() {
 			Some(l) => l,
@@ -183,15 +535,20 @@ fn load(conf: &[u8]) -> Result<Config, ConfigErr>
 			let par = it.next();
 			let val = it.next();
 			match (par, val) {
-				(Some(p), Some(v)) => [p, v],
+				(Some(p), Some(v)) if p.len() > 0 => [p, v],
+				(Some(p), None) if !p.trim_ascii().is_empty() => {
+					let p = String::from_utf8_lossy(p);
+					prob(PK::Warning, format!("no value given for {p}"));
+					continue;
+				},
 				(_, _) => continue,
 			}
 		};
 
 		// Some of the [u8] -> X conversions we use
-		let stringify = |bytes, ewhat| -> Result<String, ConfigErr> {
+		let stringify = |bytes, ewhat| -> Result<String, String> {
 			Ok(std::str::from_utf8(bytes).map_err(|e| {
-				ConfigErr::Syntax(format!("Error parsing {ewhat}: {e}"))
+				format!("Error parsing {ewhat}: {e}")
 			})?.into())
 		};
 		let pathify = |bytes: &[u8]| -> PathBuf {
@@ -201,134 +558,278 @@ fn load(conf: &[u8]) -> Result<Config, ConfigErr>
 			let npath = PathBuf::from(pstr);
 			npath
 		};
-		let regexify = |bytes: &[u8], ewhat| -> Result<Regex, ConfigErr> {
+		let regexify = |bytes: &[u8], ewhat| -> Result<Regex, String> {
 			// Regex reallys wants str, so we convert.  Also, this is
 			// only used for IgnorePaths, which is documented as being
 			// anchored to start.
 			let str = std::str::from_utf8(bytes).map_err(|e| {
-				ConfigErr::Syntax(format!("Error stringifying {ewhat}: {e}"))
+				format!("Error stringifying {ewhat}: {e}")
 			})?;
 			let str = format!("^{str}");
 			let re = Regex::new(&str).map_err(|e| {
-				ConfigErr::Syntax(format!("Error building regex from {ewhat}: {e}"))
+				format!("Error building regex from {ewhat}: {e}")
 			})?;
 			Ok(re)
 		};
-		let boolify = |bytes: &[u8]| -> Option<bool> {
+		let boolify = |bytes: &[u8], ewhat: &str| -> Result<bool, String> {
 			// sh script allows [Yy][Ee][Ss] etc.  I don't wanna bother
 			// unless I must
-			Some(match bytes {
-				b"yes" => true,
-				b"no"  => false,
-				_      => None?,
-			})
+			match bytes {
+				b"yes" => Ok(true),
+				b"no"  => Ok(false),
+				_      => Err(format!("Bad {ewhat} value {}",
+						String::from_utf8_lossy(bytes))),
+			}
 		};
 
-		// Now let's see what params and vals we're messing with
-		match par
+		// Space-separated lists of things
+		let words = || val.split(|c| *c == b' ').filter(|w| w.len() > 0);
+
+		// Now let's see what params and vals we're messing with.  Any
+		// problem comes out as an Err(msg) and gets noted below.
+		let res: Result<(), String> = (|| { match par
 		{
 			b"KeyPrint" => config.keyprint = stringify(val, "KeyPrint")?,
 			b"ServerName" => config.servername = stringify(val, "ServerName")?,
 			b"Components" => {
-				for comp in val.split(|c| *c == b' ')
+				let mut errs = Vec::new();
+				for comp in words()
 				{
-					if comp.len() == 0 { continue }
-					let cstr = stringify(comp, "Component")?;
-					let comp: Component = cstr.parse()
-							.map_err(|e| ConfigErr::Syntax(e))?;
-					config.components.insert(comp);
+					let comp = stringify(comp, "Component")
+							.and_then(|c| c.parse::<Component>());
+					match comp {
+						Ok(c) => { config.components.insert(c); },
+						Err(e) => errs.push(e),
+					}
 				}
+				if errs.len() > 0 { return Err(errs.join("; ")); }
 			},
-			b"IgnorePaths" => {
-				for path in val.split(|c| *c == b' ')
+			b"IgnorePaths" | b"IDSIgnorePaths" | b"UpdateIfUnmodified"
+					| b"MergeChanges" => {
+				let (ewhat, list) = match par {
+					b"IgnorePaths" => ("IgnorePaths", &mut config.ignore_paths),
+					b"IDSIgnorePaths" => ("IDSIgnorePaths",
+							&mut config.ids_ignore_paths),
+					b"UpdateIfUnmodified" => ("UpdateIfUnmodified",
+							&mut config.update_if_unmodified),
+					_ => ("MergeChanges", &mut config.merge_changes),
+				};
+				let mut errs = Vec::new();
+				for path in words()
 				{
-					if path.len() == 0 { continue }
 					// x-ref note in regexify()
-					config.ignore_paths.push(regexify(path, "IgnorePaths")?);
+					match regexify(path, ewhat) {
+						Ok(re) => list.push(re),
+						Err(e) => errs.push(e),
+					}
 				}
+				if errs.len() > 0 { return Err(errs.join("; ")); }
 			},
-			b"IDSIgnorePaths" => {
-				for path in val.split(|c| *c == b' ')
-				{
-					if path.len() == 0 { continue }
-					// x-ref note in regexify()
-					config.ids_ignore_paths.push(regexify(path, "IgnorePaths")?);
-				}
+			b"BaseDir" => {
+				if val.len() > 0 { config.basedir = pathify(val); }
 			},
-			b"UpdateIfUnmodified" => {
-				for path in val.split(|c| *c == b' ')
+			b"WorkDir" => {
+				if val.len() > 0 { config.workdir = pathify(val); }
+			},
+			b"CreateBootEnv" => {
+				config.create_boot_env = boolify(val, "CreateBootEnv")?;
+			},
+			b"BootEnvRoot" => {
+				if val.len() > 0
 				{
-					if path.len() == 0 { continue }
-					// x-ref note in regexify()
-					config.update_if_unmodified.push(
-							regexify(path, "UpdateIfUnmodified")?);
+					config.boot_env_root = Some(stringify(val, "BootEnvRoot")?);
+					prob(PK::Warning, "BootEnvRoot doesn't do anything"
+							.to_string());
 				}
 			},
-			b"MergeChanges" => {
-				for path in val.split(|c| *c == b' ')
+			b"MergeNormalize" => {
+				config.merge_normalize = match val {
+					b"whitespace" => true,
+					b"none"       => false,
+					_ => return Err(format!("Bad MergeNormalize value {}",
+							String::from_utf8_lossy(val))),
+				};
+			},
+			b"KeepModifiedMetadata" => {
+				config.keep_modified_metadata = boolify(val,
+						"KeepModifiedMetadata")?;
+			},
+			b"ManageGitSrc" => {
+				config.manage_git_src = boolify(val, "ManageGitSrc")?;
+			},
+			b"AllowUnexpectedPrefixes" => {
+				config.allow_unexpected_prefixes = boolify(val,
+						"AllowUnexpectedPrefixes")?;
+			},
+			b"AnnotateXattr" => {
+				let an = stringify(val, "AnnotateXattr")?;
+				if crate::util::xattr::AttrName::parse(&an).is_none()
 				{
-					if path.len() == 0 { continue }
-					config.merge_changes.push(regexify(path, "MergeChanges")?);
+					return Err(format!("Bad AnnotateXattr value {an} (want \
+							e.g. user.managed_by)"));
 				}
+				config.annotate_xattr = Some(an);
 			},
-			b"BaseDir" => {
-				if val.len() == 0 { continue }
-				config.basedir = pathify(val);
+			b"ProtectAnnotated" => {
+				config.protect_annotated = boolify(val, "ProtectAnnotated")?;
 			},
-			b"WorkDir" => {
-				if val.len() == 0 { continue }
-				config.workdir = pathify(val);
+			b"MailTo" => {
+				config.mailto = Some(stringify(val, "MailTo")?)
 			},
-			b"CreateBootEnv" => {
-				config.create_boot_env = boolify(val).ok_or_else(|| {
-					ConfigErr::Syntax(format!("Bad CreateBootEnv value {}",
-						String::from_utf8_lossy(val)))
+			b"PreferServer" => {
+				config.prefer_server = Some(stringify(val, "PreferServer")?)
+			},
+			b"MetadataCache" => {
+				config.metadata_cache = boolify(val, "MetadataCache")?;
+			},
+			b"ServerCacheTTL" => {
+				let ttl = stringify(val, "ServerCacheTTL")?;
+				config.server_cache_ttl = ttl.trim().parse().map_err(|e| {
+					format!("Bad ServerCacheTTL value {ttl}: {e}")
 				})?;
 			},
-			b"BootEnvRoot" => {
-				if val.len() == 0 { continue }
-				eprintln!("BootEnvRoot doesn't do anything...");
-				config.boot_env_root = Some(stringify(val, "BootEnvRoot")?);
+			b"InstallMBPerSec" => {
+				let mbps = stringify(val, "InstallMBPerSec")?;
+				config.install_mb_per_sec = match mbps.trim().parse() {
+					Ok(0) | Err(_) => return Err(format!("Bad \
+							InstallMBPerSec value {mbps}")),
+					Ok(n) => n,
+				};
 			},
-			b"KeepModifiedMetadata" => {
-				config.keep_modified_metadata  = boolify(val).ok_or_else(|| {
-					ConfigErr::Syntax(format!("Bad KeepModifiedMetadata value {}",
-						String::from_utf8_lossy(val)))
+
+			b"MaxRemovalPercent" => {
+				let pct = stringify(val, "MaxRemovalPercent")?;
+				config.max_removal_percent = match pct.trim().parse() {
+					Ok(n) if n <= 100 => n,
+					_ => return Err(format!("Bad MaxRemovalPercent value \
+							{pct}")),
+				};
+			},
+
+			b"InstallHistoryDetail" => {
+				let n = stringify(val, "InstallHistoryDetail")?;
+				config.install_history_detail = n.trim().parse()
+						.map_err(|_| format!("Bad InstallHistoryDetail \
+							value {n}"))?;
+			},
+
+			b"MaxWorkdirSize" => {
+				let sz = stringify(val, "MaxWorkdirSize")?;
+				config.max_workdir_size = Some(parse_size(&sz)
+						.map_err(|e| format!("Bad MaxWorkdirSize value {sz}: \
+							{e}"))?);
+			},
+
+			b"CronJitter" => {
+				let jit = stringify(val, "CronJitter")?;
+				config.cron_jitter = parse_cron_jitter(&jit)
+						.map_err(|e| format!("Bad CronJitter value {jit}: {e}"))?;
+			},
+			b"CronLockWait" => {
+				let wait = stringify(val, "CronLockWait")?;
+				config.cron_lock_wait = wait.trim().parse().map_err(|e| {
+					format!("Bad CronLockWait value {wait}: {e}")
 				})?;
 			},
-			b"MailTo" => {
-				config.mailto = Some(stringify(val, "MailTo")?)
+
+			b"InstallHelpers" => {
+				config.install_helpers = match val {
+					b"auto"   => HelperMode::Auto,
+					b"chroot" => HelperMode::Chroot,
+					b"host"   => HelperMode::Host,
+					b"none"   => HelperMode::Skip,
+					_ => return Err(format!("Bad InstallHelpers value {}",
+							String::from_utf8_lossy(val))),
+				};
 			},
 
-			// Explicitly call out some things I'm intentionally skipping
-			// support of for now.
-			b"AllowAdd" => {
-				match boolify(val)
+			b"PreserveTimestamps" => {
+				config.preserve_timestamps = match val {
+					b"no"       => Timestamps::Now,
+					b"upstream" => Timestamps::Upstream,
+					_ => return Err(format!("Bad PreserveTimestamps value {}",
+							String::from_utf8_lossy(val))),
+				};
+			},
+
+			b"FetchUser" => {
+				config.fetch_user = match val {
+					b"auto" => FetchUser::Auto,
+					b"none" => FetchUser::Off,
+					_ => FetchUser::Named(stringify(val, "FetchUser")?),
+				};
+			},
+
+			b"NoRestartServices" => {
+				for svc in words()
 				{
-					Some(v) if !v => {
-						let estr = format!("AllowAdd=no");
-						return Err(ConfigErr::Unsupported(estr));
-					}
-					_ => (),
+					let svc = stringify(svc, "NoRestartServices")?;
+					config.no_restart_services.push(svc);
 				}
 			},
+
 			b"AllowDelete" => {
-				match boolify(val)
-				{
-					Some(v) if !v => {
-						let estr = format!("AllowDelete=no");
-						return Err(ConfigErr::Unsupported(estr));
-					}
-					_ => (),
+				config.allow_delete = boolify(val, "AllowDelete")?;
+			},
+
+			// Explicitly call out some things I'm intentionally skipping
+			// support of for now.
+			b"AllowAdd" => {
+				let pstr = String::from_utf8_lossy(par);
+				match boolify(val, &pstr) {
+					Ok(true) => (),
+					Ok(false) => prob(PK::Unsupported, format!("{pstr}=no")),
+					Err(e) => prob(PK::Warning, format!("{e}, ignored")),
 				}
 			},
 
-			_ => continue,
+			// Other things freebsd-update.sh does, that we don't
+			b"StrictComponents" | b"BackupKernel" | b"BackupKernelDir"
+					| b"BackupKernelSymbolFiles" => {
+				let pstr = String::from_utf8_lossy(par);
+				prob(PK::Warning, format!("{pstr} isn't supported, ignored"));
+			},
+
+			_ => {
+				let pstr = String::from_utf8_lossy(par);
+				let near = KNOWN_PARAMS.iter()
+						.find(|k| similar(k, &pstr));
+				prob(PK::Warning, match near {
+					Some(k) => format!("unknown param {pstr} (did you \
+							mean {k}?), ignored"),
+					None => format!("unknown param {pstr}, ignored"),
+				});
+			},
 		};
+		Ok(()) })();
+
+		if let Err(e) = res { prob(PK::Syntax, e); }
 	}
 
+	(config, probs)
+}
 
-	Ok(config)
+
+/// Are two param names close enough to be a likely typo?  Ignoring
+/// case, within a couple single-character edits.
+fn similar(a: &str, b: &str) -> bool
+{
+	let a: Vec<char> = a.to_lowercase().chars().collect();
+	let b: Vec<char> = b.to_lowercase().chars().collect();
+
+	// Levenshtein, a row at a time
+	let mut prev: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.iter().enumerate()
+	{
+		let mut cur = vec![i + 1; b.len() + 1];
+		for (j, cb) in b.iter().enumerate()
+		{
+			let sub = prev[j] + if ca == cb { 0 } else { 1 };
+			cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+		}
+		prev = cur;
+	}
+	prev[b.len()] <= 2
 }
 
 
@@ -458,6 +959,29 @@ MergeChanges /etc/ /boot/device.hints
 		assert_eq!(conf.mailto, None);
 	}
 
+	#[test]
+	fn cli_filters()
+	{
+		use regex_lite::Regex;
+
+		let mut conf = load(DEFCONF).unwrap();
+		assert_eq!(conf.add_cli_filters(&[], &[]), None);
+		assert_eq!(conf.ignore_paths.len(), 1);
+
+		let ex = [Regex::new("^/etc/mail").unwrap()];
+		let inc = [Regex::new("^/usr/bin/").unwrap(),
+				Regex::new("^/bin/").unwrap()];
+		let note = conf.add_cli_filters(&ex, &inc).unwrap();
+		assert_eq!(note, "computed with nonstandard path filters: \
+				--exclude ^/etc/mail --include-only ^/usr/bin/ \
+				--include-only ^/bin/");
+
+		let ign: Vec<_> = conf.ignore_paths.iter().map(|r| r.as_str())
+				.collect();
+		assert_eq!(ign, ["^/foo/bar", "^/etc/mail"]);
+		assert_eq!(conf.include_only.len(), 2);
+	}
+
 	#[test]
 	fn component()
 	{
@@ -517,6 +1041,18 @@ MergeChanges /etc/ /boot/device.hints
 		assert_eq!(conf.boot_env_root, Some("as/df".to_string()));
 	}
 
+	#[test]
+	fn allowunexpectedprefixes()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.allow_unexpected_prefixes, false);
+
+		let conf = load(b"AllowUnexpectedPrefixes yes").unwrap();
+		assert_eq!(conf.allow_unexpected_prefixes, true);
+
+		load(b"AllowUnexpectedPrefixes sometimes").unwrap_err();
+	}
+
 	#[test]
 	fn keepmodifiedmetadata()
 	{
@@ -532,6 +1068,54 @@ MergeChanges /etc/ /boot/device.hints
 	}
 
 
+	#[test]
+	fn git_src()
+	{
+		use crate::components::BaseComponent as BC;
+
+		let td = tempfile::tempdir().unwrap();
+		let src = td.path().join("usr/src");
+		std::fs::create_dir_all(src.join(".git")).unwrap();
+		std::fs::write(src.join("COPYRIGHT"), b"").unwrap();
+
+		let conf = |comps: &str, extra: &str| {
+			let c = format!("BaseDir {}\nComponents {comps}\n{extra}",
+					td.path().display());
+			load(c.as_bytes()).unwrap()
+		};
+		let has_src = |c: &super::Config| c.components.iter()
+				.any(|c| c.comp == BC::Src);
+
+		// Default: git checkout means hands off, and say so.
+		let mut c = conf("src world kernel", "");
+		assert!(c.finalize_components().is_some());
+		assert!(!has_src(&c));
+		assert_eq!(c.components.len(), 2);
+
+		// Asked for, and src listed: keep it.
+		let mut c = conf("src world kernel", "ManageGitSrc yes");
+		assert_eq!(c.manage_git_src, true);
+		assert_eq!(c.finalize_components(), None);
+		assert!(has_src(&c));
+
+		// Asked for, but src not listed: doesn't add it, nothing to say.
+		let mut c = conf("world kernel", "ManageGitSrc yes");
+		assert_eq!(c.finalize_components(), None);
+		assert!(!has_src(&c));
+
+		// Subcomponents go too.
+		let mut c = conf("src/src world", "ManageGitSrc no");
+		assert!(c.finalize_components().is_some());
+		assert!(!has_src(&c));
+
+		// Not git, src installed; normal.
+		std::fs::remove_dir(src.join(".git")).unwrap();
+		let mut c = conf("src world kernel", "");
+		assert_eq!(c.finalize_components(), None);
+		assert!(has_src(&c));
+	}
+
+
 	#[test]
 	fn mailto()
 	{
@@ -547,6 +1131,284 @@ MergeChanges /etc/ /boot/device.hints
 	}
 
 
+	#[test]
+	fn metadata_cache()
+	{
+		// Off by default
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.metadata_cache, false);
+
+		let conf = load(b"MetadataCache yes").unwrap();
+		assert_eq!(conf.metadata_cache, true);
+
+		load(b"MetadataCache sometimes").expect_err("not a bool");
+	}
+
+
+	#[test]
+	fn server_cache_ttl()
+	{
+		// Default is an hour
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.server_cache_ttl, 3600);
+
+		let conf = load(b"ServerCacheTTL 60").unwrap();
+		assert_eq!(conf.server_cache_ttl, 60);
+
+		load(b"ServerCacheTTL soon").expect_err("non-numeric TTL");
+
+		// --no-server-cache turns it off
+		let mut args = make_fake_clargs();
+		args.no_server_cache = true;
+		let conf = load_config(b"ServerCacheTTL 60", &args).unwrap();
+		assert_eq!(conf.server_cache_ttl, 0);
+	}
+
+
+	#[test]
+	fn prefer_server()
+	{
+		// Nothing by default
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.prefer_server, None);
+
+		let conf = load(b"PreferServer mirror.example.org").unwrap();
+		assert_eq!(conf.prefer_server.as_deref(), Some("mirror.example.org"));
+
+		// --prefer-server wins, but not having it leaves the config be
+		let mut args = make_fake_clargs();
+		let conf = load_config(b"PreferServer a.example.org", &args).unwrap();
+		assert_eq!(conf.prefer_server.as_deref(), Some("a.example.org"));
+		args.prefer_server = Some("b.example.org".to_string());
+		let conf = load_config(b"PreferServer a.example.org", &args).unwrap();
+		assert_eq!(conf.prefer_server.as_deref(), Some("b.example.org"));
+	}
+
+
+	#[test]
+	fn cron_settings()
+	{
+		use super::{parse_cron_jitter, CRON_JITTER_MAX};
+
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.cron_jitter, 3600);
+		assert_eq!(conf.cron_lock_wait, 0);
+
+		let conf = load(b"CronJitter 600\nCronLockWait 1800").unwrap();
+		assert_eq!(conf.cron_jitter, 600);
+		assert_eq!(conf.cron_lock_wait, 1800);
+
+		// Jitter bounds
+		assert_eq!(parse_cron_jitter("0"), Ok(0));
+		assert_eq!(parse_cron_jitter(" 14400 "), Ok(CRON_JITTER_MAX));
+		parse_cron_jitter("14401").expect_err("over max");
+		parse_cron_jitter("-1").expect_err("negative");
+		parse_cron_jitter("1h").expect_err("units");
+		load(b"CronJitter 86400").expect_err("over max in config");
+		load(b"CronLockWait forever").expect_err("non-numeric wait");
+	}
+
+
+	#[test]
+	fn merge_normalize()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.merge_normalize, true);
+
+		let conf = load(b"MergeNormalize none").unwrap();
+		assert_eq!(conf.merge_normalize, false);
+		let conf = load(b"MergeNormalize whitespace").unwrap();
+		assert_eq!(conf.merge_normalize, true);
+
+		load(b"MergeNormalize yes").expect_err("not a mode");
+	}
+
+
+	#[test]
+	fn install_helpers()
+	{
+		use super::HelperMode;
+
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.install_helpers, HelperMode::Auto);
+
+		let conf = load(b"InstallHelpers chroot").unwrap();
+		assert_eq!(conf.install_helpers, HelperMode::Chroot);
+		let conf = load(b"InstallHelpers none").unwrap();
+		assert_eq!(conf.install_helpers, HelperMode::Skip);
+
+		load(b"InstallHelpers sometimes").expect_err("not a mode");
+	}
+
+
+	#[test]
+	fn install_history_detail()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.install_history_detail, 3);
+
+		let conf = load(b"InstallHistoryDetail 0").unwrap();
+		assert_eq!(conf.install_history_detail, 0);
+		let conf = load(b"InstallHistoryDetail 10").unwrap();
+		assert_eq!(conf.install_history_detail, 10);
+
+		load(b"InstallHistoryDetail all").expect_err("non-numeric");
+		load(b"InstallHistoryDetail -1").expect_err("negative");
+	}
+
+
+	#[test]
+	fn max_workdir_size()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.max_workdir_size, None);
+
+		let conf = load(b"MaxWorkdirSize 1500M").unwrap();
+		assert_eq!(conf.max_workdir_size, Some(1500 << 20));
+		let conf = load(b"MaxWorkdirSize 2GB").unwrap();
+		assert_eq!(conf.max_workdir_size, Some(2 << 30));
+		let conf = load(b"MaxWorkdirSize 4096").unwrap();
+		assert_eq!(conf.max_workdir_size, Some(4096));
+
+		load(b"MaxWorkdirSize lots").expect_err("non-numeric");
+		load(b"MaxWorkdirSize 1.5G").expect_err("not whole");
+		load(b"MaxWorkdirSize 99999999999T").expect_err("overflow");
+	}
+
+
+	#[test]
+	fn preserve_timestamps()
+	{
+		use super::Timestamps;
+
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.preserve_timestamps, Timestamps::Now);
+
+		let conf = load(b"PreserveTimestamps upstream").unwrap();
+		assert_eq!(conf.preserve_timestamps, Timestamps::Upstream);
+		let conf = load(b"PreserveTimestamps no").unwrap();
+		assert_eq!(conf.preserve_timestamps, Timestamps::Now);
+
+		load(b"PreserveTimestamps yes").expect_err("not a mode");
+	}
+
+
+	#[test]
+	fn fetch_user()
+	{
+		use super::FetchUser;
+
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.fetch_user, FetchUser::Auto);
+
+		let conf = load(b"FetchUser none").unwrap();
+		assert_eq!(conf.fetch_user, FetchUser::Off);
+		let conf = load(b"FetchUser _update").unwrap();
+		assert_eq!(conf.fetch_user, FetchUser::Named("_update".into()));
+	}
+
+
+	#[test]
+	fn annotate_xattr()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.annotate_xattr, None);
+		assert_eq!(conf.protect_annotated, false);
+
+		let conf = load(b"AnnotateXattr user.managed_by\n\
+				ProtectAnnotated yes").unwrap();
+		assert_eq!(conf.annotate_xattr.as_deref(), Some("user.managed_by"));
+		assert_eq!(conf.protect_annotated, true);
+
+		load(b"AnnotateXattr managed_by").expect_err("no namespace");
+	}
+
+
+	#[test]
+	fn max_removal_percent()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.max_removal_percent, 20);
+
+		let conf = load(b"MaxRemovalPercent 50").unwrap();
+		assert_eq!(conf.max_removal_percent, 50);
+
+		load(b"MaxRemovalPercent 101").expect_err("over 100");
+		load(b"MaxRemovalPercent lots").expect_err("non-numeric");
+	}
+
+
+	#[test]
+	fn install_mb_per_sec()
+	{
+		let conf = load(b"").unwrap();
+		assert_eq!(conf.install_mb_per_sec, 50);
+
+		let conf = load(b"InstallMBPerSec 200").unwrap();
+		assert_eq!(conf.install_mb_per_sec, 200);
+
+		load(b"InstallMBPerSec 0").expect_err("zero speed");
+		load(b"InstallMBPerSec fast").expect_err("non-numeric speed");
+	}
+
+
+	#[test]
+	fn no_restart_services()
+	{
+		let conf = load(b"").unwrap();
+		assert!(conf.no_restart_services.is_empty());
+
+		let conf = load(b"NoRestartServices sshd  local_unbound").unwrap();
+		assert_eq!(conf.no_restart_services, ["sshd", "local_unbound"]);
+	}
+
+
+	#[test]
+	fn problems()
+	{
+		use super::{parse, ProblemKind as PK};
+
+		let conf = b"# A comment\n\
+				ServerName update.FreeBSD.org\n\
+				CreateBootEnv maybe\n\
+				\n\
+				Severname foo.example.com\n\
+				AllowAdd no\n\
+				MailTo\n\
+				BackupKernel yes\n\
+				IgnorePaths /ok /b(ad\n\
+				Whatever 12\n";
+		let (conf, probs) = parse(conf);
+		let got: Vec<_> = probs.iter().map(|p| (p.line.unwrap(), p.kind))
+				.collect();
+		assert_eq!(got, [(3, PK::Syntax), (5, PK::Warning), (6, PK::Unsupported),
+				(7, PK::Warning), (8, PK::Warning), (9, PK::Syntax),
+				(10, PK::Warning)]);
+
+		// We still get what was usable
+		assert_eq!(conf.servername, "update.FreeBSD.org");
+		assert_eq!(conf.ignore_paths.len(), 1);
+
+		// Typo'd params get a suggestion, others don't
+		assert!(probs[1].msg.contains("did you mean ServerName?"),
+				"{}", probs[1]);
+		assert!(!probs[6].msg.contains("did you mean"), "{}", probs[6]);
+		assert_eq!(probs[0].to_string(), "line 3: Bad CreateBootEnv value maybe");
+
+		// And loading errors with all the errors, not the warnings
+		let err = load(b"CreateBootEnv maybe\nAllowAdd no\nMailTo\n")
+				.unwrap_err();
+		match err {
+			super::ConfigErr::Problems(p) => assert_eq!(p.len(), 2),
+			e => panic!("Unexpected error {e}"),
+		}
+
+		// Stock config has nothing to complain about
+		let (_, probs) = parse(DEFCONF);
+		assert!(probs.is_empty(), "{probs:?}");
+	}
+
+
 	fn make_fake_clargs() -> crate::command::FrArgs
 	{
 		crate::command::FrArgs::default()
@@ -580,9 +1442,11 @@ MergeChanges /etc/ /boot/device.hints
 	#[test]
 	fn allow_delete()
 	{
-		let conf = b"AllowDelete yes";
-		load(conf).expect("AllowDelete yes ok");
-		let conf = b"AllowDelete no";
-		load(conf).expect_err("AllowDelete no not ok");
+		assert!(load(b"").unwrap().allow_delete);
+		let conf = load(b"AllowDelete yes").expect("AllowDelete yes ok");
+		assert!(conf.allow_delete);
+		let conf = load(b"AllowDelete no").expect("AllowDelete no ok");
+		assert!(!conf.allow_delete);
+		load(b"AllowDelete sometimes").unwrap_err();
 	}
 }
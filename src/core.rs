@@ -5,23 +5,74 @@
 pub(crate) mod rtdirs;
 pub(crate) use rtdirs::RtDirs;
 
+/// Locking a basedir's state
+pub(crate) mod lock;
+
 /// Generic threadpool implementation
 pub(crate) mod pool;
 
 /// FS scanning
 pub(crate) mod scan;
 
+/// Checking the basedir FS is one we can handle
+pub(crate) mod fsprobe;
+
 /// Hashfile fetching
 pub(crate) mod hashfetch;
 
+/// Fetching as somebody other than root
+pub(crate) mod privsep;
+
+/// Recording and replaying what servers said
+pub(crate) mod session;
+
+/// Stashing current files
+pub(crate) mod stash;
+
 /// Patching
 pub(crate) mod patchcheck;
 
+/// Acquiring metadata from the server
+pub(crate) mod mdfetch;
+
+/// Metadata from release distribution sets instead
+pub(crate) mod dist;
+
 /// Metadata filtering bits
 pub(crate) mod filter;
 
+/// Databases generated from other files
+pub(crate) mod derived;
+
+/// Noticing pkgbase-managed systems
+pub(crate) mod pkgbase;
+
+/// Sanity checking removals
+pub(crate) mod removal;
+
+/// Sorting changes by risk
+pub(crate) mod risk;
+
+/// Keeping the files dir under MaxWorkdirSize
+pub(crate) mod evict;
+
 /// File merging bits
 pub(crate) mod merge;
 
 /// Installing bits
 pub(crate) mod install;
+
+/// Installing straight from upstream metadata
+pub(crate) mod extract;
+
+/// Moving pending installs between systems
+pub(crate) mod bundle;
+
+/// Explaining what happens to a path
+pub(crate) mod why;
+
+/// What changed between two patch levels
+pub(crate) mod patchdiff;
+
+/// Remembering what finished installs changed
+pub(crate) mod history;
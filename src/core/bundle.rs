@@ -0,0 +1,808 @@
+//! Carrying a pending install to another system.
+//!
+//! For systems that can't reach an update server, a pending fetch or
+//! upgrade can be made on one that can, and bundled up to move over.
+//! The bundle is a tar of a header saying what it's for, the manifest,
+//! the signed key, tag and tINDEX its metadata index came from, and just
+//! the `<hash>.gz` files installing it needs (plus the metadata files its
+//! index points at), optionally run through zstd.  The signed bits get
+//! checked against the importing side's own KeyPrint, and everything in
+//! files/ is named by its hash, so it can check every one of them before
+//! trusting it.  The manifest is just what the exporting side says, so
+//! everything it would install or remove gets checked against the signed
+//! metadata, and what's on the importing system now; x-ref
+//! check_manifest().
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+
+use anyhow::{anyhow, bail};
+
+use crate::core::RtDirs;
+use crate::info::{Version, AVersion};
+use crate::metadata::{Metadata, MetadataIdx};
+use crate::server::SignedFiles;
+use crate::state::Manifest;
+use crate::util::hash::Sha256HashBuf;
+use crate::util::tar;
+
+
+/// Bump if what goes in a bundle changes incompatibly.
+const FORMAT: u32 = 1;
+
+/// Names of things in the tar
+const HEADER: &str = "bundle.json";
+const MANIFEST: &str = "manifest.json";
+const FILES: &str = "files/";
+const KEY: &str = "signed/pub.ssl";
+const TAG: &str = "signed/latest.ssl";
+const TIDX: &str = "signed/tINDEX";
+
+/// What we (de)compress with, and how we recognize its output
+const ZSTD: &str = "/usr/bin/zstd";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+
+/// What a bundle is for.
+#[derive(Debug, Clone, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Header
+{
+	pub(crate) format: u32,
+
+	/// fetch or upgrade
+	pub(crate) mtype: String,
+
+	/// What the exporting system was running, which is what the
+	/// manifest was worked out against, and what it's going to
+	pub(crate) from: Version,
+	pub(crate) to: AVersion,
+
+	/// Whose updates these are
+	pub(crate) keyprint: String,
+	pub(crate) arch: String,
+
+	/// The metadata index (and its version) from the exporting state
+	pub(crate) meta_idx: Option<MetadataIdx>,
+	pub(crate) meta_idx_vers: Option<AVersion>,
+
+	/// How many hashfiles are in it
+	pub(crate) nfiles: usize,
+}
+
+impl Header
+{
+	pub(crate) fn new(manifest: &Manifest, from: Version, keyprint: String,
+			arch: String, nfiles: usize) -> Self
+	{
+		Self { format: FORMAT, mtype: manifest.mtype().to_string(), from,
+				to: manifest.version().clone(), keyprint, arch,
+				meta_idx: None, meta_idx_vers: None, nfiles }
+	}
+
+	/// Is this any use on a system running `running`, with our keyprint
+	/// and arch?
+	pub(crate) fn check(&self, running: &Version, keyprint: &str,
+			arch: &str) -> Result<(), anyhow::Error>
+	{
+		if self.format != FORMAT
+		{ bail!("Unknown bundle format {}", self.format); }
+		if self.keyprint != keyprint
+		{
+			let kp: String = self.keyprint.chars().take(16).collect();
+			bail!("Bundle is for a different KeyPrint ({kp}...)");
+		}
+		if self.arch != arch
+		{ bail!("Bundle is for {}, but this system is {arch}", self.arch); }
+		if self.from != *running
+		{
+			bail!("Bundle is a {} from {} (kernel {}, world {}), but this \
+					system is running kernel {}, world {}", self.mtype,
+					self.from, self.from.kernel, self.from.user,
+					running.kernel, running.user);
+		}
+		Ok(())
+	}
+}
+
+
+/// Check the signed bits against `keyprint`, and that they vouch for the
+/// header's metadata index, and the patch level it's going to.
+pub(crate) fn verify_signed(header: &Header, signed: &SignedFiles,
+		keyprint: &str) -> Result<(), anyhow::Error>
+{
+	use crate::util::hash;
+
+	let (idx, vers) = match (&header.meta_idx, &header.meta_idx_vers) {
+		(Some(i), Some(v)) => (i, v),
+		_ => bail!("No metadata index to check the signed tag against"),
+	};
+
+	let kt = crate::server::verify_tag_arch(&signed.key, &signed.tag,
+			keyprint, &header.arch, vers)
+			.map_err(|e| anyhow!("Signed tag doesn't verify: {e}"))?;
+	if kt.patch() != header.to.patch
+	{
+		let p = kt.patch().unwrap_or(0);
+		bail!("Pending {} is to {}, but the signed tag is for -p{p}",
+				header.mtype, header.to);
+	}
+
+	hash::check_sha256(&signed.tidx, kt.tidx(), "tINDEX")?;
+	let bad = idx.mismatches(&MetadataIdx::parse(&signed.tidx)?);
+	if !bad.is_empty()
+	{
+		let bad: Vec<_> = bad.iter().map(|t| t.to_uppercase()).collect();
+		bail!("Metadata index doesn't match the signed tINDEX (INDEX-{})",
+				bad.join(", INDEX-"));
+	}
+	Ok(())
+}
+
+
+/// Which metadata file says what a pending install ends up with: the
+/// INDEX-NEW of the version a fetch is within, or the INDEX-ALL of the
+/// release an upgrade goes to.
+pub(crate) fn signed_which(manifest: &Manifest) -> &'static str
+{
+	match manifest {
+		Manifest::Fetch(_)   => "new",
+		Manifest::Upgrade(_) => "all",
+	}
+}
+
+
+/// Load up the signed metadata a manifest should come from, out of our
+/// files dir.  `idx` needs to already be checked against the tag.
+fn signed_md(idx: &MetadataIdx, which: &str, rtdirs: &RtDirs)
+		-> Result<Metadata, anyhow::Error>
+{
+	use crate::util::{compress, hash};
+
+	let wu = which.to_uppercase();
+	let h = match idx.get_matching(&[which]).first() {
+		Some(h) => h.to_buf(),
+		None => bail!("No INDEX-{wu} in the metadata index"),
+	};
+	let buf = compress::decompress_to_vec(&rtdirs.hashfile(&h))
+			.map_err(|e| anyhow!("Signed INDEX-{wu} not in bundle: {e}"))?;
+	hash::check_sha256(&buf, h.as_ref(), &format!("INDEX-{wu}"))?;
+	let mdg = crate::metadata::parse_reader(&mut &buf[..])
+			.map_err(|e| anyhow!("Parsing INDEX-{wu}: {}", e[0]))?;
+	Ok(mdg.into_metadata())
+}
+
+
+/// Check that a manifest only installs and removes what the signed
+/// metadata `signed` says to.  Where it doesn't, it can still be
+/// something a fetch/upgrade legitimately does with what's on the system
+/// (looked at under `basedir`): leaving something as it is, keeping our
+/// perms over upstream's (KeepModifiedMetadata), or a clean merge of our
+/// file with upstream's (MergeChanges), which gets redone here to see
+/// it comes out the same.  Anything else, whoever made the bundle made
+/// up, so none of it goes.
+fn check_manifest(manifest: &Manifest, signed: &Metadata, rtdirs: &RtDirs,
+		basedir: &Path) -> Result<(), anyhow::Error>
+{
+	use std::collections::HashMap;
+	use std::path::PathBuf;
+
+	let none = HashMap::new();
+	let merges = match manifest {
+		Manifest::Fetch(_) => &none,
+		Manifest::Upgrade(mup) => {
+			if mup.kernel || mup.world || !mup.old_libs.is_empty()
+			{ bail!("Pending upgrade is already partly installed"); }
+			if !mup.merge_conflict.is_empty()
+			{ bail!("Pending upgrade has unresolved merge conflicts"); }
+			&mup.merge_clean
+		},
+	};
+	let (cur, new) = manifest.cur_new();
+
+	// Removing anything upstream still has isn't something either would
+	// do, so that's right out.
+	let spaths = signed.allpaths_hashset_nodash();
+	let npaths = new.allpaths_hashset_nodash();
+	let mut rm: Vec<_> = cur.allpaths_hashset_nodash().into_iter()
+			.filter(|p| !npaths.contains(p) && spaths.contains(p))
+			.collect();
+	rm.sort_unstable();
+	if let Some(p) = rm.first()
+	{ bail!("It would remove {}, which upstream still has", p.display()); }
+
+	// Anything that's just what upstream says is fine.  What isn't, we
+	// go look at what we've got.
+	fn odd<'a, T: PartialEq>(ours: &'a HashMap<PathBuf, T>,
+			signed: &'a HashMap<PathBuf, T>)
+			-> impl Iterator<Item = &'a PathBuf>
+	{
+		ours.iter().filter(|(p, e)| signed.get(*p) != Some(*e))
+				.map(|(p, _)| p)
+	}
+	let mut odd: Vec<PathBuf> = odd(&new.files, &signed.files)
+			.chain(odd(&new.dirs, &signed.dirs))
+			.chain(odd(&new.symlinks, &signed.symlinks))
+			.chain(odd(&new.hardlinks, &signed.hardlinks))
+			.chain(merges.keys()).cloned().collect();
+	if odd.is_empty() { return Ok(()); }
+	odd.sort_unstable();
+	odd.dedup();
+	let local = crate::core::scan::scan(basedir.to_path_buf(), odd.clone())?;
+
+	// Upstream's, but with our perms
+	macro_rules! kept {
+		($s:expr, $l:expr) => {{
+			let mut k = $s.clone();
+			(k.uid, k.gid, k.mode, k.flags) = ($l.uid, $l.gid, $l.mode,
+					$l.flags);
+			k
+		}};
+	}
+	// Upstream's as it is or with our perms, or just what we've got
+	macro_rules! vouched {
+		($fld:ident, $p:expr, $e:expr, $s:expr) => {{
+			let l = local.$fld.get($p);
+			let s = $s;
+			l == Some($e) || s == Some($e)
+					|| s.zip(l).is_some_and(|(s, l)| kept!(s, l) == *$e)
+		}};
+	}
+
+	for p in &odd
+	{
+		let ok = if let Some(e) = new.files.get(p)
+		{
+			let mut s = signed.files.get(p).cloned();
+			if let (Some(c), Some(s)) = (merges.get(p), s.as_mut())
+			{
+				remerge(p, c, s, local.files.get(p), rtdirs)?;
+				s.sha256 = (&c.res).into();
+			}
+			vouched!(files, p, e, s.as_ref())
+		}
+		else if let Some(e) = new.dirs.get(p)
+		{ vouched!(dirs, p, e, signed.dirs.get(p)) }
+		else if let Some(e) = new.symlinks.get(p)
+		{ vouched!(symlinks, p, e, signed.symlinks.get(p)) }
+		else if let Some(e) = new.hardlinks.get(p)
+		{
+			let e = Some(e);
+			local.hardlinks.get(p) == e || signed.hardlinks.get(p) == e
+		}
+		else
+		{ false };
+		if !ok
+		{
+			bail!("{} isn't what the signed metadata or this system says",
+					p.display());
+		}
+	}
+	Ok(())
+}
+
+
+/// Redo a clean merge, from upstream's new file `s` and ours `l`, to make
+/// sure it comes out to what the manifest says.
+///
+/// The merge base we can't check, since it's from the running version's
+/// metadata, which doesn't come along.  But a merge is all our lines and
+/// upstream's, so the most a made-up base could do is pick the wrong
+/// side of a change.
+fn remerge(p: &Path, c: &crate::core::merge::Clean,
+		s: &crate::metadata::MetaFile, l: Option<&crate::metadata::MetaFile>,
+		rtdirs: &RtDirs)
+		-> Result<(), anyhow::Error>
+{
+	use crate::core::merge::merge_files;
+	use crate::util::compress::decompress_to_vec;
+	use crate::util::hash::sha256_reader;
+
+	let pd = p.display();
+	if c.resolved
+	{
+		bail!("{pd} was merged by hand where the bundle was made, which \
+				can't be checked here");
+	}
+	if c.new != s.sha256.to_buf()
+	{ bail!("{pd} was merged with something other than upstream's"); }
+	if l.map(|l| l.sha256.to_buf()) != Some(c.cur)
+	{ bail!("{pd} was merged with something other than ours"); }
+
+	let [old, cur, new] = [&c.old, &c.cur, &c.new]
+			.map(|h| decompress_to_vec(&rtdirs.hashfile(h)));
+	let mut out = Vec::new();
+	let m = merge_files(&old?, &cur?, &new?, c.normalized, &mut out);
+	let res = sha256_reader(&mut &out[..])?.to_buf();
+	if m.is_err() || res != c.res
+	{ bail!("{pd} doesn't merge to what the bundle says it does"); }
+	Ok(())
+}
+
+
+/// What we got out of a bundle.
+#[derive(Debug)]
+pub(crate) struct Imported
+{
+	pub(crate) header: Header,
+	pub(crate) manifest: Manifest,
+
+	/// Already checked with verify_signed()
+	pub(crate) signed: SignedFiles,
+}
+
+
+/// Write out a bundle.  It's built up next to `out`, and only put in
+/// place once it's complete.
+pub(crate) fn export(out: &Path, zstd: bool, header: &Header,
+		manifest: &Manifest, signed: &SignedFiles, hashes: &[Sha256HashBuf],
+		rtdirs: &RtDirs)
+		-> Result<(), anyhow::Error>
+{
+	use std::io::BufWriter;
+
+	let dir = match out.parent() {
+		Some(d) if d != Path::new("") => d,
+		_ => Path::new("."),
+	};
+	let tf = tempfile::NamedTempFile::new_in(dir)?;
+
+	let (child, wtr): (Option<Child>, Box<dyn Write>) = match zstd {
+		false => (None, Box::new(tf.reopen()?)),
+		true  => {
+			let mut child = Command::new(ZSTD).args(["-q", "-c"])
+					.stdin(Stdio::piped()).stdout(tf.reopen()?).spawn()
+					.map_err(|e| anyhow!("Running {ZSTD}: {e}"))?;
+			let inp = child.stdin.take().expect("asked for stdin");
+			(Some(child), Box::new(inp))
+		},
+	};
+
+	let mut tw = tar::Writer::new(BufWriter::new(wtr));
+	let json = serde_json::to_vec_pretty(header)?;
+	tw.file(HEADER, 0o644, json.len() as u64, &mut &json[..])?;
+	let json = serde_json::to_vec(manifest)?;
+	tw.file(MANIFEST, 0o644, json.len() as u64, &mut &json[..])?;
+	for (name, buf) in [(KEY, &signed.key), (TAG, &signed.tag),
+			(TIDX, &signed.tidx)]
+	{
+		tw.file(name, 0o644, buf.len() as u64, &mut &buf[..])?;
+	}
+	for h in hashes
+	{
+		let src = rtdirs.hashfile(h);
+		let mut fh = std::fs::File::open(&src)
+				.map_err(|e| anyhow!("{}: {e}", src.display()))?;
+		let len = fh.metadata()?.len();
+		tw.file(&format!("{FILES}{h}.gz"), 0o644, len, &mut fh)?;
+	}
+
+	// Have to close the pipe for zstd to finish up
+	drop(tw.finish()?);
+	if let Some(mut child) = child
+	{
+		let status = child.wait()?;
+		if !status.success() { bail!("{ZSTD} failed: {status}"); }
+	}
+
+	tf.as_file().sync_all()?;
+	tf.persist(out)?;
+	Ok(())
+}
+
+
+/// Read in a bundle, checking the header with `check` before anything
+/// else.  The hashfiles go into our files dir, each only once it's been
+/// checked against its name, and nothing comes back unless the signed
+/// bits check out against `keyprint`, and the manifest against them and
+/// the system under `basedir`.
+pub(crate) fn import(file: &Path, rtdirs: &RtDirs, basedir: &Path,
+		keyprint: &str,
+		check: impl FnOnce(&Header) -> Result<(), anyhow::Error>)
+		-> Result<Imported, anyhow::Error>
+{
+	use std::fs::File;
+	use std::io::BufReader;
+
+	let mut magic = [0u8; 4];
+	let zstd = File::open(file)?.read_exact(&mut magic).is_ok()
+			&& magic == ZSTD_MAGIC;
+
+	let (child, rdr): (Option<Child>, Box<dyn Read>) = match zstd {
+		false => (None, Box::new(File::open(file)?)),
+		true  => {
+			let mut child = Command::new(ZSTD).args(["-q", "-d", "-c"])
+					.arg(file).stdout(Stdio::piped()).spawn()
+					.map_err(|e| anyhow!("Running {ZSTD}: {e}"))?;
+			let out = child.stdout.take().expect("asked for stdout");
+			(Some(child), Box::new(out))
+		},
+	};
+
+	let ret = read(tar::Reader::new(BufReader::new(rdr)), rtdirs, basedir,
+			keyprint, check);
+	if let Some(mut child) = child
+	{
+		if ret.is_err() { child.kill().ok(); }
+		let status = child.wait()?;
+		if ret.is_ok() && !status.success()
+		{ bail!("{ZSTD} failed on {}: {status}", file.display()); }
+	}
+	ret
+}
+
+
+/// Pull in one of the JSON bits.
+fn small<R: Read>(rdr: &mut tar::Reader<R>, e: &tar::Entry)
+		-> Result<Vec<u8>, anyhow::Error>
+{
+	if e.size > 1024 * 1024 * 1024
+	{ bail!("{} is implausibly big", e.path.display()); }
+	let mut buf = Vec::with_capacity(e.size as usize);
+	rdr.data(&mut buf)?;
+	Ok(buf)
+}
+
+
+fn read(mut rdr: tar::Reader<impl Read>, rtdirs: &RtDirs, basedir: &Path,
+		keyprint: &str,
+		check: impl FnOnce(&Header) -> Result<(), anyhow::Error>)
+		-> Result<Imported, anyhow::Error>
+{
+	use std::os::unix::fs::PermissionsExt as _;
+	use crate::util::hash;
+
+	// The header comes first, so we know it's for us before we go
+	// putting anything anywhere.
+	let header: Header = match rdr.next()? {
+		Some(e) if e.path == Path::new(HEADER) => {
+			serde_json::from_slice(&small(&mut rdr, &e)?)?
+		},
+		_ => bail!("Not a pending install bundle (no {HEADER})"),
+	};
+	check(&header)?;
+
+	let mut manifest: Option<Manifest> = None;
+	let (mut key, mut tag, mut tidx) = (None, None, None);
+	let mut nfiles = 0;
+	while let Some(e) = rdr.next()?
+	{
+		let name = e.path.to_string_lossy().into_owned();
+		if e.kind != tar::Kind::File { bail!("Unexpected {name} in bundle"); }
+		if name == MANIFEST
+		{
+			manifest = Some(serde_json::from_slice(&small(&mut rdr, &e)?)?);
+			continue;
+		}
+		let signed = match name.as_str() {
+			KEY  => Some(&mut key),
+			TAG  => Some(&mut tag),
+			TIDX => Some(&mut tidx),
+			_ => None,
+		};
+		if let Some(s) = signed
+		{
+			*s = Some(small(&mut rdr, &e)?);
+			continue;
+		}
+
+		// Anything else had better be a hashfile, so it can't land
+		// anywhere but files/, and we can check it.
+		let hstr = name.strip_prefix(FILES).and_then(|n| n.strip_suffix(".gz"))
+				.filter(|h| h.len() == 64
+					&& h.bytes().all(|b| b.is_ascii_hexdigit()))
+				.ok_or_else(|| anyhow!("Unexpected {name} in bundle"))?;
+
+		let mut tf = tempfile::NamedTempFile::new_in(rtdirs.files())?;
+		rdr.data(&mut tf)?;
+		let mut gzd = flate2::read::GzDecoder::new(tf.reopen()?);
+		hash::check_sha256_reader(&mut gzd, hstr)
+				.map_err(|e| anyhow!("Bad file in bundle: {name}: {e}"))?;
+		tf.as_file().set_permissions(std::fs::Permissions::from_mode(0o644))?;
+		tf.persist(rtdirs.files().join(format!("{hstr}.gz")))?;
+		nfiles += 1;
+	}
+
+	let manifest = manifest.ok_or_else(|| anyhow!("No {MANIFEST} in bundle"))?;
+	if nfiles != header.nfiles
+	{
+		bail!("Bundle should have {} files, but only had {nfiles}; \
+				truncated?", header.nfiles);
+	}
+	if manifest.version() != &header.to
+	{
+		bail!("Bundle says it's to {}, but its manifest is to {}",
+				header.to, manifest.version());
+	}
+
+	// What it all hangs off
+	let signed = match (key, tag, tidx) {
+		(Some(key), Some(tag), Some(tidx)) => SignedFiles { key, tag, tidx },
+		_ => bail!("Bundle is missing its signed key, tag, or tINDEX"),
+	};
+	verify_signed(&header, &signed, keyprint)
+			.map_err(|e| anyhow!("Bad bundle: {e}"))?;
+
+	// And what the manifest would do against what that vouches for
+	let vouch = || -> Result<(), anyhow::Error> {
+		let idx = header.meta_idx.as_ref()
+				.ok_or_else(|| anyhow!("No metadata index"))?;
+		let smd = signed_md(idx, signed_which(&manifest), rtdirs)?;
+		check_manifest(&manifest, &smd, rtdirs, basedir)
+	};
+	vouch().map_err(|e| anyhow!("Bad bundle: {e}"))?;
+	Ok(Imported { header, manifest, signed })
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::metadata::{Metadata, MetaFile};
+
+	/// A workdir with a hashfile in it, and a pending fetch needing it,
+	/// plus the INDEX-NEW that says so.  Gives back the hashes of both.
+	fn setup() -> (tempfile::TempDir, RtDirs, Manifest, [Sha256HashBuf; 2])
+	{
+		use crate::util::compress::write_hashfile;
+
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init("/".as_ref(), wd.path()).unwrap();
+
+		let hash = write_hashfile(rtdirs.files(), b"#!/bin/sh\necho hi\n");
+		let md = format!("world|base|/bin/x|f|0|0|0555|0|{hash}|\n\
+				world|base|/bin/y|f|0|0|0555|0|{hash}|\n");
+		let mdhash = write_hashfile(rtdirs.files(), md.as_bytes());
+
+		let mut new = Metadata::default();
+		let f = MetaFile { path: "/bin/x".into(), sha256: hash,
+				mode: 0o555, ..Default::default() };
+		new.files.insert("/bin/x".into(), f);
+		let vers = "14.2-RELEASE-p2".parse().unwrap();
+		let mani = Manifest::new_fetch(Metadata::default(), new, vers);
+		(wd, rtdirs, mani, [hash.to_buf(), mdhash.to_buf()])
+	}
+
+	fn running(v: &str) -> Version
+	{
+		let v: AVersion = v.parse().unwrap();
+		Version { kernel: v.clone(), user: v }
+	}
+
+	/// A header for the pending fetch, with the signed bits vouching for
+	/// its index (with INDEX-NEW `mdhash`) under a key made up on the
+	/// spot, and that key's KeyPrint.
+	fn signed(mani: &Manifest, from: Version, mdhash: &Sha256HashBuf)
+			-> (Header, SignedFiles, String)
+	{
+		use openssl::rsa::{Rsa, Padding};
+		use crate::util::hash::sha256_reader;
+
+		let rsa = Rsa::generate(2048).unwrap();
+		let key = rsa.public_key_to_pem().unwrap();
+		let kp = sha256_reader(&mut &key[..]).unwrap().to_string();
+
+		let tidx = format!("INDEX-ALL|{}\nINDEX-NEW|{mdhash}\n",
+				"ab".repeat(32)).into_bytes();
+		let th = sha256_reader(&mut &tidx[..]).unwrap();
+		let tag = format!("freebsd-update|amd64|14.2-RELEASE|2|{th}|{}",
+				1719792000);
+		let mut enc = vec![0; rsa.size() as usize];
+		let n = rsa.private_encrypt(tag.as_bytes(), &mut enc, Padding::PKCS1)
+				.unwrap();
+		enc.truncate(n);
+
+		let mut hdr = Header::new(mani, from, kp.clone(), "amd64".into(), 2);
+		let idx = MetadataIdx::parse(&tidx).unwrap();
+		hdr.meta_idx = Some(idx.clone_matching(&["new"]));
+		hdr.meta_idx_vers = Some("14.2-RELEASE".parse().unwrap());
+		(hdr, SignedFiles { key, tag: enc, tidx }, kp)
+	}
+
+	#[test]
+	fn roundtrip()
+	{
+		let (src, srcdirs, mani, hashes) = setup();
+		let [hash, mdhash] = hashes;
+		let from = running("14.2-RELEASE-p1");
+		let (hdr, sf, kp) = signed(&mani, from.clone(), &mdhash);
+
+		let out = src.path().join("pending.tar");
+		export(&out, false, &hdr, &mani, &sf, &hashes, &srcdirs).unwrap();
+
+		let dst = tempfile::tempdir().unwrap();
+		let dstdirs = RtDirs::init("/".as_ref(), dst.path()).unwrap();
+		let imp = import(&out, &dstdirs, dst.path(), &kp,
+				|h| h.check(&from, &kp, "amd64")).unwrap();
+		assert_eq!(imp.header, hdr);
+		assert_eq!(imp.manifest.version(), mani.version());
+		assert_eq!(imp.manifest.install_hashes(), mani.install_hashes());
+		assert_eq!((imp.signed.key, imp.signed.tag), (sf.key, sf.tag));
+		dstdirs.check_hashfile(&hash).unwrap();
+
+		// A system on some other version won't take it, and doesn't
+		// get anything put in its files dir.
+		std::fs::remove_file(dstdirs.hashfile(&hash)).unwrap();
+		let other = running("14.1-RELEASE-p5");
+		let err = import(&out, &dstdirs, dst.path(), &kp,
+				|h| h.check(&other, &kp, "amd64")).unwrap_err();
+		assert!(err.to_string().contains("but this system is running"),
+				"{err}");
+		assert!(!dstdirs.hashfile(&hash).exists());
+	}
+
+	#[test]
+	fn bad_file()
+	{
+		let (src, srcdirs, mani, hashes) = setup();
+		let [hash, mdhash] = hashes;
+		let from = running("14.2-RELEASE-p1");
+		let (hdr, sf, kp) = signed(&mani, from, &mdhash);
+
+		// Mangle the hashfile; it still goes in the bundle, but doesn't
+		// come out.
+		let mut gz = std::fs::read(srcdirs.hashfile(&hash)).unwrap();
+		let n = gz.len();
+		gz[n - 9] ^= 0xff;
+		std::fs::write(srcdirs.hashfile(&hash), gz).unwrap();
+
+		let out = src.path().join("pending.tar");
+		export(&out, false, &hdr, &mani, &sf, &hashes, &srcdirs).unwrap();
+
+		let dst = tempfile::tempdir().unwrap();
+		let dstdirs = RtDirs::init("/".as_ref(), dst.path()).unwrap();
+		let base = dst.path();
+		let err = import(&out, &dstdirs, base, &kp, |_| Ok(())).unwrap_err();
+		assert!(err.to_string().contains("Bad file in bundle"), "{err}");
+		assert!(!dstdirs.hashfile(&hash).exists());
+
+		// And something that's not a bundle at all
+		std::fs::write(&out, tar::tests::mk_tar(&[
+				tar::tests::T::File("etc/passwd", 0o644, "root")])).unwrap();
+		let err = import(&out, &dstdirs, base, &kp, |_| Ok(())).unwrap_err();
+		assert!(err.to_string().contains("Not a pending install bundle"),
+				"{err}");
+	}
+
+	#[test]
+	fn signed_check()
+	{
+		let (src, srcdirs, mani, hashes) = setup();
+		let from = running("14.2-RELEASE-p1");
+		let (hdr, sf, kp) = signed(&mani, from, &hashes[1]);
+		let err = |h: &Header, sf: &SignedFiles, kp: &str|
+				verify_signed(h, sf, kp).unwrap_err().to_string();
+		verify_signed(&hdr, &sf, &kp).unwrap();
+
+		// Some other key entirely
+		let other = "1a".repeat(32);
+		let e = err(&hdr, &sf, &other);
+		assert!(e.contains("Signed tag doesn't verify"), "{e}");
+
+		// An index the tag doesn't vouch for
+		let mut h2 = hdr.clone();
+		h2.meta_idx = Some(MetadataIdx::parse(format!("INDEX-ALL|{}\n",
+				"ef".repeat(32)).as_bytes()).unwrap());
+		let e = err(&h2, &sf, &kp);
+		assert!(e.contains("doesn't match the signed tINDEX (INDEX-ALL)"),
+				"{e}");
+
+		// Or the tINDEX it does, but fiddled with
+		let mut sf2 = SignedFiles { key: sf.key.clone(), tag: sf.tag.clone(),
+				tidx: sf.tidx.clone() };
+		sf2.tidx[10] ^= 1;
+		let e = err(&hdr, &sf2, &kp);
+		assert!(e.contains("Bad tINDEX hash"), "{e}");
+
+		// A different patch level than the tag's
+		let mut h2 = hdr.clone();
+		h2.to = "14.2-RELEASE-p3".parse().unwrap();
+		let e = err(&h2, &sf, &kp);
+		assert!(e.contains("signed tag is for -p2"), "{e}");
+
+		// And import won't take one it can't check, even if the header's
+		// OK; the hashfiles are fine to keep, being checked by name.
+		let out = src.path().join("pending.tar");
+		export(&out, false, &hdr, &mani, &sf, &hashes, &srcdirs).unwrap();
+		let dst = tempfile::tempdir().unwrap();
+		let dstdirs = RtDirs::init("/".as_ref(), dst.path()).unwrap();
+		let base = dst.path();
+		let e = import(&out, &dstdirs, base, &other, |_| Ok(())).unwrap_err();
+		assert!(e.to_string().starts_with("Bad bundle: Signed tag"), "{e}");
+	}
+
+	#[test]
+	fn forged()
+	{
+		use std::os::unix::fs::PermissionsExt as _;
+		use crate::util::compress::write_hashfile;
+
+		let (src, srcdirs, mani, hashes) = setup();
+		let from = running("14.2-RELEASE-p1");
+		let sys = tempfile::tempdir().unwrap();
+		let (_, base) = mani.cur_new();
+
+		// Bundle up a fetch with these cur/new, and bring it in to sys.
+		let bring = |cur: Metadata, new: Metadata| {
+			let vers = "14.2-RELEASE-p2".parse().unwrap();
+			let mani = Manifest::new_fetch(cur, new, vers);
+			let (mut hdr, sf, kp) = signed(&mani, from.clone(), &hashes[1]);
+			let mut hs: Vec<_> = mani.install_hashes().iter()
+					.map(|h| h.to_buf()).chain([hashes[1]]).collect();
+			hs.sort_unstable();
+			hs.dedup();
+			hdr.nfiles = hs.len();
+			let out = src.path().join("pending.tar");
+			export(&out, false, &hdr, &mani, &sf, &hs, &srcdirs).unwrap();
+
+			let dst = tempfile::tempdir().unwrap();
+			let dstdirs = RtDirs::init("/".as_ref(), dst.path()).unwrap();
+			import(&out, &dstdirs, sys.path(), &kp, |_| Ok(()))
+					.map(|_| ()).map_err(|e| e.to_string())
+		};
+		bring(Metadata::default(), base.clone()).unwrap();
+
+		// Something upstream never said anything about
+		let mut new = base.clone();
+		let sha256 = write_hashfile(srcdirs.files(), b"toor::0:0::0:0::/:\n");
+		let path = "/etc/master.passwd";
+		new.files.insert(path.into(), MetaFile { path: path.into(), sha256,
+				mode: 0o600, ..Default::default() });
+		let e = bring(Metadata::default(), new).unwrap_err();
+		assert!(e.starts_with("Bad bundle: /etc/master.passwd isn't"), "{e}");
+
+		// Or something it did, with different perms
+		let mut new = base.clone();
+		new.files.get_mut(Path::new("/bin/x")).unwrap().mode = 0o4555;
+		let e = bring(Metadata::default(), new).unwrap_err();
+		assert!(e.starts_with("Bad bundle: /bin/x isn't"), "{e}");
+
+		// Or taking away something it still has
+		let e = bring(base.clone(), Metadata::default()).unwrap_err();
+		assert!(e.contains("would remove /bin/x, which upstream still"),
+				"{e}");
+
+		// But with what's here, upstream's file can have our perms
+		// (KeepModifiedMetadata), and anything can stay as it is.
+		std::fs::create_dir(sys.path().join("bin")).unwrap();
+		for (f, mode) in [("bin/x", 0o700), ("bin/z", 0o755)]
+		{
+			let f = sys.path().join(f);
+			std::fs::write(&f, "#!/bin/sh\necho older\n").unwrap();
+			let perm = std::fs::Permissions::from_mode(mode);
+			std::fs::set_permissions(&f, perm).unwrap();
+		}
+		write_hashfile(srcdirs.files(), b"#!/bin/sh\necho older\n");
+		let paths = vec!["/bin/x".into(), "/bin/z".into()];
+		let local = crate::core::scan::scan(sys.path().into(), paths).unwrap();
+		let lx = &local.files[Path::new("/bin/x")];
+		let mut new = base.clone();
+		let x = new.files.get_mut(Path::new("/bin/x")).unwrap();
+		(x.uid, x.gid, x.mode) = (lx.uid, lx.gid, lx.mode);
+		let z = local.files[Path::new("/bin/z")].clone();
+		new.files.insert("/bin/z".into(), z);
+		bring(Metadata::default(), new).unwrap();
+	}
+
+	#[test]
+	fn header_check()
+	{
+		let (_wd, _rtdirs, mani, _hash) = setup();
+		let from = running("14.2-RELEASE-p1");
+		let hdr = Header::new(&mani, from.clone(), "kp".into(),
+				"amd64".into(), 1);
+
+		assert!(hdr.check(&from, "kp", "amd64").is_ok());
+		let err = hdr.check(&from, "other", "amd64").unwrap_err();
+		assert!(err.to_string().contains("KeyPrint"), "{err}");
+		let err = hdr.check(&from, "kp", "arm64").unwrap_err();
+		assert!(err.to_string().contains("arm64"), "{err}");
+
+		// Kernel and world both have to match
+		let mut split = from.clone();
+		split.user = "14.2-RELEASE".parse().unwrap();
+		assert!(hdr.check(&split, "kp", "amd64").is_err());
+	}
+}
@@ -0,0 +1,157 @@
+//! Databases derived from other files.
+//!
+//! Things like pwd.db get generated from a source file (master.passwd)
+//! by some tool (pwd_mkdb), and that output isn't byte-for-byte
+//! reproducible.  So there's no point merging them, installing
+//! upstream's copy just to regenerate it right after, or getting excited
+//! about their hashes not matching.
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+
+/// How a derived file gets regenerated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Regen
+{
+	/// pwd_mkdb(8) from master.passwd
+	PwdMkdb,
+
+	/// cap_mkdb(1) on login.conf
+	LoginConf,
+
+	/// cap_mkdb(1) on termcap
+	Termcap,
+
+	/// services_mkdb(8) from services
+	ServicesMkdb,
+}
+
+impl Regen
+{
+	/// The ones install always runs after world, derived files or no.
+	pub(crate) const ALWAYS: &[Regen] = &[Regen::PwdMkdb, Regen::LoginConf];
+}
+
+
+/// One derived file
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Derived
+{
+	/// The derived file
+	pub(crate) path: &'static str,
+
+	/// What it's made from
+	pub(crate) source: &'static str,
+
+	/// How it's made
+	pub(crate) regen: Regen,
+}
+
+
+/// All the derived files we know about.
+pub(crate) const DERIVED: &[Derived] = &[
+	Derived { path: "/etc/passwd",     source: "/etc/master.passwd",
+			regen: Regen::PwdMkdb },
+	Derived { path: "/etc/pwd.db",     source: "/etc/master.passwd",
+			regen: Regen::PwdMkdb },
+	Derived { path: "/etc/spwd.db",    source: "/etc/master.passwd",
+			regen: Regen::PwdMkdb },
+	Derived { path: "/etc/login.conf.db", source: "/etc/login.conf",
+			regen: Regen::LoginConf },
+	Derived { path: "/usr/share/misc/termcap.db",
+			source: "/usr/share/misc/termcap", regen: Regen::Termcap },
+	Derived { path: "/var/db/services.db", source: "/etc/services",
+			regen: Regen::ServicesMkdb },
+];
+
+
+/// Is this path a derived file?
+pub(crate) fn lookup(p: &Path) -> Option<&'static Derived>
+{
+	DERIVED.iter().find(|d| p == Path::new(d.path))
+}
+
+
+/// Sort out which derived files not to install from upstream, out of a
+/// set of paths we're installing.  That's any whose source is getting
+/// installed too; it'll be regenerated afterward instead, so we don't
+/// write it twice, with a window where it disagrees with its source.
+///
+/// Returns the paths to skip, and what'll need regenerating.
+pub(crate) fn skip_installing<'a>(installing: &HashSet<&'a Path>)
+		-> (Vec<&'a Path>, BTreeSet<Regen>)
+{
+	let mut skip = Vec::new();
+	let mut regen = BTreeSet::new();
+	for p in installing
+	{
+		let Some(d) = lookup(p) else { continue };
+		if !installing.contains(Path::new(d.source)) { continue; }
+		skip.push(*p);
+		regen.insert(d.regen);
+	}
+	skip.sort_unstable();
+	(skip, regen)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashSet;
+	use std::path::Path;
+	use super::{lookup, Regen};
+
+	#[test]
+	fn table()
+	{
+		let d = lookup(Path::new("/etc/spwd.db")).unwrap();
+		assert_eq!(d.source, "/etc/master.passwd");
+		assert_eq!(d.regen, Regen::PwdMkdb);
+		let d = lookup(Path::new("/etc/login.conf.db")).unwrap();
+		assert_eq!(d.source, "/etc/login.conf");
+		let d = lookup(Path::new("/var/db/services.db")).unwrap();
+		assert_eq!(d.regen, Regen::ServicesMkdb);
+
+		// Sources aren't derived, and neither are lookalikes.
+		assert!(lookup(Path::new("/etc/master.passwd")).is_none());
+		assert!(lookup(Path::new("/etc/login.conf")).is_none());
+		assert!(lookup(Path::new("/jail/etc/pwd.db")).is_none());
+
+		// Every source is a real path, not derived itself.
+		for d in super::DERIVED
+		{
+			assert!(d.source.starts_with('/'), "{d:?}");
+			assert!(lookup(Path::new(d.source)).is_none(), "{d:?}");
+		}
+	}
+
+	#[test]
+	fn skip_installing()
+	{
+		let p = Path::new;
+
+		// master.passwd is coming along, so its dbs get regenerated
+		// instead; login.conf isn't, so its db is installed as usual.
+		let inst = HashSet::from([p("/etc/master.passwd"), p("/etc/pwd.db"),
+				p("/etc/spwd.db"), p("/etc/login.conf.db"), p("/bin/sh")]);
+		let (skip, regen) = super::skip_installing(&inst);
+		assert_eq!(skip, [p("/etc/pwd.db"), p("/etc/spwd.db")]);
+		assert_eq!(regen.into_iter().collect::<Vec<_>>(), [Regen::PwdMkdb]);
+
+		// Nothing derived, nothing to do.
+		let inst = HashSet::from([p("/etc/master.passwd"), p("/bin/sh")]);
+		let (skip, regen) = super::skip_installing(&inst);
+		assert!(skip.is_empty());
+		assert!(regen.is_empty());
+
+		// Both halves of a couple
+		let inst = HashSet::from([p("/etc/services"), p("/var/db/services.db"),
+				p("/usr/share/misc/termcap"), p("/usr/share/misc/termcap.db")]);
+		let (skip, regen) = super::skip_installing(&inst);
+		assert_eq!(skip.len(), 2);
+		assert_eq!(regen.into_iter().collect::<Vec<_>>(),
+				[Regen::Termcap, Regen::ServicesMkdb]);
+	}
+}
@@ -0,0 +1,510 @@
+//! Release distribution sets as a source of metadata.
+//!
+//! Systems that can't reach an update server may still have the release
+//! media around.  The base.txz/kernel.txz/etc. sets on it can stand in
+//! for INDEX-ALL; we walk through the archives to build up the same
+//! Metadata the server would give us, and for extract, pull the file
+//! contents straight out of them into the files dir.
+//!
+//! Naturally, the sets only know what the RELEASE shipped with, so
+//! anything patched since then is going to look different.
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::components::Component;
+use crate::core::RtDirs;
+use crate::metadata::{Metadata, MetadataGroup, IgnoreReport};
+use crate::metadata::{MetaFile, MetaDir, MetaSymLink, MetaHardLink};
+use crate::util::hash::Sha256Hash;
+use crate::util::tar;
+
+
+/// The sets we know about, and what component they are.
+const SETS: &[(&str, &str)] = &[
+	("base.txz",       "world/base"),
+	("base-dbg.txz",   "world/base-dbg"),
+	("lib32.txz",      "world/lib32"),
+	("lib32-dbg.txz",  "world/lib32-dbg"),
+	("kernel.txz",     "kernel/generic"),
+	("kernel-dbg.txz", "kernel/generic-dbg"),
+	("src.txz",        "src/src"),
+];
+
+/// What we decompress with
+const XZ: &str = "/usr/bin/xz";
+
+/// Bump if what we derive from a set changes, to ignore older caches.
+const CACHE_VERS: u32 = 1;
+
+
+/// One of the sets.
+#[derive(Debug)]
+pub(crate) struct DistSet
+{
+	/// The .txz
+	pub(crate) path: PathBuf,
+
+	/// What component it holds
+	pub(crate) comp: Component,
+
+	/// Hash of the .txz, which is what we key the cache on
+	pub(crate) sha256: Sha256Hash,
+}
+
+impl DistSet
+{
+	fn name(&self) -> std::borrow::Cow<'_, str>
+	{
+		self.path.file_name().unwrap_or_default().to_string_lossy()
+	}
+}
+
+
+/// What we got out of the sets; roughly, what mdfetch::acquire() gets
+/// out of the server.
+#[derive(Debug)]
+pub(crate) struct DistMetadata
+{
+	/// The sets we found
+	pub(crate) sets: Vec<DistSet>,
+
+	/// The metadata, with components and ignores already filtered, as
+	/// with the INDEX files.
+	pub(crate) all: MetadataGroup,
+
+	/// What IgnorePaths removed
+	pub(crate) ignored: IgnoreReport,
+}
+
+
+/// Find the sets in a dir, and load up their metadata.
+pub(crate) fn acquire(dir: &Path, rtdirs: &RtDirs,
+		config: &crate::config::Config)
+		-> Result<DistMetadata, anyhow::Error>
+{
+	println!("Reading distribution sets from {}:", dir.display());
+	let sets = find(dir)?;
+	let mut all = metadata(&sets, &rtdirs.distcache()?)?;
+
+	all.keep_components(&config.components);
+	let ignored = all.remove_paths_matching_report(&config.ignore_paths);
+	all.rewrite_kern_dirs()?;
+
+	println!("NOTE: Using only the RELEASE contents of the distribution \
+			sets.\n  Any patches released since then aren't reflected, and \
+			will\n  show up as differences.");
+	Ok(DistMetadata { sets, all, ignored })
+}
+
+
+/// Find (and hash) the sets in a dir.  If there's a MANIFEST, as on the
+/// release media, the hashes had better match it.
+pub(crate) fn find(dir: &Path) -> Result<Vec<DistSet>, anyhow::Error>
+{
+	let manifest = read_manifest(dir);
+
+	let mut ret = Vec::new();
+	for (name, comp) in SETS
+	{
+		let path = dir.join(name);
+		if !path.is_file() { continue; }
+
+		let sha256 = crate::util::hash::sha256_file(&path)?;
+		if let Some(mh) = manifest.as_ref().and_then(|m| m.get(*name))
+		{
+			if *mh != sha256.to_string()
+			{
+				anyhow::bail!("{}: hash {sha256} doesn't match MANIFEST \
+						({mh})", path.display());
+			}
+		}
+
+		let comp = comp.parse().map_err(|e| anyhow::anyhow!("{e}"))?;
+		ret.push(DistSet { path, comp, sha256 });
+	}
+
+	if ret.is_empty()
+	{
+		anyhow::bail!("No distribution sets (base.txz etc.) found in {}",
+				dir.display());
+	}
+	Ok(ret)
+}
+
+
+/// The MANIFEST is lines of tab-separated "name hash count ...".
+fn read_manifest(dir: &Path) -> Option<HashMap<String, String>>
+{
+	let mf = std::fs::read_to_string(dir.join("MANIFEST")).ok()?;
+	let ret = mf.lines().filter_map(|l| {
+		let mut fields = l.split('\t');
+		Some((fields.next()?.to_string(), fields.next()?.to_string()))
+	}).collect();
+	Some(ret)
+}
+
+
+/// Get the Metadata for each set, from the cache if we've done it
+/// before, else by reading through the archive.
+pub(crate) fn metadata(sets: &[DistSet], cachedir: &Path)
+		-> Result<MetadataGroup, anyhow::Error>
+{
+	use std::io::Write as _;
+
+	let mut md = HashMap::with_capacity(sets.len());
+	for s in sets
+	{
+		print!("  {} ({})...  ", s.name(), s.comp);
+		std::io::stdout().flush()?;
+
+		let cfile = cachedir.join(format!("{}-v{CACHE_VERS}.json", s.sha256));
+		let m = match load_cache(&cfile) {
+			Some(m) => {
+				println!("{} entries (cached).", m.len());
+				m
+			},
+			None => {
+				let m = read_set(s)?;
+				save_cache(&cfile, &m)?;
+				println!("{} entries.", m.len());
+				m
+			},
+		};
+		md.insert(s.comp, m);
+	}
+
+	Ok(MetadataGroup::from_components(md))
+}
+
+
+fn load_cache(file: &Path) -> Option<Metadata>
+{
+	let fh = std::fs::File::open(file).ok()?;
+	serde_json::from_reader(BufReader::new(fh)).ok()
+}
+
+
+fn save_cache(file: &Path, md: &Metadata) -> Result<(), anyhow::Error>
+{
+	use std::io::BufWriter;
+
+	let dir = file.parent().unwrap_or(Path::new("."));
+	let tf = tempfile::NamedTempFile::new_in(dir)?;
+	let mut bw = BufWriter::new(tf);
+	serde_json::to_writer(&mut bw, md)?;
+	bw.into_inner()?.persist(file)?;
+	Ok(())
+}
+
+
+/// Start decompressing a set, and hand back a tar reader on it.
+fn open(set: &DistSet)
+		-> Result<(Child, tar::Reader<BufReader<ChildStdout>>), anyhow::Error>
+{
+	let mut child = Command::new(XZ).arg("-dc").arg(&set.path)
+			.stdout(Stdio::piped()).spawn()
+			.map_err(|e| anyhow::anyhow!("Running {XZ}: {e}"))?;
+	let out = child.stdout.take().expect("asked for stdout");
+	Ok((child, tar::Reader::new(BufReader::new(out))))
+}
+
+
+/// Wait for the decompressor to finish up.  If we stopped reading early,
+/// we just kill it.
+fn close(set: &DistSet, mut child: Child, early: bool)
+		-> Result<(), anyhow::Error>
+{
+	if early { child.kill().ok(); }
+	let status = child.wait()?;
+	if !early && !status.success()
+	{ anyhow::bail!("{XZ} failed on {}: {status}", set.path.display()); }
+	Ok(())
+}
+
+
+/// Walk through a set building up its Metadata.
+fn read_set(set: &DistSet) -> Result<Metadata, anyhow::Error>
+{
+	let (child, mut rdr) = open(set)?;
+	let md = build_metadata(&mut rdr)
+			.map_err(|e| anyhow::anyhow!("{}: {e}", set.path.display()));
+	close(set, child, md.is_err())?;
+	md
+}
+
+
+/// Turn a stream of tar entries into Metadata, hashing the files along
+/// the way.
+pub(crate) fn build_metadata(rdr: &mut tar::Reader<impl Read>)
+		-> Result<Metadata, std::io::Error>
+{
+	use sha2::{Sha256, Digest as _};
+	use tar::Kind as K;
+
+	let mut md = Metadata::default();
+	while let Some(e) = rdr.next()?
+	{
+		let path = match norm(&e.path) {
+			Some(p) => p,
+			None => continue,
+		};
+		let (uid, gid, mode) = (e.uid, e.gid, e.mode);
+		let flags = e.fflags.as_deref().map(crate::core::install::flags_parse)
+				.unwrap_or(0);
+
+		match e.kind
+		{
+			K::File => {
+				let mut hasher = Sha256::new();
+				rdr.data(&mut hasher)?;
+				let sha256 = <[u8; 32]>::from(hasher.finalize()).into();
+				let f = MetaFile { path: path.clone(), sha256, uid, gid, mode,
+						flags };
+				md.files.insert(path, f);
+			},
+			K::Dir => {
+				let d = MetaDir { path: path.clone(), uid, gid, mode, flags };
+				md.dirs.insert(path, d);
+			},
+			K::Symlink(target) => {
+				let s = MetaSymLink { path: path.clone(), target, uid, gid,
+						mode, flags };
+				md.symlinks.insert(path, s);
+			},
+			K::Hardlink(target) => {
+				let target = match norm(&target) {
+					Some(t) => t,
+					None => continue,
+				};
+				let h = MetaHardLink { path: path.clone(), target };
+				md.hardlinks.insert(path, h);
+			},
+			K::Other(_) => (),
+		}
+	}
+
+	Ok(md)
+}
+
+
+/// Archive paths are like "./bin/sh"; we want "/bin/sh".  The top-level
+/// "./" isn't anything we track.
+fn norm(p: &Path) -> Option<PathBuf>
+{
+	use std::os::unix::ffi::OsStrExt as _;
+
+	let b = p.as_os_str().as_bytes();
+	let b = b.strip_prefix(b".").unwrap_or(b);
+	let end = b.iter().rposition(|c| *c != b'/')? + 1;
+	let b = &b[..end];
+
+	let mut ret = PathBuf::from("/");
+	ret.push(std::ffi::OsStr::from_bytes(b.strip_prefix(b"/").unwrap_or(b)));
+	Some(ret)
+}
+
+
+/// Pull the given files out of the sets, and stash them gzip'd in the
+/// files dir, as if we'd downloaded them.  Returns how many we stashed.
+pub(crate) fn stash(sets: &[DistSet], want: &HashMap<PathBuf, Sha256Hash>,
+		rtdirs: &RtDirs) -> Result<usize, anyhow::Error>
+{
+	let mut left = want.clone();
+	let mut ret = 0;
+
+	for s in sets
+	{
+		if left.is_empty() { break; }
+
+		let (child, mut rdr) = open(s)?;
+		let res = stash_from(&mut rdr, &mut left, rtdirs)
+				.map_err(|e| anyhow::anyhow!("{}: {e}", s.path.display()));
+		let early = res.is_err() || left.is_empty();
+		close(s, child, early)?;
+		ret += res?;
+	}
+
+	if !left.is_empty()
+	{
+		let mut miss: Vec<_> = left.keys().map(|p| p.display().to_string())
+				.collect();
+		miss.sort_unstable();
+		anyhow::bail!("{} file(s) not found in the distribution sets: {}",
+				miss.len(), miss.join(", "));
+	}
+	Ok(ret)
+}
+
+
+/// Stash whatever of `left` we find in one set, removing them from it.
+fn stash_from(rdr: &mut tar::Reader<impl Read>,
+		left: &mut HashMap<PathBuf, Sha256Hash>, rtdirs: &RtDirs)
+		-> Result<usize, anyhow::Error>
+{
+	use flate2::{write::GzEncoder, Compression};
+	use sha2::{Sha256, Digest as _};
+
+	let mut ret = 0;
+	while let Some(e) = rdr.next()?
+	{
+		if left.is_empty() { break; }
+		if e.kind != tar::Kind::File { continue; }
+		let path = match norm(&e.path) {
+			Some(p) => p,
+			None => continue,
+		};
+		let expect = match left.remove(&path) {
+			Some(h) => h,
+			None => continue,
+		};
+
+		// Compress it into a tempfile, hashing as we go, and move it
+		// into place if it's what we expected.
+		let tf = tempfile::NamedTempFile::new_in(rtdirs.tmp())?;
+		let mut tee = Tee { hasher: Sha256::new(),
+				out: GzEncoder::new(tf, Compression::default()) };
+		rdr.data(&mut tee)?;
+		let got: Sha256Hash = <[u8; 32]>::from(tee.hasher.finalize()).into();
+		let tf = tee.out.finish()?;
+
+		if got != expect
+		{
+			anyhow::bail!("{} hashed to {got}, expected {expect}",
+					path.display());
+		}
+		tf.persist(rtdirs.hashfile(&got.to_buf()))?;
+		ret += 1;
+	}
+	Ok(ret)
+}
+
+
+/// Hash what we're writing on the way through.
+struct Tee<W>
+{
+	hasher: sha2::Sha256,
+	out: W,
+}
+
+impl<W: std::io::Write> std::io::Write for Tee<W>
+{
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+	{
+		use sha2::Digest as _;
+		let n = self.out.write(buf)?;
+		self.hasher.update(&buf[..n]);
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> { self.out.flush() }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::Path;
+	use crate::util::tar::{self, tests::{mk_tar, T}};
+
+	#[test]
+	fn norm()
+	{
+		use super::norm;
+		let n = |p: &str| norm(Path::new(p)).map(|p| p.display().to_string());
+		assert_eq!(n("./bin/sh").as_deref(), Some("/bin/sh"));
+		assert_eq!(n("./usr/share/").as_deref(), Some("/usr/share"));
+		assert_eq!(n("bin/sh").as_deref(), Some("/bin/sh"));
+		assert_eq!(n("./"), None);
+		assert_eq!(n("."), None);
+	}
+
+	#[test]
+	fn build_metadata()
+	{
+		let tar = mk_tar(&[
+			T::Dir("./", 0o755),
+			T::Dir("./bin/", 0o755),
+			T::Pax(&[("SCHILY.fflags", "schg")]),
+			T::File("./bin/sh", 0o555, "echo hi\n"),
+			T::Hard("./bin/rsh", "./bin/sh"),
+			T::Sym("./bin/ksh", "sh"),
+		]);
+		let mut rdr = tar::Reader::new(&tar[..]);
+		let md = super::build_metadata(&mut rdr).unwrap();
+
+		assert_eq!(md.dirs.len(), 1);
+		assert_eq!(md.dirs[Path::new("/bin")].mode, 0o755);
+
+		let sh = &md.files[Path::new("/bin/sh")];
+		let sha = crate::util::hash::sha256_reader(&mut &b"echo hi\n"[..])
+				.unwrap();
+		assert_eq!((sh.sha256, sh.mode, sh.gid), (sha, 0o555, 5));
+		assert_eq!(sh.flags, libc::SF_IMMUTABLE as u32);
+
+		assert_eq!(md.hardlinks[Path::new("/bin/rsh")].target,
+				Path::new("/bin/sh"));
+		assert_eq!(md.symlinks[Path::new("/bin/ksh")].target, Path::new("sh"));
+	}
+
+	#[test]
+	fn stash()
+	{
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = crate::core::RtDirs::init("/".as_ref(), wd.path())
+				.unwrap();
+
+		let tar = mk_tar(&[
+			T::File("./bin/sh", 0o555, "echo hi\n"),
+			T::File("./bin/csh", 0o555, "echo bye\n"),
+		]);
+		let mut rdr = tar::Reader::new(&tar[..]);
+		let md = super::build_metadata(&mut rdr).unwrap();
+
+		// Just want the one
+		let sh = md.files[Path::new("/bin/sh")].sha256;
+		let mut left = [("/bin/sh".into(), sh)].into();
+		let mut rdr = tar::Reader::new(&tar[..]);
+		assert_eq!(super::stash_from(&mut rdr, &mut left, &rtdirs).unwrap(), 1);
+		assert!(left.is_empty());
+
+		let hf = rtdirs.hashfile(&sh.to_buf());
+		let got = crate::util::compress::decompress_to_vec(&hf).unwrap();
+		assert_eq!(got, b"echo hi\n");
+
+		// Wrong content is an error
+		let csh = md.files[Path::new("/bin/csh")].sha256;
+		let mut left = [("/bin/sh".into(), csh)].into();
+		let mut rdr = tar::Reader::new(&tar[..]);
+		assert!(super::stash_from(&mut rdr, &mut left, &rtdirs).is_err());
+	}
+
+	#[test]
+	fn manifest()
+	{
+		let td = tempfile::tempdir().unwrap();
+		std::fs::write(td.path().join("base.txz"), "not really").unwrap();
+
+		// No MANIFEST, sure
+		let sets = super::find(td.path()).unwrap();
+		assert_eq!(sets.len(), 1);
+		assert_eq!(sets[0].comp.to_string(), "world/base");
+
+		// Matching MANIFEST
+		let mf = format!("base.txz\t{}\t1234\tbase\t\"Base system\"\ton\n",
+				sets[0].sha256);
+		std::fs::write(td.path().join("MANIFEST"), mf).unwrap();
+		super::find(td.path()).unwrap();
+
+		// Mismatched
+		std::fs::write(td.path().join("base.txz"), "changed").unwrap();
+		let e = super::find(td.path()).unwrap_err().to_string();
+		assert!(e.contains("doesn't match MANIFEST"), "{e}");
+
+		// Nothing at all
+		let td = tempfile::tempdir().unwrap();
+		assert!(super::find(td.path()).is_err());
+	}
+}
@@ -0,0 +1,295 @@
+//! Keeping the files dir under MaxWorkdirSize.
+//!
+//! The files dir is nothing but a cache of what we've downloaded (and
+//! stashed), and it's where nearly all of a workdir's space goes.  So
+//! when it's over the cap, we throw out what's been used least recently
+//! until it isn't, never touching anything a pending install (or the
+//! fetch/upgrade in progress) needs.  Nothing keeps an index of what's
+//! in there besides the dir itself; everybody looks for <hash>.gz when
+//! they want it, so anything evicted just gets fetched again if it's
+//! ever wanted.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::util::hash::Sha256Hash;
+use crate::util::human_bytes;
+
+
+/// A file in the files dir
+#[derive(Debug, Clone)]
+pub(crate) struct Entry
+{
+	pub(crate) path: PathBuf,
+
+	/// Whose hashfile it is, if it's one
+	pub(crate) hash: Option<Sha256Hash>,
+
+	pub(crate) size: u64,
+
+	/// When it was last used, as well as we can tell.  That's the atime,
+	/// unless the fs is noatime, in which case the mtime (when we
+	/// fetched it) is at least newer.
+	pub(crate) used: i64,
+}
+
+
+/// Look over what's in the files dir.
+pub(crate) fn scan(dir: &Path) -> Result<Vec<Entry>, std::io::Error>
+{
+	use std::os::unix::fs::MetadataExt as _;
+
+	let mut ret = Vec::new();
+	for de in std::fs::read_dir(dir)?
+	{
+		let de = de?;
+		let md = de.metadata()?;
+		if !md.is_file() { continue; }
+
+		let hash = de.file_name().to_str()
+				.and_then(|f| f.strip_suffix(".gz"))
+				.and_then(|h| h.parse().ok());
+		ret.push(Entry { path: de.path(), hash, size: md.len(),
+				used: md.atime().max(md.mtime()) });
+	}
+	Ok(ret)
+}
+
+
+/// What to throw out to get under the cap
+#[derive(Debug, Default)]
+pub(crate) struct Plan
+{
+	/// Oldest first
+	pub(crate) evict: Vec<PathBuf>,
+
+	/// How much that gets back
+	pub(crate) freed: u64,
+
+	/// How much there was to start with
+	pub(crate) total: u64,
+}
+
+
+/// What we can't throw out is already more than the cap.
+#[derive(Debug, thiserror::Error)]
+#[error("MaxWorkdirSize ({}) is too small for this update; it needs at \
+		least {}", human_bytes(*.cap), human_bytes(*.needed))]
+pub(crate) struct TooSmall
+{
+	pub(crate) cap: u64,
+	pub(crate) needed: u64,
+}
+
+
+/// Work out what to evict from the files dir to get down to cap,
+/// without touching anything in keep, and leaving room for incoming
+/// bytes more.
+pub(crate) fn plan(mut ents: Vec<Entry>, keep: &HashSet<Sha256Hash>,
+		cap: u64, incoming: u64) -> Result<Plan, TooSmall>
+{
+	let kept = |e: &Entry| e.hash.as_ref().map_or(false, |h| keep.contains(h));
+	let total: u64 = ents.iter().map(|e| e.size).sum();
+	let needed: u64 = ents.iter().filter(|e| kept(e)).map(|e| e.size).sum::<u64>()
+			+ incoming;
+	if needed > cap { return Err(TooSmall { cap, needed }); }
+	let cap = cap - incoming;
+
+	let mut ret = Plan { total, ..Default::default() };
+	if total <= cap { return Ok(ret); }
+
+	// Since what's left is no more than the cap, going through all of
+	// these always gets us there.
+	ents.retain(|e| !kept(e));
+	ents.sort_unstable_by(|a, b| a.used.cmp(&b.used)
+			.then_with(|| a.path.cmp(&b.path)));
+	for e in ents
+	{
+		if total - ret.freed <= cap { break; }
+		ret.freed += e.size;
+		ret.evict.push(e.path);
+	}
+	Ok(ret)
+}
+
+
+/// What a pending fetch/upgrade still needs kept, if there is one.
+pub(crate) fn pending(state: &crate::state::State) -> HashSet<Sha256Hash>
+{
+	state.manifest.as_ref().map(|m| m.cache_hashes()).unwrap_or_default()
+}
+
+
+/// About how much nfiles more hashfiles will take.  There's no telling
+/// until they're downloaded, so guess they're like what's already
+/// there.
+fn estimate(ents: &[Entry], nfiles: usize) -> u64
+{
+	/// With nothing to go on, call it this
+	const GUESS: u64 = 64 << 10;
+
+	let hfs: Vec<_> = ents.iter().filter(|e| e.hash.is_some()).collect();
+	let avg = match hfs.len() {
+		0 => GUESS,
+		n => hfs.iter().map(|e| e.size).sum::<u64>() / n as u64,
+	};
+	avg * nfiles as u64
+}
+
+
+/// Get the files dir under the cap, if there is one, with room for
+/// incoming more hashfiles about to be downloaded, and say so if we had
+/// to do anything about it.
+pub(crate) fn enforce(files: &Path, cap: Option<u64>,
+		keep: &HashSet<Sha256Hash>, incoming: usize)
+		-> Result<(), anyhow::Error>
+{
+	let cap = match cap {
+		Some(c) => c,
+		None => return Ok(()),
+	};
+	let ents = scan(files)?;
+	let incoming = estimate(&ents, incoming);
+	let plan = plan(ents, keep, cap, incoming)?;
+	if plan.evict.is_empty() { return Ok(()); }
+
+	for f in &plan.evict
+	{
+		match std::fs::remove_file(f) {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound
+					=> return Err(e.into()),
+			_ => (),
+		}
+	}
+
+	let n = plan.evict.len();
+	let room = match incoming {
+		0 => String::new(),
+		i => format!(" with room for ~{} more", human_bytes(i)),
+	};
+	println!("Files dir was {}, over MaxWorkdirSize {}{room}; evicted {n} \
+			least recently used file{}, freeing {}.",
+			human_bytes(plan.total), human_bytes(cap),
+			crate::util::plural(n), human_bytes(plan.freed));
+	Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashSet;
+	use std::path::PathBuf;
+
+	use super::Entry;
+	use crate::util::hash::Sha256Hash;
+
+	fn hash(n: u8) -> Sha256Hash { [n; 32].into() }
+
+	/// A hashfile n, of size KB, last used at used
+	fn ent(n: u8, size: u64, used: i64) -> Entry
+	{
+		let hash = hash(n);
+		Entry { path: format!("/files/{hash}.gz").into(), hash: Some(hash),
+				size: size << 10, used }
+	}
+
+	fn evicted(plan: &super::Plan) -> Vec<PathBuf> { plan.evict.clone() }
+
+	#[test]
+	fn plan()
+	{
+		use super::plan;
+		let ents = vec![ent(1, 100, 50), ent(2, 100, 10), ent(3, 100, 30),
+				ent(4, 100, 20), ent(5, 100, 40)];
+		let none = HashSet::new();
+
+		// Under the cap, nothing to do
+		let p = plan(ents.clone(), &none, 500 << 10, 0).unwrap();
+		assert!(p.evict.is_empty());
+		assert_eq!(p.total, 500 << 10);
+
+		// Least recently used go first, and only as many as it takes
+		let p = plan(ents.clone(), &none, 300 << 10, 0).unwrap();
+		assert_eq!(evicted(&p), [ents[1].path.clone(), ents[3].path.clone()]);
+		assert_eq!(p.freed, 200 << 10);
+
+		// Unless they're needed, however old they are
+		let keep: HashSet<_> = [hash(2), hash(4)].into();
+		let p = plan(ents.clone(), &keep, 300 << 10, 0).unwrap();
+		assert_eq!(evicted(&p), [ents[2].path.clone(), ents[4].path.clone()]);
+
+		// And if the needed ones alone are over, that's an error,
+		// rather than evicting them.
+		let keep: HashSet<_> = [hash(1), hash(2), hash(3)].into();
+		let e = plan(ents.clone(), &keep, 250 << 10, 0).unwrap_err();
+		assert_eq!((e.cap, e.needed), (250 << 10, 300 << 10));
+		assert!(e.to_string().contains("needs at least 300.0K"), "{e}");
+
+		// Exactly the needed ones fit, so everything else goes
+		let p = plan(ents.clone(), &keep, 300 << 10, 0).unwrap();
+		assert_eq!(p.evict.len(), 2);
+		assert!(!p.evict.contains(&ents[0].path));
+
+		// Things that aren't hashfiles are fair game too
+		let mut ents = ents;
+		ents.push(Entry { path: "/files/junk".into(), hash: None,
+				size: 100 << 10, used: 0 });
+		let p = plan(ents.clone(), &none, 500 << 10, 0).unwrap();
+		assert_eq!(evicted(&p), [PathBuf::from("/files/junk")]);
+
+		// What's about to come in needs room too, and counts as needed
+		let p = plan(ents.clone(), &none, 500 << 10, 150 << 10).unwrap();
+		assert_eq!(p.freed, 300 << 10);
+		let keep: HashSet<_> = [hash(1), hash(2), hash(3)].into();
+		let e = plan(ents, &keep, 350 << 10, 100 << 10).unwrap_err();
+		assert_eq!(e.needed, 400 << 10);
+	}
+
+	#[test]
+	fn estimate()
+	{
+		let ents = vec![ent(1, 100, 0), ent(2, 300, 0)];
+		assert_eq!(super::estimate(&ents, 3), 600 << 10);
+		assert_eq!(super::estimate(&ents, 0), 0);
+		assert_eq!(super::estimate(&[], 2), 128 << 10);
+	}
+
+	#[test]
+	fn enforce()
+	{
+		use std::time::{Duration, SystemTime};
+
+		let td = tempfile::tempdir().unwrap();
+		let mk = |n: u8, age: u64| {
+			let p = td.path().join(format!("{}.gz", hash(n)));
+			std::fs::write(&p, vec![0u8; 1024]).unwrap();
+			let t = SystemTime::now() - Duration::from_secs(age);
+			let f = std::fs::File::options().write(true).open(&p).unwrap();
+			f.set_times(std::fs::FileTimes::new().set_accessed(t)
+					.set_modified(t)).unwrap();
+			p
+		};
+		let (old, mid, new) = (mk(1, 300), mk(2, 200), mk(3, 100));
+
+		// No cap, no touching
+		super::enforce(td.path(), None, &HashSet::new(), 0).unwrap();
+		assert!(old.exists());
+
+		// The oldest would go, but it's needed, so the next one does
+		let keep: HashSet<_> = [hash(1)].into();
+		super::enforce(td.path(), Some(2048), &keep, 0).unwrap();
+		assert!(old.exists() && !mid.exists() && new.exists());
+
+		// And too small is too small
+		let keep: HashSet<_> = [hash(1), hash(3)].into();
+		super::enforce(td.path(), Some(1024), &keep, 0).unwrap_err();
+		assert!(old.exists() && new.exists());
+
+		// Including with what's about to come in
+		let keep: HashSet<_> = [hash(1)].into();
+		super::enforce(td.path(), Some(2048), &keep, 2).unwrap_err();
+		super::enforce(td.path(), Some(2048), &keep, 1).unwrap();
+		assert!(old.exists() && !new.exists());
+	}
+}
@@ -0,0 +1,215 @@
+//! Putting paths straight back the way upstream has them.
+//!
+//! This is the back half of `extract`, which `check-sys --fix` also uses.
+//! By the time we get here, the caller has whittled the metadata down to
+//! exactly what should be put in place; we get whatever file contents we
+//! don't have yet, and install it all.
+use std::collections::HashSet;
+use std::io::{stdout, Write as _};
+use std::path::Path;
+
+use crate::core::RtDirs;
+use crate::core::dist::DistSet;
+use crate::metadata::Metadata;
+use crate::server::Server;
+use crate::util::plural;
+
+
+/// Where file contents come from.
+pub(crate) enum Source<'a>
+{
+	/// Release distribution sets
+	Dist(&'a [DistSet]),
+
+	/// The update server.  Only found if we turn out to need it, since
+	/// often enough we've already got everything.
+	Server(Box<dyn FnOnce() -> Result<Server, anyhow::Error> + 'a>),
+}
+
+
+/// Get the files for, and install, everything in all.  With dry, just
+/// say what we'd do.  With owners, the ownership of what we install is
+/// added to that OwnerManifest file.
+pub(crate) fn from_metadata(all: Metadata, rtdirs: &RtDirs, basedir: &Path,
+		src: Source, dry: bool, owners: Option<&Path>)
+		-> Result<(), anyhow::Error>
+{
+	/*
+	 * Fetch necessary hashes
+	 */
+	let mut needhashes = all.hashes_no_hash_dir(rtdirs.files());
+	if dry
+	{
+		if let Some(nh) = needhashes
+		{
+			let nh = nh.len();
+			let how = match src {
+				Source::Dist(_)   => "extracted from the distribution sets",
+				Source::Server(_) => "downloaded",
+			};
+			println!("DRY RUN: {nh} file{} would need to be {how}.",
+					plural(nh));
+			needhashes = None;
+		}
+	}
+	match (needhashes, src) {
+		(None, _) => println!("All data files present."),
+		(Some(nh), Source::Dist(sets)) => {
+			// Pull them out of the archives instead of the server.
+			use crate::util::hash::Sha256HashBuf;
+			let nh: HashSet<&Sha256HashBuf> = nh.iter().collect();
+			let want = all.files.values()
+					.filter(|f| nh.contains(&f.sha256.to_buf()))
+					.map(|f| (f.path.clone(), f.sha256)).collect();
+
+			print!("Extracting {} file{} from the distribution sets...  ",
+					nh.len(), plural(nh.len()));
+			stdout().flush()?;
+			let n = crate::core::dist::stash(sets, &want, rtdirs)?;
+			println!("{n} done.");
+		},
+		(Some(nh), Source::Server(find)) => {
+			// All encapsulated up, just build the control with the dirs
+			// and kick it off.
+			use crate::core::pool::hashcheck as hcp;
+			use crate::core::hashfetch as hf;
+
+			let tmpdir = rtdirs.tmp().to_path_buf();
+			let filesdir = rtdirs.files().to_path_buf();
+			let keep = false;
+			let ctrl = hcp::Control { tmpdir, filesdir, keep };
+
+			let mut server = find()?;
+			hf::get(&mut server, nh, ctrl)?;
+		},
+	}
+	println!("");
+
+
+	/*
+	 * Now we know everything about what we want to do.  If we're dry
+	 * running, just say it.  Else, do it.
+	 */
+	if dry
+	{
+		println!("DRY RUN: Would install the following:");
+		let mut paths = all.allpaths();
+		paths.sort_unstable();
+		for p in paths { println!("  {}", p.display()); }
+		return Ok(());
+	}
+
+	// Reuse bits from install
+	use crate::core::install;
+
+	// Same up-front check install does, so anything that's gone bad
+	// stops us before we start, and what checks out doesn't get checked
+	// again as it's installed.
+	{
+		use crate::core::pool::Pool as _;
+		use crate::core::pool::present;
+
+		const MAXBAD: usize = 20;
+		let mut hashes: Vec<_> = all.files.values()
+				.map(|f| f.sha256.to_buf()).collect();
+		hashes.sort_unstable();
+		hashes.dedup();
+		let nhf = hashes.len();
+		let ctrl = present::Control::new(rtdirs.files().to_path_buf(),
+				MAXBAD).verifying();
+		let pres = present::Present::new(nhf, MAXBAD).run(&ctrl, hashes)?;
+		let bad: Vec<_> = pres.missing.iter()
+				.map(|h| format!("{h}: missing"))
+				.chain(pres.bad.iter().map(|(h, e)| format!("{h}: {e}")))
+				.collect();
+		if !bad.is_empty()
+		{
+			for (h, _) in &pres.bad
+			{ let _ = std::fs::remove_file(rtdirs.hashfile(h)); }
+			anyhow::bail!("Files in {} missing or corrupted; run again to \
+					get them:\n  {}", rtdirs.files().display(),
+					bad.join("\n  "));
+		}
+		install::set_checked_hashfiles(pres.seen);
+	}
+
+	println!("Installing files");
+	let isplit = all.into_split_types();
+	let owners = match owners {
+		Some(f) => Some((install::OwnerManifest::load_or_new(f)?, f)),
+		None => None,
+	};
+	let mut done = isplit.clone();
+	let failed = install::split(isplit, rtdirs, basedir, Default::default(),
+			false, false)?;
+	failed.iter().for_each(|(p, _)| done.remove(p));
+
+	// Only what actually went in belongs in the ownership manifest
+	if let Some((mut om, f)) = owners
+	{
+		om.record(&done);
+		om.write(f)?;
+		println!("Wrote ownership manifest ({} entries) to {}.",
+				om.len(), f.display());
+	}
+
+	// Anything busy didn't go in, so we're not done.
+	if !failed.is_empty()
+	{
+		let nf = failed.len();
+		println!("{nf} path{} not installed:", plural(nf));
+		for (p, why) in &failed { println!("  {}: {why}", p.display()); }
+		anyhow::bail!("{nf} path{} not installed; run again once {} free",
+				plural(nf), if nf == 1 { "it's" } else { "they're" });
+	}
+
+	Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::Source;
+	use crate::core::RtDirs;
+	use crate::metadata::{Metadata, MetaDir, MetaSymLink};
+
+	#[test]
+	fn from_metadata()
+	{
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		let uid = crate::util::euid();
+		let md = Metadata {
+			dirs: [("/d".into(), MetaDir { path: "/d".into(), uid,
+					mode: 0o755, ..Default::default() })].into(),
+			symlinks: [("/d/l".into(), MetaSymLink { path: "/d/l".into(),
+					target: "there".into(), uid, mode: 0o755,
+					..Default::default() })].into(),
+			..Default::default()
+		};
+
+		// No files means nothing to fetch, so the server never gets
+		// looked for.
+		let noserver = || Source::Server(Box::new(|| {
+			anyhow::bail!("shouldn't need a server")
+		}));
+
+		// Dry run leaves things be
+		super::from_metadata(md.clone(), &rtdirs, base.path(), noserver(),
+				true, None).unwrap();
+		assert!(!base.path().join("d").exists());
+
+		// For real puts it in place, and notes the owners
+		let om = wd.path().join("owners");
+		super::from_metadata(md, &rtdirs, base.path(), noserver(), false,
+				Some(&om)).unwrap();
+		let lnk = base.path().join("d/l").read_link().unwrap();
+		assert_eq!(lnk, std::path::Path::new("there"));
+		assert!(om.is_file());
+	}
+}
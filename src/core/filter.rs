@@ -3,7 +3,7 @@
 //! This doesn't fit nearly into Metadata methods, since it's a lot of
 //! collating them together.
 use std::path::{Path, PathBuf};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::metadata::Metadata;
 
@@ -24,6 +24,130 @@ pub(crate) struct ModifiedPresentRet
 	dashes: HashSet<PathBuf>,
 }
 
+impl ModifiedPresentRet
+{
+	/// Which of those upstream actually changed the contents of between
+	/// old and new, so leaving them alone means missing something, sorted.
+	/// Anything upstream only changed the metadata of, or removed, isn't
+	/// much to miss.
+	pub(crate) fn skipped_updates(&self, old: &Metadata, new: &Metadata)
+			-> Vec<SkippedUpdate>
+	{
+		let mut ret: Vec<_> = self.files.iter().filter_map(|p| {
+			let nh = new.files.get(p)?.sha256;
+			let oh = old.files.get(p).map(|f| f.sha256);
+			if oh == Some(nh) { return None; }
+			let security = is_security_path(p);
+			Some(SkippedUpdate { path: p.clone(), security })
+		}).collect();
+		ret.sort_unstable();
+		ret
+	}
+}
+
+
+/// Places where a skipped upstream change is likely to be a security fix
+/// (say, a safer default in sshd_config).
+const SECURITY_PATHS: &[&str] = &["/etc/ssh", "/etc/ssl", "/etc/pam.d",
+		"/etc/login.conf"];
+
+/// Is this in one of the SECURITY_PATHS?
+fn is_security_path(p: &Path) -> bool
+{
+	SECURITY_PATHS.iter().any(|s| p.starts_with(s))
+}
+
+
+/// A locally modified file that UpdateIfUnmodified kept from getting
+/// upstream's changes.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct SkippedUpdate
+{
+	pub(crate) path: PathBuf,
+
+	/// It's somewhere security-sensitive
+	pub(crate) security: bool,
+}
+
+
+/// Describe a set of SkippedUpdate's for the user, a line at a time.
+pub(crate) fn skipped_updates_desc(sk: &[SkippedUpdate]) -> Vec<String>
+{
+	use crate::util::plural;
+
+	let mut ret = Vec::new();
+	let n = sk.len();
+	if n == 0 { return ret; }
+
+	const CALLOUT: &str = "SECURITY-RELEVANT UPDATE SKIPPED";
+	ret.push(format!("{n} locally modified file{} won't get upstream's \
+			changes:", plural(n)));
+	for s in sk
+	{
+		match s.security {
+			true  => ret.push(format!("    {}  <-- {CALLOUT}",
+					s.path.display())),
+			false => ret.push(format!("    {}", s.path.display())),
+		}
+	}
+
+	let nsec = sk.iter().filter(|s| s.security).count();
+	if nsec > 0
+	{
+		let cmdname = crate::util::cmdname();
+		ret.push(format!("{CALLOUT}: {nsec} of these {} security-sensitive; \
+				upstream's changes may", match nsec {
+					1 => "is",
+					_ => "are",
+				}));
+		ret.push(format!("be fixes you need.  Review them against upstream \
+				by hand (`{cmdname} why PATH` says"));
+		ret.push("what happened with each).".to_string());
+	}
+	ret
+}
+
+
+/// Describe where KeepModifiedMetadata kept local perms over upstream's.
+/// Just a count unless full, then what each one would have been.
+pub(crate) fn kept_metadata_desc(kept: &[crate::metadata::MetaKept],
+		full: bool) -> Vec<String>
+{
+	use crate::util::plural;
+
+	let mut ret = Vec::new();
+	let n = kept.len();
+	if n == 0 { return ret; }
+
+	match full {
+		false => {
+			ret.push(format!("Keeping local owner/mode/flags over \
+					upstream's on {n} path{}.", plural(n)));
+			ret.push(format!("    (`{} show-install -v metadata` for \
+					details)", crate::util::cmdname()));
+		},
+		true => {
+			ret.push(format!("Local owner/mode/flags kept over upstream's on \
+					{n} path{}:", plural(n)));
+			for k in kept
+			{
+				// Upstream taking permissions away is the interesting
+				// case; keeping ours undoes that.
+				let looser = k.local.mode & !k.upstream.mode & 0o7777 != 0;
+				let note = match looser {
+					true  => "  <-- upstream is stricter",
+					false => "",
+				};
+				ret.push(format!("  {}{note}", k.path.display()));
+				ret.push(format!("      upstream {}", k.upstream));
+				ret.push(format!("      kept     {}", k.local));
+			}
+		},
+	}
+	ret
+}
+
 
 /// Figure out what needs to be done to collate together several Metadata
 /// sets to handle UpdateIfUnmodified settings and DTRT for not-present
@@ -208,3 +332,235 @@ pub(crate) fn apply_modified_present(mpret: ModifiedPresentRet,
 
 	ret
 }
+
+
+/// What fetch's handling of local changes came to; x-ref
+/// local_changes().
+#[derive(Debug, Default)]
+pub(crate) struct LocalChanges
+{
+	/// What f-u.sh calls "modifiedfiles": locally modified, so left
+	/// alone.
+	pub(crate) modified: HashSet<PathBuf>,
+
+	/// Which of those upstream changed; x-ref
+	/// ModifiedPresentRet::skipped_updates()
+	pub(crate) skipped_updates: Vec<SkippedUpdate>,
+
+	/// Where KeepModifiedMetadata kept our owner/mode/flags
+	pub(crate) kept_metadata: Vec<crate::metadata::MetaKept>,
+}
+
+/// Fetch's UpdateIfUnmodified and KeepModifiedMetadata handling: drop
+/// what's locally modified from all of old/new/cur, and carry local
+/// metadata over into new.  `why` walks a path through this same thing,
+/// so what it says is what fetch does.
+pub(crate) fn local_changes(old: &mut Metadata, new: &mut Metadata,
+		cur: &mut Metadata, uium: &[Regex], keep_metadata: bool)
+		-> LocalChanges
+{
+	// fetch_filter_unmodified_notpresent()
+	let mpret = modified_present(old, new, cur, uium, None, None);
+	let skipped_updates = mpret.skipped_updates(old, new);
+	let modified = apply_modified_present(mpret, old, new, cur);
+
+	// Anything where the current metadata differs from old, replace
+	// new's metadata with ours.
+	let mut kept_metadata = Vec::new();
+	if keep_metadata
+	{
+		let modd = cur.modified_metadata(old);
+		if !modd.empty() { kept_metadata = new.replace_metadata_from(&modd); }
+	}
+
+	LocalChanges { modified, skipped_updates, kept_metadata }
+}
+
+
+/// AllowDelete=no: anything we've got that new would remove, new keeps
+/// just as it is instead, so it drops out as up to date.  Returns what
+/// that saved, sorted.  f-u.sh's fetch_filter_allowdelete()
+pub(crate) fn keep_deletes(new: &mut Metadata, cur: &Metadata)
+		-> Vec<PathBuf>
+{
+	fn keep<T: Clone>(cur: &HashMap<PathBuf, T>,
+			new: &mut HashMap<PathBuf, T>, have: &HashSet<PathBuf>,
+			kept: &mut Vec<PathBuf>)
+	{
+		for (p, l) in cur.iter().filter(|(p, _)| !have.contains(*p))
+		{
+			new.insert(p.clone(), l.clone());
+			kept.push(p.clone());
+		}
+	}
+
+	let have: HashSet<_> = new.allpaths_hashset_nodash().into_iter()
+			.map(|p| p.to_path_buf()).collect();
+	let mut kept = Vec::new();
+	keep(&cur.files, &mut new.files, &have, &mut kept);
+	keep(&cur.dirs, &mut new.dirs, &have, &mut kept);
+	keep(&cur.symlinks, &mut new.symlinks, &have, &mut kept);
+	keep(&cur.hardlinks, &mut new.hardlinks, &have, &mut kept);
+	for p in &kept { new.dashes.remove(p); }
+
+	kept.sort_unstable();
+	kept
+}
+
+
+/// Collate cur/new together, and remove any lines that are the same
+/// between them, leaving what install has to change.  f-u.sh's
+/// fetch_filter_uptodate()
+pub(crate) fn uptodate(new: &mut Metadata, cur: &mut Metadata)
+{
+	let ntmp = new.clone();
+	new.remove_matching(cur);
+	cur.remove_matching(&ntmp);
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::{ModifiedPresentRet, SkippedUpdate};
+	use crate::metadata::{Metadata, MetaFile};
+	use std::path::PathBuf;
+
+	fn md(files: &[(&str, u8)]) -> Metadata
+	{
+		let files = files.iter().map(|(p, h)| (PathBuf::from(p),
+				MetaFile { path: p.into(), sha256: [*h; 32].into(),
+					..Default::default() })).collect();
+		Metadata { files, ..Default::default() }
+	}
+
+	#[test]
+	fn kept_metadata_desc()
+	{
+		use crate::metadata::{MetaKept, MetaPerms};
+
+		let perms = |mode| MetaPerms { mode, ..Default::default() };
+		let kept = [
+			MetaKept { path: "/etc/secret.conf".into(),
+					upstream: perms(0o600), local: perms(0o644) },
+			MetaKept { path: "/etc/open.conf".into(),
+					upstream: perms(0o644), local: perms(0o600) },
+		];
+
+		assert!(super::kept_metadata_desc(&[], true).is_empty());
+		let short = super::kept_metadata_desc(&kept, false);
+		assert!(short[0].contains("on 2 paths"), "{short:?}");
+
+		// The tightened one gets called out, with both sides
+		let full = super::kept_metadata_desc(&kept, true);
+		assert!(full.contains(&"  /etc/secret.conf  <-- upstream is stricter"
+				.to_string()), "{full:?}");
+		assert!(full.contains(&"      upstream 0:0 mode 600 flags 0"
+				.to_string()), "{full:?}");
+		assert!(full.contains(&"      kept     0:0 mode 644 flags 0"
+				.to_string()), "{full:?}");
+		assert!(full.contains(&"  /etc/open.conf".to_string()), "{full:?}");
+	}
+
+
+	#[test]
+	fn skipped_updates()
+	{
+		let old = md(&[("/etc/ssh/sshd_config", 1), ("/etc/motd", 1),
+				("/etc/login.conf", 1), ("/etc/login.conf.db", 1),
+				("/etc/rc.conf", 1), ("/etc/gone", 1)]);
+		let new = md(&[("/etc/ssh/sshd_config", 2), ("/etc/motd", 2),
+				("/etc/login.conf", 2), ("/etc/login.conf.db", 2),
+				("/etc/rc.conf", 1), ("/etc/pam.d/sshd", 2)]);
+		let files = ["/etc/ssh/sshd_config", "/etc/motd", "/etc/login.conf",
+				"/etc/login.conf.db", "/etc/rc.conf", "/etc/gone",
+				"/etc/pam.d/sshd"].iter().map(PathBuf::from).collect();
+		let mpret = ModifiedPresentRet { files, hlinks: Default::default(),
+				dashes: Default::default() };
+
+		// rc.conf didn't change upstream, and gone is gone; the rest are
+		// missing out, sorted.
+		let sk = mpret.skipped_updates(&old, &new);
+		let su = |p: &str, security| SkippedUpdate { path: p.into(), security };
+		assert_eq!(sk, [su("/etc/login.conf", true),
+				su("/etc/login.conf.db", false), su("/etc/motd", false),
+				su("/etc/pam.d/sshd", true), su("/etc/ssh/sshd_config", true)]);
+
+		// Security ones get called out, once each plus the explanation.
+		let desc = super::skipped_updates_desc(&sk);
+		assert!(desc[0].starts_with("5 locally modified files"), "{desc:?}");
+		let marked: Vec<_> = desc.iter()
+				.filter(|l| l.ends_with("<-- SECURITY-RELEVANT UPDATE SKIPPED"))
+				.collect();
+		assert_eq!(marked.len(), 3, "{desc:?}");
+		assert!(desc.iter().any(|l| l.contains("3 of these are")), "{desc:?}");
+
+		// Nothing security-ish, no callout; nothing at all, no lines.
+		let desc = super::skipped_updates_desc(&sk[1..3]);
+		assert_eq!(desc.len(), 3, "{desc:?}");
+		assert!(super::skipped_updates_desc(&[]).is_empty());
+	}
+
+
+	#[test]
+	fn keep_deletes()
+	{
+		use std::path::Path;
+
+		// gone vanishes from new outright, dashed gets a dash; both are
+		// here, so both stay.  The one we already lack stays gone.
+		let mut new = md(&[("/bin/sh", 2)]);
+		new.dashes.insert("/bin/dashed".into());
+		new.dashes.insert("/bin/lost".into());
+		let mut cur = md(&[("/bin/sh", 1), ("/bin/gone", 1),
+				("/bin/dashed", 3)]);
+		cur.dashes.insert("/bin/lost".into());
+
+		let kept = super::keep_deletes(&mut new, &cur);
+		assert_eq!(kept, ["/bin/dashed", "/bin/gone"].map(PathBuf::from));
+		let dashed = Path::new("/bin/dashed");
+		assert_eq!(new.files[dashed].sha256, cur.files[dashed].sha256);
+		assert!(new.dashes.contains(&PathBuf::from("/bin/lost")));
+		assert!(!new.dashes.contains(dashed));
+
+		// So none of them are left to do anything about
+		super::uptodate(&mut new, &mut cur);
+		assert_eq!(new.allpaths(), [Path::new("/bin/sh")]);
+	}
+
+
+	#[test]
+	fn dash_pipeline()
+	{
+		// old has a dash for a file only new brings in; the scan
+		// reports that, plus something the user deleted, plus
+		// something new drops, as missing.
+		let mut old = md(&[("/etc/a", 1), ("/bin/rmd", 1), ("/bin/dropped", 1)]);
+		old.dashes.insert("/usr/added".into());
+		let mut new = md(&[("/etc/a", 2), ("/bin/rmd", 2), ("/usr/added", 3)]);
+		new.dashes.insert("/bin/dropped".into());
+		let mut cur = md(&[("/etc/a", 1)]);
+		for p in ["/usr/added", "/bin/rmd", "/bin/dropped"]
+		{ cur.dashes.insert(p.into()); }
+
+		// Only the deleted one is really "missing"
+		assert_eq!(cur.split_missing(&[&old, &new]), (1, 2));
+
+		// The fetch filters, in order.
+		let mpret = super::modified_present(&old, &new, &cur, &[], None, None);
+		let modified = super::apply_modified_present(mpret, &mut old,
+				&mut new, &mut cur);
+		assert!(modified.is_empty(), "{modified:?}");
+		new.remove_matching(&cur);
+
+		// Locally deleted stays deleted; the new file still comes in; the
+		// update to the unmodified one goes ahead; and the dash for what
+		// new drops matched what's there (nothing), so it's gone.
+		let mut files: Vec<_> = new.files.keys().collect();
+		files.sort_unstable();
+		assert_eq!(files, [&PathBuf::from("/etc/a"),
+				&PathBuf::from("/usr/added")]);
+		assert!(new.dashes.is_empty(), "{:?}", new.dashes);
+	}
+}
@@ -0,0 +1,484 @@
+//! Probing the basedir's filesystem for things we can't deal with.
+//!
+//! We assume throughout that two different paths are two different
+//! files.  On a case-insensitive (or unicode-normalizing) filesystem,
+//! that's not true, and e.g. two man pages differing only in case wind
+//! up as the same file, so the scan conflates them, and the install
+//! happily overwrites one with the other.  Rather than trying to support
+//! that, we just check up front and refuse.
+//!
+//! File flags are the other thing that doesn't carry everywhere; see
+//! check_flags().
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+
+
+/// What a probe found
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum FsProbe
+{
+	/// Names are names; all good
+	Ok,
+
+	/// Names differing only in case are the same file
+	CaseInsensitive,
+
+	/// Names differing only in unicode normalization are the same file
+	UnicodeNormalizing,
+}
+
+
+/// Something that can tell us whether a filesystem considers two names
+/// to be the same file.
+pub(crate) trait Prober
+{
+	/// Create a file named `a`, and see whether `b` then exists.
+	fn same(&mut self, a: &OsStr, b: &OsStr) -> Result<bool, io::Error>;
+}
+
+
+/// The real prober, working in a scratch dir under the basedir.  The
+/// dir gets cleaned up when we're dropped.
+#[derive(Debug)]
+struct DirProber
+{
+	dir: tempfile::TempDir,
+}
+
+impl DirProber
+{
+	fn new(basedir: &Path) -> Result<Self, io::Error>
+	{
+		let dir = tempfile::Builder::new().prefix(".rustdate-fsprobe.")
+				.tempdir_in(basedir)?;
+		Ok(Self { dir })
+	}
+}
+
+impl Prober for DirProber
+{
+	fn same(&mut self, a: &OsStr, b: &OsStr) -> Result<bool, io::Error>
+	{
+		let pa = self.dir.path().join(a);
+		std::fs::File::create(&pa)?;
+		let ret = self.dir.path().join(b).symlink_metadata().is_ok();
+		std::fs::remove_file(&pa)?;
+		Ok(ret)
+	}
+}
+
+
+/// Run the probes.
+pub(crate) fn probe(p: &mut impl Prober) -> Result<FsProbe, io::Error>
+{
+	if p.same("rdprobe-case".as_ref(), "RDPROBE-CASE".as_ref())?
+	{ return Ok(FsProbe::CaseInsensitive); }
+
+	// U+00E9 precomposed (NFC), vs. e + combining acute (NFD)
+	if p.same("rdprobe-\u{e9}".as_ref(), "rdprobe-e\u{301}".as_ref())?
+	{ return Ok(FsProbe::UnicodeNormalizing); }
+
+	Ok(FsProbe::Ok)
+}
+
+
+/// basedirs we've already probed (and not refused) this run.  A command
+/// may scan several times, and the answer isn't going to change in
+/// between, so there's no sense making a new probe dir each time.
+static CHECKED: std::sync::Mutex<Vec<std::path::PathBuf>>
+		= std::sync::Mutex::new(Vec::new());
+
+
+/// Make sure the filesystem under basedir is one we can work with.
+///
+/// Errors out if it's not.  If we can't tell (e.g., it's read-only), we
+/// warn and carry on.  Either way, it's only checked once per run.
+pub(crate) fn check_basedir(basedir: &Path) -> Result<(), anyhow::Error>
+{
+	if let Some(w) = check_once(basedir, || DirProber::new(basedir))?
+	{ eprintln!("WARNING: {w}"); }
+	Ok(())
+}
+
+
+/// check() the first time we see a basedir, and nothing after that.
+/// Ones that fail don't get remembered, but they're the end of the
+/// command anyway.
+fn check_once<P: Prober>(basedir: &Path,
+		prober: impl FnOnce() -> Result<P, io::Error>)
+		-> Result<Option<String>, anyhow::Error>
+{
+	let mut checked = CHECKED.lock().unwrap_or_else(|e| e.into_inner());
+	if checked.iter().any(|d| d == basedir) { return Ok(None); }
+	let ret = check(basedir, prober())?;
+	checked.push(basedir.to_path_buf());
+	Ok(ret)
+}
+
+
+/// The guts of check_basedir(), with the prober passed in.  Returns a
+/// warning if we couldn't do the check.
+fn check(basedir: &Path, prober: Result<impl Prober, io::Error>)
+		-> Result<Option<String>, anyhow::Error>
+{
+	let skip = |e: io::Error| Some(format!("can't probe the basedir {} \
+			filesystem ({e}); assuming it's case-sensitive and not \
+			unicode-normalizing.", basedir.display()));
+
+	let mut prober = match prober {
+		Ok(p) => p,
+		Err(e) => return Ok(skip(e)),
+	};
+
+	let what = match probe(&mut prober) {
+		Ok(FsProbe::Ok) => return Ok(None),
+		Ok(FsProbe::CaseInsensitive) => "case-insensitive",
+		Ok(FsProbe::UnicodeNormalizing) => "unicode-normalizing",
+		Err(e) => return Ok(skip(e)),
+	};
+	anyhow::bail!("basedir {} filesystem is {what}, which is unsupported",
+			basedir.display())
+}
+
+
+
+/*
+ * File flags.  Some filesystems don't keep some or all of them (tmpfs
+ * has no schg, NFS generally nothing at all), and chflags on them
+ * either fails or quietly doesn't do anything.  Installing into one of
+ * those "works", but the flags just aren't there, and copying the
+ * result off somewhere else later won't bring them back.
+ */
+
+/// Flags the basedir's filesystem wouldn't keep, and how many of the
+/// paths being installed wanted them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct FlagsLost
+{
+	/// What sort of filesystem it is, as statfs(2) says
+	pub(crate) fstype: String,
+
+	/// The flags that didn't stick
+	pub(crate) flags: u32,
+
+	/// How many paths wanted any of them
+	pub(crate) paths: usize,
+}
+
+impl FlagsLost
+{
+	pub(crate) fn describe(&self) -> String
+	{
+		use crate::util::plural;
+		let n = self.paths;
+		format!("the basedir's filesystem ({}) doesn't keep {} flags, so \
+				{n} path{} that should have them won't", self.fstype,
+				crate::core::install::flags_str(self.flags), plural(n))
+	}
+}
+
+
+/// What check_flags() found
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum FlagCheck
+{
+	/// Everything wanted sticks (or nothing's wanted)
+	Ok,
+
+	/// Some don't
+	Lost(FlagsLost),
+
+	/// Couldn't tell; a warning saying why
+	Unknown(String),
+}
+
+
+/// Something that can try flags out on a filesystem.
+pub(crate) trait FlagProber
+{
+	/// What sort of filesystem it is
+	fn fstype(&mut self) -> Result<String, io::Error>;
+
+	/// Set flags on a scratch file, and say what it wound up with.
+	fn set_flags(&mut self, flags: u32) -> Result<u32, io::Error>;
+}
+
+impl FlagProber for DirProber
+{
+	fn fstype(&mut self) -> Result<String, io::Error>
+	{
+		crate::util::fstype(self.dir.path())
+	}
+
+	fn set_flags(&mut self, flags: u32) -> Result<u32, io::Error>
+	{
+		let f = self.dir.path().join("rdprobe-flags");
+		std::fs::File::create(&f)?;
+		let set = chflags(&f, flags);
+		let got = crate::util::lstat(&f).map(|(st, _)| st.flags)
+				.map_err(|e| io::Error::new(io::ErrorKind::Other,
+					e.to_string()));
+
+		// Take them back off, so it (and the dir) can go away.
+		let _ = chflags(&f, 0);
+		std::fs::remove_file(&f)?;
+
+		// A filesystem that doesn't do flags at all may just say so;
+		// then whatever's there is what it kept.
+		match set {
+			Err(e) if !matches!(e.raw_os_error(),
+					Some(libc::EOPNOTSUPP | libc::EINVAL)) => Err(e),
+			_ => got,
+		}
+	}
+}
+
+/// lchflags(2), with the errno kept around
+fn chflags(f: &Path, flags: u32) -> Result<(), io::Error>
+{
+	use std::os::unix::ffi::OsStrExt as _;
+	let cf = std::ffi::CString::new(f.as_os_str().as_bytes())?;
+
+	// SAFETY: valid C string
+	match unsafe { libc::lchflags(cf.as_ptr(), flags.into()) } {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error()),
+	}
+}
+
+
+/// The system flags (schg and friends); x-ref SF_SETTABLE in
+/// <sys/stat.h>.
+const SYS_FLAGS: u32 = 0xffff0000;
+
+
+/// See whether the basedir's filesystem keeps the flags we're going to
+/// set.  wanted is the flags for each path that has any.
+pub(crate) fn check_flags(basedir: &Path, wanted: &[u32]) -> FlagCheck
+{
+	// Above securelevel 0, system flags can be set, but not cleared, and
+	// then we'd have a scratch file nobody can remove.
+	let testable = match crate::util::securelevel() {
+		Ok(l) if l > 0 => !SYS_FLAGS,
+		_ => !0,
+	};
+	flags(basedir, DirProber::new(basedir), wanted, testable)
+}
+
+
+/// The guts of check_flags(), with the prober (and which flags it's OK
+/// to try) passed in.
+fn flags(basedir: &Path, prober: Result<impl FlagProber, io::Error>,
+		wanted: &[u32], testable: u32) -> FlagCheck
+{
+	let want = wanted.iter().fold(0, |acc, f| acc | f);
+	if want == 0 { return FlagCheck::Ok; }
+
+	let skip = |why: String| FlagCheck::Unknown(format!("can't probe \
+			whether the basedir {} filesystem keeps file flags ({why}).",
+			basedir.display()));
+	let tried = want & testable;
+	if tried == 0
+	{
+		return skip(format!("can't clear {} at this securelevel",
+				crate::core::install::flags_str(want)));
+	}
+
+	let mut prober = match prober {
+		Ok(p) => p,
+		Err(e) => return skip(e.to_string()),
+	};
+	let got = match prober.set_flags(tried) {
+		Ok(g) => g,
+		Err(e) => return skip(e.to_string()),
+	};
+	let lost = tried & !got;
+	if lost == 0 { return FlagCheck::Ok; }
+
+	let fstype = prober.fstype().unwrap_or_else(|_| "unknown".to_string());
+	let paths = wanted.iter().filter(|f| *f & lost != 0).count();
+	FlagCheck::Lost(FlagsLost { fstype, flags: lost, paths })
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::ffi::{OsStr, OsString};
+	use std::io;
+	use std::path::Path;
+	use super::{Prober, FsProbe, FlagProber, FlagCheck, FlagsLost};
+
+	/// Fake prober that folds names in configurable ways
+	struct Fake
+	{
+		fold_case: bool,
+		fold_norm: bool,
+		fail: bool,
+	}
+
+	impl Prober for Fake
+	{
+		fn same(&mut self, a: &OsStr, b: &OsStr) -> Result<bool, io::Error>
+		{
+			if self.fail
+			{ return Err(io::Error::from(io::ErrorKind::PermissionDenied)); }
+
+			let fold = |s: &OsStr| -> OsString {
+				let mut s = s.to_str().unwrap().to_string();
+				if self.fold_case { s = s.to_lowercase(); }
+				if self.fold_norm { s = s.replace("e\u{301}", "\u{e9}"); }
+				s.into()
+			};
+			Ok(fold(a) == fold(b))
+		}
+	}
+
+	fn fake(fold_case: bool, fold_norm: bool) -> Fake
+	{ Fake { fold_case, fold_norm, fail: false } }
+
+	#[test]
+	fn probe()
+	{
+		use super::probe;
+		assert_eq!(probe(&mut fake(false, false)).unwrap(), FsProbe::Ok);
+		assert_eq!(probe(&mut fake(true, false)).unwrap(),
+				FsProbe::CaseInsensitive);
+		assert_eq!(probe(&mut fake(false, true)).unwrap(),
+				FsProbe::UnicodeNormalizing);
+		assert_eq!(probe(&mut fake(true, true)).unwrap(),
+				FsProbe::CaseInsensitive);
+	}
+
+	#[test]
+	fn check()
+	{
+		use super::check;
+		let bd = Path::new("/base");
+
+		assert!(check(bd, Ok(fake(false, false))).unwrap().is_none());
+
+		let e = check(bd, Ok(fake(true, false))).unwrap_err().to_string();
+		assert_eq!(e, "basedir /base filesystem is case-insensitive, \
+				which is unsupported");
+		let e = check(bd, Ok(fake(false, true))).unwrap_err().to_string();
+		assert!(e.contains("unicode-normalizing"), "{e}");
+
+		// Can't make the probe dir, or can't write in it: warn and go on
+		let ro = io::Error::from(io::ErrorKind::PermissionDenied);
+		let w = check(bd, Err::<Fake, _>(ro)).unwrap();
+		assert!(w.unwrap().starts_with("can't probe the basedir /base"));
+		let failing = Fake { fold_case: false, fold_norm: false, fail: true };
+		assert!(check(bd, Ok(failing)).unwrap().is_some());
+	}
+
+	#[test]
+	fn check_once()
+	{
+		use super::check_once;
+		use std::cell::Cell;
+
+		let probes = Cell::new(0);
+		let mk = |f: Fake| || { probes.set(probes.get() + 1); Ok(f) };
+
+		// Refused ones don't stick
+		let bd = Path::new("/fsprobe-once-bad");
+		assert!(check_once(bd, mk(fake(true, false))).is_err());
+		assert!(check_once(bd, mk(fake(true, false))).is_err());
+		assert_eq!(probes.get(), 2);
+
+		// Good ones only get looked at the once
+		let bd = Path::new("/fsprobe-once-good");
+		assert!(check_once(bd, mk(fake(false, false))).unwrap().is_none());
+		assert!(check_once(bd, mk(fake(true, false))).unwrap().is_none());
+		assert_eq!(probes.get(), 3);
+
+		// And ones we can't tell about only get warned about the once
+		let bd = Path::new("/fsprobe-once-ro");
+		let failing = || Fake { fold_case: false, fold_norm: false,
+				fail: true };
+		assert!(check_once(bd, mk(failing())).unwrap().is_some());
+		assert!(check_once(bd, mk(failing())).unwrap().is_none());
+		assert_eq!(probes.get(), 4);
+	}
+
+	/// Fake flag prober, that keeps only some of them
+	struct FakeFlags
+	{
+		keeps: u32,
+		fail: bool,
+	}
+
+	impl FlagProber for FakeFlags
+	{
+		fn fstype(&mut self) -> Result<String, io::Error>
+		{ Ok("tmpfs".to_string()) }
+
+		fn set_flags(&mut self, flags: u32) -> Result<u32, io::Error>
+		{
+			match self.fail {
+				true  => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+				false => Ok(flags & self.keeps),
+			}
+		}
+	}
+
+	#[test]
+	fn flags()
+	{
+		let bd = Path::new("/base");
+		let (schg, nodump) = (0x00020000, 0x00000001);
+		let keeps = |keeps| Ok(FakeFlags { keeps, fail: false });
+		let check = |p, wanted: &[u32], testable| super::flags(bd, p, wanted,
+				testable);
+
+		// Nothing wanted, or everything kept
+		assert_eq!(check(keeps(0), &[], !0), FlagCheck::Ok);
+		assert_eq!(check(keeps(!0), &[schg, schg | nodump], !0),
+				FlagCheck::Ok);
+
+		// Only the paths that want what got lost count
+		let wanted = [schg, nodump, schg | nodump, schg];
+		let lost = FlagsLost { fstype: "tmpfs".to_string(), flags: schg,
+				paths: 3 };
+		assert_eq!(check(keeps(nodump), &wanted, !0),
+				FlagCheck::Lost(lost.clone()));
+		assert_eq!(lost.describe(), "the basedir's filesystem (tmpfs) \
+				doesn't keep schg flags, so 3 paths that should have them \
+				won't");
+		let FlagCheck::Lost(none) = check(keeps(0), &wanted, !0)
+				else { panic!("should lose everything") };
+		assert_eq!((none.flags, none.paths), (schg | nodump, 4));
+
+		// What can't be tried isn't counted, and with nothing left to
+		// try, we can't tell.
+		assert_eq!(check(keeps(0), &wanted, !super::SYS_FLAGS),
+				FlagCheck::Lost(FlagsLost { fstype: "tmpfs".to_string(),
+					flags: nodump, paths: 2 }));
+		let FlagCheck::Unknown(w) = check(keeps(0), &[schg],
+				!super::SYS_FLAGS) else { panic!("can't try schg") };
+		assert!(w.contains("securelevel"), "{w}");
+
+		// Or the probe itself doesn't work
+		let ro = io::Error::from(io::ErrorKind::PermissionDenied);
+		let r = check(Err::<FakeFlags, _>(ro), &wanted, !0);
+		assert!(matches!(&r, FlagCheck::Unknown(w)
+				if w.starts_with("can't probe whether the basedir /base")),
+				"{r:?}");
+		let r = check(Ok(FakeFlags { keeps: 0, fail: true }), &wanted, !0);
+		assert!(matches!(r, FlagCheck::Unknown(_)), "{r:?}");
+	}
+
+	#[test]
+	fn real()
+	{
+		// Whatever we're running tests on had better be sane, and we
+		// shouldn't leave anything behind.
+		let td = tempfile::tempdir().unwrap();
+		super::check_basedir(td.path()).unwrap();
+		assert_eq!(std::fs::read_dir(td.path()).unwrap().count(), 0);
+	}
+}
@@ -11,22 +11,43 @@ use crate::server::Server;
 ///
 /// Any returned error is probably fatal; something broke, or we didn't
 /// get them all, and we _should_ get them all...
-pub(crate) fn get(srv: &Server, hashes: Vec<hash::Sha256HashBuf>,
+///
+/// When we're root, this all happens in a worker as FetchUser instead;
+/// see crate::core::privsep.
+pub(crate) fn get(srv: &mut Server, hashes: Vec<hash::Sha256HashBuf>,
 		ctrl: hcp::Control) -> Result<(), anyhow::Error>
 {
 	// We need the list of hashnames, not just the hashes.
-	println!("Fetching {} new files.", hashes.len());
 	let fnames: Vec<String> = hashes.iter()
 			.map(|f| format!("{f}.gz")).collect();
+
+	use crate::core::privsep;
+	match privsep::worker_user()? {
+		Some(w) => {
+			println!("Fetching and checking {} new files (as {}).",
+					fnames.len(), w.name);
+			privsep::get(srv, fnames, &ctrl, &w)
+		},
+		None => {
+			println!("Fetching {} new files.", fnames.len());
+			get_here(srv, fnames, ctrl)
+		},
+	}
+}
+
+
+/// The in-process get()
+fn get_here(srv: &mut Server, fnames: Vec<String>, ctrl: hcp::Control)
+		-> Result<(), anyhow::Error>
+{
 	let nf = srv.fetch_files(fnames.clone(), ctrl.tmpdir.clone())?;
 	assert_eq!(nf as usize, fnames.len());
 
 	// OK, we presumably got 'em all.  Check the hashes and store
 	// into files/.
 	// Wrap up the paths in the request struct
-	let reqs: Vec<_> = fnames.into_iter()
-			.map(|path| hcp::Req { path }).collect();
-	let rlen = reqs.len();
+	let rlen = fnames.len();
+	let reqs = fnames.into_iter().map(|path| hcp::Req { path });
 	println!("Checking {} hashes.", rlen);
 
 	// Do the pool's work
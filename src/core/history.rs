@@ -0,0 +1,198 @@
+//! The path lists of finished installs.
+//!
+//! The HistoryEntry in the statefile only has counts; once install's
+//! done, the manifest with the lists goes away.  So for the last few
+//! (x-ref Config::install_history_detail), the lists get written out
+//! alongside, gzip'd, for show-install to look back at.
+use std::path::{Path, PathBuf};
+
+use crate::state::{HistoryEntry, Manifest};
+use crate::metadata::{MetaFile, MetaKept};
+
+
+/// What a finished install changed, in the same terms show-install -v
+/// has them for a pending one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Detail
+{
+	/// Paths added/removed/updated
+	pub(crate) added: Vec<PathBuf>,
+	pub(crate) removed: Vec<PathBuf>,
+	pub(crate) updated: Vec<PathBuf>,
+
+	/// Paths that changed type, with the old and new types
+	pub(crate) changed: Vec<(PathBuf, String, String)>,
+
+	/// Files that got merged (upgrade only)
+	pub(crate) merged: Vec<PathBuf>,
+
+	/// Old shared libs removed at the end (upgrade only)
+	pub(crate) old_libs: Vec<PathBuf>,
+
+	/// Where local owner/mode/flags were kept over upstream's
+	pub(crate) kept_metadata: Vec<MetaKept>,
+
+	/// The files it replaced, as they were; x-ref Manifest::stashed()
+	#[serde(default)]
+	pub(crate) replaced: Vec<MetaFile>,
+}
+
+impl Detail
+{
+	pub(crate) fn from_manifest(m: &Manifest) -> Self
+	{
+		let sum = m.change_summary();
+		let mut changed: Vec<_> = m.type_changes().into_iter()
+				.map(|(p, c)| (p, c.old.ftype().to_string(),
+					c.new.ftype().to_string()))
+				.collect();
+		changed.sort_unstable();
+		let (mut merged, old_libs) = match m {
+			Manifest::Fetch(_) => (Vec::new(), Vec::new()),
+			Manifest::Upgrade(u) => (u.merge_clean.keys().cloned().collect(),
+					u.old_libs.clone()),
+		};
+		merged.sort_unstable();
+
+		Self {
+			added: sum.added, removed: sum.removed, updated: sum.updated,
+			changed, merged, old_libs,
+			kept_metadata: m.kept_metadata().to_vec(),
+			replaced: m.stashed().into_iter().cloned().collect(),
+		}
+	}
+}
+
+
+/// Where the detail for a history entry goes
+pub(crate) fn file(dir: &Path, he: &HistoryEntry) -> PathBuf
+{
+	dir.join(format!("{}-{}.json.gz", he.when, he.mtype))
+}
+
+
+/// Write out the detail for a history entry
+pub(crate) fn save(dir: &Path, he: &HistoryEntry, det: &Detail)
+		-> Result<(), anyhow::Error>
+{
+	std::fs::create_dir_all(dir)?;
+	let path = file(dir, he);
+	let tmp = path.with_extension("tmp");
+	crate::util::compress::write_json_gz(&tmp, det)?;
+	std::fs::rename(&tmp, &path)?;
+	Ok(())
+}
+
+
+/// Read the detail for a history entry, if it's still around.  Anything
+/// wrong with it is the same as it not being there; it's only for
+/// looking at.
+pub(crate) fn load(dir: &Path, he: &HistoryEntry) -> Option<Detail>
+{
+	let fh = std::fs::File::open(file(dir, he)).ok()?;
+	let gz = flate2::read::GzDecoder::new(std::io::BufReader::new(fh));
+	serde_json::from_reader(gz).ok()
+}
+
+
+/// Clear out the detail for all but the newest `keep` entries of the
+/// history (and anything left over from entries that have aged out of
+/// it altogether).  Returns what got removed.
+pub(crate) fn prune(dir: &Path, history: &[HistoryEntry], keep: usize)
+		-> Result<Vec<PathBuf>, std::io::Error>
+{
+	use std::collections::HashSet;
+
+	let rd = match std::fs::read_dir(dir) {
+		Ok(rd) => rd,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound
+				=> return Ok(Vec::new()),
+		Err(e) => return Err(e),
+	};
+
+	let wanted: HashSet<PathBuf> = history.iter().rev().take(keep)
+			.map(|he| file(dir, he)).collect();
+	let mut ret = Vec::new();
+	for de in rd
+	{
+		let path = de?.path();
+		let ours = path.file_name().and_then(|f| f.to_str())
+				.is_some_and(|f| f.ends_with(".json.gz")
+					|| f.ends_with(".json.tmp"));
+		if !ours || wanted.contains(&path) { continue; }
+		std::fs::remove_file(&path)?;
+		ret.push(path);
+	}
+	ret.sort_unstable();
+	Ok(ret)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use crate::state::HistoryEntry;
+
+	fn he(when: i64) -> HistoryEntry
+	{
+		HistoryEntry { mtype: "fetch".to_string(),
+				from: "14.1-RELEASE-p1".to_string(),
+				to: "14.1-RELEASE-p2".to_string(), when, audit: None,
+				deferred: Vec::new(), counts: None, flags_lost: None }
+	}
+
+	#[test]
+	fn save_load()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path().join("history");
+		let det = super::Detail { added: vec!["/bin/new".into()],
+				updated: vec!["/bin/sh".into(), "/lib/libc.so.7".into()],
+				changed: vec![("/usr/x".into(), "file".into(),
+					"symlink".into())],
+				replaced: vec![crate::metadata::MetaFile {
+					path: "/bin/sh".into(), mode: 0o555,
+					..Default::default() }],
+				..Default::default() };
+
+		assert_eq!(super::load(&dir, &he(100)), None);
+		super::save(&dir, &he(100), &det).unwrap();
+		assert_eq!(super::load(&dir, &he(100)), Some(det));
+		assert_eq!(super::load(&dir, &he(200)), None);
+
+		// Something mangled is just not there
+		std::fs::write(super::file(&dir, &he(200)), b"nope").unwrap();
+		assert_eq!(super::load(&dir, &he(200)), None);
+	}
+
+	#[test]
+	fn prune()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+		let history: Vec<_> = (1..=5).map(|n| he(n * 100)).collect();
+		let det = super::Detail::default();
+		for h in &history { super::save(dir, h, &det).unwrap(); }
+
+		// An entry that's since aged out of the history, and something
+		// that isn't ours
+		super::save(dir, &he(50), &det).unwrap();
+		std::fs::write(dir.join("README"), b"x").unwrap();
+
+		// Keep the newest 2
+		let gone = super::prune(dir, &history, 2).unwrap();
+		assert_eq!(gone.len(), 4, "{gone:?}");
+		let have: Vec<_> = history.iter()
+				.map(|h| super::load(dir, h).is_some()).collect();
+		assert_eq!(have, [false, false, false, true, true]);
+		assert!(dir.join("README").exists());
+
+		// 0 means none at all; a missing dir is nothing to do
+		super::prune(dir, &history, 0).unwrap();
+		assert!(history.iter().all(|h| super::load(dir, h).is_none()));
+		assert!(super::prune(&dir.join("nope"), &history, 2).unwrap()
+				.is_empty());
+	}
+}
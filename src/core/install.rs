@@ -9,6 +9,10 @@ pub(crate) use kernel::backup_kernel;
 mod bits;
 pub(crate) use bits::{dir, file, link, symlink, flags, rm};
 
+/// Removing batches of things
+mod remove;
+pub(crate) use remove::remove;
+
 /// Rolled up installing routines
 mod install;
 pub(crate) use install::split;
@@ -16,8 +20,37 @@ pub(crate) use install::{re_linker_file, re_so_file};
 
 /// Post-install bits
 mod post;
-pub(crate) use post::{kldxref, try_sshd_restart, rehash_certs, pwd_mkdb};
-pub(crate) use post::{cap_mkdb, makewhatis};
+pub(crate) use post::{Helper, regenerate};
+pub(crate) use post::run as run_helper;
+
+/// Restarting services whose bits we replaced
+pub(crate) mod services;
+
+/// Rebooting between steps
+mod reboot;
+pub(crate) use reboot::{reboot, resume_script, write_resume, resume_enabled};
+pub(crate) use reboot::RESUME_SCRIPT;
+
+/// Old shared lib checks
+mod libs;
+pub(crate) use libs::lib_users;
+
+/// Checking what we installed
+mod verify;
+pub(crate) use verify::{verify, audit, Mismatch};
+
+/// Recording intended ownership for unprivileged installs
+mod owners;
+pub(crate) use owners::{OwnerManifest, flags_parse, flags_str};
+
+/// Cleaning up tempfiles from installs that died
+pub(crate) mod residue;
+
+/// Noticing when we install over ourselves
+pub(crate) mod selfexe;
+
+/// Files other processes have open for writing
+mod busy;
 
 
 /// fsync() files?
@@ -31,3 +64,258 @@ pub(crate) fn set_fsync(s: bool) { FSYNC.store(s, atomic::Ordering::Relaxed) }
 
 /// Should installed files get fsync()'d before moving ?
 fn fsync() -> bool { FSYNC.load(atomic::Ordering::Relaxed) }
+
+
+/// Carry ACLs and extattrs over onto files we replace?
+static PRESERVE_EXTRAS: AtomicBool = AtomicBool::new(true);
+
+/// Override the default preserving of ACLs/extattrs
+pub(crate) fn set_preserve_extras(s: bool)
+{ PRESERVE_EXTRAS.store(s, atomic::Ordering::Relaxed) }
+
+fn preserve_extras() -> bool { PRESERVE_EXTRAS.load(atomic::Ordering::Relaxed) }
+
+/// Files we replaced whose ACLs/extattrs didn't make it to the new
+/// version, and what got lost.
+static DROPPED_EXTRAS: std::sync::Mutex<Vec<(std::path::PathBuf, String)>>
+		= std::sync::Mutex::new(Vec::new());
+
+fn note_dropped_extras(path: &std::path::Path, what: String)
+{
+	let mut de = DROPPED_EXTRAS.lock().unwrap_or_else(|e| e.into_inner());
+	de.push((path.to_path_buf(), what));
+}
+
+/// Grab (and clear) the list of files we lost ACLs/extattrs from.
+pub(crate) fn take_dropped_extras() -> Vec<(std::path::PathBuf, String)>
+{
+	let mut de = DROPPED_EXTRAS.lock().unwrap_or_else(|e| e.into_inner());
+	let mut ret = std::mem::take(&mut *de);
+	ret.sort_unstable();
+	ret
+}
+
+
+/// Paths that failed to install while we kept going (x-ref install
+/// --keep-going), and why.
+pub(crate) type Failed = Vec<(std::path::PathBuf, String)>;
+
+/// Is this an error that isn't about the one path, but means nothing
+/// else is going to work either?  A full or read-only filesystem isn't
+/// something to keep going past.
+fn systemic(err: &anyhow::Error) -> bool
+{
+	err.chain().filter_map(|e| e.downcast_ref::<std::io::Error>())
+			.filter_map(|e| e.raw_os_error())
+			.any(|c| matches!(c, libc::ENOSPC | libc::EROFS | libc::EDQUOT))
+}
+
+
+/// Pinned mtime for what we install, if PreserveTimestamps asked for
+/// one; x-ref upstream_mtime().
+static INSTALL_MTIME: std::sync::Mutex<Option<i64>>
+		= std::sync::Mutex::new(None);
+
+/// Pin (or unpin) the mtime installed files and symlinks get.
+pub(crate) fn set_install_mtime(t: Option<i64>)
+{
+	let mut im = INSTALL_MTIME.lock().unwrap_or_else(|e| e.into_inner());
+	*im = t;
+}
+
+fn install_mtime() -> Option<i64>
+{ *INSTALL_MTIME.lock().unwrap_or_else(|e| e.into_inner()) }
+
+
+/// The mtime everything installed from one release/patch level gets
+/// under `PreserveTimestamps upstream`.  The server doesn't tell us
+/// anything like a build time, so it's made up from the version, the
+/// same on every machine: seconds past 2000-01-01, with the version
+/// spelled out in the digits.  So 14.2-RELEASE-p3 is
+/// 946684800 + 14_02_99_003 (rank 99 being RELEASE; ALPHAn 10+n, BETAn
+/// 40+n, RCn 70+n), and later versions always sort later.  Even 99.9
+/// stays under 2^31, and in the past until 2031.
+pub(crate) fn upstream_mtime(vers: &crate::info::AVersion) -> i64
+{
+	const Y2K: i64 = 946684800;
+
+	let mut nums = vers.release.split('.')
+			.map(|n| n.parse::<i64>().unwrap_or(0));
+	let major = nums.next().unwrap_or(0);
+	let minor = nums.next().unwrap_or(0).min(99);
+	let rank = {
+		let rt = vers.reltype.as_str();
+		let tag = rt.trim_end_matches(|c: char| c.is_ascii_digit());
+		let n = rt[tag.len()..].parse::<i64>().unwrap_or(0).min(29);
+		match tag {
+			"RELEASE" => 99,
+			"RC"      => 70 + n,
+			"BETA"    => 40 + n,
+			"ALPHA"   => 10 + n,
+			_         => n.min(9),
+		}
+	};
+	let patch = (vers.patch.unwrap_or(0) as i64).min(999);
+
+	Y2K + major * 10_000_000 + minor * 100_000 + rank * 1000 + patch
+}
+
+
+/// Hashfiles as the up-front check verified them (install's, or
+/// extract's); x-ref bits::open_hashfile().
+static CHECKED_HASHFILES: std::sync::Mutex<Option<std::collections::HashMap<
+		crate::util::hash::Sha256HashBuf,
+		crate::core::pool::present::HashfileId>>>
+		= std::sync::Mutex::new(None);
+
+/// Remember what the hashfiles we'll install from looked like.
+pub(crate) fn set_checked_hashfiles(seen: std::collections::HashMap<
+		crate::util::hash::Sha256HashBuf,
+		crate::core::pool::present::HashfileId>)
+{
+	let mut ch = CHECKED_HASHFILES.lock().unwrap_or_else(|e| e.into_inner());
+	*ch = Some(seen);
+}
+
+fn checked_hashfile(h: &crate::util::hash::Sha256HashBuf)
+		-> Option<crate::core::pool::present::HashfileId>
+{
+	let ch = CHECKED_HASHFILES.lock().unwrap_or_else(|e| e.into_inner());
+	ch.as_ref().and_then(|m| m.get(h).copied())
+}
+
+
+/// Paths we're running from, to install last; x-ref selfexe.
+static INSTALL_LAST: std::sync::Mutex<Vec<std::path::PathBuf>>
+		= std::sync::Mutex::new(Vec::new());
+
+/// Set what goes last.
+pub(crate) fn set_install_last(paths: Vec<std::path::PathBuf>)
+{
+	let mut il = INSTALL_LAST.lock().unwrap_or_else(|e| e.into_inner());
+	*il = paths;
+}
+
+fn install_last() -> std::collections::HashSet<std::path::PathBuf>
+{
+	let il = INSTALL_LAST.lock().unwrap_or_else(|e| e.into_inner());
+	il.iter().cloned().collect()
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn hashfile_swapped()
+	{
+		use crate::core::RtDirs;
+		use crate::metadata::MetaFile;
+		use crate::util::compress::{gz_bytes as gz, write_hashfile};
+
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		let content = b"the real thing\n";
+		let hash = write_hashfile(rtdirs.files(), content);
+		let hb = hash.to_buf();
+		let hf = rtdirs.hashfile(&hb);
+
+		// What the up-front check saw; then something else renames
+		// something over it.
+		let md = hf.metadata().unwrap();
+		super::set_checked_hashfiles([(hb, (&md).into())].into());
+		let swap = |b: &[u8]| {
+			let tmp = hf.with_extension("tmp");
+			std::fs::write(&tmp, gz(b)).unwrap();
+			std::fs::rename(&tmp, &hf).unwrap();
+		};
+		swap(b"something else\n");
+
+		let f = MetaFile { path: "/x".into(), sha256: hash,
+				uid: crate::util::euid(), mode: 0o644, ..Default::default() };
+		let dst = base.path().join("x");
+		let e = super::file(&dst, &f, &rtdirs).expect_err("should notice");
+		assert!(e.to_string().contains("changed since it was checked"), "{e}");
+		assert!(!dst.exists());
+
+		// A different file with the right stuff in it is fine
+		swap(content);
+		super::file(&dst, &f, &rtdirs).unwrap();
+		assert_eq!(std::fs::read(&dst).unwrap(), content);
+
+		// And once it's open, what happens to the path doesn't matter.
+		let md = hf.metadata().unwrap();
+		super::set_checked_hashfiles([(hb, (&md).into())].into());
+		let fh = super::bits::open_hashfile(&hf, &hb).unwrap();
+		swap(b"something else\n");
+		let mut out = Vec::new();
+		crate::util::compress::decompress_gz_fh_write(&fh, &mut out).unwrap();
+		assert_eq!(out, content);
+
+		super::set_checked_hashfiles(Default::default());
+	}
+
+	#[test]
+	fn systemic()
+	{
+		use std::io::Error;
+		use super::systemic;
+
+		let err = |c| anyhow::Error::from(Error::from_raw_os_error(c));
+		assert!(systemic(&err(libc::ENOSPC)));
+		assert!(systemic(&err(libc::EROFS)));
+		assert!(systemic(&err(libc::ENOSPC).context("Installing /bin/sh")));
+		assert!(!systemic(&err(libc::ENOENT)));
+		assert!(!systemic(&err(libc::EACCES)));
+		assert!(!systemic(&anyhow::anyhow!("No space left on device")));
+	}
+
+	#[test]
+	fn upstream_mtime()
+	{
+		use super::upstream_mtime;
+		let mt = |v: &str| upstream_mtime(&v.parse().unwrap());
+
+		assert_eq!(mt("14.2-RELEASE-p3"), 946684800 + 140_299_003);
+		assert_eq!(mt("14.2-RELEASE"), 946684800 + 140_299_000);
+		assert_eq!(mt("14.2-RC1"), 946684800 + 140_271_000);
+
+		// Always later for later versions
+		let order = ["13.4-RELEASE-p9", "14.0-ALPHA2", "14.0-ALPHA3",
+				"14.0-BETA1", "14.0-BETA3", "14.0-RC2", "14.0-RELEASE",
+				"14.0-RELEASE-p1", "14.1-RELEASE", "15.0-RELEASE"];
+		let mts: Vec<_> = order.iter().map(|v| mt(v)).collect();
+		assert!(mts.windows(2).all(|w| w[0] < w[1]), "{mts:?}");
+
+		// And it all fits a 32-bit time_t, with room to spare
+		assert_eq!(mt("99.9-RELEASE-p999"), 1_937_684_799);
+		assert!(mt("99.99-RELEASE-p999") < i32::MAX.into());
+	}
+
+	#[test]
+	fn pinned_mtime()
+	{
+		use std::os::unix::fs::MetadataExt as _;
+		use crate::metadata::MetaSymLink;
+
+		let td = tempfile::tempdir().unwrap();
+		let dst = td.path().join("link");
+		let l = MetaSymLink { target: "nowhere".into(), ..Default::default() };
+
+		// Nothing else installs in the tests, so the global's ours.
+		let t = super::upstream_mtime(&"14.2-RELEASE-p3".parse().unwrap());
+		super::set_install_mtime(Some(t));
+		super::symlink(&dst, &l).unwrap();
+		super::set_install_mtime(None);
+
+		// The link got it, without needing anything to point at.
+		let md = dst.symlink_metadata().unwrap();
+		assert!(md.is_symlink());
+		assert_eq!(md.mtime(), t);
+		assert_eq!(md.atime(), t);
+	}
+}
@@ -11,7 +11,9 @@
  */
 use crate::metadata::{MetaFile, MetaHardLink, MetaDir, MetaSymLink};
 use crate::core::RtDirs;
-use super::fsync;
+use crate::util::out::sayln;
+use super::{fsync, preserve_extras, note_dropped_extras, install_mtime};
+use super::checked_hashfile;
 
 use std::fs;
 use std::path::Path;
@@ -59,21 +61,56 @@ pub(crate) fn dir(dst: &Path, d: &MetaDir) -> Result<(), IOErr>
 
 
 
+/// Open up the hashfile we're about to install from.
+///
+/// If it's not the same file install's up-front check looked at
+/// (something else sharing the workdir swapped it out or rewrote it in
+/// between, say), whatever's there now has to decompress to the right
+/// hash before we'll use it.  Either way, the install reads from this fd,
+/// so what got looked at is what gets installed.
+pub(crate) fn open_hashfile(path: &Path,
+		hash: &crate::util::hash::Sha256HashBuf)
+		-> Result<fs::File, anyhow::Error>
+{
+	use std::io::Seek as _;
+
+	let mut fh = fs::File::open(path)?;
+	let md = fh.metadata()?;
+	if checked_hashfile(hash) == Some((&md).into()) { return Ok(fh); }
+
+	let mut gzd = flate2::read::GzDecoder::new(&fh);
+	crate::util::hash::check_sha256_reader(&mut gzd, hash.as_ref())
+			.map_err(|e| anyhow::anyhow!("Hashfile {} changed since it \
+				was checked, and doesn't match anymore: {e}",
+				path.display()))?;
+	fh.rewind()?;
+	Ok(fh)
+}
+
+
 /// Creating a file
 pub(crate) fn file(dst: &Path, f: &MetaFile, rtdirs: &RtDirs)
 		-> Result<(), anyhow::Error>
 {
-	// First off, we better have the input hashfile, so do a cheap
-	// double-check.  It should be impossible to fail this (unless
-	// something's racing us), since the 'install' command checked
-	// hashfile existence right up front.
+	// First off, we better have the input hashfile.  It should be
+	// impossible to not (unless something's racing us), since the
+	// 'install' command checked hashfile existence right up front.  We
+	// hang onto it open from here, so nothing can swap it out from under
+	// us later.
 	let hbuf = f.sha256.to_buf();
-	let hashfile = rtdirs.hashfile(&hbuf);
-	let _ = hashfile.metadata()?;
+	let hashfile = open_hashfile(&rtdirs.hashfile(&hbuf), &hbuf)?;
 
 	// If there's a dir there, kill it off (loudly)
 	rm_dir(&dst)?;
 
+	// If we're replacing a file, see if it has anything on it we'd lose
+	// by putting a whole new file there.  This is best-effort; if we
+	// can't even look, well, we'll never know.
+	let extras = match dst.symlink_metadata() {
+		Ok(md) if md.is_file() => crate::util::xattr::get(dst).ok(),
+		_ => None,
+	}.filter(|x| !x.is_empty());
+
 	// Write things out into a tempfile
 	let tmpfile = {
 		use tempfile::Builder;
@@ -86,7 +123,8 @@ pub(crate) fn file(dst: &Path, f: &MetaFile, rtdirs: &RtDirs)
 				let dp = dst.display();
 				IOErr::new(ek, format!("No parent dir for {dp}??"))
 			})?;
-		let (tfh, tpath) = Builder::new().tempfile_in(dstdir)?.keep()?;
+		let (tfh, tpath) = Builder::new().prefix(super::residue::TMP_PREFIX)
+				.tempfile_in(dstdir)?.keep()?;
 
 		// Buffer it
 		use std::io::BufWriter;
@@ -94,7 +132,7 @@ pub(crate) fn file(dst: &Path, f: &MetaFile, rtdirs: &RtDirs)
 		let mut tbw = BufWriter::with_capacity(FILE_BUFSZ, tfh);
 
 		// Decompress the data into it
-		rtdirs.decompress_hash_write(&hbuf, &mut tbw)?;
+		crate::util::compress::decompress_gz_fh_write(&hashfile, &mut tbw)?;
 
 		// Tear down the buffer, and optionally fsync.
 		let tfh = tbw.into_inner()?;
@@ -104,9 +142,24 @@ pub(crate) fn file(dst: &Path, f: &MetaFile, rtdirs: &RtDirs)
 		tpath
 	};
 
+	// Carry over the extras, before set_perms(), since applying an ACL
+	// can twiddle the mode.
+	if let Some(x) = extras
+	{
+		let kept = match preserve_extras() {
+			true  => x.apply(&tmpfile).map_err(|e| e.to_string()),
+			false => Err("not preserved".to_string()),
+		};
+		if let Err(e) = kept
+		{ note_dropped_extras(dst, format!("{} ({e})", x.describe())); }
+	}
+
 	// Set the perms as necessary
 	set_perms(&tmpfile, f.uid, f.gid, Some(f.mode))?;
 
+	// Pin the mtime, if we're doing that; rename(2) won't touch it.
+	if let Some(t) = install_mtime() { crate::util::lutimes(&tmpfile, t)?; }
+
 	// Now put the tmpfile in the final location, and we're done.
 	std::fs::rename(&tmpfile, dst)?;
 	Ok(())
@@ -144,13 +197,18 @@ pub(crate) fn link(dst: &Path, l: &MetaHardLink, basedir: &Path)
 		let tm = tpath.symlink_metadata()?;
 		if lm.dev() == tm.dev() && lm.ino() == tm.ino()
 		{ return Ok(()); }
-
-		// Otherwise, kill it off (quietly) and move on
-		fs::remove_file(&dst)?;
 	}
 
-	// And make the link
-	fs::hard_link(&tpath, &dst)?;
+	// Make the link under a temp name alongside, and rename it over
+	// whatever's there, so there's never a moment where dst is missing.
+	let dstdir = dst.parent().ok_or_else(|| {
+			use std::io::ErrorKind;
+			let dp = dst.display();
+			IOErr::new(ErrorKind::NotFound, format!("No parent dir for {dp}??"))
+		})?;
+	let tlink = tempfile::Builder::new().prefix(super::residue::TMP_PREFIX)
+			.make_in(dstdir, |p| fs::hard_link(&tpath, p))?;
+	tlink.persist(dst).map_err(|e| e.error)?;
 
 	Ok(())
 }
@@ -164,22 +222,30 @@ pub(crate) fn symlink(dst: &Path, l: &MetaSymLink) -> Result<(), IOErr>
 	rm_dir(&dst)?;
 
 	// If anything else is there, check stuff
+	let mut make = true;
 	if exists(dst)
 	{
 		// If it's already a symlink, and already pointing at the right
-		// target, we're done.
-		if dst.is_symlink() && dst.read_link()? == l.target
-		{ return Ok(()); }
-
-		// Otherwise, kill it off (quietly) and move on
-		fs::remove_file(&dst)?;
+		// target, it can stay; only its owner/mode might need fixing.
+		// Otherwise, kill it off (quietly) and move on.
+		match dst.is_symlink() && dst.read_link()? == l.target {
+			true  => make = false,
+			false => fs::remove_file(&dst)?,
+		}
 	}
 
 	// And make the link
-	use std::os::unix::fs::symlink;
-	symlink(&l.target, &dst)?;
+	if make
+	{
+		use std::os::unix::fs::symlink;
+		symlink(&l.target, &dst)?;
 
-	Ok(())
+		// The link itself gets the pinned mtime too, not what it points
+		// at.
+		if let Some(t) = install_mtime() { crate::util::lutimes(dst, t)?; }
+	}
+
+	set_link_perms(dst, l.uid, l.gid, l.mode)
 }
 
 
@@ -238,7 +304,7 @@ pub(crate) fn rm(f: &Path) -> Result<bool, IOErr>
 fn rm_dir(d: &Path) -> Result<bool, IOErr>
 {
 	if !exists(d) || !is_dir(d) { return Ok(false); }
-	println!("Removing conflicting directory {}", d.display());
+	sayln!("Removing conflicting directory {}", d.display());
 	fs::remove_dir_all(d)?;
 	Ok(true)
 }
@@ -281,6 +347,33 @@ fn set_perms(f: &Path, uid: u32, gid: u32, mode: Option<u32>)
 }
 
 
+/// set_perms(), but for a symlink itself, rather than what it points at.
+/// They're just about meaningless on a symlink, but they're there, and
+/// the metadata says what they should be.
+fn set_link_perms(l: &Path, uid: u32, gid: u32, mode: u32)
+		-> Result<(), IOErr>
+{
+	use crate::util::euid;
+	use std::os::unix::fs::{lchown, MetadataExt as _};
+
+	let md = l.symlink_metadata()?;
+
+	let uid = match md.uid() == uid {
+		true => None,
+		false => Some(uid),
+	};
+	let gid = match md.gid() == gid {
+		true => None,
+		false => Some(gid),
+	};
+	if (uid.is_some() || gid.is_some()) && (euid() == 0)
+	{ lchown(l, uid, gid)?; }
+
+	if md.mode() & 0o7777 != mode { crate::util::lchmod(l, mode)?; }
+
+	Ok(())
+}
+
 
 /*
  * Wrappers: a lot of Path:: methods trasverse symlinks for
@@ -0,0 +1,328 @@
+//! Noticing files something else has open for writing.
+//!
+//! Installing a file renames a new one over the old.  Anything that had
+//! the old one open for writing (a log, a db some daemon is rebuilding)
+//! carries right on writing to that, now unlinked, and whatever it
+//! writes just vanishes.  So before replacing files in the few places
+//! that sort of thing lives, look for anybody writing to them.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::command::BusyFiles;
+use crate::metadata::MetadataLine;
+use crate::util::out::sayln;
+
+
+/// Where files something might be writing to live; nothing outside these
+/// gets checked.
+const BUSY_DIRS: &[&str] = &["/etc", "/var"];
+
+
+/// A process with a file open for writing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Writer
+{
+	pub(crate) pid: u32,
+	pub(crate) comm: String,
+	pub(crate) path: PathBuf,
+}
+
+
+/// Somewhere to find out what's open for writing.
+pub(crate) trait OpenFiles
+{
+	/// Every file open for writing, by anybody we can see
+	fn writers(&self) -> Result<Vec<Writer>, anyhow::Error>;
+}
+
+
+/// The real thing: procstat(1)'s list of open files.
+#[derive(Debug)]
+pub(crate) struct Procstat;
+
+impl OpenFiles for Procstat
+{
+	fn writers(&self) -> Result<Vec<Writer>, anyhow::Error>
+	{
+		const CMD: &str = "/usr/bin/procstat";
+		let out = std::process::Command::new(CMD).args(["-a", "-f"])
+				.output()?;
+		if !out.status.success()
+		{
+			anyhow::bail!("{CMD} -a -f failed: {:?}", out.status);
+		}
+		Ok(parse_procstat(&String::from_utf8_lossy(&out.stdout)))
+	}
+}
+
+
+/// Pull the files open for writing out of procstat -f output.  That's
+///
+///   PID COMM FD T V FLAGS REF OFFSET PRO NAME
+///
+/// one per open file.  We want vnodes (T is "v") with the write flag
+/// (the "w" second in FLAGS), that have a name.
+fn parse_procstat(out: &str) -> Vec<Writer>
+{
+	out.lines().filter_map(|l| {
+		let flds: Vec<&str> = l.split_whitespace().collect();
+		let pid = flds.first()?.parse().ok()?;
+		if flds.len() < 10 || flds[3] != "v" { return None; }
+		if flds[5].as_bytes().get(1) != Some(&b'w') { return None; }
+		let name = flds[9..].join(" ");
+		if !name.starts_with('/') { return None; }
+		Some(Writer { pid, comm: flds[1].to_string(), path: name.into() })
+	}).collect()
+}
+
+
+/// Which of paths somebody has open for writing, and who.  The paths
+/// are as in the manifest, relative to basedir.
+pub(crate) fn busy(of: &impl OpenFiles, basedir: &Path, paths: &[&Path])
+		-> Result<BTreeMap<PathBuf, Vec<Writer>>, anyhow::Error>
+{
+	use crate::util::path_join;
+
+	let want: HashMap<PathBuf, &Path> = paths.iter()
+			.filter(|p| BUSY_DIRS.iter().any(|d| p.starts_with(d)))
+			.map(|p| (path_join(basedir, p), *p)).collect();
+	let mut ret: BTreeMap<PathBuf, Vec<Writer>> = BTreeMap::new();
+	if want.is_empty() { return Ok(ret); }
+
+	for w in of.writers()?
+	{
+		if let Some(p) = want.get(&w.path)
+		{ ret.entry(p.to_path_buf()).or_default().push(w); }
+	}
+	Ok(ret)
+}
+
+
+/// Who's got it, for saying so
+pub(crate) fn who(ws: &[Writer]) -> String
+{
+	let procs: BTreeSet<_> = ws.iter().map(|w| (w.pid, &w.comm)).collect();
+	let procs: Vec<_> = procs.into_iter()
+			.map(|(pid, comm)| format!("{comm} (pid {pid})")).collect();
+	format!("open for writing by {}", procs.join(", "))
+}
+
+
+/// What hold() and recheck() made of things
+#[derive(Debug, Default)]
+pub(crate) struct Held
+{
+	/// For hold(), what to try again once everything else is in; for
+	/// recheck(), what's free to go in now.
+	pub(crate) later: HashMap<PathBuf, MetadataLine>,
+
+	/// What's not going in, and why
+	pub(crate) skipped: Vec<(PathBuf, String)>,
+}
+
+
+/// Take the files somebody's writing to out of what's about to be
+/// installed.  Under retry they come back in later, to try again once
+/// everything else is in; under skip, they're just skipped.  If we can't
+/// tell, we say so and leave them be.
+pub(crate) fn hold(of: &impl OpenFiles, mode: BusyFiles, basedir: &Path,
+		files: &mut HashMap<PathBuf, MetadataLine>) -> Held
+{
+	let mut ret = Held::default();
+	if mode == BusyFiles::Force { return ret; }
+
+	let paths: Vec<&Path> = files.keys().map(|p| p.as_path()).collect();
+	let busy = match busy(of, basedir, &paths) {
+		Ok(b) => b,
+		Err(e) => {
+			sayln!("WARNING: couldn't check for files open for writing \
+					({e}); installing them regardless.");
+			return ret;
+		},
+	};
+
+	for (p, ws) in busy
+	{
+		let Some(l) = files.remove(&p) else { continue };
+		let why = who(&ws);
+		match mode {
+			BusyFiles::Skip => ret.skipped.push((p, why)),
+			_ => {
+				sayln!("{} is {why}; leaving it for last.", p.display());
+				ret.later.insert(p, l);
+			},
+		}
+	}
+	ret
+}
+
+
+/// Once everything else is in, see which of what hold() held back are
+/// free now.  Anything still busy is skipped.
+pub(crate) fn recheck(of: &impl OpenFiles, basedir: &Path,
+		mut later: HashMap<PathBuf, MetadataLine>) -> Held
+{
+	let paths: Vec<&Path> = later.keys().map(|p| p.as_path()).collect();
+	let busy = match busy(of, basedir, &paths) {
+		Ok(b) => b,
+		Err(e) => {
+			sayln!("WARNING: couldn't check again for files open for \
+					writing ({e}); installing them regardless.");
+			BTreeMap::new()
+		},
+	};
+
+	let mut skipped = Vec::with_capacity(busy.len());
+	for (p, ws) in busy
+	{
+		later.remove(&p);
+		skipped.push((p, who(&ws)));
+	}
+	Held { later, skipped }
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::cell::{Cell, RefCell};
+	use std::collections::HashMap;
+	use std::path::{Path, PathBuf};
+
+	use crate::command::BusyFiles;
+	use crate::metadata::{MetadataLine, MetaFile};
+	use super::{OpenFiles, Writer};
+
+	/// A fake table of open files, that can change between looks, and
+	/// counts how often it got looked at.
+	#[derive(Default)]
+	struct Fake
+	{
+		tables: RefCell<Vec<Vec<Writer>>>,
+		looks: Cell<usize>,
+		fail: bool,
+	}
+
+	impl OpenFiles for Fake
+	{
+		fn writers(&self) -> Result<Vec<Writer>, anyhow::Error>
+		{
+			self.looks.set(self.looks.get() + 1);
+			if self.fail { anyhow::bail!("no procstat here"); }
+			let mut t = self.tables.borrow_mut();
+			match t.len() {
+				0 => Ok(Vec::new()),
+				1 => Ok(t[0].clone()),
+				_ => Ok(t.remove(0)),
+			}
+		}
+	}
+
+	fn w(pid: u32, comm: &str, path: &str) -> Writer
+	{ Writer { pid, comm: comm.to_string(), path: path.into() } }
+
+	fn fake(tables: Vec<Vec<Writer>>) -> Fake
+	{ Fake { tables: RefCell::new(tables), ..Default::default() } }
+
+	fn files(paths: &[&str]) -> HashMap<PathBuf, MetadataLine>
+	{
+		paths.iter().map(|p| (PathBuf::from(p), MetadataLine::File(
+				MetaFile { path: p.into(), ..Default::default() })))
+				.collect()
+	}
+
+	#[test]
+	fn parse_procstat()
+	{
+		let out = "\
+  PID COMM                FD T V FLAGS    REF  OFFSET PRO NAME
+  612 syslogd           text v r r-------   -       - -   /usr/sbin/syslogd
+  612 syslogd              4 v r -w-a----   1   52344 -   /var/log/messages
+  612 syslogd              5 v r -w-a----   1       0 -   /var/log/my log
+  700 sh                   0 v c rw------   5 1234567 -   /dev/pts/0
+  701 cat                  3 v r r-------   1       0 -   /etc/motd
+  702 dbd                  7 v r rw------   1    4096 -   -
+  703 nc                   3 s - rw------   1       0 TCP 127.0.0.1:80
+";
+		let got = super::parse_procstat(out);
+		assert_eq!(got, [w(612, "syslogd", "/var/log/messages"),
+				w(612, "syslogd", "/var/log/my log"),
+				w(700, "sh", "/dev/pts/0")]);
+	}
+
+	#[test]
+	fn busy()
+	{
+		let base = Path::new("/jail");
+		let paths = [Path::new("/var/log/messages"), Path::new("/etc/motd"),
+				Path::new("/bin/sh")];
+
+		// Only /etc and /var count, and under the basedir
+		let of = fake(vec![vec![w(1, "syslogd", "/jail/var/log/messages"),
+				w(2, "syslogd", "/var/log/messages"),
+				w(3, "sh", "/jail/bin/sh"),
+				w(4, "vi", "/jail/etc/motd"),
+				w(4, "vi", "/jail/etc/motd")]]);
+		let got = super::busy(&of, base, &paths).unwrap();
+		let got: Vec<_> = got.iter().map(|(p, ws)| (p.to_str().unwrap(),
+				super::who(ws))).collect();
+		assert_eq!(got, [("/etc/motd", "open for writing by vi (pid 4)"
+					.to_string()),
+				("/var/log/messages", "open for writing by syslogd (pid 1)"
+					.to_string())]);
+
+		// Nothing that could be busy, no need to look
+		let of = fake(Vec::new());
+		assert!(super::busy(&of, base, &paths[2..]).unwrap().is_empty());
+		assert_eq!(of.looks.get(), 0);
+	}
+
+	#[test]
+	fn hold()
+	{
+		let base = Path::new("/");
+		let table = vec![w(1, "syslogd", "/var/log/messages"),
+				w(9, "dbd", "/var/db/x.db")];
+		let all = files(&["/var/log/messages", "/var/db/x.db", "/etc/motd"]);
+
+		// Skip takes them straight out
+		let mut fs = all.clone();
+		let h = super::hold(&fake(vec![table.clone()]), BusyFiles::Skip,
+				base, &mut fs);
+		assert!(h.later.is_empty());
+		assert_eq!(h.skipped, [
+				(PathBuf::from("/var/db/x.db"),
+					"open for writing by dbd (pid 9)".to_string()),
+				(PathBuf::from("/var/log/messages"),
+					"open for writing by syslogd (pid 1)".to_string())]);
+		assert_eq!(fs.len(), 1);
+
+		// Force doesn't even look
+		let mut fs = all.clone();
+		let of = fake(vec![table.clone()]);
+		let h = super::hold(&of, BusyFiles::Force, base, &mut fs);
+		assert!(h.later.is_empty() && h.skipped.is_empty());
+		assert_eq!((fs.len(), of.looks.get()), (3, 0));
+
+		// Retry holds them for later; by then dbd's done, but syslogd
+		// isn't.
+		let mut fs = all.clone();
+		let of = fake(vec![table.clone(), table[..1].to_vec()]);
+		let h = super::hold(&of, BusyFiles::Retry, base, &mut fs);
+		assert!(h.skipped.is_empty());
+		assert_eq!((fs.len(), h.later.len()), (1, 2));
+		let h = super::recheck(&of, base, h.later);
+		assert_eq!(h.later.keys().collect::<Vec<_>>(), [Path::new(
+				"/var/db/x.db")]);
+		assert_eq!(h.skipped, [(PathBuf::from("/var/log/messages"),
+				"open for writing by syslogd (pid 1)".to_string())]);
+
+		// Can't tell, so in they go
+		let mut fs = all.clone();
+		let of = Fake { fail: true, ..Default::default() };
+		let h = super::hold(&of, BusyFiles::Retry, base, &mut fs);
+		assert!(h.later.is_empty() && h.skipped.is_empty());
+		assert_eq!(fs.len(), 3);
+	}
+}
@@ -2,14 +2,15 @@
 //!
 //! These were originally just internals of src/install.rs, but are big
 //! enough and otherwise useful enough to share.
+use crate::command::BusyFiles;
 use crate::core::RtDirs;
 use crate::metadata::MetadataLine;
 use crate::metadata::SplitTypes;
 use crate::util::{plural, path_join};
 use crate::core::install as install;
+use crate::util::out::{self, say, sayln};
 
-use std::io::{stdout, Write as _};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 
@@ -31,10 +32,42 @@ pub(crate) fn re_so_file() -> Regex
 
 
 /// Once we have a SplitTypes, install it all.
-pub(crate) fn split(smd: SplitTypes, rtdirs: &RtDirs, basedir: &Path,
-		dry: bool)
-		-> Result<(), anyhow::Error>
+///
+/// Files something's writing to get handled per busy (x-ref busy).  With
+/// keep_going, paths we can't install (short of systemic trouble) get
+/// passed over rather than stopping us.  Either way, what didn't go in
+/// gets returned, for the caller to deal with.
+pub(crate) fn split(mut smd: SplitTypes, rtdirs: &RtDirs, basedir: &Path,
+		busy: BusyFiles, keep_going: bool, dry: bool)
+		-> Result<install::Failed, anyhow::Error>
 {
+	// Anything that won't fit under basedir, we'd find out about
+	// halfway through, which is the worst time.  So find out now.
+	let all = [&smd.dirs, &smd.files, &smd.syms, &smd.hards];
+	check_path_lens(basedir, all.iter().flat_map(|hm| hm.keys()))?;
+
+	// Files something else is writing to wait, or get skipped; x-ref
+	// busy.  Skipped ones stay pending like any other failure.  Hardlinks
+	// to the ones waiting wait with them, or they'd link to the old file.
+	use install::busy;
+	let held = match dry {
+		true  => busy::Held::default(),
+		false => busy::hold(&busy::Procstat, busy, basedir, &mut smd.files),
+	};
+	let mut failed = install::Failed::new();
+	for (p, why) in held.skipped
+	{
+		sayln!("Skipping {}; it's {why}.", p.display());
+		skip(&mut smd, p, why, &mut failed);
+	}
+	let later_hards: HashMap<_, _> = {
+		let (later, now) = std::mem::take(&mut smd.hards).into_iter()
+				.partition(|(_, l)| link_target(l)
+					.is_some_and(|t| held.later.contains_key(t)));
+		smd.hards = now;
+		later
+	};
+
 	// Now start installing the bits.  f-u.sh just goes through the
 	// manifest lexically and splats things in place.  I'm going to do it
 	// by type instead; handle all the dirs, then the files, then the
@@ -43,14 +76,14 @@ pub(crate) fn split(smd: SplitTypes, rtdirs: &RtDirs, basedir: &Path,
 	// Maybe should look at setting up threadpools for this, but it's not
 	// quite trivial; we have to worry about ordering issues.  At least
 	// for dirs...   hm.  Revisit this.
-	let dry_do_one = |hm: &HashMap<_, _>|
+	let dry_do_one = |hm: &HashMap<_, _>, failed: &mut install::Failed|
 			-> Result<(), anyhow::Error> {
 		match dry {
 			true => {
-				println!("  (dry run, not installing)");
+				sayln!("  (dry run, not installing)");
 				Ok(())
 			},
-			false => do_mdl_installs(hm, rtdirs, basedir)
+			false => do_mdl_installs(hm, rtdirs, basedir, keep_going, failed)
 		}
 	};
 
@@ -61,62 +94,122 @@ pub(crate) fn split(smd: SplitTypes, rtdirs: &RtDirs, basedir: &Path,
 
 	if dlen > 0
 	{
-		println!("{} director{}", dlen,
+		sayln!("{} director{}", dlen,
 			if dlen > 1 { "ies" } else { "y" });
-		dry_do_one(&smd.dirs)?;
+		dry_do_one(&smd.dirs, &mut failed)?;
 	}
 
 	if flen > 0
 	{
-		println!("{} file{}", flen, plural(flen));
-		dry_do_one(&smd.files)?;
+		sayln!("{} file{}", flen, plural(flen));
+		dry_do_one(&smd.files, &mut failed)?;
 	}
 
 	if slen > 0
 	{
-		println!("{} symlink{}", slen, plural(slen));
-		dry_do_one(&smd.syms)?;
+		sayln!("{} symlink{}", slen, plural(slen));
+		dry_do_one(&smd.syms, &mut failed)?;
 	}
 
 	if hlen > 0
 	{
-		println!("{} hardlink{}", hlen, plural(hlen));
-		dry_do_one(&smd.hards)?;
+		sayln!("{} hardlink{}", hlen, plural(hlen));
+		dry_do_one(&smd.hards, &mut failed)?;
+	}
+
+	// Now what was busy, if it's not any more.  This is the last thing
+	// we install, so whatever was writing has had all the rest of the
+	// install to finish up; we don't wait around past that.
+	if !held.later.is_empty()
+	{
+		smd.hards = later_hards;
+		let now = busy::recheck(&busy::Procstat, basedir, held.later);
+		for (p, why) in now.skipped
+		{
+			let why = format!("still {why}");
+			sayln!("Skipping {}; it's {why}.", p.display());
+			skip(&mut smd, p, why, &mut failed);
+		}
+		let nl = now.later.len();
+		if nl > 0
+		{
+			sayln!("{nl} file{} no longer busy", plural(nl));
+			do_mdl_installs(&now.later, rtdirs, basedir, keep_going,
+					&mut failed)?;
+		}
+		if !smd.hards.is_empty()
+		{
+			do_mdl_installs(&smd.hards, rtdirs, basedir, keep_going,
+					&mut failed)?;
+		}
 	}
 
 
 
-	// Second pass: set schg flags.
+	// Second pass: set schg flags.  Not on what didn't get installed,
+	// though.
+	for (p, _) in &failed { smd.flags.remove(p); }
 	let flen = smd.flags.len();
 	if flen > 0 && dry
 	{
-		println!("{flen} flag{}  (dry run)", plural(flen));
+		sayln!("{flen} flag{}  (dry run)", plural(flen));
 	}
 	else if flen > 0 && crate::util::euid() != 0
 	{
-		println!("Not setting {flen} flag{} because you're not root.",
+		sayln!("Not setting {flen} flag{} because you're not root.",
 				plural(flen));
 	}
 	else if flen > 0
 	{
 		// Not bothering to progress this, there's rarely
 		// non-single-digit.
-		print!("Setting {flen} flag{}...  ", plural(flen));
-		stdout().flush()?;
+		say!("Setting {flen} flag{}...  ", plural(flen));
+		out::flush();
 		for (p, mdl) in &smd.flags
 		{
 			let flags = mdl.flags().expect("Must exist if we get here");
 			install::flags(p, flags)?;
 		}
-		println!("Done.");
+		sayln!("Done.");
 	}
 
-	Ok(())
+	failed.sort_unstable();
+	Ok(failed)
 }
 
 
 
-use indicatif::ProgressBar;
+/// Take a file that's not going in out of the rest of what is: its
+/// flags, and any hardlinks to it, which stay pending along with it.
+fn skip(smd: &mut SplitTypes, p: PathBuf, why: String,
+		failed: &mut install::Failed)
+{
+	let links: Vec<PathBuf> = smd.hards.iter()
+			.filter(|(_, l)| link_target(l) == Some(&p))
+			.map(|(lp, _)| lp.clone()).collect();
+	for l in links
+	{
+		smd.hards.remove(&l);
+		smd.flags.remove(&l);
+		let lwhy = format!("hardlink to {}, which is {why}", p.display());
+		failed.push((l, lwhy));
+	}
+	smd.flags.remove(&p);
+	failed.push((p, why));
+}
+
+/// What a hardlink links to, if it's a hardlink.
+fn link_target(l: &MetadataLine) -> Option<&PathBuf>
+{
+	match l {
+		MetadataLine::HardLink(h) => Some(&h.target),
+		_ => None,
+	}
+}
+
+
+
+use crate::core::pool::Progress;
 
 /// Iterate over a set of MetadataLine's, doing the installs.
 ///
@@ -135,11 +228,12 @@ use indicatif::ProgressBar;
 /// feels like there are drawbacks both ways though, so I'm going to
 /// forge ahead.
 fn do_mdl_installs(hm: &HashMap<PathBuf, MetadataLine>, rtdirs: &RtDirs,
-		basedir: &Path) -> Result<(), anyhow::Error>
+		basedir: &Path, keep_going: bool, failed: &mut install::Failed)
+		-> Result<(), anyhow::Error>
 {
 	use crate::metadata::MetadataLine as ML;
 
-	let pb = ProgressBar::new(hm.len().try_into().unwrap());
+	let mut pb = Progress::new(hm.len());
 
 
 	// If one entry is a dir, they're all dirs, so just shortcut and make
@@ -150,8 +244,9 @@ fn do_mdl_installs(hm: &HashMap<PathBuf, MetadataLine>, rtdirs: &RtDirs,
 		{
 			ML::Dir(_) => {
 				let paths: Vec<_> = hm.keys().sorted().collect();
-				let ret = do_mdl_installs_inner(&paths, &pb, hm,
-						rtdirs, basedir);
+				let ret = do_mdl_installs_inner(&paths, &mut pb, hm,
+						rtdirs, basedir, &Default::default(), keep_going,
+						failed);
 				pb.finish();
 				return ret;
 			},
@@ -187,9 +282,21 @@ fn do_mdl_installs(hm: &HashMap<PathBuf, MetadataLine>, rtdirs: &RtDirs,
 	}
 
 
-	// OK, now go through 'em in order
-	let doit = |v| {
-		do_mdl_installs_inner(v, &pb, hm, rtdirs, basedir)
+	// OK, now go through 'em in order.  Within each batch, keep
+	// everything in a dir together, so we can sync the dirs once apiece
+	// rather than churning back and forth (x-ref DirSync).
+	by_dir(&mut lds);
+	by_dir(&mut shlibs);
+	by_dir(&mut rest);
+
+	// Anything we're running from goes at the end of its batch.
+	let last = install::install_last();
+	for v in [&mut lds, &mut shlibs, &mut rest]
+	{ to_end(v, &last); }
+
+	let mut doit = |v| {
+		do_mdl_installs_inner(v, &mut pb, hm, rtdirs, basedir, &last,
+				keep_going, failed)
 	};
 	doit(&lds)?;
 	doit(&shlibs)?;
@@ -201,26 +308,368 @@ fn do_mdl_installs(hm: &HashMap<PathBuf, MetadataLine>, rtdirs: &RtDirs,
 	Ok(())
 }
 
-fn do_mdl_installs_inner(paths: &[impl AsRef<Path>], pb: &ProgressBar,
+fn do_mdl_installs_inner(paths: &[impl AsRef<Path>], pb: &mut Progress,
 		hm: &HashMap<PathBuf, MetadataLine>, rtdirs: &RtDirs,
-		basedir: &Path) -> Result<(), anyhow::Error>
+		basedir: &Path, last: &HashSet<PathBuf>, keep_going: bool,
+		failed: &mut install::Failed) -> Result<(), anyhow::Error>
 {
 	use crate::metadata::MetadataLine as ML;
 
+	let mut dsync = DirSync::new(install::fsync());
+	let mut synced = false;
 	for p in paths
 	{
+		// Before replacing what we're running from, get everything
+		// else onto disk, so dying right then leaves as little as
+		// possible half-done.
+		if !synced && last.contains(p.as_ref())
+		{
+			dsync.finish()?;
+			if install::fsync()
+			{
+				// SAFETY: no args, can't fail
+				unsafe { libc::sync() };
+			}
+			synced = true;
+		}
+
 		let mdl = hm.get(p.as_ref()).unwrap();
 		let dst = path_join(basedir, p);
-		match mdl
+		let res: Result<(), anyhow::Error> = match mdl
 		{
-			ML::Dir(m)      => install::dir(&dst, m)?,
-			ML::File(m)     => install::file(&dst, m, &rtdirs)?,
-			ML::SymLink(m)  => install::symlink(&dst, m)?,
-			ML::HardLink(m) => install::link(&dst, m, basedir)?,
+			ML::Dir(m)      => install::dir(&dst, m).map_err(Into::into),
+			ML::File(m)     => install::file(&dst, m, &rtdirs),
+			ML::SymLink(m)  => install::symlink(&dst, m).map_err(Into::into),
+			ML::HardLink(m) => install::link(&dst, m, basedir)
+					.map_err(Into::into),
 			_ => unreachable!("Impossible!"),
+		};
+
+		// With --keep-going, one path we can't do gets noted for the end
+		// and we move along; running out of disk or the like still stops
+		// us right here.
+		if let Err(e) = res
+		{
+			if !keep_going || install::systemic(&e) { return Err(e); }
+			failed.push((p.as_ref().to_path_buf(), format!("{e:#}")));
+			pb.inc();
+			continue;
 		}
-		pb.inc(1);
+
+		// Dirs themselves get their entries in their parents, but
+		// there's no grouping there to take advantage of.
+		if !matches!(mdl, ML::Dir(_)) { dsync.enter(&dst)?; }
+		pb.inc();
 	}
+	dsync.finish()?;
 
 	Ok(())
 }
+
+
+/// Move anything in last to the end, keeping the order otherwise.
+fn to_end(paths: &mut Vec<&PathBuf>, last: &HashSet<PathBuf>)
+{
+	if last.is_empty() { return; }
+	let (mut keep, end): (Vec<&PathBuf>, Vec<&PathBuf>) = paths.drain(..)
+			.partition(|p| !last.contains(*p));
+	keep.extend(end);
+	*paths = keep;
+}
+
+
+/// Order paths so everything in a given dir is together, in name order
+/// within it.  Plain sorting by path mostly does that, but subdirs land
+/// in the middle of their parent's files.
+fn by_dir(paths: &mut [&PathBuf])
+{
+	paths.sort_unstable_by(|a, b| {
+		(a.parent(), a.file_name()).cmp(&(b.parent(), b.file_name()))
+	});
+}
+
+
+/// Syncing dirs we install a run of things into.
+///
+/// Each file goes in via a tempfile and rename, which leaves each rename
+/// to get to disk on its own schedule, and with thousands of little files
+/// in a dir (like manpages), that gets bursty.  Since by_dir() brings
+/// everything in a dir through together, we can hold the dir open while
+/// we're in it, and fsync it once when we move along.
+struct DirSync
+{
+	/// Whether to bother at all
+	enabled: bool,
+
+	/// The dir we're currently in, if any
+	cur: Option<(PathBuf, std::fs::File)>,
+
+	/// How many dirs we've synced
+	synced: usize,
+}
+
+impl DirSync
+{
+	fn new(enabled: bool) -> Self
+	{
+		Self { enabled, cur: None, synced: 0 }
+	}
+
+	/// Note that we just installed this path.  If it's in a different
+	/// dir than the last one, sync up the last one and move along.
+	fn enter(&mut self, dst: &Path) -> Result<(), std::io::Error>
+	{
+		if !self.enabled { return Ok(()); }
+		let dir = match dst.parent() {
+			Some(d) => d,
+			None => return Ok(()),
+		};
+
+		if let Some((cd, _)) = &self.cur
+		{
+			if cd == dir { return Ok(()); }
+		}
+		self.finish()?;
+		let fh = std::fs::File::open(dir)?;
+		self.cur = Some((dir.to_path_buf(), fh));
+		Ok(())
+	}
+
+	/// Sync whatever dir we're in.
+	fn finish(&mut self) -> Result<(), std::io::Error>
+	{
+		if let Some((_, fh)) = self.cur.take()
+		{
+			fh.sync_all()?;
+			self.synced += 1;
+		}
+		Ok(())
+	}
+}
+
+
+/// Make sure paths will all fit under PATH_MAX once they're under
+/// basedir.
+fn check_path_lens<'a>(basedir: &Path,
+		paths: impl Iterator<Item = &'a PathBuf>)
+		-> Result<(), anyhow::Error>
+{
+	use crate::util::{too_long, PATH_MAX};
+
+	let mut long: Vec<_> = paths
+			.filter(|p| too_long(&path_join(basedir, p))).collect();
+	let eg = match long.iter().min() {
+		Some(p) => p.to_string_lossy().chars().take(60).collect::<String>(),
+		None => return Ok(()),
+	};
+	long.sort_unstable();
+	long.dedup();
+
+	let nl = long.len();
+	anyhow::bail!("{nl} path{} would be too long under {} (PATH_MAX is \
+			{PATH_MAX}), e.g. {eg}...; not installing anything.",
+			plural(nl), basedir.display())
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use crate::core::RtDirs;
+	use crate::metadata::{Metadata, MetaDir, MetaSymLink};
+	use crate::util::out;
+
+	#[test]
+	fn split_closed_stdout()
+	{
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		let uid = crate::util::euid();
+		let md = Metadata {
+			dirs: [("/a/b".into(), MetaDir { path: "/a/b".into(), uid,
+					mode: 0o755, ..Default::default() })].into(),
+			symlinks: [("/a/b/c".into(), MetaSymLink { path: "/a/b/c".into(),
+					target: "nowhere".into(), uid, mode: 0o755,
+					..Default::default() })].into(),
+			..Default::default()
+		};
+
+		// Nobody's listening to anything we say, but that's no reason
+		// not to do the work.
+		out::set_sink(Some(Box::new(out::closed_pipe())));
+		let ret = super::split(md.into(), &rtdirs, base.path(),
+				Default::default(), false, false);
+		out::set_sink(None);
+		ret.expect("install should succeed");
+
+		assert!(base.path().join("a/b").is_dir());
+		let lnk = base.path().join("a/b/c").read_link().unwrap();
+		assert_eq!(lnk, std::path::Path::new("nowhere"));
+	}
+
+	#[test]
+	fn keep_going()
+	{
+		use crate::metadata::SplitTypes;
+
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		// A dangling symlink where there should be a dir, so anything
+		// going under it fails.
+		std::os::unix::fs::symlink("gone", base.path().join("loc")).unwrap();
+
+		let uid = crate::util::euid();
+		let dir = |p: &str| (p.into(), MetaDir { path: p.into(), uid,
+				mode: 0o755, ..Default::default() });
+		let md = Metadata {
+			dirs: [dir("/ok"), dir("/loc/sub"), dir("/zz")].into(),
+			symlinks: [("/ok/l".into(), MetaSymLink { path: "/ok/l".into(),
+					target: "nowhere".into(), uid, mode: 0o755,
+					..Default::default() })].into(),
+			..Default::default()
+		};
+		let smd = || -> SplitTypes { md.clone().into() };
+
+		// Normally, that stops us
+		let ret = super::split(smd(), &rtdirs, base.path(), Default::default(),
+				false, false);
+		ret.expect_err("poisoned dir should fail");
+
+		// Keeping going, it's noted and everything else goes in
+		let ret = super::split(smd(), &rtdirs, base.path(), Default::default(),
+				true, false);
+		let failed = ret.expect("install should carry on");
+		let fpaths: Vec<_> = failed.iter().map(|(p, _)| p.to_str().unwrap())
+				.collect();
+		assert_eq!(fpaths, ["/loc/sub"], "{failed:?}");
+		assert!(base.path().join("ok").is_dir());
+		assert!(base.path().join("zz").is_dir());
+		assert!(base.path().join("ok/l").is_symlink());
+		assert!(!base.path().join("loc/sub").exists());
+	}
+
+	#[test]
+	fn skip()
+	{
+		use crate::metadata::{MetadataLine, MetaFile, MetaHardLink};
+
+		let file = |p: &str| -> (std::path::PathBuf, MetadataLine) {
+			(p.into(), MetaFile { path: p.into(), flags: 0o400000,
+					..Default::default() }.into())
+		};
+		let hard = |p: &str, t: &str| -> (std::path::PathBuf, MetadataLine) {
+			(p.into(), MetaHardLink { path: p.into(), target: t.into() }
+					.into())
+		};
+		let lines = [file("/bin/a"), file("/bin/b"), hard("/bin/la", "/bin/a"),
+				hard("/bin/lb", "/bin/b")].into();
+		let mut smd = crate::metadata::SplitTypes::from_map_lines(lines);
+		smd.flags.insert("/bin/la".into(), file("/bin/la").1);
+
+		// Skipping a file takes its flags and links along with it
+		let mut failed = Vec::new();
+		smd.files.remove(std::path::Path::new("/bin/a"));
+		super::skip(&mut smd, "/bin/a".into(), "busy".into(), &mut failed);
+		failed.sort_unstable();
+		assert_eq!(failed, [("/bin/a".into(), "busy".into()),
+				("/bin/la".into(), "hardlink to /bin/a, which is busy".into())]);
+
+		let keys = |hm: &std::collections::HashMap<_, _>| {
+			let mut k: Vec<_> = hm.keys().cloned().collect();
+			k.sort_unstable();
+			k
+		};
+		let b: Vec<std::path::PathBuf> = vec!["/bin/b".into()];
+		let lb: Vec<std::path::PathBuf> = vec!["/bin/lb".into()];
+		assert_eq!(keys(&smd.flags), b);
+		assert_eq!(keys(&smd.hards), lb);
+	}
+
+	#[test]
+	fn by_dir()
+	{
+		use std::path::PathBuf;
+
+		let paths: Vec<PathBuf> = ["/a/b/z", "/a/b/sub/x", "/a/b/a", "/a/c",
+				"/a/b/sub/a"].iter().map(PathBuf::from).collect();
+		let mut refs: Vec<_> = paths.iter().collect();
+		super::by_dir(&mut refs);
+		let got: Vec<_> = refs.iter().map(|p| p.to_str().unwrap()).collect();
+		assert_eq!(got, ["/a/c", "/a/b/a", "/a/b/z", "/a/b/sub/a",
+				"/a/b/sub/x"]);
+	}
+
+	#[test]
+	fn to_end()
+	{
+		use std::collections::HashSet;
+		use std::path::PathBuf;
+
+		let paths: Vec<PathBuf> = ["/lib/libc.so.7", "/lib/libm.so.5",
+				"/lib/libthr.so.3", "/lib/libz.so.6"].iter()
+				.map(PathBuf::from).collect();
+		let last: HashSet<PathBuf> = ["/lib/libthr.so.3", "/lib/libc.so.7"]
+				.iter().map(PathBuf::from).collect();
+		let mut refs: Vec<_> = paths.iter().collect();
+		super::to_end(&mut refs, &last);
+		let got: Vec<_> = refs.iter().map(|p| p.to_str().unwrap()).collect();
+		assert_eq!(got, ["/lib/libm.so.5", "/lib/libz.so.6", "/lib/libc.so.7",
+				"/lib/libthr.so.3"]);
+
+		// Nothing to move, nothing moves
+		let mut refs: Vec<_> = paths.iter().collect();
+		super::to_end(&mut refs, &HashSet::new());
+		assert!(refs.iter().zip(&paths).all(|(a, b)| *a == b));
+	}
+
+	#[test]
+	fn dir_sync()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let (d1, d2) = (td.path().join("d1"), td.path().join("d2"));
+		std::fs::create_dir(&d1).unwrap();
+		std::fs::create_dir(&d2).unwrap();
+
+		// Each run of a dir is one sync.
+		let mut ds = super::DirSync::new(true);
+		for p in [d1.join("a"), d1.join("b"), d2.join("c"), d1.join("d")]
+		{ ds.enter(&p).unwrap(); }
+		ds.finish().unwrap();
+		assert_eq!(ds.synced, 3);
+
+		// And nothing when we're not fsync'ing.
+		let mut ds = super::DirSync::new(false);
+		ds.enter(&d1.join("a")).unwrap();
+		ds.finish().unwrap();
+		assert_eq!(ds.synced, 0);
+	}
+
+	#[test]
+	fn path_lens()
+	{
+		use std::path::{Path, PathBuf};
+		use crate::util::PATH_MAX;
+		use super::check_path_lens;
+
+		// Fits at /, but not under a deep enough basedir
+		let p: PathBuf = "/x".repeat(PATH_MAX / 2 - 10).into();
+		let ok: PathBuf = "/bin/sh".into();
+		let ps = [ok.clone(), p.clone()];
+		check_path_lens(Path::new("/"), ps.iter()).unwrap();
+
+		let deep: PathBuf = "/jail".repeat(10).into();
+		let e = check_path_lens(&deep, ps.iter()).expect_err("too long");
+		let e = e.to_string();
+		assert!(e.starts_with("1 path would be too long"), "{e}");
+		assert!(e.contains("/x/x/x"), "{e}");
+
+		check_path_lens(&deep, [ok].iter()).unwrap();
+	}
+}
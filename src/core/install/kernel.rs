@@ -7,7 +7,11 @@ use std::path::{Path, PathBuf};
 /// This is the "outside" bit of f-u.sh's backup_kernel().  Though note
 /// that we don't conditionalize, and we don't allow configuring the
 /// BackupKernelDir; I'm just hardcoding it all.
-pub(crate) fn backup_kernel(basedir: &Path) -> Result<(), anyhow::Error>
+///
+/// Returns the dir (under basedir) we backed up to, if there was
+/// anything to back up.
+pub(crate) fn backup_kernel(basedir: &Path)
+		-> Result<Option<PathBuf>, anyhow::Error>
 {
 	// XXX f-u.sh seems a little broken WRT ${BASEDIR} here; it always
 	// uses the `kern.bootfile` result for the 'running kernel'.  But
@@ -24,7 +28,7 @@ pub(crate) fn backup_kernel(basedir: &Path) -> Result<(), anyhow::Error>
 
 	// If there's no kernel in that place, there's nothing to backup.
 	let skern = crate::util::path_join(basedir, &srcdir).join("kernel");
-	if !skern.exists() { return Ok(()); }
+	if !skern.exists() { return Ok(None); }
 
 	let bakdir = match backup_dir(basedir) {
 		Some(p) => p,
@@ -33,7 +37,7 @@ pub(crate) fn backup_kernel(basedir: &Path) -> Result<(), anyhow::Error>
 
 	// JFDI
 	do_backup(basedir, &srcdir, &bakdir)?;
-	Ok(())
+	Ok(Some(bakdir))
 }
 
 
@@ -0,0 +1,145 @@
+//! Checking on old shared libs before removing them.
+//!
+//! The last step of an upgrade removes the old .so's.  If something
+//! running still has them mapped, it's a pretty good sign the user
+//! hasn't rebuilt everything yet, so we want to be able to say so.
+
+use std::process::Command;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+
+/// A process using one or more of the libs we're asking about.
+#[derive(Debug)]
+pub(crate) struct LibUser
+{
+	pub(crate) pid: u32,
+	pub(crate) comm: String,
+	pub(crate) libs: Vec<PathBuf>,
+}
+
+
+/// Find running processes with any of the given libs mapped.
+///
+/// Uses procstat(1) to look at the VM mappings of everything running.
+/// The libs are relative to the basedir, as they are in the manifest.
+pub(crate) fn lib_users(basedir: &Path, libs: &[PathBuf])
+		-> Result<Vec<LibUser>, anyhow::Error>
+{
+	use crate::util::path_join;
+
+	if libs.is_empty() { return Ok(Vec::new()); }
+
+	// Map the on-disk paths back to what we'll report.
+	let want: HashSet<PathBuf> = libs.iter()
+			.map(|l| path_join(basedir, l)).collect();
+
+	// procstat -v output is "PID START END ... PATH", one per mapping.
+	const CMD: &str = "/usr/bin/procstat";
+	let out = Command::new(CMD).args(["-a", "-v"]).output()?;
+	if !out.status.success()
+	{
+		anyhow::bail!("{CMD} -a -v failed: {:?}", out.status);
+	}
+	let users = parse_vmaps(&String::from_utf8_lossy(&out.stdout), &want);
+	if users.is_empty() { return Ok(Vec::new()); }
+
+	// Now get names for 'em
+	let out = Command::new(CMD).arg("-a").output()?;
+	let comms = parse_comms(&String::from_utf8_lossy(&out.stdout));
+
+	let ret = users.into_iter().map(|(pid, libs)| {
+		let comm = comms.get(&pid).cloned()
+				.unwrap_or_else(|| "?".to_string());
+		let mut libs: Vec<_> = libs.into_iter().collect();
+		libs.sort_unstable();
+		LibUser { pid, comm, libs }
+	}).collect();
+	Ok(ret)
+}
+
+
+/// Which processes have which of want mapped, out of procstat -v
+/// output.
+fn parse_vmaps(vmaps: &str, want: &HashSet<PathBuf>)
+		-> BTreeMap<u32, HashSet<PathBuf>>
+{
+	let mut users: BTreeMap<u32, HashSet<PathBuf>> = BTreeMap::new();
+	for l in vmaps.lines()
+	{
+		let mut flds = l.split_whitespace();
+		let pid: u32 = match flds.next().and_then(|p| p.parse().ok()) {
+			Some(p) => p,
+			None => continue,  // header, probably
+		};
+		let path = match flds.last() {
+			Some(p) if p.starts_with('/') => Path::new(p),
+			_ => continue,
+		};
+		if want.contains(path)
+		{
+			users.entry(pid).or_default().insert(path.to_path_buf());
+		}
+	}
+	users
+}
+
+
+/// Process names out of plain procstat output, which has COMM as the
+/// last field.
+fn parse_comms(pslist: &str) -> BTreeMap<u32, String>
+{
+	pslist.lines().filter_map(|l| {
+		let mut flds = l.split_whitespace();
+		let pid: u32 = flds.next()?.parse().ok()?;
+		let comm = flds.last()?.to_string();
+		Some((pid, comm))
+	}).collect()
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashSet;
+	use std::path::PathBuf;
+
+	#[test]
+	fn parse_vmaps()
+	{
+		let out = "\
+  PID              START                END PRT  RES PRES REF SHD FLAG  TP PATH
+  612           0x200000           0x2a4000 r--   84  340   9   3 CN--- vn /usr/sbin/sshd
+  612        0x826c00000        0x826c3f000 r--   63   66  36  18 CN--- vn /lib/libcrypto.so.30
+  612        0x826c3f000        0x826c40000 rw-    1    0   1   0 C---- vn /lib/libcrypto.so.30
+  612        0x827000000        0x827200000 rw-    6    6   1   0 C---- df 
+  840        0x8260a4000        0x8260b0000 r--   12   12  40  20 CN--- vn /lib/libc.so.7
+  901        0x826c00000        0x826c3f000 r--   63   66  36  18 CN--- vn /lib/libcrypto.so.30
+";
+		let want: HashSet<PathBuf> = ["/lib/libcrypto.so.30", "/lib/libz.so.6"]
+				.iter().map(PathBuf::from).collect();
+		let users = super::parse_vmaps(out, &want);
+
+		let pids: Vec<_> = users.keys().copied().collect();
+		assert_eq!(pids, [612, 901]);
+		assert_eq!(users[&612].len(), 1);
+		assert!(users[&612].contains(&PathBuf::from("/lib/libcrypto.so.30")));
+
+		assert!(super::parse_vmaps("", &want).is_empty());
+	}
+
+	#[test]
+	fn parse_comms()
+	{
+		let out = "\
+  PID  PPID  PGID   SID  TSID THR LOGIN    WCHAN     EMUL          COMM
+    1     0     1     1     0   1 root     wait      FreeBSD ELF64 init
+  612     1   612   612     0   1 root     select    FreeBSD ELF64 sshd
+";
+		let comms = super::parse_comms(out);
+		assert_eq!(comms.len(), 2);
+		assert_eq!(comms[&1], "init");
+		assert_eq!(comms[&612], "sshd");
+	}
+}
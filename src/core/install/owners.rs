@@ -0,0 +1,371 @@
+//! Recording intended ownership of installed files.
+//!
+//! When installing as non-root (e.g., building a system image in a dir
+//! you own), we can't chown/chflags anything, so everything winds up
+//! owned by you.  This keeps track of what the metadata _says_ the
+//! ownership/modes/flags should be, and writes it out in mtree(5)
+//! format, so it can be fed to `mtree -U` or bsdtar at image-packing
+//! time.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::{MetadataLine, SplitTypes};
+
+
+/// What sort of thing an entry is
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind
+{
+	Dir,
+	File,
+	Link(PathBuf),
+	/// Hardlink; ownership comes from the target
+	Hard(PathBuf),
+}
+
+/// One path's worth
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry
+{
+	kind: Kind,
+	uid: u32,
+	gid: u32,
+	mode: u32,
+	flags: u32,
+}
+
+
+/// The accumulated ownership info.
+#[derive(Debug, Default)]
+pub(crate) struct OwnerManifest
+{
+	entries: BTreeMap<PathBuf, Entry>,
+}
+
+
+impl OwnerManifest
+{
+	/// Load up what an earlier run wrote (e.g., the kernel step of an
+	/// upgrade), or start fresh if there isn't one.
+	pub(crate) fn load_or_new(file: &Path) -> Result<Self, anyhow::Error>
+	{
+		match std::fs::read_to_string(file) {
+			Ok(s) => Self::parse(&s),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound
+					=> Ok(Self::default()),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+
+	/// How many entries?
+	pub(crate) fn len(&self) -> usize { self.entries.len() }
+
+
+	/// Record everything in a SplitTypes about to be installed.
+	///
+	/// Any parent dirs not otherwise listed get recorded too, as
+	/// root-owned 0755, since the install will create them implicitly.
+	pub(crate) fn record(&mut self, smd: &SplitTypes)
+	{
+		use MetadataLine as ML;
+
+		let all = smd.dirs.iter().chain(smd.files.iter())
+				.chain(smd.syms.iter()).chain(smd.hards.iter());
+		for (p, mdl) in all
+		{
+			let ent = match mdl {
+				ML::Dir(m) => Entry { kind: Kind::Dir, uid: m.uid,
+						gid: m.gid, mode: m.mode, flags: m.flags },
+				ML::File(m) => Entry { kind: Kind::File, uid: m.uid,
+						gid: m.gid, mode: m.mode, flags: m.flags },
+				ML::SymLink(m) => Entry { kind: Kind::Link(m.target.clone()),
+						uid: m.uid, gid: m.gid, mode: m.mode, flags: m.flags },
+				ML::HardLink(m) => Entry { kind: Kind::Hard(m.target.clone()),
+						uid: 0, gid: 0, mode: 0, flags: 0 },
+				ML::Dash(_) => continue,
+			};
+			self.entries.insert(p.clone(), ent);
+		}
+
+		// Now the parents
+		let parents: Vec<PathBuf> = self.entries.keys()
+				.flat_map(|p| p.ancestors().skip(1))
+				.filter(|a| !a.as_os_str().is_empty())
+				.map(|a| a.to_path_buf()).collect();
+		for p in parents
+		{
+			self.entries.entry(p).or_insert(Entry { kind: Kind::Dir,
+					uid: 0, gid: 0, mode: 0o755, flags: 0 });
+		}
+	}
+
+
+	/// Render out as an mtree spec.
+	pub(crate) fn mtree(&self) -> String
+	{
+		let mut out = String::from("#mtree\n");
+		for (p, e) in &self.entries
+		{
+			// Hardlinks take their target's bits; if we don't know the
+			// target, there's nothing to say about ownership.
+			let (e, hard) = match &e.kind {
+				Kind::Hard(t) => match self.entries.get(t) {
+					Some(te) => (te, true),
+					None => {
+						out.push_str(&format!("{} type=file\n", mt_path(p)));
+						continue;
+					},
+				},
+				_ => (e, false),
+			};
+
+			let mut line = mt_path(p);
+			let ty = match (&e.kind, hard) {
+				(_, true)      => "file",
+				(Kind::Dir, _) => "dir",
+				(Kind::Link(_), _) => "link",
+				_ => "file",
+			};
+			line.push_str(&format!(" type={ty} uid={} gid={} mode={:04o}",
+					e.uid, e.gid, e.mode));
+			if let (Kind::Link(t), false) = (&e.kind, hard)
+			{ line.push_str(&format!(" link={}", mt_escape(t))); }
+			if e.flags != 0
+			{ line.push_str(&format!(" flags={}", flags_str(e.flags))); }
+			line.push('\n');
+			out.push_str(&line);
+		}
+		out
+	}
+
+
+	/// Write the mtree spec out to a file.
+	pub(crate) fn write(&self, file: &Path) -> Result<(), std::io::Error>
+	{
+		std::fs::write(file, self.mtree())
+	}
+
+
+	/// Parse back in an mtree spec that we wrote.  This isn't a general
+	/// mtree parser; it only understands what we write.
+	fn parse(s: &str) -> Result<Self, anyhow::Error>
+	{
+		let mut ret = Self::default();
+		for (n, l) in s.lines().enumerate()
+		{
+			let l = l.trim();
+			if l.is_empty() || l.starts_with('#') { continue; }
+
+			let mut flds = l.split_whitespace();
+			let path = flds.next().unwrap();
+			let path = match mt_unescape(path).strip_prefix('.') {
+				Some("") => PathBuf::from("/"),
+				Some(p) => PathBuf::from(p),
+				None => anyhow::bail!("line {}: bad path {path}", n + 1),
+			};
+
+			let mut ent = Entry { kind: Kind::File, uid: 0, gid: 0, mode: 0,
+					flags: 0 };
+			for kv in flds
+			{
+				let (k, v) = match kv.split_once('=') {
+					Some(kv) => kv,
+					None => anyhow::bail!("line {}: bad keyword {kv}", n + 1),
+				};
+				let num = |v: &str, radix| u32::from_str_radix(v, radix)
+						.map_err(|e| anyhow::anyhow!("line {}: bad {k}: {e}",
+								n + 1));
+				match k
+				{
+					"type" => ent.kind = match v {
+						"dir"  => Kind::Dir,
+						"link" => Kind::Link(PathBuf::new()),
+						_      => Kind::File,
+					},
+					"uid"   => ent.uid = num(v, 10)?,
+					"gid"   => ent.gid = num(v, 10)?,
+					"mode"  => ent.mode = num(v, 8)?,
+					"flags" => ent.flags = flags_parse(v),
+					"link"  => ent.kind = Kind::Link(mt_unescape(v).into()),
+					_ => (),
+				}
+			}
+			ret.entries.insert(path, ent);
+		}
+		Ok(ret)
+	}
+}
+
+
+
+/// The mtree-ish name for a path: relative to the root, with "." for the
+/// root itself.
+fn mt_path(p: &Path) -> String
+{
+	let e = mt_escape(p);
+	match e.as_str() {
+		"/" => ".".to_string(),
+		_ => format!(".{e}"),
+	}
+}
+
+/// Escape the way mtree(8) does (strsvis(3) with VIS_OCTAL and friends):
+/// anything unprintable, whitespace, or special to mtree or globbing
+/// goes as \ooo.
+fn mt_escape(p: &Path) -> String
+{
+	let mut ret = String::new();
+	for b in p.as_os_str().as_encoded_bytes()
+	{
+		match b
+		{
+			b'\\' | b'#' | b'*' | b'?' | b'[' | b']' | b'=' =>
+					ret.push_str(&format!("\\{b:03o}")),
+			0x21..=0x7e => ret.push(*b as char),
+			_ => ret.push_str(&format!("\\{b:03o}")),
+		}
+	}
+	ret
+}
+
+/// Undo mt_escape()
+fn mt_unescape(s: &str) -> String
+{
+	let b = s.as_bytes();
+	let mut out = Vec::with_capacity(b.len());
+	let mut i = 0;
+	while i < b.len()
+	{
+		if b[i] == b'\\' && i + 3 < b.len()
+		{
+			let oct = std::str::from_utf8(&b[i + 1..i + 4]).ok()
+					.and_then(|o| u8::from_str_radix(o, 8).ok());
+			if let Some(c) = oct
+			{
+				out.push(c);
+				i += 4;
+				continue;
+			}
+		}
+		out.push(b[i]);
+		i += 1;
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+
+/// File flags, by their chflags(1) names.  Anything else isn't expected
+/// to be in metadata, and gets dropped.
+const FLAG_NAMES: &[(u32, &str)] = &[
+	(0x00000001, "nodump"),
+	(0x00000002, "uchg"),
+	(0x00000004, "uappnd"),
+	(0x00000008, "opaque"),
+	(0x00000010, "uunlnk"),
+	(0x00010000, "arch"),
+	(0x00020000, "schg"),
+	(0x00040000, "sappnd"),
+	(0x00100000, "sunlnk"),
+];
+
+/// Flags as names, the way chflags(1) (and mtree(5)) have them
+pub(crate) fn flags_str(flags: u32) -> String
+{
+	let names: Vec<_> = FLAG_NAMES.iter().filter(|(f, _)| flags & f != 0)
+			.map(|(_, n)| *n).collect();
+	match names.is_empty() {
+		true  => "none".to_string(),
+		false => names.join(","),
+	}
+}
+
+/// Flags from a list of names like chflags(1) takes (and mtree(5) and
+/// tar write); unknown names are ignored.
+pub(crate) fn flags_parse(s: &str) -> u32
+{
+	s.split(',').filter_map(|n| FLAG_NAMES.iter().find(|(_, fnm)| *fnm == n))
+			.fold(0, |acc, (f, _)| acc | f)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::OwnerManifest;
+	use crate::metadata::SplitTypes;
+
+	fn mk_split() -> SplitTypes
+	{
+		use crate::metadata::{Metadata, MetaFile, MetaHardLink, MetaDir,
+				MetaSymLink};
+		use std::path::PathBuf;
+
+		let f = |p: &str, gid, mode| (PathBuf::from(p), MetaFile {
+				path: p.into(), gid, mode, ..Default::default() });
+		let md = Metadata {
+			files: [f("/bin/sh", 0, 0o555), f("/bin/[", 0, 0o555),
+					f("/usr/share/misc/odd name", 5, 0o440)].into(),
+			hardlinks: [("/bin/test".into(), MetaHardLink {
+					path: "/bin/test".into(), target: "/bin/[".into() })].into(),
+			dirs: [("/var/empty".into(), MetaDir { path: "/var/empty".into(),
+					mode: 0o555, flags: 0x20000, ..Default::default() })].into(),
+			symlinks: [("/usr/sbin/sendmail".into(), MetaSymLink {
+					path: "/usr/sbin/sendmail".into(),
+					target: "/usr/sbin/mailwrapper".into(), mode: 0o755,
+					..Default::default() })].into(),
+			..Default::default()
+		};
+		md.into()
+	}
+
+	const EXPECT: &str = "\
+#mtree
+. type=dir uid=0 gid=0 mode=0755
+./bin type=dir uid=0 gid=0 mode=0755
+./bin/\\133 type=file uid=0 gid=0 mode=0555
+./bin/sh type=file uid=0 gid=0 mode=0555
+./bin/test type=file uid=0 gid=0 mode=0555
+./usr type=dir uid=0 gid=0 mode=0755
+./usr/sbin type=dir uid=0 gid=0 mode=0755
+./usr/sbin/sendmail type=link uid=0 gid=0 mode=0755 link=/usr/sbin/mailwrapper
+./usr/share type=dir uid=0 gid=0 mode=0755
+./usr/share/misc type=dir uid=0 gid=0 mode=0755
+./usr/share/misc/odd\\040name type=file uid=0 gid=5 mode=0440
+./var type=dir uid=0 gid=0 mode=0755
+./var/empty type=dir uid=0 gid=0 mode=0555 flags=schg
+";
+
+	#[test]
+	fn mtree()
+	{
+		let mut om = OwnerManifest::default();
+		om.record(&mk_split());
+		assert_eq!(om.mtree(), EXPECT);
+	}
+
+	#[test]
+	fn roundtrip()
+	{
+		let mut om = OwnerManifest::default();
+		om.record(&mk_split());
+		let om2 = OwnerManifest::parse(&om.mtree()).unwrap();
+		assert_eq!(om2.mtree(), EXPECT, "reparsed writes the same");
+	}
+
+	#[test]
+	fn escape()
+	{
+		use std::path::Path;
+		let p = Path::new("/a b/c#d\\e\tf");
+		let e = super::mt_escape(p);
+		assert_eq!(e, "/a\\040b/c\\043d\\134e\\011f");
+		assert_eq!(super::mt_unescape(&e), p.to_str().unwrap());
+
+		// Escapes right at the end, whole or cut short
+		assert_eq!(super::mt_unescape("/a\\040"), "/a ");
+		assert_eq!(super::mt_unescape("/a\\04"), "/a\\04");
+		assert_eq!(super::mt_unescape("/a\\"), "/a\\");
+	}
+}
@@ -2,149 +2,439 @@
 //!
 //! This is mostly running utils to post-process stuff for some kinda
 //! installed bit.
+//!
+//! When the basedir isn't /, the host's copies of those utils might not
+//! exist, or might not be the right ones to run.  So each one gets
+//! decided on individually: chroot in and run the basedir's own, run the
+//! host's pointed at the basedir, or give up and tell the user what to
+//! run later.  If the chroot'd one can't even be started, the host's gets
+//! a try before giving up.
 
-
-// XXX These are _very_ similar and we can probably factor out a lot, but
-// there's juuust enough small diffs it'd take some doing to make it
-// clean, so for the moment, I'm just letting it all repeat.
-
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::io::{stdout, Write as _};
-use std::path::Path;
+use crate::config::HelperMode;
+use crate::util::out::{self, say, sayln};
 
 
-/// Do kldxref's for the kernel
-pub(crate) fn kldxref(basedir: &Path) -> Result<(), anyhow::Error>
+/// The things we run after installing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Helper
+{
+	Kldxref,
+	Certctl,
+	PwdMkdb,
+	LoginConf,
+	Termcap,
+	ServicesMkdb,
+	Makewhatis,
+}
+
+impl Helper
 {
-	// f-u.sh also does some conditionalization on this, but heck with
-	// it, I'm just gonna do it.
-	print!("Running kldxref...  ");
-	stdout().flush()?;
+	/// The util it runs, as a path in whatever root it runs in.
+	fn tool(&self) -> &'static str
+	{
+		match self {
+			Self::Kldxref      => "/usr/sbin/kldxref",
+			Self::Certctl      => "/usr/sbin/certctl",
+			Self::PwdMkdb      => "/usr/sbin/pwd_mkdb",
+			Self::LoginConf    => "/usr/bin/cap_mkdb",
+			Self::Termcap      => "/usr/bin/cap_mkdb",
+			Self::ServicesMkdb => "/usr/sbin/services_mkdb",
+			Self::Makewhatis   => "/usr/bin/makewhatis",
+		}
+	}
 
-	const CMD: &str = "/usr/sbin/kldxref";
-	let cret = Command::new(CMD)
-			.args([
-				"-R".as_ref(), basedir.join("boot").as_os_str(),
-			]).status()?;
-	match cret.success() {
-		true  => println!("Done."),
-		false => println!("failed\n{cret:?}\n"),
+	/// What to say we're doing
+	fn desc(&self) -> &'static str
+	{
+		match self {
+			Self::Kldxref      => "Running kldxref",
+			Self::Certctl      => "Rehashing certs",
+			Self::PwdMkdb      => "Rebuilding passwd db",
+			Self::LoginConf    => "Rebuilding login.conf db",
+			Self::Termcap      => "Rebuilding termcap db",
+			Self::ServicesMkdb => "Rebuilding services db",
+			Self::Makewhatis   => "Rebuilding manpage indices",
+		}
 	}
 
-	Ok(())
+	/// The invocations to do it, for a system at root (which is / if
+	/// we're chroot'd in).  basedir is where it really is, for deciding
+	/// what needs doing.
+	fn invocations(&self, basedir: &Path, root: &Path) -> Vec<Invocation>
+	{
+		let rp = |p: &str| root.join(p).into_os_string();
+		let inv = |args: Vec<OsString>| {
+			let mut argv = vec![self.tool().into()];
+			argv.extend(args);
+			Invocation { env: Vec::new(), argv }
+		};
+		match self {
+			// f-u.sh also does some conditionalization on this, but
+			// heck with it, I'm just gonna do it.
+			Self::Kldxref => vec![inv(vec!["-R".into(), rp("boot")])],
+
+			// certctl(1) has been around since 12.2; f-u.sh tries to
+			// support systems before that.  I don't.
+			Self::Certctl => {
+				let mut i = inv(vec!["rehash".into()]);
+				if root != Path::new("/")
+				{ i.env.push(("DESTDIR", root.to_path_buf())); }
+				vec![i]
+			},
+
+			Self::PwdMkdb => vec![inv(vec!["-d".into(), rp("etc"),
+					"-p".into(), rp("etc/master.passwd")])],
+			Self::LoginConf => vec![inv(vec![rp("etc/login.conf")])],
+			Self::Termcap => vec![inv(vec![rp("usr/share/misc/termcap")])],
+			Self::ServicesMkdb => vec![inv(vec!["-q".into(),
+					"-o".into(), rp("var/db/services.db"),
+					rp("etc/services")])],
+
+			// f-u.sh does some jiggery to see if we need to rebuid
+			// anything.  I'm not going to bother; it's fast enough to
+			// just let it run.
+			Self::Makewhatis => ["usr/share/man", "usr/share/openssl/man"]
+					.iter()
+					.filter(|d| basedir.join(d).join("mandoc.db").is_file())
+					.map(|d| inv(vec![rp(d)]))
+					.collect(),
+		}
+	}
+
+	/// The invocations, either chroot'd into basedir or the host's aimed
+	/// at it.
+	fn invocations_in(&self, basedir: &Path, chroot: bool) -> Vec<Invocation>
+	{
+		match chroot {
+			false => self.invocations(basedir, basedir),
+			true  => self.invocations(basedir, Path::new("/")).into_iter()
+					.map(|i| i.chrooted(basedir)).collect(),
+		}
+	}
 }
 
 
-/// Check if sshd is running, and maybe try restarting it.
-pub(crate) fn try_sshd_restart() -> Result<(), anyhow::Error>
+/// One command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Invocation
 {
-	// We don't even try if you're not root
-	if crate::util::euid() != 0 { return Ok(()) }
+	env: Vec<(&'static str, PathBuf)>,
+	argv: Vec<OsString>,
+}
 
-	// See if we can see it running
-	const SVC: &str = "/usr/sbin/service";
-	let cret = Command::new(SVC).args(["sshd", "status"]).status()?;
-	if !cret .success() { return Ok(()) }
+impl Invocation
+{
+	/// Put it in a chroot
+	fn chrooted(self, basedir: &Path) -> Self
+	{
+		let mut argv = vec!["/usr/sbin/chroot".into(),
+				basedir.as_os_str().to_owned()];
+		argv.extend(self.argv);
+		Self { env: self.env, argv }
+	}
 
-	// It is, kick it.
-	println!("Restarting sshd after upgrade.");
+	/// Run it
+	fn run(&self) -> Result<std::process::ExitStatus, std::io::Error>
+	{
+		Command::new(&self.argv[0]).args(&self.argv[1..])
+				.envs(self.env.iter().map(|(k, v)| (*k, v))).status()
+	}
 
-	// If it fails, warn very loudly
-	let cret = Command::new(SVC).args(["sshd", "restart"]).status()?;
-	match cret.success() {
-		true  => println!("  Done."),
-		false => eprintln!("\nWARNING WARNING WARNING: restart sshd failed\n\
-				{cret:?}\n"),
+	/// What to tell the user to run
+	fn display(&self) -> String
+	{
+		let env = self.env.iter()
+				.map(|(k, v)| format!("{k}={}", v.display()));
+		let argv = self.argv.iter()
+				.map(|a| a.to_string_lossy().into_owned());
+		env.chain(argv).collect::<Vec<_>>().join(" ")
 	}
+}
 
-	// And move on
-	Ok(())
+
+/// How a helper ends up getting run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum How
+{
+	/// The host's copy, pointed at the basedir
+	Host,
+
+	/// The basedir's own copy, chroot'd in
+	Chroot,
+
+	/// Not at all; the user gets told what to run
+	Skip,
 }
 
 
-/// Rehash certs
-pub(crate) fn rehash_certs(basedir: &Path) -> Result<(), anyhow::Error>
+/// What we know about the situation a helper would run in.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Situation
 {
-	// certctl isn't quiet
-	println!("Rehashing certs...  ");
-	//stdout().flush()?;
+	/// basedir is /, so the host is the target
+	pub(crate) atroot: bool,
 
-	const CMD: &str = "/usr/sbin/certctl";
-	let cret = Command::new(CMD).arg("rehash")
-			.env("DESTDIR", basedir).status()?;
-	match cret.success() {
-		true  => println!("  Done."),
-		false => println!("failed\n{cret:?}\n"),
-	}
+	/// We can chroot: we're root, the basedir looks like a whole system,
+	/// and it has its own copy of the tool.
+	pub(crate) chroot_ok: bool,
 
-	Ok(())
+	/// The host has the tool
+	pub(crate) host_ok: bool,
+}
+
+impl Situation
+{
+	/// Look around for a helper
+	fn probe(h: Helper, basedir: &Path) -> Self
+	{
+		let atroot = basedir == Path::new("/");
+		let tool = h.tool().trim_start_matches('/');
+		let complete = ["libexec/ld-elf.so.1", "bin/sh"].iter()
+				.all(|p| basedir.join(p).is_file());
+		let chroot_ok = crate::util::euid() == 0 && complete
+				&& basedir.join(tool).is_file();
+		let host_ok = Path::new(h.tool()).is_file();
+		Self { atroot, chroot_ok, host_ok }
+	}
 }
 
 
-/// Rebuild passwd db
-pub(crate) fn pwd_mkdb(basedir: &Path) -> Result<(), anyhow::Error>
+/// Decide how to run a helper.  This is the whole policy; everything
+/// else is just doing what it says.
+pub(crate) fn decide(mode: HelperMode, s: &Situation) -> How
 {
-	print!("Rebuilding passwd db...  ");
-	stdout().flush()?;
+	use HelperMode as M;
+	match (mode, s.atroot, s.chroot_ok, s.host_ok) {
+		// Told not to
+		(M::Skip, _, _, _) => How::Skip,
+
+		// Working on the running system; host's copy is the system's
+		// copy.
+		(_, true, _, true)  => How::Host,
+		(_, true, _, false) => How::Skip,
+
+		// Prefer the basedir's own when we can get into it
+		(M::Auto | M::Chroot, false, true, _) => How::Chroot,
 
-	const CMD: &str = "/usr/sbin/pwd_mkdb";
-	let cret = Command::new(CMD)
-			.args([
-				"-d".as_ref(), basedir.join("etc").as_os_str(),
-				"-p".as_ref(), basedir.join("etc/master.passwd").as_os_str(),
-			]).status()?;
-	match cret.success() {
-		true  => println!("Done."),
-		false => println!("failed\n{cret:?}\n"),
+		// Else the host's, aimed at the basedir
+		(M::Auto | M::Host, false, _, true) => How::Host,
+
+		// Nothing that'll work
+		_ => How::Skip,
+	}
+}
+
+
+/// If running it `how` fell over, is there another way to try?  The
+/// basedir's copy may not run under our kernel, say, but if we were
+/// left to pick, the host's may still do.
+fn fallback(mode: HelperMode, how: How, s: &Situation) -> How
+{
+	match (mode, how, s.host_ok) {
+		(HelperMode::Auto, How::Chroot, true) => How::Host,
+		_ => How::Skip,
 	}
+}
+
 
+/// Run some invocations, complaining about any that fail, but only
+/// giving up if one can't be run at all.
+fn attempt(invs: &[Invocation]) -> Result<(), std::io::Error>
+{
+	for i in invs
+	{
+		let cret = i.run()?;
+		if !cret.success()
+		{ sayln!("failed running {}\n{cret:?}\n", i.display()); }
+	}
 	Ok(())
 }
 
 
-/// Rebuild login.conf db
-pub(crate) fn cap_mkdb(basedir: &Path) -> Result<(), anyhow::Error>
+/// Tell the user what to run later, and note it in deferred.
+fn defer(h: Helper, why: &str, invs: Vec<Invocation>,
+		deferred: &mut Vec<String>)
 {
-	print!("Rebuilding login.conf db...  ");
-	stdout().flush()?;
+	sayln!("{}: {why}, run later:", h.desc());
+	for i in invs
+	{
+		let cmd = i.display();
+		sayln!("  {cmd}");
+		deferred.push(cmd);
+	}
+}
+
+
+/// Run a helper against basedir, however mode and the situation allow.
+/// Anything we couldn't run gets added to deferred, as the command lines
+/// the user should run themselves.
+pub(crate) fn run(h: Helper, basedir: &Path, mode: HelperMode,
+		deferred: &mut Vec<String>) -> Result<(), anyhow::Error>
+{
+	let sit = Situation::probe(h, basedir);
+	let how = decide(mode, &sit);
+
+	let invs = match how {
+		How::Host   => h.invocations_in(basedir, false),
+		How::Chroot => h.invocations_in(basedir, true),
+		How::Skip   => {
+			// Suggest the chroot if there's no host copy to point at it
+			let chroot = !sit.atroot && !sit.host_ok;
+			defer(h, "skipped", h.invocations_in(basedir, chroot), deferred);
+			return Ok(());
+		},
+	};
 
-	const CMD: &str = "/usr/bin/cap_mkdb";
-	let cret = Command::new(CMD)
-			.args([
-				basedir.join("etc/login.conf"),
-			]).status()?;
-	match cret.success() {
-		true  => println!("Done."),
-		false => println!("failed\n{cret:?}\n"),
+	// certctl isn't quiet
+	match h {
+		Helper::Certctl => sayln!("{}...  ", h.desc()),
+		_ => { say!("{}...  ", h.desc()); out::flush(); },
+	}
+	let err = match attempt(&invs) {
+		Ok(()) => { sayln!("Done."); return Ok(()); },
+		Err(e) => e,
+	};
+	sayln!("couldn't run it: {err}");
+
+	// Maybe the host's copy will do instead
+	if fallback(mode, how, &sit) == How::Host
+	{
+		say!("{} with the host's {}...  ", h.desc(), h.tool());
+		out::flush();
+		match attempt(&h.invocations_in(basedir, false)) {
+			Ok(()) => { sayln!("Done."); return Ok(()); },
+			Err(e) => sayln!("couldn't run that either: {e}"),
+		}
 	}
+	defer(h, "deferred", invs, deferred);
 
 	Ok(())
 }
 
 
-/// Rebuild man indices.
-///
-/// f-u.sh does some jiggery to see if we need to rebuid anything.  I'm
-/// not going to bother; it's fast enough to just let it run.
-pub(crate) fn makewhatis(basedir: &Path) -> Result<(), anyhow::Error>
+/// Regenerate a derived db
+pub(crate) fn regenerate(r: crate::core::derived::Regen, basedir: &Path,
+		mode: HelperMode, deferred: &mut Vec<String>)
+		-> Result<(), anyhow::Error>
 {
-	print!("Rebuilding manpage indices...  ");
-	stdout().flush()?;
+	use crate::core::derived::Regen as R;
+	let h = match r {
+		R::PwdMkdb      => Helper::PwdMkdb,
+		R::LoginConf    => Helper::LoginConf,
+		R::Termcap      => Helper::Termcap,
+		R::ServicesMkdb => Helper::ServicesMkdb,
+	};
+	run(h, basedir, mode, deferred)
+}
+
+
 
-	const CMD: &str = "/usr/bin/makewhatis";
+#[cfg(test)]
+mod tests
+{
+	use std::path::Path;
+	use crate::config::HelperMode as M;
+	use super::{decide, fallback, attempt, Helper, How, Situation};
+	use super::Invocation;
 
-	for mdstr in ["usr/share/man", "usr/share/openssl/man"]
+	#[test]
+	fn decide_table()
 	{
-		let mdir = basedir.join(mdstr);
-		if !mdir.join("mandoc.db").is_file() { continue; }
+		let sit = |atroot, chroot_ok, host_ok| Situation { atroot,
+				chroot_ok, host_ok };
 
-		let cret = Command::new(CMD).arg(mdir).status()?;
-		match cret.success() {
-			true  => { print!("/{mdstr} "); stdout().flush()?; },
-			false => { println!("failed on /{mdstr}:\n{cret:?}\n"); },
-		}
+		// (mode, situation, expected)
+		let table = [
+			// The running system; same as it ever was
+			(M::Auto,   sit(true, false, true),   How::Host),
+			(M::Chroot, sit(true, false, true),   How::Host),
+			(M::Auto,   sit(true, false, false),  How::Skip),
+
+			// A complete basedir we can chroot into wins under auto
+			(M::Auto,   sit(false, true, true),   How::Chroot),
+			(M::Auto,   sit(false, true, false),  How::Chroot),
+			(M::Auto,   sit(false, false, true),  How::Host),
+			(M::Auto,   sit(false, false, false), How::Skip),
+
+			// Forced one way or the other
+			(M::Chroot, sit(false, true, true),   How::Chroot),
+			(M::Chroot, sit(false, false, true),  How::Skip),
+			(M::Host,   sit(false, true, true),   How::Host),
+			(M::Host,   sit(false, true, false),  How::Skip),
+
+			// Or not at all
+			(M::Skip,   sit(true, true, true),    How::Skip),
+			(M::Skip,   sit(false, true, true),   How::Skip),
+		];
+		for (mode, s, want) in table
+		{ assert_eq!(decide(mode, &s), want, "{mode:?} {s:?}"); }
 	}
 
-	println!("Done.");
-	Ok(())
+	#[test]
+	fn fallback_table()
+	{
+		let sit = |host_ok| Situation { atroot: false, chroot_ok: true,
+				host_ok };
+
+		// Only a chroot we picked ourselves falls back, and only to a
+		// host copy that's there.
+		assert_eq!(fallback(M::Auto, How::Chroot, &sit(true)), How::Host);
+		assert_eq!(fallback(M::Auto, How::Chroot, &sit(false)), How::Skip);
+		assert_eq!(fallback(M::Chroot, How::Chroot, &sit(true)), How::Skip);
+		assert_eq!(fallback(M::Auto, How::Host, &sit(true)), How::Skip);
+		assert_eq!(fallback(M::Host, How::Host, &sit(true)), How::Skip);
+	}
+
+	#[test]
+	fn attempt_exec()
+	{
+		let inv = |argv: &[&str]| Invocation { env: Vec::new(),
+				argv: argv.iter().map(|a| a.into()).collect() };
+
+		// Exiting unhappy is just complained about; not being able to
+		// run it at all is what gets handed back.
+		assert!(attempt(&[inv(&["/bin/sh", "-c", "exit 3"])]).is_ok());
+		let td = tempfile::tempdir().unwrap();
+		let nope = td.path().join("nope");
+		let nope = nope.to_str().unwrap();
+		assert!(attempt(&[inv(&["/bin/sh", "-c", "true"]), inv(&[nope])])
+				.is_err());
+	}
+
+	#[test]
+	fn invocations()
+	{
+		let base = Path::new("/img");
+
+		// Host runs aim the tool at the basedir
+		let i = &Helper::PwdMkdb.invocations(base, base)[0];
+		assert_eq!(i.display(), "/usr/sbin/pwd_mkdb -d /img/etc \
+				-p /img/etc/master.passwd");
+		let i = &Helper::Certctl.invocations(base, base)[0];
+		assert_eq!(i.display(), "DESTDIR=/img /usr/sbin/certctl rehash");
+
+		// Chroot'd ones work on /, from inside
+		let i = Helper::Kldxref.invocations(base, Path::new("/"))
+				.remove(0).chrooted(base);
+		assert_eq!(i.display(), "/usr/sbin/chroot /img /usr/sbin/kldxref \
+				-R /boot");
+		let i = Helper::Certctl.invocations(base, Path::new("/"))
+				.remove(0).chrooted(base);
+		assert_eq!(i.display(), "/usr/sbin/chroot /img /usr/sbin/certctl \
+				rehash");
+
+		// makewhatis only for indices that exist
+		let td = tempfile::tempdir().unwrap();
+		let md = td.path().join("usr/share/man");
+		std::fs::create_dir_all(&md).unwrap();
+		assert!(Helper::Makewhatis.invocations(td.path(), td.path())
+				.is_empty());
+		std::fs::write(md.join("mandoc.db"), b"").unwrap();
+		let invs = Helper::Makewhatis.invocations(td.path(), Path::new("/"));
+		assert_eq!(invs.len(), 1);
+		assert_eq!(invs[0].display(), "/usr/bin/makewhatis /usr/share/man");
+	}
 }
@@ -0,0 +1,164 @@
+//! Rebooting between install steps, and resuming after.
+//!
+//! For cross-version upgrades, the kernel gets installed, then we need a
+//! reboot before installing world.  `install --reboot` lets us do that
+//! reboot ourselves, and the resume rc.d script lets the next boot pick
+//! up the world step without somebody having to log in and do it.
+
+use std::process::Command;
+use crate::util::out::{self, say, sayln};
+use std::path::Path;
+
+
+/// Where we install the resume rc.d script (relative to the basedir).
+pub(crate) const RESUME_SCRIPT: &str =
+		"/usr/local/etc/rc.d/freebsd_rustdate_resume";
+
+
+/// Sync and schedule a reboot.
+///
+/// We give shutdown(8) a minute of grace, so anybody logged in gets
+/// warned, and the user has a chance to kill it off if they change their
+/// mind.
+pub(crate) fn reboot() -> Result<(), anyhow::Error>
+{
+	// Get everything we wrote out to disk before anything else happens.
+	say!("Syncing filesystems...  ");
+	out::flush();
+	unsafe { libc::sync(); }
+	sayln!("Done.");
+
+	sayln!("\n\
+		*** Rebooting in 1 minute to finish the kernel install. ***\n\
+		    (kill the shutdown(8) process to cancel)\n");
+
+	const CMD: &str = "/sbin/shutdown";
+	let cret = Command::new(CMD)
+			.args(["-r", "+1", "freebsd-rustdate kernel install"])
+			.status()?;
+	if !cret.success()
+	{
+		anyhow::bail!("Scheduling reboot failed: {cret:?}\n\
+				Please reboot manually.");
+	}
+
+	Ok(())
+}
+
+
+/// Build up the rc.d script used to resume an install on the next boot.
+///
+/// It's a one-shot; it only does anything if the marker file exists, and
+/// removes the marker before running, so a failed install won't loop us
+/// through reboots.
+pub(crate) fn resume_script(cmd: &Path, args: &[String], marker: &Path)
+		-> String
+{
+	let cmd = sh_quote(&cmd.to_string_lossy());
+	let marker = sh_quote(&marker.to_string_lossy());
+	let args = args.iter().map(|a| sh_quote(a))
+			.collect::<Vec<_>>().join(" ");
+
+	format!(r##"#!/bin/sh
+#
+# Generated by freebsd-rustdate `install --enable-resume`.
+#
+# Resume a pending freebsd-rustdate install after a reboot requested by
+# `install --reboot`.  Does nothing unless that left a marker behind.
+
+# PROVIDE: freebsd_rustdate_resume
+# REQUIRE: LOGIN
+# KEYWORD: nojail
+
+. /etc/rc.subr
+
+name="freebsd_rustdate_resume"
+rcvar="freebsd_rustdate_resume_enable"
+start_cmd="${{name}}_start"
+stop_cmd=":"
+
+load_rc_config $name
+: ${{freebsd_rustdate_resume_enable:="YES"}}
+
+marker={marker}
+
+freebsd_rustdate_resume_start()
+{{
+	[ -f "${{marker}}" ] || return 0
+	rm -f "${{marker}}"
+	echo "Resuming freebsd-rustdate install."
+	{cmd} {args} install
+}}
+
+run_rc_command "$1"
+"##)
+}
+
+
+/// Single-quote something for sh, so nothing in it means anything.  A '
+/// inside has to come out of the quotes to get escaped.
+fn sh_quote(s: &str) -> String
+{
+	format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+
+/// Write the resume script into place.
+pub(crate) fn write_resume(basedir: &Path, script: &str)
+		-> Result<(), anyhow::Error>
+{
+	use std::fs;
+	use std::os::unix::fs::PermissionsExt as _;
+
+	let dst = crate::util::path_join(basedir, RESUME_SCRIPT);
+	if let Some(p) = dst.parent() { fs::create_dir_all(p)?; }
+	fs::write(&dst, script)?;
+	fs::set_permissions(&dst, fs::Permissions::from_mode(0o555))?;
+	Ok(())
+}
+
+
+/// Is the resume script installed?
+pub(crate) fn resume_enabled(basedir: &Path) -> bool
+{
+	crate::util::path_join(basedir, RESUME_SCRIPT).is_file()
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::Path;
+
+	#[test]
+	fn sh_quote()
+	{
+		use super::sh_quote;
+		assert_eq!(sh_quote("plain"), "'plain'");
+		assert_eq!(sh_quote(""), "''");
+		assert_eq!(sh_quote("it's"), r"'it'\''s'");
+		assert_eq!(sh_quote("$(rm -rf /) `x` \"x\""),
+				r#"'$(rm -rf /) `x` "x"'"#);
+	}
+
+	#[test]
+	fn resume_script()
+	{
+		let args = ["-b".to_string(), "/jails/bob's".to_string()];
+		let s = super::resume_script(Path::new("/usr/local/sbin/frd"), &args,
+				Path::new("/var/db/it's here"));
+		assert!(s.contains("\nmarker='/var/db/it'\\''s here'\n"), "{s}");
+		assert!(s.contains("\t'/usr/local/sbin/frd' '-b' \
+				'/jails/bob'\\''s' install\n"), "{s}");
+
+		// And sh agrees about what that means
+		let line = s.lines().find(|l| l.ends_with(" install")).unwrap();
+		let echo = line.trim().replace(" install", "")
+				.replacen("'/usr/local/sbin/frd'", "printf '%s|'", 1);
+		let out = std::process::Command::new("/bin/sh").args(["-c", &echo])
+				.output().unwrap();
+		assert_eq!(String::from_utf8(out.stdout).unwrap(),
+				"-b|/jails/bob's|");
+	}
+}
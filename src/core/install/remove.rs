@@ -0,0 +1,156 @@
+//! Removing a batch of paths
+//!
+//! Upstream removing a dir doesn't mean there's nothing of yours in it;
+//! if there's still local stuff anywhere under it, we leave it be
+//! rather than failing to rmdir it and complaining.
+use std::collections::HashSet;
+use std::fs;
+use std::io::Error as IOErr;
+use std::path::{Path, PathBuf};
+
+
+/// How removing went.
+#[derive(Debug, Default)]
+pub(crate) struct Removed
+{
+	/// Dirs we tried to remove, but couldn't
+	pub(crate) failed: Vec<PathBuf>,
+
+	/// Dirs we didn't try to remove, because they (somewhere down) still
+	/// have things in them that aren't ours to remove.
+	pub(crate) retained: Vec<PathBuf>,
+}
+
+
+/// Remove a set of paths under basedir.
+///
+/// This goes reverse-depth-first, so a dir's contents are gone by the
+/// time we get to the dir itself.
+pub(crate) fn remove(basedir: &Path, rms: &[impl AsRef<Path>])
+		-> Result<Removed, IOErr>
+{
+	let rmset: HashSet<&Path> = rms.iter().map(|p| p.as_ref()).collect();
+	let mut ret = Removed::default();
+
+	use itertools::Itertools as _;
+	for p in rmset.iter().sorted_unstable().rev()
+	{
+		let rmp = crate::util::path_join(basedir, p);
+
+		let isdir = rmp.symlink_metadata().map(|m| m.is_dir())
+				.unwrap_or(false);
+		// If we can't tell, just go ahead and let the rmdir fail.
+		if isdir && has_local(&rmp, p, &rmset).unwrap_or(false)
+		{
+			ret.retained.push(p.to_path_buf());
+			continue;
+		}
+
+		if super::rm(&rmp)? { ret.failed.push(p.to_path_buf()); }
+	}
+
+	Ok(ret)
+}
+
+
+/// Does a dir have anything in it (recursively) that isn't in the set
+/// we're removing?  `rel` is dir's path as rmset knows it.
+fn has_local(dir: &Path, rel: &Path, rmset: &HashSet<&Path>)
+		-> Result<bool, IOErr>
+{
+	for ent in fs::read_dir(dir)?
+	{
+		let ent = ent?;
+		let erel = rel.join(ent.file_name());
+		if !rmset.contains(erel.as_path()) { return Ok(true); }
+
+		// Something we meant to remove, but it's still here; presumably
+		// a dir we kept, so see what's in it.
+		if ent.file_type()?.is_dir() && has_local(&ent.path(), &erel, rmset)?
+		{ return Ok(true); }
+	}
+	Ok(false)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::fs;
+	use std::path::{Path, PathBuf};
+
+	/// Make up a tree of dirs (trailing /) and files under basedir
+	fn mktree(base: &Path, paths: &[&str])
+	{
+		for p in paths
+		{
+			let full = base.join(p.trim_start_matches('/'));
+			match p.ends_with('/') {
+				true  => fs::create_dir_all(&full).unwrap(),
+				false => fs::write(&full, "x").unwrap(),
+			}
+		}
+	}
+
+	fn exists(base: &Path, p: &str) -> bool
+	{ base.join(p.trim_start_matches('/')).symlink_metadata().is_ok() }
+
+	fn pb(ps: &[&str]) -> Vec<PathBuf> { ps.iter().map(PathBuf::from).collect() }
+
+	#[test]
+	fn all_ours()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let bd = td.path();
+		mktree(bd, &["/a/", "/a/b/", "/a/b/f1", "/a/f2"]);
+
+		let rms = ["/a", "/a/b", "/a/b/f1", "/a/f2"];
+		let got = super::remove(bd, &rms).unwrap();
+		assert!(got.failed.is_empty(), "{got:?}");
+		assert!(got.retained.is_empty(), "{got:?}");
+		assert!(!exists(bd, "/a"));
+	}
+
+	#[test]
+	fn local_file()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let bd = td.path();
+		mktree(bd, &["/a/", "/a/f1", "/a/mine"]);
+
+		let got = super::remove(bd, &["/a", "/a/f1"]).unwrap();
+		assert_eq!(got.retained, pb(&["/a"]));
+		assert!(got.failed.is_empty());
+		assert!(!exists(bd, "/a/f1"));
+		assert!(exists(bd, "/a/mine"));
+	}
+
+	#[test]
+	fn nested_local()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let bd = td.path();
+
+		// Local file down in a subdir that upstream is also removing;
+		// both are kept.  A sibling subdir that's all upstream's goes.
+		// And a local subdir of our own (not in the removal set) keeps
+		// its parent around too.
+		mktree(bd, &["/a/", "/a/f1", "/a/b/", "/a/b/f2", "/a/b/c/",
+				"/a/b/c/mine", "/a/d/", "/a/d/f3",
+				"/e/", "/e/f4", "/e/local/", "/e/local/deeper/"]);
+
+		let rms = ["/a", "/a/f1", "/a/b", "/a/b/f2", "/a/b/c", "/a/d",
+				"/a/d/f3", "/e", "/e/f4"];
+		let got = super::remove(bd, &rms).unwrap();
+		let mut retained = got.retained.clone();
+		retained.sort();
+		assert_eq!(retained, pb(&["/a", "/a/b", "/a/b/c", "/e"]));
+		assert!(got.failed.is_empty(), "{got:?}");
+
+		assert!(exists(bd, "/a/b/c/mine"));
+		assert!(exists(bd, "/e/local/deeper"));
+		for gone in ["/a/f1", "/a/b/f2", "/a/d", "/e/f4"]
+		{ assert!(!exists(bd, gone), "{gone} should be gone"); }
+	}
+}
@@ -0,0 +1,244 @@
+//! Tempfiles left behind by an install that died partway.
+//!
+//! Files (and hardlinks) get made under a temp name in their destination
+//! dir, then renamed into place; x-ref bits::file().  If we get killed
+//! in between, the temp file just stays there, turning up in backups and
+//! `ls -a` to confuse people.  They all start with TMP_PREFIX, so we can
+//! find them again.  We only look in the dirs a manifest touches, since
+//! those are the only places we'd have made them, rather than walking
+//! the whole basedir.
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+
+/// What our install tempfiles are named; tempfile adds some random
+/// chars after.
+pub(crate) const TMP_PREFIX: &str = ".freebsd-rustdate.tmp.";
+
+/// Leave anything younger than this alone; it could be another install
+/// working right now.  Age goes by ctime, not mtime: install sets the
+/// mtime on its tempfiles (back to the release's, with
+/// PreserveTimestamps) before renaming them, but nothing can move a
+/// ctime back, so it says when the file was last touched.
+pub(crate) const MIN_AGE: Duration = Duration::from_secs(5 * 60);
+
+
+/// The dirs worth looking in: what a pending manifest touches, and what
+/// the last one to finish did.
+pub(crate) fn dirs(pending: Option<&crate::state::Manifest>, last: &[PathBuf])
+		-> Vec<PathBuf>
+{
+	let mut ret: Vec<PathBuf> = last.to_vec();
+	if let Some(m) = pending { ret.extend(m.install_dirs()); }
+	ret.sort_unstable();
+	ret.dedup();
+	ret
+}
+
+
+/// Residue found in a set of dirs.
+#[derive(Debug, Default)]
+pub(crate) struct Residue
+{
+	/// What we found (and removed, unless we were just looking)
+	pub(crate) found: Vec<PathBuf>,
+
+	/// What we couldn't remove, and why
+	pub(crate) failed: Vec<(PathBuf, std::io::Error)>,
+}
+
+impl Residue
+{
+	/// Lines about it, for saying.  Nothing if there wasn't any.
+	pub(crate) fn describe(&self, dry: bool) -> Vec<String>
+	{
+		use crate::util::plural;
+
+		let mut ret = Vec::new();
+		let nf = self.found.len();
+		if nf == 0 { return ret; }
+		ret.push(format!("{nf} leftover install tempfile{} from an earlier \
+				install{}:", plural(nf), match dry {
+					true  => " (not removing, dry run)",
+					false => ", removing",
+				}));
+		ret.extend(self.found.iter().map(|p| format!("  {}", p.display())));
+		for (p, e) in &self.failed
+		{ ret.push(format!("Couldn't remove {}: {e}", p.display())); }
+		ret
+	}
+}
+
+
+/// Look for our tempfiles older than min_age in dirs (relative to
+/// basedir), and remove them unless dry.
+pub(crate) fn sweep(basedir: &Path, dirs: &[PathBuf], min_age: Duration,
+		dry: bool) -> Residue
+{
+	let before = SystemTime::now().checked_sub(min_age)
+			.unwrap_or(SystemTime::UNIX_EPOCH);
+	sweep_before(basedir, dirs, before, dry)
+}
+
+
+/// sweep(), for tempfiles last changed no later than before.
+fn sweep_before(basedir: &Path, dirs: &[PathBuf], before: SystemTime,
+		dry: bool) -> Residue
+{
+	let mut ret = Residue::default();
+	ret.found = find(basedir, dirs, before);
+	if dry { return ret; }
+
+	for p in &ret.found
+	{
+		if let Err(e) = std::fs::remove_file(p)
+		{ ret.failed.push((p.clone(), e)); }
+	}
+	ret
+}
+
+
+/// Find them.  Unreadable or missing dirs are just skipped; it's not
+/// worth failing an install over.
+fn find(basedir: &Path, dirs: &[PathBuf], before: SystemTime)
+		-> Vec<PathBuf>
+{
+	use std::os::unix::ffi::OsStrExt as _;
+	use std::os::unix::fs::MetadataExt as _;
+
+	let mut ret = Vec::new();
+	for d in dirs
+	{
+		// Only real dirs; something that's turned into a symlink could
+		// send us off anywhere.
+		let full = crate::util::path_join(basedir, d);
+		match full.symlink_metadata() {
+			Ok(md) if md.is_dir() => (),
+			_ => continue,
+		}
+		let rd = match std::fs::read_dir(&full) {
+			Ok(rd) => rd,
+			Err(_) => continue,
+		};
+
+		for de in rd.filter_map(|e| e.ok())
+		{
+			if !de.file_name().as_bytes().starts_with(TMP_PREFIX.as_bytes())
+			{ continue; }
+
+			// Plain files only; we never leave anything else.  This
+			// doesn't follow symlinks.
+			let md = match de.metadata() {
+				Ok(md) if md.is_file() => md,
+				_ => continue,
+			};
+			let ctime = SystemTime::UNIX_EPOCH
+					+ Duration::new(md.ctime().max(0) as u64,
+						md.ctime_nsec().max(0) as u32);
+			if ctime <= before { ret.push(de.path()); }
+		}
+	}
+	ret.sort_unstable();
+	ret
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::fs;
+	use std::path::PathBuf;
+	use std::time::{Duration, SystemTime};
+	use super::TMP_PREFIX;
+
+	#[test]
+	fn sweep()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let base = td.path();
+		for d in ["bin", "etc", "usr/share"]
+		{ fs::create_dir_all(base.join(d)).unwrap(); }
+
+		// Make a file, with an mtime way back, like install leaves them
+		// with PreserveTimestamps.
+		let mk = |p: &str| {
+			let f = fs::File::create(base.join(p)).unwrap();
+			let when = SystemTime::now() - Duration::from_secs(86400 * 365);
+			f.set_modified(when).unwrap();
+		};
+		mk(&format!("bin/{TMP_PREFIX}aB3xQz"));
+		mk(&format!("etc/{TMP_PREFIX}Zz9k1P"));
+		mk("etc/.tmpQ2x7Pa");
+		mk("etc/passwd");
+		mk(&format!("usr/share/{TMP_PREFIX}notmine"));
+		fs::create_dir(base.join(format!("bin/{TMP_PREFIX}dir"))).unwrap();
+		std::os::unix::fs::symlink("/etc/passwd",
+				base.join(format!("bin/{TMP_PREFIX}link"))).unwrap();
+
+		// Then one that comes along later; give the fs clock time to
+		// tick over.
+		let before = SystemTime::now();
+		std::thread::sleep(Duration::from_millis(50));
+		mk(&format!("etc/{TMP_PREFIX}young1"));
+
+		// The manifest's dirs; usr/share isn't one.  And a dir that went
+		// away since doesn't matter.
+		let dirs: Vec<PathBuf> = ["/bin", "/etc", "/gone"].iter()
+				.map(PathBuf::from).collect();
+
+		// The mtimes don't make any of them old; they were all just
+		// touched, so could be an install going right now.
+		let res = super::sweep(base, &dirs, super::MIN_AGE, true);
+		assert!(res.found.is_empty(), "{res:?}");
+
+		// Looking doesn't touch anything
+		let res = super::sweep_before(base, &dirs, before, true);
+		let want = [base.join(format!("bin/{TMP_PREFIX}aB3xQz")),
+				base.join(format!("etc/{TMP_PREFIX}Zz9k1P"))];
+		assert_eq!(res.found, want);
+		assert!(want.iter().all(|p| p.exists()));
+		let desc = res.describe(true);
+		assert!(desc[0].starts_with("2 leftover install tempfiles"), "{desc:?}");
+
+		// Now for real; only the old prefixed files in our dirs go
+		let res = super::sweep_before(base, &dirs, before, false);
+		assert_eq!(res.found, want);
+		assert!(res.failed.is_empty());
+		assert!(want.iter().all(|p| !p.exists()));
+		for p in [format!("etc/{TMP_PREFIX}young1"), "etc/.tmpQ2x7Pa".into(),
+				"etc/passwd".into(), format!("usr/share/{TMP_PREFIX}notmine"),
+				format!("bin/{TMP_PREFIX}dir"), format!("bin/{TMP_PREFIX}link")]
+		{ assert!(base.join(&p).symlink_metadata().is_ok(), "{p}"); }
+
+		// And nothing left to say after
+		let res = super::sweep_before(base, &dirs, before, false);
+		assert!(res.describe(false).is_empty());
+	}
+
+	#[test]
+	fn dirs()
+	{
+		use crate::metadata::{Metadata, MetaFile};
+		use crate::state::Manifest;
+
+		let md = |ps: &[&str]| {
+			let mut m = Metadata::default();
+			for p in ps
+			{
+				m.files.insert(p.into(), MetaFile { path: p.into(),
+						..Default::default() });
+			}
+			m
+		};
+		let mani = Manifest::new_fetch(md(&["/bin/ls", "/etc/rc"]),
+				md(&["/bin/ls", "/usr/bin/vi"]),
+				"14.2-RELEASE-p2".parse().unwrap());
+		let last = [PathBuf::from("/etc"), PathBuf::from("/boot/kernel")];
+		let got = super::dirs(Some(&mani), &last);
+		assert_eq!(got, ["/bin", "/boot/kernel", "/etc", "/usr/bin"]
+				.map(PathBuf::from));
+		assert_eq!(super::dirs(None, &last), ["/boot/kernel", "/etc"]
+				.map(PathBuf::from));
+	}
+}
@@ -0,0 +1,176 @@
+//! Installing over what we're running from.
+//!
+//! If we're in base (or somebody put us in /usr/sbin), an install can
+//! replace our own binary, or shared libs we've got mapped.  The running
+//! process copes fine, since things get renamed over rather than written
+//! in place.  But dying between, say, the new libthr and the new libc
+//! leaves a mismatched pair for the next run, and `cron` re-execs
+//! whatever's at our path by then.  So we notice, say so, and put those
+//! last in their batch, with everything before them synced to disk
+//! first; x-ref do_mdl_installs().
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::SplitTypes;
+
+
+/// What we're running from, as paths on the host: the binary, and every
+/// shared object the runtime linker has mapped for us.
+pub(crate) fn running() -> Vec<PathBuf>
+{
+	let mut ret = Vec::new();
+	if let Ok(p) = std::env::current_exe() { ret.push(p); }
+	ret.extend(loaded_objects());
+	ret
+}
+
+
+/// The shared objects we've got loaded.  This is the same list procstat
+/// -v would show as mapped vnodes, minus the data files, and without
+/// needing to go through libprocstat.
+fn loaded_objects() -> Vec<PathBuf>
+{
+	use std::ffi::{CStr, OsStr};
+	use std::os::unix::ffi::OsStrExt as _;
+
+	unsafe extern "C" fn each(info: *mut libc::dl_phdr_info,
+			_size: libc::size_t, data: *mut libc::c_void) -> libc::c_int
+	{
+		// SAFETY: data is the Vec loaded_objects() handed in, and info
+		// is good for the length of the call.
+		let ret = unsafe { &mut *(data as *mut Vec<PathBuf>) };
+		let name = unsafe { (*info).dlpi_name };
+		if name.is_null() { return 0; }
+
+		// The main program shows up with an empty name; the vdso and
+		// such without a path.
+		let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+		if name.first() == Some(&b'/')
+		{ ret.push(OsStr::from_bytes(name).into()); }
+		0
+	}
+
+	let mut ret: Vec<PathBuf> = Vec::new();
+	// SAFETY: each() only touches ret, and only during the call.
+	unsafe {
+		libc::dl_iterate_phdr(Some(each),
+				&mut ret as *mut Vec<PathBuf> as *mut libc::c_void);
+	}
+	ret
+}
+
+
+/// Where a host path is in basedir's terms (as the metadata has it), if
+/// it's under basedir at all.  Symlinks get resolved on both sides, so
+/// /compat/whatever or a symlinked /usr don't throw it off.
+pub(crate) fn in_basedir(p: &Path, basedir: &Path) -> Option<PathBuf>
+{
+	let canon = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.into());
+	let p = canon(p);
+	let rel = p.strip_prefix(canon(basedir)).ok()?;
+	Some(Path::new("/").join(rel))
+}
+
+
+/// Which of the paths to install are things we're running from.  Both
+/// sides are in basedir terms.
+pub(crate) fn matching<'a>(install: impl IntoIterator<Item = &'a PathBuf>,
+		running: &[PathBuf]) -> Vec<PathBuf>
+{
+	let running: HashSet<&Path> = running.iter().map(|p| p.as_path())
+			.collect();
+	let mut ret: Vec<PathBuf> = install.into_iter()
+			.filter(|p| running.contains(p.as_path()))
+			.cloned().collect();
+	ret.sort_unstable();
+	ret
+}
+
+
+/// Which of what's about to be installed we're running from.
+pub(crate) fn find(smd: &SplitTypes, basedir: &Path) -> Vec<PathBuf>
+{
+	let running: Vec<PathBuf> = running().iter()
+			.filter_map(|p| in_basedir(p, basedir)).collect();
+	let inst = smd.files.keys().chain(smd.hards.keys())
+			.chain(smd.syms.keys());
+	matching(inst, &running)
+}
+
+
+/// Lines about it, for saying.  Nothing if there wasn't any.
+pub(crate) fn describe(ours: &[PathBuf]) -> Vec<String>
+{
+	let mut ret = Vec::new();
+	if ours.is_empty() { return ret; }
+
+	ret.push("Note: this install replaces files this program is running \
+			from:".to_string());
+	ret.extend(ours.iter().map(|p| format!("  {}", p.display())));
+	ret.push("They'll go in last, after everything else is synced to \
+			disk.  If we die partway, the\npending install is still \
+			there; just run install again (from the new binary, if it \
+			got that far).".to_string());
+	ret
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+
+	fn pb(ps: &[&str]) -> Vec<PathBuf>
+	{ ps.iter().map(PathBuf::from).collect() }
+
+	#[test]
+	fn matching()
+	{
+		let inst = pb(&["/lib/libc.so.7", "/lib/libthr.so.3", "/bin/ls",
+				"/usr/sbin/freebsd-rustdate", "/usr/lib/libssl.so.30"]);
+		let running = pb(&["/usr/sbin/freebsd-rustdate", "/lib/libthr.so.3",
+				"/lib/libc.so.7", "/lib/libm.so.5"]);
+		let got = super::matching(&inst, &running);
+		assert_eq!(got, pb(&["/lib/libc.so.7", "/lib/libthr.so.3",
+				"/usr/sbin/freebsd-rustdate"]));
+
+		// Nothing in common, nothing to say
+		assert!(super::matching(&inst, &pb(&["/usr/local/bin/x"]))
+				.is_empty());
+		assert!(super::describe(&[]).is_empty());
+		let desc = super::describe(&got);
+		assert_eq!(desc[1], "  /lib/libc.so.7");
+		assert_eq!(desc.len(), 5);
+	}
+
+	#[test]
+	fn in_basedir()
+	{
+		use super::in_basedir;
+		let td = tempfile::tempdir().unwrap();
+		let bd = td.path().join("jail");
+		std::fs::create_dir_all(bd.join("usr/sbin")).unwrap();
+		std::fs::write(bd.join("usr/sbin/prog"), b"").unwrap();
+		std::os::unix::fs::symlink("jail", td.path().join("link")).unwrap();
+
+		assert_eq!(in_basedir(&bd.join("usr/sbin/prog"), &bd),
+				Some("/usr/sbin/prog".into()));
+		assert_eq!(in_basedir(&td.path().join("link/usr/sbin/prog"), &bd),
+				Some("/usr/sbin/prog".into()));
+		assert_eq!(in_basedir(&td.path().join("elsewhere"), &bd), None);
+		assert_eq!(in_basedir("/lib/libc.so.7".as_ref(), "/".as_ref()),
+				Some("/lib/libc.so.7".into()));
+	}
+
+	#[test]
+	fn running()
+	{
+		// We're at least running the test binary, and on anything
+		// dynamic, libc.
+		let r = super::running();
+		let me = std::env::current_exe().unwrap();
+		assert_eq!(r[0], me);
+		assert!(r.iter().all(|p| p.is_absolute()), "{r:?}");
+	}
+}
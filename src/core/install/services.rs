@@ -0,0 +1,268 @@
+//! Restarting services after install.
+//!
+//! Some running services don't take kindly to having their bits
+//! replaced out from under them; sshd is the big one (PR263489), since
+//! new logins fail until it's restarted.  So we work out which ones the
+//! install touched, what we're going to do about each, and say so before
+//! (or, in a dry run, instead of) doing it.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::util::jail::Jail;
+use crate::util::out::{say, sayln};
+
+
+/// Services we know to restart, and the paths that mean they need it.
+/// A path matches exactly, or with a .suffix (for versioned libs).
+const WATCHED: &[(&str, &[&str])] = &[
+	("sshd", &["/usr/sbin/sshd", "/usr/libexec/sshd-session",
+			"/usr/libexec/sshd-auth", "/usr/lib/libprivatessh.so"]),
+];
+
+
+/// Where we're installing, as far as restarting things goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target
+{
+	/// The running system
+	Root,
+
+	/// A running jail
+	Jail,
+
+	/// A running jail that's getting restarted as a whole
+	/// afterward
+	JailRestart,
+
+	/// Some dir that nothing's running out of
+	Elsewhere,
+}
+
+
+/// What we'll do about a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Plan
+{
+	/// Restart it (if it's running)
+	Restart,
+
+	/// Excluded by NoRestartServices/--no-restart-services
+	Excluded,
+
+	/// We're not root, so can't
+	NotRoot,
+
+	/// The jail restart will take care of it
+	WithJail,
+}
+
+
+/// A service the install touched, and what we'll do about it.
+#[derive(Debug)]
+pub(crate) struct Action
+{
+	/// Service name, as service(8) knows it
+	pub(crate) svc: &'static str,
+
+	/// An installed path that means it needs restarting
+	pub(crate) why: PathBuf,
+
+	pub(crate) plan: Plan,
+}
+
+impl Action
+{
+	fn describe(&self, dry: bool) -> String
+	{
+		let (svc, why) = (self.svc, self.why.display());
+		match (self.plan, dry) {
+			(Plan::Restart, true)  => format!("Would restart {svc}: {why} \
+					updated"),
+			(Plan::Restart, false) => format!("Restarting {svc}: {why} \
+					updated"),
+			(Plan::Excluded, _) => format!("Not restarting {svc} (excluded): \
+					{why} updated; restart it by hand"),
+			(Plan::NotRoot, _)  => format!("Can't restart {svc} as non-root: \
+					{why} updated; restart it by hand"),
+			(Plan::WithJail, _) => format!("{svc}: {why} updated; will be \
+					restarted with the jail"),
+		}
+	}
+}
+
+
+/// Decide what to do about one service.  None means nothing at all,
+/// since nothing's running from what we installed.
+pub(crate) fn decide(target: Target, excluded: bool, root: bool)
+		-> Option<Plan>
+{
+	match target {
+		Target::Elsewhere   => None,
+		Target::JailRestart => Some(Plan::WithJail),
+		Target::Root | Target::Jail => Some(match (excluded, root) {
+			(true, _)      => Plan::Excluded,
+			(false, false) => Plan::NotRoot,
+			(false, true)  => Plan::Restart,
+		}),
+	}
+}
+
+
+/// Work out what to do about services, given the paths installed.
+/// `excluded` says whether a service shouldn't be automatically
+/// restarted.
+pub(crate) fn plan<'a>(installed: impl IntoIterator<Item = &'a Path>,
+		target: Target, excluded: impl Fn(&str) -> bool, root: bool)
+		-> Vec<Action>
+{
+	// Which services, and why?
+	let mut hits: Vec<Option<PathBuf>> = vec![None; WATCHED.len()];
+	for p in installed
+	{
+		let pstr = p.to_string_lossy();
+		for (i, (_, wpaths)) in WATCHED.iter().enumerate()
+		{
+			if hits[i].is_some() { continue; }
+			let hit = wpaths.iter().any(|w| match pstr.strip_prefix(w) {
+				Some(rest) => rest.is_empty() || rest.starts_with('.'),
+				None => false,
+			});
+			if hit { hits[i] = Some(p.to_path_buf()); }
+		}
+	}
+
+	WATCHED.iter().zip(hits).filter_map(|(&(svc, _), why)| {
+		let why = why?;
+		let plan = decide(target, excluded(svc), root)?;
+		Some(Action { svc, why, plan })
+	}).collect()
+}
+
+
+/// Say what we're doing about services, and (when not a dry run) do it.
+/// If we're installing into a running jail, it's the jail's services we
+/// care about, so poke at them via jexec.
+pub(crate) fn run(acts: &[Action], jail: Option<&Jail>, dry: bool)
+		-> Result<(), anyhow::Error>
+{
+	for act in acts
+	{
+		let desc = act.describe(dry);
+		if dry || act.plan != Plan::Restart
+		{
+			let dstr = if dry { "   (dry run)" } else { "" };
+			sayln!("{desc}{dstr}");
+			continue;
+		}
+		restart(act.svc, jail, &desc)?;
+	}
+	Ok(())
+}
+
+
+/// Restart a service, if it's running.
+fn restart(svc: &str, jail: Option<&Jail>, desc: &str)
+		-> Result<(), anyhow::Error>
+{
+	// service(8) either here or in the jail
+	const SVC: &str = "/usr/sbin/service";
+	let service = |act: &str| -> Result<std::process::ExitStatus, std::io::Error> {
+		match jail {
+			Some(j) => j.exec(&[SVC, svc, act]),
+			None    => Command::new(SVC).args([svc, act]).status(),
+		}
+	};
+
+	// See if we can see it running.  If jexec itself can't go, that's
+	// just as good as not running.
+	match service("status") {
+		Ok(cret) if cret.success() => (),
+		_ => return Ok(()),
+	}
+
+	// It is, kick it.
+	match jail {
+		Some(j) => say!("{desc} (in jail {j})...  "),
+		None    => say!("{desc}...  "),
+	}
+	crate::util::out::flush();
+
+	// If it fails, warn very loudly
+	let cret = service("restart")?;
+	match cret.success() {
+		true  => sayln!("Done."),
+		false => eprintln!("\nWARNING WARNING WARNING: restart {svc} failed\n\
+				{cret:?}\n"),
+	}
+
+	Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::Path;
+	use super::{decide, plan, Target as T, Plan as P};
+
+	#[test]
+	fn decisions()
+	{
+		// (target, excluded, root) => plan
+		let table = [
+			(T::Root,        false, true,  Some(P::Restart)),
+			(T::Root,        true,  true,  Some(P::Excluded)),
+			(T::Root,        false, false, Some(P::NotRoot)),
+			(T::Root,        true,  false, Some(P::Excluded)),
+			(T::Jail,        false, true,  Some(P::Restart)),
+			(T::Jail,        true,  true,  Some(P::Excluded)),
+			(T::Jail,        false, false, Some(P::NotRoot)),
+			(T::JailRestart, false, true,  Some(P::WithJail)),
+			(T::JailRestart, true,  true,  Some(P::WithJail)),
+			(T::Elsewhere,   false, true,  None),
+			(T::Elsewhere,   true,  false, None),
+		];
+		for (t, ex, root, want) in table
+		{
+			assert_eq!(decide(t, ex, root), want, "{t:?} {ex} {root}");
+		}
+	}
+
+	#[test]
+	fn planning()
+	{
+		let paths = |ps: &'static [&'static str]| ps.iter().map(Path::new);
+		let none = |_: &str| false;
+
+		// Nothing of sshd's, nothing to do
+		let got = plan(paths(&["/bin/sh", "/usr/sbin/sshd-keygen",
+				"/etc/ssh/sshd_config"]), T::Root, none, true);
+		assert!(got.is_empty(), "{got:?}");
+
+		// Versioned lib counts
+		let got = plan(paths(&["/bin/sh", "/usr/lib/libprivatessh.so.5"]),
+				T::Root, none, true);
+		assert_eq!(got.len(), 1);
+		assert_eq!(got[0].svc, "sshd");
+		assert_eq!(got[0].why, Path::new("/usr/lib/libprivatessh.so.5"));
+		assert_eq!(got[0].plan, P::Restart);
+
+		// First reason found is the one we give
+		let got = plan(paths(&["/usr/sbin/sshd", "/usr/libexec/sshd-session"]),
+				T::Root, |s| s == "sshd", true);
+		assert_eq!(got.len(), 1);
+		assert_eq!(got[0].why, Path::new("/usr/sbin/sshd"));
+		assert_eq!(got[0].plan, P::Excluded);
+
+		// Other services being excluded don't matter
+		let got = plan(paths(&["/usr/sbin/sshd"]), T::Jail,
+				|s| s == "local_unbound", true);
+		assert_eq!(got[0].plan, P::Restart);
+
+		assert_eq!(got[0].describe(true),
+				"Would restart sshd: /usr/sbin/sshd updated");
+		assert_eq!(got[0].describe(false),
+				"Restarting sshd: /usr/sbin/sshd updated");
+	}
+}
@@ -0,0 +1,330 @@
+//! Post-install verification.
+//!
+//! After we've installed a set of things, go back and look at what's
+//! actually on disk, and make sure it matches what we meant to put
+//! there.  Things like a failed chflags partway through a run (e.g.,
+//! securelevel) can leave the system not quite what the metadata says,
+//! and nothing else would notice.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::MetadataLine;
+
+
+/// Something on disk that doesn't match what we installed.
+#[derive(Debug)]
+pub(crate) struct Mismatch
+{
+	/// Where
+	pub(crate) path: PathBuf,
+
+	/// What's wrong; "missing", "type", or one of the
+	/// MetadataLineDiff::dtype()'s
+	pub(crate) what: &'static str,
+
+	/// Description of how it differs
+	pub(crate) detail: String,
+}
+
+
+/// Check the paths we installed against what's on disk.
+///
+/// This is just lstat()'ing unless `hash` is set, in which case we also
+/// re-hash all the files.  Returns whatever mismatches we find, sorted
+/// by type and then path.
+pub(crate) fn verify(basedir: &Path, expect: &HashMap<PathBuf, MetadataLine>,
+		hash: bool) -> Result<Vec<Mismatch>, anyhow::Error>
+{
+	use crate::metadata::MetadataLine as ML;
+	use crate::metadata::MetadataLineDiff as D;
+
+	let mut paths: Vec<PathBuf> = expect.keys().cloned().collect();
+	paths.sort_unstable();
+	let cur = crate::core::scan::scan_inner(basedir.to_path_buf(), paths,
+			hash)?;
+
+	// If we're not root, install didn't try setting owners or flags, so
+	// there's no point complaining they're not set.  And the scanner
+	// only bothers reporting schg, so that's the only flag we can
+	// compare.
+	let root = crate::util::euid() == 0;
+	let schg = libc::SF_IMMUTABLE as u32;
+
+	let mut ret = Vec::new();
+	for (p, up) in expect
+	{
+		let mut bad = |what, detail| ret.push(Mismatch { path: p.clone(),
+				what, detail });
+
+		let my = match cur.get_path(p) {
+			None | Some(ML::Dash(_)) => {
+				bad("missing", "doesn't exist".to_string());
+				continue;
+			},
+			Some(m) => m,
+		};
+
+		// Which of a set of hardlinks the scanner calls the "file"
+		// depends on which it ran across first, and the target may not
+		// be something we installed this time around anyway.  So as long
+		// as it's there as one or the other, call it good.
+		if let (ML::HardLink(_), ML::File(_) | ML::HardLink(_)) = (up, &my)
+		{ continue; }
+
+		if up.ftype() != my.ftype()
+		{
+			bad("type", format!("{} expected {}", my.ftype(), up.ftype()));
+			continue;
+		}
+
+		let diffs = my.diff(up).ok().flatten().unwrap_or_default();
+		for d in diffs
+		{
+			let keep = match &d {
+				D::Uid(..) | D::Gid(..) => root,
+				D::Flags(s, o) => root && (s & schg) != (o & schg),
+				D::Sha256(..) => hash,
+				D::Mode(..) | D::Target(..) => true,
+			};
+			if keep { bad(d.dtype(), d.to_string()); }
+		}
+	}
+
+	ret.sort_unstable_by(|a, b| a.what.cmp(b.what)
+			.then_with(|| a.path.cmp(&b.path)));
+	Ok(ret)
+}
+
+
+/// The closing audit of an upgrade: check everything it should have left
+/// on disk, both what it installed and what it figured was already
+/// right (if we know; x-ref ManiUpgrade::unchanged()), with full
+/// hashing.  Returns how many paths were checked, and any mismatches.
+pub(crate) fn audit(basedir: &Path, mup: &crate::state::ManiUpgrade,
+		unchanged: Option<&crate::metadata::Metadata>)
+		-> Result<(usize, Vec<Mismatch>), anyhow::Error>
+{
+	// get_from_paths() sorts out the merges for us.  Whatever's
+	// unchanged can't have been merged, so can come straight out.
+	let paths = |md: &crate::metadata::Metadata| -> Vec<PathBuf> {
+		md.allpaths_hashset_nodash().into_iter().map(|p| p.to_path_buf())
+				.collect()
+	};
+	let mut expect = mup.get_from_paths(paths(&mup.new));
+	if let Some(unch) = unchanged
+	{ expect.extend(unch.get_from_paths(paths(unch))); }
+
+	// Derived db's got regenerated, so won't match upstream's.
+	expect.retain(|p, _| crate::core::derived::lookup(p).is_none());
+
+	let bad = verify(basedir, &expect, true)?;
+	Ok((expect.len(), bad))
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashMap;
+	use std::path::PathBuf;
+	use std::os::unix::fs::PermissionsExt as _;
+
+	use crate::core::RtDirs;
+	use crate::metadata::{MetadataLine, MetaFile, MetaDir};
+	use crate::util::hash::Sha256Hash;
+
+	/// Stash some content where install will look for it
+	fn stash(rtdirs: &RtDirs, content: &str) -> Sha256Hash
+	{
+		crate::util::compress::write_hashfile(rtdirs.files(),
+				content.as_bytes())
+	}
+
+	/// Install some lines under rtdirs' basedir, quietly
+	fn install(rtdirs: &RtDirs, base: &std::path::Path,
+			lines: HashMap<PathBuf, MetadataLine>)
+	{
+		crate::util::out::set_sink(Some(Box::new(std::io::sink())));
+		let ret = crate::core::install::split(
+				crate::metadata::SplitTypes::from_map_lines(lines),
+				rtdirs, base, Default::default(), false, false);
+		crate::util::out::set_sink(None);
+		assert!(ret.unwrap().is_empty());
+	}
+
+	#[test]
+	fn verify()
+	{
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		let sha256 = stash(&rtdirs, "echo hi\n");
+
+		// Ownership only gets checked if we're root, in which case we
+		// can set it to anything anyway.
+		let (uid, gid) = (crate::util::euid(), 0);
+		let dir = MetaDir { path: "/bin".into(), uid, gid, mode: 0o755,
+				flags: 0 };
+		let file = |p: &str| MetaFile { path: p.into(), uid, gid,
+				mode: 0o555, flags: 0, sha256 };
+		let lines: HashMap<PathBuf, MetadataLine> = [
+			("/bin".into(), dir.into()),
+			("/bin/sh".into(), file("/bin/sh").into()),
+			("/bin/csh".into(), file("/bin/csh").into()),
+		].into();
+
+		install(&rtdirs, base.path(), lines.clone());
+
+		// Right after install, all good.
+		let bad = super::verify(base.path(), &lines, true).unwrap();
+		assert!(bad.is_empty(), "clean install: {bad:?}");
+
+		// Now perturb one's mode, and whack the other.
+		let sh = base.path().join("bin/sh");
+		std::fs::set_permissions(&sh, std::fs::Permissions::from_mode(0o755))
+				.unwrap();
+		std::fs::remove_file(base.path().join("bin/csh")).unwrap();
+
+		let bad = super::verify(base.path(), &lines, false).unwrap();
+		let got: Vec<_> = bad.iter().map(|m| (m.what, m.path.to_str().unwrap()))
+				.collect();
+		assert_eq!(got, [("missing", "/bin/csh"), ("mode", "/bin/sh")]);
+		assert_eq!(bad[1].detail, "mode 755 expected 555");
+
+		// Content changes only show up in a full check
+		std::fs::write(&sh, "echo bye\n").unwrap();
+		let bad = super::verify(base.path(), &lines, false).unwrap();
+		assert!(!bad.iter().any(|m| m.what == "hash"), "no hashing");
+		let bad = super::verify(base.path(), &lines, true).unwrap();
+		assert!(bad.iter().any(|m| m.what == "hash"), "hashing");
+	}
+
+	#[test]
+	fn audit()
+	{
+		use crate::metadata::Metadata;
+		use crate::state::Manifest;
+		use crate::core::merge::Clean;
+
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		let (uid, gid) = (crate::util::euid(), 0);
+		let dir = |p: &str| MetaDir { path: p.into(), uid, gid, mode: 0o755,
+				flags: 0 };
+		let file = |p: &str, sha256| MetaFile { path: p.into(), uid, gid,
+				mode: 0o644, flags: 0, sha256 };
+
+		// An upgrade that updates sh and merges motd, and figured csh
+		// was already fine.
+		let sh = stash(&rtdirs, "new sh\n");
+		let motd_up = stash(&rtdirs, "Welcome to 15\n");
+		let motd_res = stash(&rtdirs, "Welcome to 15\nlocal bits\n");
+		let csh = stash(&rtdirs, "csh\n");
+
+		let mut new = Metadata::default();
+		new.dirs.insert("/bin".into(), dir("/bin"));
+		new.dirs.insert("/etc".into(), dir("/etc"));
+		new.files.insert("/bin/sh".into(), file("/bin/sh", sh));
+		new.files.insert("/etc/motd".into(), file("/etc/motd", motd_up));
+		let mut unchanged = Metadata::default();
+		unchanged.files.insert("/bin/csh".into(), file("/bin/csh", csh));
+
+		let mut merges = HashMap::new();
+		merges.insert("/etc/motd".into(), Clean { old: Default::default(),
+				new: motd_up.to_buf(), cur: Default::default(),
+				res: motd_res.to_buf(), resolved: false,
+				normalized: false });
+
+		let vers = "15.0-RELEASE".parse().unwrap();
+		let mut mani = Manifest::new_upgrade(Metadata::default(), new, vers,
+				merges, HashMap::new());
+		let mup = match &mut mani {
+			Manifest::Upgrade(u) => u,
+			_ => unreachable!(),
+		};
+
+		// Installed, and what was "already there"
+		let lines = |md: &Metadata| md.allpaths().into_iter()
+				.map(|p| p.to_path_buf()).collect::<Vec<_>>();
+		install(&rtdirs, base.path(), mup.get_from_paths(lines(&mup.new)));
+		install(&rtdirs, base.path(),
+				unchanged.get_from_paths(lines(&unchanged)));
+		let unchanged = Some(&unchanged);
+
+		// All good, including the merge result (not upstream's) for motd
+		let (checked, bad) = super::audit(base.path(), mup, unchanged)
+				.unwrap();
+		assert_eq!(checked, 5);
+		assert!(bad.is_empty(), "clean upgrade: {bad:?}");
+
+		// Without knowing what was already there, only what it did
+		let (checked, _) = super::audit(base.path(), mup, None).unwrap();
+		assert_eq!(checked, 4);
+
+		// Something it assumed was right, and something it did, go bad.
+		std::fs::write(base.path().join("bin/csh"), "not csh\n").unwrap();
+		std::fs::remove_file(base.path().join("etc/motd")).unwrap();
+
+		let (checked, bad) = super::audit(base.path(), mup, unchanged)
+				.unwrap();
+		assert_eq!(checked, 5);
+		let got: Vec<_> = bad.iter().map(|m| (m.what, m.path.to_str().unwrap()))
+				.collect();
+		assert_eq!(got, [("hash", "/bin/csh"), ("missing", "/etc/motd")]);
+	}
+
+	#[test]
+	fn symlink_perms()
+	{
+		use std::os::unix::fs::MetadataExt as _;
+		use crate::metadata::MetaSymLink;
+
+		crate::util::set_euid();
+		let base = tempfile::tempdir().unwrap();
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init(base.path(), wd.path()).unwrap();
+
+		// The link gets its own mode, and if we're root, owner.
+		let (uid, gid) = (crate::util::euid(), 0);
+		let dir = MetaDir { path: "/bin".into(), uid, gid, mode: 0o755,
+				flags: 0 };
+		let link = MetaSymLink { path: "/bin/l".into(), target: "sh".into(),
+				uid, gid, mode: 0o700, flags: 0 };
+		let lines: HashMap<PathBuf, MetadataLine> = [
+			("/bin".into(), dir.into()),
+			("/bin/l".into(), MetadataLine::SymLink(link.clone())),
+		].into();
+		install(&rtdirs, base.path(), lines.clone());
+
+		let lpath = base.path().join("bin/l");
+		let md = lpath.symlink_metadata().unwrap();
+		assert!(md.is_symlink());
+		assert_eq!(md.mode() & 0o7777, link.mode);
+		assert_eq!(md.uid(), link.uid);
+		if uid == 0 { assert_eq!(md.gid(), link.gid); }
+		let bad = super::verify(base.path(), &lines, false).unwrap();
+		assert!(bad.is_empty(), "clean install: {bad:?}");
+
+		// Something else changing it shows up...
+		crate::util::lchmod(&lpath, 0o755).unwrap();
+		let bad = super::verify(base.path(), &lines, false).unwrap();
+		let got: Vec<_> = bad.iter().map(|m| (m.what, m.path.to_str().unwrap()))
+				.collect();
+		assert_eq!(got, [("mode", "/bin/l")]);
+
+		// ...and installing over it again fixes it, without needing the
+		// link replaced.
+		install(&rtdirs, base.path(), lines.clone());
+		let md2 = lpath.symlink_metadata().unwrap();
+		assert_eq!(md2.ino(), md.ino());
+		assert_eq!(md2.mode() & 0o7777, link.mode);
+		let bad = super::verify(base.path(), &lines, false).unwrap();
+		assert!(bad.is_empty(), "fixed up: {bad:?}");
+	}
+}
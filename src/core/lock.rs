@@ -0,0 +1,192 @@
+//! Per-basedir lock.
+//!
+//! This is just an flock(2) on a file in the statedir, so it goes away
+//! on its own when we exit, however we exit.  Nothing takes it but
+//! `cron` so far, so it only keeps cron runs from piling up on each
+//! other.
+//!
+//! Whoever holds it writes their pid in it, purely so `doctor` can say
+//! who; the flock is what matters, and the file left behind after is
+//! harmless.
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+
+/// A held lock.  Dropping it lets go.
+#[derive(Debug)]
+pub(crate) struct BasedirLock
+{
+	_file: File,
+}
+
+
+/// Something went wrong taking the lock.
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+pub(crate) enum LockErr
+{
+	/// Somebody else still had it when we gave up waiting.
+	#[error("{0} is still locked by another run")]
+	Busy(String),
+
+	/// Couldn't even try.
+	#[error("locking {0}: {1}")]
+	IO(String, std::io::Error),
+}
+
+
+impl BasedirLock
+{
+	/// Take the lock on a file, waiting up to wait for whoever has it to
+	/// let go.
+	pub(crate) fn take(file: &Path, wait: Duration) -> Result<Self, LockErr>
+	{
+		let fstr = || file.display().to_string();
+		let fh = std::fs::OpenOptions::new().create(true).write(true)
+				.truncate(false).open(file)
+				.map_err(|e| LockErr::IO(fstr(), e))?;
+
+		let deadline = Instant::now() + wait;
+		loop
+		{
+			match try_flock(&fh) {
+				Ok(true) => {
+					note_pid(&fh);
+					return Ok(Self { _file: fh });
+				},
+				Ok(false) => (),
+				Err(e) => return Err(LockErr::IO(fstr(), e)),
+			}
+
+			let now = Instant::now();
+			if now >= deadline { return Err(LockErr::Busy(fstr())); }
+			let nap = (deadline - now).min(Duration::from_secs(1));
+			std::thread::sleep(nap);
+		}
+	}
+}
+
+
+/// What's up with a lockfile.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LockStatus
+{
+	/// No lockfile at all
+	Absent,
+
+	/// Somebody has it, with their pid if they wrote it
+	Held(Option<u32>),
+
+	/// Nobody has it, with the pid of whoever last did
+	Free(Option<u32>),
+}
+
+/// Look at a lockfile, without waiting on it or leaving it held.
+pub(crate) fn status(file: &Path) -> Result<LockStatus, std::io::Error>
+{
+	let fh = match File::open(file) {
+		Ok(fh) => fh,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound
+				=> return Ok(LockStatus::Absent),
+		Err(e) => return Err(e),
+	};
+	let pid = std::fs::read_to_string(file).ok()
+			.and_then(|s| s.trim().parse().ok());
+	match try_flock(&fh)? {
+		true  => Ok(LockStatus::Free(pid)),
+		false => Ok(LockStatus::Held(pid)),
+	}
+}
+
+
+/// Is a process still around?
+pub(crate) fn pid_alive(pid: u32) -> bool
+{
+	let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+	ret == 0 || std::io::Error::last_os_error().raw_os_error()
+			== Some(libc::EPERM)
+}
+
+
+/// Write our pid into a lockfile we just took.  It's only informational,
+/// so failing doesn't matter.
+fn note_pid(mut fh: &File)
+{
+	use std::io::Write as _;
+	if fh.set_len(0).is_ok()
+	{ let _ = writeln!(fh, "{}", std::process::id()); }
+}
+
+
+/// Try for an exclusive lock without blocking.  false means somebody
+/// else has it.
+fn try_flock(fh: &File) -> Result<bool, std::io::Error>
+{
+	use std::os::fd::AsRawFd as _;
+
+	let ret = unsafe { libc::flock(fh.as_raw_fd(),
+			libc::LOCK_EX | libc::LOCK_NB) };
+	if ret == 0 { return Ok(true); }
+	let err = std::io::Error::last_os_error();
+	match err.raw_os_error() {
+		Some(libc::EWOULDBLOCK) => Ok(false),
+		_ => Err(err),
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::{BasedirLock, LockErr};
+	use std::time::{Duration, Instant};
+
+	#[test]
+	fn take()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let lf = td.path().join("lock");
+
+		let held = BasedirLock::take(&lf, Duration::ZERO).unwrap();
+
+		// Separate open file, so it conflicts even within the process
+		let start = Instant::now();
+		let busy = BasedirLock::take(&lf, Duration::from_millis(300));
+		assert!(matches!(busy, Err(LockErr::Busy(_))), "{busy:?}");
+		assert!(start.elapsed() >= Duration::from_millis(300));
+
+		drop(held);
+		BasedirLock::take(&lf, Duration::ZERO).expect("free again");
+	}
+
+	#[test]
+	fn status()
+	{
+		use super::LockStatus as LS;
+
+		let td = tempfile::tempdir().unwrap();
+		let lf = td.path().join("lock");
+		assert_eq!(super::status(&lf).unwrap(), LS::Absent);
+
+		// Held, by us
+		let me = std::process::id();
+		let held = BasedirLock::take(&lf, Duration::ZERO).unwrap();
+		assert_eq!(super::status(&lf).unwrap(), LS::Held(Some(me)));
+		assert!(lf.exists());
+
+		// Let go, the file's still there naming us
+		drop(held);
+		assert_eq!(super::status(&lf).unwrap(), LS::Free(Some(me)));
+		assert!(super::pid_alive(me));
+
+		// Somebody long gone
+		let mut child = std::process::Command::new("true").spawn().unwrap();
+		let dead = child.id();
+		child.wait().unwrap();
+		assert!(!super::pid_alive(dead));
+		std::fs::write(&lf, format!("{dead}\n")).unwrap();
+		assert_eq!(super::status(&lf).unwrap(), LS::Free(Some(dead)));
+	}
+}
@@ -0,0 +1,449 @@
+//! Acquiring metadata from a server.
+//!
+//! Most of the commands that look at upstream metadata go through the
+//! same dance: load the index, fetch whatever metadata files we don't
+//! already have, check their hashes, and parse them out.  This does that
+//! once, reporting progress through a callback so each command can keep
+//! its own output.
+use crate::metadata::{MetadataIdx, MetadataGroup, IgnoreReport};
+use crate::config::Config;
+use super::RtDirs;
+
+use std::io::{stdout, Write as _};
+
+
+/// Where we get metadata from.  In real life, that's a Server; this
+/// lets tests fake one up.
+pub(crate) trait MetaSource
+{
+	/// Load up the metadata index.
+	fn get_metadata_idx(&mut self) -> Result<MetadataIdx, anyhow::Error>;
+
+	/// Fetch a set of metadata files into the files dir.
+	fn fetch_metafiles(&mut self, files: Vec<String>)
+			-> Result<u32, anyhow::Error>;
+}
+
+impl MetaSource for crate::server::Server
+{
+	fn get_metadata_idx(&mut self) -> Result<MetadataIdx, anyhow::Error>
+	{ crate::server::Server::get_metadata_idx(self) }
+
+	fn fetch_metafiles(&mut self, files: Vec<String>)
+			-> Result<u32, anyhow::Error>
+	{ crate::server::Server::fetch_metafiles(self, files) }
+}
+
+
+/// A MetaSource whose index we've already got in hand (say, one for a
+/// past patch level), getting the files from the real source.
+pub(crate) struct WithIdx<'a, S: MetaSource>
+{
+	pub(crate) src: &'a mut S,
+	pub(crate) idx: MetadataIdx,
+}
+
+impl<S: MetaSource> MetaSource for WithIdx<'_, S>
+{
+	fn get_metadata_idx(&mut self) -> Result<MetadataIdx, anyhow::Error>
+	{ Ok(self.idx.clone()) }
+
+	fn fetch_metafiles(&mut self, files: Vec<String>)
+			-> Result<u32, anyhow::Error>
+	{ self.src.fetch_metafiles(files) }
+}
+
+
+/// The metadata index for some patch level (vers) of the server's
+/// release.  The server's own tag only covers its latest; anything
+/// earlier needs one a fetch/upgrade here saved, which still has to
+/// check out against the key.  Errors say which piece is missing.
+pub(crate) fn idx_at(server: &mut crate::server::Server, rtdirs: &RtDirs,
+		keyprint: &str, vers: &crate::info::AVersion)
+		-> Result<MetadataIdx, anyhow::Error>
+{
+	use anyhow::{anyhow, bail};
+	use crate::server::SignedFiles;
+
+	let latest = server.keytag_patchnum().unwrap_or(0);
+	let patch = vers.patch.unwrap_or(0);
+	if patch > latest
+	{ bail!("The server only has up to -p{latest}, not {vers}."); }
+
+	let tidx = match patch == latest {
+		true => server.keytag_tidx()
+				.ok_or_else(|| anyhow!("Error: keytag should exist"))?
+				.to_string(),
+		false => {
+			use std::io::ErrorKind as EK;
+			let tag = match SignedFiles::archived_tag(&rtdirs.signed_dir(),
+					vers) {
+				Ok(t) => t,
+				Err(e) if e.kind() == EK::NotFound => bail!("No signed tag \
+						for {vers}: the server only offers its latest \
+						(-p{latest}), and no fetch or upgrade here saved \
+						one for {vers}."),
+				Err(e) => bail!("Loading the saved tag for {vers}: {e}"),
+			};
+			let kt = server.verify_other_tag(&tag, keyprint, vers)
+					.map_err(|e| anyhow!("Saved tag for {vers} doesn't \
+						verify: {e}"))?;
+			if kt.patch() != vers.patch
+			{
+				bail!("Saved tag for {vers} is really for -p{}",
+						kt.patch().unwrap_or(0));
+			}
+			kt.tidx().to_string()
+		},
+	};
+
+	match server.get_metadata_idx_at(&tidx)? {
+		Some(i) => Ok(i),
+		None => bail!("The server no longer carries the metadata index \
+				for {vers} (t/{tidx})."),
+	}
+}
+
+
+/// The steps we go through, for reporting.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Progress<'a>
+{
+	/// Starting to load the index
+	Index,
+	/// Got it
+	IndexOk,
+	/// Looking for the metadata files we need
+	Files,
+	/// All already here
+	FilesPresent,
+	/// Some weren't, going to fetch them
+	FilesMissing(usize),
+	/// Fetched 'em
+	FilesFetched,
+	/// Checking hashes of the metadata files
+	Hashes,
+	/// All good
+	HashesOk,
+	/// Some weren't
+	HashErrors(&'a [String]),
+	/// Starting to parse
+	Parsing,
+	/// Parsed one
+	Parsed(&'a str),
+	/// Done parsing
+	ParsingOk,
+}
+
+
+/// The usual way of showing Progress on stdout.
+///
+/// The labels are what we call the index and the files in the "Loading
+/// ..." and "Getting ..." lines; commands word those a little
+/// differently (e.g., some name the version).
+pub(crate) fn printer(idx: &str, files: &str) -> impl FnMut(Progress)
+{
+	let idx = idx.to_string();
+	let files = files.to_string();
+	move |p| {
+		use Progress as P;
+		match p {
+			P::Index        => print!("Loading {idx}..."),
+			P::IndexOk      => println!("   OK."),
+			P::Files        => print!("Getting {files}...  "),
+			P::FilesPresent => println!("All present."),
+			P::FilesMissing(n) => {
+				println!("{n} missing.");
+				println!("Fetching...");
+			},
+			P::FilesFetched => println!("Done."),
+			P::Hashes       => print!("Checking metadata file hashes...  "),
+			P::HashesOk     => println!("   OK."),
+			P::HashErrors(e) => println!("   Errors found.\n{}", e.join("\n")),
+			P::Parsing      => print!("Parsing metadata files...  "),
+			P::Parsed(w)    => print!(" {w}"),
+			P::ParsingOk    => println!("   OK."),
+		}
+		stdout().flush().ok();
+	}
+}
+
+/// The printer most commands use.
+pub(crate) fn default_printer() -> impl FnMut(Progress)
+{
+	printer("metadata index", "all metadata files")
+}
+
+
+/// Parsed metadata, and the index it came from.
+#[derive(Debug)]
+pub(crate) struct AcquiredMetadata
+{
+	/// The index
+	pub(crate) idx: MetadataIdx,
+
+	/// The parsed metadata files, by name ("all", "old", etc)
+	groups: Vec<(String, MetadataGroup)>,
+
+	/// What IgnorePaths removed from all of them
+	pub(crate) ignored: IgnoreReport,
+}
+
+impl AcquiredMetadata
+{
+	/// Pull out one of the parsed metadata files.  Asking for one we
+	/// weren't told to parse is a programmer error.
+	pub(crate) fn take(&mut self, which: &str) -> MetadataGroup
+	{
+		let pos = self.groups.iter().position(|(w, _)| w == which)
+				.unwrap_or_else(|| panic!("Didn't parse {which}"));
+		self.groups.remove(pos).1
+	}
+}
+
+
+
+/// Get the index and make sure we've got good copies of the metadata
+/// files.
+///
+/// `which` are the metadata files we need.  If `prev` is given (i.e.,
+/// the index from an earlier run), its files are fetched too if we don't
+/// have them.
+pub(crate) fn fetch_files(src: &mut impl MetaSource, rtdirs: &RtDirs,
+		which: &[&str], prev: Option<&MetadataIdx>,
+		report: &mut dyn FnMut(Progress))
+		-> Result<MetadataIdx, anyhow::Error>
+{
+	// Load up the metadata index stuff
+	report(Progress::Index);
+	let mdidx = src.get_metadata_idx()?;
+	report(Progress::IndexOk);
+
+	// Find and download any missing metadata files
+	report(Progress::Files);
+	let metamiss = {
+		let fd = rtdirs.files();
+		let cd = rtdirs.mdcache();
+		let mut missing = mdidx.not_in_dir(fd, cd, which);
+		if let Some(md) = prev
+		{ missing.extend(md.not_in_dir(fd, cd, which)); }
+		missing
+	};
+	match metamiss.len()
+	{
+		0 => report(Progress::FilesPresent),
+		n => {
+			report(Progress::FilesMissing(n));
+			let files = metamiss.into_iter().collect();
+			src.fetch_metafiles(files)?;
+			report(Progress::FilesFetched);
+		},
+	};
+
+	// Check all the metafiles hashes
+	report(Progress::Hashes);
+	let hres = {
+		let fd = rtdirs.files();
+		let td = rtdirs.tmp();
+		mdidx.check_hashes(fd, td, rtdirs.mdcache(), which)
+	};
+	match hres {
+		Ok(_) => {
+			report(Progress::HashesOk);
+			if let Some(cd) = rtdirs.mdcache() { MetadataIdx::prune_cache(cd); }
+		},
+		Err(e) => {
+			let estrs: Vec<_> = e.iter().map(|e| e.to_string()).collect();
+			report(Progress::HashErrors(&estrs));
+			match e.iter().any(|e| e.bad_file()) {
+				true  => anyhow::bail!("Invalid metafiles, bailing."),
+				false => anyhow::bail!("Couldn't decompress metafiles \
+						(out of space in the workdir?), bailing."),
+			}
+		},
+	};
+
+	Ok(mdidx)
+}
+
+
+/// Parse out metadata files that fetch_files() has gotten ready, in the
+/// given order.
+pub(crate) fn parse(mdidx: MetadataIdx, rtdirs: &RtDirs, config: &Config,
+		which: &[&str], report: &mut dyn FnMut(Progress))
+		-> Result<AcquiredMetadata, anyhow::Error>
+{
+	report(Progress::Parsing);
+	let mut groups = Vec::with_capacity(which.len());
+	let mut ignored = IgnoreReport::new(&config.ignore_paths);
+	for w in which
+	{
+		let (mdg, ign) = mdidx.parse_one_full(w, rtdirs.tmp(), config)?;
+		report(Progress::Parsed(w));
+		ignored.merge(ign);
+		groups.push((w.to_string(), mdg));
+	}
+	report(Progress::ParsingOk);
+
+	Ok(AcquiredMetadata { idx: mdidx, groups, ignored })
+}
+
+
+/// The whole thing: fetch_files() then parse().
+///
+/// `fetch` is what to make sure we have, `parse` what to parse out of
+/// that (which may be in a different order).
+pub(crate) fn acquire(src: &mut impl MetaSource, rtdirs: &RtDirs,
+		config: &Config, fetch: &[&str], parse: &[&str],
+		prev: Option<&MetadataIdx>, report: &mut dyn FnMut(Progress))
+		-> Result<AcquiredMetadata, anyhow::Error>
+{
+	let mdidx = fetch_files(src, rtdirs, fetch, prev, report)?;
+	self::parse(mdidx, rtdirs, config, parse, report)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::cell::RefCell;
+	use std::path::PathBuf;
+
+	/// A fake server, with a set of metadata files it can "fetch".
+	struct FakeSrc
+	{
+		idx: Vec<u8>,
+		files: Vec<(String, Vec<u8>)>,
+		filesdir: PathBuf,
+		fetched: RefCell<Vec<String>>,
+	}
+
+	impl MetaSource for FakeSrc
+	{
+		fn get_metadata_idx(&mut self) -> Result<MetadataIdx, anyhow::Error>
+		{ MetadataIdx::parse(&self.idx) }
+
+		fn fetch_metafiles(&mut self, files: Vec<String>)
+				-> Result<u32, anyhow::Error>
+		{
+			for f in &files
+			{
+				let (_, data) = self.files.iter().find(|(n, _)| n == f)
+						.ok_or_else(|| anyhow::anyhow!("no {f}"))?;
+				std::fs::write(self.filesdir.join(f), data)?;
+				self.fetched.borrow_mut().push(f.clone());
+			}
+			Ok(files.len() as u32)
+		}
+	}
+
+	/// Build a gzip'd metadata file, and its hash.
+	fn mk_mdfile(content: &str) -> (String, Vec<u8>)
+	{
+		let hash = crate::util::hash::sha256_reader(&mut content.as_bytes())
+				.unwrap().to_string();
+		(hash, crate::util::compress::gz_bytes(content.as_bytes()))
+	}
+
+	fn setup() -> (tempfile::TempDir, RtDirs, FakeSrc)
+	{
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = RtDirs::init("/".as_ref(), wd.path()).unwrap();
+
+		let (ahash, agz) = mk_mdfile("world|base|/bin/sh|f|0|0|0555|0|\
+				871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|\n");
+		let (ohash, ogz) = mk_mdfile("world|base|/bin/csh|f|0|0|0555|0|\
+				871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|\n");
+		let idx = format!("INDEX-ALL|{ahash}\nINDEX-OLD|{ohash}\n");
+
+		let files = vec![(format!("{ahash}.gz"), agz),
+				(format!("{ohash}.gz"), ogz)];
+		let filesdir = rtdirs.files().to_path_buf();
+		let src = FakeSrc { idx: idx.into_bytes(), files, filesdir,
+				fetched: RefCell::new(vec![]) };
+		(wd, rtdirs, src)
+	}
+
+	#[test]
+	fn fetch_missing()
+	{
+		let (_wd, rtdirs, mut src) = setup();
+		let mut steps = Vec::new();
+		let mut rep = |p: Progress| steps.push(format!("{p:?}"));
+
+		// Only asking for "all" only fetches that.
+		let idx = fetch_files(&mut src, &rtdirs, &["all"], None, &mut rep)
+				.unwrap();
+		assert_eq!(*src.fetched.borrow(), [src.files[0].0.clone()]);
+		assert_eq!(steps, ["Index", "IndexOk", "Files", "FilesMissing(1)",
+				"FilesFetched", "Hashes", "HashesOk"]);
+
+		// Second time around, it's there already.
+		src.fetched.borrow_mut().clear();
+		steps.clear();
+		let mut rep = |p: Progress| steps.push(format!("{p:?}"));
+		fetch_files(&mut src, &rtdirs, &["all", "old"], Some(&idx), &mut rep)
+				.unwrap();
+		assert_eq!(*src.fetched.borrow(), [src.files[1].0.clone()],
+				"only old fetched");
+		assert!(steps.contains(&"FilesMissing(1)".to_string()));
+	}
+
+	#[test]
+	fn prev_idx_files()
+	{
+		let (_wd, rtdirs, mut src) = setup();
+		let mut rep = |_p: Progress| ();
+
+		// A previous index pointing at old as "all" gets it fetched too.
+		let ohash = src.files[1].0.trim_end_matches(".gz").to_string();
+		let prev = MetadataIdx::parse(format!("INDEX-ALL|{ohash}\n")
+				.as_bytes()).unwrap();
+		fetch_files(&mut src, &rtdirs, &["all"], Some(&prev), &mut rep)
+				.unwrap();
+		let mut got = src.fetched.borrow().clone();
+		got.sort();
+		let mut expect: Vec<_> = src.files.iter().map(|f| f.0.clone())
+				.collect();
+		expect.sort();
+		assert_eq!(got, expect, "both fetched");
+	}
+
+	#[test]
+	fn with_idx()
+	{
+		let (_wd, rtdirs, mut src) = setup();
+		let mut rep = |_p: Progress| ();
+
+		// An index of our own, just pointing at old as "all"; the
+		// source's files still get used.
+		let ohash = src.files[1].0.trim_end_matches(".gz").to_string();
+		let idx = MetadataIdx::parse(format!("INDEX-ALL|{ohash}\n")
+				.as_bytes()).unwrap();
+		let mut wi = WithIdx { src: &mut src, idx: idx.clone() };
+		let got = fetch_files(&mut wi, &rtdirs, &["all"], None, &mut rep)
+				.unwrap();
+		assert_eq!(got, idx);
+		assert_eq!(*src.fetched.borrow(), [src.files[1].0.clone()]);
+	}
+
+	#[test]
+	fn bad_hash()
+	{
+		let (_wd, rtdirs, mut src) = setup();
+
+		// Corrupt the "all" file the server hands out.
+		let (_, garbage) = mk_mdfile("not what you expected\n");
+		src.files[0].1 = garbage;
+
+		let mut errs = 0;
+		let mut rep = |p: Progress| {
+			if let Progress::HashErrors(e) = p { errs = e.len(); }
+		};
+		fetch_files(&mut src, &rtdirs, &["all"], None, &mut rep)
+				.expect_err("bad hash should fail");
+		assert_eq!(errs, 1, "one bad file reported");
+	}
+}
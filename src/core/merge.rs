@@ -7,29 +7,39 @@
 use std::path::{Path, PathBuf};
 use std::fs;
 
-/// Files we don't bother trying to merge.
-static DONT_MERGE_STRS: &[&str]  = &[
-	// passwd stuff; this will all be regen'd from master.passwd
-	// anyway.
-	"/etc/passwd",
-	"/etc/spwd.db",
-	"/etc/pwd.db",
-
-	// Other .db files generated from /etc files
-	"/etc/login.conf.db",
-
-	// XXX This probably wouldn't have wound up in MergeChanges
-	// anyway?  That feels a bit like a bug...
-	"/var/db/services.db",
-];
-
-/// Don't merge particular Path's
+/// Reporting on what happened to /etc-ish files in an upgrade
+pub(crate) mod report;
+
+/// Don't merge particular Path's.  That's the derived files (passwd,
+/// the .db's, etc); they'll all be regen'd from their sources anyway.
 pub(crate) fn dont_merge() -> Vec<PathBuf>
 {
-	let pbuf = |p: &&str| -> PathBuf {
-		<&str as AsRef<Path>>::as_ref(p).to_path_buf()
-	};
-	DONT_MERGE_STRS.iter().map(|p| pbuf(p)).collect()
+	use crate::core::derived::DERIVED;
+	DERIVED.iter().map(|d| PathBuf::from(d.path)).collect()
+}
+
+
+/// The base to merge a MergeChanges file from, if it wants merging at
+/// all: it has to be in both old and new (there's nothing to merge
+/// otherwise), and match neither, nor the very-old version we may have
+/// from being behind on patches.
+///
+/// We don't really _know_ what version of the file the user started
+/// from, but guess it's the entry from old and run with it.
+///
+/// While f-u.sh does full comparisons and so will include e.g.
+/// permission-related mismatches, this just goes with hashes.
+pub(crate) fn merge_base<'a>(cur: &crate::metadata::MetaFile,
+		old: Option<&'a crate::metadata::MetaFile>,
+		new: Option<&crate::metadata::MetaFile>,
+		vold: Option<&crate::metadata::MetaFile>)
+		-> Option<&'a crate::metadata::MetaFile>
+{
+	let (old, new) = (old?, new?);
+	let ch = cur.sha256;
+	if ch == old.sha256 || ch == new.sha256 { return None; }
+	if vold.is_some_and(|v| v.sha256 == ch) { return None; }
+	Some(old)
 }
 
 
@@ -62,6 +72,15 @@ pub(crate) struct Clean
 
 	/// The resulting file's hash; should be stored in <filesdir>.
 	pub(crate) res: Sha256HashBuf,
+
+	/// This started out conflicted, and got fixed up in resolve-merges.
+	#[serde(default)]
+	pub(crate) resolved: bool,
+
+	/// This only merged once trailing whitespace and final newlines were
+	/// normalized (MergeNormalize), so res has them normalized too.
+	#[serde(default)]
+	pub(crate) normalized: bool,
 }
 
 
@@ -84,6 +103,25 @@ pub(crate) struct Conflict
 }
 
 
+/// Locally modified file that UpdateIfUnmodified left alone.  We don't
+/// need the new version for installing, but it gets fetched into
+/// `<filesdir>` anyway so there's something to compare against.
+#[derive(Debug)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Skipped
+{
+	/// The 'current' file hash; what's on the running system.
+	pub(crate) cur: Sha256HashBuf,
+
+	/// The 'new' file hash; the upstream version we didn't install
+	pub(crate) new: Sha256HashBuf,
+
+	/// It's somewhere security-sensitive; x-ref filter::SkippedUpdate
+	#[serde(default)]
+	pub(crate) security: bool,
+}
+
+
 
 
 
@@ -107,13 +145,33 @@ pub(crate) enum MergeError
 }
 
 
+/// How a successful merge went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Merged
+{
+	/// Just merged
+	Clean,
+
+	/// Conflicted as-is, but merged once whitespace was normalized; the
+	/// output is normalized too.
+	Normalized,
+}
+
+
 /// Attempt to merge files into an output file.
 ///
+/// With normalize, if the merge conflicts, we try again with trailing
+/// whitespace and final newlines normalized on all 3 sides, since people
+/// editing with editors that fiddle with those otherwise get "conflicts"
+/// with nothing in them.  That's only ever done to files that look like
+/// text.
+///
 /// Err(Conflicts) is "expected" to happen with some regularity, and is a
 /// signal to pass up to the user to resolve.  IO errors are probably
 /// something fatal.
 pub(crate) fn merge_files(old: &[u8], cur: &[u8], new: &[u8],
-		out: &mut fs::File) -> Result<(), MergeError>
+		normalize: bool, out: &mut impl std::io::Write)
+		-> Result<Merged, MergeError>
 {
 	// diffy only works on in-memory stuff.  It has separate functions
 	// for merging &str's and &[u8]'s, but inspection of the source
@@ -122,22 +180,147 @@ pub(crate) fn merge_files(old: &[u8], cur: &[u8], new: &[u8],
 	use diffy::merge_bytes;
 
 	// merge_bytes() returns the Vec<u8> of the merge results, but in Ok
-	// for success and Err for conflicts.  So extract it out, and define
-	// our return.
-	let ret;
-	use MergeError::Conflicts as EC;
-	let mbytes = match merge_bytes(&old, &cur, &new) {
-		Ok(b)  => { ret = Ok(());  b },
-		Err(b) => { ret = Err(EC); b },
+	// for success and Err for conflicts.
+	let conflicted = match merge_bytes(&old, &cur, &new) {
+		Ok(b)  => { out.write_all(&b)?; return Ok(Merged::Clean); },
+		Err(b) => b,
 	};
 
-	// Write out and return
-	use std::io::Write as _;
-	out.write_all(&mbytes)?;
+	// Conflicted; does it go away if we ignore the whitespace?
+	let text = [old, cur, new].iter().all(|b| is_text(b));
+	if normalize && text
+	{
+		let (o, c, n) = (normalize_ws(old), normalize_ws(cur),
+				normalize_ws(new));
+		if let Ok(b) = merge_bytes(&o, &c, &n)
+		{
+			out.write_all(&b)?;
+			return Ok(Merged::Normalized);
+		}
+	}
+
+	// Nope, it's a real conflict.  diffy puts each marker straight after
+	// the last line before it, so a side without a final newline gets
+	// the next marker stuck on the end of its last line ("foo=======").
+	// Nothing spots those, resolve-merges included, so redo it with the
+	// newlines there.  If that merges clean, the final newline was all
+	// it was about; then there's nothing better to show than what we've
+	// got.
+	let conflicted = match [old, cur, new].iter().all(|b| ends_nl(b)) {
+		true  => conflicted,
+		false => {
+			let nl = |b: &[u8]| {
+				let mut v = b.to_vec();
+				if !ends_nl(b) { v.push(b'\n'); }
+				v
+			};
+			merge_bytes(&nl(old), &nl(cur), &nl(new)).err()
+					.unwrap_or(conflicted)
+		},
+	};
+	out.write_all(&conflicted)?;
+	Err(MergeError::Conflicts)
+}
+
+
+/// Empty, or ends with a newline
+fn ends_nl(b: &[u8]) -> bool
+{
+	b.is_empty() || b.ends_with(b"\n")
+}
+
+
+/// Does this look like text?  Same guess as git and diff make: no NUL's
+/// up front.
+fn is_text(b: &[u8]) -> bool
+{
+	let head = &b[..b.len().min(8000)];
+	!head.contains(&0)
+}
+
+
+/// Strip trailing whitespace off every line, and end with exactly one
+/// newline (unless it's empty).
+fn normalize_ws(b: &[u8]) -> Vec<u8>
+{
+	let mut ret = Vec::with_capacity(b.len() + 1);
+	for line in b.split(|c| *c == b'\n')
+	{
+		let end = line.iter().rposition(|c| !c.is_ascii_whitespace())
+				.map_or(0, |i| i + 1);
+		ret.extend_from_slice(&line[..end]);
+		ret.push(b'\n');
+	}
+
+	// Blank lines at the end all come down to the one newline.
+	while ret.last() == Some(&b'\n') { ret.pop(); }
+	if !ret.is_empty() { ret.push(b'\n'); }
 	ret
 }
 
 
+/*
+ * Files that are new upstream, but we already have locally.
+ */
+use crate::metadata::Metadata;
+use regex_lite::Regex;
+
+/// Find files that are new in `new` (not in `old` at all), but already
+/// exist in `cur` with different contents.
+///
+/// This happens when a release starts shipping a config file at a path
+/// where the user already had one of their own.  Since it's not in old,
+/// none of the usual modified-vs-old checks see it, and it'd just be
+/// "added" right over the top of the local file.  We only care about
+/// paths matching `pats` (generally, MergeChanges and
+/// UpdateIfUnmodified); anything else, upstream wins as usual.
+///
+/// Returns sorted paths.
+pub(crate) fn new_local_collisions(old: &Metadata, cur: &Metadata,
+		new: &Metadata, pats: &[&[Regex]]) -> Vec<PathBuf>
+{
+	let dontmerge = dont_merge();
+	let matches = |p: &Path| -> bool {
+		let ps = p.to_string_lossy();
+		pats.iter().any(|res| res.iter().any(|r| r.is_match(&ps)))
+	};
+
+	let mut ret: Vec<PathBuf> = cur.files.iter().filter_map(|(p, cf)| {
+		if dontmerge.contains(p) { return None; }
+		let nf = new.files.get(p)?;
+		if old.files.contains_key(p) || old.hardlinks.contains_key(p)
+		{ return None; }
+		if cf.sha256 == nf.sha256 { return None; }
+		if !matches(p) { return None; }
+		Some(p.clone())
+	}).collect();
+	ret.sort_unstable();
+	ret
+}
+
+
+/// The hash of an empty file, which is what we use as the merge base for
+/// files that didn't exist in the old version.
+pub(crate) static EMPTY_SHA256: &str =
+		"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Make sure there's an empty file in the files dir, to serve as a merge
+/// base.  Returns its hash.
+pub(crate) fn empty_base(filesdir: &Path)
+		-> Result<crate::util::hash::Sha256Hash, anyhow::Error>
+{
+	let hash: crate::util::hash::Sha256Hash = EMPTY_SHA256.parse()?;
+	let gzf = filesdir.join(format!("{hash}.gz"));
+	if !gzf.is_file()
+	{
+		use flate2::{write::GzEncoder, Compression};
+		let fh = fs::File::create(&gzf)?;
+		GzEncoder::new(fh, Compression::default()).finish()?;
+	}
+	Ok(hash)
+}
+
+
 
 /*
  * Creating diffs.
@@ -175,3 +358,314 @@ pub(crate) fn merge_diff(fname: &Path, src: &[u8], dst: &[u8]) -> Vec<u8>
 
 	pbytes
 }
+
+
+/// Count the lines added/removed between two versions of a file, for a
+/// diffstat-ish summary.
+pub(crate) fn merge_diffstat(src: &[u8], dst: &[u8]) -> (usize, usize)
+{
+	use diffy::{create_patch_bytes, Line};
+
+	let patch = create_patch_bytes(src, dst);
+	let (mut add, mut rm) = (0, 0);
+	for h in patch.hunks()
+	{
+		for l in h.lines()
+		{
+			match l {
+				Line::Insert(_) => add += 1,
+				Line::Delete(_) => rm += 1,
+				Line::Context(_) => (),
+			}
+		}
+	}
+
+	(add, rm)
+}
+
+
+/// Are the only differences between two versions of a file in
+/// whitespace, blank lines, or comment lines?
+///
+/// This is pretty simpleminded; a line is a comment if its first
+/// non-whitespace is one of the given prefixes, and other lines are
+/// compared with whitespace runs collapsed.  Trailing comments on a line
+/// with other stuff count as real changes.
+pub(crate) fn trivial_changes(src: &[u8], dst: &[u8], comments: &[String])
+		-> bool
+{
+	let significant = |buf: &[u8]| -> Vec<Vec<u8>> {
+		buf.split(|c| *c == b'\n').filter_map(|l| {
+			let words: Vec<&[u8]> = l.split(|c| c.is_ascii_whitespace())
+					.filter(|w| !w.is_empty()).collect();
+			let first = words.first()?;
+			if comments.iter().any(|c| first.starts_with(c.as_bytes()))
+			{ return None; }
+			Some(words.join(&b' '))
+		}).collect()
+	};
+
+	significant(src) == significant(dst)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	fn comments() -> Vec<String> { vec!["#".to_string(), ";".to_string()] }
+
+	#[test]
+	fn trivial_changes()
+	{
+		use super::trivial_changes as tc;
+
+		let orig = b"# A config file\nfoo=bar\n\nbaz = quux\n";
+
+		// Identical is trivially trivial
+		assert!(tc(orig, orig, &comments()), "same file");
+
+		// Comment changes, blank lines, and whitespace shuffling
+		let cmt = b"# A different comment\n; and another\nfoo=bar\nbaz = quux\n";
+		assert!(tc(orig, cmt, &comments()), "comment changes");
+		let ws = b"# A config file\nfoo=bar\n\n\n  baz\t=   quux  \n";
+		assert!(tc(orig, ws, &comments()), "whitespace changes");
+
+		// Real changes aren't
+		let real = b"# A config file\nfoo=baz\n\nbaz = quux\n";
+		assert!(!tc(orig, real, &comments()), "value change");
+		let added = b"# A config file\nfoo=bar\nnew=thing\nbaz = quux\n";
+		assert!(!tc(orig, added, &comments()), "added line");
+
+		// Commenting out a line is a real change too
+		let cmtout = b"# A config file\n#foo=bar\n\nbaz = quux\n";
+		assert!(!tc(orig, cmtout, &comments()), "commented out line");
+
+		// And with no comment prefixes, comments count
+		assert!(!tc(orig, cmt, &[]), "no comment prefixes");
+	}
+
+	#[test]
+	fn merge_base()
+	{
+		use crate::metadata::MetaFile;
+
+		let mf = |c: &str| MetaFile { path: "/etc/x".into(),
+				sha256: crate::util::hash::sha256_reader(&mut c.as_bytes())
+					.unwrap(), ..Default::default() };
+		let (old, new, mine) = (mf("old"), mf("new"), mf("mine"));
+		let mb = |c, o, n, v| super::merge_base(c, o, n, v);
+
+		assert_eq!(mb(&mine, Some(&old), Some(&new), None), Some(&old));
+		assert_eq!(mb(&old, Some(&old), Some(&new), None), None);
+		assert_eq!(mb(&new, Some(&old), Some(&new), None), None);
+		assert_eq!(mb(&mine, Some(&old), Some(&new), Some(&mine)), None);
+		assert_eq!(mb(&mine, None, Some(&new), None), None);
+		assert_eq!(mb(&mine, Some(&old), None, None), None);
+	}
+
+	#[test]
+	fn new_local_collisions()
+	{
+		use crate::metadata::{Metadata, MetaFile};
+		use std::path::PathBuf;
+
+		let h = |c: &str| crate::util::hash::sha256_reader(&mut c.as_bytes())
+				.unwrap();
+		let mf = |p: &str, c: &str| (PathBuf::from(p),
+				MetaFile { path: p.into(), sha256: h(c), ..Default::default() });
+		let md = |fs: Vec<(PathBuf, MetaFile)>| Metadata {
+				files: fs.into_iter().collect(), ..Default::default() };
+
+		let old = md(vec![mf("/etc/existing.conf", "old")]);
+		let cur = md(vec![
+			mf("/etc/existing.conf", "mine"),   // in old; normal merge
+			mf("/etc/collide.conf", "mine"),    // new upstream, local differs
+			mf("/etc/same.conf", "upstream"),   // new upstream, local matches
+			mf("/usr/lib/collide.so", "mine"),  // not a pattern we care about
+			mf("/etc/passwd", "mine"),          // never merged
+		]);
+		let new = md(vec![
+			mf("/etc/existing.conf", "new"),
+			mf("/etc/collide.conf", "upstream"),
+			mf("/etc/same.conf", "upstream"),
+			mf("/usr/lib/collide.so", "upstream"),
+			mf("/etc/passwd", "upstream"),
+		]);
+
+		let mc = vec![regex_lite::Regex::new("^/etc/").unwrap()];
+		let got = super::new_local_collisions(&old, &cur, &new, &[&mc]);
+		assert_eq!(got, [PathBuf::from("/etc/collide.conf")]);
+
+		// Nothing matching, nothing found
+		let got = super::new_local_collisions(&old, &cur, &new, &[&[]]);
+		assert!(got.is_empty(), "no patterns");
+
+		// The empty base really is empty
+		let ehash = h("").to_string();
+		assert_eq!(ehash, super::EMPTY_SHA256);
+	}
+
+	#[test]
+	fn normalize_ws()
+	{
+		use super::normalize_ws as nw;
+
+		assert_eq!(nw(b"a  \nb\t\r\n\n\n"), b"a\nb\n");
+		assert_eq!(nw(b"a\nb"), b"a\nb\n");
+		assert_eq!(nw(b"  a\n\nb\n"), b"  a\n\nb\n", "leading/inner kept");
+		assert_eq!(nw(b""), b"");
+		assert_eq!(nw(b"\n \n"), b"");
+	}
+
+	#[test]
+	fn merge_normalized()
+	{
+		use super::{merge_files, Merged, MergeError};
+
+		let mrg = |o: &str, c: &str, n: &str, norm| {
+			let mut out = Vec::new();
+			let r = merge_files(o.as_bytes(), c.as_bytes(), n.as_bytes(),
+					norm, &mut out);
+			(r, String::from_utf8(out).unwrap())
+		};
+
+		// Our editor dropped the final newline while we changed the top;
+		// upstream changed the last line.  Nothing really conflicting,
+		// but diff3 thinks so.
+		let old = "a=1\nb=2\nc=3\nd=4\n";
+		let cur = "a=9\nb=2\nc=3\nd=4";
+		let new = "a=1\nb=2\nc=3\nd=5\n";
+		let (r, _) = mrg(old, cur, new, false);
+		assert!(matches!(r, Err(MergeError::Conflicts)), "{r:?}");
+		let (r, out) = mrg(old, cur, new, true);
+		assert!(matches!(r, Ok(Merged::Normalized)), "{r:?}");
+		assert_eq!(out, "a=9\nb=2\nc=3\nd=5\n");
+
+		// Editor stripped trailing whitespace we never meant to touch,
+		// while upstream changed that line.
+		let old = "x = 1 \ny = 2\nz = 3\nw = 4\n";
+		let cur = "x = 1\ny = 2\nz = 3\nw = 7\n";
+		let new = "x = 2 \ny = 2\nz = 3\nw = 4\n";
+		let (r, _) = mrg(old, cur, new, false);
+		assert!(r.is_err());
+		let (r, out) = mrg(old, cur, new, true);
+		assert!(matches!(r, Ok(Merged::Normalized)), "{r:?}");
+		assert_eq!(out, "x = 2\ny = 2\nz = 3\nw = 7\n");
+
+		// Clean merges don't get touched at all.
+		let (r, out) = mrg("a\nb\nc\nd\n", "a \nb\nc\nd\n",
+				"a\nb\nc\nd\ne\n", true);
+		assert!(matches!(r, Ok(Merged::Clean)), "{r:?}");
+		assert_eq!(out, "a \nb\nc\nd\ne\n");
+
+		// Real conflicts stay conflicts, with the original markers.
+		let (r, out) = mrg("a\n", "b \n", "c\n", true);
+		assert!(r.is_err());
+		assert!(out.contains("b \n"), "{out}");
+
+		// And binary-looking files never get normalized.
+		let (r, _) = mrg("\0a=1\nb=2\nc=3\nd=4\n", "\0a=9\nb=2\nc=3\nd=4",
+				"\0a=1\nb=2\nc=3\nd=5\n", true);
+		assert!(r.is_err());
+	}
+
+	#[test]
+	fn no_final_newline()
+	{
+		// Markers always start their own lines, even when the side before
+		// doesn't end in a newline.
+		let mut out = Vec::new();
+		let r = super::merge_files(b"a\nb", b"a\nc", b"a\nd", false,
+				&mut out);
+		assert!(r.is_err());
+		assert_eq!(String::from_utf8(out).unwrap(), "a\n<<<<<<< ours\nc\n\
+				||||||| original\nb\n=======\nd\n>>>>>>> theirs\n");
+	}
+
+
+	/*
+	 * Whatever's lying around locally, merging it shouldn't fall over,
+	 * and whatever comes out should make sense.
+	 */
+	use proptest::prelude::*;
+
+	const MARKERS: [&[u8]; 4] = [b"<<<<<<<", b"|||||||", b"=======",
+			b">>>>>>>"];
+
+	/// Small blobs, a few lines apiece; sometimes text, sometimes not.
+	fn blob() -> impl Strategy<Value = Vec<u8>>
+	{
+		let bytes = prop::sample::select(&b"ab \n\n\0\xff"[..]);
+		proptest::collection::vec(bytes, 0..48)
+	}
+
+	/// Where some marker is in a blob, and if it started a line
+	fn markers(b: &[u8]) -> Vec<(usize, bool)>
+	{
+		MARKERS.iter().flat_map(|m| b.windows(m.len()).enumerate()
+				.filter(move |(_, w)| w == m)
+				.map(move |(i, _)| (i, i == 0 || b[i - 1] == b'\n')))
+				.collect()
+	}
+
+	proptest!
+	{
+		#[test]
+		fn any_merge(old in blob(), cur in blob(), new in blob(),
+				norm in any::<bool>())
+		{
+			use super::{merge_files, MergeError};
+
+			let mut out = Vec::new();
+			match merge_files(&old, &cur, &new, norm, &mut out) {
+				// None of ours in the input, so there'd better not be
+				// any in the output.
+				Ok(_) => prop_assert!(markers(&out).is_empty()),
+				Err(MergeError::Conflicts) => {
+					let ms = markers(&out);
+					prop_assert!(!ms.is_empty());
+
+					// They're on their own lines, unless the conflict
+					// was only about a final newline.
+					let nl = |b: &[u8]| { let mut v = b.to_vec();
+							if !super::ends_nl(b) { v.push(b'\n'); } v };
+					let onlynl = diffy::merge_bytes(&nl(&old), &nl(&cur),
+							&nl(&new)).is_ok();
+					prop_assert!(onlynl || ms.iter().all(|(_, s)| *s),
+							"{:?}", String::from_utf8_lossy(&out));
+				},
+				Err(e) => prop_assert!(false, "{e}"),
+			}
+		}
+
+		#[test]
+		fn one_side(old in blob(), new in blob())
+		{
+			// Nothing changed locally means just taking theirs, and vice
+			// versa.
+			use super::{merge_files, Merged};
+
+			let mut out = Vec::new();
+			let r = merge_files(&old, &old, &new, false, &mut out);
+			prop_assert!(matches!(r, Ok(Merged::Clean)), "{r:?}");
+			prop_assert_eq!(&out, &new);
+
+			let mut out = Vec::new();
+			let r = merge_files(&old, &new, &old, false, &mut out);
+			prop_assert!(matches!(r, Ok(Merged::Clean)), "{r:?}");
+			prop_assert_eq!(&out, &new);
+		}
+	}
+
+
+	#[test]
+	fn diffstat()
+	{
+		let orig = b"a\nb\nc\n";
+		let new  = b"a\nB\nc\nd\n";
+		let (add, rm) = super::merge_diffstat(orig, new);
+		assert_eq!(add, 2, "2 lines added");
+		assert_eq!(rm,  1, "1 line removed");
+	}
+}
@@ -0,0 +1,301 @@
+//! Report of /etc differences from a pristine new release.
+//!
+//! After an upgrade, the question somebody reviewing it usually wants
+//! answered is "what's different in /etc from a stock install of the new
+//! version?"  The answer is scattered across the merges, the files
+//! UpdateIfUnmodified skipped, and what KeepModifiedMetadata kept, so
+//! this pulls it all together, for everything under the MergeChanges
+//! and UpdateIfUnmodified patterns.
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use regex_lite::Regex;
+
+use crate::core::RtDirs;
+use crate::state::ManiUpgrade;
+
+
+/// What happened with a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Class
+{
+	/// Local changes merged cleanly with upstream's
+	Merged,
+
+	/// Merge conflicted, and was fixed up in resolve-merges
+	Resolved,
+
+	/// Merge conflicted, and still needs resolving
+	Conflict,
+
+	/// Locally modified, so UpdateIfUnmodified left it alone
+	LocalKept,
+
+	/// Replaced by upstream's version
+	Upstream,
+
+	/// Contents untouched, but local owner/mode/flags kept over
+	/// upstream's (KeepModifiedMetadata)
+	MetadataOnly,
+}
+
+impl Class
+{
+	pub(crate) fn desc(&self) -> &'static str
+	{
+		match self {
+			Self::Merged       => "merged cleanly",
+			Self::Resolved     => "conflict resolved",
+			Self::Conflict     => "conflict unresolved",
+			Self::LocalKept    => "local kept, update skipped",
+			Self::Upstream     => "upstream taken",
+			Self::MetadataOnly => "metadata-only modified",
+		}
+	}
+}
+
+
+/// One file in the report
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+pub(crate) struct Entry
+{
+	pub(crate) path: PathBuf,
+	pub(crate) class: Class,
+
+	/// Local metadata was kept over upstream's too
+	pub(crate) metadata_kept: bool,
+
+	/// Diff from the pristine new version to what's installed (or will
+	/// be).  None when they're the same, or we couldn't tell.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) diff: Option<String>,
+
+	/// Anything else worth knowing, like why there's no diff
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub(crate) note: Option<String>,
+}
+
+
+/// The whole report
+#[derive(Debug)]
+#[derive(serde::Serialize)]
+pub(crate) struct Report
+{
+	/// Version we're upgrading from, and to
+	pub(crate) from: String,
+	pub(crate) to: String,
+
+	/// Whether world was installed when this was made
+	pub(crate) installed: bool,
+
+	pub(crate) files: Vec<Entry>,
+}
+
+impl Report
+{
+	/// How many files of each class.
+	pub(crate) fn counts(&self) -> BTreeMap<Class, usize>
+	{
+		let mut ret = BTreeMap::new();
+		for e in &self.files { *ret.entry(e.class).or_insert(0) += 1; }
+		ret
+	}
+
+	/// Write it out as JSON.
+	pub(crate) fn write(&self, file: &Path) -> Result<(), anyhow::Error>
+	{
+		let json = serde_json::to_vec_pretty(self)?;
+		std::fs::write(file, json)?;
+		Ok(())
+	}
+}
+
+
+/// Sort out what happened to everything under the patterns (generally
+/// MergeChanges and UpdateIfUnmodified).  Returns (path, class,
+/// metadata kept), sorted by path.
+pub(crate) fn classify(mup: &ManiUpgrade, pats: &[&[Regex]])
+		-> Vec<(PathBuf, Class, bool)>
+{
+	let matches = |p: &Path| -> bool {
+		let ps = p.to_string_lossy();
+		pats.iter().any(|res| res.iter().any(|r| r.is_match(&ps)))
+	};
+
+	// Merges and skips only happen under the patterns anyway, so don't
+	// filter them; that way, changing the config later doesn't lose
+	// them.
+	let mut cls: BTreeMap<&Path, Class> = BTreeMap::new();
+	for (p, c) in &mup.merge_clean
+	{
+		let class = match c.resolved {
+			true  => Class::Resolved,
+			false => Class::Merged,
+		};
+		cls.insert(p, class);
+	}
+	for p in mup.merge_conflict.keys() { cls.insert(p, Class::Conflict); }
+	for p in mup.skipped.keys() { cls.insert(p, Class::LocalKept); }
+
+	// Anything else new is upstream's.
+	for p in mup.new.files.keys().filter(|p| matches(p))
+	{ cls.entry(p).or_insert(Class::Upstream); }
+
+	// And metadata can be kept on top of any of the above, or on its
+	// own.
+	let kept: HashSet<&Path> = mup.kept_metadata.iter()
+			.map(|k| k.path.as_path()).filter(|p| matches(p)).collect();
+	for p in &kept { cls.entry(p).or_insert(Class::MetadataOnly); }
+
+	cls.into_iter().map(|(p, c)| (p.to_path_buf(), c, kept.contains(p)))
+			.collect()
+}
+
+
+/// Build up the report, with diffs from what's in the files dir (and,
+/// for skipped files, what's on the system under basedir).
+pub(crate) fn build(mup: &ManiUpgrade, pats: &[&[Regex]], rtdirs: &RtDirs,
+		basedir: &Path, from: String, to: String)
+		-> Result<Report, anyhow::Error>
+{
+	use crate::core::merge::merge_diff;
+	use crate::util::compress::decompress_to_vec;
+	use crate::util::hash::Sha256HashBuf;
+
+	// Hashfiles might have been cleaned out from under us; that's a
+	// note, not an error.
+	let hashfile = |hb: &Sha256HashBuf| -> Result<Option<Vec<u8>>, anyhow::Error> {
+		let gzf = rtdirs.hashfile(hb);
+		match gzf.is_file() {
+			true  => Ok(Some(decompress_to_vec(&gzf)?)),
+			false => Ok(None),
+		}
+	};
+	let diff = |p: &Path, new: &[u8], ours: &[u8]| -> Option<String> {
+		if new == ours { return None; }
+		let d = merge_diff(p, new, ours);
+		Some(String::from_utf8_lossy(&d).into_owned())
+	};
+	let missing = || Some("pristine new version not in the files dir".to_string());
+
+	let mut files = Vec::new();
+	for (path, class, metadata_kept) in classify(mup, pats)
+	{
+		let (mut dif, mut note) = (None, None);
+
+		// Which versions to compare, if any
+		let pair = match class {
+			Class::Merged | Class::Resolved => {
+				let c = &mup.merge_clean[&path];
+				Some((hashfile(&c.new)?, hashfile(&c.res)?))
+			},
+			Class::Conflict => {
+				note = Some("still has conflict markers; run resolve-merges"
+						.to_string());
+				let c = &mup.merge_conflict[&path];
+				Some((hashfile(&c.new)?, hashfile(&c.res)?))
+			},
+			Class::LocalKept => {
+				let s = &mup.skipped[&path];
+				let local = crate::util::path_join(basedir, &path);
+				match std::fs::read(&local) {
+					Ok(l) => Some((hashfile(&s.new)?, Some(l))),
+					Err(e) => {
+						note = Some(format!("can't read {}: {e}",
+								local.display()));
+						None
+					},
+				}
+			},
+			Class::Upstream | Class::MetadataOnly => None,
+		};
+
+		match pair {
+			Some((Some(n), Some(o))) => dif = diff(&path, &n, &o),
+			Some(_) if note.is_none() => note = missing(),
+			_ => (),
+		}
+		let normalized = mup.merge_clean.get(&path).is_some_and(|c| c.normalized);
+		if normalized && note.is_none()
+		{
+			note = Some("merged after normalizing trailing whitespace and \
+					final newlines".to_string());
+		}
+
+		files.push(Entry { path, class, metadata_kept, diff: dif, note });
+	}
+
+	Ok(Report { from, to, installed: mup.world, files })
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+	use super::Class as C;
+
+	#[test]
+	fn classify()
+	{
+		use crate::metadata::{Metadata, MetaFile, MetaKept, MetaPerms};
+		use crate::core::merge::{Clean, Conflict, Skipped};
+		use crate::state::Manifest;
+
+		let mf = |p: &str| (PathBuf::from(p),
+				MetaFile { path: p.into(), ..Default::default() });
+		let new = Metadata { files: [
+				mf("/etc/merged.conf"), mf("/etc/fixed.conf"),
+				mf("/etc/conflict.conf"), mf("/etc/upstream.conf"),
+				mf("/bin/sh"),
+			].into_iter().collect(), ..Default::default() };
+
+		let vers = "14.2-RELEASE".parse().unwrap();
+		let mut mani = Manifest::new_upgrade(Metadata::default(), new, vers,
+				Default::default(), Default::default());
+		let mup = match &mut mani {
+			Manifest::Upgrade(u) => u,
+			_ => unreachable!(),
+		};
+
+		let clean = |resolved| Clean { old: Default::default(),
+				new: Default::default(), cur: Default::default(),
+				res: Default::default(), resolved, normalized: false };
+		mup.merge_clean.insert("/etc/merged.conf".into(), clean(false));
+		mup.merge_clean.insert("/etc/fixed.conf".into(), clean(true));
+		mup.merge_conflict.insert("/etc/conflict.conf".into(), Conflict {
+				old: Default::default(), new: Default::default(),
+				cur: Default::default(), res: Default::default() });
+		mup.skipped.insert("/etc/mine.conf".into(), Skipped {
+				cur: Default::default(), new: Default::default(),
+				security: false });
+		let mk = |p: &str| MetaKept { path: p.into(),
+				upstream: MetaPerms { uid: 0, gid: 0, mode: 0o644, flags: 0 },
+				local: MetaPerms { uid: 0, gid: 0, mode: 0o600, flags: 0 } };
+		mup.kept_metadata = vec![mk("/etc/merged.conf"),
+				mk("/etc/modes.conf"), mk("/bin/sh")];
+
+		let pats = [regex_lite::Regex::new("^/etc/").unwrap()];
+		let got = super::classify(mup, &[&pats]);
+		let want = [
+			("/etc/conflict.conf", C::Conflict,     false),
+			("/etc/fixed.conf",    C::Resolved,     false),
+			("/etc/merged.conf",   C::Merged,       true),
+			("/etc/mine.conf",     C::LocalKept,    false),
+			("/etc/modes.conf",    C::MetadataOnly, true),
+			("/etc/upstream.conf", C::Upstream,     false),
+		];
+		let want: Vec<_> = want.into_iter()
+				.map(|(p, c, m)| (PathBuf::from(p), c, m)).collect();
+		assert_eq!(got, want);
+
+		// No patterns; merges and skips still show, the rest don't.
+		let got = super::classify(mup, &[]);
+		assert_eq!(got.len(), 4, "{got:?}");
+		assert!(got.iter().all(|(_, c, _)| *c < C::Upstream), "{got:?}");
+	}
+}
@@ -27,9 +27,8 @@ pub(crate) fn patch(patches: Vec<String>, ctrl: pp::Control)
 	use crate::core::pool::Pool as _;
 
 	// OK, first, we apply all the patches.
-	let preqs: Vec<_> = patches.into_iter()
-			.map(|patch| pp::Req{patch}).collect();
-	let prlen = preqs.len();
+	let prlen = patches.len();
+	let preqs = patches.into_iter().map(|patch| pp::Req{patch});
 	println!("Trying to apply {prlen} patches.");
 	let patchres = {
 		let pp = pp::Patch::new(prlen);
@@ -45,10 +44,10 @@ pub(crate) fn patch(patches: Vec<String>, ctrl: pp::Control)
 	if oklen == 0 { return Ok(Vec::new()); }
 
 	// Check the hashes, and compress them into <filesdir> if the match.
-	let hcreqs: Vec<_> = oks.into_iter().map(|res| {
+	let hcreqs = oks.into_iter().map(|res| {
 			let path = format!("{}.gz", res.hash);
 			hcp::Req { path }
-		}).collect();
+		});
 	println!("Checking hashes.");
 	let hcres = {
 		let sp = hcp::HashCheck::new(oklen);
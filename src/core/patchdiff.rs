@@ -0,0 +1,190 @@
+//! What changed between two sets of upstream metadata.
+//!
+//! For "what did pN actually touch" questions; this is purely upstream
+//! against upstream, nothing about the local system.  The comparing is
+//! the same remove_matching_checksys() check-sys uses, so a hardlink
+//! only counts as changed if it changed itself, not just its target.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::{Metadata, MetadataGroup};
+
+
+/// A path that changed, and what components have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Changed
+{
+	pub(crate) path: PathBuf,
+	pub(crate) components: Vec<String>,
+}
+
+
+/// All the changes, each sorted by path.
+#[derive(Debug, Default)]
+pub(crate) struct PatchDiff
+{
+	/// Not there before
+	pub(crate) added: Vec<Changed>,
+
+	/// Not there after
+	pub(crate) removed: Vec<Changed>,
+
+	/// Different contents (or a different type of thing altogether)
+	pub(crate) content: Vec<Changed>,
+
+	/// Same contents, different owner/mode/flags
+	pub(crate) metadata: Vec<Changed>,
+}
+
+impl PatchDiff
+{
+	/// Nothing changed at all?
+	pub(crate) fn is_empty(&self) -> bool
+	{
+		self.added.is_empty() && self.removed.is_empty()
+				&& self.content.is_empty() && self.metadata.is_empty()
+	}
+}
+
+
+/// Compare old and new.
+pub(crate) fn diff(old: &MetadataGroup, new: &MetadataGroup) -> PatchDiff
+{
+	let oldmd: Metadata = old.clone().into_metadata();
+	let newmd: Metadata = new.clone().into_metadata();
+
+	// What's different from each side
+	let mut newdiff = newmd.clone();
+	newdiff.remove_matching_checksys(&oldmd);
+	let mut olddiff = oldmd.clone();
+	olddiff.remove_matching_checksys(&newmd);
+
+	let oldpaths = oldmd.allpaths_hashset_nodash();
+	let newpaths = newmd.allpaths_hashset_nodash();
+	let mut changed: HashSet<&Path> = newdiff.allpaths_hashset_nodash();
+	changed.extend(olddiff.allpaths_hashset_nodash());
+
+	let entry = |p: &Path, g: &MetadataGroup| {
+		let mut components: Vec<_> = g.path_components(p).into_iter()
+				.map(|c| c.to_string()).collect();
+		components.sort_unstable();
+		Changed { path: p.to_path_buf(), components }
+	};
+
+	let mut ret = PatchDiff::default();
+	for p in changed
+	{
+		match (oldpaths.contains(p), newpaths.contains(p)) {
+			(false, false) => (),
+			(false, true)  => ret.added.push(entry(p, new)),
+			(true,  false) => ret.removed.push(entry(p, old)),
+			(true,  true)  => match same_content(&oldmd, &newmd, p) {
+				true  => ret.metadata.push(entry(p, new)),
+				false => ret.content.push(entry(p, new)),
+			},
+		}
+	}
+
+	for l in [&mut ret.added, &mut ret.removed, &mut ret.content,
+			&mut ret.metadata]
+	{ l.sort_unstable_by(|a, b| a.path.cmp(&b.path)); }
+	ret
+}
+
+
+/// Is a path in both the same sort of thing with the same contents?
+/// Dirs don't have any contents, so they're always the same; a
+/// hardlink only shows up changed when it points somewhere else, which
+/// counts.
+fn same_content(old: &Metadata, new: &Metadata, p: &Path) -> bool
+{
+	if let (Some(o), Some(n)) = (old.files.get(p), new.files.get(p))
+	{ return o.sha256 == n.sha256; }
+	if let (Some(o), Some(n)) = (old.symlinks.get(p), new.symlinks.get(p))
+	{ return o.target == n.target; }
+	old.dirs.contains_key(p) && new.dirs.contains_key(p)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashMap;
+	use std::path::PathBuf;
+
+	use crate::metadata::{Metadata, MetadataGroup, MetaFile, MetaSymLink};
+	use crate::util::hash::Sha256Hash;
+
+	fn hash(s: &str) -> Sha256Hash
+	{
+		crate::util::hash::sha256_reader(&mut s.as_bytes()).unwrap()
+	}
+
+	fn file(p: &str, c: &str, mode: u32) -> (PathBuf, MetaFile)
+	{
+		(p.into(), MetaFile { path: p.into(), sha256: hash(c), mode,
+				..Default::default() })
+	}
+
+	fn group(comps: &[(&str, Metadata)]) -> MetadataGroup
+	{
+		let hm: HashMap<_, _> = comps.iter()
+				.map(|(c, m)| (c.parse().unwrap(), m.clone())).collect();
+		MetadataGroup::from_components(hm)
+	}
+
+	#[test]
+	fn diff()
+	{
+		let base = |files: Vec<(PathBuf, MetaFile)>| Metadata {
+				files: files.into_iter().collect(), ..Default::default() };
+
+		let old = group(&[
+			("world/base", base(vec![
+				file("/bin/sh", "sh1", 0o555),
+				file("/bin/ls", "ls1", 0o555),
+				file("/etc/rc", "rc1", 0o644),
+				file("/usr/bin/gone", "x", 0o555),
+			])),
+			("world/lib32", base(vec![
+				file("/usr/lib32/libc.so.7", "c1", 0o444),
+			])),
+		]);
+
+		let mut newbase = base(vec![
+			file("/bin/sh", "sh2", 0o555),
+			file("/bin/ls", "ls1", 0o555),
+			file("/etc/rc", "rc1", 0o600),
+			file("/usr/bin/new", "y", 0o555),
+		]);
+		// Used to be a file, now a symlink
+		newbase.files.remove(std::path::Path::new("/bin/ls"));
+		newbase.symlinks.insert("/bin/ls".into(), MetaSymLink {
+				path: "/bin/ls".into(), target: "/rescue/ls".into(),
+				..Default::default() });
+		// Known to be gone
+		newbase.dashes.insert("/usr/bin/gone".into());
+		let new = group(&[
+			("world/base", newbase),
+			("world/lib32", base(vec![
+				file("/usr/lib32/libc.so.7", "c2", 0o444),
+			])),
+		]);
+
+		let d = super::diff(&old, &new);
+		let paths = |l: &[super::Changed]| l.iter()
+				.map(|c| c.path.to_str().unwrap().to_string())
+				.collect::<Vec<_>>();
+		assert_eq!(paths(&d.added), ["/usr/bin/new"]);
+		assert_eq!(paths(&d.removed), ["/usr/bin/gone"]);
+		assert_eq!(paths(&d.content), ["/bin/ls", "/bin/sh",
+				"/usr/lib32/libc.so.7"]);
+		assert_eq!(paths(&d.metadata), ["/etc/rc"]);
+		assert_eq!(d.content[2].components, ["world/lib32"]);
+		assert_eq!(d.removed[0].components, ["world/base"]);
+
+		// And the same on both sides is nothing
+		assert!(super::diff(&new, &new).is_empty());
+	}
+}
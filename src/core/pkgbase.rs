@@ -0,0 +1,276 @@
+//! Noticing a basedir whose base system is managed by pkgbase.
+//!
+//! If base is installed as FreeBSD-* packages, pkg thinks it owns those
+//! files, and so do we.  Two update mechanisms fighting over the same
+//! files ends badly (pkg's checksums stop matching, the next `pkg
+//! upgrade` puts back whatever we replaced, etc), so we look before
+//! doing anything and refuse unless told otherwise.
+use std::path::{Path, PathBuf};
+
+
+/// The real pkg(8), not the /usr/sbin/pkg bootstrapper, which would
+/// offer to go install itself.
+const PKG: &str = "/usr/local/sbin/pkg";
+
+
+/// What we found
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct PkgBase
+{
+	/// Installed base packages
+	pub(crate) packages: Vec<String>,
+
+	/// A repo config that enables a base repo
+	pub(crate) repo: Option<PathBuf>,
+}
+
+impl PkgBase
+{
+	/// Are there base packages actually installed?  A repo config alone
+	/// just means somebody's thinking about it.
+	pub(crate) fn managed(&self) -> bool { !self.packages.is_empty() }
+
+	/// Explain it, a line at a time.
+	pub(crate) fn describe(&self, basedir: &Path) -> Vec<String>
+	{
+		let mut ret = Vec::new();
+		let bd = basedir.display();
+		if self.managed()
+		{
+			let n = self.packages.len();
+			let eg = self.packages.iter().take(3).map(|s| s.as_str())
+					.collect::<Vec<_>>().join(", ");
+			ret.push(format!("{bd} has {n} FreeBSD base package{} \
+					installed ({eg}{}), so it looks like pkgbase manages \
+					it.", crate::util::plural(n),
+					match n > 3 { true => ", ...", false => "" }));
+			ret.push("Updating it this way too would fight with pkg over \
+					the same files; use `pkg upgrade` instead.".to_string());
+		}
+		if let Some(r) = &self.repo
+		{
+			ret.push(format!("{} enables a FreeBSD base repo.",
+					r.display()));
+		}
+		ret
+	}
+}
+
+
+/// Pull the base packages out of `pkg query -e '%n ~ FreeBSD-*' %n`
+/// output.
+pub(crate) fn parse_query(out: &str) -> Vec<String>
+{
+	let mut ret: Vec<String> = out.lines().map(|l| l.trim())
+			.filter(|l| l.starts_with("FreeBSD-"))
+			.map(|l| l.to_string()).collect();
+	ret.sort_unstable();
+	ret.dedup();
+	ret
+}
+
+
+/// Does a pkg repo config enable a base repo?  These are UCL, but we
+/// only need to look at each `Name: { ... }` block for a base-looking
+/// name or url, and not being turned off.
+pub(crate) fn repo_is_base(conf: &str) -> bool
+{
+	// Drop comments first, so a commented-out example doesn't count
+	let conf: String = conf.lines()
+			.map(|l| l.split('#').next().unwrap_or(""))
+			.collect::<Vec<_>>().join("\n");
+
+	// ...and ${ABI} and friends, since their } isn't a block's
+	let mut plain = String::with_capacity(conf.len());
+	let mut rest = conf.as_str();
+	while let Some(i) = rest.find("${")
+	{
+		plain.push_str(&rest[..i]);
+		rest = rest[i..].find('}').map_or("", |j| &rest[i + j + 1..]);
+	}
+	plain.push_str(rest);
+
+	plain.split('}').any(|blk| {
+		let blk = blk.to_ascii_lowercase();
+		let base = blk.contains("freebsd-base") || blk.contains("/base_");
+		let off = ["no", "false", "off"].iter().any(|v| {
+			blk.contains(&format!("enabled: {v}"))
+				|| blk.contains(&format!("enabled = {v}"))
+		});
+		base && !off
+	})
+}
+
+
+/// Ask pkg what base packages are installed under basedir.  None if
+/// there's no pkg db there, or no pkg to ask.
+fn query(basedir: &Path) -> Option<String>
+{
+	if !basedir.join("var/db/pkg/local.sqlite").is_file() { return None; }
+	if !Path::new(PKG).is_file() { return None; }
+
+	let out = std::process::Command::new(PKG)
+			.arg("-r").arg(basedir)
+			.args(["query", "-e", "%n ~ FreeBSD-*", "%n"])
+			.stderr(std::process::Stdio::null())
+			.output().ok()?;
+	Some(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+
+/// Find any base repo configs under basedir.
+fn repos(basedir: &Path) -> Vec<(PathBuf, String)>
+{
+	let mut ret = Vec::new();
+	for d in ["etc/pkg", "usr/local/etc/pkg/repos"]
+	{
+		let rd = match std::fs::read_dir(basedir.join(d)) {
+			Ok(rd) => rd,
+			Err(_) => continue,
+		};
+		let mut confs: Vec<_> = rd.filter_map(|e| e.ok())
+				.map(|e| e.path())
+				.filter(|p| p.extension().map_or(false, |e| e == "conf"))
+				.collect();
+		confs.sort_unstable();
+		for p in confs
+		{
+			if let Ok(s) = std::fs::read_to_string(&p) { ret.push((p, s)); }
+		}
+	}
+	ret
+}
+
+
+/// Put together what query() and repos() found.  None if neither
+/// found anything.
+fn assess(query: Option<&str>, repos: &[(PathBuf, String)])
+		-> Option<PkgBase>
+{
+	let packages = query.map(parse_query).unwrap_or_default();
+	let repo = repos.iter().find(|(_, c)| repo_is_base(c))
+			.map(|(p, _)| p.clone());
+	match packages.is_empty() && repo.is_none() {
+		true  => None,
+		false => Some(PkgBase { packages, repo }),
+	}
+}
+
+
+/// Look at a basedir.
+pub(crate) fn detect(basedir: &Path) -> Option<PkgBase>
+{
+	assess(query(basedir).as_deref(), &repos(basedir))
+}
+
+
+/// The pre-flight check: refuse a pkgbase-managed basedir unless
+/// allowed, and mention a base repo config in any case.
+pub(crate) fn check(basedir: &Path, allow: bool) -> Result<(), anyhow::Error>
+{
+	let pb = match detect(basedir) {
+		Some(pb) => pb,
+		None => return Ok(()),
+	};
+
+	let desc = pb.describe(basedir);
+	if !pb.managed()
+	{
+		desc.iter().for_each(|l| eprintln!("WARNING: {l}"));
+		return Ok(());
+	}
+
+	desc.iter().for_each(|l| eprintln!("{l}"));
+	match allow {
+		true  => eprintln!("Going ahead anyway (--allow-pkgbase).\n"),
+		false => {
+			eprintln!("If you're really sure, rerun with --allow-pkgbase.");
+			anyhow::bail!("basedir {} is managed by pkgbase",
+					basedir.display());
+		},
+	}
+	Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+
+	#[test]
+	fn parse_query()
+	{
+		let out = "FreeBSD-runtime\nFreeBSD-utilities\n\
+				FreeBSD-clibs\nFreeBSD-runtime\n";
+		assert_eq!(super::parse_query(out), ["FreeBSD-clibs",
+				"FreeBSD-runtime", "FreeBSD-utilities"]);
+
+		// Nothing matched; or junk that isn't base
+		assert!(super::parse_query("").is_empty());
+		assert!(super::parse_query("pkg\nvim\n").is_empty());
+	}
+
+	#[test]
+	fn repo_is_base()
+	{
+		let base = r#"
+			FreeBSD-base: {
+				url: "pkg+https://pkg.FreeBSD.org/${ABI}/base_latest",
+				mirror_type: "srv",
+				enabled: yes
+			}
+		"#;
+		assert!(super::repo_is_base(base));
+
+		// The stock repo alone isn't it
+		let stock = r#"
+			FreeBSD: {
+				url: "pkg+https://pkg.FreeBSD.org/${ABI}/quarterly",
+				enabled: yes
+			}
+		"#;
+		assert!(!super::repo_is_base(stock));
+
+		// Turned off, or commented out, isn't either
+		assert!(!super::repo_is_base(&base.replace("enabled: yes",
+				"enabled: no")));
+		let commented: String = base.lines().map(|l| format!("# {l}\n"))
+				.collect();
+		assert!(!super::repo_is_base(&commented));
+
+		// Turned off stock, with base on, is
+		let both = format!("{}\n{base}",
+				stock.replace("enabled: yes", "enabled: false"));
+		assert!(super::repo_is_base(&both));
+	}
+
+	#[test]
+	fn assess()
+	{
+		let repo = |c: &str| vec![(PathBuf::from("/r/base.conf"),
+				c.to_string())];
+		let base = "FreeBSD-base: { url: \"pkg+https://x/base_latest\" }";
+
+		// Nothing at all
+		assert_eq!(super::assess(None, &[]), None);
+		assert_eq!(super::assess(Some("vim\n"), &repo("nope")), None);
+
+		// Packages installed is managed
+		let pb = super::assess(Some("FreeBSD-runtime\n"), &[]).unwrap();
+		assert!(pb.managed());
+		let desc = pb.describe("/".as_ref());
+		assert!(desc[0].contains("1 FreeBSD base package"), "{desc:?}");
+
+		// A repo without packages is only a warning
+		let pb = super::assess(Some(""), &repo(base)).unwrap();
+		assert!(!pb.managed());
+		assert_eq!(pb.repo, Some(PathBuf::from("/r/base.conf")));
+
+		// And the real thing doesn't trip on an empty dir
+		let td = tempfile::tempdir().unwrap();
+		assert_eq!(super::detect(td.path()), None);
+		super::check(td.path(), false).unwrap();
+	}
+}
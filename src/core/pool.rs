@@ -20,6 +20,13 @@ pub(crate) mod hashcheck;
 /// bspatch'ing
 pub(crate) mod patch;
 
+/// Checking hashfiles are present
+pub(crate) mod present;
+
+/// Progress bar for pools
+mod progress;
+pub(crate) use progress::Progress;
+
 
 // Settings for parallelism level.  Really, this is config/command-line
 // stuff, but quite often pool setup is a long way removed from having
@@ -36,10 +43,25 @@ static JOBS_NET: AtomicU32 = AtomicU32::new(4);
 /// technically more IO bound, but...
 static JOBS_CPU: AtomicU32 = AtomicU32::new(4);
 
+/// How many workers can be doing heavy disk writing at once, across all
+/// the pools; x-ref io_slot().  0 means we haven't been told, and go
+/// by jobs_cpu().
+static JOBS_IO: AtomicU32 = AtomicU32::new(0);
+
 /// Read the network job limit
-fn jobs_net() -> u32 { JOBS_NET.load(Ordering::Relaxed) }
+pub(crate) fn jobs_net() -> u32 { JOBS_NET.load(Ordering::Relaxed) }
 /// Read the CPU job limit
-fn jobs_cpu() -> u32 { JOBS_CPU.load(Ordering::Relaxed) }
+pub(crate) fn jobs_cpu() -> u32 { JOBS_CPU.load(Ordering::Relaxed) }
+/// Change the heavy IO limit after the fact; io-bench wants to try a few.
+pub(crate) fn set_jobs_io(n: u32) { JOBS_IO.store(n, Ordering::Relaxed) }
+/// Read the heavy IO limit
+pub(crate) fn jobs_io() -> u32
+{
+	match JOBS_IO.load(Ordering::Relaxed) {
+		0 => jobs_cpu().max(4),
+		n => n,
+	}
+}
 
 
 /// Initialize parallelism levels.  This is expected to just get called
@@ -57,7 +79,12 @@ fn jobs_cpu() -> u32 { JOBS_CPU.load(Ordering::Relaxed) }
 /// slow IO, higher values may be less useful.  The default cap of 6 is
 /// because it's probably fast enough for most uses, and you might want
 /// your system to not be swamped out by this, but hey, you do you.
-pub(crate) fn init_jobs(net: &Option<u32>, cpu: &Option<u32>)
+///
+/// The heavy IO limit defaults to the CPU one (but at least 4), so it
+/// only holds things back if it's set lower; that way -j can go up for
+/// the hashing without the writing going up with it.
+pub(crate) fn init_jobs(net: &Option<u32>, cpu: &Option<u32>,
+		io: &Option<u32>)
 {
 	let newnet = net.unwrap_or(4);
 	let newcpu = match cpu {
@@ -75,13 +102,62 @@ pub(crate) fn init_jobs(net: &Option<u32>, cpu: &Option<u32>)
 	// Guard against somebody setting 0
 	if newnet < 1 { panic!("{newnet} network threads is insane."); }
 	if newcpu < 1 { panic!("{newcpu} cpu threads is insane."); }
+	if *io == Some(0) { panic!("0 io slots is insane."); }
 
 	JOBS_NET.store(newnet, Ordering::Relaxed);
 	JOBS_CPU.store(newcpu, Ordering::Relaxed);
+	JOBS_IO.store(io.unwrap_or(0), Ordering::Relaxed);
 }
 
 
 
+/*
+ * Heavy IO slots.  Some pools (stash, hashcheck, patch) are as much
+ * writing as they are CPU, so -j workers also means -j streams of
+ * writes, and a slow disk gets thrashed.  Their workers take a slot
+ * around the bits that write a lot, so only jobs_io() are at it at
+ * once, however many threads are hashing and (de)compressing.  It's a
+ * global, not per-pool, so overlapping pools share it too.
+ */
+use std::sync::{Condvar, Mutex};
+static IO_BUSY: Mutex<u32> = Mutex::new(0);
+static IO_FREED: Condvar = Condvar::new();
+
+/// A heavy IO slot; it's given back when this drops.
+#[must_use]
+pub(crate) struct IoSlot(());
+
+impl Drop for IoSlot
+{
+	fn drop(&mut self)
+	{
+		let mut busy = IO_BUSY.lock().unwrap_or_else(|e| e.into_inner());
+		*busy -= 1;
+		IO_FREED.notify_one();
+	}
+}
+
+/// Wait for a heavy IO slot.
+pub(crate) fn io_slot() -> IoSlot
+{
+	let max = jobs_io();
+	let mut busy = IO_BUSY.lock().unwrap_or_else(|e| e.into_inner());
+	while *busy >= max
+	{
+		busy = IO_FREED.wait(busy).unwrap_or_else(|e| e.into_inner());
+	}
+	*busy += 1;
+	IoSlot(())
+}
+
+
+
+
+/// How many requests or results we let queue up, for a given number of
+/// threads.  Enough that the workers don't sit waiting on the feeding
+/// or draining, but not so many that it adds up to much.
+fn queue_len(nthr: u32) -> usize { nthr as usize * 4 }
+
 
 /// The overarching trait that implements pools.  Individual users will
 /// need to define a bunch of these types as appropriate for them, and
@@ -180,21 +256,30 @@ pub(crate) trait Pool: Sized
 	/// way for e.g. an individual worker to halt things in the middle
 	/// and return an error.  The individual impl can only control what's
 	/// in the Self::PoolResult.
-	fn run(mut self, ctrl: &Self::Control, items: Vec<Self::WorkRequest>)
+	///
+	/// The requests and results both go through bounded channels, and
+	/// requests are pulled from items as there's room for them, so only
+	/// a handful are ever in flight no matter how many there are in
+	/// total.  That does mean a slow work_result() slows everything
+	/// down, rather than letting results pile up in memory.
+	fn run<I>(mut self, ctrl: &Self::Control, items: I)
 			-> Result<Self::PoolResult, anyhow::Error>
+		where I: IntoIterator<Item = Self::WorkRequest>,
+		      I::IntoIter: Send,
 	{
 		// Spawn off a thread scope for all the fun details
 		std::thread::scope(|s|
 				-> Result<Self::PoolResult, anyhow::Error> {
 
 			// Prep channels for passing requests and results around.
+			let nthr = self.nthreads();
+			if nthr == 0 { panic!("nthreads {nthr} is insane"); }
 			use crossbeam::channel;
-			let (req_snd, req_rcv) = channel::unbounded();
-			let (res_snd, res_rcv) = channel::unbounded();
+			let cap = queue_len(nthr);
+			let (req_snd, req_rcv) = channel::bounded(cap);
+			let (res_snd, res_rcv) = channel::bounded(cap);
 
 			// Spawn off the threadpool
-			let nthr = self.nthreads();
-			if nthr == 0 { panic!("nthreads {nthr} is insane"); }
 			for _ in 1..=nthr
 			{
 				let uctrl = Self::mk_unitcontrol(&ctrl);
@@ -224,21 +309,24 @@ pub(crate) trait Pool: Sized
 			drop(res_snd);
 
 
-			// Now feed in all the work items
-			for i in items.into_iter()
-			{
-				// I'm a little unclear on why it needs 'static to send
-				// an owned value in a scoped thread...  the individual
-				// impl's were just fine with creating and sending on the
-				// fly.
-				req_snd.send(i)?;
-			}
+			// Feed in all the work items.  Since the channels are
+			// bounded, this has to happen off on its own, or we'd block
+			// on a full request channel while the workers block on a
+			// full results one that we're not draining yet.
+			let items = items.into_iter();
+			s.spawn(move || {
+				for i in items
+				{
+					// Only fails if the workers are all gone, in which
+					// case there's nobody to do anything anyway.
+					if req_snd.send(i).is_err() { break; }
+				}
 
-			// Now we've sent all the work to do, so get rid of our
-			// sending channel; that will let the workerss all silently
-			// fall out of their receive loops when there's nothing left
-			// to do.
-			drop(req_snd);
+				// Now we've sent all the work to do, so req_snd gets
+				// dropped; that will let the workers all silently fall
+				// out of their receive loops when there's nothing left
+				// to do.
+			});
 
 
 			// Now call the impl'ers function to process the results as
@@ -254,3 +342,100 @@ pub(crate) trait Pool: Sized
 		})
 	}
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	/// Do-nothing pool, keeping track of how many requests were ever
+	/// handed out but not yet come back through work_result().
+	struct Count
+	{
+		produced: Arc<AtomicUsize>,
+		seen: usize,
+		maxq: usize,
+	}
+
+	impl super::Pool for Count
+	{
+		type PoolResult = (usize, usize);
+		type Control = ();
+		type UnitControl = ();
+		fn mk_unitcontrol(_: &()) {}
+
+		type WorkRequest = usize;
+		type WorkResult  = usize;
+		type WorkErr     = ();
+		fn work(_: &(), req: usize) -> Result<usize, ()> { Ok(req) }
+
+		fn work_result(&mut self, _resp: Result<usize, ()>)
+		{
+			self.seen += 1;
+			let inflight = self.produced.load(Ordering::SeqCst) - self.seen;
+			self.maxq = self.maxq.max(inflight);
+
+			// Be a slow consumer now and then, so things back up
+			if self.seen % 1000 == 0
+			{ std::thread::sleep(std::time::Duration::from_millis(1)); }
+		}
+
+		fn finalize(self) -> (usize, usize) { (self.seen, self.maxq) }
+	}
+
+	#[test]
+	fn bounded()
+	{
+		use super::Pool as _;
+
+		const N: usize = 100_000;
+		let produced = Arc::new(AtomicUsize::new(0));
+		let count = Count { produced: produced.clone(), seen: 0, maxq: 0 };
+		let nthr = count.nthreads();
+
+		let prod = produced.clone();
+		let items = (0..N).inspect(move |_| {
+			prod.fetch_add(1, Ordering::SeqCst);
+		});
+		let (seen, maxq) = count.run(&(), items).unwrap();
+		assert_eq!(seen, N);
+
+		// Whatever's queued each way, plus one per worker and one the
+		// feeder's holding onto.
+		let bound = 2 * super::queue_len(nthr) + nthr as usize + 2;
+		assert!(maxq <= bound, "{maxq} in flight, expected <= {bound}");
+	}
+
+	#[test]
+	fn io_slots()
+	{
+		use std::sync::atomic::AtomicU32;
+		use std::time::Duration;
+
+		// Only 2 at once, however many want in
+		super::JOBS_IO.store(2, Ordering::Relaxed);
+		let (cur, max) = (AtomicU32::new(0), AtomicU32::new(0));
+		std::thread::scope(|s| {
+			for _ in 0..8
+			{
+				s.spawn(|| {
+					for _ in 0..20
+					{
+						let _slot = super::io_slot();
+						let n = cur.fetch_add(1, Ordering::SeqCst) + 1;
+						max.fetch_max(n, Ordering::SeqCst);
+						std::thread::sleep(Duration::from_micros(200));
+						cur.fetch_sub(1, Ordering::SeqCst);
+					}
+				});
+			}
+		});
+		super::JOBS_IO.store(0, Ordering::Relaxed);
+
+		let max = max.load(Ordering::SeqCst);
+		assert!((1..=2).contains(&max), "{max} at once");
+	}
+}
@@ -1,5 +1,7 @@
 //! HTTP fetch pool impl 
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use url::Url;
 
@@ -10,7 +12,7 @@ use url::Url;
 pub(crate) struct Fetch
 {
 	/// We'll kick a progress bar
-	pb: indicatif::ProgressBar,
+	pb: super::Progress,
 
 	/// We pre-seeded how many files we expected.  I'm not sure how much
 	/// we really need this...
@@ -19,6 +21,9 @@ pub(crate) struct Fetch
 	/// We'll track the files we got
 	okfiles: Vec<String>,
 
+	/// And how much they added up to
+	bytes: u64,
+
 	/// And errors we found
 	errs: Vec<GetErr>,
 }
@@ -28,9 +33,10 @@ impl Fetch
 	pub(crate) fn new(pblen: usize) -> Self
 	{
 		Self {
-			pb: indicatif::ProgressBar::new(pblen.try_into().unwrap()),
+			pb: super::Progress::new(pblen),
 			nfiles: pblen.try_into().unwrap(),
 			okfiles: Vec::with_capacity(pblen),  // Assume success
+			bytes: 0,
 			errs: Vec::new(),
 		}
 	}
@@ -47,6 +53,9 @@ pub(crate) struct PoolResult
 	/// Successful files
 	pub(crate) okfiles: Vec<String>,
 
+	/// Bytes downloaded for them
+	pub(crate) bytes: u64,
+
 	/// Errors
 	pub(crate) errs: Option<PoolErrs>,
 }
@@ -62,6 +71,27 @@ pub(crate) struct PoolErrs
 	pub(crate) errs: Vec<GetErr>,
 }
 
+impl PoolErrs
+{
+	/// The files the server says it just doesn't have.
+	pub(crate) fn not_found(&self) -> Vec<String>
+	{
+		self.errs.iter().filter_map(|e| match e {
+			GetErr::Status(u, 404) => u.path_segments()?.last()
+					.map(|f| f.to_string()),
+			_ => None,
+		}).collect()
+	}
+}
+
+/// If a fetch failed because of files the server doesn't have, which
+/// ones.
+pub(crate) fn not_found(e: &anyhow::Error) -> Vec<String>
+{
+	e.downcast_ref::<PoolErrs>().map(|pe| pe.not_found())
+			.unwrap_or_default()
+}
+
 
 
 /// Control for the fetching pool
@@ -69,13 +99,121 @@ pub(crate) struct PoolErrs
 pub(crate) struct Control
 {
 	/// HTTP agent
-	pub(crate) agent: ureq::Agent,
+	pub(crate) agent: crate::server::Client,
 
 	/// Base URL to work from
 	pub(crate) baseurl: Url,
 
 	/// Output path to dump the file into
 	pub(crate) path: PathBuf,
+
+	/// Anything shorter than this can't be what we asked for, and gets
+	/// thrown out as a GetErr::Short rather than saved.
+	pub(crate) min_size: u64,
+
+	/// Where each worker tallies up how the server's doing
+	pub(crate) stats: Stats,
+
+	/// Where to keep big downloads that got cut off, to pick up from
+	/// later; None to just start over every time.
+	pub(crate) resume: Option<Resume>,
+}
+
+/// The smallest a gzip file can be: header, an empty deflate block, and
+/// the trailer.  Everything under m/ and f/ is gzip'd, so anything
+/// smaller is a broken server (or proxy) rather than a real file.
+pub(crate) const MIN_GZ: u64 = 20;
+
+
+/// Keeping the start of big downloads that got cut off, to pick up from
+/// with a Range request instead of starting over.  They're kept under
+/// their filename (so, their hash), and what's there is how far we got.
+/// Nothing trusts them; whatever they end up as still gets its hash
+/// checked like anything else.
+#[derive(Debug, Clone)]
+pub(crate) struct Resume
+{
+	/// Where they go
+	pub(crate) dir: PathBuf,
+
+	/// Anything we got less of than this isn't worth keeping
+	pub(crate) min: u64,
+}
+
+/// Smaller files than this just get fetched over again; it's the couple
+/// hundred meg compiler and debug bits that hurt to lose at 95%.
+pub(crate) const RESUME_MIN: u64 = 8 * 1024 * 1024;
+
+/// How many times in a row we'll pick one up again before leaving it
+/// for next time.
+const RESUME_TRIES: u32 = 3;
+
+/// Leftovers that haven't been touched in this long aren't getting
+/// picked up again.
+pub(crate) const RESUME_MAX_AGE: std::time::Duration
+		= std::time::Duration::from_secs(24 * 60 * 60);
+
+impl Resume
+{
+	pub(crate) fn new(dir: PathBuf) -> Self
+	{
+		Self { dir, min: RESUME_MIN }
+	}
+
+	/// If we've got some of file kept, move it to out to carry on with,
+	/// and say how much.
+	fn take(&self, file: &str, out: &Path) -> u64
+	{
+		use std::fs;
+
+		let part = self.dir.join(file);
+		let md = match fs::symlink_metadata(&part) {
+			Ok(md) => md,
+			Err(_) => return 0,
+		};
+		if !md.is_file() || fs::rename(&part, out).is_err()
+		{
+			let _ = fs::remove_file(&part);
+			return 0;
+		}
+		md.len()
+	}
+
+	/// Put what we've got of file (in out) aside for later.  False if it
+	/// couldn't be.
+	fn keep(&self, file: &str, out: &Path) -> bool
+	{
+		use std::fs;
+		fs::create_dir_all(&self.dir).is_ok()
+				&& fs::rename(out, self.dir.join(file)).is_ok()
+	}
+
+	/// Clear out anything that's been sitting around longer than
+	/// max_age.  Returns what got removed.
+	pub(crate) fn prune(&self, max_age: std::time::Duration)
+			-> Result<Vec<PathBuf>, std::io::Error>
+	{
+		let rd = match std::fs::read_dir(&self.dir) {
+			Ok(rd) => rd,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound
+					=> return Ok(Vec::new()),
+			Err(e) => return Err(e),
+		};
+
+		let now = std::time::SystemTime::now();
+		let mut ret = Vec::new();
+		for de in rd
+		{
+			let de = de?;
+			let age = de.metadata()?.modified()
+					.map(|m| now.duration_since(m).unwrap_or_default())?;
+			if age <= max_age { continue; }
+			std::fs::remove_file(de.path())?;
+			ret.push(de.path());
+		}
+		ret.sort_unstable();
+		Ok(ret)
+	}
 }
 
 /// A single fetch request
@@ -93,8 +231,8 @@ pub(crate) struct Res
 	/// The requested file (from the request)
 	pub(crate) file: String,
 
-	// /// How many bytes we pulled down
-	// pub(crate) bytes: u64,
+	/// How many bytes we pulled down
+	pub(crate) bytes: u64,
 }
 
 /// A fetch error
@@ -106,13 +244,62 @@ pub(crate) enum GetErr
 	#[error("URL building error: {0}")]
 	Url(#[from] url::ParseError),
 
-	/// HTTP error
+	/// HTTP error.  Boxed, since ureq's is big enough to bloat every
+	/// Result carrying one of these.
 	#[error("HTTP fetch error: {0}")]
-	Http(#[from] ureq::Error),
+	Http(#[source] Box<ureq::Error>),
+
+	/// HTTP error status from the server
+	#[error("HTTP fetch error: {0}: status code {1}")]
+	Status(Url, u16),
+
+	/// The server said it was fine, but sent back an empty or
+	/// impossibly small body.  Caching proxies do this sometimes.
+	#[error("HTTP fetch error: {0}: server sent only {1} bytes")]
+	Short(Url, u64),
+
+	/// The server (or the network) stopped answering
+	#[error("HTTP fetch error: {0}: timed out")]
+	Timeout(Url),
+
+	/// The connection went away partway through the body; how much of
+	/// the file we'd got by then.
+	#[error("HTTP fetch error: {0}: connection dropped after {1} bytes")]
+	Dropped(Url, u64),
 
 	/// Filesystem IO error of some kind
 	#[error("File I/O error: {0}")]
 	Io(#[from] std::io::Error),
+
+	/// A failure played back from a recorded session (or something the
+	/// recording never got)
+	#[error("HTTP fetch error: {0} (replayed)")]
+	Replayed(String),
+}
+
+impl From<ureq::Error> for GetErr
+{
+	fn from(e: ureq::Error) -> Self { Self::Http(Box::new(e)) }
+}
+
+impl GetErr
+{
+	/// Is this the server's fault (down, broken, not answering), rather
+	/// than it just not having the file, or something on our end?
+	pub(crate) fn server_side(&self) -> bool
+	{
+		use ureq::Error as UE;
+		match self {
+			Self::Status(_, code) => *code >= 500,
+			Self::Short(..) | Self::Timeout(_) | Self::Dropped(..) => true,
+			Self::Http(e) => match **e {
+				UE::Status(code, _) => code >= 500,
+				UE::Transport(_)    => true,
+			},
+			Self::Replayed(_) => true,
+			Self::Url(_) | Self::Io(_) => false,
+		}
+	}
 }
 
 
@@ -148,12 +335,17 @@ impl crate::core::pool::Pool for Fetch
 	fn work_result(&mut self, resp: Result<Res, GetErr>)
 	{
 		// We did a thing, kick our progress
-		self.pb.inc(1);
+		self.pb.inc();
 
 		// Did it succeeed?  Then we got 1 more.  Fail?  Rack it up.
 		match resp
 		{
-			Ok(r)  => self.okfiles.push(r.file),
+			Ok(r)  => {
+				self.pb.bytes(r.bytes);
+				self.bytes += r.bytes;
+				crate::util::bytecount::downloaded(r.bytes);
+				self.okfiles.push(r.file);
+			},
 			Err(e) => self.errs.push(e),
 		}
 	}
@@ -163,7 +355,7 @@ impl crate::core::pool::Pool for Fetch
 	fn finalize(self) -> PoolResult
 	{
 		// Split ourselves up
-		let Fetch { pb, nfiles, okfiles, errs } = self;
+		let Fetch { pb, nfiles, okfiles, bytes, errs } = self;
 
 		// The progress bar is done
 		pb.finish();
@@ -175,16 +367,66 @@ impl crate::core::pool::Pool for Fetch
 		};
 
 		// And build the struct
-		let ret = PoolResult { nfiles, okfiles, errs };
+		let ret = PoolResult { nfiles, okfiles, bytes, errs };
 		ret
 	}
 }
 
 
-/// Doing a single fetch.
+/// Doing a single fetch, and noting how it went.
 fn scan_worker(ctrl: &Control, get: Req) -> Result<Res, GetErr>
+{
+	let start = std::time::Instant::now();
+	let res = scan_worker_be(ctrl, get);
+	let host = ctrl.baseurl.host_str().unwrap_or("");
+	ctrl.stats.record(host, &res, start.elapsed());
+	res
+}
+
+fn scan_worker_be(ctrl: &Control, get: Req) -> Result<Res, GetErr>
+{
+	let Req { file } = get;
+
+	// Figure the input URL and output filename
+	let inurl = ctrl.baseurl.join(&file)?;
+	let outpath = ctrl.path.join(&file);
+
+	// A big one that gets cut off gets put aside, and (if it's the
+	// server or the network's fault) picked up again from there, a few
+	// times.  If we still haven't got it, it's waiting for the next go.
+	let mut tries = 0;
+	loop
+	{
+		let from = ctrl.resume.as_ref().map_or(0, |r| r.take(&file, &outpath));
+		let (e, have) = match get_one(ctrl, &inurl, &outpath, from) {
+			Ok(bytes) => return Ok(Res { file, bytes }),
+			Err(e) => e,
+		};
+
+		// Whatever goes wrong, don't leave a partial (or bogus) file
+		// behind for somebody to trip over later.
+		let kept = match &ctrl.resume {
+			Some(r) if have >= r.min => r.keep(&file, &outpath),
+			_ => false,
+		};
+		if !kept { let _ = std::fs::remove_file(&outpath); }
+
+		tries += 1;
+		if !kept || !e.server_side() || tries >= RESUME_TRIES
+		{ return Err(e); }
+	}
+}
+
+
+/// Fetch a file into outpath, carrying on from `from` bytes into it if
+/// that's already there (and the server will do ranges).  Gives back
+/// how much we downloaded this time, or the error and how much of the
+/// file we've got.
+fn get_one(ctrl: &Control, inurl: &Url, outpath: &Path, from: u64)
+		-> Result<u64, (GetErr, u64)>
 {
 	use std::{fs, io};
+	use io::{Read as _, Write as _};
 
 	// Unlike get_bytes(), this is writing out to the filesystem, and
 	// expects bigger files than "a little text I'll parse in".
@@ -195,40 +437,318 @@ fn scan_worker(ctrl: &Control, get: Req) -> Result<Res, GetErr>
 	// probably generous?
 	const LIMIT: u64 = 1 * 1024 * 1024 * 1024;
 
-	let Req { file } = get;
-
-	// Figure the input URL and output filename
-	let inurl = ctrl.baseurl.join(&file)?;
-	let outpath = ctrl.path.join(&file);
-
-	// Open up the output file.
-	// XXX Maybe imagine tempfiles etc. someday.
-	let outfile = fs::File::create(&outpath)?;
+	let have = || fs::metadata(outpath).map_or(0, |m| m.len());
+
+	// Make the request.  A server that doesn't do ranges just sends the
+	// whole thing, so we start over; so do we if it says what we've got
+	// is already past the end, which means it's not what we thought.
+	let resp = match from {
+		0 => ctrl.agent.get(inurl).map(|r| (r, 0)),
+		n => match ctrl.agent.get_from(inurl, n) {
+			Ok((r, true))  => Ok((r, n)),
+			Ok((r, false)) => Ok((r, 0)),
+			Err(GetErr::Status(_, 416)) => ctrl.agent.get(inurl)
+					.map(|r| (r, 0)),
+			Err(e) => Err(e),
+		},
+	};
+	let (resp, from) = resp.map_err(|e| (timed_out(e, inurl), have()))?;
 
-	// HTTP responses can come in slow, so may as well wrap this...
+	// OK, it worked, take our limit and write it in.
+	let outfile = match from {
+		0 => fs::File::create(outpath),
+		_ => fs::OpenOptions::new().append(true).open(outpath),
+	};
+	let outfile = outfile.map_err(|e| (GetErr::from(e), have()))?;
 	let mut outwrite = io::BufWriter::new(outfile);
-
-	// Make the request
-	let agent = &ctrl.agent;
-	let resp = match agent.request_url("GET", &inurl).call() {
-		Ok(r) => r,
-		Err(e) => {
-			// Cleanup a bit and bail
-			fs::remove_file(&outpath)?;
-			return Err(e)?;
-		},
+	let mut rdr = resp.take(LIMIT.saturating_sub(from));
+	let mut buf = vec![0; 64 * 1024];
+	let copied = loop {
+		let n = match rdr.read(&mut buf) {
+			Ok(0) => break Ok(()),
+			Ok(n) => n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => break Err(e),
+		};
+		if let Err(e) = outwrite.write_all(&buf[..n])
+		{ return Err((GetErr::from(e), have())); }
 	};
 
-	// OK, it worked, take our limit and write it in
-	use io::Read;
-	let mut rdr = resp.into_reader().take(LIMIT);
-	let _bytes = io::copy(&mut rdr, &mut outwrite)?;
+	// Whatever we got is on disk, so a cut-off one can be picked up
+	// from where it really stopped.
+	let flushed = outwrite.flush();
+	let size = have();
+	if let Err(e) = copied
+	{
+		let e = match timed_out(GetErr::from(e), inurl) {
+			GetErr::Io(_) => GetErr::Dropped(inurl.clone(), size),
+			e => e,
+		};
+		return Err((e, size));
+	}
+	flushed.map_err(|e| (GetErr::from(e), size))?;
+	if size < ctrl.min_size
+	{ return Err((GetErr::Short(inurl.clone(), size), size)); }
 
 	// Goodie
-	let outfile = outwrite.into_inner().map_err(|e| e.into_error())?;
-	outfile.sync_all()?;
+	let outfile = outwrite.into_inner()
+			.map_err(|e| (GetErr::from(e.into_error()), size))?;
+	outfile.sync_all().map_err(|e| (GetErr::from(e), size))?;
+	Ok(size - from)
+}
+
+
+/// Turn an error into a GetErr::Timeout if that's what it was.  ureq
+/// buries it a ways down, and once we're reading the body it's just
+/// an io::Error.
+fn timed_out(e: GetErr, url: &Url) -> GetErr
+{
+	use std::io::ErrorKind as EK;
+
+	let mut src: Option<&(dyn std::error::Error + 'static)> = Some(&e);
+	while let Some(s) = src
+	{
+		if let Some(ioe) = s.downcast_ref::<std::io::Error>()
+		{
+			if matches!(ioe.kind(), EK::TimedOut | EK::WouldBlock)
+			{ return GetErr::Timeout(url.clone()); }
+		}
+		src = s.source();
+	}
+	e
+}
+
+
+/// Keeping count of the short bodies we got for each URL over a run, so
+/// one that keeps coming back broken gets called out once, instead of
+/// being fetched forever.
+#[derive(Debug, Default)]
+pub(crate) struct ShortCount(std::collections::HashMap<Url, (u32, u64)>);
+
+impl ShortCount
+{
+	/// How many times we'll take a short body for one URL before giving
+	/// up on it.
+	pub(crate) const TRIES: u32 = 3;
+
+	/// Count up the short ones in a batch of errors.
+	pub(crate) fn add(&mut self, errs: &[GetErr])
+	{
+		for e in errs
+		{
+			if let GetErr::Short(u, sz) = e
+			{
+				let ent = self.0.entry(u.clone()).or_default();
+				*ent = (ent.0 + 1, *sz);
+			}
+		}
+	}
+
+	/// A line about each URL that's been short TRIES times, if any
+	/// have.
+	pub(crate) fn given_up(&self) -> Option<String>
+	{
+		let mut lines: Vec<String> = self.0.iter()
+			.filter(|(_, (n, _))| *n >= Self::TRIES)
+			.map(|(u, (n, sz))| {
+				let what = match sz {
+					0 => "an empty body".to_string(),
+					_ => format!("only {sz} bytes"),
+				};
+				format!("Server returned {what} for {u} {n} times; \
+						possible proxy issue.")
+			}).collect();
+		lines.sort_unstable();
+		match lines.is_empty() {
+			true  => None,
+			false => Some(lines.join("\n")),
+		}
+	}
+}
+
+
+
+/// How one server did for us over a run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct HostStats
+{
+	pub(crate) host: String,
+
+	/// Files asked for, and bytes we got for them
+	pub(crate) requests: u64,
+	pub(crate) bytes: u64,
 
-	// let res = Res { file, bytes };
-	let res = Res { file };
-	Ok(res)
+	/// Requests that failed, other than for a file it just doesn't have
+	/// (looking for patches that aren't there is routine).
+	pub(crate) errors: u64,
+
+	/// Time spent on the requests all together, and per request
+	pub(crate) total_ms: u64,
+	pub(crate) mean_ms: u64,
+}
+
+impl HostStats
+{
+	fn add(&mut self, other: &HostStats)
+	{
+		self.requests += other.requests;
+		self.bytes += other.bytes;
+		self.errors += other.errors;
+		self.total_ms += other.total_ms;
+		self.mean_ms = self.total_ms.checked_div(self.requests).unwrap_or(0);
+	}
+
+	/// A line about it for humans
+	pub(crate) fn describe(&self) -> String
+	{
+		use crate::util::{human_bytes, plural};
+		let (r, e) = (self.requests, self.errors);
+		format!("{}: {r} request{}, {}, {e} error{}, {}ms average",
+				self.host, plural(r as usize), human_bytes(self.bytes),
+				plural(e as usize), self.mean_ms)
+	}
+}
+
+
+/// The tally of how each server's doing, shared by the fetch workers.
+/// It rides along on the Server, so it covers every batch of fetching,
+/// including after failing over to another one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Stats(Arc<Mutex<BTreeMap<String, HostStats>>>);
+
+impl Stats
+{
+	/// Note how a request went
+	pub(crate) fn record(&self, host: &str, res: &Result<Res, GetErr>,
+			took: std::time::Duration)
+	{
+		let (bytes, errors) = match res {
+			Ok(r) => (r.bytes, 0),
+			Err(GetErr::Status(_, 404)) => (0, 0),
+			Err(_) => (0, 1),
+		};
+		let one = HostStats { host: host.to_string(), requests: 1, bytes,
+				errors, total_ms: took.as_millis() as u64, mean_ms: 0 };
+		self.merge(&[one]);
+	}
+
+	/// Fold in somebody else's tally (e.g., the privsep worker's).
+	pub(crate) fn merge(&self, stats: &[HostStats])
+	{
+		let mut map = self.0.lock().unwrap();
+		for hs in stats
+		{
+			map.entry(hs.host.clone())
+					.or_insert_with(|| HostStats { host: hs.host.clone(),
+						..Default::default() })
+					.add(hs);
+		}
+	}
+
+	/// What it's come to so far, by host
+	pub(crate) fn snapshot(&self) -> Vec<HostStats>
+	{
+		self.0.lock().unwrap().values().cloned().collect()
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn not_found()
+	{
+		use super::{GetErr, PoolErrs};
+		let u = |f: &str| url::Url::parse("http://example.org/x/f/")
+				.unwrap().join(f).unwrap();
+		let pe = PoolErrs { errs: vec![
+			GetErr::Status(u("aaaa.gz"), 404),
+			GetErr::Status(u("bbbb.gz"), 503),
+			GetErr::Io(std::io::ErrorKind::Other.into()),
+			GetErr::Status(u("cccc.gz"), 404),
+		] };
+		assert_eq!(pe.not_found(), ["aaaa.gz", "cccc.gz"]);
+
+		// And through an anyhow, like it usually comes
+		let e: anyhow::Error = pe.into();
+		assert_eq!(super::not_found(&e), ["aaaa.gz", "cccc.gz"]);
+		assert!(super::not_found(&anyhow::anyhow!("nope")).is_empty());
+	}
+
+
+	#[test]
+	fn stats()
+	{
+		use std::time::Duration;
+		use super::{GetErr, Res, Stats};
+
+		let u = url::Url::parse("http://a.example.org/f/x.gz").unwrap();
+		let ok = |bytes| Ok(Res { file: "x.gz".to_string(), bytes });
+		let ms = Duration::from_millis;
+
+		let st = Stats::default();
+		st.record("b.example.org", &ok(1000), ms(30));
+		st.record("a.example.org", &ok(500), ms(10));
+		st.record("a.example.org", &Err(GetErr::Status(u.clone(), 404)),
+				ms(20));
+		st.record("a.example.org", &Err(GetErr::Status(u.clone(), 503)),
+				ms(30));
+		st.record("a.example.org", &Err(GetErr::Timeout(u)), ms(40));
+
+		// Sorted by host; not-found isn't an error
+		let snap = st.snapshot();
+		let got: Vec<_> = snap.iter().map(|h| (h.host.as_str(), h.requests,
+				h.bytes, h.errors, h.mean_ms)).collect();
+		assert_eq!(got, [("a.example.org", 4, 500, 2, 25),
+				("b.example.org", 1, 1000, 0, 30)]);
+		assert_eq!(snap[1].describe(), "b.example.org: 1 request, \
+				1000B, 0 errors, 30ms average");
+
+		// Clones share it, like the pool's workers do; and somebody
+		// else's gets folded in.
+		let c = st.clone();
+		c.merge(&snap[1..]);
+		let snap = st.snapshot();
+		assert_eq!((snap[1].requests, snap[1].bytes, snap[1].mean_ms),
+				(2, 2000, 30));
+	}
+
+
+	#[test]
+	fn resume_dir()
+	{
+		use std::time::Duration;
+
+		let td = tempfile::tempdir().unwrap();
+		let res = super::Resume::new(td.path().join("partial"));
+		let out = td.path().join("out");
+
+		// Nothing there yet, and no dir is nothing to prune
+		assert_eq!(res.take("abcd.gz", &out), 0);
+		assert!(res.prune(Duration::ZERO).unwrap().is_empty());
+
+		// Put aside, and back again
+		std::fs::write(&out, b"12345").unwrap();
+		assert!(res.keep("abcd.gz", &out));
+		assert!(!out.exists());
+		assert_eq!(res.take("abcd.gz", &out), 5);
+		assert_eq!(std::fs::read(&out).unwrap(), b"12345");
+		assert!(!res.dir.join("abcd.gz").exists());
+
+		// Something that isn't a plain file just gets cleaned up
+		std::os::unix::fs::symlink("/etc/passwd", res.dir.join("x.gz"))
+				.unwrap();
+		assert_eq!(res.take("x.gz", &out), 0);
+		assert!(res.dir.join("x.gz").symlink_metadata().is_err());
+
+		// Fresh ones stay, old ones go
+		assert!(res.keep("abcd.gz", &out));
+		assert!(res.prune(super::RESUME_MAX_AGE).unwrap().is_empty());
+		std::thread::sleep(Duration::from_millis(20));
+		assert_eq!(res.prune(Duration::from_millis(10)).unwrap(),
+				[res.dir.join("abcd.gz")]);
+	}
 }
@@ -7,7 +7,6 @@ use std::path::PathBuf;
 
 use crate::util::hash;
 
-use indicatif::ProgressBar;
 
 
 
@@ -16,7 +15,7 @@ use indicatif::ProgressBar;
 pub(crate) struct HashCheck
 {
 	/// We'll kick a progress bar
-	pb: ProgressBar,
+	pb: super::Progress,
 
 	/// Sucessful results
 	oks: Vec<Res>,
@@ -30,7 +29,7 @@ impl HashCheck
 	pub(crate) fn new(pblen: usize) -> Self
 	{
 		Self {
-			pb: indicatif::ProgressBar::new(pblen.try_into().unwrap()),
+			pb: super::Progress::new(pblen),
 			oks:  Vec::new(),
 			errs: Vec::new(),
 		}
@@ -93,6 +92,9 @@ pub(crate) struct Res
 	/// Yeup, this was was OK.  The output file <hash> was correct, and
 	/// <filesdir>/<hash>.gz is made.
 	pub(crate) hash: String,
+
+	/// How big that .gz is
+	pub(crate) bytes: u64,
 }
 
 /// Error in the checking
@@ -151,13 +153,16 @@ impl crate::core::pool::Pool for HashCheck
 	fn work_result(&mut self, resp: Result<Res, HashCheckErr>)
 	{
 		// Well, we did a thing, so kick our progress
-		self.pb.inc(1);
+		self.pb.inc();
 
 		// Did it succeeed?  Then accumulate up the info.  Did it fail?
 		// Accumulate up the fails.
 		match resp
 		{
-			Ok(r)  => self.oks.push(r),
+			Ok(r)  => {
+				crate::util::bytecount::fetched(r.bytes);
+				self.oks.push(r);
+			},
 			Err(e) => self.errs.push(e),
 		}
 	}
@@ -210,9 +215,11 @@ fn hashcheck_worker(ctrl: &Control, req: Req)
 	// Decompress it into the same dir; this will give us our
 	// decompressed path too.  This silently succeeds if the decompressed file
 	// is already there, which sometimes it is.
-	let decpath = compress::decompress_gz_dirs(&ctrl.tmpdir, &ctrl.tmpdir,
-			&req.path)
-			.map_err(|e| SE::Compress(e))?;
+	let decpath = {
+		let _slot = super::io_slot();
+		compress::decompress_gz_dirs(&ctrl.tmpdir, &ctrl.tmpdir, &req.path)
+				.map_err(|e| SE::Compress(e))?
+	};
 
 	// OK, well, it's there now, right?  Shouldn't be fallible...
 	if !decpath.is_file() { Err(SE::Missing(srcpath.to_path_buf()))?; }
@@ -226,11 +233,14 @@ fn hashcheck_worker(ctrl: &Control, req: Req)
 	match srcpath.is_file() {
 		true => fs::rename(&srcpath, &dstpath)?,
 		false => {
+			let _slot = super::io_slot();
 			compress::compress_gz(&decpath, &srcpath)?;
 			fs::rename(&srcpath, &dstpath)?;
 		},
 	};
 
+	let bytes = fs::metadata(&dstpath)?.len();
+
 	// And maybe remove the decompressed file.
 	if !ctrl.keep { fs::remove_file(&decpath)?; }
 
@@ -238,6 +248,6 @@ fn hashcheck_worker(ctrl: &Control, req: Req)
 	let mut hash = req.path;
 	let gz = ".gz";
 	if hash.ends_with(gz) { hash.truncate(hash.len() - gz.len()); }
-	let res = Res { hash };
+	let res = Res { hash, bytes };
 	Ok(res)
 }
@@ -13,7 +13,6 @@
 //! so it doesn't much matter if something is wrong in this step.
 use std::path::PathBuf;
 
-use indicatif::ProgressBar;
 
 
 
@@ -22,7 +21,7 @@ use indicatif::ProgressBar;
 pub(crate) struct Patch
 {
 	/// We'll kick a progress bar
-	pb: ProgressBar,
+	pb: super::Progress,
 
 	/// Sucessful results
 	oks: Vec<Res>,
@@ -36,7 +35,7 @@ impl Patch
 	pub(crate) fn new(pblen: usize) -> Self
 	{
 		Self {
-			pb: indicatif::ProgressBar::new(pblen.try_into().unwrap()),
+			pb: super::Progress::new(pblen),
 			oks:  Vec::new(),
 			errs: Vec::new(),
 		}
@@ -174,7 +173,7 @@ impl crate::core::pool::Pool for Patch
 	fn work_result(&mut self, resp: Result<Res, PatchErr>)
 	{
 		// Well, we did a thing, so kick our progress
-		self.pb.inc(1);
+		self.pb.inc();
 
 		// Did it succeeed?  Then accumulate up the info.  Did it fail?
 		// Accumulate up the fails.
@@ -234,6 +233,7 @@ fn patch_worker(ctrl: &Control, req: Req)
 	{
 		// Try decompressing it out of filesdir
 		let compfile = format!("{inhash}.gz");
+		let _slot = super::io_slot();
 		match compress::decompress_gz_dirs(&ctrl.filesdir, &ctrl.tmpdir,
 				&compfile)
 		{
@@ -270,7 +270,10 @@ fn patch_worker(ctrl: &Control, req: Req)
 	// the paths and pass them to our patcher.
 	let dstpath = path_join(&ctrl.tmpdir, outhash);
 	let patchpath = path_join(&ctrl.tmpdir, &patch);
-	bspatch::patch(&srcpath, &dstpath, &patchpath)?;
+	{
+		let _slot = super::io_slot();
+		bspatch::patch(&srcpath, &dstpath, &patchpath)?;
+	}
 
 	// Success!  Maybe cleanup, and return.
 	if !ctrl.keep { std::fs::remove_file(&srcpath)?; }
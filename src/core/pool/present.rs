@@ -0,0 +1,321 @@
+//! Hashfile presence checking pool.
+//!
+//! This is just stat'ing a lot of files, which ought to be trivial, but
+//! on a slow (e.g., NFS) workdir, doing a few tens of thousands in a row
+//! adds up.  So spread it out, and stop early once we know things are
+//! broken.
+//!
+//! Optionally, it checks their contents too, for install to know it
+//! doesn't have to again; x-ref install::bits::open_hashfile().
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::util::hash::Sha256HashBuf;
+
+
+
+/// Below this many, don't bother with a progress bar.
+const PB_MIN: usize = 2000;
+
+
+/// An impl of the threadpool for checking hashfiles are present
+#[derive(Debug)]
+pub(crate) struct Present
+{
+	/// We'll kick a progress bar
+	pb: super::Progress,
+
+	/// Missing hashes we've found
+	missing: Vec<Sha256HashBuf>,
+
+	/// Ones that are there, but don't check out
+	bad: Vec<(Sha256HashBuf, String)>,
+
+	/// And what the ones that checked out looked like
+	seen: HashMap<Sha256HashBuf, HashfileId>,
+
+	/// How many we didn't check 'cuz we already gave up
+	skipped: usize,
+
+	/// Limit on how many we report
+	limit: usize,
+}
+
+impl Present
+{
+	/// Setup, for checking pblen hashfiles.  Same limit as the Control.
+	pub(crate) fn new(pblen: usize, limit: usize) -> Self
+	{
+		let pb = match pblen >= PB_MIN {
+			true  => super::Progress::new(pblen),
+			false => super::Progress::hidden(),
+		};
+		Self { pb, missing: Vec::new(), bad: Vec::new(),
+				seen: HashMap::new(), skipped: 0, limit }
+	}
+}
+
+
+/// Enough about a hashfile to tell if it's been swapped out (or
+/// rewritten) since we looked at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HashfileId
+{
+	dev: u64,
+	ino: u64,
+	size: u64,
+	mtime: (i64, i64),
+}
+
+impl From<&std::fs::Metadata> for HashfileId
+{
+	fn from(md: &std::fs::Metadata) -> Self
+	{
+		use std::os::unix::fs::MetadataExt as _;
+		Self { dev: md.dev(), ino: md.ino(), size: md.size(),
+				mtime: (md.mtime(), md.mtime_nsec()) }
+	}
+}
+
+
+/// The final result of a check
+#[derive(Debug)]
+pub(crate) struct PoolResult
+{
+	/// The missing hashes (up to the limit), sorted
+	pub(crate) missing: Vec<Sha256HashBuf>,
+
+	/// Did we stop before checking everything?
+	pub(crate) aborted: bool,
+
+	/// With Control::verifying(), the ones whose contents don't match
+	/// their hash (up to the limit, along with missing), sorted.
+	pub(crate) bad: Vec<(Sha256HashBuf, String)>,
+
+	/// With Control::verifying(), what the ones that checked out looked
+	/// like, for install to make sure they're still the same when it gets
+	/// to them.  Just being there isn't enough to go in here.
+	pub(crate) seen: HashMap<Sha256HashBuf, HashfileId>,
+}
+
+/// Control for checking
+#[derive(Debug, Clone)]
+pub(crate) struct Control
+{
+	/// The dir the hashfiles should be in
+	pub(crate) filesdir: PathBuf,
+
+	/// How many missing before we give up
+	pub(crate) limit: usize,
+
+	/// Check the contents match the hash too
+	verify: bool,
+
+	/// How many missing we've found so far (shared across workers)
+	found: Arc<AtomicUsize>,
+}
+
+impl Control
+{
+	pub(crate) fn new(filesdir: PathBuf, limit: usize) -> Self
+	{
+		let found = Arc::new(AtomicUsize::new(0));
+		Self { filesdir, limit, verify: false, found }
+	}
+
+	/// Decompress and hash them all too.  Anything that doesn't match
+	/// counts against the limit like a missing one.
+	pub(crate) fn verifying(self) -> Self { Self { verify: true, ..self } }
+}
+
+/// Not-OK results
+#[derive(Debug)]
+pub(crate) enum PresentErr
+{
+	/// Not there
+	Missing(Sha256HashBuf),
+
+	/// There, but the contents don't match
+	Bad(Sha256HashBuf, String),
+
+	/// Didn't bother looking, we'd already found enough missing
+	Skipped,
+}
+
+
+
+/// Now connect all those bits in
+impl crate::core::pool::Pool for Present
+{
+	// Control is shared by all, and there's nothing much in it.
+	type Control = Control;
+	type UnitControl = Control;
+	fn mk_unitcontrol(c: &Control) -> Control { c.clone() }
+
+	// The final returned results
+	type PoolResult = PoolResult;
+
+	// The individual work items and their results
+	type WorkRequest = Sha256HashBuf;
+	type WorkResult  = (Sha256HashBuf, Option<HashfileId>);
+	type WorkErr     = PresentErr;
+	fn work(ctrl: &Control, req: Sha256HashBuf)
+			-> Result<(Sha256HashBuf, Option<HashfileId>), PresentErr>
+	{
+		if ctrl.found.load(Ordering::Relaxed) >= ctrl.limit
+		{ return Err(PresentErr::Skipped); }
+
+		let hf = ctrl.filesdir.join(format!("{req}.gz"));
+		let found = || ctrl.found.fetch_add(1, Ordering::Relaxed);
+		match std::fs::metadata(&hf) {
+			Ok(md) if md.is_file() => (),
+			_ => {
+				found();
+				return Err(PresentErr::Missing(req));
+			},
+		}
+		if !ctrl.verify { return Ok((req, None)); }
+
+		// The id is of the file we actually read through, not whatever
+		// the path might point at by now.
+		let check = || -> Result<HashfileId, anyhow::Error> {
+			let fh = std::fs::File::open(&hf)?;
+			let id = (&fh.metadata()?).into();
+			let mut gzd = flate2::read::GzDecoder::new(&fh);
+			crate::util::hash::check_sha256_reader(&mut gzd, req.as_ref())?;
+			Ok(id)
+		};
+		match check() {
+			Ok(id) => Ok((req, Some(id))),
+			Err(e) => {
+				found();
+				Err(PresentErr::Bad(req, e.to_string()))
+			},
+		}
+	}
+
+
+	// This is an IO job, but the CPU setting is what we use for FS
+	// stuff.
+	fn nthreads(&self) -> u32 { super::jobs_cpu() }
+
+
+	// Accumulate
+	fn work_result(&mut self,
+			resp: Result<(Sha256HashBuf, Option<HashfileId>), PresentErr>)
+	{
+		self.pb.inc();
+		match resp
+		{
+			Ok((h, Some(id))) => { self.seen.insert(h, id); },
+			Ok((_, None)) => (),
+			Err(PresentErr::Missing(h)) => self.missing.push(h),
+			Err(PresentErr::Bad(h, e)) => self.bad.push((h, e)),
+			Err(PresentErr::Skipped) => self.skipped += 1,
+		}
+	}
+
+
+	// Finalize up our in-progress tracking to our final result
+	fn finalize(self) -> PoolResult
+	{
+		let Present { pb, mut missing, mut bad, seen, skipped, limit } = self;
+		pb.finish_and_clear();
+
+		// Workers racing each other may have found a few past the limit
+		missing.sort_unstable();
+		bad.sort_unstable();
+		let aborted = skipped > 0 || missing.len() + bad.len() > limit;
+		missing.truncate(limit);
+		bad.truncate(limit - missing.len());
+
+		PoolResult { missing, aborted, bad, seen }
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::{Present, Control};
+	use crate::core::pool::Pool as _;
+	use crate::util::hash::Sha256HashBuf;
+
+	fn mk_hash(i: usize) -> Sha256HashBuf
+	{
+		let s = format!("{i}");
+		crate::util::hash::sha256_reader(&mut s.as_bytes()).unwrap().into()
+	}
+
+	#[test]
+	fn present()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let hashes: Vec<_> = (0..50).map(mk_hash).collect();
+
+		// Make all but 5 of 'em
+		for h in &hashes[5..]
+		{ std::fs::write(td.path().join(format!("{h}.gz")), b"").unwrap(); }
+
+		// Limit above the number missing finds all of 'em.
+		let ctrl = Control::new(td.path().to_path_buf(), 20);
+		let res = Present::new(hashes.len(), 20).run(&ctrl, hashes.clone())
+				.unwrap();
+		let mut expect = hashes[..5].to_vec();
+		expect.sort_unstable();
+		assert_eq!(res.missing, expect);
+		assert!(!res.aborted);
+
+		// Limit below it stops early with just that many.
+		let ctrl = Control::new(td.path().to_path_buf(), 2);
+		let res = Present::new(hashes.len(), 2).run(&ctrl, hashes.clone())
+				.unwrap();
+		assert_eq!(res.missing.len(), 2);
+		assert!(res.aborted);
+
+		// And all there is fine
+		let ctrl = Control::new(td.path().to_path_buf(), 2);
+		let res = Present::new(45, 2).run(&ctrl, hashes[5..].to_vec())
+				.unwrap();
+		assert!(res.missing.is_empty());
+		assert!(!res.aborted);
+
+		// Just being there doesn't make them checked.
+		assert!(res.seen.is_empty());
+	}
+
+	#[test]
+	fn verifying()
+	{
+		use crate::util::compress::{gz_bytes, write_hashfile};
+
+		let td = tempfile::tempdir().unwrap();
+		let hashes: Vec<Sha256HashBuf> = (0..10).map(|i| {
+			write_hashfile(td.path(), format!("{i}").as_bytes()).to_buf()
+		}).collect();
+
+		// One gets mangled, and one's missing
+		let bad = &hashes[3];
+		std::fs::write(td.path().join(format!("{bad}.gz")), gz_bytes(b"nope"))
+				.unwrap();
+		let gone = &hashes[4];
+		std::fs::remove_file(td.path().join(format!("{gone}.gz"))).unwrap();
+
+		let ctrl = Control::new(td.path().to_path_buf(), 5).verifying();
+		let res = Present::new(10, 5).run(&ctrl, hashes.clone()).unwrap();
+		assert_eq!(res.missing, [gone.clone()]);
+		assert_eq!(res.bad.len(), 1);
+		assert_eq!(&res.bad[0].0, bad);
+		assert!(!res.aborted);
+
+		// The good ones are remembered as they were
+		assert_eq!(res.seen.len(), 8);
+		assert!(!res.seen.contains_key(bad));
+		let h = &hashes[7];
+		let md = td.path().join(format!("{h}.gz")).metadata().unwrap();
+		assert_eq!(res.seen[h], (&md).into());
+	}
+}
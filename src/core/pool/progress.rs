@@ -0,0 +1,78 @@
+//! Progress bar for pools.
+//!
+//! work_result() runs on the main thread for every single item, so
+//! anything slow in it holds up draining results.  indicatif is pretty
+//! good about rate-limiting its drawing, but there's still a lock and
+//! such per inc(), so we batch those up.
+//!
+//! This is also where the status file (crate::util::status) hears about
+//! progress, for the same reasons.
+use std::time::{Duration, Instant};
+
+use indicatif::ProgressBar;
+
+
+/// Pass along to the bar every this many...
+const STEP: u64 = 64;
+
+/// ...or this often, whichever comes first, so slow work (like big
+/// fetches) still moves the bar along.
+const TICK: Duration = Duration::from_millis(100);
+
+
+/// A ProgressBar that only gets updated every so often.
+#[derive(Debug)]
+pub(crate) struct Progress
+{
+	pb: ProgressBar,
+	pending: u64,
+	last: Instant,
+}
+
+impl Progress
+{
+	pub(crate) fn new(len: usize) -> Self
+	{ Self::from_bar(ProgressBar::new(len.try_into().unwrap())) }
+
+	/// One that doesn't show anything
+	pub(crate) fn hidden() -> Self
+	{ Self::from_bar(ProgressBar::hidden()) }
+
+	fn from_bar(pb: ProgressBar) -> Self
+	{ Self { pb, pending: 0, last: Instant::now() } }
+
+
+	/// One more done
+	pub(crate) fn inc(&mut self)
+	{
+		self.pending += 1;
+		if self.pending >= STEP || self.last.elapsed() >= TICK
+		{
+			self.pb.inc(self.pending);
+			self.pending = 0;
+			self.last = Instant::now();
+			crate::util::status::items(self.pb.position(), self.pb.length());
+		}
+	}
+
+	/// Some bytes done, where we know
+	pub(crate) fn bytes(&mut self, n: u64)
+	{
+		crate::util::status::bytes(n);
+	}
+
+
+	/// All done
+	pub(crate) fn finish(self)
+	{
+		self.pb.inc(self.pending);
+		self.pb.finish();
+	}
+
+	/// All done, and get it off the screen
+	pub(crate) fn finish_and_clear(self)
+	{
+		self.pb.inc(self.pending);
+		self.pb.finish_and_clear();
+	}
+}
@@ -4,7 +4,6 @@ use std::path::PathBuf;
 use crate::util;
 use util::hash::Sha256Hash;
 
-use indicatif::ProgressBar;
 
 
 
@@ -13,7 +12,7 @@ use indicatif::ProgressBar;
 pub(crate) struct Scan
 {
 	/// We'll kick a progress bar
-	pb: ProgressBar,
+	pb: super::Progress,
 
 	/// Sucessful scan results
 	oks: Vec<Res>,
@@ -21,6 +20,12 @@ pub(crate) struct Scan
 	/// Missing files
 	missings: Vec<PathBuf>,
 
+	/// Paths we weren't allowed to look at
+	denied: Vec<PathBuf>,
+
+	/// Paths too long to look at
+	too_long: Vec<PathBuf>,
+
 	/// Other scan errors
 	errs: Vec<ScanErr>,
 }
@@ -30,10 +35,12 @@ impl Scan
 	pub(crate) fn new(pblen: usize) -> Self
 	{
 		Self {
-			pb: indicatif::ProgressBar::new(pblen.try_into().unwrap()),
+			pb: super::Progress::new(pblen),
 			oks:  Vec::new(),
 			errs: Vec::new(),
 			missings: Vec::new(),
+			denied: Vec::new(),
+			too_long: Vec::new(),
 		}
 	}
 }
@@ -49,6 +56,14 @@ pub(crate) struct PoolResult
 	/// Missing files
 	pub(crate) missing: Vec<PathBuf>,
 
+	/// Paths we couldn't lstat for lack of permission (only with
+	/// Control::unpriv)
+	pub(crate) denied: Vec<PathBuf>,
+
+	/// Paths too long (under the basedir) to even lstat.  Nothing
+	/// upstream ships could be, so these get skipped, not failed on.
+	pub(crate) too_long: Vec<PathBuf>,
+
 	/// Errors
 	pub(crate) errs: Option<PoolErrs>,
 }
@@ -75,6 +90,13 @@ pub(crate) struct Control
 	/// Whether to hash files
 	#[derivative(Default(value="true"))]
 	pub(crate) hash: bool,
+
+	/// Permission problems are expected (we're not root), so note them
+	/// rather than calling them errors.
+	pub(crate) unpriv: bool,
+
+	/// Note which files carry this extattr (AnnotateXattr)
+	pub(crate) annotate: Option<util::xattr::AttrName>,
 }
 
 /// The result of a single file scan
@@ -91,9 +113,16 @@ pub(crate) struct Res
 	/// not dirs or symlinks or the like.
 	pub(crate) sha256: Option<Sha256Hash>,
 
+	/// A file we wanted to hash, but weren't allowed to read (only with
+	/// Control::unpriv).
+	pub(crate) unreadable: bool,
+
 	/// Symlinks would have a target
 	pub(crate) symlink: Option<PathBuf>,
 
+	/// A file with Control::annotate's extattr on it
+	pub(crate) annotated: bool,
+
 	// Misc FS metadata
 	pub(crate) dev:   u64,
 	pub(crate) ino:   u64,
@@ -147,6 +176,15 @@ pub(crate) enum ScanErr
 	#[error("No such file")]
 	Nonexistent(PathBuf),
 
+	/// Not allowed to look at it.  Only comes out with Control::unpriv;
+	/// otherwise it's just another Io/Misc.
+	#[error("Permission denied")]
+	Denied(PathBuf),
+
+	/// Path (under the basedir) is too long to look at.
+	#[error("Path too long")]
+	TooLong(PathBuf),
+
 	/// Filesystem IO error of some kind
 	#[error("File I/O error: {0}")]
 	Io(#[from] std::io::Error),
@@ -164,6 +202,7 @@ impl From<util::LstatErr> for ScanErr
 		match e {
 			LE::CString(e)     => Self::Misc(format!("CString error: {e}")),
 			LE::Nonexistent(p) => Self::Nonexistent(p),
+			LE::TooLong(p)     => Self::TooLong(p),
 			LE::Lstat(_, estr) => Self::Misc(format!("lstat(2): {estr}")),
 		}
 	}
@@ -203,7 +242,7 @@ impl crate::core::pool::Pool for Scan
 	fn work_result(&mut self, resp: Result<Res, ScanErr>)
 	{
 		// Well, we did a thing, so kick our progress
-		self.pb.inc(1);
+		self.pb.inc();
 
 		// Did it succeeed?  Then accumulate up the info.  Did it fail?
 		// Assumulate up the fails.
@@ -218,6 +257,8 @@ impl crate::core::pool::Pool for Scan
 				match e
 				{
 					SE::Nonexistent(p) => self.missings.push(p),
+					SE::Denied(p) => self.denied.push(p),
+					SE::TooLong(p) => self.too_long.push(p),
 					e => self.errs.push(e),
 				}
 			},
@@ -229,7 +270,7 @@ impl crate::core::pool::Pool for Scan
 	fn finalize(self) -> PoolResult
 	{
 		// Split ourselves up
-		let Scan { pb, oks, missings, errs } = self;
+		let Scan { pb, oks, missings, denied, too_long, errs } = self;
 
 		// The progress bar is done
 		pb.finish();
@@ -242,7 +283,7 @@ impl crate::core::pool::Pool for Scan
 
 		// And build the struct
 		let missing = missings;
-		let ret = PoolResult { oks, missing, errs };
+		let ret = PoolResult { oks, missing, denied, too_long, errs };
 		ret
 	}
 }
@@ -309,10 +350,13 @@ fn scan_worker(ctrl: &Control, path: PathBuf) -> Result<Res, ScanErr>
 		// that'll mess us up.
 		let (myst, _) = util::lstat(&realpath)
 				.map_err(|e| {
-					use util::LstatErr::Nonexistent as NE;
+					use util::LstatErr as LE;
 					match e {
-						NE(_) => NE(path.clone()),
-						e => e
+						LE::Nonexistent(_) => SE::Nonexistent(path.clone()),
+						LE::TooLong(_) => SE::TooLong(path.clone()),
+						LE::Lstat(libc::EACCES, _) if ctrl.unpriv
+								=> SE::Denied(path.clone()),
+						e => e.into(),
 					}
 				})?;
 
@@ -337,24 +381,27 @@ fn scan_worker(ctrl: &Control, path: PathBuf) -> Result<Res, ScanErr>
 	// symlink, we need to see what it's pointing to.
 	let mut sha256 = None;
 	let mut symlink = None;
+	let mut unreadable = false;
+	let mut annotated = false;
 	match ftype
 	{
 		FileType::File => {
+			if let Some(an) = &ctrl.annotate
+			{ annotated = an.on(&realpath); }
+
 			if ctrl.hash
 			{
 				use crate::util::hash;
-				let fh = match hash::sha256_file(&realpath) {
-					Ok(h) => h,
-					Err(e) => {
-						use hash::Sha256ReaderErr as HE;
-						let ne = match e {
-							HE::IO(e) => e.into(),
-							x => SE::Misc(x.to_string()),
-						};
-						return Err(ne);
-					},
+				use hash::Sha256ReaderErr as HE;
+				use std::io::ErrorKind as EK;
+				match hash::sha256_file(&realpath) {
+					Ok(h) => sha256 = Some(h),
+					Err(HE::IO(e)) if ctrl.unpriv
+							&& e.kind() == EK::PermissionDenied
+							=> unreadable = true,
+					Err(HE::IO(e)) => return Err(e.into()),
+					Err(x) => return Err(SE::Misc(x.to_string())),
 				};
-				sha256 = Some(fh);
 			}
 		},
 		FileType::SymLink => {
@@ -366,7 +413,33 @@ fn scan_worker(ctrl: &Control, path: PathBuf) -> Result<Res, ScanErr>
 
 
 	// OK, if we made it here, we succeeded at stating a thing.
-	let res = Res { path, ftype, symlink, sha256,
+	let res = Res { path, ftype, symlink, sha256, unreadable, annotated,
 			dev, ino, nlink, uid, gid, mode, flags };
 	Ok(res)
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn too_long()
+	{
+		use std::path::PathBuf;
+		use super::{scan_worker, Control, ScanErr as SE};
+		use crate::util::PATH_MAX;
+
+		// Fine on its own, but not once it's under the basedir
+		let td = tempfile::tempdir().unwrap();
+		let ctrl = Control { basedir: td.path().into(), ..Default::default() };
+		let path: PathBuf = "/a".repeat(PATH_MAX / 2 - 1).into();
+		assert!(path.as_os_str().len() < PATH_MAX);
+
+		match scan_worker(&ctrl, path.clone()) {
+			Err(SE::TooLong(p)) => assert_eq!(p, path, "relative path"),
+			Err(e) => panic!("Expected TooLong, got {e}"),
+			Ok(r) => panic!("Expected TooLong, got {r:?}"),
+		}
+	}
+}
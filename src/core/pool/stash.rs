@@ -3,7 +3,6 @@ use std::path::PathBuf;
 
 use crate::util::hash;
 
-use indicatif::ProgressBar;
 
 
 
@@ -12,13 +11,13 @@ use indicatif::ProgressBar;
 pub(crate) struct Stash
 {
 	/// We'll kick a progress bar
-	pb: ProgressBar,
+	pb: super::Progress,
 
 	/// Sucessful results
 	oks: Vec<Res>,
 
-	/// Errors
-	errs: Vec<StashErr>,
+	/// Errors, with the file they were for
+	errs: Vec<(PathBuf, StashErr)>,
 }
 
 impl Stash
@@ -26,7 +25,7 @@ impl Stash
 	pub(crate) fn new(pblen: usize) -> Self
 	{
 		Self {
-			pb: indicatif::ProgressBar::new(pblen.try_into().unwrap()),
+			pb: super::Progress::new(pblen),
 			oks:  Vec::new(),
 			errs: Vec::new(),
 		}
@@ -51,8 +50,8 @@ pub(crate) struct PoolResult
 #[error("{errs:?}")]  // kinda fugly...
 pub(crate) struct PoolErrs
 {
-	/// Some number of individual errors
-	pub(crate) errs: Vec<StashErr>,
+	/// Some number of individual errors, and the files they were for
+	pub(crate) errs: Vec<(PathBuf, StashErr)>,
 }
 
 /// Control for scanning; we're under a basedir
@@ -87,6 +86,12 @@ pub(crate) struct Res
 	/// The file we knocked out
 	#[allow(dead_code)]
 	pub(crate) path: PathBuf,
+
+	/// Its hashfile was already there, so we didn't have to do anything
+	pub(crate) existed: bool,
+
+	/// How big a hashfile we wrote (0 if it existed)
+	pub(crate) bytes: u64,
 }
 
 /// Error in stashing
@@ -137,10 +142,11 @@ impl crate::core::pool::Pool for Stash
 	// The individual work items and their results
 	type WorkRequest = Req;
 	type WorkResult  = Res;
-	type WorkErr     = StashErr;
-	fn work(ctrl: &Control, req: Req) -> Result<Res, StashErr>
+	type WorkErr     = (PathBuf, StashErr);
+	fn work(ctrl: &Control, req: Req) -> Result<Res, (PathBuf, StashErr)>
 	{
-		stash_worker(ctrl, req)
+		let path = req.path.clone();
+		stash_worker(ctrl, req).map_err(|e| (path, e))
 	}
 
 
@@ -149,16 +155,19 @@ impl crate::core::pool::Pool for Stash
 
 
 	// Processing the result of a stash
-	fn work_result(&mut self, resp: Result<Res, StashErr>)
+	fn work_result(&mut self, resp: Result<Res, (PathBuf, StashErr)>)
 	{
 		// Well, we did a thing, so kick our progress
-		self.pb.inc(1);
+		self.pb.inc();
 
 		// Did it succeeed?  Then accumulate up the info.  Did it fail?
 		// Accumulate up the fails.
 		match resp
 		{
-			Ok(r)  => self.oks.push(r),
+			Ok(r)  => {
+				crate::util::bytecount::stashed(r.bytes);
+				self.oks.push(r);
+			},
 			Err(e) => self.errs.push(e),
 		}
 	}
@@ -207,19 +216,28 @@ fn stash_worker(ctrl: &Control, req: Req) -> Result<Res, StashErr>
 	let finalpath = &ctrl.filesdir.join(&hgzstr);
 
 
+	// If we already have it (say, from a run that died partway through),
+	// there's nothing to do.  It's content-addressed, so whatever's there
+	// is what we'd write anyway.
+	if finalpath.is_file()
+	{ return Ok(Res { path: req.path, existed: true, bytes: 0 }); }
+
 	// Trivial check.  Of course, this is racy too, but it's already a
 	// tiny race since the scan, and if you're messing with system files
 	// while you're running a system upgrade util, you deserve to get
 	// grumpy errors.
 	if !srcpath.is_file() { Err(SE::BadType(srcpath.to_path_buf()))?; }
 
-	// Copy it
+	// Copy it.  From here on is all writing, so take an IO slot for
+	// the rest.
+	let _slot = super::io_slot();
 	fs::copy(&srcpath, tmppath)?;
 
 	// Check the hash
 	use hash::check_sha256_file;
 	if let Err(e) = check_sha256_file(&tmppath, hstr)
 	{
+		let _ = fs::remove_file(tmppath);
 		use hash::Sha256ReaderErr as HE;
 		return Err(match e {
 			HE::Hash(exp, got) => SE::HashMismatch(exp, got),
@@ -229,14 +247,19 @@ fn stash_worker(ctrl: &Control, req: Req) -> Result<Res, StashErr>
 	}
 
 	// Compress it (still in the temp loc, just to be crash safe-ish).
+	// From the copy we checked, not the original, so what we stash is
+	// what matched the hash.  Then the copy can go; with a few GB of
+	// these, it adds up.
 	use crate::util::compress;
 	let tmpgz = ctrl.tmpdir.join(&hgzstr);
-	compress::compress_gz(&srcpath, &tmpgz)?;
+	compress::compress_gz(tmppath, &tmpgz)?;
+	fs::remove_file(tmppath)?;
 
 	// And move over to final
+	let bytes = fs::metadata(&tmpgz)?.len();
 	fs::rename(&tmpgz, &finalpath)?;
 
 	// And we're done
-	let res = Res { path: req.path };
+	let res = Res { path: req.path, existed: false, bytes };
 	Ok(res)
 }
@@ -0,0 +1,731 @@
+//! Doing the network-facing work as somebody other than root.
+//!
+//! Fetching and checking files means chewing on whatever a server (or
+//! anybody in between) hands us, and there's no call for that to happen
+//! as root.  So when we are root, the fetch and hash check that
+//! hashfetch::get() does run in a child re-exec'd as an unprivileged
+//! user, in a staging dir only it can write.  It tells us which files
+//! checked out, and we copy those into files/, checking them again on
+//! the way; nothing it hands back is taken on its word.
+//!
+//! Capsicum would be nice on top of that, but the fetching needs to
+//! resolve names and connect(), which capability mode doesn't allow
+//! without casper, so that's for later.  Metadata files
+//! (fetch_metafiles()) still come down in-process; they're few, and
+//! get checked against the signed index before anything looks inside.
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::FetchUser;
+use crate::core::pool::hashcheck as hcp;
+use crate::server::Server;
+
+
+/// Who we try under FetchUser auto, in order.
+const AUTO_USERS: &[&str] = &["_fbsdupdate", "nobody"];
+
+/// What the worker gets run as, in argv.
+const WORKER_CMD: &str = "privsep-worker";
+
+
+/// The configured FetchUser.  Unset means nobody told us, so don't
+/// separate; that's tests and such that never load a config.
+static USER: OnceLock<FetchUser> = OnceLock::new();
+
+/// Set the FetchUser, once, after loading the config.
+pub(crate) fn set_user(fu: &FetchUser) { let _ = USER.set(fu.clone()); }
+
+
+/// Who a worker runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Worker
+{
+	pub(crate) name: String,
+	pub(crate) uid: u32,
+	pub(crate) gid: u32,
+}
+
+
+/// Look a user up in the passwd db.
+fn lookup_user(name: &str) -> Option<(u32, u32)>
+{
+	uzers::get_user_by_name(name).map(|u| (u.uid(), u.primary_group_id()))
+}
+
+
+/// Decide who a worker runs as.  None means do it ourselves: we're not
+/// root, so there's nothing to drop, or we've been told not to.
+pub(crate) fn pick_user(fu: &FetchUser, euid: u32,
+		lookup: impl Fn(&str) -> Option<(u32, u32)>)
+		-> Result<Option<Worker>, anyhow::Error>
+{
+	if euid != 0 { return Ok(None); }
+
+	let found = |n: &str| lookup(n)
+			.map(|(uid, gid)| Worker { name: n.to_string(), uid, gid });
+	let w = match fu {
+		FetchUser::Off => return Ok(None),
+		FetchUser::Auto => AUTO_USERS.iter().find_map(|n| found(n)),
+		FetchUser::Named(n) => match found(n) {
+			Some(w) => Some(w),
+			None => anyhow::bail!("FetchUser {n} doesn't exist"),
+		},
+	};
+
+	match w {
+		Some(w) if w.uid == 0 => anyhow::bail!("FetchUser {} is uid 0, \
+				which wouldn't drop anything", w.name),
+		Some(w) => Ok(Some(w)),
+		None => {
+			eprintln!("WARNING: none of {} exist to fetch as, so fetching \
+					as root.", AUTO_USERS.join(", "));
+			Ok(None)
+		},
+	}
+}
+
+
+/// The worker to use for this run, if any.  Recording or replaying a
+/// session means fetching in-process, where the session is.
+pub(crate) fn worker_user() -> Result<Option<Worker>, anyhow::Error>
+{
+	if crate::core::session::active() { return Ok(None); }
+	match USER.get() {
+		Some(fu) => pick_user(fu, crate::util::euid(), lookup_user),
+		None => Ok(None),
+	}
+}
+
+
+
+/*
+ * What goes back and forth.  The job goes down the worker's stdin, and
+ * the report comes back up its stdout; stderr is left alone, so the
+ * progress bars still show up.
+ */
+
+/// What the worker is to do
+#[derive(Debug, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Job
+{
+	/// Where to get them from
+	pub(crate) baseurl: String,
+
+	/// <hash>.gz files to get
+	pub(crate) files: Vec<String>,
+
+	/// Leave the decompressed copies too (hcp::Control.keep)
+	pub(crate) keep: bool,
+
+	/// Our parallelism, since the worker doesn't get our args
+	pub(crate) jobs_net: u32,
+	pub(crate) jobs_cpu: u32,
+	pub(crate) jobs_io: u32,
+
+	/// Keep big downloads that get cut off in part/, and pick up from
+	/// whatever we've left there.
+	#[serde(default)]
+	pub(crate) resume: bool,
+}
+
+/// What the worker says happened
+#[derive(Debug, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Report
+{
+	/// Files that fetched and checked out, and are waiting in ok/
+	pub(crate) ok: Vec<String>,
+
+	/// Some of the failures were the server's fault, so another one
+	/// might do better.
+	pub(crate) server_err: bool,
+
+	/// What went wrong with the rest
+	pub(crate) errs: Vec<String>,
+
+	/// Bytes it downloaded, for our bytecount; it has its own.
+	#[serde(default)]
+	pub(crate) downloaded: u64,
+
+	/// How the server did, for the Server's tally
+	#[serde(default)]
+	pub(crate) servers: Vec<crate::core::pool::fetch::HostStats>,
+}
+
+
+/// Where the worker downloads to, and leaves what checked out; relative
+/// to the staging dir it's started in.  And where cut off downloads get
+/// handed back and forth.
+const INDIR: &str = "in";
+const OKDIR: &str = "ok";
+const PARTDIR: &str = "part";
+
+
+
+/*
+ * The parent side
+ */
+
+/// A staging dir for a worker.  The dir itself stays ours, so the worker
+/// can't swap its subdirs out for symlinks to somewhere interesting;
+/// only the in/, ok/, and part/ under it are the worker's.
+struct Stage
+{
+	dir: tempfile::TempDir,
+}
+
+impl Stage
+{
+	fn new(tmpdir: &Path, w: &Worker) -> Result<Self, anyhow::Error>
+	{
+		use std::os::unix::fs::{chown, PermissionsExt as _};
+		use std::fs::{create_dir, set_permissions, Permissions};
+
+		let dir = tempfile::Builder::new().prefix("privsep.")
+				.tempdir_in(tmpdir)?;
+		set_permissions(dir.path(), Permissions::from_mode(0o711))?;
+		for sub in [INDIR, OKDIR, PARTDIR]
+		{
+			let sd = dir.path().join(sub);
+			create_dir(&sd)?;
+			set_permissions(&sd, Permissions::from_mode(0o700))?;
+			chown(&sd, Some(w.uid), Some(w.gid))?;
+		}
+		Ok(Self { dir })
+	}
+
+	fn path(&self) -> &Path { self.dir.path() }
+}
+
+
+/// A NULL-terminated array of C strings, for exec'ing with.  Built up
+/// before the fork, since we can't go allocating after it.
+struct CStrs
+{
+	_strs: Vec<std::ffi::CString>,
+	ptrs: Vec<*const libc::c_char>,
+}
+
+// The pointers are into _strs, which we hold onto, and nothing writes
+// through them.
+unsafe impl Send for CStrs {}
+unsafe impl Sync for CStrs {}
+
+impl CStrs
+{
+	fn new(strs: impl IntoIterator<Item = Vec<u8>>)
+			-> Result<Self, std::ffi::NulError>
+	{
+		let strs = strs.into_iter().map(std::ffi::CString::new)
+				.collect::<Result<Vec<_>, _>>()?;
+		let mut ptrs: Vec<_> = strs.iter().map(|s| s.as_ptr()).collect();
+		ptrs.push(std::ptr::null());
+		Ok(Self { _strs: strs, ptrs })
+	}
+}
+
+
+/// Build the command to run prog (with args) as the worker, in dir.
+///
+/// The chdir has to happen before we give up root, since the workdir
+/// (and so the staging dir in it) generally isn't reachable by anybody
+/// else.  Which is rather the point; once it's running, relative paths
+/// get it into the staging dir, and that's all it can get at.
+///
+/// Same goes for prog itself: we may well be running out of somewhere
+/// the worker can't get to (~root/.cargo/bin, say).  So it gets opened
+/// here, while we're still root, and exec'd by fd once we're not.
+pub(crate) fn worker_command(w: &Worker, dir: &Path, prog: &Path,
+		args: &[&str]) -> Result<std::process::Command, anyhow::Error>
+{
+	use std::os::unix::process::CommandExt as _;
+	use std::os::unix::ffi::OsStrExt as _;
+	use std::os::fd::AsRawFd as _;
+	use anyhow::Context as _;
+
+	let exe = std::fs::File::open(prog)
+			.with_context(|| format!("opening {}", prog.display()))?;
+	let argv = CStrs::new(std::iter::once(prog.as_os_str().as_bytes())
+			.chain(args.iter().map(|a| a.as_bytes()))
+			.map(|a| a.to_vec()))?;
+	let envp = CStrs::new(std::env::vars_os().map(|(k, v)| {
+		let mut kv = k.as_bytes().to_vec();
+		kv.push(b'=');
+		kv.extend_from_slice(v.as_bytes());
+		kv
+	}))?;
+	let cdir = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+	let (uid, gid) = (w.uid, w.gid);
+
+	let mut cmd = std::process::Command::new(prog);
+	cmd.args(args);
+	// Only async-signal-safe stuff in here; we're between fork and exec.
+	// fexecve() doesn't come back unless it failed, so std never gets
+	// to its own exec.
+	unsafe {
+		cmd.pre_exec(move || {
+			// The whole CStrs, not just the pointers, has to come along.
+			let (argv, envp) = (&argv, &envp);
+			let err = || Err(std::io::Error::last_os_error());
+			if libc::chdir(cdir.as_ptr()) != 0 { return err(); }
+			if libc::setgroups(1, &gid) != 0 { return err(); }
+			if libc::setgid(gid) != 0 { return err(); }
+			if libc::setuid(uid) != 0 { return err(); }
+			// Paranoia; if we could get root back, we didn't drop it.
+			if uid != 0 && libc::setuid(0) == 0
+			{
+				return Err(std::io::Error::new(
+						std::io::ErrorKind::PermissionDenied,
+						"privileges not dropped"));
+			}
+			libc::fexecve(exe.as_raw_fd(), argv.ptrs.as_ptr(),
+					envp.ptrs.as_ptr());
+			err()
+		});
+	}
+	Ok(cmd)
+}
+
+
+/// Run a job through a worker in a staging dir.
+fn run_job(w: &Worker, stage: &Stage, job: &Job)
+		-> Result<Report, anyhow::Error>
+{
+	use std::process::Stdio;
+
+	use anyhow::Context as _;
+
+	let me = std::env::current_exe()?;
+	let mut cmd = worker_command(w, stage.path(), &me, &[WORKER_CMD])?;
+	let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped())
+			.spawn()
+			.with_context(|| format!("Couldn't start the fetch worker \
+					as {}", w.name))?;
+
+	// Dropping stdin after is what tells it that's all.
+	if let Some(stdin) = child.stdin.take()
+	{ serde_json::to_writer(stdin, job)?; }
+
+	let out = child.wait_with_output()?;
+	if !out.status.success()
+	{
+		anyhow::bail!("Fetch worker (as {}) failed: {}", w.name, out.status);
+	}
+	Ok(serde_json::from_slice(&out.stdout)?)
+}
+
+
+/// What something the worker left us should check out as.
+#[derive(Debug, Clone, Copy)]
+enum Expect<'a>
+{
+	/// A <hash>.gz, that decompresses to something with that hash
+	Gz(&'a str),
+
+	/// The decompressed file itself
+	Plain(&'a str),
+
+	/// A cut off download; there's nothing to check it against yet, but
+	/// it gets checked with the rest once it's done.
+	Partial,
+}
+
+
+/// Copy a file the worker left us into dst, checking it on the way.
+///
+/// The worker's file itself never comes in.  It could have kept an fd
+/// open on it to rewrite it after we'd looked, or made it a hardlink to
+/// something (/etc/master.passwd, say) we'd really rather not chmod.
+/// So it has to be a plain file of the worker's, opened without
+/// following symlinks, and what we check is our own root-owned copy,
+/// that nothing else can touch, before it goes into place.
+fn adopt(src: &Path, dst: &Path, w: &Worker, expect: Expect)
+		-> Result<(), anyhow::Error>
+{
+	use std::os::unix::fs::{fchown, MetadataExt as _, OpenOptionsExt as _,
+			PermissionsExt as _};
+	use std::io::{Seek as _, SeekFrom};
+	use std::fs;
+	use crate::util::hash::check_sha256_reader;
+
+	let odd = || anyhow::anyhow!("Fetch worker left something odd as {}",
+			src.display());
+	let mut sfh = fs::OpenOptions::new().read(true)
+			.custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+			.open(src).map_err(|_| odd())?;
+	let md = sfh.metadata()?;
+	let ok = md.file_type().is_file() && md.nlink() == 1 && md.uid() == w.uid;
+	if !ok { return Err(odd()); }
+
+	let dir = dst.parent().unwrap_or(Path::new("."));
+	let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+	std::io::copy(&mut sfh, &mut tmp)?;
+	drop(sfh);
+
+	let fh = tmp.as_file_mut();
+	fh.seek(SeekFrom::Start(0))?;
+	let checked = match expect {
+		Expect::Gz(h) => check_sha256_reader(
+				&mut flate2::read::GzDecoder::new(&*fh), h).is_ok(),
+		Expect::Plain(h) => check_sha256_reader(fh, h).is_ok(),
+		Expect::Partial => true,
+	};
+	if !checked
+	{
+		anyhow::bail!("{} from the fetch worker doesn't check out",
+				src.display());
+	}
+
+	fchown(&*fh, Some(crate::util::euid()), Some(0))?;
+	fh.set_permissions(fs::Permissions::from_mode(0o644))?;
+	tmp.persist(dst)?;
+	Ok(())
+}
+
+
+/// Take in whatever the worker says came out OK.  Only names we asked
+/// for count; anything else it claims is ignored.  Returns the fnames
+/// we got.
+fn adopt_all(stage: &Path, rep: &Report, todo: &[String],
+		ctrl: &hcp::Control, w: &Worker) -> Result<Vec<String>, anyhow::Error>
+{
+	use std::collections::HashSet;
+
+	let want: HashSet<&str> = todo.iter().map(|f| f.as_str()).collect();
+	let mut got = Vec::with_capacity(rep.ok.len());
+	for hash in &rep.ok
+	{
+		let fname = format!("{hash}.gz");
+		if !want.contains(fname.as_str()) { continue; }
+
+		let dst = ctrl.filesdir.join(&fname);
+		adopt(&stage.join(OKDIR).join(&fname), &dst, w, Expect::Gz(hash))?;
+		crate::util::bytecount::fetched(std::fs::metadata(&dst)?.len());
+		if ctrl.keep
+		{
+			adopt(&stage.join(INDIR).join(hash), &ctrl.tmpdir.join(hash),
+					w, Expect::Plain(hash))?;
+		}
+		got.push(fname);
+	}
+	Ok(got)
+}
+
+
+/// Hand the worker what we've kept of cut off downloads we're about to
+/// ask it for, to pick up from.  Whatever it can't have, it'll just
+/// fetch over.
+fn lend_partials(keep: &Path, stage: &Path, todo: &[String], w: &Worker)
+{
+	use std::os::unix::fs::chown;
+	use std::fs;
+
+	for f in todo
+	{
+		let src = keep.join(f);
+		let plain = fs::symlink_metadata(&src).is_ok_and(|m| m.is_file());
+		if !plain { continue; }
+		let dst = stage.join(PARTDIR).join(f);
+		if fs::rename(&src, &dst).is_err() { continue; }
+		if chown(&dst, Some(w.uid), Some(w.gid)).is_err()
+		{ let _ = fs::remove_file(&dst); }
+	}
+}
+
+
+/// And take back whatever the worker's left there of the ones it still
+/// didn't get, for next time.  They come in by way of adopt() like
+/// anything else, though there's no checking them till they're whole.
+fn reclaim_partials(stage: &Path, keep: &Path, todo: &[String], w: &Worker)
+{
+	for f in todo
+	{
+		let src = stage.join(PARTDIR).join(f);
+		if std::fs::symlink_metadata(&src).is_err() { continue; }
+		if std::fs::create_dir_all(keep).is_err() { return; }
+		let _ = adopt(&src, &keep.join(f), w, Expect::Partial);
+	}
+}
+
+
+/// hashfetch::get(), by way of a worker.  Failing over is still ours to
+/// do, since we have the Server; the worker only gets a URL.
+pub(crate) fn get(srv: &mut Server, fnames: Vec<String>,
+		ctrl: &hcp::Control, w: &Worker) -> Result<(), anyhow::Error>
+{
+	use crate::core::pool;
+
+	let mut todo = fnames;
+	loop
+	{
+		let stage = Stage::new(&ctrl.tmpdir, w)?;
+		let keep = srv.resume().map(|r| r.dir.clone());
+		if let Some(k) = &keep { lend_partials(k, stage.path(), &todo, w); }
+		let job = Job {
+			baseurl: srv.files_url()?.to_string(),
+			files: todo.clone(),
+			keep: ctrl.keep,
+			jobs_net: pool::jobs_net(),
+			jobs_cpu: pool::jobs_cpu(),
+			jobs_io: pool::jobs_io(),
+			resume: keep.is_some(),
+		};
+		let rep = run_job(w, &stage, &job)?;
+		crate::util::bytecount::downloaded(rep.downloaded);
+		srv.add_stats(&rep.servers);
+
+		let got = adopt_all(stage.path(), &rep, &todo, ctrl, w)?;
+		todo.retain(|f| !got.contains(f));
+		if let Some(k) = &keep { reclaim_partials(stage.path(), k, &todo, w); }
+		if todo.is_empty() { return Ok(()); }
+
+		if rep.server_err && srv.failover() { continue; }
+		for e in &rep.errs { eprintln!("  {e}"); }
+		anyhow::bail!("Couldn't fetch and check {} files.", todo.len());
+	}
+}
+
+
+
+/*
+ * The worker side
+ */
+
+/// Run as the worker: read the job, do it, say how it went.  We're
+/// already unprivileged and in the staging dir when we get here.
+pub(crate) fn worker_main() -> Result<(), anyhow::Error>
+{
+	if crate::util::euid() == 0
+	{ anyhow::bail!("The fetch worker shouldn't be running as root."); }
+
+	let job: Job = serde_json::from_reader(std::io::stdin().lock())?;
+	crate::core::pool::init_jobs(&Some(job.jobs_net), &Some(job.jobs_cpu),
+			&Some(job.jobs_io));
+
+	let rep = do_job(&job);
+	serde_json::to_writer(std::io::stdout().lock(), &rep)?;
+	Ok(())
+}
+
+
+/// Fetch and check.  Everything goes in the report, errors included.
+fn do_job(job: &Job) -> Report
+{
+	let mut rep = Report::default();
+
+	let baseurl = match url::Url::parse(&job.baseurl) {
+		Ok(u) => u,
+		Err(e) => { rep.errs.push(format!("Bad URL: {e}")); return rep; },
+	};
+
+	use crate::core::pool::fetch::{Resume, Stats};
+	let stats = Stats::default();
+	let resume = job.resume.then(|| Resume::new(PARTDIR.into()));
+	let fres = crate::server::fetch_files_url(baseurl, job.files.clone(),
+			PathBuf::from(INDIR), &stats, resume);
+	rep.servers = stats.snapshot();
+	let fetched = match fres {
+		Ok(f) => f,
+		Err(e) => { rep.errs.push(e.to_string()); return rep; },
+	};
+	rep.downloaded = fetched.bytes;
+	if let Some(errs) = fetched.errs
+	{
+		rep.server_err = errs.errs.iter().any(|e| e.server_side());
+		rep.errs.extend(errs.errs.iter().map(|e| e.to_string()));
+	}
+
+	let ctrl = hcp::Control { tmpdir: INDIR.into(), filesdir: OKDIR.into(),
+			keep: job.keep };
+	let reqs = fetched.okfiles.iter().map(|f| hcp::Req { path: f.clone() });
+	let hcres = {
+		use crate::core::pool::Pool as _;
+		let sp = hcp::HashCheck::new(fetched.okfiles.len());
+		sp.run(&ctrl, reqs)
+	};
+	match hcres {
+		Ok(r) => {
+			rep.ok = r.oks.into_iter().map(|r| r.hash).collect();
+			if let Some(errs) = r.errs
+			{ rep.errs.extend(errs.errs.iter().map(|e| e.to_string())); }
+		},
+		Err(e) => rep.errs.push(e.to_string()),
+	}
+
+	rep
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use crate::config::FetchUser as FU;
+	use super::Worker;
+
+	fn users(n: &str) -> Option<(u32, u32)>
+	{
+		match n {
+			"nobody" => Some((65534, 65534)),
+			"_update" => Some((789, 789)),
+			"toor" => Some((0, 0)),
+			_ => None,
+		}
+	}
+
+	#[test]
+	fn pick_user()
+	{
+		let pick = |fu: &FU, euid| super::pick_user(fu, euid, users);
+		let w = |n: &str, id| Some(Worker { name: n.into(), uid: id, gid: id });
+
+		// Not root, nothing to drop
+		assert_eq!(pick(&FU::Auto, 1001).unwrap(), None);
+
+		// Auto falls back to nobody without _fbsdupdate
+		assert_eq!(pick(&FU::Auto, 0).unwrap(), w("nobody", 65534));
+		assert_eq!(pick(&FU::Off, 0).unwrap(), None);
+		assert_eq!(pick(&FU::Named("_update".into()), 0).unwrap(),
+				w("_update", 789));
+
+		// Asking for somebody who isn't there, or is root anyway
+		pick(&FU::Named("_nope".into()), 0).expect_err("no such user");
+		pick(&FU::Named("toor".into()), 0).expect_err("uid 0");
+	}
+
+	#[test]
+	fn protocol()
+	{
+		let job = super::Job { baseurl: "http://x/f/".into(),
+				files: vec!["ab.gz".into()], keep: true,
+				jobs_net: 4, jobs_cpu: 2, jobs_io: 4, resume: true };
+		let js = serde_json::to_string(&job).unwrap();
+		assert_eq!(serde_json::from_str::<super::Job>(&js).unwrap(), job);
+
+		// A URL that won't parse comes back as an error, not a panic
+		let job = super::Job { baseurl: "not a url".into(), ..job };
+		let rep = super::do_job(&job);
+		assert!(rep.ok.is_empty());
+		assert!(rep.errs[0].contains("Bad URL"), "{rep:?}");
+	}
+
+	#[test]
+	fn adopt_all()
+	{
+		use std::fs;
+		use std::os::unix::fs::MetadataExt as _;
+		use super::OKDIR as OK;
+		use crate::util::compress::{gz_bytes as gzip, write_hashfile};
+
+		let td = tempfile::tempdir().unwrap();
+		let (stage, filesdir) = (td.path().join("stage"), td.path().join("f"));
+		for d in ["stage/ok", "stage/in", "f"]
+		{ fs::create_dir_all(td.path().join(d)).unwrap(); }
+		let me = fs::metadata(td.path()).unwrap();
+		let w = Worker { name: "me".into(), uid: me.uid(), gid: me.gid() };
+
+		let ctrl = super::hcp::Control { tmpdir: td.path().into(),
+				filesdir: filesdir.clone(), keep: false };
+
+		// Real <hash>.gz's, as the worker would leave them
+		let put = |f: &str, data: &[u8]| fs::write(stage.join(OK).join(f),
+				data).unwrap();
+		let (ha, hb) = (write_hashfile(&stage.join(OK), b"a").to_string(),
+				write_hashfile(&stage.join(OK), b"b").to_string());
+		let (fa, fb) = (format!("{ha}.gz"), format!("{hb}.gz"));
+		let rep = super::Report { ok: vec![ha, hb], ..Default::default() };
+
+		// Only what we asked for gets taken
+		if me.uid() == 0
+		{
+			// chown()'ing to root only works if we are
+			let todo = vec![fa.clone()];
+			let got = super::adopt_all(&stage, &rep, &todo, &ctrl, &w).unwrap();
+			assert_eq!(got, [fa.clone()]);
+			assert!(filesdir.join(&fa).is_file());
+			assert!(!filesdir.join(&fb).exists());
+		}
+		let todo = vec![fb.clone()];
+
+		// Something that isn't what it says it is doesn't make it in
+		put(&fb, b"not even gzip");
+		super::adopt_all(&stage, &rep, &todo, &ctrl, &w)
+				.expect_err("not gzip");
+		put(&fb, &gzip(b"not b"));
+		super::adopt_all(&stage, &rep, &todo, &ctrl, &w)
+				.expect_err("wrong hash");
+		assert!(!filesdir.join(&fb).exists());
+
+		// A hardlink to something else doesn't make it through
+		let other = td.path().join("precious");
+		fs::write(&other, b"secret").unwrap();
+		fs::remove_file(stage.join(OK).join(&fb)).unwrap();
+		fs::hard_link(&other, stage.join(OK).join(&fb)).unwrap();
+		super::adopt_all(&stage, &rep, &todo, &ctrl, &w)
+				.expect_err("hardlink");
+		assert!(!filesdir.join(&fb).exists());
+		assert_eq!(fs::read(&other).unwrap(), b"secret");
+
+		// Nor does a symlink
+		fs::remove_file(stage.join(OK).join(&fb)).unwrap();
+		std::os::unix::fs::symlink(&other, stage.join(OK).join(&fb)).unwrap();
+		super::adopt_all(&stage, &rep, &todo, &ctrl, &w)
+				.expect_err("symlink");
+		assert!(!filesdir.join(&fb).exists());
+
+		// And nothing's left lying around from any of that
+		let left: Vec<_> = fs::read_dir(&filesdir).unwrap()
+				.map(|de| de.unwrap().file_name()).collect();
+		assert!(left.len() <= 1, "{left:?}");
+	}
+
+	#[test]
+	fn sandboxed()
+	{
+		use std::fs;
+		use std::os::unix::fs::PermissionsExt as _;
+		use std::path::Path;
+
+		// Only root can drop to somebody else
+		if crate::util::euid() != 0 { return; }
+		let w = match super::pick_user(&FU::Auto, 0, super::lookup_user) {
+			Ok(Some(w)) => w,
+			_ => return,
+		};
+
+		// A root-only workdir, with a secret and a staging dir in it
+		let td = tempfile::tempdir().unwrap();
+		fs::set_permissions(td.path(), fs::Permissions::from_mode(0o700))
+				.unwrap();
+		let secret = td.path().join("secret");
+		fs::write(&secret, b"secret").unwrap();
+		let stage = super::Stage::new(td.path(), &w).unwrap();
+
+		let sh = |script: &str| {
+			super::worker_command(&w, stage.path(), Path::new("/bin/sh"),
+					&["-c", script]).unwrap().status().unwrap()
+		};
+
+		// It can work in its own dirs...
+		assert!(sh("echo hi > in/x && mv in/x ok/x").success());
+		assert!(stage.path().join("ok/x").is_file());
+
+		// ...but not get at anything else, in the workdir or out
+		let cat = format!("cat {}", secret.display());
+		assert!(!sh(&cat).success(), "read the secret");
+		assert!(!sh("cat ../secret").success(), "read the secret relatively");
+		assert!(!sh("touch ../x").success(), "wrote the stage dir");
+		assert!(!sh("rm -rf ok && ln -s / ok").success(), "replaced ok/");
+		assert!(!sh("cat /etc/master.passwd").success(), "read master.passwd");
+		assert!(!sh("id -u | grep -qx 0").success(), "still root");
+
+		// It can still run a program it couldn't get to by path, since
+		// that gets opened before it drops root.
+		let hidden = td.path().join("sh");
+		fs::copy("/bin/sh", &hidden).unwrap();
+		let st = super::worker_command(&w, stage.path(), &hidden,
+				&["-c", "true"]).unwrap().status().unwrap();
+		assert!(st.success(), "couldn't run {}", hidden.display());
+	}
+}
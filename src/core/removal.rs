@@ -0,0 +1,157 @@
+//! Sanity checking what we're about to remove.
+//!
+//! A truncated INDEX still hashes right if it was truncated before it
+//! was signed, and then everything it's missing looks like it went away
+//! in the new release.  We can't tell that from the signature, but we
+//! can notice when the result is implausible.
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+
+
+/// Dirs that never legitimately end up empty.
+const ESSENTIAL_DIRS: &[&str] = &["/bin", "/sbin", "/lib", "/libexec",
+		"/usr/bin", "/usr/sbin", "/usr/lib", "/usr/libexec"];
+
+/// Don't bother with the percentage below this many removals; small
+/// trees (or tiny fetches) can easily have a big fraction go away.
+const MIN_REMOVALS: usize = 50;
+
+
+/// An implausibly large removal.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct MassRemoval
+{
+	/// How many we'd remove
+	pub(crate) removing: usize,
+
+	/// Out of how many currently tracked
+	pub(crate) tracked: usize,
+
+	/// Too big a fraction of tracked
+	pub(crate) over_pct: bool,
+
+	/// Essential dirs that would end up with nothing in them
+	pub(crate) emptied: Vec<PathBuf>,
+}
+
+
+/// Check the removals implied by going from cur to new.  None if it all
+/// looks plausible.
+pub(crate) fn check(cur: &Metadata, new: &Metadata, max_pct: u8)
+		-> Option<MassRemoval>
+{
+	let curpaths = cur.allpaths_hashset_nodash();
+	let newpaths = new.allpaths_hashset_nodash();
+	let tracked = curpaths.len();
+	let removing = curpaths.difference(&newpaths).count();
+
+	let over_pct = removing >= MIN_REMOVALS
+			&& removing * 100 > tracked * max_pct as usize;
+
+	let under = |ps: &std::collections::HashSet<&Path>, d: &Path| {
+		ps.iter().any(|p| p != &d && p.starts_with(d))
+	};
+	let emptied: Vec<PathBuf> = ESSENTIAL_DIRS.iter().map(Path::new)
+			.filter(|d| under(&curpaths, d) && !under(&newpaths, d))
+			.map(|d| d.to_path_buf()).collect();
+
+	match over_pct || !emptied.is_empty() {
+		true  => Some(MassRemoval { removing, tracked, over_pct, emptied }),
+		false => None,
+	}
+}
+
+
+impl MassRemoval
+{
+	/// Explain it, a line at a time.
+	pub(crate) fn describe(&self, max_pct: u8) -> Vec<String>
+	{
+		let MassRemoval { removing, tracked, .. } = self;
+		let pct = removing * 100 / (*tracked).max(1);
+		let mut ret = vec![format!("This would remove {removing} of the \
+				{tracked} paths currently tracked ({pct}%).")];
+		if self.over_pct
+		{
+			ret.push(format!("That's more than MaxRemovalPercent \
+					({max_pct}%)."));
+		}
+		for d in &self.emptied
+		{ ret.push(format!("It would leave nothing in {}.", d.display())); }
+		ret.push("This usually means the metadata (INDEX) was truncated, \
+				upstream or by a mirror,".to_string());
+		ret.push("not that the release really removes all that.  Try again \
+				later, or another server.".to_string());
+		ret
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use crate::metadata::{Metadata, MetaFile};
+	use std::path::PathBuf;
+
+	fn md(paths: &[String]) -> Metadata
+	{
+		let files = paths.iter().map(|p| (PathBuf::from(p),
+				MetaFile { path: p.into(), ..Default::default() })).collect();
+		Metadata { files, ..Default::default() }
+	}
+
+	/// A fake system: 100 things in each of a few dirs
+	fn system() -> Vec<String>
+	{
+		let mut ret = Vec::new();
+		for d in ["/bin", "/lib", "/usr/bin", "/usr/share/misc"]
+		{ ret.extend((0..100).map(|i| format!("{d}/f{i}"))); }
+		ret
+	}
+
+	#[test]
+	fn plausible()
+	{
+		// A normal update: a few things go, a few come
+		let cur = system();
+		let mut new = cur.clone();
+		new.truncate(390);
+		new.push("/usr/bin/newthing".to_string());
+		assert_eq!(super::check(&md(&cur), &md(&new), 20), None);
+
+		// A small tree losing most of itself is under MIN_REMOVALS
+		let cur: Vec<_> = (0..20).map(|i| format!("/usr/share/x/{i}"))
+				.collect();
+		assert_eq!(super::check(&md(&cur), &md(&cur[..2]), 20), None);
+	}
+
+	#[test]
+	fn truncated()
+	{
+		// INDEX cut off halfway; everything past it "goes away"
+		let cur = system();
+		let new = &cur[..200];
+		let mr = super::check(&md(&cur), &md(new), 20).unwrap();
+		assert_eq!(mr.removing, 200);
+		assert_eq!(mr.tracked, 400);
+		assert!(mr.over_pct);
+		assert_eq!(mr.emptied, [PathBuf::from("/usr/bin")]);
+		let desc = mr.describe(20);
+		assert!(desc[0].contains("(50%)"), "{desc:?}");
+
+		// A higher threshold lets the fraction go, but not emptying
+		// /usr/bin.
+		let mr = super::check(&md(&cur), &md(new), 60).unwrap();
+		assert!(!mr.over_pct);
+		assert_eq!(mr.emptied, [PathBuf::from("/usr/bin")]);
+
+		// Just /lib going missing, under the threshold; still caught
+		let new: Vec<_> = cur.iter().filter(|p| !p.starts_with("/lib/"))
+				.cloned().collect();
+		let mr = super::check(&md(&cur), &md(&new), 30).unwrap();
+		assert!(!mr.over_pct);
+		assert_eq!(mr.emptied, [PathBuf::from("/lib")]);
+	}
+}
@@ -0,0 +1,271 @@
+//! Sorting changed paths by how much they matter.
+//!
+//! "Update 4000 files" doesn't tell a reviewer much.  Knowing which of
+//! those are the kernel, setuid binaries, libs everything links against,
+//! etc. is a lot more useful for deciding how hard to look.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+
+
+/// How many setuid/kernel paths we'll name outright before just giving
+/// the count.
+pub(crate) const LIST_MAX: usize = 10;
+
+
+/// The buckets, highest-risk first.  A path lands in the first one it
+/// fits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) enum Risk
+{
+	/// Anything under /boot
+	Kernel,
+
+	/// Files with the setuid or setgid bit
+	Setuid,
+
+	/// Shared libraries
+	SharedLib,
+
+	/// /etc/rc*
+	RcScript,
+
+	/// /usr/sbin and /usr/libexec
+	Daemon,
+
+	/// Everything else
+	Other,
+}
+
+impl Risk
+{
+	pub(crate) const ALL: &[Risk] = &[Risk::Kernel, Risk::Setuid,
+			Risk::SharedLib, Risk::RcScript, Risk::Daemon, Risk::Other];
+
+	/// What to call it when printing
+	pub(crate) fn desc(&self) -> &'static str
+	{
+		match self {
+			Self::Kernel    => "kernel",
+			Self::Setuid    => "setuid/setgid",
+			Self::SharedLib => "shared libraries",
+			Self::RcScript  => "rc/init scripts",
+			Self::Daemon    => "daemons",
+			Self::Other     => "other",
+		}
+	}
+}
+
+
+/// The changed paths, bucketed.
+#[derive(Debug, Default)]
+pub(crate) struct Risks
+{
+	pub(crate) buckets: BTreeMap<Risk, Vec<PathBuf>>,
+}
+
+
+/// The short form of Risks, to keep in the ManifestBrief.  The setuid
+/// and kernel paths are only kept when there are few enough to list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct RiskSummary
+{
+	/// Non-empty buckets, in Risk order
+	pub(crate) counts: Vec<(Risk, usize)>,
+
+	#[serde(default)]
+	pub(crate) setuid: Vec<PathBuf>,
+
+	#[serde(default)]
+	pub(crate) kernel: Vec<PathBuf>,
+}
+
+
+/// Which bucket does a path go in?  Modes come from new, or cur for
+/// things going away.
+pub(crate) fn classify(p: &Path, cur: &Metadata, new: &Metadata,
+		shlib: &regex_lite::Regex) -> Risk
+{
+	if crate::util::is_kernel_dir(&p) { return Risk::Kernel; }
+
+	let mode = new.files.get(p).or_else(|| cur.files.get(p))
+			.map(|f| f.mode).unwrap_or(0);
+	if mode & 0o6000 != 0 { return Risk::Setuid; }
+
+	if shlib.is_match(&p.to_string_lossy()) { return Risk::SharedLib; }
+
+	// rc, rc.d/*, rc.conf, rc.subr, etc
+	let rc = p.parent() == Some(Path::new("/etc"))
+			|| p.starts_with("/etc/rc.d");
+	let rcname = p.strip_prefix("/etc").ok()
+			.and_then(|r| r.to_str())
+			.map_or(false, |r| r.starts_with("rc"));
+	if rc && rcname { return Risk::RcScript; }
+
+	if p.starts_with("/usr/sbin") || p.starts_with("/usr/libexec")
+	{ return Risk::Daemon; }
+
+	Risk::Other
+}
+
+
+/// Bucket up a bunch of changed paths.
+pub(crate) fn categorize<'a>(paths: impl IntoIterator<Item = &'a PathBuf>,
+		cur: &Metadata, new: &Metadata) -> Risks
+{
+	let shlib = crate::core::install::re_so_file();
+	let mut buckets: BTreeMap<Risk, Vec<PathBuf>> = BTreeMap::new();
+	for p in paths
+	{
+		let r = classify(p, cur, new, &shlib);
+		buckets.entry(r).or_default().push(p.clone());
+	}
+	buckets.values_mut().for_each(|v| v.sort_unstable());
+	Risks { buckets }
+}
+
+
+impl Risks
+{
+	/// Boil down for the brief
+	pub(crate) fn summary(&self) -> RiskSummary
+	{
+		let counts = self.buckets.iter().map(|(r, v)| (*r, v.len()))
+				.collect();
+		let short = |r| match self.buckets.get(&r) {
+			Some(v) if v.len() <= LIST_MAX => v.clone(),
+			_ => Vec::new(),
+		};
+		RiskSummary { counts, setuid: short(Risk::Setuid),
+				kernel: short(Risk::Kernel) }
+	}
+}
+
+
+impl RiskSummary
+{
+	/// How many in a bucket
+	pub(crate) fn count(&self, r: Risk) -> usize
+	{
+		self.counts.iter().find(|(cr, _)| *cr == r).map_or(0, |(_, n)| *n)
+	}
+
+	/// Lines to print.  Empty if there's nothing at all.
+	pub(crate) fn describe(&self) -> Vec<String>
+	{
+		let mut ret = Vec::new();
+		if self.counts.iter().all(|(_, n)| *n == 0) { return ret; }
+
+		ret.push("Changed paths by category:".to_string());
+		for r in Risk::ALL
+		{
+			let n = self.count(*r);
+			if n == 0 { continue; }
+			ret.push(format!("  {:<17} {n}", r.desc()));
+			let names = match r {
+				Risk::Setuid => &self.setuid,
+				Risk::Kernel => &self.kernel,
+				_ => continue,
+			};
+			names.iter().for_each(|p| ret.push(format!("    {}",
+					p.display())));
+		}
+		ret
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::{Path, PathBuf};
+	use crate::metadata::{Metadata, MetaFile, MetaSymLink};
+	use super::Risk;
+
+	fn file(md: &mut Metadata, p: &str, mode: u32)
+	{
+		let f = MetaFile { path: p.into(), mode, ..Default::default() };
+		md.files.insert(p.into(), f);
+	}
+
+	#[test]
+	fn categorize()
+	{
+		let mut cur = Metadata::default();
+		let mut new = Metadata::default();
+		file(&mut new, "/boot/kernel/kernel", 0o555);
+		file(&mut new, "/usr/bin/su", 0o4555);
+		file(&mut new, "/usr/bin/wall", 0o2555);
+		// setuid and a shared lib; setuid wins, and only once
+		file(&mut new, "/usr/lib/libsuid.so.1", 0o4444);
+		file(&mut new, "/lib/libc.so.7", 0o444);
+		file(&mut new, "/etc/rc.subr", 0o444);
+		file(&mut new, "/etc/rc.d/sshd", 0o555);
+		file(&mut new, "/etc/rctl.conf", 0o644);
+		file(&mut new, "/usr/sbin/sshd", 0o555);
+		file(&mut new, "/usr/libexec/getty", 0o555);
+		file(&mut new, "/bin/sh", 0o555);
+		// Not an rc script, just named like one
+		file(&mut new, "/etc/mail/rc.foo", 0o644);
+		// Going away, mode comes from cur
+		file(&mut cur, "/usr/bin/oldsuid", 0o4555);
+		// Symlinks don't have setuid that matters
+		new.symlinks.insert("/usr/lib/libx.so".into(), MetaSymLink {
+				path: "/usr/lib/libx.so".into(), mode: 0o4755,
+				..Default::default() });
+
+		let mut paths: Vec<PathBuf> = new.allpaths().into_iter()
+				.map(|p| p.to_path_buf()).collect();
+		paths.push("/usr/bin/oldsuid".into());
+		let risks = super::categorize(&paths, &cur, &new);
+
+		let b = |r| -> Vec<&Path> {
+			risks.buckets.get(&r).map_or(Vec::new(),
+					|v| v.iter().map(|p| p.as_path()).collect())
+		};
+		let p = Path::new;
+		assert_eq!(b(Risk::Kernel), [p("/boot/kernel/kernel")]);
+		assert_eq!(b(Risk::Setuid), [p("/usr/bin/oldsuid"), p("/usr/bin/su"),
+				p("/usr/bin/wall"), p("/usr/lib/libsuid.so.1")]);
+		assert_eq!(b(Risk::SharedLib), [p("/lib/libc.so.7")]);
+		assert_eq!(b(Risk::RcScript), [p("/etc/rc.d/sshd"), p("/etc/rc.subr"),
+				p("/etc/rctl.conf")]);
+		assert_eq!(b(Risk::Daemon), [p("/usr/libexec/getty"),
+				p("/usr/sbin/sshd")]);
+		assert_eq!(b(Risk::Other), [p("/bin/sh"), p("/etc/mail/rc.foo"),
+				p("/usr/lib/libx.so")]);
+
+		// Everything counted exactly once
+		let total: usize = risks.buckets.values().map(|v| v.len()).sum();
+		assert_eq!(total, paths.len());
+
+		// Summary keeps the short lists
+		let sum = risks.summary();
+		assert_eq!(sum.count(Risk::Setuid), 4);
+		assert_eq!(sum.setuid.len(), 4);
+		assert_eq!(sum.kernel, [p("/boot/kernel/kernel")]);
+		let desc = sum.describe();
+		assert!(desc.iter().any(|l| l.trim() == "/usr/bin/su"), "{desc:?}");
+	}
+
+	#[test]
+	fn long_lists()
+	{
+		let cur = Metadata::default();
+		let mut new = Metadata::default();
+		for i in 0..=super::LIST_MAX
+		{ file(&mut new, &format!("/boot/kernel/m{i}.ko"), 0o555); }
+		let paths: Vec<PathBuf> = new.allpaths().into_iter()
+				.map(|p| p.to_path_buf()).collect();
+		let sum = super::categorize(&paths, &cur, &new).summary();
+
+		// Too many to name, but still counted
+		assert!(sum.kernel.is_empty());
+		assert_eq!(sum.count(Risk::Kernel), super::LIST_MAX + 1);
+		assert!(super::RiskSummary::default().describe().is_empty());
+	}
+}
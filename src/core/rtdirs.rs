@@ -5,6 +5,18 @@ use crate::state;
 use crate::util::hash::Sha256HashBuf;
 
 
+use std::sync::atomic::{self, AtomicBool};
+
+/// Go ahead with a workdir others can write to?  x-ref check_secure().
+static INSECURE_OK: AtomicBool = AtomicBool::new(false);
+
+/// Override the workdir ownership/permission checks
+pub(crate) fn set_insecure_ok(s: bool)
+{ INSECURE_OK.store(s, atomic::Ordering::Relaxed) }
+
+fn insecure_ok() -> bool { INSECURE_OK.load(atomic::Ordering::Relaxed) }
+
+
 /// Runtime dirs.  This gives info about directories we may need to
 /// access at runtime that are specific to a given invocation of $0.
 #[derive(Debug)]
@@ -43,6 +55,14 @@ pub(crate) struct RtDirs
 	/// out the directory, so this should do a pretty good job of
 	/// automatically cleaning up, unless we get kill'd or the like.
 	tmp: tempfile::TempDir,
+
+	/// Metadata decompression cache.
+	///
+	/// If enabled (via `MetadataCache`), this is `workdir/cache/`, where
+	/// we keep decompressed metadata files named by their hash across
+	/// runs, so we don't have to gunzip a few hundred megs of INDEX
+	/// every time.
+	mdcache: Option<PathBuf>,
 }
 
 
@@ -50,9 +70,10 @@ pub(crate) struct RtDirs
 // Trivial getters
 impl RtDirs
 {
-	// pub(crate) fn state(&self) -> &Path { &self.state }
+	pub(crate) fn state(&self) -> &Path { &self.state }
 	pub(crate) fn files(&self) -> &Path { &self.files }
 	pub(crate) fn tmp(&self)   -> &Path { &self.tmp.as_ref() }
+	pub(crate) fn mdcache(&self) -> Option<&Path> { self.mdcache.as_deref() }
 
 	/// Build the full path to a .gz file with a given hash in our files
 	/// dir.
@@ -65,6 +86,107 @@ impl RtDirs
 		let hgz = format!("{hb}.gz");
 		self.files().join(hgz)
 	}
+
+	/// Files in the files dir that aren't the hashfile for something in
+	/// keep, and how big they are; what `clean --files` gets rid of.
+	/// It's all just cache, so anything not needed for a pending install
+	/// can go.
+	pub(crate) fn unneeded_files(&self,
+			keep: &std::collections::HashSet<crate::util::hash::Sha256Hash>)
+			-> Result<Vec<(PathBuf, u64)>, std::io::Error>
+	{
+		use crate::util::hash::Sha256Hash;
+
+		let mut ret = Vec::new();
+		for de in std::fs::read_dir(self.files())?
+		{
+			let de = de?;
+			let md = de.metadata()?;
+			if !md.is_file() { continue; }
+
+			let fname = de.file_name();
+			let kept = fname.to_str()
+					.and_then(|f| f.strip_suffix(".gz"))
+					.and_then(|h| h.parse::<Sha256Hash>().ok())
+					.map_or(false, |h| keep.contains(&h));
+			if !kept { ret.push((de.path(), md.len())); }
+		}
+		ret.sort_unstable();
+		Ok(ret)
+	}
+
+	/// Where a running command says what it's up to; x-ref
+	/// crate::util::status.
+	pub(crate) fn status_file(&self) -> PathBuf
+	{
+		self.state.join("status.json")
+	}
+
+	/// The /etc report install saves at the end of an upgrade, since
+	/// the manifest it comes from goes away; x-ref
+	/// crate::core::merge::report.
+	pub(crate) fn etc_report_file(&self) -> PathBuf
+	{
+		self.state.join("etc-report.json")
+	}
+
+	/// Marker file left behind by `install --reboot`, so the resume
+	/// script knows to pick up where we left off.
+	pub(crate) fn resume_marker(&self) -> PathBuf
+	{
+		self.state.join("resume-install")
+	}
+
+	/// Lockfile for runs against this basedir; x-ref crate::core::lock.
+	pub(crate) fn lock_file(&self) -> PathBuf
+	{
+		self.state.join("lock")
+	}
+
+	/// Where `cron` notes its last successful run, for monitoring to
+	/// check up on.
+	pub(crate) fn cron_marker(&self) -> PathBuf
+	{
+		self.state.join("cron-last.json")
+	}
+
+	/// Where `clean --pending` keeps copies of the manifests it throws
+	/// away, in case somebody needs to know what was in flight.
+	pub(crate) fn discarded_dir(&self) -> PathBuf
+	{
+		self.state.join("discarded")
+	}
+
+	/// Where the path lists of the last few finished installs go; x-ref
+	/// crate::core::history.
+	pub(crate) fn history_dir(&self) -> PathBuf
+	{
+		self.state.join("history")
+	}
+
+	/// Where we keep the signed key/tag/index that our saved metadata
+	/// came from, for re-verifying later.
+	pub(crate) fn signed_dir(&self) -> PathBuf
+	{
+		self.state.join("signed")
+	}
+
+	/// Where the start of big downloads that got cut off waits to be
+	/// picked up from; x-ref crate::core::pool::fetch::Resume.
+	pub(crate) fn partial_dir(&self) -> PathBuf
+	{
+		self.state.join("partial")
+	}
+
+	/// Where we keep metadata derived from release distribution sets,
+	/// named by the set's hash.  It's dist/ under the workdir, next to
+	/// files/, and gets made if needed.
+	pub(crate) fn distcache(&self) -> Result<PathBuf, std::io::Error>
+	{
+		let dist = self.files.with_file_name("dist");
+		dodir(&dist, Some(0o700))?;
+		Ok(dist)
+	}
 }
 
 
@@ -77,8 +199,8 @@ impl RtDirs
 	pub(crate) fn init(basedir: &Path, workdir: &Path)
 			-> Result<Self, std::io::Error>
 	{
-		// Try and guard against obvious programmer screwup of passing
-		// dirs in the wrong order.  While it's often the case that
+		// Try and guard against passing dirs in the wrong order (by us,
+		// or by the user with -b/-w).  While it's often the case that
 		// workdir is under basedir (e.g., system at / and workdir in
 		// /var/db/freebsd-update), and probably often the case that
 		// they're disjoint (e.g., system /othersys and workdir
@@ -86,13 +208,22 @@ impl RtDirs
 		// to have the basedir be _under_ the workdir.  While there's no
 		// obvious reason it would be impossible, it sounds pretty
 		// stupid, so I think it's a good tradeoff to not support that,
-		// in favor of being extra careful against the programmer
-		// screwing up.
-		if basedir.starts_with(workdir)
+		// in favor of being extra careful.
+		//
+		// All this has to happen before we go making any dirs, or we
+		// leave junk in whatever we were wrongly pointed at.
+		use crate::check::{looks_like_workdir, looks_like_sysroot};
+		let swapped = basedir.starts_with(workdir)
+				|| (looks_like_workdir(basedir) && looks_like_sysroot(workdir));
+		if swapped
 		{
-			panic!("Error: basedir {} seems to be inside workdir \
-					{}; this probably means the programmer screwed \
-					up my args.", basedir.display(), workdir.display());
+			use std::io::{Error, ErrorKind as EK};
+			let msg = format!("basedir {} and workdir {} look backwards.  \
+					The basedir (-b) is the system being updated, and the \
+					workdir (-w) is where downloads and state get kept; \
+					maybe the flags are swapped?", basedir.display(),
+					workdir.display());
+			return Err(Error::new(EK::InvalidInput, msg));
 		}
 
 		// If basedir doesn't exist, WTF.  That shoulda been caught
@@ -110,7 +241,7 @@ impl RtDirs
 
 		// files/ is under workdir
 		let files = workdir.join("files");
-		dodir(&files, None)?;
+		dodir(&files, Some(0o755))?;
 
 		// statedir is named after the basedir, and is under workdir
 		let state = workdir.join(statesubdir(basedir));
@@ -119,15 +250,39 @@ impl RtDirs
 		// tmpdir goes in a tmp/ dir
 		let tmpdir = workdir.join("tmp");
 		dodir(&tmpdir, Some(0o700))?;
+
+		// Install runs as root and trusts what's in here, so nobody else
+		// better be able to mess with it.
+		if !insecure_ok()
+		{
+			let euid = uzers::get_effective_uid();
+			for d in [workdir, &files, &state, &tmpdir]
+			{ check_secure(d, euid)?; }
+		}
 		let tmp = tempfile::TempDir::new_in(&tmpdir)?;
 
 
 		// OK, all setup.  Return ourselves
-		let ret = RtDirs { state, files, tmp };
+		let ret = RtDirs { state, files, tmp, mdcache: None };
 		Ok(ret)
 	}
 
 
+	/// Turn on (or not) the metadata decompression cache.  It lives in
+	/// cache/ under the workdir, next to files/.
+	pub(crate) fn with_mdcache(mut self, on: bool)
+			-> Result<Self, std::io::Error>
+	{
+		if on
+		{
+			let cache = self.files.with_file_name("cache");
+			dodir(&cache, Some(0o700))?;
+			self.mdcache = Some(cache);
+		}
+		Ok(self)
+	}
+
+
 
 	/// Loading inter-run state.  This is stored in our state dir, so we
 	/// access it through here.
@@ -166,6 +321,35 @@ impl RtDirs
 	}
 
 
+	/// Loading inter-run state, without the (possibly huge) manifest.
+	///
+	/// For things that only care about the rest of the state, or just
+	/// need State::manifest_brief.  Saving it back is fine; the manifest
+	/// is left as it was.
+	pub(crate) fn state_load_brief(&self)
+			-> Result<state::State, state::StateLoadErr>
+	{
+		match self.state_load_brief_raw() {
+			Ok(s) => Ok(s.unwrap_or_else(|| state::State::default())),
+			Err(e) => Err(e),
+		}
+	}
+
+
+	/// state_load_brief(), but not defaulting, like state_load_raw().
+	pub(crate) fn state_load_brief_raw(&self)
+			-> Result<Option<state::State>, state::StateLoadErr>
+	{
+		match state::load_brief_from_dir(&self.state) {
+			Ok(s) => Ok(Some(s)),
+			Err(e) => match e {
+				state::StateLoadErr::None => Ok(None),
+				e => Err(e),
+			},
+		}
+	}
+
+
 	/// Write a state back into our statedir
 	pub(crate) fn state_save(&self, state: &state::State)
 			-> Result<(), state::StateLoadErr>
@@ -175,17 +359,18 @@ impl RtDirs
 
 
 
-	/// Decompress a hash.gz file from our files dir into a `Writer`er.
-	/// Probably usually a `BufWriter`, but hey, it's your (function)
-	/// call...
-	pub(crate) fn decompress_hash_write(&self, hash: &Sha256HashBuf,
-			out: &mut impl std::io::Write) -> Result<(), anyhow::Error>
+	/// Check that a hash.gz file in our files dir decompresses to what
+	/// its name says, without writing anything out.
+	pub(crate) fn check_hashfile(&self, hash: &Sha256HashBuf)
+			-> Result<(), anyhow::Error>
 	{
-		use crate::util::compress;
-
-		// OK, we know the filenames to deal with.
 		let src = self.hashfile(hash);
-		compress::decompress_gz_write(&src, out)
+		let fh = std::fs::File::open(&src)?;
+		let mut gzd = flate2::read::GzDecoder::new(fh);
+
+		use crate::util::hash;
+		hash::check_sha256_reader(&mut gzd, hash.as_ref())?;
+		Ok(())
 	}
 
 
@@ -239,6 +424,39 @@ fn dodir(dir: &Path, mode: Option<u32>) -> Result<(), std::io::Error>
 }
 
 
+/// Make sure nobody but us (or root) can write into a dir.  It has to
+/// be owned by one of us, and not writable by group or other.
+fn check_secure(dir: &Path, euid: u32) -> Result<(), std::io::Error>
+{
+	use std::os::unix::fs::MetadataExt as _;
+	use std::io::{Error, ErrorKind as EK};
+
+	let md = dir.metadata()?;
+	let (uid, mode) = (md.uid(), md.mode() & 0o7777);
+
+	let bad = match (uid == euid || uid == 0, mode & 0o022 == 0) {
+		(true, true)   => return Ok(()),
+		(false, true)  => format!("owned by uid {uid}"),
+		(true, false)  => format!("writable by group/other (mode {mode:04o})"),
+		(false, false) => format!("owned by uid {uid}, and writable by \
+				group/other (mode {mode:04o})"),
+	};
+	let msg = format!("{} is {bad}, so others could tamper with updates.  \
+			Fix it (chown/chmod go-w), or run with --insecure-workdir if \
+			you're really sure.", dir.display());
+	Err(Error::new(EK::PermissionDenied, msg))
+}
+
+
+/// Where the statedir for a basedir/workdir pair is, without making
+/// or checking anything; for quick read-only peeks, where RtDirs::init()
+/// would be overkill.
+pub(crate) fn state_dir_for(basedir: &Path, workdir: &Path) -> PathBuf
+{
+	workdir.join(statesubdir(basedir))
+}
+
+
 // Figuring statedir name.
 fn statesubdir(basedir: &Path) -> PathBuf
 {
@@ -259,3 +477,83 @@ fn statesubdir(basedir: &Path) -> PathBuf
 	let bdh = USNP.encode(bdbytes);
 	format!("state.{bdh}").into()
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn check_secure()
+	{
+		use std::fs::{set_permissions, Permissions};
+		use std::os::unix::fs::PermissionsExt as _;
+		use super::check_secure;
+
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+		let me = uzers::get_effective_uid();
+		let mode = |m| set_permissions(dir, Permissions::from_mode(m)).unwrap();
+
+		// Ours, and only we can write
+		for m in [0o700, 0o755, 0o711, 0o555]
+		{
+			mode(m);
+			check_secure(dir, me).unwrap_or_else(|e| panic!("{m:o}: {e}"));
+		}
+
+		// Others can write
+		for m in [0o775, 0o757, 0o777, 0o1777, 0o720]
+		{
+			mode(m);
+			let e = check_secure(dir, me).expect_err(&format!("{m:o}"));
+			assert!(e.to_string().contains(&format!("mode {m:04o}")), "{e}");
+		}
+		mode(0o700);
+
+		// Somebody else's.  Root's is fine, but if we're root, we can't
+		// easily make a dir somebody else owns, so pretend to be them.
+		match me {
+			0 => {
+				check_secure(dir, 1234).unwrap();
+			},
+			_ => {
+				let e = check_secure(dir, me + 1).expect_err("not ours");
+				assert!(e.to_string().contains(&format!("uid {me}")), "{e}");
+				assert!(e.to_string().contains("--insecure-workdir"), "{e}");
+			},
+		}
+
+		// Doesn't exist
+		check_secure(&dir.join("nope"), me).expect_err("nonexistent");
+	}
+
+	#[test]
+	fn swapped()
+	{
+		use std::fs::create_dir_all;
+		use super::RtDirs;
+
+		// basedir under workdir
+		let td = tempfile::tempdir().unwrap();
+		let wd = td.path().join("wd");
+		let bd = wd.join("sys");
+		create_dir_all(&bd).unwrap();
+		let e = RtDirs::init(&bd, &wd).expect_err("inside");
+		assert!(e.to_string().contains("swapped"), "{e}");
+
+		// Workdir that's a system, basedir that's a workdir
+		let td = tempfile::tempdir().unwrap();
+		let (bd, wd) = (td.path().join("bd"), td.path().join("wd"));
+		create_dir_all(bd.join("files")).unwrap();
+		std::fs::write(bd.join("files").join(format!("{}.gz",
+				"f".repeat(64))), b"").unwrap();
+		for d in ["bin", "etc", "libexec"]
+		{ create_dir_all(wd.join(d)).unwrap(); }
+		RtDirs::init(&bd, &wd).expect_err("swapped");
+
+		// And we didn't go making anything in it
+		assert!(!wd.join("files").exists());
+		assert!(!wd.join("tmp").exists());
+	}
+}
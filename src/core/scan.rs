@@ -21,13 +21,66 @@ pub(crate) fn scan(basedir: PathBuf, paths: Vec<PathBuf>)
 pub(crate) fn scan_inner(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
 		-> Result<Metadata, anyhow::Error>
 {
+	use crate::core::pool::scan as pool;
+	let ctrl = pool::Control { basedir, hash, ..Default::default() };
+	Ok(scan_ctrl(ctrl, paths)?.md)
+}
+
+
+/// What a scan found, when we need more than just the Metadata.
+#[derive(Debug)]
+pub(crate) struct Scanned
+{
+	/// What we found
+	pub(crate) md: Metadata,
+
+	/// Paths we couldn't fully look at for lack of permission.  Those we
+	/// couldn't even lstat aren't in md at all (not even as dashes);
+	/// files we couldn't read are, but without a real hash.
+	pub(crate) unreadable: Vec<PathBuf>,
+
+	/// Files with the extattr we were asked to look for
+	pub(crate) annotated: Vec<PathBuf>,
+}
+
+/// Scan when we may not be root, so permission problems are expected.
+/// Rather than failing the scan, what we couldn't look at gets noted in
+/// Scanned::unreadable.
+pub(crate) fn scan_unpriv(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
+		-> Result<Scanned, anyhow::Error>
+{
+	use crate::core::pool::scan as pool;
+	let ctrl = pool::Control { basedir, hash, unpriv: true,
+			..Default::default() };
+	scan_ctrl(ctrl, paths)
+}
+
+
+/// Scan, also noting which files have a given extattr.  Without one,
+/// it's just a plain scan.
+pub(crate) fn scan_annotated(basedir: PathBuf, paths: Vec<PathBuf>,
+		annotate: Option<crate::util::xattr::AttrName>)
+		-> Result<Scanned, anyhow::Error>
+{
+	use crate::core::pool::scan as pool;
+	let ctrl = pool::Control { basedir, annotate, ..Default::default() };
+	scan_ctrl(ctrl, paths)
+}
+
+
+fn scan_ctrl(ctrl: crate::core::pool::scan::Control, paths: Vec<PathBuf>)
+		-> Result<Scanned, anyhow::Error>
+{
+	// Make sure paths mean what we think they do under there
+	crate::core::fsprobe::check_basedir(&ctrl.basedir)?;
+
 	// First, kick off a pool of scanners to rack up info about all these
 	// files
 	use crate::core::pool::scan as pool;
 
 	// Build up the pool
 	// XXX Be less dumb about nthreads
-	let ctrl = pool::Control { basedir, hash, ..Default::default() };
+	let hash = ctrl.hash;
 	let sp = pool::Scan::new(paths.len());
 
 	// Send it
@@ -37,12 +90,23 @@ pub(crate) fn scan_inner(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
 	};
 
 	// Split it up for easier access.
-	let pool::PoolResult { mut oks, missing, errs } = scanres;
+	let pool::PoolResult { mut oks, missing, denied, too_long, errs } = scanres;
 
 
 	// If there were any _error_ errors, we should just expect to fail.
 	if let Some(errs) = errs { return Err(errs)?; }
 
+	// Paths too long to look at can't be anything real from upstream,
+	// so they're left out entirely (not even as dashes), but say so.
+	if let Some(eg) = too_long.iter().min()
+	{
+		let nl = too_long.len();
+		eprintln!("WARNING: skipped {nl} path{} too long to look at under \
+				{} (e.g., {}...)", crate::util::plural(nl),
+				ctrl.basedir.display(), eg.to_string_lossy()
+				.chars().take(60).collect::<String>());
+	}
+
 	// OK, now go through those results and sort them out into a form
 	// that's useful for our caller.  'errs' we already handled.
 
@@ -64,6 +128,11 @@ pub(crate) fn scan_inner(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
 	// Missing we already got, just type convert it
 	md.dashes = HashSet::from_iter(missing.into_iter());
 
+	// Couldn't-look-at's start with what we couldn't lstat, and pick up
+	// unreadable files as we go.
+	let mut unreadable = denied;
+	let mut annotated = Vec::new();
+
 	// OK, now just go over 'em one by one.  Since .into_iter() takes
 	// ownership, it disassembles the Vec as we go, so we shouldn't be
 	// making copies of the data, or repeatedly popping off the front.
@@ -119,10 +188,12 @@ pub(crate) fn scan_inner(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
 				// Files have name, hash, and permissions.  Hash should
 				// be guaranteed present.
 				let path = f.path;
-				let sha256 = match hash {
+				let sha256 = match hash && !f.unreadable {
 					true => f.sha256.expect("Must have hash for file"),
 					false => Default::default(), // standin value
 				};
+				if f.unreadable { unreadable.push(path.clone()); }
+				if f.annotated { annotated.push(path.clone()); }
 				let mdf = MetaFile { path, sha256, uid, gid, mode, flags };
 				md.files.insert(mdf.path.clone(), mdf);
 			},
@@ -140,7 +211,30 @@ pub(crate) fn scan_inner(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
 
 
 	// And that's it
-	Ok(md)
+	unreadable.sort_unstable();
+	annotated.sort_unstable();
+	Ok(Scanned { md, unreadable, annotated })
+}
+
+
+
+/// Summarize what a scan found, for telling the user.  Paths upstream
+/// says shouldn't be there aren't "missing", so they get counted
+/// separately; x-ref Metadata::split_missing().
+pub(crate) fn found_desc(cur: &Metadata, upstream: &[&Metadata]) -> String
+{
+	let ndir  = cur.dirs.len();
+	let nfile = cur.files.len();
+	let nsl   = cur.symlinks.len();
+	let nhl   = cur.hardlinks.len();
+	let (nmiss, nabs) = cur.split_missing(upstream);
+	let mut ret = format!("Found {ndir} dirs, {nfile} files, {nsl} symlinks, \
+			{nhl} hardlinks, and {nmiss} missing files.");
+	if nabs > 0
+	{
+		ret.push_str(&format!("  ({nabs} paths expected to be absent.)"));
+	}
+	ret
 }
 
 
@@ -151,6 +245,8 @@ pub(crate) fn scan_inner(basedir: PathBuf, paths: Vec<PathBuf>, hash: bool)
 pub(crate) fn schg(basedir: PathBuf, paths: Vec<PathBuf>)
 		-> Result<Vec<(PathBuf, u32)>, anyhow::Error>
 {
+	crate::core::fsprobe::check_basedir(&basedir)?;
+
 	// Let our scanning pool do the walking
 	use crate::core::pool::scan as pool;
 	let ctrl = pool::Control { basedir, hash: false, ..Default::default() };
@@ -163,7 +259,8 @@ pub(crate) fn schg(basedir: PathBuf, paths: Vec<PathBuf>)
 	};
 
 	// Split it up for easier access.
-	let pool::PoolResult { oks, missing: _, errs } = scanres;
+	let pool::PoolResult { oks, missing: _, denied: _, too_long: _, errs }
+			= scanres;
 
 	// If there were any _error_ errors, we should just expect to fail.
 	if let Some(errs) = errs { return Err(errs)?; }
@@ -182,3 +279,103 @@ pub(crate) fn schg(basedir: PathBuf, paths: Vec<PathBuf>)
 
 	Ok(ret)
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn unpriv()
+	{
+		use std::fs;
+		use std::os::unix::fs::PermissionsExt as _;
+		use std::path::PathBuf;
+
+		// Root can read anything, so there's nothing to test
+		if crate::util::euid() == 0
+		{
+			eprintln!("Running as root, skipping");
+			return;
+		}
+
+		let td = tempfile::tempdir().unwrap();
+		let bd = td.path();
+		fs::write(bd.join("ok"), "ok").unwrap();
+		fs::write(bd.join("secret"), "shh").unwrap();
+		fs::create_dir(bd.join("closed")).unwrap();
+		fs::write(bd.join("closed/inside"), "x").unwrap();
+		let mode = |p: &str, m| fs::set_permissions(bd.join(p),
+				fs::Permissions::from_mode(m)).unwrap();
+		mode("secret", 0o000);
+		mode("closed", 0o000);
+
+		let paths: Vec<PathBuf> = ["/ok", "/secret", "/closed/inside",
+				"/gone"].iter().map(PathBuf::from).collect();
+
+		// Normally that's an error
+		let err = super::scan_inner(bd.into(), paths.clone(), true);
+		assert!(err.is_err());
+
+		// Unprivileged, it's just noted
+		let got = super::scan_unpriv(bd.into(), paths, true);
+		mode("closed", 0o755);
+		let got = got.unwrap();
+		assert_eq!(got.unreadable, [PathBuf::from("/closed/inside"),
+				PathBuf::from("/secret")]);
+		assert!(got.md.files.contains_key(&PathBuf::from("/ok")));
+		assert!(got.md.files.contains_key(&PathBuf::from("/secret")));
+		assert!(got.md.dashes.contains(&PathBuf::from("/gone")));
+		assert!(got.md.get_path(&PathBuf::from("/closed/inside")).is_none());
+	}
+
+	#[test]
+	fn too_long()
+	{
+		use std::path::PathBuf;
+		use crate::util::PATH_MAX;
+
+		let td = tempfile::tempdir().unwrap();
+		std::fs::write(td.path().join("ok"), "ok").unwrap();
+
+		// Skipped, not failed on, and not mistaken for missing either
+		let long: PathBuf = "/node_modules".repeat(PATH_MAX / 13 + 1).into();
+		let paths = vec![PathBuf::from("/ok"), long.clone()];
+		let md = super::scan(td.path().into(), paths).unwrap();
+		assert!(md.files.contains_key(&PathBuf::from("/ok")));
+		assert!(md.get_path(&long).is_none());
+		assert!(!md.dashes.contains(&long));
+	}
+
+
+	#[test]
+	fn found_desc()
+	{
+		use crate::metadata::{Metadata, MetaFile};
+
+		// Upstream has /a and /b for real, /c as a dash
+		let mut up = Metadata::default();
+		for p in ["/a", "/b"]
+		{
+			up.files.insert(p.into(), MetaFile { path: p.into(),
+					..Default::default() });
+		}
+		up.dashes.insert("/c".into());
+
+		// Found /a, didn't find /b or /c
+		let mut cur = Metadata::default();
+		let a = up.files[std::path::Path::new("/a")].clone();
+		cur.files.insert("/a".into(), a);
+		cur.dashes.insert("/b".into());
+		cur.dashes.insert("/c".into());
+
+		assert_eq!(cur.split_missing(&[&up]), (1, 1));
+		assert_eq!(super::found_desc(&cur, &[&up]), "Found 0 dirs, 1 files, \
+				0 symlinks, 0 hardlinks, and 1 missing files.  \
+				(1 paths expected to be absent.)");
+
+		// Nothing expected-absent, nothing extra said
+		cur.dashes.remove(std::path::Path::new("/c"));
+		assert!(super::found_desc(&cur, &[&up]).ends_with("1 missing files."));
+	}
+}
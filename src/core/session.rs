@@ -0,0 +1,410 @@
+//! Recording a run's server traffic, and playing it back.
+//!
+//! With --record-session, everything the server sends us gets saved
+//! into a dir, along with a log of what we asked for and notes on what
+//! we decided along the way (see note()).  Then --replay-session on that
+//! dir feeds those same answers back through instead of the network, so
+//! somebody else's run can be reproduced, given the same config and
+//! version (--as-version) and a similar enough system.
+//!
+//! Layout of the dir:
+//!   requests.log    <url> <status or "error: ...">, one per request
+//!   decisions.log   notes from the recorded run
+//!   decisions.replay.log   the same, from a replay of it
+//!   responses/      bodies, by URL path
+//!
+//! Bodies of hashfiles and patches (f/ and bp/) are only kept for a
+//! limited sample, since a whole upgrade's worth can be gigs; a replay
+//! gets those as failures after the sample runs out.  Everything else
+//! (keys, tags, indices, metadata) is kept whole.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use url::Url;
+
+use crate::core::pool::fetch::GetErr;
+use crate::server::{Client, Transport};
+
+
+/// How many hashfiles/patches we keep bodies for, and how big they can
+/// each be.
+const SAMPLE_FILES: usize = 32;
+const SAMPLE_BYTES: u64 = 4 * 1024 * 1024;
+
+const REQLOG: &str = "requests.log";
+const DECLOG: &str = "decisions.log";
+const REPLAY_DECLOG: &str = "decisions.replay.log";
+const RESPONSES: &str = "responses";
+
+
+/// The session we're in, if any.
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+#[derive(Debug)]
+enum Session
+{
+	Record(Arc<Recorder>),
+	Replay(Arc<Replayer>),
+}
+
+
+/// Set up from the args.  At most one of them should be given; clap
+/// sees to that.
+pub(crate) fn init(record: Option<&Path>, replay: Option<&Path>)
+		-> Result<(), anyhow::Error>
+{
+	let sess = match (record, replay) {
+		(Some(d), _) => Session::Record(Arc::new(Recorder::new(d)?)),
+		(None, Some(d)) => Session::Replay(Arc::new(Replayer::new(d)?)),
+		(None, None) => return Ok(()),
+	};
+	let _ = SESSION.set(sess);
+	note(format_args!("args {}",
+			std::env::args().collect::<Vec<_>>().join(" ")));
+	Ok(())
+}
+
+
+/// Are we recording or replaying?
+pub(crate) fn active() -> bool { SESSION.get().is_some() }
+
+/// Are we replaying?  Then nothing should be going to the network.
+pub(crate) fn replaying() -> bool
+{ matches!(SESSION.get(), Some(Session::Replay(_))) }
+
+
+/// Put the session (if any) in the path of a real HTTP client.
+pub(crate) fn wrap(inner: Client) -> Client
+{
+	match SESSION.get() {
+		None => inner,
+		Some(Session::Record(r)) => Arc::new(Recording { inner,
+				rec: r.clone() }),
+		Some(Session::Replay(r)) => r.clone(),
+	}
+}
+
+
+/// Note something we decided (what components we kept, how many paths
+/// made it through some stage, etc) in the session's log.
+pub(crate) fn note(what: impl std::fmt::Display)
+{
+	match SESSION.get() {
+		None => (),
+		Some(Session::Record(r)) => log_line(&r.declog, &what),
+		Some(Session::Replay(r)) => log_line(&r.declog, &what),
+	}
+}
+
+/// Best-effort; a log we can't write isn't worth dying over.
+fn log_line(log: &Mutex<File>, what: &dyn std::fmt::Display)
+{
+	let mut f = log.lock().unwrap();
+	let _ = writeln!(f, "{what}");
+}
+
+
+/// Where a URL's body lives in the session dir.  Just its path; which
+/// server it came from doesn't matter, they're all serving the same
+/// things.
+fn resp_path(dir: &Path, url: &Url) -> PathBuf
+{
+	let p = url.path().trim_start_matches('/');
+	dir.join(RESPONSES).join(p)
+}
+
+/// Is this a hashfile or patch, rather than metadata?
+fn sampled(url: &Url) -> bool
+{
+	let dir = url.path_segments().and_then(|s| s.rev().nth(1));
+	matches!(dir, Some("f") | Some("bp"))
+}
+
+fn open_log(p: &Path) -> Result<Mutex<File>, anyhow::Error>
+{
+	use anyhow::Context as _;
+	let f = std::fs::OpenOptions::new().create(true).append(true).open(p)
+			.with_context(|| format!("Opening {}", p.display()))?;
+	Ok(Mutex::new(f))
+}
+
+
+
+/*
+ * Recording
+ */
+
+#[derive(Debug)]
+struct Recorder
+{
+	dir: PathBuf,
+	reqlog: Mutex<File>,
+	declog: Mutex<File>,
+
+	/// How many of the sampled bodies we've taken
+	nsampled: AtomicUsize,
+}
+
+impl Recorder
+{
+	fn new(dir: &Path) -> Result<Self, anyhow::Error>
+	{
+		std::fs::create_dir_all(dir.join(RESPONSES))?;
+		Ok(Self {
+			dir: dir.to_path_buf(),
+			reqlog: open_log(&dir.join(REQLOG))?,
+			declog: open_log(&dir.join(DECLOG))?,
+			nsampled: AtomicUsize::new(0),
+		})
+	}
+
+	/// Should we keep this one's body?
+	fn keep(&self, url: &Url) -> bool
+	{
+		if !sampled(url) { return true; }
+		self.nsampled.fetch_add(1, Ordering::Relaxed) < SAMPLE_FILES
+	}
+}
+
+
+/// A real client, with everything it gets written down.
+#[derive(Debug)]
+struct Recording
+{
+	inner: Client,
+	rec: Arc<Recorder>,
+}
+
+impl Transport for Recording
+{
+	fn get(&self, url: &Url) -> Result<Box<dyn Read + Send>, GetErr>
+	{
+		let res = self.inner.get(url);
+		let what = match &res {
+			Ok(_) => "200".to_string(),
+			Err(GetErr::Status(_, code)) => code.to_string(),
+			Err(e) => format!("error: {e}"),
+		};
+		log_line(&self.rec.reqlog, &format_args!("{url} {what}"));
+
+		let rdr = res?;
+		if !self.rec.keep(url) { return Ok(rdr); }
+		let path = resp_path(&self.rec.dir, url);
+		let limit = match sampled(url) {
+			true  => SAMPLE_BYTES,
+			false => u64::MAX,
+		};
+		Ok(Box::new(Tee::new(rdr, path, limit)))
+	}
+}
+
+
+/// Copies what's read through it into a file.  It only shows up under
+/// its real name once it's all there; if it's not read to the end, or
+/// turns out bigger than the limit, it's dropped.
+struct Tee
+{
+	inner: Box<dyn Read + Send>,
+	out: Option<(File, PathBuf)>,
+	path: PathBuf,
+	left: u64,
+}
+
+impl Tee
+{
+	fn new(inner: Box<dyn Read + Send>, path: PathBuf, left: u64) -> Self
+	{
+		let mut part = path.clone().into_os_string();
+		part.push(".part");
+		let part = PathBuf::from(part);
+		let out = path.parent()
+				.and_then(|d| std::fs::create_dir_all(d).ok())
+				.and_then(|_| File::create(&part).ok())
+				.map(|f| (f, part));
+		Self { inner, out, path, left }
+	}
+
+	fn give_up(&mut self)
+	{
+		if let Some((_, part)) = self.out.take()
+		{ let _ = std::fs::remove_file(part); }
+	}
+}
+
+impl Read for Tee
+{
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+	{
+		let n = self.inner.read(buf)?;
+		let Some((f, part)) = &mut self.out else { return Ok(n) };
+
+		if n == 0
+		{
+			// All there
+			let _ = std::fs::rename(&*part, &self.path);
+			self.out = None;
+			return Ok(0);
+		}
+
+		match n as u64 > self.left || f.write_all(&buf[..n]).is_err() {
+			true  => self.give_up(),
+			false => self.left -= n as u64,
+		}
+		Ok(n)
+	}
+}
+
+impl Drop for Tee
+{
+	fn drop(&mut self) { self.give_up(); }
+}
+
+
+
+/*
+ * Replaying
+ */
+
+#[derive(Debug)]
+struct Replayer
+{
+	dir: PathBuf,
+
+	/// What didn't work out, by URL path.  The last word wins, if
+	/// something was asked for more than once.
+	failed: HashMap<String, String>,
+
+	declog: Mutex<File>,
+}
+
+impl Replayer
+{
+	fn new(dir: &Path) -> Result<Self, anyhow::Error>
+	{
+		use anyhow::Context as _;
+		let rlpath = dir.join(REQLOG);
+		let reqlog = std::fs::read_to_string(&rlpath)
+				.with_context(|| format!("Reading recorded session {}",
+					rlpath.display()))?;
+
+		let mut failed = HashMap::new();
+		for l in reqlog.lines()
+		{
+			let Some((url, what)) = l.split_once(' ') else { continue };
+			let Ok(url) = Url::parse(url) else { continue };
+			let key = url.path().to_string();
+			match what {
+				"200" => failed.remove(&key),
+				_ => failed.insert(key, what.to_string()),
+			};
+		}
+
+		Ok(Self {
+			dir: dir.to_path_buf(),
+			failed,
+			declog: open_log(&dir.join(REPLAY_DECLOG))?,
+		})
+	}
+}
+
+impl Transport for Replayer
+{
+	fn get(&self, url: &Url) -> Result<Box<dyn Read + Send>, GetErr>
+	{
+		if let Some(what) = self.failed.get(url.path())
+		{
+			return match what.parse::<u16>() {
+				Ok(code) => Err(GetErr::Status(url.clone(), code)),
+				Err(_) => Err(GetErr::Replayed(what.clone())),
+			};
+		}
+
+		match File::open(resp_path(&self.dir, url)) {
+			Ok(f) => Ok(Box::new(f)),
+			Err(_) => Err(GetErr::Replayed(format!("{url}: not in the \
+					recorded session"))),
+		}
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::sync::Arc;
+	use std::io::Read as _;
+
+	use url::Url;
+
+	use crate::server::Transport as _;
+	use crate::server::transport::tests::Fake;
+	use crate::core::pool::fetch::GetErr;
+
+	fn get(t: &dyn crate::server::Transport, url: &Url)
+			-> Result<Vec<u8>, GetErr>
+	{
+		let mut buf = Vec::new();
+		t.get(url)?.read_to_end(&mut buf)?;
+		Ok(buf)
+	}
+
+	#[test]
+	fn record_replay()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path().join("sess");
+		let burl = Url::parse("http://srv.example/14.2-RELEASE/amd64/")
+				.unwrap();
+		let u = |p: &str| burl.join(p).unwrap();
+
+		let mut files: Vec<(String, Vec<u8>)> = vec![
+			("14.2-RELEASE/amd64/latest.ssl".into(), b"tag".to_vec()),
+			("14.2-RELEASE/amd64/m/abcd.gz".into(), b"meta".to_vec()),
+		];
+		files.extend((0..super::SAMPLE_FILES + 3).map(|i|
+				(format!("14.2-RELEASE/amd64/f/{i}.gz"), vec![b'x'; i])));
+		let fs: Vec<(&str, &[u8])> = files.iter()
+				.map(|(p, b)| (p.as_str(), b.as_slice())).collect();
+		let fake: crate::server::Client = Arc::new(Fake::new(&fs));
+
+		// Record a bit of everything
+		{
+			let rec = Arc::new(super::Recorder::new(&dir).unwrap());
+			let t = super::Recording { inner: fake, rec };
+			assert_eq!(get(&t, &u("latest.ssl")).unwrap(), b"tag");
+			assert_eq!(get(&t, &u("m/abcd.gz")).unwrap(), b"meta");
+			get(&t, &u("bp/nope")).expect_err("404");
+			for i in 0..super::SAMPLE_FILES + 3
+			{ get(&t, &u(&format!("f/{i}.gz"))).unwrap(); }
+
+			// Something not read all the way doesn't get kept
+			drop(t.get(&u("m/abcd.gz")).unwrap());
+			assert!(dir.join("responses/14.2-RELEASE/amd64/m/abcd.gz")
+					.is_file());
+		}
+		let log = std::fs::read_to_string(dir.join("requests.log")).unwrap();
+		assert!(log.starts_with("http://srv.example/14.2-RELEASE/amd64/\
+				latest.ssl 200\n"), "{log}");
+		assert!(log.contains("bp/nope 404\n"), "{log}");
+
+		// And it all comes back from anywhere else, without any server.
+		let rp = super::Replayer::new(&dir).unwrap();
+		let burl = Url::parse("http://elsewhere/14.2-RELEASE/amd64/")
+				.unwrap();
+		let u = |p: &str| burl.join(p).unwrap();
+		assert_eq!(get(&rp, &u("latest.ssl")).unwrap(), b"tag");
+		assert_eq!(get(&rp, &u("m/abcd.gz")).unwrap(), b"meta");
+		assert!(matches!(get(&rp, &u("bp/nope")),
+				Err(GetErr::Status(_, 404))));
+		assert_eq!(get(&rp, &u("f/3.gz")).unwrap(), b"xxx");
+
+		// Except past the sample
+		let last = format!("f/{}.gz", super::SAMPLE_FILES + 2);
+		assert!(matches!(get(&rp, &u(&last)), Err(GetErr::Replayed(_))));
+	}
+}
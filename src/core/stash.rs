@@ -0,0 +1,286 @@
+//! Stashing the current system's files into the files dir.
+//!
+//! fetch and upgrade both want copies of every current file (for
+//! patching, merging, and rollback), and with a few thousand files and
+//! several GB, a lot can go wrong partway.  Whatever does get stashed
+//! stays (it's content-addressed), so a re-run picks up where the last
+//! one stopped, and a handful of files we can't read don't throw away
+//! the rest.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+use crate::util::plural;
+
+
+/// If more than this percent of the files fail, something's more
+/// broadly wrong (full disk, say), so give up rather than carrying on
+/// without them.
+const MAX_FAIL_PCT: usize = 1;
+
+
+/// Stash whatever of cur's files aren't already in filesdir.
+///
+/// Files we can't stash get listed, and dropped from cur, since nothing
+/// later can use them without a hashfile anyway; they won't be rolled
+/// back, or removed if upstream dropped them.  But if any of them are
+/// required (e.g., they're about to be merged), or too many failed, we
+/// error out instead.
+pub(crate) fn stash_current(cur: &mut Metadata, basedir: &Path,
+		tmpdir: &Path, filesdir: &Path, required: &HashSet<&Path>)
+		-> Result<(), anyhow::Error>
+{
+	let (nstash, res) = {
+		let stashfiles = match cur.files_no_hash_dir(filesdir) {
+			Some(sf) => sf,
+			None => return Ok(()),
+		};
+		let nstash = stashfiles.len();
+		println!("Stashing {nstash} current file{}.", plural(nstash));
+		let res = cur.stash_files(&stashfiles, basedir.to_path_buf(),
+				tmpdir.to_path_buf(), filesdir.to_path_buf())?;
+		if res.existed > 0
+		{
+			println!("  {} stashed, {} already there.", res.stashed,
+					res.existed);
+		}
+		(nstash, res)
+	};
+
+	let nf = res.failed.len();
+	if nf == 0 { return Ok(()); }
+	eprintln!("Couldn't stash {nf} file{}:", plural(nf));
+	for (p, e) in &res.failed { eprintln!("    {}: {e}", p.display()); }
+
+	// Identical files share a hashfile, so more than just the ones that
+	// failed may be missing theirs.
+	let missing: HashSet<PathBuf> = cur.files_no_hash_dir(filesdir)
+			.unwrap_or_default().into_iter().map(|p| p.to_path_buf())
+			.collect();
+
+	let mut needed: Vec<_> = missing.iter()
+			.filter(|p| required.contains(p.as_path())).collect();
+	if !needed.is_empty()
+	{
+		needed.sort_unstable();
+		let nl: Vec<_> = needed.iter().map(|p| p.display().to_string())
+				.collect();
+		anyhow::bail!("Can't go on without stashing:\n  {}", nl.join("\n  "));
+	}
+	if too_many(nf, nstash)
+	{
+		anyhow::bail!("{nf} of {nstash} files failed to stash; not going \
+				on without them.");
+	}
+
+	eprintln!("Going on without {}; they won't be rolled back, or removed \
+			if they're gone upstream.", match missing.len() {
+				1 => "it".to_string(),
+				n => format!("those {n}"),
+			});
+	cur.remove_paths(&missing);
+	Ok(())
+}
+
+
+/// Is nf failures out of ntot too many to carry on?
+fn too_many(nf: usize, ntot: usize) -> bool
+{
+	nf * 100 > ntot * MAX_FAIL_PCT
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashSet;
+	use std::path::{Path, PathBuf};
+	use crate::metadata::{Metadata, MetaFile};
+
+	/// A basedir with n files, and metadata for them.
+	fn mkbase(n: usize) -> (tempfile::TempDir, Metadata)
+	{
+		use crate::util::hash::sha256_file;
+
+		let td = tempfile::tempdir().unwrap();
+		let mut md = Metadata::default();
+		std::fs::create_dir(td.path().join("bin")).unwrap();
+		for i in 0..n
+		{
+			let rel = format!("bin/f{i}");
+			let full = td.path().join(&rel);
+			std::fs::write(&full, format!("file {i}\n")).unwrap();
+			let path = PathBuf::from(format!("/{rel}"));
+			let sha256 = sha256_file(&full).unwrap();
+			md.files.insert(path.clone(),
+					MetaFile { path, sha256, ..Default::default() });
+		}
+		(td, md)
+	}
+
+	/// Where our stash goes
+	fn dirs() -> (tempfile::TempDir, PathBuf, PathBuf)
+	{
+		let td = tempfile::tempdir().unwrap();
+		let tmp = td.path().join("tmp");
+		let files = td.path().join("files");
+		std::fs::create_dir(&tmp).unwrap();
+		std::fs::create_dir(&files).unwrap();
+		(td, tmp, files)
+	}
+
+	#[test]
+	fn one_unreadable()
+	{
+		let (base, mut cur) = mkbase(200);
+		let (_td, tmp, files) = dirs();
+
+		// f7 turned into something we can't stash since the scan
+		let f7 = base.path().join("bin/f7");
+		std::fs::remove_file(&f7).unwrap();
+		std::fs::create_dir(&f7).unwrap();
+
+		let none = HashSet::new();
+		super::stash_current(&mut cur, base.path(), &tmp, &files, &none)
+				.expect("one bad file isn't fatal");
+		assert_eq!(cur.files.len(), 199);
+		assert!(!cur.files.contains_key(Path::new("/bin/f7")));
+		assert_eq!(std::fs::read_dir(&files).unwrap().count(), 199);
+		assert_eq!(std::fs::read_dir(&tmp).unwrap().count(), 0,
+				"temp copies cleaned up");
+
+		// Nothing left to do on a re-run
+		assert!(cur.files_no_hash_dir(&files).is_none());
+	}
+
+	#[test]
+	fn required_or_too_many()
+	{
+		let (base, cur) = mkbase(50);
+		std::fs::remove_file(base.path().join("bin/f3")).unwrap();
+
+		// If we need it, we can't do without it
+		let (_td, tmp, files) = dirs();
+		let mut c = cur.clone();
+		let req: HashSet<_> = [Path::new("/bin/f3")].into();
+		let err = super::stash_current(&mut c, base.path(), &tmp, &files,
+				&req).unwrap_err();
+		assert!(err.to_string().contains("/bin/f3"), "{err}");
+
+		// 1 of 50 is more than we'll put up with
+		let (_td, tmp, files) = dirs();
+		let mut c = cur.clone();
+		super::stash_current(&mut c, base.path(), &tmp, &files,
+				&HashSet::new()).expect_err("2% failed");
+
+		// But everything else still got stashed, so a re-run only has
+		// the one to do.
+		let left = c.files_no_hash_dir(&files).unwrap();
+		assert_eq!(left, [Path::new("/bin/f3")]);
+
+		assert!(!super::too_many(1, 100));
+		assert!(super::too_many(2, 100));
+	}
+
+	#[test]
+	fn existing_kept()
+	{
+		let (base, cur) = mkbase(3);
+		let (_td, tmp, files) = dirs();
+
+		// Something's already there for f1; we shouldn't touch it.
+		let mf = &cur.files[Path::new("/bin/f1")];
+		let hgz = files.join(format!("{}.gz", mf.sha256));
+		std::fs::write(&hgz, "already here").unwrap();
+
+		let paths: Vec<_> = cur.files.keys().map(|p| p.as_path()).collect();
+		let res = cur.stash_files(&paths, base.path().to_path_buf(),
+				tmp.clone(), files.clone()).unwrap();
+		assert_eq!((res.stashed, res.existed), (2, 1));
+		assert!(res.failed.is_empty());
+		assert_eq!(std::fs::read_to_string(&hgz).unwrap(), "already here");
+	}
+
+	#[test]
+	fn bytes_add_up()
+	{
+		use crate::core::pool::{fetch, hashcheck as hcp, Pool as _};
+		use crate::util::{bytecount, compress};
+		use crate::state::Manifest;
+
+		let (base, mut cur) = mkbase(20);
+		let wd = tempfile::tempdir().unwrap();
+		let rtdirs = crate::core::RtDirs::init(base.path(), wd.path())
+				.unwrap();
+		let tmp = rtdirs.tmp().to_path_buf();
+		let files = rtdirs.files().to_path_buf();
+		let filesdir_size = || -> u64 {
+			std::fs::read_dir(&files).unwrap()
+					.map(|e| e.unwrap().metadata().unwrap().len()).sum()
+		};
+
+		// Stash everything current
+		bytecount::reset();
+		super::stash_current(&mut cur, base.path(), &tmp, &files,
+				&HashSet::new()).unwrap();
+		let b = bytecount::get();
+		assert_eq!(b.stashed, filesdir_size());
+		assert_eq!((b.downloaded, b.fetched), (0, 0));
+
+		// "Download" new versions of a few; the fetch pool hears about
+		// them like it would from its workers.
+		let mut new = Metadata::default();
+		let mut fp = fetch::Fetch::new(5);
+		let (mut gzs, mut plain) = (Vec::new(), 0);
+		for i in 0..5
+		{
+			let content = format!("new file {i}, a bit longer\n");
+			plain += content.len() as u64;
+			let sha256 = compress::write_hashfile(&tmp, content.as_bytes());
+			let gz = format!("{sha256}.gz");
+
+			let bytes = std::fs::metadata(tmp.join(&gz)).unwrap().len();
+			fp.work_result(Ok(fetch::Res { file: gz.clone(), bytes }));
+			gzs.push(gz);
+
+			let path = PathBuf::from(format!("/bin/f{i}"));
+			new.files.insert(path.clone(),
+					MetaFile { path, sha256, ..Default::default() });
+		}
+		let fres = fp.finalize();
+		assert_eq!(fres.okfiles, gzs);
+		assert_eq!(bytecount::get().downloaded, fres.bytes);
+
+		// Check and store them
+		let ctrl = hcp::Control { tmpdir: tmp.clone(),
+				filesdir: files.clone(), keep: false };
+		let reqs = gzs.iter().map(|g| hcp::Req { path: g.clone() });
+		let hcres = hcp::HashCheck::new(gzs.len()).run(&ctrl, reqs).unwrap();
+		assert!(hcres.errs.is_none());
+
+		// Everything downloaded got stored, and between stash and fetch
+		// that's all of the files dir.
+		let b = bytecount::get();
+		assert_eq!(b.fetched, fres.bytes);
+		assert_eq!(b.stored(), filesdir_size());
+
+		// Install writes out the new ones, decompressed
+		let mut mani = Manifest::new_fetch(cur, new,
+				"14.2-RELEASE-p2".parse().unwrap());
+		assert_eq!(mani.install_bytes(), None);
+		mani.size_install(&rtdirs).unwrap();
+		assert_eq!(mani.install_bytes(), Some(plain));
+		assert_eq!(mani.brief().install_bytes, Some(plain));
+
+		// And it needs everything in there; anything else can go.
+		let keep = mani.install_hashes();
+		assert!(rtdirs.unneeded_files(&keep).unwrap().is_empty());
+		let junk = files.join("leftover.gz");
+		std::fs::write(&junk, "12345").unwrap();
+		assert_eq!(rtdirs.unneeded_files(&keep).unwrap(), [(junk, 5)]);
+		let all = rtdirs.unneeded_files(&Default::default()).unwrap();
+		assert_eq!(all.iter().map(|(_, b)| b).sum::<u64>(),
+				filesdir_size());
+	}
+}
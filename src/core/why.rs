@@ -0,0 +1,441 @@
+//! Explaining what fetch would do with a single path, and why.
+//!
+//! What happens to any given path is decided piecemeal through fetch:
+//! component filtering, IgnorePaths, UpdateIfUnmodified,
+//! KeepModifiedMetadata, and finally comparing with what's on the system.
+//! Upgrades add MergeChanges ahead of UpdateIfUnmodified.  So this walks
+//! one path through those same steps (the same code, not a
+//! re-implementation of it), noting where it drops out.
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::metadata::{Metadata, MetadataGroup, MetadataLine};
+use crate::state::Manifest;
+
+
+/// What ends up happening to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Disposition
+{
+	/// Upstream doesn't know anything about it
+	NotUpstream,
+
+	/// Only in components we're not updating
+	InactiveComponent,
+
+	/// IgnorePaths
+	Ignored,
+
+	/// Outside of --include-only
+	NotIncluded,
+
+	/// Locally modified, so left alone
+	SkippedModified,
+
+	/// Locally modified, so an upgrade merges upstream's changes in
+	Merge,
+
+	/// Already matches upstream
+	Current,
+
+	/// Will be replaced with the new version
+	Update,
+
+	/// Isn't here, will be
+	Add,
+
+	/// Gone upstream, will be removed
+	Remove,
+
+	/// Gone upstream, but AllowDelete=no keeps it
+	KeptDeleted,
+}
+
+impl Disposition
+{
+	pub(crate) fn desc(&self) -> &'static str
+	{
+		match self {
+			Self::NotUpstream       => "not in upstream metadata",
+			Self::InactiveComponent => "left alone (component not in use)",
+			Self::Ignored           => "ignored",
+			Self::NotIncluded       => "left alone (not included)",
+			Self::SkippedModified   => "skipped as locally modified",
+			Self::Merge             => "will merge",
+			Self::Current           => "already current",
+			Self::Update            => "will update",
+			Self::Add               => "will add",
+			Self::Remove            => "will remove",
+			Self::KeptDeleted       => "left alone (AllowDelete no)",
+		}
+	}
+}
+
+
+/// Everything we found out about a path.
+#[derive(Debug)]
+pub(crate) struct Explanation
+{
+	/// Components upstream has it in, and whether we're using each.
+	pub(crate) components: Vec<(String, bool)>,
+
+	/// Config patterns that match it, as (setting, pattern).
+	pub(crate) patterns: Vec<(&'static str, String)>,
+
+	/// Its entries in the old and new metadata, and on the system.
+	pub(crate) old: Option<MetadataLine>,
+	pub(crate) new: Option<MetadataLine>,
+	pub(crate) cur: Option<MetadataLine>,
+
+	/// KeepModifiedMetadata kept the local owner/mode/flags.
+	pub(crate) metadata_kept: bool,
+
+	/// How it all comes out
+	pub(crate) disposition: Disposition,
+
+	/// The specific thing that decided it
+	pub(crate) rule: String,
+}
+
+
+/// Walk a path through fetch's decisions, or with upgrade, an
+/// upgrade's.
+///
+/// old and new are the metadata for the running version, as parsed from
+/// the files, without any of the usual component or path filtering
+/// applied.  cur is a scan of the system (which only needs to cover the
+/// path).
+pub(crate) fn explain(path: &Path, mut old: MetadataGroup,
+		mut new: MetadataGroup, mut cur: Metadata, config: &Config,
+		upgrade: bool) -> Explanation
+{
+	use crate::metadata::MetadataLine as ML;
+	use Disposition as D;
+
+	// Nothing else matters, so don't drag it through everything.
+	let only: HashSet<&Path> = [path].into();
+	old.keep_paths(&only);
+	new.keep_paths(&only);
+	cur.keep_paths(&only);
+
+	let mut components: Vec<_> = old.path_components(path).into_iter()
+			.chain(new.path_components(path))
+			.map(|c| {
+				let used = config.components.iter().any(|k| k.contains(c));
+				(c.to_string(), used)
+			}).collect();
+	components.sort_unstable();
+	components.dedup();
+
+	let pstr = path.to_string_lossy();
+	let pats = [
+		("IgnorePaths",        &config.ignore_paths),
+		("--include-only",     &config.include_only),
+		("UpdateIfUnmodified", &config.update_if_unmodified),
+		("MergeChanges",       &config.merge_changes),
+	];
+	let patterns: Vec<_> = pats.iter().flat_map(|(n, res)| {
+		res.iter().filter(|r| r.is_match(&pstr))
+				.map(|r| (*n, r.as_str().to_string()))
+	}).collect();
+
+	let mut ret = Explanation {
+		components, patterns,
+		old: old.clone().into_metadata().get_path(path),
+		new: new.clone().into_metadata().get_path(path),
+		cur: cur.get_path(path),
+		metadata_kept: false,
+		disposition: D::NotUpstream, rule: String::new(),
+	};
+	let done = |mut ret: Explanation, d: Disposition, rule: String| {
+		ret.disposition = d;
+		ret.rule = rule;
+		ret
+	};
+	let matched = |ret: &Explanation, set: &str| -> Option<String> {
+		ret.patterns.iter().find(|(n, _)| *n == set).map(|(_, p)| p.clone())
+	};
+
+	if ret.old.is_none() && ret.new.is_none()
+	{
+		return done(ret, D::NotUpstream, "no entry in the old or new \
+				metadata for this version".to_string());
+	}
+
+	// Now the filters, in the order fetch does them.
+	old.keep_components(&config.components);
+	new.keep_components(&config.components);
+	if old.len() + new.len() == 0
+	{
+		let comps: Vec<_> = ret.components.iter().map(|(c, _)| c.as_str())
+				.collect();
+		let rule = format!("only in {}, which isn't in Components",
+				comps.join(", "));
+		return done(ret, D::InactiveComponent, rule);
+	}
+
+	if let Some(p) = matched(&ret, "IgnorePaths")
+	{
+		let rule = format!("IgnorePaths pattern `{p}`");
+		return done(ret, D::Ignored, rule);
+	}
+	if !config.include_only.is_empty()
+			&& matched(&ret, "--include-only").is_none()
+	{
+		let rule = "doesn't match any --include-only pattern".to_string();
+		return done(ret, D::NotIncluded, rule);
+	}
+
+	let mut old = old.into_metadata();
+	let mut new = new.into_metadata();
+
+	// Upgrades merge MergeChanges files before anything else gets a look
+	// at them.
+	if let Some(p) = matched(&ret, "MergeChanges").filter(|_| upgrade)
+	{
+		use crate::core::merge;
+		let base = cur.files.get(path).and_then(|cf| {
+			merge::merge_base(cf, old.files.get(path), new.files.get(path),
+					None)
+		});
+		if base.is_some() && !merge::dont_merge().iter().any(|d| d == path)
+		{
+			let rule = format!("MergeChanges pattern `{p}`, and it \
+					matches neither the old nor new version");
+			return done(ret, D::Merge, rule);
+		}
+	}
+
+	use crate::core::filter;
+	let lc = filter::local_changes(&mut old, &mut new, &mut cur,
+			&config.update_if_unmodified, config.keep_modified_metadata);
+	if lc.modified.contains(path)
+	{
+		let rule = match matched(&ret, "UpdateIfUnmodified") {
+			Some(p) => format!("UpdateIfUnmodified pattern `{p}`, and it \
+					matches neither the old nor new version"),
+			None => "removed locally, though the old version had it, so \
+					it stays removed".to_string(),
+		};
+		return done(ret, D::SkippedModified, rule);
+	}
+
+	ret.metadata_kept = lc.kept_metadata.iter().any(|k| k.path == path);
+
+	if !config.allow_delete
+			&& filter::keep_deletes(&mut new, &cur).iter().any(|p| p == path)
+	{
+		let rule = "gone from the new version, but AllowDelete is \
+				off".to_string();
+		return done(ret, D::KeptDeleted, rule);
+	}
+
+	filter::uptodate(&mut new, &mut cur);
+
+	let nl = new.get_path(path);
+	let cl = cur.get_path(path).filter(|l| !matches!(l, ML::Dash(_)));
+	let (d, rule) = match (nl, cl) {
+		(None, None)       => (D::Current, "the system already matches \
+				the new version"),
+		(Some(_), Some(_)) => (D::Update, "differs from the new version"),
+		(Some(_), None)    => (D::Add, "in the new version, but not on \
+				the system"),
+		(None, Some(_))    => (D::Remove, "on the system, but gone from \
+				the new version"),
+	};
+	done(ret, d, rule.to_string())
+}
+
+
+/// What a pending manifest has in store for a path, if anything.
+pub(crate) fn pending(mani: &Manifest, path: &Path) -> Option<String>
+{
+	use crate::metadata::MetadataLine as ML;
+
+	let (cur, new) = match mani {
+		Manifest::Fetch(f)   => (&f.cur, &f.new),
+		Manifest::Upgrade(u) => (&u.cur, &u.new),
+	};
+
+	if let Manifest::Upgrade(u) = mani
+	{
+		if u.merge_conflict.contains_key(path)
+		{ return Some("merge, with conflicts to resolve first".to_string()); }
+		if let Some(c) = u.merge_clean.get(path)
+		{
+			return Some(match (c.resolved, c.normalized) {
+				(true, _)      => "merge (conflicts resolved)",
+				(false, true)  => "merge (cleanly, after normalizing \
+						whitespace)",
+				(false, false) => "merge (cleanly)",
+			}.to_string());
+		}
+		if u.skipped.contains_key(path)
+		{
+			return Some("skipped as locally modified (UpdateIfUnmodified)"
+					.to_string());
+		}
+	}
+
+	let real = |l: Option<MetadataLine>| l.filter(|l| !matches!(l, ML::Dash(_)));
+	let what = match (real(cur.get_path(path)), real(new.get_path(path))) {
+		(None, None)       => return None,
+		(Some(_), Some(_)) => "update",
+		(None, Some(_))    => "add",
+		(Some(_), None)    => "remove",
+	};
+
+	let kept = match mani {
+		Manifest::Upgrade(u) => u.kept_metadata.iter().any(|k| k.path == path),
+		Manifest::Fetch(_)   => false,
+	};
+	match kept {
+		true  => Some(format!("{what}, keeping local metadata")),
+		false => Some(what.to_string()),
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashMap;
+	use std::path::Path;
+
+	use crate::metadata::{Metadata, MetadataGroup, MetaFile};
+	use crate::util::hash::Sha256Hash;
+	use super::Disposition as D;
+
+	fn hash(s: &str) -> Sha256Hash
+	{
+		crate::util::hash::sha256_reader(&mut s.as_bytes()).unwrap()
+	}
+
+	fn md(files: &[(&str, &str)]) -> Metadata
+	{
+		let files = files.iter().map(|(p, c)| ((*p).into(), MetaFile {
+				path: (*p).into(), sha256: hash(c), mode: 0o644,
+				..Default::default() })).collect();
+		Metadata { files, ..Default::default() }
+	}
+
+	fn group(comp: &str, files: &[(&str, &str)]) -> MetadataGroup
+	{
+		let mut hm = HashMap::new();
+		hm.insert(comp.parse().unwrap(), md(files));
+		MetadataGroup::from_components(hm)
+	}
+
+	fn conf(extra: &str) -> crate::config::Config
+	{
+		let c = format!("Components world\n{extra}");
+		crate::config::parse(c.as_bytes()).0
+	}
+
+	/// old has v1, new has v2, and the system has whatever we say.
+	fn why(path: &str, comp: &str, cur: &str, extra: &str) -> super::Explanation
+	{
+		why_as(path, comp, cur, extra, false)
+	}
+
+	fn why_as(path: &str, comp: &str, cur: &str, extra: &str, upgrade: bool)
+			-> super::Explanation
+	{
+		let files = |v| [("/etc/a.conf", v), ("/bin/sh", v)];
+		let old = group(comp, &files("v1"));
+		let new = group(comp, &files("v2"));
+		let cur = md(&[(path, cur)]);
+		super::explain(Path::new(path), old, new, cur, &conf(extra), upgrade)
+	}
+
+	#[test]
+	fn dispositions()
+	{
+		let bs = "/bin/sh";
+		let ac = "/etc/a.conf";
+
+		let e = why(bs, "world/base", "v1", "");
+		assert_eq!(e.disposition, D::Update, "{e:?}");
+		assert_eq!(e.components, [("world/base".to_string(), true)]);
+		assert!(e.old.is_some() && e.new.is_some() && e.cur.is_some());
+
+		let e = why(bs, "world/base", "v2", "");
+		assert_eq!(e.disposition, D::Current, "{e:?}");
+
+		let e = why("/nowhere", "world/base", "v1", "");
+		assert_eq!(e.disposition, D::NotUpstream, "{e:?}");
+
+		let e = why(bs, "src/src", "v1", "");
+		assert_eq!(e.disposition, D::InactiveComponent, "{e:?}");
+		assert_eq!(e.components, [("src/src".to_string(), false)]);
+
+		let e = why(bs, "world/base", "v1", "IgnorePaths /bin/");
+		assert_eq!(e.disposition, D::Ignored, "{e:?}");
+		assert_eq!(e.rule, "IgnorePaths pattern `^/bin/`");
+
+		// Modified locally under UpdateIfUnmodified is left alone;
+		// unmodified still updates, but we note the pattern.
+		let uium = "UpdateIfUnmodified /etc/\nMergeChanges /etc/";
+		let e = why(ac, "world/base", "mine", uium);
+		assert_eq!(e.disposition, D::SkippedModified, "{e:?}");
+		assert!(e.rule.contains("`^/etc/`"), "{e:?}");
+		let e = why(ac, "world/base", "v1", uium);
+		assert_eq!(e.disposition, D::Update, "{e:?}");
+		let pats: Vec<_> = e.patterns.iter().map(|(n, _)| *n).collect();
+		assert_eq!(pats, ["UpdateIfUnmodified", "MergeChanges"]);
+
+		// An upgrade merges the modified one instead, but the unmodified
+		// one's the same either way.
+		let e = why_as(ac, "world/base", "mine", uium, true);
+		assert_eq!(e.disposition, D::Merge, "{e:?}");
+		assert!(e.rule.starts_with("MergeChanges pattern `^/etc/`"), "{e:?}");
+		let e = why_as(ac, "world/base", "v1", uium, true);
+		assert_eq!(e.disposition, D::Update, "{e:?}");
+
+		// Without MergeChanges, it's skipped there too
+		let e = why_as(ac, "world/base", "mine", "UpdateIfUnmodified /etc/",
+				true);
+		assert_eq!(e.disposition, D::SkippedModified, "{e:?}");
+	}
+
+	#[test]
+	fn allow_delete()
+	{
+		let csh = "/bin/csh";
+		let why_del = |extra| {
+			let old = group("world/base", &[(csh, "v1"), ("/bin/sh", "v1")]);
+			let new = group("world/base", &[("/bin/sh", "v2")]);
+			let cur = md(&[(csh, "v1")]);
+			super::explain(Path::new(csh), old, new, cur, &conf(extra), false)
+		};
+
+		let e = why_del("");
+		assert_eq!(e.disposition, D::Remove, "{e:?}");
+		let e = why_del("AllowDelete no");
+		assert_eq!(e.disposition, D::KeptDeleted, "{e:?}");
+
+		// Doesn't get in the way of anything else
+		let e = why("/bin/sh", "world/base", "v1", "AllowDelete no");
+		assert_eq!(e.disposition, D::Update, "{e:?}");
+	}
+
+	#[test]
+	fn pending()
+	{
+		use crate::state::Manifest;
+		use super::pending;
+
+		let cur = md(&[("/bin/sh", "v1"), ("/bin/csh", "v1")]);
+		let new = md(&[("/bin/sh", "v2"), ("/bin/ls", "v2")]);
+		let vers = "14.2-RELEASE-p2".parse().unwrap();
+		let mani = Manifest::new_fetch(cur, new, vers);
+
+		let p = |s| pending(&mani, Path::new(s));
+		assert_eq!(p("/bin/sh").as_deref(), Some("update"));
+		assert_eq!(p("/bin/ls").as_deref(), Some("add"));
+		assert_eq!(p("/bin/csh").as_deref(), Some("remove"));
+		assert_eq!(p("/bin/cat"), None);
+	}
+}
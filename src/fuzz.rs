@@ -0,0 +1,11 @@
+//! Hooks for cargo-fuzz (see fuzz/ at the top).  The things worth
+//! fuzzing are all crate-internal, so they get poked at through here;
+//! none of this is API.
+
+
+/// Run whatever it is through the metadata parser.  Errors are fine;
+/// falling over isn't.
+pub fn metadata(data: &[u8])
+{
+	let _ = crate::metadata::parse_reader(&mut &data[..]);
+}
@@ -1,5 +1,7 @@
 //! Various info lookups
-pub(crate) mod version;
-pub(crate) use version::{Version, AVersion};
+//!
+//! Only the version types are public; x-ref the crate docs.
+pub mod version;
+pub use version::{Version, AVersion};
 
 pub(crate) mod kernel;
@@ -1,15 +1,54 @@
 //! Info about running system version
-
-#[derive(Debug)]
-pub(crate) struct Version
+//!
+//! Version and AVersion are part of the public API; x-ref the crate
+//! docs.  The rest is ours.
+
+/// What a system is running; the kernel and userland versions, like
+/// `freebsd-version -ku` gives.
+///
+/// Parses either from that output (kernel line, then userland), or from a
+/// single version that's taken as both.
+///
+/// ```
+/// use freebsd_rustdate::info::Version;
+///
+/// let v: Version = "14.1-RELEASE-p5\n14.1-RELEASE-p6\n".parse().unwrap();
+/// assert_eq!(v.kernel().to_string(), "14.1-RELEASE-p5");
+/// assert_eq!(v.max().to_string(), "14.1-RELEASE-p6");
+///
+/// let v: Version = "14.2-RELEASE".parse().unwrap();
+/// assert_eq!(v.kernel(), v.user());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Version
 {
 	pub(crate) kernel: AVersion,
 	pub(crate) user: AVersion,
 }
 
-#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
+/// A single FreeBSD version, like "14.1-RELEASE-p6".
+///
+/// These order the way you'd expect; by release number (numerically,
+/// so 9.3 comes before 14.0), then type, then patch level, with no
+/// patch before any.
+///
+/// ```
+/// use freebsd_rustdate::info::AVersion;
+///
+/// let v: AVersion = "14.1-RELEASE-p6".parse().unwrap();
+/// assert_eq!((v.release(), v.reltype(), v.patch()),
+///         ("14.1", "RELEASE", Some(6)));
+/// assert_eq!(v.to_string(), "14.1-RELEASE-p6");
+///
+/// let old: AVersion = "9.3-RELEASE".parse().unwrap();
+/// assert!(old < v);
+/// assert!(v < "14.1-RELEASE-p7".parse().unwrap());
+/// assert!("14.1".parse::<AVersion>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[derive(serde::Serialize, serde::Deserialize)]
-pub(crate) struct AVersion
+pub struct AVersion
 {
 	/// The release: "12.3", "14.0", etc.
 	pub(crate) release: String,
@@ -42,12 +81,67 @@ impl fmt::Display for Version
 }
 
 
+impl AVersion
+{
+	/// The release; "14.1"
+	pub fn release(&self) -> &str { &self.release }
+
+	/// The release type; "RELEASE", "STABLE", "RC1", etc.
+	pub fn reltype(&self) -> &str { &self.reltype }
+
+	/// The patch level, if any
+	pub fn patch(&self) -> Option<u32> { self.patch }
+}
+
+impl Ord for AVersion
+{
+	fn cmp(&self, other: &Self) -> std::cmp::Ordering
+	{
+		// Releases are dotted numbers; anything non-numeric (shouldn't
+		// happen) sorts first, and the string breaks any ties so we
+		// stay consistent with Eq.
+		let nums = |r: &str| -> Vec<u32> {
+			r.split('.').map(|n| n.parse().unwrap_or(0)).collect()
+		};
+		nums(&self.release).cmp(&nums(&other.release))
+				.then_with(|| self.release.cmp(&other.release))
+				.then_with(|| self.reltype.cmp(&other.reltype))
+				.then_with(|| self.patch.cmp(&other.patch))
+	}
+}
+
+impl PartialOrd for AVersion
+{
+	fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering>
+	{ Some(self.cmp(other)) }
+}
+
+
 impl Version
 {
-	pub(crate) fn max(&self) -> &AVersion
+	/// The kernel version
+	pub fn kernel(&self) -> &AVersion { &self.kernel }
+
+	/// The userland version
+	pub fn user(&self) -> &AVersion { &self.user }
+
+	/// Whichever's newer.  This is what we think of as the version the
+	/// system's running.
+	pub fn max(&self) -> &AVersion
 	{
 		std::cmp::max(&self.kernel, &self.user)
 	}
+
+	/// Where we'd be after a patch-level update; ourselves, at the
+	/// patch the server says.  Everything that describes the target of a
+	/// fetch should come from here, so it all agrees (and so it all
+	/// follows --as-version).
+	pub(crate) fn with_patch(&self, patch: Option<u32>) -> AVersion
+	{
+		let mut ret = self.max().clone();
+		ret.patch = patch;
+		ret
+	}
 }
 
 
@@ -77,16 +171,105 @@ use std::path::Path;
 pub(crate) fn get(bdir: &Path) -> Result<Version, anyhow::Error>
 {
 	let vout = run_freebsd_version(bdir)?;
-	parse_freebsd_version(&vout)
+	parse_freebsd_version(&vout).map_err(|e| anyhow::anyhow!("Can't tell \
+			what version this system is running from freebsd-version: \
+			{e}"))
 }
 
 
-/// Generate a fake version from a given string
+/// Generate a fake version from a given string.  This is what somebody
+/// typed, so it's parse_release_arg()'s forgiving parsing.
 pub(crate) fn fake(vstr: &str) -> Result<Version, anyhow::Error>
 {
-	let kernel = parse_version_row(vstr, "kernel")?;
-	let user   = parse_version_row(vstr, "user")?;
-	Ok(Version { kernel, user })
+	let v = parse_release_arg(vstr).map_err(|e| anyhow::anyhow!("Error \
+			in version '{vstr}': {e}"))?;
+	Ok(Version { kernel: v.clone(), user: v })
+}
+
+
+/// Parse a release name somebody typed (e.g., `upgrade -r`).
+///
+/// AVersion's FromStr wants exactly what freebsd-version says, but
+/// people type "14.2", or "14.2-release", or paste "FreeBSD
+/// 14.2-RELEASE-p1" out of uname.  So: an optional "FreeBSD " up front,
+/// a bare MAJOR.MINOR means -RELEASE, and the usual types can be any
+/// case.  What comes out is the canonical form, same as if it'd been
+/// typed that way.
+pub(crate) fn parse_release_arg(s: &str) -> Result<AVersion, String>
+{
+	let s = s.trim();
+	let s = match s.get(..7) {
+		Some(p) if p.eq_ignore_ascii_case("freebsd")
+				&& s[7..].chars().next().map_or(true, |c| c.is_whitespace())
+				=> s[7..].trim_start(),
+		_ => s,
+	};
+	if s.is_empty() { return Err("No version given".to_string()); }
+
+	// Split off the type, and a patch on a bare release (14.2-p1).
+	let (release, rest) = match s.split_once('-') {
+		Some((r, t)) => (r, Some(t)),
+		None => (s, None),
+	};
+	let rest = match rest {
+		None => "RELEASE".to_string(),
+		Some(t) if is_patch(t) => format!("RELEASE-p{}", &t[1..]),
+		Some(t) => norm_type(t),
+	};
+
+	// The release has to be numbers, and at least MAJOR.MINOR; "14"
+	// could be any of several.
+	let nums: Vec<&str> = release.split('.').collect();
+	let numeric = nums.iter()
+			.all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+	if !numeric
+	{
+		Err(format!("'{s}' doesn't look like a release (e.g., \
+				14.2-RELEASE)"))?
+	}
+	if nums.len() < 2
+	{
+		Err(format!("'{s}' is ambiguous; give MAJOR.MINOR (e.g., \
+				{release}.0)"))?
+	}
+
+	format!("{release}-{rest}").parse()
+}
+
+/// Is this a patch level (p3, P3)?
+fn is_patch(s: &str) -> bool
+{
+	let mut b = s.bytes();
+	matches!(b.next(), Some(b'p' | b'P')) && s.len() > 1
+			&& b.all(|b| b.is_ascii_digit())
+}
+
+/// The type (and maybe patch) bit of a typed release, with the types we
+/// know uppercased, and -P3 made -p3.  Anything we don't know is left
+/// as given, to get turned away (or not) like it would be anyway.
+fn norm_type(t: &str) -> String
+{
+	let (ty, pat) = match t.rsplit_once('-') {
+		Some((ty, p)) if is_patch(p) => (ty, Some(&p[1..])),
+		_ => (t, None),
+	};
+
+	let up = ty.to_ascii_uppercase();
+	let counted = ["ALPHA", "BETA", "RC"].iter().any(|k| {
+		up.strip_prefix(*k).is_some_and(|n| !n.is_empty()
+				&& n.bytes().all(|b| b.is_ascii_digit()))
+	});
+	let known = counted
+			|| ["RELEASE", "STABLE", "CURRENT", "PRERELEASE"].contains(&&*up);
+	let ty = match known {
+		true  => up,
+		false => ty.to_string(),
+	};
+
+	match pat {
+		Some(p) => format!("{ty}-p{p}"),
+		None    => ty,
+	}
 }
 
 
@@ -134,9 +317,26 @@ fn parse_freebsd_version(vout: &[u8]) -> Result<Version, anyhow::Error>
 	Ok(Version { kernel, user })
 }
 
+impl std::str::FromStr for Version
+{
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err>
+	{
+		let lines: Vec<_> = s.lines().map(|l| l.trim())
+				.filter(|l| !l.is_empty()).collect();
+		match lines[..] {
+			[one] => fake(one).map_err(|e| e.to_string()),
+			_ => parse_freebsd_version(s.as_bytes())
+					.map_err(|e| e.to_string()),
+		}
+	}
+}
+
 fn parse_version_row(row: &str, rdesc: &str) -> Result<AVersion, anyhow::Error>
 {
-	row.parse().map_err(|e| anyhow::anyhow!("Error in {rdesc}: {e}"))
+	row.parse().map_err(|e| anyhow::anyhow!("Error in {rdesc} version \
+			'{row}': {e}"))
 }
 
 impl std::str::FromStr for AVersion
@@ -145,19 +345,22 @@ impl std::str::FromStr for AVersion
 
 	fn from_str(s: &str) -> Result<Self, Self::Err>
 	{
-		// <version>-p<patch>
-		let mut riter = s.split("-p");
-		let relbit = riter.next().ok_or("No release")?;
-		let pat = riter.next();
-
-		// <version> = 1.2-STABLE, etc
-		let mut rsp = relbit.split("-");
-		let release = rsp.next()
-			.map(|s| s.to_string())
-			.ok_or_else(|| format!("No version"))?;
-		let reltype = rsp.next()
-			.map(|s| s.to_string())
+		// <version>-p<patch>.  Only a trailing -p<digits> is a patch
+		// level; custom builds can have about anything after the type.
+		let (relbit, pat) = match s.rsplit_once("-p") {
+			Some((r, p)) if !p.is_empty()
+					&& p.bytes().all(|b| b.is_ascii_digit()) => (r, Some(p)),
+			_ => (s, None),
+		};
+
+		// <version> = 1.2-STABLE, etc.  Whatever follows the release is
+		// the type, dashes and all, so "14.2-STABLE-mybuild" still
+		// parses, and gets turned away later as not being a release.
+		let (release, reltype) = relbit.split_once('-')
 			.ok_or_else(|| format!("No version type"))?;
+		if release.is_empty() { Err(format!("No version"))? }
+		if reltype.is_empty() { Err(format!("No version type"))? }
+		let (release, reltype) = (release.to_string(), reltype.to_string());
 
 		let patch = match pat {
 			None => None,
@@ -210,6 +413,78 @@ mod tests
 		assert_eq!(vers.user.patch,   Some(1));
 	}
 
+	#[test]
+	fn reltypes()
+	{
+		let av = |s: &str| -> (String, String, Option<u32>) {
+			let v: AVersion = s.parse().unwrap();
+			assert_eq!(v.to_string(), s, "round trip");
+			(v.release, v.reltype, v.patch)
+		};
+		let want = |r: &str, t: &str, p| (r.to_string(), t.to_string(), p);
+
+		assert_eq!(av("14.2-STABLE"), want("14.2", "STABLE", None));
+		assert_eq!(av("15.0-CURRENT"), want("15.0", "CURRENT", None));
+		assert_eq!(av("15.0-ALPHA3"), want("15.0", "ALPHA3", None));
+		assert_eq!(av("14.3-BETA2-p1"), want("14.3", "BETA2", Some(1)));
+		assert_eq!(av("14.3-RC1"), want("14.3", "RC1", None));
+		assert_eq!(av("14.3-RC1-p2"), want("14.3", "RC1", Some(2)));
+		assert_eq!(av("13.4-PRERELEASE"), want("13.4", "PRERELEASE", None));
+
+		// Custom builds can stick whatever on the end; it's all type
+		assert_eq!(av("14.2-STABLE-mybuild"),
+				want("14.2", "STABLE-mybuild", None));
+		assert_eq!(av("14.2-STABLE-pfsense"),
+				want("14.2", "STABLE-pfsense", None));
+		assert_eq!(av("14.2-STABLE-mybuild-p3"),
+				want("14.2", "STABLE-mybuild", Some(3)));
+
+		// Still have to have at least a release and a type
+		for bad in ["14.2", "14.2-", "-RELEASE", "", "14.2-RELEASE-p99999999999"]
+		{ assert!(bad.parse::<AVersion>().is_err(), "{bad}"); }
+
+		// And it says what it couldn't make sense of
+		let err = parse_freebsd_version(b"14.2\n14.2-RELEASE\n")
+				.unwrap_err().to_string();
+		assert!(err.contains("kernel version '14.2'"), "{err}");
+	}
+
+	#[test]
+	fn with_patch()
+	{
+		// Faked, it's all the fake, patch and all, whatever the host
+		// might be.
+		let vers = super::fake("13.2-RELEASE-p7").unwrap();
+		assert_eq!(vers.to_string(), "13.2-RELEASE-p7");
+		assert_eq!(vers.with_patch(Some(9)).to_string(), "13.2-RELEASE-p9");
+		assert_eq!(vers.with_patch(None).to_string(), "13.2-RELEASE");
+
+		// Otherwise, it's from whichever of kernel/user is further along
+		let vout = b"14.1-RELEASE-p2\n14.1-RELEASE-p4\n";
+		let vers = parse_freebsd_version(vout).unwrap();
+		assert_eq!(vers.with_patch(Some(5)).to_string(), "14.1-RELEASE-p5");
+	}
+
+	#[test]
+	fn ordering()
+	{
+		let av = |s: &str| -> AVersion { s.parse().unwrap() };
+
+		let mut vs = vec![av("14.1-RELEASE-p2"), av("9.3-RELEASE"),
+				av("14.1-RELEASE"), av("14.0-STABLE"), av("13.10-RELEASE"),
+				av("14.1-RC1"), av("14.1-RELEASE-p10")];
+		vs.sort();
+		let got: Vec<_> = vs.iter().map(|v| v.to_string()).collect();
+		assert_eq!(got, ["9.3-RELEASE", "13.10-RELEASE", "14.0-STABLE",
+				"14.1-RC1", "14.1-RELEASE", "14.1-RELEASE-p2",
+				"14.1-RELEASE-p10"]);
+
+		// And max() goes by that, not string order
+		let vers: Version = "9.3-RELEASE\n10.1-RELEASE\n".parse().unwrap();
+		assert_eq!(vers.max().to_string(), "10.1-RELEASE");
+		assert!("".parse::<Version>().is_err());
+	}
+
 	#[test]
 	fn display_version()
 	{
@@ -222,4 +497,62 @@ mod tests
 		vers.kernel.patch = Some(3);
 		assert_eq!(vers.to_string(), "12.3-RELEASE-p3");
 	}
+
+
+	#[test]
+	fn release_arg()
+	{
+		let ok = [
+			("14.2",                       "14.2-RELEASE"),
+			("14.2-RELEASE",               "14.2-RELEASE"),
+			("14.2-release",               "14.2-RELEASE"),
+			("14.2-Release-p3",            "14.2-RELEASE-p3"),
+			("14.2-RELEASE-P3",            "14.2-RELEASE-p3"),
+			("14.2-p3",                    "14.2-RELEASE-p3"),
+			("  14.2  ",                   "14.2-RELEASE"),
+			("FreeBSD 14.2-RELEASE-p1",    "14.2-RELEASE-p1"),
+			("freebsd 14.2",               "14.2-RELEASE"),
+			("15.0-rc2",                   "15.0-RC2"),
+			("15.0-beta1",                 "15.0-BETA1"),
+			("15.0-alpha3-p1",             "15.0-ALPHA3-p1"),
+			("15.0-current",               "15.0-CURRENT"),
+			("13.4-prerelease",            "13.4-PRERELEASE"),
+			("14.2-STABLE-mybuild",        "14.2-STABLE-mybuild"),
+			("1.2.3-RC1-p1",               "1.2.3-RC1-p1"),
+		];
+		for (given, want) in ok
+		{
+			let got = parse_release_arg(given)
+					.unwrap_or_else(|e| panic!("{given}: {e}"));
+			assert_eq!(got.to_string(), want, "{given}");
+
+			// And it's the same thing as the canonical form, as far as
+			// anything downstream can tell.
+			assert_eq!(got, want.parse::<AVersion>().unwrap(), "{given}");
+		}
+
+		let bad = [
+			("",                 "No version given"),
+			("FreeBSD ",         "No version given"),
+			("14",               "'14' is ambiguous; give MAJOR.MINOR \
+					(e.g., 14.0)"),
+			("14-p1",            "'14-p1' is ambiguous"),
+			("fourteen",         "'fourteen' doesn't look like a release"),
+			("14.2.",            "'14.2.' doesn't look like a release"),
+			("v14.2",            "'v14.2' doesn't look like a release"),
+			("14.2-",            "No version type"),
+			("-RELEASE",         "'-RELEASE' doesn't look like a release"),
+			("14.2-RELEASE-p99999999999", "Bad patch version"),
+		];
+		for (given, want) in bad
+		{
+			let err = parse_release_arg(given).unwrap_err();
+			assert!(err.starts_with(want), "{given}: {err}");
+		}
+
+		// --as-version goes the same way
+		assert_eq!(fake("14.2").unwrap().to_string(), "14.2-RELEASE");
+		let err = fake("14").unwrap_err().to_string();
+		assert!(err.contains("ambiguous"), "{err}");
+	}
 }
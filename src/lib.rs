@@ -1,16 +1,44 @@
 //! Main freebsd-rustdate impl lib
+//!
+//! Mostly this is just the guts of the freebsd-rustdate binary, but a few
+//! pieces are public, for other tools that want to handle things exactly
+//! the way we do (say, validating generated freebsd-update.conf files):
+//!
+//! - [`config::Config::load`], parsing a config file's contents.
+//! - [`components::Component`] and its parts, as found in `Components`.
+//! - [`info::version::AVersion`] and [`info::version::Version`], FreeBSD
+//!   version strings.
+//!
+//! Those follow semver; anything else that happens to be reachable (like
+//! `command`) doesn't, and may change in any release.
+//!
+//! ```
+//! use freebsd_rustdate::config::Config;
+//! use freebsd_rustdate::components::Component;
+//!
+//! let conf = Config::load(b"Components src world/lib32\n").unwrap();
+//! let lib32: Component = "world/lib32".parse().unwrap();
+//! assert!(conf.components().contains(&lib32));
+//! ```
 
+/// Our own version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // Config
 pub mod config;
 
-// Commands and args
+// Commands and args.  Public only so the binary can get at it; not part
+// of the stable API.
+#[doc(hidden)]
 pub mod command;
 
 // Components-related bits
 pub mod components;
 
+// Entry points for cargo-fuzz; not part of the API either.
+#[doc(hidden)]
+pub mod fuzz;
+
 // Misc shared core pieces
 mod core;
 
@@ -21,7 +49,7 @@ mod util;
 mod check;
 
 // Loading up info about the system
-mod info;
+pub mod info;
 
 // The state of an in-progress upgrade
 mod state;
@@ -5,6 +5,7 @@ fn main() -> Result<ExitCode, anyhow::Error>
 {
 	// What are we supposed to do?
 	let cmd = FR::command::parse();
+	FR::command::set_sigpipe(&cmd);
 
 	// Doit
 	FR::command::run(cmd)
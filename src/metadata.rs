@@ -13,12 +13,14 @@ pub(crate) use idx::MetadataIdx;
 /// Structs for the info
 mod structs;
 pub(crate) use structs::{MetaFile, MetaHardLink, MetaDir, MetaSymLink};
+pub(crate) use structs::{MetaPerms, MetaKept};
 use structs::MetaDash;
 pub(crate) use structs::Metadata;
 
 /// Full parsing
 mod parse;
 pub(crate) use parse::ParseFileErr;
+pub(crate) use parse::reader as parse_reader;
 
 /// MetadataGroup handling; this is most of the things related to a given
 /// metadata file.
@@ -31,7 +33,7 @@ pub(crate) use group::MetadataGroup;
 mod line;
 pub(crate) use line::MetadataLine;
 pub(crate) use line::MetaChange;
-//pub(crate) use line::MetadataLineDiff;
+pub(crate) use line::MetadataLineDiff;
 
 /// Metadata handling; once we've dealt with components, we do a lot on
 /// the collected Metadata ifself.
@@ -45,6 +47,21 @@ mod files;
 mod split;
 pub(crate) use split::SplitTypes;
 
+/// Accounting for what IgnorePaths removed.
+mod ignored;
+pub(crate) use ignored::IgnoreReport;
+
+/// Keeping upstream metadata to where base lives.
+mod prefixes;
+
+/// Quick matching of paths against lots of patterns.
+mod pathmatch;
+pub(crate) use pathmatch::PathMatcher;
+
+/// Flattening metadata out into uniform rows, for dumping.
+mod flat;
+pub(crate) use flat::FlatLine;
+
 
 
 
@@ -6,6 +6,21 @@ use super::{Metadata, MetaFile};
 use crate::util::hash;
 
 
+/// How a Metadata::stash_files() went.
+#[derive(Debug, Default)]
+pub(crate) struct Stashed
+{
+	/// How many hashfiles we wrote
+	pub(crate) stashed: usize,
+
+	/// How many were already there
+	pub(crate) existed: usize,
+
+	/// Files we couldn't stash, and why, sorted
+	pub(crate) failed: Vec<(PathBuf, String)>,
+}
+
+
 impl Metadata
 {
 	/// Get a list of all the files in a metadata that don't have
@@ -51,11 +66,20 @@ impl Metadata
 	/// things we expect to need against our <filesdir> stash.
 	fn files_hashdir_compare_be(&self, hdir: &Path) -> Option<Vec<&MetaFile>>
 	{
-		let mut ret = Vec::new();
+		// One pass over the dir is a lot cheaper than a stat() for each
+		// of thousands of files, and makes re-checking after a stash
+		// that died partway cheap.  If we can't read it for some reason,
+		// fall back to looking one at a time.
+		let have = hashdir_names(hdir);
+		let present = |hgz: &str| match &have {
+			Some(h) => h.contains(hgz),
+			None    => hdir.join(hgz).is_file(),
+		};
 
+		let mut ret = Vec::new();
 		self.files.iter().for_each(|(_fpath, mf)| {
 			let hgz = format!("{}.gz", mf.sha256);
-			if !hdir.join(hgz).is_file() { ret.push(mf) }
+			if !present(&hgz) { ret.push(mf) }
 		});
 
 		match ret.len() {
@@ -70,9 +94,14 @@ impl Metadata
 	/// stash into.  This sticks the files into <filehash>.gz in that
 	/// dir.  This is used to store up unmodified copies and rollback
 	/// data.
+	///
+	/// Failures on individual files don't stop the rest; they come back
+	/// in the Stashed for the caller to decide how much they matter.
+	/// Whatever did get stashed stays, so the next run's
+	/// files_no_hash_dir() won't include it.
 	pub(crate) fn stash_files(&self, files: &[&Path],
 			basedir: PathBuf, tmpdir: PathBuf, filesdir: PathBuf)
-			-> Result<usize, anyhow::Error>
+			-> Result<Stashed, anyhow::Error>
 	{
 		use crate::core::pool::stash as pool;
 
@@ -81,13 +110,13 @@ impl Metadata
 		// hardlinks), so we can get duplicated hashes, which means we
 		// could get collisions in the process.
 		let mut hseen = std::collections::HashSet::new();
-		let reqs: Vec<pool::Req> = files.iter().filter_map(|p| {
+		let reqs = files.iter().filter_map(|p| {
 			let path = p.to_path_buf();
 			let hash = self.files[&path].sha256.to_buf();
 			if hseen.contains(&hash) { return None; }
 			hseen.insert(hash.clone());
 			Some(pool::Req { path, hash })
-		}).collect();
+		});
 
 		// We build a threadpool do to this
 		let ctrl = pool::Control { basedir, filesdir, tmpdir };
@@ -101,13 +130,12 @@ impl Metadata
 
 		// See what we got
 		let pool::PoolResult { oks, errs } = stres;
-
-		// No errors (presumptively normal) means everything's peachy.
-		// Errors means not so peachy
-		match errs {
-			Some(e) => Err(e)?,
-			None    => Ok(oks.len()),
-		}
+		let existed = oks.iter().filter(|r| r.existed).count();
+		let stashed = oks.len() - existed;
+		let mut failed: Vec<_> = errs.map(|e| e.errs).unwrap_or_default()
+				.into_iter().map(|(p, e)| (p, e.to_string())).collect();
+		failed.sort_unstable();
+		Ok(Stashed { stashed, existed, failed })
 	}
 
 
@@ -135,3 +163,16 @@ impl Metadata
 		}
 	}
 }
+
+
+/// The names of all the files in a hashdir, if we can read it.
+fn hashdir_names(hdir: &Path) -> Option<std::collections::HashSet<String>>
+{
+	let rd = std::fs::read_dir(hdir).ok()?;
+	let names = rd.filter_map(|de| {
+		let de = de.ok()?;
+		if !de.file_type().ok()?.is_file() { return None; }
+		de.file_name().into_string().ok()
+	}).collect();
+	Some(names)
+}
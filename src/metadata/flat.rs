@@ -0,0 +1,178 @@
+//! Flattened-out metadata, for dumping.
+//!
+//! This is the parsed-out contents of a metadata file, turned back into
+//! one row per path in a uniform shape, so it can be handed off to
+//! something that doesn't want to know about the INDEX format (JSON,
+//! CSV, etc).
+use std::path::{Path, PathBuf};
+
+use crate::components::Component;
+use super::{Metadata, MetadataGroup};
+
+
+/// One row of flattened metadata.
+///
+/// Fields that don't apply to a given type (e.g., a hash on a dir) are
+/// None.  Mode and flags are octal strings, as in the INDEX files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize)]
+pub(crate) struct FlatLine
+{
+	pub(crate) path: PathBuf,
+	#[serde(rename = "type")]
+	pub(crate) ftype: &'static str,
+	pub(crate) uid: Option<u32>,
+	pub(crate) gid: Option<u32>,
+	pub(crate) mode: Option<String>,
+	pub(crate) flags: Option<String>,
+	pub(crate) sha256: Option<String>,
+	pub(crate) target: Option<PathBuf>,
+	pub(crate) component: String,
+}
+
+
+impl FlatLine
+{
+	/// Column names, in the order csv_row() puts things.
+	pub(crate) const CSV_HEADER: &'static str =
+			"path,type,uid,gid,mode,flags,sha256,target,component";
+
+	/// Build up a line of CSV.
+	pub(crate) fn csv_row(&self) -> String
+	{
+		fn opt<T: ToString>(v: &Option<T>) -> String
+		{
+			v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+		}
+		fn path(p: &Path) -> String { csv_quote(&p.to_string_lossy()) }
+
+		let target = self.target.as_deref().map(path).unwrap_or_default();
+		[path(&self.path), self.ftype.to_string(), opt(&self.uid),
+				opt(&self.gid), opt(&self.mode), opt(&self.flags),
+				opt(&self.sha256), target, self.component.clone()]
+			.join(",")
+	}
+}
+
+
+/// Quote a CSV field if it needs it.  Paths can have about anything in
+/// them.
+fn csv_quote(s: &str) -> String
+{
+	match s.contains([',', '"', '\n', '\r'])
+	{
+		true  => format!("\"{}\"", s.replace('"', "\"\"")),
+		false => s.to_string(),
+	}
+}
+
+
+/// Octal, the way the INDEX files show it.
+fn oct_mode(v: u32)  -> Option<String> { Some(format!("{v:04o}")) }
+fn oct_flags(v: u32) -> Option<String> { Some(format!("{v:o}")) }
+
+
+impl Metadata
+{
+	/// Flatten out all our entries, tagged with the given component.
+	fn flatten(&self, comp: &Component) -> Vec<FlatLine>
+	{
+		let component = comp.to_string();
+		let blank = |path: &Path, ftype| FlatLine {
+			path: path.to_path_buf(), ftype, uid: None, gid: None,
+			mode: None, flags: None, sha256: None, target: None,
+			component: component.clone(),
+		};
+
+		let mut ret = Vec::with_capacity(self.len());
+		ret.extend(self.files.values().map(|f| FlatLine {
+			uid: Some(f.uid), gid: Some(f.gid),
+			mode: oct_mode(f.mode), flags: oct_flags(f.flags),
+			sha256: Some(f.sha256.to_string()),
+			..blank(&f.path, "file")
+		}));
+		ret.extend(self.hardlinks.values().map(|h| FlatLine {
+			target: Some(h.target.clone()),
+			..blank(&h.path, "hardlink")
+		}));
+		ret.extend(self.dirs.values().map(|d| FlatLine {
+			uid: Some(d.uid), gid: Some(d.gid),
+			mode: oct_mode(d.mode), flags: oct_flags(d.flags),
+			..blank(&d.path, "directory")
+		}));
+		ret.extend(self.symlinks.values().map(|s| FlatLine {
+			uid: Some(s.uid), gid: Some(s.gid),
+			mode: oct_mode(s.mode), flags: oct_flags(s.flags),
+			target: Some(s.target.clone()),
+			..blank(&s.path, "symlink")
+		}));
+		ret.extend(self.dashes.iter().map(|p| blank(p, "dashline")));
+		ret
+	}
+}
+
+
+impl MetadataGroup
+{
+	/// Flatten out everything in the group, sorted by path (and then
+	/// component, in the unlikely case of a path showing up in more than
+	/// one), so the output is stable across runs.
+	pub(crate) fn flatten(&self) -> Vec<FlatLine>
+	{
+		let mut ret: Vec<FlatLine> = self.md.iter()
+				.flat_map(|(comp, md)| md.flatten(comp)).collect();
+		ret.sort_unstable_by(|a, b| a.path.cmp(&b.path)
+				.then_with(|| a.component.cmp(&b.component)));
+		ret
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	fn mk_mdg() -> super::MetadataGroup
+	{
+		let lines = r##"
+world|base|/bin/[|f|0|0|0555|0|3ad985a50b79037b9672cf197fbc67bd54766199e190055101ea7d8c64ca843b|
+world|base|/bin/test|f|0|0|0555|0|3ad985a50b79037b9672cf197fbc67bd54766199e190055101ea7d8c64ca843b|/bin/[
+world|base|/var/empty|d|0|0|0555|400000||
+kernel|generic|/boot/kernel/if_igb.ko|L|0|0|0755|0|if_em.ko|
+world|base|/nonexistent|-||||||
+"##;
+		crate::metadata::parse::reader(&mut lines.as_bytes())
+				.expect("should parse")
+	}
+
+	#[test]
+	fn flatten()
+	{
+		let flat = mk_mdg().flatten();
+		let paths: Vec<_> = flat.iter().map(|f| f.path.to_str().unwrap())
+				.collect();
+		assert_eq!(paths, ["/bin/[", "/bin/test", "/boot/kernel/if_igb.ko",
+				"/nonexistent", "/var/empty"], "sorted by path");
+
+		let dir = &flat[4];
+		assert_eq!(dir.ftype, "directory");
+		assert_eq!(dir.mode.as_deref(), Some("0555"));
+		assert_eq!(dir.flags.as_deref(), Some("400000"));
+		assert_eq!(dir.sha256, None);
+		assert_eq!(dir.component, "world/base");
+
+		let hl = &flat[1];
+		assert_eq!(hl.ftype, "hardlink");
+		assert_eq!(hl.target.as_deref(), Some("/bin/[".as_ref()));
+	}
+
+	#[test]
+	fn csv()
+	{
+		let flat = mk_mdg().flatten();
+		assert_eq!(flat[2].csv_row(), "/boot/kernel/if_igb.ko,symlink,0,0,\
+				0755,0,,if_em.ko,kernel/generic");
+		assert_eq!(flat[3].csv_row(), "/nonexistent,dashline,,,,,,,world/base");
+		assert_eq!(super::csv_quote("a,\"b\""), "\"a,\"\"b\"\"\"");
+	}
+}
@@ -5,11 +5,12 @@
 //! process where we care about components.  Most code paths will do that
 //! a little, but then have chosen which components they work with, and
 //! converted down to a flat Metadata, which is simpler to work with.
-use std::path::Path;
-use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::components::Component;
 use super::Metadata;
+use super::PathMatcher;
 
 use regex_lite::Regex;
 
@@ -32,6 +33,13 @@ pub(crate) struct MetadataGroup
 
 impl MetadataGroup
 {
+	/// Build one up from already-separated components.
+	pub(crate) fn from_components(md: HashMap<Component, Metadata>) -> Self
+	{
+		Self { md }
+	}
+
+
 	/// Get a list of all the pathnames in this MetadataGroup.
 	pub(crate) fn allpaths(&self) -> Vec<&Path>
 	{
@@ -53,6 +61,14 @@ impl MetadataGroup
 	}
 
 
+	/// The per-component Metadata's, for things that just want to look
+	/// through all of them.
+	pub(crate) fn parts(&self) -> Vec<&Metadata>
+	{
+		self.md.values().collect()
+	}
+
+
 	/// Get a list of what components this Group has
 	pub(crate) fn components(&self) -> HashSet<Component>
 	{
@@ -60,6 +76,22 @@ impl MetadataGroup
 	}
 
 
+	/// Which components have an entry for a given path?
+	pub(crate) fn path_components(&self, p: &Path) -> Vec<&Component>
+	{
+		self.md.iter().filter(|(_c, md)| md.get_path(p).is_some())
+				.map(|(c, _md)| c).collect()
+	}
+
+
+	/// Keep [only] the entries matching some set of paths.
+	pub(crate) fn keep_paths(&mut self, paths: &HashSet<&Path>)
+	{
+		self.md.iter_mut()
+				.for_each(|(_comp, md)| md.keep_paths(paths))
+	}
+
+
 	/// Given a list of files, gen a list of which components have >=
 	/// half of their files existing in that list.
 	pub(crate) fn components_check(&self, existing: &HashSet<&Path>)
@@ -84,6 +116,37 @@ impl MetadataGroup
 	}
 
 
+	/// Which of our components don't exist at all in another group
+	/// (i.e., the next release's).  Each of their paths either isn't in
+	/// the other group anywhere, or has moved to some other component.
+	pub(crate) fn vanished_components(&self, new: &Self) -> Vec<Vanished>
+	{
+		let newcomps = new.components();
+		let newpaths: HashSet<&Path> = new.allpaths().into_iter().collect();
+
+		let mut ret: Vec<Vanished> = self.md.iter()
+				.filter(|(comp, _md)| !newcomps.contains(comp))
+				.map(|(comp, md)| {
+			let mut gone = Vec::new();
+			let mut moved = BTreeMap::new();
+			for p in md.allpaths_hashset_nodash()
+			{
+				match newpaths.contains(p) {
+					false => gone.push(p.to_path_buf()),
+					true  => new.path_components(p).into_iter()
+							.for_each(|c| *moved.entry(c.clone())
+								.or_insert(0) += 1),
+				}
+			}
+			gone.sort_unstable();
+			Vanished { comp: comp.clone(), gone, moved }
+		}).collect();
+
+		ret.sort_unstable_by(|a, b| a.comp.cmp(&b.comp));
+		ret
+	}
+
+
 	/// Strip components we don't care about from a MetadataGroup.
 	pub(crate) fn keep_components(&mut self, keep: &HashSet<Component>)
 	{
@@ -102,8 +165,9 @@ impl MetadataGroup
 	/// Strip non-matching paths from a MetadataGroup.
 	pub(crate) fn keep_paths_matching(&mut self, paths: &[Regex])
 	{
+		let pm = PathMatcher::new(paths);
 		self.md.iter_mut()
-				.for_each(|(_comp, md)| md.keep_paths_matching(paths))
+				.for_each(|(_comp, md)| md.keep_paths_matcher(&pm))
 	}
 
 
@@ -114,8 +178,9 @@ impl MetadataGroup
 		// us search in &[u8]'s; regex_lite doesn't seem to.  I'll just
 		// stick here and str-ify for now until it turns out to be a
 		// problem or we get a lot of non-UTF8 paths.
+		let pm = PathMatcher::new(paths);
 		self.md.iter_mut()
-				.for_each(|(_comp, md)| md.remove_paths_matching(paths))
+				.for_each(|(_comp, md)| md.remove_paths_matcher(&pm))
 	}
 
 
@@ -175,6 +240,51 @@ impl MetadataGroup
 
 
 
+/// A component that's gone away; x-ref vanished_components().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Vanished
+{
+	/// What it was
+	pub(crate) comp: Component,
+
+	/// Its paths that don't exist anywhere anymore
+	pub(crate) gone: Vec<PathBuf>,
+
+	/// Where the rest of them went, and how many
+	pub(crate) moved: BTreeMap<Component, usize>,
+}
+
+impl Vanished
+{
+	/// Say what's happening with it, a line at a time.  `delete` is
+	/// whether its files actually go (AllowDelete).
+	pub(crate) fn describe(&self, release: impl std::fmt::Display,
+			delete: bool) -> Vec<String>
+	{
+		use crate::util::plural;
+
+		let Vanished { comp, gone, moved } = self;
+		let ng = gone.len();
+		let fate = match delete {
+			true  => "will be removed",
+			false => "will be left alone (AllowDelete no)",
+		};
+		let mut ret = vec![match ng {
+			0 => format!("Component {comp} no longer exists in {release}."),
+			_ => format!("Component {comp} no longer exists in {release}; \
+					its {ng} file{} {fate}.", plural(ng)),
+		}];
+		for (to, n) in moved
+		{
+			ret.push(format!("  {n} of its files moved to {to}, and will \
+					be updated there."));
+		}
+		ret
+	}
+}
+
+
+
 // In many cases, once we've keep_components()'d a MetadataGroup, we no
 // longer care about the component layer, so there's value in just
 // flattening down a single list.
@@ -258,6 +368,62 @@ world|base|/etc/pam.d/xdm|f|0|0|0644|0|3c6c43e880a1215022980e169b1e044bfdb38c66d
 	}
 
 
+	#[test]
+	fn vanished_components()
+	{
+		use std::collections::BTreeMap;
+		use crate::metadata::parse::reader;
+
+		let hash = "871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0";
+		let line = |c: &str, p: &str| format!("world|{c}|{p}|f|0|0|0644|0|{hash}|\n");
+		let group = |ls: &[(&str, &str)]| {
+			let s: String = ls.iter().map(|(c, p)| line(c, p)).collect();
+			reader(&mut s.as_bytes()).unwrap()
+		};
+		let lib32dbg: Component = "world/lib32-dbg".parse().unwrap();
+		let basedbg: Component = "world/base-dbg".parse().unwrap();
+
+		let old = group(&[("base", "/bin/sh"), ("lib32", "/usr/lib32/libc.so.7"),
+				("lib32", "/usr/lib32/libm.so.5"), ("lib32", "/usr/bin/ldd32"),
+				("lib32-dbg", "/usr/lib/debug/usr/lib32/libc.so.7.debug")]);
+
+		// Nothing changed, nothing vanished
+		assert!(old.vanished_components(&old).is_empty());
+
+		// Something appearing doesn't count either
+		let mut appeared = old.clone();
+		appeared.md.extend(group(&[("base-dbg", "/usr/lib/debug/bin/sh.debug")]).md);
+		assert!(old.vanished_components(&appeared).is_empty());
+
+		// lib32 goes away, with ldd32 moving over to base; lib32-dbg
+		// gets renamed, more or less.
+		let new = group(&[("base", "/bin/sh"), ("base", "/usr/bin/ldd32"),
+				("base-dbg", "/usr/lib/debug/usr/lib32/libc.so.7.debug")]);
+		let van = old.vanished_components(&new);
+		assert_eq!(van.len(), 2);
+
+		assert_eq!(van[0].comp, lib32_comp());
+		assert_eq!(van[0].gone, ["/usr/lib32/libc.so.7", "/usr/lib32/libm.so.5"]
+				.map(std::path::PathBuf::from));
+		assert_eq!(van[0].moved, BTreeMap::from([(base_comp(), 1)]));
+		let desc = van[0].describe("15.0-RELEASE", true);
+		assert_eq!(desc[0], "Component world/lib32 no longer exists in \
+				15.0-RELEASE; its 2 files will be removed.");
+		assert_eq!(desc[1], "  1 of its files moved to world/base, and will \
+				be updated there.");
+		let desc = van[0].describe("15.0-RELEASE", false);
+		assert_eq!(desc[0], "Component world/lib32 no longer exists in \
+				15.0-RELEASE; its 2 files will be left alone \
+				(AllowDelete no).");
+
+		assert_eq!(van[1].comp, lib32dbg);
+		assert!(van[1].gone.is_empty());
+		assert_eq!(van[1].moved, BTreeMap::from([(basedbg, 1)]));
+		assert_eq!(van[1].describe("15.0-RELEASE", true)[0], "Component \
+				world/lib32-dbg no longer exists in 15.0-RELEASE.");
+	}
+
+
 	#[test]
 	fn remove_keep_paths_matching()
 	{
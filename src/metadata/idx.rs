@@ -81,14 +81,39 @@ impl MetadataIdx
 		new
 	}
 
+	/// Fill in whatever other has, over what we've got.
+	pub(crate) fn update_from(&mut self, other: &Self)
+	{
+		if other.hash_all.is_some() { self.hash_all = other.hash_all.clone(); }
+		if other.hash_new.is_some() { self.hash_new = other.hash_new.clone(); }
+		if other.hash_old.is_some() { self.hash_old = other.hash_old.clone(); }
+	}
+
 	fn alltypes() -> &'static [&'static str]
 	{
 		&["all", "new", "old"]
 	}
 
+	/// Which entries we have that aren't the same in another index?
+	/// This is for checking a saved [sub]set against a full index.
+	pub(crate) fn mismatches(&self, other: &Self) -> Vec<&'static str>
+	{
+		Self::alltypes().iter().filter(|t| {
+			match self.get(t) {
+				None => false,
+				Some(h) => other.get(t) != Some(h),
+			}
+		}).copied().collect()
+	}
+
 
 	/// Which of our metadata files don't exist in a given output dir?
-	pub(crate) fn not_in_dir(&self, dir: &Path, which:&[impl AsRef<str>])
+	///
+	/// If we've got a decompression cache, anything good already in there
+	/// doesn't count as missing.  Bad entries get thrown out, and we'll
+	/// need the .gz after all.
+	pub(crate) fn not_in_dir(&self, dir: &Path, cache: Option<&Path>,
+			which:&[impl AsRef<str>])
 			-> HashSet<String>
 	{
 		let mut missing = HashSet::new();
@@ -96,14 +121,19 @@ impl MetadataIdx
 		let check_file_hash = |h: &str| -> bool {
 			dir.join(h).is_file()
 		};
+		let in_cache = |h: &str| -> bool {
+			cache.map(|c| cache_good(c, h)).unwrap_or(false)
+		};
 
 		// As with f-u.sh, our goal here is "get the <which> entries from
 		// our current metadata ($TINDEXHASH)".  Higher levels also do
 		// the "and entries from previous runs' metadata (tINDEX.present)
 		// if they differ" by doing this in another &self.
 		self.get_matching(which).iter().for_each(|h| {
-			let gzfile = format!("{}.gz", h);
-			if !check_file_hash(&gzfile) { missing.insert(gzfile); }
+			let hash = h.to_string();
+			let gzfile = format!("{hash}.gz");
+			if !check_file_hash(&gzfile) && !in_cache(&hash)
+			{ missing.insert(gzfile); }
 		});
 
 		missing
@@ -117,64 +147,111 @@ impl MetadataIdx
 	/// "waste" a little temporary space to save decompressing multiple
 	/// times, like the sh does.
 	///
-	/// Returns Ok or the list of files with mismatched sums
+	/// If `cache` is given, it's a persistent dir of already
+	/// decompressed files (named by their hash).  Anything good in there
+	/// gets used instead of decompressing again, and anything we do
+	/// decompress gets put in there for next time.  Entries that don't
+	/// match their name, or that we can't use for whatever reason, are
+	/// just a miss.
+	///
+	/// .gz files that are actually bad (wrong hash, corrupt gzip data)
+	/// are deleted, so they'll be re-fetched.  Failures on our end of
+	/// things (like running out of space decompressing) aren't the
+	/// file's fault, so they're left alone.
+	///
+	/// Returns Ok or the list of problems
 	pub(crate) fn check_hashes(&self, fromdir: &Path, todir: &Path,
-			which:&[impl AsRef<str>])
-			-> Result<(), Vec<String>>
+			cache: Option<&Path>, which:&[impl AsRef<str>])
+			-> Result<(), Vec<MetaFileErr>>
 	{
-		// Get the list of .gz filenames
-		let mut mdfiles = Vec::with_capacity(which.len());
-		self.get_matching(which).iter().for_each(|h| {
-			let gzfile = format!("{}.gz", h);
-			mdfiles.push(gzfile);
-		});
+		use crate::util::hash;
+		use crate::util::compress::{decompress_gz_file_sides, GzErr};
+		use hash::Sha256ReaderErr as SRE;
 
-		// Iter over 'em and accumulate any wrong'uns
-		let mut efiles = Vec::new();
-		for f in mdfiles.iter()
+		let mut errs = Vec::new();
+		for h in self.get_matching(which)
 		{
 			// The basename is the SHA256, which makes it simple.
-			// We'll decompress and cache the decompressed files in
-			// our tmpdir.
-			let hash = f.trim_end_matches(".gz");
-
-			macro_rules! err { () => { efiles.push(f); } }
-
-			// Get the decompressed file
-			use crate::util::compress;
-			let dret = compress::decompress_gz_dirs(fromdir, todir, f);
-			let pf = match dret {
-				Ok(file) => file,
-				Err(_) => { err!(); continue; },
-			};
+			let hash = h.to_string();
+			let gzfile = fromdir.join(format!("{hash}.gz"));
+			let outfile = todir.join(&hash);
+
+			// Already got it decompressed this run?  (Shouldn't happen,
+			// but nothing says we can't be called twice)
+			if outfile.is_file()
+					&& hash::check_sha256_file(&outfile, &hash).is_ok()
+			{ continue; }
+
+			// Got a good one in the cache?
+			if let Some(cdir) = cache
+			{
+				if cache_get(cdir, &hash, &outfile) { continue; }
+			}
 
-			// Shouldn't be possible
-			if !pf.is_file() { err!(); continue; }
+			// Decompress it out
+			match decompress_gz_file_sides(&gzfile, &outfile) {
+				Ok(()) => (),
+				Err(GzErr::Open(p, e)) => {
+					use std::io::ErrorKind as EK;
+					match e.kind() {
+						EK::NotFound => errs.push(MetaFileErr::Missing(p)),
+						_ => errs.push(MetaFileErr::Local(p, e)),
+					}
+					continue;
+				},
+				Err(GzErr::Read(p, e)) => {
+					let _ = std::fs::remove_file(&p);
+					errs.push(MetaFileErr::Corrupt(p, e));
+					continue;
+				},
+				Err(GzErr::Write(p, e)) => {
+					errs.push(MetaFileErr::Local(p, e));
+					continue;
+				},
+			}
 
 			// OK, now we can check its hash
-			use crate::util::hash;
-			match hash::check_sha256_file(&pf, hash) {
+			match hash::check_sha256_file(&outfile, &hash) {
 				Ok(()) => (),
-				Err(_) => { err!(); continue; },
+				Err(SRE::IO(e)) => {
+					errs.push(MetaFileErr::Local(outfile, e));
+					continue;
+				},
+				Err(_) => {
+					let _ = std::fs::remove_file(&outfile);
+					let _ = std::fs::remove_file(&gzfile);
+					errs.push(MetaFileErr::Mismatch(gzfile));
+					continue;
+				},
 			};
+
+			// Good, so stash it for next time.  Not having the cache
+			// work out isn't worth failing over.
+			if let Some(cdir) = cache
+			{ cache_put(cdir, &hash, &outfile); }
 		}
 
+		match errs.is_empty() {
+			true  => Ok(()),
+			false => Err(errs),
+		}
+	}
+
 
-		// If it was all OK, then we're done
-		if efiles.len() == 0 { return Ok(()); }
-
-		// If not, come up with at least mildly useful errors.
-		let eret = efiles.into_iter().map(|p| {
-				let path = fromdir.join(p);
-				let pdis = path.display();
-				if path.exists()
-				{
-					std::fs::remove_file(&path).unwrap();
-					format!("{pdis}: mismatched checksum, deleting.")
-				}
-				else { format!("{pdis}: missing") }
-			}).collect();
-		Err(eret)
+	/// Throw out cache entries nothing's used in a while (and any
+	/// leftover temp copies).  It's only a cache, so trouble here isn't
+	/// worth mentioning.
+	pub(crate) fn prune_cache(cdir: &Path)
+	{
+		let Ok(rd) = std::fs::read_dir(cdir) else { return };
+		let now = std::time::SystemTime::now();
+		for de in rd.flatten()
+		{
+			let old = de.metadata().and_then(|m| m.modified()).ok()
+					.and_then(|t| now.duration_since(t).ok())
+					.is_some_and(|age| age > CACHE_MAX_AGE);
+			if old { let _ = std::fs::remove_file(de.path()); }
+		}
 	}
 
 
@@ -213,9 +290,13 @@ impl MetadataIdx
 
 	/// Handy frontend: parse out a single metadata file from this index,
 	/// and do the common alterations to its contents.
+	///
+	/// Also returns the accounting of what got removed via the ignore
+	/// paths config, for callers that want to report on it.
 	pub(crate) fn parse_one_full(&self, which: &str,
 			dir: &Path, config: &crate::config::Config)
-			-> Result<super::MetadataGroup, anyhow::Error>
+			-> Result<(super::MetadataGroup, super::IgnoreReport),
+				anyhow::Error>
 	{
 		let mut mdg = match self.parse_one(dir, which) {
 			Ok(m) => m,
@@ -227,17 +308,132 @@ impl MetadataIdx
 			},
 		};
 
+		// Drop anything somewhere base has no business being, before
+		// it gets any further.
+		if let Some(w) = super::prefixes::enforce(&mut mdg, which, config)
+		{
+			println!("");
+			eprintln!("{w}");
+		}
+
 		// Make the various alterations to the contents of the metadata
-		// we generally want to do.
+		// we generally want to do.  If a session's being recorded,
+		// what each step left gets noted.
+		use crate::core::session::note;
+		let comps = |m: &super::MetadataGroup| {
+			let mut cs: Vec<_> = m.md.keys().map(|c| c.to_string()).collect();
+			cs.sort_unstable();
+			cs.join(" ")
+		};
+		note(format_args!("{which}: {} paths, components {}", mdg.len(),
+				comps(&mdg)));
 		mdg.keep_components(&config.components);
-		mdg.remove_paths_matching(&config.ignore_paths);
+		note(format_args!("{which}: {} paths after keeping components {}",
+				mdg.len(), comps(&mdg)));
+		let ignored = mdg.remove_paths_matching_report(&config.ignore_paths);
+		if !config.include_only.is_empty()
+		{ mdg.keep_paths_matching(&config.include_only); }
+		note(format_args!("{which}: {} paths after IgnorePaths/IncludeOnly",
+				mdg.len()));
 		mdg.rewrite_kern_dirs()?;
 
 		// And there it is.
-		Ok(mdg)
+		Ok((mdg, ignored))
+	}
+}
+
+/// Something wrong with a metadata file in check_hashes().
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+pub(crate) enum MetaFileErr
+{
+	/// Not there at all
+	#[error("{}: missing", .0.display())]
+	Missing(PathBuf),
+
+	/// Decompressed fine, but to the wrong thing
+	#[error("{}: mismatched checksum, deleting.", .0.display())]
+	Mismatch(PathBuf),
+
+	/// Not valid gzip data (corrupt, truncated)
+	#[error("{}: corrupt ({1}), deleting.", .0.display())]
+	Corrupt(PathBuf, std::io::Error),
+
+	/// Something went wrong on our end (out of space, etc); the file
+	/// itself may be fine, so it's kept.
+	#[error("{}: local error ({1}), keeping.", .0.display())]
+	Local(PathBuf, std::io::Error),
+}
+
+impl MetaFileErr
+{
+	/// Is this a problem with the file (vs. with us)?
+	pub(crate) fn bad_file(&self) -> bool
+	{
+		!matches!(self, Self::Local(..))
+	}
+}
+
+
+/// How long a cache entry can go unused before prune_cache() tosses it.
+const CACHE_MAX_AGE: std::time::Duration
+		= std::time::Duration::from_secs(30 * 86400);
+
+/// Is there a good entry in the cache for this hash?  Bad ones get
+/// removed.
+fn cache_good(cdir: &Path, hash: &str) -> bool
+{
+	use crate::util::hash::check_sha256_file;
+
+	let cfile = cdir.join(hash);
+	if !cfile.is_file() { return false; }
+	if check_sha256_file(&cfile, hash).is_err()
+	{
+		let _ = std::fs::remove_file(&cfile);
+		return false;
 	}
+	true
 }
 
+/// Try to pull a decompressed metadata file out of the cache into place.
+/// Returns whether we got a good one; anything going wrong is just a
+/// miss.
+fn cache_get(cdir: &Path, hash: &str, outfile: &Path) -> bool
+{
+	if !cache_good(cdir, hash) { return false; }
+
+	// They're both in the workdir, so linking should usually work.
+	let cfile = cdir.join(hash);
+	let _ = std::fs::remove_file(outfile);
+	if std::fs::hard_link(&cfile, outfile).is_err()
+			&& std::fs::copy(&cfile, outfile).is_err()
+	{ return false; }
+
+	// Keep it from looking unused to prune_cache()
+	if let Ok(fh) = std::fs::File::open(&cfile)
+	{ let _ = fh.set_modified(std::time::SystemTime::now()); }
+	true
+}
+
+
+/// Stash a (known good) decompressed metadata file in the cache.
+fn cache_put(cdir: &Path, hash: &str, file: &Path)
+{
+	let cfile = cdir.join(hash);
+	if cfile.exists() { return; }
+	if std::fs::hard_link(file, &cfile).is_err()
+	{
+		// Copy to a temp name so a partial copy never looks like a
+		// real entry.
+		let tmpf = cdir.join(format!(".{hash}.tmp"));
+		match std::fs::copy(file, &tmpf) {
+			Ok(_)  => { let _ = std::fs::rename(&tmpf, &cfile); },
+			Err(_) => { let _ = std::fs::remove_file(&tmpf); },
+		}
+	}
+}
+
+
 // XXX It seems like sometimes we might have additional metadata files to
 // work with?  Unclear ATM...
 
@@ -347,4 +543,159 @@ INDEX-OLD|ce46b8868d86aecb5f44d4f9c84b241c5007e28c13d2521db590b9be36c60491
 		let idx = parse_metadataidx(MDIDX.as_bytes()).unwrap();
 		assert_eq!(idx, mk_midx_bits());
 	}
+
+
+	/*
+	 * check_hashes() error handling and the decompression cache
+	 */
+	use std::path::{Path, PathBuf};
+	use super::MetaFileErr;
+	use crate::util::compress::gz_bytes as gz;
+
+	const CONTENT: &str = "world|base|/bin/sh|f|0|0|0555|0|\
+			871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|\n";
+	/// Setup files/tmp/cache dirs, and an index with just "all" pointing
+	/// at CONTENT.  Returns the gz file path too.
+	fn setup_dirs() -> (tempfile::TempDir, MetadataIdx, PathBuf)
+	{
+		let td = tempfile::tempdir().unwrap();
+		for d in ["files", "tmp", "cache"]
+		{ std::fs::create_dir(td.path().join(d)).unwrap(); }
+
+		let hash = crate::util::hash::sha256_reader(&mut CONTENT.as_bytes())
+				.unwrap();
+		let mut idx = MetadataIdx::default();
+		idx.hash_all = Some(hash.clone());
+		let gzf = td.path().join("files").join(format!("{hash}.gz"));
+		(td, idx, gzf)
+	}
+
+	fn check(td: &Path, idx: &MetadataIdx, cache: bool)
+			-> Result<(), Vec<MetaFileErr>>
+	{
+		let cdir = td.join("cache");
+		let cache = if cache { Some(cdir.as_path()) } else { None };
+		idx.check_hashes(&td.join("files"), &td.join("tmp"), cache, &["all"])
+	}
+
+	#[test]
+	fn check_hashes_good()
+	{
+		let (td, idx, gzf) = setup_dirs();
+		std::fs::write(&gzf, gz(CONTENT.as_bytes())).unwrap();
+		check(td.path(), &idx, false).unwrap();
+		assert!(idx.one_tmpfile(&td.path().join("tmp"), "all").unwrap()
+				.is_file(), "decompressed out");
+	}
+
+	#[test]
+	fn check_hashes_mismatch()
+	{
+		let (td, idx, gzf) = setup_dirs();
+		std::fs::write(&gzf, gz(b"something else")).unwrap();
+		let errs = check(td.path(), &idx, false).unwrap_err();
+		assert!(matches!(errs[..], [MetaFileErr::Mismatch(_)]), "{errs:?}");
+		assert!(!gzf.exists(), "mismatched file deleted");
+	}
+
+	#[test]
+	fn check_hashes_corrupt()
+	{
+		let (td, idx, gzf) = setup_dirs();
+
+		// Not gzip at all
+		std::fs::write(&gzf, b"this is not gzip data").unwrap();
+		let errs = check(td.path(), &idx, false).unwrap_err();
+		assert!(matches!(errs[..], [MetaFileErr::Corrupt(..)]), "{errs:?}");
+		assert!(!gzf.exists(), "corrupt file deleted");
+
+		// Truncated
+		let mut gzd = gz(CONTENT.as_bytes());
+		gzd.truncate(gzd.len() / 2);
+		std::fs::write(&gzf, gzd).unwrap();
+		let errs = check(td.path(), &idx, false).unwrap_err();
+		assert!(matches!(errs[..], [MetaFileErr::Corrupt(..)]), "{errs:?}");
+		assert!(errs[0].bad_file());
+		assert!(!gzf.exists(), "truncated file deleted");
+	}
+
+	#[test]
+	fn check_hashes_missing()
+	{
+		let (td, idx, _gzf) = setup_dirs();
+		let errs = check(td.path(), &idx, false).unwrap_err();
+		assert!(matches!(errs[..], [MetaFileErr::Missing(_)]), "{errs:?}");
+	}
+
+	#[test]
+	fn check_hashes_local()
+	{
+		// Can't write the output (standing in for a full disk); that's
+		// not the .gz's fault, so it stays.
+		let (td, idx, gzf) = setup_dirs();
+		std::fs::write(&gzf, gz(CONTENT.as_bytes())).unwrap();
+		std::fs::remove_dir(td.path().join("tmp")).unwrap();
+		let errs = check(td.path(), &idx, false).unwrap_err();
+		assert!(matches!(errs[..], [MetaFileErr::Local(..)]), "{errs:?}");
+		assert!(!errs[0].bad_file());
+		assert!(gzf.exists(), "local failure keeps the file");
+	}
+
+	#[test]
+	fn check_hashes_cache()
+	{
+		let (td, idx, gzf) = setup_dirs();
+		let files = td.path().join("files");
+		let cdir = td.path().join("cache");
+		let hash = idx.hash_all.as_ref().unwrap().to_string();
+		let centry = cdir.join(&hash);
+
+		// Miss: decompresses and populates the cache
+		std::fs::write(&gzf, gz(CONTENT.as_bytes())).unwrap();
+		check(td.path(), &idx, true).unwrap();
+		assert_eq!(std::fs::read(&centry).unwrap(), CONTENT.as_bytes());
+
+		// Hit: no .gz needed at all, and it's not "missing"
+		std::fs::remove_file(&gzf).unwrap();
+		std::fs::remove_file(td.path().join("tmp").join(&hash)).unwrap();
+		assert!(idx.not_in_dir(&files, Some(&cdir), &["all"]).is_empty());
+		assert_eq!(idx.not_in_dir(&files, None, &["all"]).len(), 1);
+		check(td.path(), &idx, true).unwrap();
+		assert!(td.path().join("tmp").join(&hash).is_file(), "from cache");
+
+		// Bad cache entry is just a miss; it gets tossed, so we know to
+		// go get the .gz after all.
+		std::fs::remove_file(td.path().join("tmp").join(&hash)).unwrap();
+		std::fs::remove_file(&centry).unwrap();
+		std::fs::write(&centry, b"garbage").unwrap();
+		assert_eq!(idx.not_in_dir(&files, Some(&cdir), &["all"]).len(), 1);
+		assert!(!centry.exists(), "bad cache entry removed");
+
+		std::fs::write(&centry, b"garbage").unwrap();
+		std::fs::write(&gzf, gz(CONTENT.as_bytes())).unwrap();
+		check(td.path(), &idx, true).unwrap();
+		assert_eq!(std::fs::read(&centry).unwrap(), CONTENT.as_bytes(),
+				"cache repopulated");
+	}
+
+	#[test]
+	fn prune_cache()
+	{
+		use std::time::{Duration, SystemTime};
+
+		let td = tempfile::tempdir().unwrap();
+		let (fresh, stale) = (td.path().join("fresh"), td.path().join("stale"));
+		std::fs::write(&fresh, b"x").unwrap();
+		std::fs::write(&stale, b"x").unwrap();
+		let then = SystemTime::now() - super::CACHE_MAX_AGE
+				- Duration::from_secs(60);
+		std::fs::File::open(&stale).unwrap().set_modified(then).unwrap();
+
+		MetadataIdx::prune_cache(td.path());
+		assert!(fresh.exists());
+		assert!(!stale.exists());
+
+		// Nothing there is fine too
+		MetadataIdx::prune_cache(&td.path().join("nope"));
+	}
 }
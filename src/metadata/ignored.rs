@@ -0,0 +1,207 @@
+//! Accounting for paths removed by IgnorePaths-style filtering.
+//!
+//! Removing ignored paths is the whole point of the config, but when
+//! auditing it's useful to know just what got removed, and to notice
+//! patterns that don't match anything anymore.
+use std::path::{Path, PathBuf};
+use std::collections::BTreeSet;
+
+use super::{Metadata, MetadataGroup, PathMatcher};
+
+use regex_lite::Regex;
+
+
+/// What each of a set of ignore patterns removed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IgnoreReport
+{
+	/// Each pattern, and the paths it matched.  Kept in the order given
+	/// (which is generally config file order).  A path may show up under
+	/// multiple patterns, if they overlap.
+	pub(crate) patterns: Vec<(Regex, BTreeSet<PathBuf>)>,
+
+	/// All of them at once, so we can quickly skip the (usual) paths
+	/// nothing matches before going through them one by one.
+	matcher: PathMatcher,
+}
+
+
+impl IgnoreReport
+{
+	/// Setup an empty report for a set of patterns.
+	pub(crate) fn new(res: &[Regex]) -> Self
+	{
+		let patterns = res.iter().map(|r| (r.clone(), BTreeSet::new()))
+				.collect();
+		let matcher = PathMatcher::new(res);
+		Self { patterns, matcher }
+	}
+
+
+	/// Check a path against our patterns, recording which match.
+	/// Returns whether any did.
+	pub(crate) fn record(&mut self, p: &Path) -> bool
+	{
+		let pstr = p.to_string_lossy();
+		if !self.matcher.is_match(&pstr) { return false; }
+
+		let mut matched = false;
+		for (re, paths) in self.patterns.iter_mut()
+		{
+			if re.is_match(&pstr)
+			{
+				paths.insert(p.to_path_buf());
+				matched = true;
+			}
+		}
+		matched
+	}
+
+
+	/// Absorb the results from another report on the same set of
+	/// patterns (e.g., from another metadata file).
+	pub(crate) fn merge(&mut self, other: Self)
+	{
+		for (mine, theirs) in self.patterns.iter_mut().zip(other.patterns)
+		{
+			debug_assert_eq!(mine.0.as_str(), theirs.0.as_str());
+			mine.1.extend(theirs.1);
+		}
+	}
+
+
+	/// How many distinct paths were ignored in total?
+	pub(crate) fn total(&self) -> usize
+	{
+		let all: BTreeSet<_> = self.patterns.iter()
+				.flat_map(|(_, p)| p.iter()).collect();
+		all.len()
+	}
+
+
+	/// Patterns that didn't match anything.
+	pub(crate) fn unused(&self) -> Vec<&str>
+	{
+		self.patterns.iter().filter(|(_, p)| p.is_empty())
+				.map(|(r, _)| r.as_str()).collect()
+	}
+
+
+	/// Show the summary, and if given a file, write out the full lists
+	/// to it.
+	pub(crate) fn show(&self, full: Option<&Path>)
+			-> Result<(), std::io::Error>
+	{
+		use crate::util::plural;
+
+		let tot = self.total();
+		println!("\n{tot} path{} ignored via IgnorePaths:", plural(tot));
+		for (re, paths) in &self.patterns
+		{
+			let n = paths.len();
+			println!("  {n:>7}  {}", re.as_str());
+		}
+
+		let unused = self.unused();
+		if unused.len() > 0
+		{
+			println!("Patterns matching nothing (stale config?):");
+			for u in unused { println!("  {u}"); }
+		}
+
+		if let Some(f) = full
+		{
+			use std::io::Write as _;
+			let fh = std::fs::File::create(f)?;
+			let mut bw = std::io::BufWriter::new(fh);
+			for (re, paths) in &self.patterns
+			{
+				writeln!(bw, "# {}", re.as_str())?;
+				for p in paths { writeln!(bw, "{}", p.display())?; }
+				writeln!(bw, "")?;
+			}
+			bw.flush()?;
+			println!("Full ignored path lists written to {}", f.display());
+		}
+		println!("");
+
+		Ok(())
+	}
+}
+
+
+
+impl Metadata
+{
+	/// Remove entries matching a set of regexps, recording what was
+	/// removed in an IgnoreReport.
+	pub(crate) fn remove_paths_matching_report(&mut self,
+			rpt: &mut IgnoreReport)
+	{
+		self.files.retain(|k, _v|     { !rpt.record(k) });
+		self.dirs.retain(|k, _v|      { !rpt.record(k) });
+		self.symlinks.retain(|k, _v|  { !rpt.record(k) });
+		self.hardlinks.retain(|k, _v| { !rpt.record(k) });
+		self.dashes.retain(|k|        { !rpt.record(k) });
+	}
+}
+
+
+impl MetadataGroup
+{
+	/// Strip matching paths from a MetadataGroup, and return the
+	/// accounting of what each pattern removed.
+	pub(crate) fn remove_paths_matching_report(&mut self, paths: &[Regex])
+			-> IgnoreReport
+	{
+		let mut rpt = IgnoreReport::new(paths);
+		self.md.iter_mut()
+				.for_each(|(_comp, md)| md.remove_paths_matching_report(&mut rpt));
+		rpt
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use regex_lite::Regex;
+
+	#[test]
+	fn overlapping_patterns()
+	{
+		let mdlines = r##"
+world|base|/usr/share/doc/a|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/usr/share/doc/b|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/usr/share/man/c|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/usr/bin/d|f|0|0|0555|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+src|src|/usr/src/e|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+"##;
+		let mut rdr = mdlines.as_bytes();
+		let mut mdg = crate::metadata::parse::reader(&mut rdr).unwrap();
+
+		let res = [
+			Regex::new("^/usr/share").unwrap(),
+			Regex::new("^/usr/share/doc").unwrap(),
+			Regex::new("^/usr/src").unwrap(),
+			Regex::new("^/nonexistent").unwrap(),
+		];
+		let rpt = mdg.remove_paths_matching_report(&res);
+
+		// Only /usr/bin/d is left
+		assert_eq!(mdg.len(), 1, "1 path left");
+
+		// Overlapping patterns both get credit, but the total is unique
+		let counts: Vec<_> = rpt.patterns.iter().map(|(_, p)| p.len())
+				.collect();
+		assert_eq!(counts, [3, 2, 1, 0], "per-pattern counts");
+		assert_eq!(rpt.total(), 4, "4 distinct paths");
+		assert_eq!(rpt.unused(), ["^/nonexistent"], "stale pattern");
+
+		// Merging in another run sums up unique paths
+		let mut rpt2 = rpt.clone();
+		rpt2.merge(rpt.clone());
+		assert_eq!(rpt2.total(), 4, "merge doesn't duplicate");
+	}
+}
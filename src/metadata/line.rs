@@ -125,13 +125,13 @@ pub(crate) enum MetadataLineDiff
 	/// Hash differs  (files)
 	Sha256(Sha256Hash, Sha256Hash),
 
-	/// Owning uid  (files, dirs)
+	/// Owning uid  (files, dirs, symlinks)
 	Uid(uid_t, uid_t),
 
-	/// Owning gid  (files, dirs)
+	/// Owning gid  (files, dirs, symlinks)
 	Gid(gid_t, gid_t),
 
-	/// File mode  (files, dirs)
+	/// File mode  (files, dirs, symlinks)
 	Mode(mode_t, mode_t),
 
 	/// Flags  (files, dirs)
@@ -246,16 +246,21 @@ impl MetadataLine
 				return Ok(None);
 			},
 
-			// Hard/symlinks only compare target
+			// Hardlinks only compare target
 			L::HardLink(s) => {
 				let o = typed!(HardLink);
 				mkdiff!(s, o);
 				if s.target != o.target { diff!(Target, target); }
 			},
+
+			// Symlinks have a target, and their own ownership too
 			L::SymLink(s) => {
 				let o = typed!(SymLink);
 				mkdiff!(s, o);
 				if s.target != o.target { diff!(Target, target); }
+				if s.uid != o.uid { diff!(Uid, uid); }
+				if s.gid != o.gid { diff!(Gid, gid); }
+				if s.mode != o.mode { diff!(Mode, mode); }
 			},
 
 			// Dirs have a full set of ownership
@@ -353,4 +358,33 @@ mod tests
 		let gotdis = &d[0].to_string();
 		assert_eq!(&expdis, gotdis, "Got right Display for differing target");
 	}
+
+
+	#[test]
+	fn symlink_diff()
+	{
+		use metadata::MetadataLine as ML;
+		use metadata::MetaSymLink;
+		use super::MetadataLineDiff as MLD;
+
+		let sl = MetaSymLink { path: "/usr/bin/l".into(),
+				target: "../../bin/x".into(), uid: 0, gid: 0, mode: 0o755,
+				flags: 0 };
+		let sll = ML::SymLink(sl.clone());
+		assert!(sll.diff(&sll.clone()).unwrap().is_none());
+
+		// Left behind by an unprivileged extract: wrong owner, and a
+		// different mode.
+		let mine = MetaSymLink { uid: 1001, gid: 1001, mode: 0o777,
+				..sl.clone() };
+		let d = ML::SymLink(mine).diff(&sll).unwrap().expect("diffs");
+		let dt: Vec<_> = d.iter().map(|d| d.dtype()).collect();
+		assert_eq!(dt, ["uid", "gid", "mode"]);
+		assert!(matches!(d[2], MLD::Mode(0o777, 0o755)), "{:?}", d[2]);
+
+		// The target still counts too
+		let mine = MetaSymLink { target: "/bin/x".into(), ..sl };
+		let d = ML::SymLink(mine).diff(&sll).unwrap().expect("diffs");
+		assert!(matches!(d[..], [MLD::Target(..)]), "{d:?}");
+	}
 }
@@ -5,6 +5,7 @@ use std::collections::{HashSet, HashMap};
 use super::Metadata;
 use super::MetaChange;
 use super::MetadataLine;
+use super::PathMatcher;
 
 use regex_lite::Regex;
 
@@ -58,6 +59,38 @@ impl Metadata
 		self.allpaths_hashset_inner(false)
 	}
 
+	/// Split our dashes (in a scan result, the paths we looked for and
+	/// didn't find) into (really missing, expected to be absent), going
+	/// by the upstream metadata we scanned for.
+	///
+	/// This is where the policy on dash lines lives.  Upstream uses them
+	/// to say "known not to be present" (e.g., in old, for things only
+	/// new brings in); scans reuse them for "looked, not there".  So:
+	///
+	/// - Scanning: dash paths get looked at like everything else
+	///   (allpaths() includes them), since something sitting where
+	///   upstream says nothing should be is worth knowing about.
+	/// - Comparing: a dash matches a dash, like any identical entries,
+	///   and is a difference against anything real.  x-ref
+	///   filter::modified_present() for the cur-vs-old case.
+	/// - Installing: a dash never makes us install or remove anything;
+	///   that side uses allpaths_hashset_nodash().
+	///
+	/// And here: a path isn't "missing" unless some upstream has a real
+	/// entry for it, and none of them says it shouldn't be there.
+	pub(crate) fn split_missing(&self, upstream: &[&Metadata])
+			-> (usize, usize)
+	{
+		let real = |p: &Path| upstream.iter().any(|u| {
+			u.files.contains_key(p) || u.dirs.contains_key(p)
+				|| u.symlinks.contains_key(p) || u.hardlinks.contains_key(p)
+		});
+		let absent = |p: &Path| upstream.iter().any(|u| u.dashes.contains(p));
+		let missing = self.dashes.iter()
+				.filter(|p| real(p) && !absent(p)).count();
+		(missing, self.dashes.len() - missing)
+	}
+
 	// Inner impl of building up the paths
 	fn allpaths_hashset_inner(&self, dashes: bool) -> HashSet<&Path>
 	{
@@ -187,10 +220,8 @@ impl Metadata
 	/// Retain paths matching a set of regexes.
 	pub(crate) fn filter_paths_regexps(&mut self, re: &[Regex])
 	{
-		let matches = |p: &PathBuf| -> bool {
-			let f = p.to_string_lossy();
-			re.iter().any(|r| r.is_match(&f))
-		};
+		let pm = PathMatcher::new(re);
+		let matches = |p: &PathBuf| pm.is_match_path(p);
 
 		self.dirs.retain(      |k, _v| matches(k));
 		self.files.retain(     |k, _v| matches(k));
@@ -246,9 +277,14 @@ impl Metadata
 	}
 
 
-	/// Copy metadata from another MD.
+	/// Copy metadata from another MD.  Returns where that overrode
+	/// something different, sorted by path.
 	pub(crate) fn replace_metadata_from(&mut self, other: &Self)
+			-> Vec<super::MetaKept>
 	{
+		use super::{MetaKept, MetaPerms};
+		let mut kept = Vec::new();
+
 		// Only file/symlink/dir have metadata
 		macro_rules! copy {
 			($fld:ident) => {
@@ -258,6 +294,13 @@ impl Metadata
 						Some(b) => b,
 						None    => continue,
 					};
+					let upstream = MetaPerms { uid: a.uid, gid: a.gid,
+							mode: a.mode, flags: a.flags };
+					let local = MetaPerms { uid: b.uid, gid: b.gid,
+							mode: b.mode, flags: b.flags };
+					if upstream != local
+					{ kept.push(MetaKept { path: ak.clone(), upstream, local }); }
+
 					a.uid   = b.uid;
 					a.gid   = b.gid;
 					a.mode  = b.mode;
@@ -268,6 +311,9 @@ impl Metadata
 		copy!(files);
 		copy!(dirs);
 		copy!(symlinks);
+
+		kept.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+		kept
 	}
 
 
@@ -311,8 +357,7 @@ impl Metadata
 			Some(&*a) != other.hardlinks.get(k)
 		});
 
-		// It's unclear that the dash lines ever really matter where
-		// we're using this, but for completeness...
+		// Dash matches dash; x-ref split_missing() on the policy.
 		self.dashes.retain(|k| !other.dashes.contains(k));
 	}
 
@@ -354,8 +399,7 @@ impl Metadata
 			if matched { ret.insert(k.to_path_buf()); }
 		});
 
-		// It's unclear that the dash lines ever really matter where
-		// we're using this, but for completeness...
+		// x-ref remove_matching_inner() on dashes
 		self.dashes.iter().for_each(|k| {
 			if other.dashes.contains(k) { ret.insert(k.to_path_buf()); }
 		});
@@ -392,13 +436,10 @@ impl Metadata
 	}
 
 
-	/// Remove entries with the path matching some set of regexps.
-	pub(crate) fn remove_paths_matching(&mut self, paths: &[Regex])
+	/// Remove entries with the path matching a prebuilt PathMatcher.
+	pub(crate) fn remove_paths_matcher(&mut self, pm: &PathMatcher)
 	{
-		let matching = |p: &Path| -> bool {
-			let pstr = p.to_string_lossy();
-			paths.iter().any(|r| r.is_match(&pstr))
-		};
+		let matching = |p: &Path| pm.is_match_path(p);
 
 		self.files.retain(|k, _v|     { !matching(k) });
 		self.dirs.retain(|k, _v|      { !matching(k) });
@@ -408,13 +449,10 @@ impl Metadata
 	}
 
 
-	/// Keep [only] the entries matching some set of paths.
-	pub(crate) fn keep_paths_matching(&mut self, paths: &[Regex])
+	/// Keep [only] the entries matching a prebuilt PathMatcher.
+	pub(crate) fn keep_paths_matcher(&mut self, pm: &PathMatcher)
 	{
-		let matching = |p: &Path| -> bool {
-			let pstr = p.to_string_lossy();
-			paths.iter().any(|r| r.is_match(&pstr))
-		};
+		let matching = |p: &Path| pm.is_match_path(p);
 
 		self.files.retain(|k, _v|     { matching(k) });
 		self.dirs.retain(|k, _v|      { matching(k) });
@@ -456,8 +494,8 @@ impl Metadata
 		// doing something like remove_matching(), except just removing
 		// things that are the same type.
 		//
-		// I think we'll just ignore the crap outta the Dash lines, they
-		// seem underspecified and probably don't matter here.
+		// Dash lines don't count; "not there" to something isn't a type
+		// change we do anything about.  x-ref split_missing().
 		let mut cur = self.clone();
 		cur.files.retain(     |k, _v| !other.files.contains_key(k));
 		cur.dirs.retain(      |k, _v| !other.dirs.contains_key(k));
@@ -612,6 +650,42 @@ mod tests
 	}
 
 
+	#[test]
+	fn replace_metadata_kept()
+	{
+		use crate::metadata::MetaFile;
+
+		// Upstream tightens a config file's mode, but we'd loosened ours
+		let fname: PathBuf = "/etc/secret.conf".into();
+		let mk = |mode| MetaFile { path: fname.clone(), mode,
+				..Default::default() };
+		let mut old = Metadata::default();
+		let mut new = Metadata::default();
+		let mut cur = Metadata::default();
+		old.files.insert(fname.clone(), mk(0o644));
+		new.files.insert(fname.clone(), mk(0o600));
+		cur.files.insert(fname.clone(), mk(0o664));
+
+		// And one that's locally modified, but upstream agrees now
+		let same: PathBuf = "/etc/same.conf".into();
+		let mks = |mode| MetaFile { path: same.clone(), mode,
+				..Default::default() };
+		old.files.insert(same.clone(), mks(0o644));
+		new.files.insert(same.clone(), mks(0o640));
+		cur.files.insert(same.clone(), mks(0o640));
+
+		let modd = cur.modified_metadata(&old);
+		let kept = new.replace_metadata_from(&modd);
+
+		// We still keep ours, but it's on the record, not silent
+		assert_eq!(new.files[&fname].mode, 0o664);
+		assert_eq!(kept.len(), 1, "{kept:?}");
+		assert_eq!(kept[0].path, fname);
+		assert_eq!(kept[0].upstream.mode, 0o600);
+		assert_eq!(kept[0].local.mode, 0o664);
+	}
+
+
 	#[test]
 	fn remove_matching()
 	{
@@ -704,12 +778,12 @@ mod tests
 		use regex_lite::Regex;
 		let fo_re  = Regex::new(r"/fo").unwrap();
 		let baz_re = Regex::new(r"/baz").unwrap();
-		let both_re = &[fo_re, baz_re];
+		let both = crate::metadata::PathMatcher::new(&[fo_re, baz_re]);
 
 		// Try keeping just the /fo's
 		let mut md1 = md.clone();
 		assert_eq!(md1.len(), 2, "Starts with 2");
-		md1.keep_paths_matching(both_re);
+		md1.keep_paths_matcher(&both);
 		assert_eq!(md1.len(), 1, "Ended with 1");
 		assert!(md1.dashes.contains(&pfoo), "Kept foo");
 		assert!(!md1.dashes.contains(&pbar), "Lost bar");
@@ -717,7 +791,7 @@ mod tests
 		// And removing
 		let mut md1 = md.clone();
 		assert_eq!(md1.len(), 2, "Starts with 2");
-		md1.remove_paths_matching(both_re);
+		md1.remove_paths_matcher(&both);
 		assert_eq!(md1.len(), 1, "Ended with 1");
 		assert!(!md1.dashes.contains(&pfoo), "Lost foo");
 		assert!(md1.dashes.contains(&pbar), "Kept bar");
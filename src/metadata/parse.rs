@@ -177,93 +177,136 @@ impl std::str::FromStr for ParseLine
 
 		// Next the file/dir/whatever name
 		let path = get_path(flds.next())?;
+		if !path.is_absolute()
+		{
+			anyhow::bail!("{}: path isn't absolute", path.display());
+		}
 
+		// Nothing real is this long, and nothing downstream could do
+		// anything with it anyway.
+		if crate::util::too_long(&path)
+		{
+			let pstr: String = path.to_string_lossy().chars().take(60)
+					.collect();
+			anyhow::bail!("{pstr}...: path too long ({} bytes)",
+					path.as_os_str().len());
+		}
 
-		// And now we have the type
-		let rtype = flds.next().ok_or_else(|| anyhow!("no type field"))?;
-
-		// So handle each type and get the MetadataLine
-		let mdline = match rtype {
-			"f" => {
-				// A "file".  Or maybe a hardlink.
-
-				// First, simple perms
-				let uid   = get_uid(flds.next())?;
-				let gid   = get_gid(flds.next())?;
-				let mode  = get_mode(flds.next())?;
-				let flags = get_flags(flds.next())?;
-
-				// The hash
-				let sha256 = get_sha256(flds.next())?;
-
-				// And maybe a hardlink dest.  This _should_ always
-				// succeed, but it _may_ be an empty string, which turns
-				// into an empty path, so we'll have to use
-				// .is_absolute() to differentiate.
-				let hardlink = get_path(flds.next())?;
-
-				// OK, we got everything; assemble whatever sorta return
-				// we expect.
-				use super::{MetaFile, MetaHardLink};
-				match hardlink.is_absolute() {
-					true  => {
-						// Yep, it's a hardlink
-						let mhl = MetaHardLink { path, target: hardlink };
-						MetadataLine::HardLink(mhl)
-					},
-					false => {
-						// It's a file
-						let mf = MetaFile { path, sha256, uid, gid,
-								mode, flags };
-						MetadataLine::File(mf)
-					},
-				}
-			},
-			"d" => {
-				// A "directory".
-
-				// Has the same perms as a file
-				let uid   = get_uid(flds.next())?;
-				let gid   = get_gid(flds.next())?;
-				let mode  = get_mode(flds.next())?;
-				let flags = get_flags(flds.next())?;
-
-				let md = super::MetaDir { path, uid, gid, mode, flags };
-				MetadataLine::Dir(md)
-			},
-			"L" => {
-				// Symlink time
-
-				// Usual perms
-				let uid   = get_uid(flds.next())?;
-				let gid   = get_gid(flds.next())?;
-				let mode  = get_mode(flds.next())?;
-				let flags = get_flags(flds.next())?;
-
-				// And a destination path
-				let target = get_path(flds.next())?;
-
-				// And that's a symlink
-				let msl = super::MetaSymLink { path, target, uid, gid,
-						mode, flags };
-				MetadataLine::SymLink(msl)
-			},
-			"-" => {
-				// This is...  a "known not present" or something?
-				let md = super::MetaDash { path };
-				MetadataLine::Dash(md)
-			},
-			_ => {
-				// Dunno what this could be
-				Err(anyhow!("Unexpected record type: {rtype}"))?
-			},
-		};
+		// There's no quoting in the format, so a '|' in a path (or a
+		// symlink target) pushes all the later fields over.  That makes
+		// for confusing errors about whatever lands in uid or the like,
+		// or even a silently wrong parse, so call it out up front.  It's
+		// always the full count, even with some left blank; dash lines
+		// can be shorter.
+		let nflds = s.split('|').count();
+		if nflds > NFIELDS
+		{
+			anyhow::bail!("{}: {nflds} fields, expected {NFIELDS} \
+					(a '|' in a path?)", path.display());
+		}
+
+		// Anything wrong past here, say which path it was for.
+		let pstr = path.display().to_string();
+		let mdline = record(path, &mut flds)
+				.map_err(|e| anyhow!("{pstr}: {e}"))?;
 
 		Ok(Self { component, mdline })
 	}
 }
 
 
+/// Fields in a metadata line.
+const NFIELDS: usize = 10;
+
+
+/// Parse out the type-specific remainder of a line.
+fn record(path: std::path::PathBuf, flds: &mut std::str::Split<'_, char>)
+		-> Result<MetadataLine, AError>
+{
+	// And now we have the type
+	let rtype = flds.next().ok_or_else(|| anyhow!("no type field"))?;
+
+	// So handle each type and get the MetadataLine
+	let mdline = match rtype {
+		"f" => {
+			// A "file".  Or maybe a hardlink.
+
+			// First, simple perms
+			let uid   = get_uid(flds.next())?;
+			let gid   = get_gid(flds.next())?;
+			let mode  = get_mode(flds.next())?;
+			let flags = get_flags(flds.next())?;
+
+			// The hash
+			let sha256 = get_sha256(flds.next())?;
+
+			// And maybe a hardlink dest.  This _should_ always
+			// succeed, but it _may_ be an empty string, which turns
+			// into an empty path, so we'll have to use
+			// .is_absolute() to differentiate.
+			let hardlink = get_path(flds.next())?;
+
+			// OK, we got everything; assemble whatever sorta return
+			// we expect.
+			use super::{MetaFile, MetaHardLink};
+			match hardlink.is_absolute() {
+				true  => {
+					// Yep, it's a hardlink
+					let mhl = MetaHardLink { path, target: hardlink };
+					MetadataLine::HardLink(mhl)
+				},
+				false => {
+					// It's a file
+					let mf = MetaFile { path, sha256, uid, gid,
+							mode, flags };
+					MetadataLine::File(mf)
+				},
+			}
+		},
+		"d" => {
+			// A "directory".
+
+			// Has the same perms as a file
+			let uid   = get_uid(flds.next())?;
+			let gid   = get_gid(flds.next())?;
+			let mode  = get_mode(flds.next())?;
+			let flags = get_flags(flds.next())?;
+
+			let md = super::MetaDir { path, uid, gid, mode, flags };
+			MetadataLine::Dir(md)
+		},
+		"L" => {
+			// Symlink time
+
+			// Usual perms
+			let uid   = get_uid(flds.next())?;
+			let gid   = get_gid(flds.next())?;
+			let mode  = get_mode(flds.next())?;
+			let flags = get_flags(flds.next())?;
+
+			// And a destination path
+			let target = get_path(flds.next())?;
+
+			// And that's a symlink
+			let msl = super::MetaSymLink { path, target, uid, gid,
+					mode, flags };
+			MetadataLine::SymLink(msl)
+		},
+		"-" => {
+			// This is...  a "known not present" or something?
+			let md = super::MetaDash { path };
+			MetadataLine::Dash(md)
+		},
+		_ => {
+			// Dunno what this could be
+			Err(anyhow!("Unexpected record type: {rtype}"))?
+		},
+	};
+
+	Ok(mdline)
+}
+
+
 // Helpers for the parsing
 fn get_path(s: Option<&str>) -> Result<std::path::PathBuf, AError>
 {
@@ -459,6 +502,77 @@ mod tests
 	}
 
 
+	/// Try a line that should fail, and check the error.
+	fn bad_line(inline: &str, want: &[&str])
+	{
+		let err = inline.parse::<ParseLine>()
+				.expect_err(&format!("shoulda failed: {inline}"));
+		let err = err.to_string();
+		for w in want
+		{
+			assert!(err.contains(w), "'{inline}' error should have '{w}': \
+					{err}");
+		}
+	}
+
+	#[test]
+	fn pipe_paths()
+	{
+		const H: &str = "3ad985a50b79037b9672cf197fbc67bd54766199e190055101ea7d8c64ca843b";
+		let toomany = "11 fields, expected 10";
+
+		// A '|' in the path of each type gets called out by the path up
+		// to it, rather than failing on (or worse, accepting) whatever
+		// winds up in the later fields.
+		bad_line(&format!("world|base|/usr/share/man/a|b.1|f|0|0|0444|0|{H}|"),
+				&["/usr/share/man/a:", toomany]);
+		bad_line(&format!("world|base|/usr/share/man/a|f|f|0|0|0444|0|{H}|"),
+				&["/usr/share/man/a:", toomany]);
+		bad_line("world|base|/usr/a|b|d|0|0|0755|0||", &["/usr/a:", toomany]);
+		bad_line("world|base|/usr/a|L|L|0|0|0755|0|x|", &["/usr/a:", toomany]);
+		bad_line("world|base|/usr/a|b|-||||||", &["/usr/a:", toomany]);
+
+		// Or in a link target
+		bad_line(&format!("world|base|/bin/test|f|0|0|0555|0|{H}|/bin/a|b"),
+				&["/bin/test:", toomany]);
+		bad_line("world|base|/usr/x|L|0|0|0755|0|a|b|", &["/usr/x:", toomany]);
+
+		// Errors from later fields say what path they're about
+		bad_line("world|base|/bin/x|f|0|0", &["/bin/x:", "no mode"]);
+		bad_line("world|base|/bin/x|d|0|nope|0755|0||", &["/bin/x:", "invalid gid"]);
+		bad_line("world|base|/bin/x|L|0|0|0755", &["/bin/x:", "no flags"]);
+		bad_line(&format!("world|base|/bin/x|f|0|0|0555|0|{}|", &H[1..]),
+				&["/bin/x:"]);
+
+		// The tail end of a line that had a newline in it won't start
+		// with something sensible.
+		bad_line("usr/bin/x|f|0|0|0555|0||", &["Bad component"]);
+		bad_line("world|base|bin/x|f|0|0|0555|0||", &["bin/x: path isn't \
+				absolute"]);
+
+		// Can't be anything real, and we couldn't do anything with it
+		let long = "/node_modules".repeat(crate::util::PATH_MAX / 13 + 1);
+		bad_line(&format!("world|base|{long}|d|0|0|0755|0||"),
+				&["/node_modules/node_modules", "path too long"]);
+	}
+
+	#[test]
+	fn newline_path()
+	{
+		// The line reader splits it in two; both halves are errors, and
+		// the first names what we've got of the path.
+		let inlines = "world|base|/bin/ok|d|0|0|0755|0||\n\
+				world|base|/usr/share/man/a\nb.1|f|0|0|0444|0||\n";
+		let errs = super::parse_reader_lines(&mut inlines.as_bytes())
+				.expect_err("shoulda failed");
+		let errs: Vec<_> = errs.iter().map(|e| e.to_string()).collect();
+		assert_eq!(errs.len(), 2, "{errs:?}");
+		assert!(errs[0].contains("2: /usr/share/man/a: no type field"),
+				"{errs:?}");
+		assert!(errs[1].contains("3: Bad component"), "{errs:?}");
+	}
+
+
 	#[test]
 	fn symlink()
 	{
@@ -613,4 +727,90 @@ world|base|/nonexistent|-||||||"
 		let ne: &Path = "/nonexistent".as_ref();
 		assert!(mdg.md[&wcomp].dashes.contains(ne));
 	}
+
+
+	/*
+	 * Throwing junk at it.  What comes off the server, we take as it
+	 * comes, so whatever it is, we'd better say Ok or Err and not fall
+	 * over.
+	 */
+	use proptest::prelude::*;
+
+	/// Lines shaped a lot like real ones, with junk in every field
+	const LINE: &str = "(world|kernel|src|x)?\\|(base|generic|src|lib32|)\\|\
+			/?[a-z/. \\[é]{0,12}\\|[fdL\\-Q]?\\|[0-9+\\- ]{0,4}\\|[0-9a-z]{0,4}\\|\
+			[0-7+\\-9]{0,13}\\|[0-7a]{0,12}\\|([0-9a-f]{64}|[0-9a-fG]{0,4})\\|\
+			/?[a-z/]{0,6}(\\|[a-z]{0,2}){0,2}";
+
+	/// Write a parsed line back out, as a clean version of whatever it
+	/// came from.
+	fn unparse(pl: &ParseLine) -> String
+	{
+		use super::MetadataLine as ML;
+
+		let comp: &str = pl.component.comp.as_ref();
+		let sub = pl.component.subcomp.as_ref().map_or("", |s| s.as_ref());
+		let ps = |p: &std::path::Path| p.to_str().unwrap().to_string();
+		let rest = match &pl.mdline {
+			ML::File(f) => format!("{}|f|{}|{}|{:o}|{:o}|{}|", ps(&f.path),
+					f.uid, f.gid, f.mode, f.flags, f.sha256.to_buf()),
+			ML::HardLink(f) => format!("{}|f|0|0|0|0|{}|{}", ps(&f.path),
+					"0".repeat(64), ps(&f.target)),
+			ML::Dir(f) => format!("{}|d|{}|{}|{:o}|{:o}||", ps(&f.path),
+					f.uid, f.gid, f.mode, f.flags),
+			ML::SymLink(f) => format!("{}|L|{}|{}|{:o}|{:o}|{}|",
+					ps(&f.path), f.uid, f.gid, f.mode, f.flags,
+					ps(&f.target)),
+			ML::Dash(f) => format!("{}|-||||||", ps(&f.path)),
+		};
+		format!("{comp}|{sub}|{rest}")
+	}
+
+	proptest!
+	{
+		#[test]
+		fn any_bytes(b in proptest::collection::vec(any::<u8>(), 0..200))
+		{
+			let _ = String::from_utf8_lossy(&b).parse::<ParseLine>();
+		}
+
+		#[test]
+		fn roundtrip(line in LINE)
+		{
+			// Whatever it made of it, it makes again out of a clean line.
+			let Ok(pl) = line.parse::<ParseLine>() else { return Ok(()); };
+			let again = unparse(&pl);
+			let pl2 = again.parse::<ParseLine>().map_err(|e|
+					TestCaseError::fail(format!("{line} -> {again}: {e}")))?;
+			prop_assert_eq!(format!("{pl:?}"), format!("{pl2:?}"),
+					"{} -> {}", line, again);
+		}
+
+		#[test]
+		fn any_doc(b in proptest::collection::vec(any::<u8>(), 0..1024))
+		{
+			let _ = super::reader(&mut &b[..]);
+		}
+
+		#[test]
+		fn doc(lines in proptest::collection::vec(LINE, 0..8))
+		{
+			// Same answers as one at a time
+			let doc = lines.join("\n");
+			let each: Vec<_> = lines.iter().map(|l| l.parse::<ParseLine>())
+					.collect();
+			let nbad = each.iter().filter(|r| r.is_err()).count();
+			match super::parse_reader_lines(&mut doc.as_bytes()) {
+				Ok(pls) => {
+					prop_assert_eq!(nbad, 0);
+					let each: Vec<_> = each.into_iter()
+							.map(|r| format!("{:?}", r.unwrap())).collect();
+					let pls: Vec<_> = pls.iter().map(|p| format!("{p:?}"))
+							.collect();
+					prop_assert_eq!(each, pls);
+				},
+				Err(errs) => prop_assert_eq!(errs.len(), nbad),
+			}
+		}
+	}
 }
@@ -0,0 +1,218 @@
+//! Matching paths against a big pile of patterns.
+//!
+//! IgnorePaths and friends can get long, especially when they're
+//! machine-generated, and checking every path in a metadata file against
+//! every regex one by one adds up fast.  In practice, nearly all of them
+//! are plain anchored prefixes like "^/usr/share/doc", which we can
+//! check with a binary search instead of running a regex at all.  Whatever
+//! is left over gets run as regexes the old way.
+use regex_lite::Regex;
+
+
+/// A set of path patterns, squashed down for quick any-match checks.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PathMatcher
+{
+	/// Literal prefixes, sorted, with any that are covered by a shorter
+	/// one dropped.  That leaves no entry a prefix of another, which is
+	/// what lets us binary search.
+	prefixes: Vec<String>,
+
+	/// Everything that isn't a simple prefix, deduped.
+	res: Vec<Regex>,
+}
+
+
+impl PathMatcher
+{
+	/// Build up from a set of regexes.
+	pub(crate) fn new(res: &[Regex]) -> Self
+	{
+		let mut prefixes = Vec::new();
+		let mut others: Vec<Regex> = Vec::new();
+		for re in res
+		{
+			match literal_prefix(re.as_str()) {
+				Some(p) => prefixes.push(p),
+				None => {
+					if !others.iter().any(|o| o.as_str() == re.as_str())
+					{ others.push(re.clone()); }
+				},
+			}
+		}
+
+		// Sorting puts any prefix right before the things it covers, so
+		// one pass drops the redundant ones (and exact dupes).
+		prefixes.sort_unstable();
+		let mut kept: Vec<String> = Vec::with_capacity(prefixes.len());
+		for p in prefixes
+		{
+			match kept.last() {
+				Some(l) if p.starts_with(l.as_str()) => (),
+				_ => kept.push(p),
+			}
+		}
+
+		Self { prefixes: kept, res: others }
+	}
+
+
+	/// Does anything match this string?
+	pub(crate) fn is_match(&self, s: &str) -> bool
+	{
+		// With no entry a prefix of another, the only one that can be a
+		// prefix of s is the last one sorting <= s.
+		let idx = self.prefixes.partition_point(|p| p.as_str() <= s);
+		if idx > 0 && s.starts_with(self.prefixes[idx - 1].as_str())
+		{ return true; }
+
+		self.res.iter().any(|r| r.is_match(s))
+	}
+
+
+	/// Does anything match this path?
+	pub(crate) fn is_match_path(&self, p: &std::path::Path) -> bool
+	{
+		self.is_match(&p.to_string_lossy())
+	}
+}
+
+
+/// If a regex is just "^" and a literal string, what's the string?
+///
+/// Escaped punctuation (like "\.") is fine; anything else regex-y means
+/// it's not a plain prefix.
+fn literal_prefix(re: &str) -> Option<String>
+{
+	let rest = re.strip_prefix('^')?;
+
+	let mut ret = String::with_capacity(rest.len());
+	let mut chars = rest.chars();
+	while let Some(c) = chars.next()
+	{
+		match c {
+			'\\' => {
+				// \d, \w, \b and the like are classes or assertions, not
+				// literals.
+				let e = chars.next()?;
+				if e.is_alphanumeric() || e.is_whitespace() { return None; }
+				ret.push(e);
+			},
+			'.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{'
+					| '}' | '^' | '$' => return None,
+			_ => ret.push(c),
+		}
+	}
+	Some(ret)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::PathMatcher;
+	use regex_lite::Regex;
+
+	fn res(pats: &[&str]) -> Vec<Regex>
+	{
+		pats.iter().map(|p| Regex::new(p).unwrap()).collect()
+	}
+
+	#[test]
+	fn literal_prefix()
+	{
+		use super::literal_prefix as lp;
+
+		assert_eq!(lp("^/usr/share/doc").as_deref(), Some("/usr/share/doc"));
+		assert_eq!(lp(r"^/etc/rc\.conf").as_deref(), Some("/etc/rc.conf"));
+		assert_eq!(lp("^").as_deref(), Some(""));
+		assert_eq!(lp("/usr/share"), None, "unanchored");
+		assert_eq!(lp("^/etc/rc.conf"), None, "bare .");
+		assert_eq!(lp("^/usr/(bin|sbin)"), None, "alternation");
+		assert_eq!(lp(r"^/usr/bin/\w+"), None, "class");
+		assert_eq!(lp(r"^/boot/kernel\"), None, "trailing \\");
+	}
+
+	#[test]
+	fn dedupe()
+	{
+		let pm = PathMatcher::new(&res(&["^/usr/share/doc", "^/usr/share",
+				"^/usr/share", "^/usr/src", "^/etc/rc.conf", "^/etc/rc.conf"]));
+		assert_eq!(pm.prefixes, ["/usr/share", "/usr/src"]);
+		assert_eq!(pm.res.len(), 1);
+	}
+
+	#[test]
+	fn same_as_regexes()
+	{
+		let pats = res(&["^/usr/share/doc", "^/usr/share/man", "^/a",
+				"^/a/b/x", "^/etc/rc.conf", "^/boot/kernel[0-9]",
+				"^/var/db/$", r"^/usr/lib/libc\.so", "debug"]);
+		let pm = PathMatcher::new(&pats);
+
+		let paths = ["/usr/share/doc/README", "/usr/share/docs", "/usr/share",
+				"/usr/share/misc", "/a", "/a/c", "/a/b/y", "/b", "/",
+				"/etc/rc.conf", "/etc/rcXconf", "/boot/kernel1/kernel",
+				"/boot/kernel/kernel", "/var/db/", "/var/db/x",
+				"/usr/lib/libc.so.7", "/usr/lib/libcXso", "/usr/lib/debug/x",
+				""];
+		for p in paths
+		{
+			let want = pats.iter().any(|r| r.is_match(p));
+			assert_eq!(pm.is_match(p), want, "{p}");
+		}
+
+		// Empty sets match nothing; a bare ^ matches everything.
+		assert!(!PathMatcher::new(&[]).is_match("/bin/sh"));
+		assert!(PathMatcher::new(&res(&["^", "^/x"])).is_match("/bin/sh"));
+	}
+
+	/// Not a real test, just a quick comparison of how long things take.
+	/// Run with `cargo test --release pathmatch::tests::bench -- --ignored
+	/// --nocapture`.
+	#[test]
+	#[ignore]
+	fn bench()
+	{
+		use std::time::Instant;
+		use std::path::PathBuf;
+		use crate::metadata::{Metadata, MetaFile};
+
+		let npaths = 100_000;
+		let npats = 500;
+
+		let paths: Vec<_> = (0..npaths)
+				.map(|i| format!("/usr/jails/j{}/usr/share/f{i}", i % 1000))
+				.collect();
+		let mut pats: Vec<String> = (0..npats)
+				.map(|i| format!("^/usr/jails/j{}/usr/share/", i * 2))
+				.collect();
+		pats.push("^/usr/jails/j1/etc/rc.conf".to_string());
+		let pats = pats.iter().map(|p| Regex::new(p).unwrap())
+				.collect::<Vec<_>>();
+
+		let md = Metadata { files: paths.iter().map(|p| (PathBuf::from(p),
+				MetaFile { path: p.into(), ..Default::default() }))
+				.collect(), ..Default::default() };
+
+		// The old way
+		let start = Instant::now();
+		let mut old = md.clone();
+		old.files.retain(|k, _| {
+			let ks = k.to_string_lossy();
+			!pats.iter().any(|r| r.is_match(&ks))
+		});
+		let told = start.elapsed();
+
+		let start = Instant::now();
+		let mut new = md.clone();
+		new.remove_paths_matcher(&PathMatcher::new(&pats));
+		let tnew = start.elapsed();
+
+		println!("{npaths} paths, {} patterns: regexes {told:?}, \
+				matcher {tnew:?}", pats.len());
+		assert_eq!(old, new);
+		assert_eq!(new.files.len(), npaths / 2);
+	}
+}
@@ -0,0 +1,169 @@
+//! Keeping upstream metadata to where the base system lives.
+//!
+//! Base only ever installs under a small, known set of top-level dirs.
+//! A server handing us metadata for /home or /usr/local stuff is broken
+//! (or worse), and taking it at its word would have us scanning, and
+//! maybe "removing", things that were never ours.  So anything outside
+//! gets dropped at parse time, unless AllowUnexpectedPrefixes says
+//! somebody's doing that on purpose.
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Component as PC, Path, PathBuf};
+
+use super::{Metadata, MetadataGroup};
+
+
+/// What can be at the top of the tree.  This is what BSD.root.dist and
+/// the dist sets put there.
+const BASE_TOPS: &[&str] = &[".cshrc", ".profile", "COPYRIGHT", "bin",
+		"boot", "dev", "etc", "lib", "libexec", "media", "mnt", "net",
+		"proc", "rescue", "root", "sbin", "sys", "tmp", "usr", "var"];
+
+/// Dirs base makes, but never puts anything in.
+const NOT_BASE: &[&str] = &["/usr/local"];
+
+
+/// If a path is somewhere base doesn't go, the prefix that says so.
+pub(crate) fn unexpected_prefix(p: &Path) -> Option<String>
+{
+	let mut comps = p.components();
+	if comps.next() != Some(PC::RootDir)
+	{ return Some(p.display().to_string()); }
+
+	// / itself is fine, and the first level has to be one of ours.
+	// Anything with ..'s in it can't be trusted to stay there.
+	let top = match comps.next() {
+		None => return None,
+		Some(PC::Normal(t)) => t,
+		Some(_) => return Some(p.display().to_string()),
+	};
+	if !BASE_TOPS.iter().any(|b| top == *b)
+	{ return Some(format!("/{}", top.to_string_lossy())); }
+	if comps.any(|c| c == PC::ParentDir)
+	{ return Some(p.display().to_string()); }
+
+	NOT_BASE.iter().find(|nb| p.starts_with(nb) && p != Path::new(nb))
+			.map(|nb| nb.to_string())
+}
+
+
+impl Metadata
+{
+	/// Remove anything where base doesn't go, counting it up by prefix.
+	fn remove_unexpected_prefixes(&mut self,
+			found: &mut BTreeMap<String, usize>)
+	{
+		let bad: HashSet<PathBuf> = self.allpaths_hashset().into_iter()
+				.filter_map(|p| {
+					let pre = unexpected_prefix(p)?;
+					*found.entry(pre).or_default() += 1;
+					Some(p.to_path_buf())
+				}).collect();
+		self.remove_paths(&bad);
+	}
+}
+
+
+impl MetadataGroup
+{
+	/// Remove anything where base doesn't go.  Returns the prefixes that
+	/// turned up, and how many entries were under each.
+	pub(crate) fn remove_unexpected_prefixes(&mut self) -> Vec<(String, usize)>
+	{
+		let mut found = BTreeMap::new();
+		self.md.values_mut()
+				.for_each(|md| md.remove_unexpected_prefixes(&mut found));
+		found.into_iter().collect()
+	}
+}
+
+
+/// What parse_one_full() does about it: strip them out, unless the
+/// config says to allow them.  Gives back a warning to show if anything
+/// got stripped.
+pub(crate) fn enforce(mdg: &mut MetadataGroup, which: &str,
+		config: &crate::config::Config) -> Option<String>
+{
+	if config.allow_unexpected_prefixes { return None; }
+
+	let found = mdg.remove_unexpected_prefixes();
+	if found.is_empty() { return None; }
+
+	let n: usize = found.iter().map(|(_, c)| c).sum();
+	let pres: Vec<_> = found.iter().map(|(p, c)| format!("{p} ({c})"))
+			.collect();
+	Some(format!("Warning: {which} metadata has {n} entr{} outside the \
+			base system, ignoring: {}.  Set AllowUnexpectedPrefixes if \
+			your server does that on purpose.",
+			match n { 1 => "y", _ => "ies" }, pres.join(", ")))
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::Path;
+
+	const MDLINES: &str = r##"
+world|base|/bin/sh|f|0|0|0555|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/.cshrc|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/usr/local|d|0|0|0755|0||
+world|base|/usr/local/bin/bash|f|0|0|0555|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/usr/local/etc/rc.conf|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|base|/home/fred/.profile|f|0|0|0644|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|lib32|/usr/lib32/libc.so.7|f|0|0|0444|0|871846b8e369beaa915910e3cdc8563997c4cfbfcbdbf8ab6012af15c8cc7dd0|
+world|lib32|/home/fred/lib32|d|0|0|0755|0||
+"##;
+
+	fn mdg() -> crate::metadata::MetadataGroup
+	{
+		let mut rdr = MDLINES.as_bytes();
+		crate::metadata::parse::reader(&mut rdr).unwrap()
+	}
+
+	#[test]
+	fn prefixes()
+	{
+		use super::unexpected_prefix as up;
+		let up = |p: &str| up(Path::new(p));
+
+		assert_eq!(up("/"), None);
+		assert_eq!(up("/bin/sh"), None);
+		assert_eq!(up("/COPYRIGHT"), None);
+		assert_eq!(up("/usr/local"), None);
+		assert_eq!(up("/usr/local/bin/bash").as_deref(), Some("/usr/local"));
+		assert_eq!(up("/usr/localize").as_deref(), None);
+		assert_eq!(up("/home/fred").as_deref(), Some("/home"));
+		assert_eq!(up("/usr/../home").as_deref(), Some("/usr/../home"));
+		assert_eq!(up("bin/sh").as_deref(), Some("bin/sh"));
+	}
+
+	#[test]
+	fn rejected()
+	{
+		let conf = crate::config::parse(b"Components world\n").0;
+		let mut mdg = mdg();
+		let warn = super::enforce(&mut mdg, "new", &conf).unwrap();
+		assert!(warn.contains("new metadata has 4 entries"), "{warn}");
+		assert!(warn.contains("/home (2), /usr/local (2)"), "{warn}");
+
+		let mut paths = mdg.allpaths();
+		paths.sort_unstable();
+		assert_eq!(paths, ["/.cshrc", "/bin/sh", "/usr/lib32/libc.so.7",
+				"/usr/local"].map(Path::new));
+
+		// Nothing more to say the second time
+		assert_eq!(super::enforce(&mut mdg, "new", &conf), None);
+	}
+
+	#[test]
+	fn allowed()
+	{
+		let conf = crate::config::parse(b"Components world\n\
+				AllowUnexpectedPrefixes yes\n").0;
+		let mut mdg = mdg();
+		let before = mdg.len();
+		assert_eq!(super::enforce(&mut mdg, "new", &conf), None);
+		assert_eq!(mdg.len(), before, "nothing removed");
+	}
+}
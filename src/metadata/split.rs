@@ -1,5 +1,5 @@
 //! SplitTypes handling
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 
 use crate::metadata::Metadata;
@@ -10,7 +10,7 @@ use crate::metadata::MetadataLine;
 /*
  * Split out types: this is mostly used in install-like processes.
  */
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub(crate) struct SplitTypes
 {
 	pub(crate) dirs:  HashMap<PathBuf, MetadataLine>,
@@ -67,6 +67,15 @@ impl SplitTypes
 
 		ret
 	}
+
+
+	/// Drop a path from everything
+	pub(crate) fn remove(&mut self, p: &Path)
+	{
+		for hm in [&mut self.dirs, &mut self.files, &mut self.syms,
+				&mut self.hards, &mut self.flags]
+		{ hm.remove(p); }
+	}
 }
 
 
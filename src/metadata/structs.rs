@@ -21,18 +21,24 @@ use std::collections::{HashMap, HashSet};
 pub(crate) struct Metadata
 {
 	/// All the directories in this set
+	#[serde(serialize_with = "crate::util::sorted::map")]
 	pub(crate) dirs: HashMap<PathBuf, MetaDir>,
 
 	/// All the symlinks
+	#[serde(serialize_with = "crate::util::sorted::map")]
 	pub(crate) symlinks: HashMap<PathBuf, MetaSymLink>,
 
 	/// The files
+	#[serde(serialize_with = "crate::util::sorted::map")]
 	pub(crate) files: HashMap<PathBuf, MetaFile>,
 
 	/// And the hardlinks
+	#[serde(serialize_with = "crate::util::sorted::map")]
 	pub(crate) hardlinks: HashMap<PathBuf, MetaHardLink>,
 
-	/// Also the dash lines
+	/// Also the dash lines: paths known not to be present.  x-ref
+	/// Metadata::split_missing() on what we do with them.
+	#[serde(serialize_with = "crate::util::sorted::set")]
 	pub(crate) dashes: HashSet<PathBuf>,
 }
 
@@ -139,10 +145,11 @@ pub(crate) struct MetaSymLink
 	/// The target of the symlink
 	pub(crate) target: PathBuf,
 
-	// Misc FS metadata, though its meaning for symlinks is questionable.
-	#[derivative(PartialEq = "ignore")]
+	// Misc FS metadata.  It doesn't mean much for a symlink, but
+	// FreeBSD does keep it, and we set it on install.
+	#[derivative(PartialEq(compare_with="cmp_ugid"))]
 	pub(crate) uid:   uid_t,
-	#[derivative(PartialEq = "ignore")]
+	#[derivative(PartialEq(compare_with="cmp_ugid"))]
 	pub(crate) gid:   gid_t,
 	#[derivative(Debug(format_with="oct_fmt_u32"))]
 	pub(crate) mode:  mode_t,
@@ -164,3 +171,41 @@ pub(crate) struct MetaDash
 	/// About a file
 	pub(crate) path: PathBuf,
 }
+
+
+
+/// The owner/group/mode/flags of some line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct MetaPerms
+{
+	pub(crate) uid:   uid_t,
+	pub(crate) gid:   gid_t,
+	pub(crate) mode:  mode_t,
+	pub(crate) flags: flags_t,
+}
+
+impl std::fmt::Display for MetaPerms
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+	{
+		let MetaPerms { uid, gid, mode, flags } = self;
+		write!(f, "{uid}:{gid} mode {mode:o} flags {flags:x}")
+	}
+}
+
+
+/// A path where KeepModifiedMetadata kept our local perms over what
+/// upstream would have installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct MetaKept
+{
+	pub(crate) path: PathBuf,
+
+	/// What upstream wanted
+	pub(crate) upstream: MetaPerms,
+
+	/// What we kept instead
+	pub(crate) local: MetaPerms,
+}
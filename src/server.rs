@@ -9,11 +9,17 @@ pub(crate) use server::Server;
 /// Looking up and building server info (SRV lookups, etc)
 pub(crate) mod lookup;
 
+/// What requests go through (real HTTP, or not)
+pub(crate) mod transport;
+pub(crate) use transport::{Client, Transport};
+
 /// General http bits
 mod http;
+pub(crate) use http::fetch_files_url;
 
 /// Bit for loading public key and "tag" (basic metadata) from a server
 mod keytag;
+pub(crate) use keytag::{KeyTagError, SignedFiles, verify_tag, verify_tag_arch};
 
 /// Loading metadata stuff from the server
 mod metadata;
@@ -30,12 +30,12 @@ impl Server
 		// XXX patchdir differs for upgrade?  Tackle this then...
 		let baseurl = self.cache.burl()?.join("bp/")?;
 		let path = tmpdir;
-		let ctrl = fetch::Control { agent, baseurl, path };
+		let ctrl = fetch::Control { agent, baseurl, path, min_size: 0,
+				stats: self.cache.stats.clone(), resume: None };
 
 		// Prep up requests
 		let reqs = patches.into_iter()
-				.map(|file| fetch::Req { file })
-				.collect();
+				.map(|file| fetch::Req { file });
 
 		// Run it
 		let fres = {
@@ -57,12 +57,18 @@ impl Server
 	/// get all the requested files, this is probably fatal.  Returns the
 	/// number of fetched files on success, which probably doesn't mean
 	/// much...
-	pub(crate) fn fetch_files(&self, files: Vec<String>,
+	pub(crate) fn fetch_files(&mut self, files: Vec<String>,
 			tmpdir: PathBuf) -> Result<u32, anyhow::Error>
 	{
 		// Complete files are under <baseurl>/f
-		let url = self.cache.burl()?.join("f/")?;
-		self.fetch_files_from_to(url, files, tmpdir)
+		self.fetch_files_from_to("f/", files, tmpdir)
+	}
+
+	/// Where fetch_files() gets them from, for somebody doing it without
+	/// us (the privsep worker).
+	pub(crate) fn files_url(&self) -> Result<Url, anyhow::Error>
+	{
+		Ok(self.cache.burl()?.join("f/")?)
 	}
 
 
@@ -72,47 +78,98 @@ impl Server
 	 * Higher-level calling funcs
 	 */
 
-	/// Fetch a set of files from a base URL into a dir.  Mostly a
-	/// backend-sorta thing that we build more special-purpose frontends
-	/// onto.
+	/// Fetch a set of files from a subdir of the base URL into a dir.
+	/// Mostly a backend-sorta thing that we build more special-purpose
+	/// frontends onto.
 	///
 	/// This very much expects to succeed at getting every file, and if
 	/// it doesn't, that's an error.  That's simpler for cases where we
 	/// require that result, but not so suitable for cases where we're OK
 	/// with not finding everything we're trying.
-	pub(super) fn fetch_files_from_to(&self, baseurl: Url,
+	///
+	/// If the server falls over partway through, we switch to the next
+	/// one that'll have us (see failover()) and carry on with whatever
+	/// we didn't get yet.
+	///
+	/// Everything under here is gzip'd, so empty or too-short bodies get
+	/// thrown out as they come in, and asked for again.  One that keeps
+	/// coming back that way gets given up on with a note about it, since
+	/// that's usually a proxy that's cached something broken, and asking
+	/// again next run won't do any better.
+	pub(super) fn fetch_files_from_to(&mut self, sub: &str,
 			files: Vec<String>, path: PathBuf)
 			-> Result<u32, anyhow::Error>
 	{
-		let agent = self.cache.agent()?.clone();
+		use crate::core::pool::fetch::MIN_GZ;
+		self.fetch_files_from_to_be(sub, files, path, MIN_GZ, Self::failover)
+	}
 
-		// Setup a fetching pool
+	// Separated out so tests can thunk in the failing over (and serve up
+	// things that aren't gzip'd)
+	fn fetch_files_from_to_be(&mut self, sub: &str, files: Vec<String>,
+			path: PathBuf, min_size: u64,
+			failover: impl Fn(&mut Self) -> bool)
+			-> Result<u32, anyhow::Error>
+	{
 		use crate::core::pool::fetch;
-		let fp = fetch::Fetch::new(files.len());
-		let ctrl = fetch::Control { agent, baseurl, path };
-
-		// Build up the individual requests
-		let reqs = files.into_iter()
-				.map(|file| fetch::Req { file })
-				.collect();
 
-		// And run it
-		let fres = {
-			use crate::core::pool::Pool as _;
-			fp.run(&ctrl, reqs)?
-		};
-
-
-		// Figure out smarter returns sometime.  For now, if there are
-		// errors, just return them, else the file count.
-		if let Some(errs) = fres.errs { return Err(errs)?; }
-		if fres.nfiles as usize != fres.okfiles.len()
+		let nfiles = files.len();
+		let mut todo = files;
+		let mut shorts = fetch::ShortCount::default();
+		loop
 		{
-			anyhow::bail!("Expected {} files, fetched {}",
-					fres.nfiles, fres.okfiles.len());
+			let agent = self.cache.agent()?.clone();
+			let baseurl = self.cache.burl()?.join(sub)?;
+
+			// Setup a fetching pool
+			let fp = fetch::Fetch::new(todo.len());
+			let ctrl = fetch::Control { agent, baseurl, path: path.clone(),
+					min_size, stats: self.cache.stats.clone(),
+					resume: self.cache.resume.clone() };
+
+			// Build up the individual requests
+			let reqs = todo.iter()
+					.map(|file| fetch::Req { file: file.clone() });
+
+			// And run it
+			let fres = {
+				use crate::core::pool::Pool as _;
+				fp.run(&ctrl, reqs)?
+			};
+
+			let errs = match fres.errs {
+				None => {
+					if fres.nfiles as usize != fres.okfiles.len()
+					{
+						anyhow::bail!("Expected {} files, fetched {}",
+								fres.nfiles, fres.okfiles.len());
+					}
+					break;
+				},
+				Some(errs) => errs,
+			};
+
+			// Short bodies are worth another try or two here, but not
+			// forever.
+			shorts.add(&errs.errs);
+			if let Some(msg) = shorts.given_up()
+			{ return Err(anyhow::Error::new(errs).context(msg)); }
+			let allshort = errs.errs.iter()
+					.all(|e| matches!(e, fetch::GetErr::Short(..)));
+
+			// Files that just aren't there aren't going to be anywhere
+			// else either, but if the server's broken, somebody else
+			// might do better.
+			let srverr = errs.errs.iter().any(|e| e.server_side());
+			if !allshort && (!srverr || !failover(self))
+			{ return Err(errs)?; }
+
+			let got: std::collections::HashSet<_> = fres.okfiles.into_iter()
+					.collect();
+			todo.retain(|f| !got.contains(f));
 		}
 
-		Ok(fres.nfiles)
+		Ok(nfiles as u32)
 	}
 
 
@@ -131,8 +188,7 @@ impl Server
 	pub(in crate::server) fn get_bytes(&self, url: &url::Url)
 			-> Result<Vec<u8>, anyhow::Error>
 	{
-		let agent = self.cache.agent()?.clone();
-		get_bytes(&agent, url)
+		get_bytes(self.cache.agent()?, url)
 	}
 }
 
@@ -148,7 +204,7 @@ impl Server
 ///
 /// This way we can do those checks _before_ deciding "we'll keep this
 /// server" and holding that Agent around for the rest of the process.
-pub(in crate::server) fn get_bytes(agent: &ureq::Agent, url: &url::Url)
+pub(in crate::server) fn get_bytes(agent: &super::Client, url: &url::Url)
 		-> Result<Vec<u8>, anyhow::Error>
 {
 	// These are small files to directly poke at, so set a limit big
@@ -156,35 +212,523 @@ pub(in crate::server) fn get_bytes(agent: &ureq::Agent, url: &url::Url)
 	// if somebody messes with us.
 	const LIMIT: u64 = 10 * 1024 * 1024;
 
-	let resp = agent.request_url("GET", &url)
-		.call()?;
-	let clen: Option<usize> = match resp.header("Content-Length") {
-		Some(len) => match len.parse() {
-			Ok(cl) => Some(cl),
-			Err(_) => None,
-		},
-		None => None,
-	};
-	let mut data: Vec<u8> = match clen {
-		Some(b) => Vec::with_capacity(b),
-		None    => Vec::new(),
-	};
+	let rdr = agent.get(url)?;
+	let mut data: Vec<u8> = Vec::new();
 
 	use std::io::Read;
-	resp.into_reader().take(LIMIT).read_to_end(&mut data)?;
+	rdr.take(LIMIT).read_to_end(&mut data)?;
 	Ok(data)
 }
 
 
 
+/// Was this get_bytes() error the server saying it doesn't have the
+/// thing at all (vs. some other failure)?
+pub(in crate::server) fn is_not_found(e: &anyhow::Error) -> bool
+{
+	use crate::core::pool::fetch::GetErr;
+	matches!(e.downcast_ref::<GetErr>(), Some(GetErr::Status(_, 404)))
+}
+
+
+
+/// Fetch a set of files from under a URL into a dir, without a Server;
+/// the privsep worker only gets handed the URL.  No failing over, that's
+/// up to whoever has the Server.  How it went gets tallied into stats,
+/// for passing back.
+///
+/// Each file is its own request, over whichever pooled connection is
+/// free.  There's no batching them up: the servers only have the files
+/// one by one under f/ and bp/, and a multi-range request only gets
+/// pieces of a single one.
+pub(crate) fn fetch_files_url(baseurl: Url, files: Vec<String>,
+		path: PathBuf, stats: &crate::core::pool::fetch::Stats,
+		resume: Option<crate::core::pool::fetch::Resume>)
+		-> Result<crate::core::pool::fetch::PoolResult, anyhow::Error>
+{
+	use crate::core::pool::fetch;
+
+	let fp = fetch::Fetch::new(files.len());
+	let ctrl = fetch::Control { agent: mk_agent(), baseurl, path,
+			min_size: fetch::MIN_GZ, stats: stats.clone(), resume };
+	let reqs = files.into_iter().map(|file| fetch::Req { file });
+
+	use crate::core::pool::Pool as _;
+	fp.run(&ctrl, reqs)
+}
+
+
+
 /// Creating an Agent for our use.  Centralize to make later adjustments
 /// a little easier...
-pub(in crate::server) fn mk_agent() -> ureq::Agent
+///
+/// Clones of the Agent share its connection pool, and the fetch pool
+/// hands every worker a clone.  ureq only keeps 1 idle connection per
+/// host by default though, so all but one worker would throw theirs away
+/// after every request and open a new one for the next; on a
+/// high-latency link that setup time is most of what we'd spend.  So
+/// keep enough around for all of them.
+///
+/// If we're recording or replaying a session, that gets wrapped around
+/// (or replaces) the real thing here.
+pub(in crate::server) fn mk_agent() -> super::Client
 {
 	use std::time::Duration;
 
-	ureq::AgentBuilder::new()
+	let idle = crate::core::pool::jobs_net() as usize;
+	let agent = ureq::AgentBuilder::new()
 		.timeout_connect(Duration::from_secs(10))
 		.timeout_read(Duration::from_secs(10))
-		.build()
+		.max_idle_connections_per_host(idle)
+		.max_idle_connections(idle.max(100))
+		.build();
+	let http = super::transport::Http(agent);
+	crate::core::session::wrap(std::sync::Arc::new(http))
+}
+
+
+
+/// How long we give a server to answer while we're still looking for one
+/// to use.  Plenty for fetching a couple tiny files from a live server,
+/// and short enough that dead ones (e.g., firewalled v6 addresses) don't
+/// hold up startup for ages.
+pub(in crate::server) const PROBE_TIMEOUT: std::time::Duration
+		= std::time::Duration::from_secs(4);
+
+/// An Agent for probing servers, that gives up after timeout.  Only for
+/// the little discovery fetches; bulk transfers get mk_agent()'s more
+/// patient one.
+pub(in crate::server) fn mk_probe_agent(timeout: std::time::Duration)
+		-> super::Client
+{
+	let agent = ureq::AgentBuilder::new()
+		.timeout_connect(timeout)
+		.timeout_read(timeout)
+		.build();
+	let http = super::transport::Http(agent);
+	crate::core::session::wrap(std::sync::Arc::new(http))
+}
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::io::{BufRead as _, BufReader, Write as _};
+	use std::net::TcpListener;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	/// A minimal keep-alive HTTP server, serving up every path as its
+	/// own name (or a 404 for anything starting with "missing", and
+	/// broken bodies for "empty" and "trunc").
+	/// Returns its URL and a count of connections it's accepted.
+	fn serve() -> (url::Url, Arc<AtomicUsize>)
+	{ serve_until(usize::MAX) }
+
+	/// serve(), but it starts throwing 503's after `ok` requests.
+	fn serve_until(ok: usize) -> (url::Url, Arc<AtomicUsize>)
+	{
+		let lst = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/", lst.local_addr().unwrap());
+		let nconns = Arc::new(AtomicUsize::new(0));
+		let nreqs = Arc::new(AtomicUsize::new(0));
+
+		let count = nconns.clone();
+		std::thread::spawn(move || {
+			for conn in lst.incoming()
+			{
+				let Ok(mut conn) = conn else { break };
+				count.fetch_add(1, Ordering::SeqCst);
+				let nreqs = nreqs.clone();
+				std::thread::spawn(move || {
+					let mut rdr = BufReader::new(conn.try_clone().unwrap());
+					loop
+					{
+						// Request line, then headers up to a blank line
+						let mut req = String::new();
+						if rdr.read_line(&mut req).unwrap_or(0) == 0 { break; }
+						let mut hdr = String::new();
+						while rdr.read_line(&mut hdr).unwrap_or(0) > 2
+						{ hdr.clear(); }
+
+						let path = req.split(' ').nth(1).unwrap_or("/")
+								.trim_start_matches('/').to_string();
+						let n = nreqs.fetch_add(1, Ordering::SeqCst);
+						let (status, body) = match path.starts_with("missing") {
+							_ if n >= ok => ("503 Service Unavailable",
+									"down".to_string()),
+							true  => ("404 Not Found", "nope".to_string()),
+							false if path.starts_with("empty")
+									=> ("200 OK", String::new()),
+							false if path.starts_with("trunc")
+									=> ("200 OK", "xy".to_string()),
+							false => ("200 OK", path),
+						};
+						let resp = format!("HTTP/1.1 {status}\r\n\
+								Content-Length: {}\r\n\r\n{body}", body.len());
+						if conn.write_all(resp.as_bytes()).is_err() { break; }
+					}
+				});
+			}
+		});
+
+		(url::Url::parse(&url).unwrap(), nconns)
+	}
+
+	#[test]
+	fn conn_reuse()
+	{
+		use crate::core::pool::{fetch, Pool as _};
+
+		let (baseurl, nconns) = serve();
+		let td = tempfile::tempdir().unwrap();
+
+		// Plenty of requests, including some failing ones, which still
+		// shouldn't cost us the connection.
+		let mut files: Vec<String> = (0..60).map(|i| format!("f{i}"))
+				.collect();
+		files.extend((0..10).map(|i| format!("missing{i}")));
+		let reqs = files.iter().map(|f| fetch::Req { file: f.clone() });
+
+		let agent = super::mk_agent();
+		let ctrl = fetch::Control { agent, baseurl,
+				path: td.path().to_path_buf(), min_size: 0,
+				stats: Default::default(), resume: None };
+		let fres = fetch::Fetch::new(files.len()).run(&ctrl, reqs).unwrap();
+		assert_eq!(fres.okfiles.len(), 60);
+		assert_eq!(fres.errs.map(|e| e.errs.len()), Some(10));
+
+		// Every request got counted; the missing ones aren't errors.
+		let hs = ctrl.stats.snapshot();
+		assert_eq!(hs.len(), 1);
+		assert_eq!((hs[0].host.as_str(), hs[0].requests, hs[0].errors),
+				("127.0.0.1", 70, 0));
+		assert_eq!(hs[0].bytes, fres.bytes);
+
+		let got = std::fs::read_to_string(td.path().join("f17")).unwrap();
+		assert_eq!(got, "f17");
+
+		// One connection per worker, at most.
+		let nthr = crate::core::pool::jobs_net() as usize;
+		let nconns = nconns.load(Ordering::SeqCst);
+		assert!(nconns <= nthr, "{nconns} connections for {nthr} workers");
+	}
+
+	/// A Server that's already been "verified", pointing at a mock.
+	fn mock_server(host: &str, url: &url::Url, tidx: &str)
+			-> super::Server
+	{
+		use super::super::keytag::KeyTag;
+
+		let mut srv = super::Server { host: host.to_string(),
+				..Default::default() };
+		srv.cache.burl = Some(url.clone());
+		srv.cache.agent = Some(super::mk_agent());
+		srv.cache.keytag = Some(KeyTag { patch: Some(3),
+				tidx: tidx.to_string(), eoltime: 0 });
+		srv
+	}
+
+	#[test]
+	fn idx_not_found()
+	{
+		let (url, _) = serve();
+
+		// Not there at all is a None
+		let gone = url.join("missing/").unwrap();
+		let mut srv = mock_server("srv", &gone, "abcd");
+		assert!(srv.get_metadata_idx_at("abcd").unwrap().is_none());
+
+		// There, but not matching its hash, is still an error
+		let mut srv = mock_server("srv", &url, "abcd");
+		srv.get_metadata_idx_at("abcd").expect_err("hash mismatch");
+	}
+
+	#[test]
+	fn failover()
+	{
+		let (badurl, _) = serve_until(20);
+		let (goodurl, _) = serve();
+		let td = tempfile::tempdir().unwrap();
+		let path = td.path().to_path_buf();
+		let files: Vec<String> = (0..60).map(|i| format!("f{i}")).collect();
+
+		// The first one starts failing partway through.  The next one is
+		// fine, but offering something else, so no good.  The last one
+		// will do.
+		let mk = || {
+			let mut srv = mock_server("flaky", &badurl, "abcd");
+			srv.cache.fallback = vec![
+				mock_server("other", &goodurl, "ef01"),
+				mock_server("good", &goodurl, "abcd"),
+			];
+			srv
+		};
+
+		// There's no real key/tag to check, so just keep what we faked.
+		let fo = |s: &mut super::Server| s.failover_with(|_| Ok(()));
+
+		let mut srv = mk();
+		let n = srv.fetch_files_from_to_be("", files.clone(), path.clone(),
+				0, fo).unwrap();
+		assert_eq!(n, 60);
+		assert_eq!(srv.name(), "good", "skipped the mismatched one");
+		assert_eq!(srv.cache.fallback.len(), 0);
+
+		// What happened on the way there still counts.  They're both
+		// on 127.0.0.1, so it's one host: 20 good and 40 503's from the
+		// first, then the other 40 from the last.
+		let hs = srv.stats();
+		assert_eq!(hs.len(), 1, "{hs:?}");
+		assert_eq!((hs[0].requests, hs[0].errors), (100, 40));
+		for f in &files
+		{
+			let got = std::fs::read_to_string(td.path().join(f)).unwrap();
+			assert_eq!(&got, f);
+		}
+
+		// Nobody to switch to, it's just an error.
+		let mut srv = mk();
+		srv.cache.fallback.clear();
+		let err = srv.fetch_files_from_to_be("", files.clone(), path.clone(),
+				0, fo).unwrap_err();
+		assert!(err.to_string().contains("503"), "{err}");
+		assert_eq!(srv.name(), "flaky");
+
+		// And missing files don't count as the server failing.
+		let (url, _) = serve();
+		let mut srv = mock_server("fine", &url, "abcd");
+		srv.cache.fallback = vec![mock_server("good", &goodurl, "abcd")];
+		let missing = vec!["f1".to_string(), "missing1".to_string()];
+		srv.fetch_files_from_to_be("", missing, path, 0, fo).unwrap_err();
+		assert_eq!(srv.name(), "fine");
+	}
+
+	#[test]
+	fn short_bodies()
+	{
+		use crate::core::pool::{fetch, Pool as _};
+		use fetch::GetErr;
+
+		let (url, _) = serve();
+		let td = tempfile::tempdir().unwrap();
+		let files = ["okay-file", "empty1", "trunc1"];
+		let reqs = files.iter().map(|f| fetch::Req { file: f.to_string() });
+		let ctrl = fetch::Control { agent: super::mk_agent(),
+				baseurl: url.clone(), path: td.path().to_path_buf(),
+				min_size: 5, stats: Default::default(), resume: None };
+		let fres = fetch::Fetch::new(files.len()).run(&ctrl, reqs).unwrap();
+		assert_eq!(fres.okfiles, ["okay-file"]);
+
+		// Each gets called what it is, and neither leaves a file behind
+		let mut errs = fres.errs.unwrap().errs;
+		errs.sort_by_key(|e| e.to_string());
+		assert!(matches!(&errs[..], [GetErr::Short(e, 0), GetErr::Short(t, 2)]
+				if e.path() == "/empty1" && t.path() == "/trunc1"), "{errs:?}");
+		assert!(errs.iter().all(|e| e.server_side()));
+		assert!(!td.path().join("empty1").exists());
+		assert!(!td.path().join("trunc1").exists());
+
+		// A fetch that keeps getting one gives up on it, with a note,
+		// rather than going around forever.  Nobody to fail over to
+		// needed.
+		let mut srv = mock_server("proxied", &url, "abcd");
+		let files = vec!["okay-file".to_string(), "empty2".to_string()];
+		let err = srv.fetch_files_from_to_be("", files, td.path().into(), 5,
+				|_| panic!("Shouldn't fail over")).unwrap_err();
+		let msg = err.to_string();
+		assert!(msg.contains("empty body for") && msg.contains("empty2")
+				&& msg.contains(&format!("{} times",
+				fetch::ShortCount::TRIES)), "{msg}");
+		assert!(td.path().join("okay-file").exists());
+		assert!(!td.path().join("empty2").exists());
+	}
+
+	#[test]
+	fn timeout()
+	{
+		use crate::core::pool::{fetch, Pool as _};
+
+		// Takes the connection, never says anything
+		let lst = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = url::Url::parse(&format!("http://{}/",
+				lst.local_addr().unwrap())).unwrap();
+		std::thread::spawn(move || {
+			let mut held = Vec::new();
+			for conn in lst.incoming() { held.push(conn); }
+		});
+
+		let td = tempfile::tempdir().unwrap();
+		let agent = super::mk_probe_agent(
+				std::time::Duration::from_millis(200));
+		let ctrl = fetch::Control { agent, baseurl: url,
+				path: td.path().to_path_buf(), min_size: 0,
+				stats: Default::default(), resume: None };
+		let reqs = [fetch::Req { file: "slow".to_string() }];
+		let fres = fetch::Fetch::new(1).run(&ctrl, reqs).unwrap();
+		let errs = fres.errs.unwrap().errs;
+		assert!(matches!(&errs[..], [fetch::GetErr::Timeout(_)]), "{errs:?}");
+		assert!(errs[0].server_side());
+		assert!(!td.path().join("slow").exists());
+	}
+
+
+	/// A server with one big file (at any path), that cuts off the n'th
+	/// request for it at drops[n] bytes into the file, and does ranges
+	/// if honor_range.  Returns its URL and the range starts it's been
+	/// asked for.
+	fn serve_big(body: Arc<Vec<u8>>, drops: Vec<u64>, honor_range: bool)
+			-> (url::Url, Arc<std::sync::Mutex<Vec<Option<u64>>>>)
+	{
+		use std::sync::Mutex;
+
+		let lst = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/", lst.local_addr().unwrap());
+		let asked = Arc::new(Mutex::new(Vec::new()));
+
+		let ranges = asked.clone();
+		std::thread::spawn(move || {
+			for conn in lst.incoming()
+			{
+				let Ok(mut conn) = conn else { break };
+				let mut rdr = BufReader::new(conn.try_clone().unwrap());
+				loop
+				{
+					let mut req = String::new();
+					if rdr.read_line(&mut req).unwrap_or(0) == 0 { break; }
+					let mut from = None;
+					let mut hdr = String::new();
+					while rdr.read_line(&mut hdr).unwrap_or(0) > 2
+					{
+						let lc = hdr.to_ascii_lowercase();
+						if let Some(r) = lc.strip_prefix("range: bytes=")
+						{
+							from = r.trim().trim_end_matches('-').parse().ok();
+						}
+						hdr.clear();
+					}
+
+					let n = {
+						let mut r = ranges.lock().unwrap();
+						r.push(from);
+						r.len() - 1
+					};
+					let len = body.len() as u64;
+					let (status, start) = match from {
+						Some(f) if honor_range => ("206 Partial Content", f),
+						_ => ("200 OK", 0),
+					};
+					let mut head = format!("HTTP/1.1 {status}\r\n\
+							Content-Length: {}\r\n", len - start);
+					if start > 0
+					{
+						head += &format!("Content-Range: bytes {start}-{}/{len}\r\n",
+								len - 1);
+					}
+					head += "\r\n";
+					let end = drops.get(n).copied().filter(|&d| d > start)
+							.unwrap_or(len).min(len);
+					let _ = conn.write_all(head.as_bytes());
+					let _ = conn.write_all(&body[start as usize..end as usize]);
+					if end < len
+					{
+						let _ = conn.shutdown(std::net::Shutdown::Both);
+						break;
+					}
+				}
+			}
+		});
+
+		(url::Url::parse(&url).unwrap(), asked)
+	}
+
+	/// Something to fetch that isn't all the same byte
+	fn big_body(len: usize) -> Arc<Vec<u8>>
+	{
+		Arc::new((0..len).map(|i| (i % 251) as u8).collect())
+	}
+
+	/// Fetch "big" from url into dir, keeping partials in dir/part.
+	fn fetch_big(url: &url::Url, dir: &std::path::Path, min: u64)
+			-> crate::core::pool::fetch::PoolResult
+	{
+		use crate::core::pool::{fetch, Pool as _};
+
+		let resume = fetch::Resume { dir: dir.join("part"), min };
+		let ctrl = fetch::Control { agent: super::mk_agent(),
+				baseurl: url.clone(), path: dir.to_path_buf(), min_size: 0,
+				stats: Default::default(), resume: Some(resume) };
+		let reqs = [fetch::Req { file: "big".to_string() }];
+		fetch::Fetch::new(1).run(&ctrl, reqs).unwrap()
+	}
+
+	#[test]
+	fn resume()
+	{
+		use crate::core::pool::fetch::GetErr;
+
+		let body = big_body(40_000);
+		let td = tempfile::tempdir().unwrap();
+		let (big, part) = (td.path().join("big"), td.path().join("part/big"));
+
+		// A couple drops get picked up from where they stopped, all in
+		// the one go.
+		let (url, asked) = serve_big(body.clone(), vec![10_000, 30_000], true);
+		let fres = fetch_big(&url, td.path(), 1024);
+		assert_eq!(fres.okfiles, ["big"]);
+		assert_eq!(&std::fs::read(&big).unwrap(), &*body);
+		assert_eq!(*asked.lock().unwrap(), [None, Some(10_000), Some(30_000)]);
+		assert!(!part.exists());
+		std::fs::remove_file(&big).unwrap();
+
+		// Too many, and it gives up for now, but keeps what it's got for
+		// next time, which finishes it off.
+		let drops = vec![5_000, 10_000, 15_000, 20_000];
+		let (url, asked) = serve_big(body.clone(), drops, true);
+		let errs = fetch_big(&url, td.path(), 1024).errs.unwrap().errs;
+		assert!(matches!(&errs[..], [GetErr::Dropped(_, 15_000)]), "{errs:?}");
+		assert!(errs[0].server_side());
+		assert!(!big.exists());
+		assert_eq!(std::fs::metadata(&part).unwrap().len(), 15_000);
+
+		let fres = fetch_big(&url, td.path(), 1024);
+		assert_eq!(fres.okfiles, ["big"]);
+		assert_eq!(&std::fs::read(&big).unwrap(), &*body);
+		assert_eq!(*asked.lock().unwrap(), [None, Some(5_000), Some(10_000),
+				Some(15_000), Some(20_000)]);
+		assert!(!part.exists());
+		std::fs::remove_file(&big).unwrap();
+
+		// Not enough to be worth keeping
+		let (url, asked) = serve_big(body.clone(), vec![500], true);
+		let errs = fetch_big(&url, td.path(), 1024).errs.unwrap().errs;
+		assert!(matches!(&errs[..], [GetErr::Dropped(_, 500)]), "{errs:?}");
+		assert_eq!(*asked.lock().unwrap(), [None]);
+		assert!(!big.exists());
+		assert!(!part.exists());
+	}
+
+	#[test]
+	fn resume_ignored()
+	{
+		let body = big_body(40_000);
+		let td = tempfile::tempdir().unwrap();
+
+		// Asking for the rest gets the whole thing, so it starts over.
+		let (url, asked) = serve_big(body.clone(), vec![10_000], false);
+		let fres = fetch_big(&url, td.path(), 1024);
+		assert_eq!(fres.okfiles, ["big"]);
+		assert_eq!(&std::fs::read(td.path().join("big")).unwrap(), &*body);
+		assert_eq!(*asked.lock().unwrap(), [None, Some(10_000)]);
+
+		// And a partial that's somehow longer than the file is thrown out
+		// the same way.
+		std::fs::create_dir_all(td.path().join("part")).unwrap();
+		std::fs::write(td.path().join("part/big"), vec![0; 50_000]).unwrap();
+		let (url, asked) = serve_big(body.clone(), vec![], false);
+		let fres = fetch_big(&url, td.path(), 1024);
+		assert_eq!(fres.okfiles, ["big"]);
+		assert_eq!(&std::fs::read(td.path().join("big")).unwrap(), &*body);
+		assert_eq!(*asked.lock().unwrap(), [Some(50_000)]);
+	}
 }
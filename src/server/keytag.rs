@@ -6,7 +6,7 @@ use crate::info::version::AVersion;
 
 /// Data about a key and the tag info
 #[derive(Debug, Default)]
-pub(in crate::server) struct KeyTag
+pub(crate) struct KeyTag
 {
 	// /// The public key from the server: should match the hash of it we
 	// /// have in the config, and is used to decrypt the below fields from
@@ -27,6 +27,9 @@ pub(in crate::server) struct KeyTag
 // Parse out a string of the keytag into our struct
 impl KeyTag
 {
+	pub(crate) fn tidx(&self) -> &str { &self.tidx }
+	pub(crate) fn patch(&self) -> Option<u32> { self.patch }
+
 	fn from_str(s: &str, xarch: &str, xvers: &AVersion)
 			-> Result<Self, anyhow::Error>
 	{
@@ -98,10 +101,12 @@ impl Server
 	/// Try fetching the key and "tag" from a Server.  Returns the info
 	/// if we got it, or some sorta error if we didn't.
 	pub(crate) fn get_key_tag(&mut self, vers: &AVersion, keyprint: &str)
-			-> Result<(), anyhow::Error>
+			-> Result<(), KeyTagError>
 	{
+		use KeyTagError as KTE;
+
 		// Gets used in a few places...
-		let arch = crate::info::kernel::arch()?;
+		let arch = crate::info::kernel::arch().map_err(KTE::Fetch)?;
 
 		// Build up what our URL's will look like.  We probably won't
 		// have this yet...
@@ -110,45 +115,187 @@ impl Server
 			None => {
 				let burl = format!("http://{}/{}-{}/{}/", self.host,
 						vers.release, vers.reltype, arch);
-				let burl = url::Url::parse(&burl)?;
+				let burl = url::Url::parse(&burl)
+						.map_err(|e| KTE::Fetch(e.into()))?;
 				self.cache.burl = Some(burl);
 				self.cache.burl.as_ref().unwrap()
 			},
 		};
 
-		let kurl = burl.join("pub.ssl")?;
-		let turl = burl.join("latest.ssl")?;
+		let kurl = burl.join("pub.ssl").map_err(|e| KTE::Fetch(e.into()))?;
+		let turl = burl.join("latest.ssl").map_err(|e| KTE::Fetch(e.into()))?;
 
 
-		// Setup HTTP requesting and loading
-		let agent = crate::server::http::mk_agent();
+		// Setup HTTP requesting and loading.  We may well be one of
+		// several servers being tried, so don't wait around too long;
+		// whoever gets picked gets a proper agent for the real work.
+		use crate::server::http;
+		let agent = http::mk_probe_agent(http::PROBE_TIMEOUT);
 
 
 		// Load in the key: just a blob of bytes.  But we should know its
 		// hash...
 		// X-ref comment on get_bytes() about how it's used differently
 		// here than anywhere else.
-		let key = super::http::get_bytes(&agent, &kurl)?;
-		use crate::util::hash;
-		hash::check_sha256(&key, &keyprint, "public key")?;
-		// And it's a PEM-encoded thing, so we can just call it a String.
-		let key = String::from_utf8(key)?;
+		let key = super::http::get_bytes(&agent, &kurl).map_err(KTE::Fetch)?;
 
 		// OK, now load up the tag; we'll do more processing
-		let tag = super::http::get_bytes(&agent, &turl)?;
+		let tag = super::http::get_bytes(&agent, &turl).map_err(KTE::Fetch)?;
+
+		// Check the key, decrypt, and parse.  A key that's not the one we
+		// expect is worth calling out specially, since it's much more
+		// likely a config problem than a server one.
+		use crate::util::hash;
+		hash::check_sha256(&key, keyprint, "public key")
+				.map_err(KTE::KeyPrint)?;
+		let kt = verify_tag(&key, &tag, keyprint, vers).map_err(KTE::Tag)?;
+
+		// Stash that and an agent for the real work, and the raw bits in
+		// case somebody wants to keep 'em around for later
+		// re-verification.
+		self.cache.agent = Some(http::mk_agent());
+		self.cache.keytag = Some(kt);
+		self.cache.rawkey = Some(key);
+		self.cache.rawtag = Some(tag);
+
+		Ok(())
+	}
 
-		// Wacky handrolled crypto, what fun
-		let tag = decrypt_tag(key.as_bytes(), &tag)?;
 
-		// Parse it out of the string
-		let kt = KeyTag::from_str(&tag, &arch, vers)?;
+	/// The signed bits we got from the server: the key, the tag, and the
+	/// metadata index the tag points at.  Only available after we've
+	/// loaded the metadata index.
+	pub(crate) fn signed_files(&self) -> Result<SignedFiles, anyhow::Error>
+	{
+		use anyhow::anyhow;
+		let c = &self.cache;
+		let err = |w| anyhow!("Error: raw {w} should exist");
+		let key  = c.rawkey.clone().ok_or_else(|| err("key"))?;
+		let tag  = c.rawtag.clone().ok_or_else(|| err("tag"))?;
+		let tidx = c.rawtidx.clone().ok_or_else(|| err("tINDEX"))?;
+		Ok(SignedFiles { key, tag, tidx })
+	}
 
-		// Stash that and the agent
-		self.cache.agent = Some(agent);
-		self.cache.keytag = Some(kt);
 
+	/// Check some other tag (e.g., one SignedFiles::archive_tag() kept)
+	/// against the key we got from this server.
+	pub(crate) fn verify_other_tag(&self, tag: &[u8], keyprint: &str,
+			vers: &AVersion) -> Result<KeyTag, anyhow::Error>
+	{
+		let key = self.cache.rawkey.as_ref()
+				.ok_or_else(|| anyhow::anyhow!("Error: raw key should exist"))?;
+		verify_tag(key, tag, keyprint, vers)
+	}
+}
+
+
+/// Why we couldn't get a usable key/tag from a server.
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+pub(crate) enum KeyTagError
+{
+	/// Couldn't get anything from it
+	#[error("{0}")]
+	Fetch(anyhow::Error),
+
+	/// Got a key, but not one matching our KeyPrint
+	#[error("{0}")]
+	KeyPrint(anyhow::Error),
+
+	/// The tag didn't decrypt or parse right
+	#[error("Bad tag: {0}")]
+	Tag(anyhow::Error),
+}
+
+/// Check a public key against our keyprint, then use it to decrypt and
+/// parse a tag.
+pub(crate) fn verify_tag(key: &[u8], tag: &[u8], keyprint: &str,
+		vers: &AVersion) -> Result<KeyTag, anyhow::Error>
+{
+	let arch = crate::info::kernel::arch()?;
+	verify_tag_arch(key, tag, keyprint, &arch, vers)
+}
+
+/// verify_tag(), for a given arch rather than whatever we're running.
+pub(crate) fn verify_tag_arch(key: &[u8], tag: &[u8], keyprint: &str,
+		arch: &str, vers: &AVersion) -> Result<KeyTag, anyhow::Error>
+{
+	use crate::util::hash;
+	hash::check_sha256(key, keyprint, "public key")?;
+
+	// Wacky handrolled crypto, what fun
+	let tag = decrypt_tag(key, tag)?;
+
+	// Parse it out of the string
+	KeyTag::from_str(&tag, arch, vers)
+}
+
+
+
+/// The signed chain from the server, as raw bytes, so it can be saved
+/// and re-verified later without going back to the server.
+#[derive(Debug)]
+pub(crate) struct SignedFiles
+{
+	/// The public key (pub.ssl)
+	pub(crate) key: Vec<u8>,
+
+	/// The signed tag (latest.ssl)
+	pub(crate) tag: Vec<u8>,
+
+	/// The metadata index the tag gives the hash of
+	pub(crate) tidx: Vec<u8>,
+}
+
+impl SignedFiles
+{
+	const KEY: &'static str = "pub.ssl";
+	const TAG: &'static str = "latest.ssl";
+	const TIDX: &'static str = "tINDEX";
+	const TAGS: &'static str = "tags";
+
+	/// Write them out into a dir.
+	pub(crate) fn save(&self, dir: &std::path::Path)
+			-> Result<(), std::io::Error>
+	{
+		use std::fs;
+		fs::create_dir_all(dir)?;
+		fs::write(dir.join(Self::KEY), &self.key)?;
+		fs::write(dir.join(Self::TAG), &self.tag)?;
+		fs::write(dir.join(Self::TIDX), &self.tidx)?;
 		Ok(())
 	}
+
+	/// Load them back from a dir.
+	pub(crate) fn load(dir: &std::path::Path)
+			-> Result<Self, std::io::Error>
+	{
+		use std::fs;
+		let key  = fs::read(dir.join(Self::KEY))?;
+		let tag  = fs::read(dir.join(Self::TAG))?;
+		let tidx = fs::read(dir.join(Self::TIDX))?;
+		Ok(Self { key, tag, tidx })
+	}
+
+
+	/// Keep a copy of the tag under the version (patch level and all)
+	/// it's for.  The server only ever offers its latest, so these are
+	/// the only way back to the metadata for earlier patch levels.
+	pub(crate) fn archive_tag(&self, dir: &std::path::Path, vers: &AVersion)
+			-> Result<(), std::io::Error>
+	{
+		use std::fs;
+		let tdir = dir.join(Self::TAGS);
+		fs::create_dir_all(&tdir)?;
+		fs::write(tdir.join(format!("{vers}.ssl")), &self.tag)
+	}
+
+	/// Load back a tag archive_tag() kept.
+	pub(crate) fn archived_tag(dir: &std::path::Path, vers: &AVersion)
+			-> Result<Vec<u8>, std::io::Error>
+	{
+		std::fs::read(dir.join(Self::TAGS).join(format!("{vers}.ssl")))
+	}
 }
 
 
@@ -215,6 +362,28 @@ pub(super) mod tests
 		assert_eq!(tagdec.as_bytes(), tag_expect);
 	}
 
+	#[test]
+	fn archived_tags()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let sf = |tag: &[u8]| SignedFiles { key: b"key".to_vec(),
+				tag: tag.to_vec(), tidx: b"idx".to_vec() };
+		let p3: AVersion = "14.2-RELEASE-p3".parse().unwrap();
+		let p4: AVersion = "14.2-RELEASE-p4".parse().unwrap();
+
+		sf(b"three").archive_tag(td.path(), &p3).unwrap();
+		sf(b"four").archive_tag(td.path(), &p4).unwrap();
+		assert_eq!(SignedFiles::archived_tag(td.path(), &p3).unwrap(),
+				b"three");
+		assert_eq!(SignedFiles::archived_tag(td.path(), &p4).unwrap(),
+				b"four");
+
+		// Never kept one for p2
+		let p2: AVersion = "14.2-RELEASE-p2".parse().unwrap();
+		let e = SignedFiles::archived_tag(td.path(), &p2).unwrap_err();
+		assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+	}
+
 	#[test]
 	fn parse_keytag_simple()
 	{
@@ -1,15 +1,40 @@
 //! Looking up the list of servers
 use super::Server;
 
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+
+
+use std::sync::atomic::{self, AtomicBool};
+
+/// Can we go to a public resolver if there's nothing else?  x-ref
+/// resolver_ladder().
+static FALLBACK_DNS: AtomicBool = AtomicBool::new(false);
+
+/// Allow (or not) falling back to a public resolver
+pub(crate) fn set_fallback_dns(s: bool)
+{ FALLBACK_DNS.store(s, atomic::Ordering::Relaxed) }
+
+fn fallback_dns() -> bool { FALLBACK_DNS.load(atomic::Ordering::Relaxed) }
+
 
 /// Figure out our whole list of servers, based on the given name.
 ///
 /// This is the main external entry point, that the rest of the code hits
-/// to put together the list of servers to try reaching out to.
-pub(crate) fn servers(sname: &str) -> Result<Vec<Server>, anyhow::Error>
+/// to put together the list of servers to try reaching out to.  Unless
+/// quiet, it says where it's getting its DNS from.
+pub(crate) fn servers(sname: &str, quiet: bool)
+		-> Result<Vec<Server>, anyhow::Error>
 {
+	// Playing back a recorded session doesn't touch the network at all;
+	// any server will do, since it's all coming from the recording.
+	if crate::core::session::replaying()
+	{
+		let nsrv = Server { host: sname.to_string(), ..Server::default() };
+		return Ok(vec![nsrv]);
+	}
+
 	// Let's see what we get outta DNS...
-	let srvs = match srv_lookup(sname)? {
+	let srvs = match srv_lookup(sname, quiet)? {
 		Some(srvs) => srvs,
 		None => {
 			// OK, time to fake a single "just this name".  Also this
@@ -38,6 +63,38 @@ pub(crate) fn servers(sname: &str) -> Result<Vec<Server>, anyhow::Error>
 }
 
 
+/// Move a preferred host (e.g., the one that worked last time) to the
+/// front of the list of servers to try.
+///
+/// If it's not in the list at all, the SRV records have changed since we
+/// last looked, so we don't trust the hint and leave the list alone.
+pub(crate) fn prefer(srvs: &mut Vec<Server>, host: &str)
+{
+	let idx = srvs.iter().position(|s| s.host.eq_ignore_ascii_case(host));
+	if let Some(idx) = idx
+	{
+		let srv = srvs.remove(idx);
+		srvs.insert(0, srv);
+	}
+}
+
+
+/// Put a pinned host (--prefer-server) at the front of the list.  Unlike
+/// a prefer() hint, it's something the user asked for, so it goes in
+/// even if the SRV records don't know about it; it still has to pass the
+/// same key and tag checks as anybody else, and if it doesn't, the rest
+/// of the list is still behind it.
+pub(crate) fn pin(srvs: &mut Vec<Server>, host: &str)
+{
+	let idx = srvs.iter().position(|s| s.host.eq_ignore_ascii_case(host));
+	let srv = match idx {
+		Some(idx) => srvs.remove(idx),
+		None => Server { host: host.to_string(), ..Server::default() },
+	};
+	srvs.insert(0, srv);
+}
+
+
 
 
 
@@ -48,11 +105,134 @@ pub(crate) fn servers(sname: &str) -> Result<Vec<Server>, anyhow::Error>
 
 
 
+/// Where we're looking things up from, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DnsSource
+{
+	/// resolv.conf, as is
+	ResolvConf,
+
+	/// resolv.conf, minus this many lines that wouldn't parse
+	Lenient(usize),
+
+	/// Whatever's listening on localhost, since resolv.conf was no help
+	/// (for the reason given)
+	Local(&'static str),
+
+	/// The public last resort, which we're allowed to use
+	Fallback(&'static str),
+}
+
+impl DnsSource
+{
+	/// The one line to say about it
+	pub(crate) fn describe(&self) -> String
+	{
+		use crate::util::plural;
+		match self {
+			Self::ResolvConf => format!("Using DNS servers from \
+					{RESOLV_CONF}."),
+			Self::Lenient(n) => format!("Using DNS servers from {RESOLV_CONF} \
+					(ignoring {n} line{} that couldn't be parsed).",
+					plural(*n)),
+			Self::Local(why) => format!("{RESOLV_CONF} {why}; using the \
+					local resolver on 127.0.0.1."),
+			Self::Fallback(why) => format!("{RESOLV_CONF} {why}, and nothing \
+					is answering on 127.0.0.1; using the public resolver \
+					{FALLBACK_NAME}, as --fallback-dns allows."),
+		}
+	}
+}
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const FALLBACK_NAME: &str = "Quad9 (9.9.9.9)";
+
+
+/// Figure out what resolver config to use, given what's in resolv.conf
+/// (None if it's missing or unreadable, like a resolvconf symlink into a
+/// /var/run that isn't there yet), whether something's answering DNS on
+/// localhost, and whether we can go public.
+///
+/// In order: resolv.conf as it is; resolv.conf skipping lines that
+/// won't parse; 127.0.0.1 (which is what libc does without any
+/// nameservers too); and only if allowed, a public resolver.
+pub(crate) fn resolver_ladder(conf: Option<&[u8]>, local: bool,
+		fallback: bool)
+		-> Result<(DnsSource, ResolverConfig, ResolverOpts), String>
+{
+	use hickory_resolver::system_conf::parse_resolv_conf;
+	use hickory_resolver::config::NameServerConfigGroup;
+	type DS = DnsSource;
+
+	let has_ns = |c: &ResolverConfig| !c.name_servers().is_empty();
+	let why = match conf {
+		None => "is missing",
+		Some(data) => match parse_resolv_conf(data) {
+			Ok((c, o)) if has_ns(&c) => return Ok((DS::ResolvConf, c, o)),
+			Ok(_) => "lists no nameservers",
+			Err(_) => {
+				// Keep what we can make sense of, line by line
+				let mut kept = Vec::new();
+				let mut skipped = 0;
+				for l in data.split(|c| *c == b'\n')
+				{
+					match parse_resolv_conf(l) {
+						Ok(_) => { kept.extend_from_slice(l); kept.push(b'\n'); },
+						Err(_) => skipped += 1,
+					}
+				}
+				match parse_resolv_conf(&kept) {
+					Ok((c, o)) if has_ns(&c)
+							=> return Ok((DS::Lenient(skipped), c, o)),
+					_ => "couldn't be parsed into any nameservers",
+				}
+			},
+		},
+	};
+
+	let opts = ResolverOpts::default();
+	if local
+	{
+		let lh = [std::net::IpAddr::from([127, 0, 0, 1])];
+		let ns = NameServerConfigGroup::from_ips_clear(&lh, 53, true);
+		let c = ResolverConfig::from_parts(None, vec![], ns);
+		return Ok((DS::Local(why), c, opts));
+	}
+	if fallback
+	{ return Ok((DS::Fallback(why), ResolverConfig::quad9(), opts)); }
+
+	Err(format!("Can't look up servers: {RESOLV_CONF} {why}, and nothing \
+			is answering DNS on 127.0.0.1.  Fix {RESOLV_CONF}, or run with \
+			--fallback-dns to use {FALLBACK_NAME}."))
+}
+
+
+/// Is anything answering DNS on localhost?  local_unbound and friends
+/// take TCP as well as UDP, and that's much easier to ask quickly.
+fn local_dns() -> bool
+{
+	use std::net::{SocketAddr, TcpStream};
+	use std::time::Duration;
+	let addr = SocketAddr::from(([127, 0, 0, 1], 53));
+	TcpStream::connect_timeout(&addr, Duration::from_millis(500)).is_ok()
+}
+
+
 /// Internal helper: Do the SRV lookup for a name
-fn srv_lookup(sname: &str) -> Result<Option<Vec<Server>>, anyhow::Error>
+fn srv_lookup(sname: &str, quiet: bool)
+		-> Result<Option<Vec<Server>>, anyhow::Error>
 {
 	use hickory_resolver::Resolver;
-	let resolver = Resolver::from_system_conf()?;
+	let conf = std::fs::read(RESOLV_CONF).ok();
+	let local = || local_dns();
+	let (src, rconf, ropts) = match conf.as_deref() {
+		// Don't bother poking localhost if we won't need it
+		Some(c) => resolver_ladder(Some(c), false, false)
+				.or_else(|_| resolver_ladder(Some(c), local(), fallback_dns())),
+		None => resolver_ladder(None, local(), fallback_dns()),
+	}.map_err(anyhow::Error::msg)?;
+	if !quiet { println!("{}", src.describe()); }
+	let resolver = Resolver::new(rconf, ropts)?;
 
 	// Let's see what we get...
 	let srvname = format!("_http._tcp.{}", sname);
@@ -241,4 +421,118 @@ mod tests
 		assert_eq!(srvs[1][1].host, "jane");
 		assert_eq!(srvs[1][2].host, "barbara");
 	}
+
+	#[test]
+	fn resolver_ladder()
+	{
+		use super::resolver_ladder as rl;
+		use super::DnsSource as DS;
+		let ns = |c: &ResolverConfig| -> Vec<String> {
+			let mut ips: Vec<_> = c.name_servers().iter()
+					.map(|n| n.socket_addr.ip().to_string()).collect();
+			ips.dedup();
+			ips
+		};
+
+		// The normal case
+		let good = b"search example.org\nnameserver 192.0.2.1\n";
+		let (src, c, _) = rl(Some(good), false, false).unwrap();
+		assert_eq!(src, DS::ResolvConf);
+		assert_eq!(ns(&c), ["192.0.2.1"]);
+
+		// Something in there we can't make sense of gets skipped
+		let odd = b"nameserver 192.0.2.1\noptions ndots:lots\n\
+				nameserver 192.0.2.2\n";
+		let (src, c, _) = rl(Some(odd), true, true).unwrap();
+		assert_eq!(src, DS::Lenient(1));
+		assert_eq!(ns(&c), ["192.0.2.1", "192.0.2.2"]);
+
+		// Nothing usable: local if it's there, then the public one if
+		// we're allowed, otherwise we can't.
+		for conf in [None, Some(&b"search example.org\n"[..]),
+				Some(&b"nameserver bogus\n"[..])]
+		{
+			let (src, c, _) = rl(conf, true, true).unwrap();
+			assert!(matches!(src, DS::Local(_)), "{src:?}");
+			assert_eq!(ns(&c), ["127.0.0.1"]);
+
+			let (src, c, _) = rl(conf, false, true).unwrap();
+			assert!(matches!(src, DS::Fallback(_)), "{src:?}");
+			assert!(ns(&c).contains(&"9.9.9.9".to_string()), "{:?}", ns(&c));
+
+			let e = rl(conf, false, false).unwrap_err();
+			assert!(e.contains("--fallback-dns"), "{e}");
+		}
+
+		// And each one has something to say
+		let (src, _, _) = rl(None, true, false).unwrap();
+		assert_eq!(src.describe(), "/etc/resolv.conf is missing; using the \
+				local resolver on 127.0.0.1.");
+	}
+
+	#[test]
+	fn preferred()
+	{
+		let hosts = |srvs: &Vec<Server>| -> Vec<String> {
+			srvs.iter().map(|s| s.host.clone()).collect()
+		};
+		let base: Vec<Server> = srvs_by_pri(test_servers()).into_iter()
+				.flatten().collect();
+		let orig = hosts(&base);
+
+		// Preferred host goes first, everything else keeps its order as
+		// the fallback.
+		let mut srvs = srvs_by_pri(test_servers()).into_iter()
+				.flatten().collect();
+		prefer(&mut srvs, "slowpoke");
+		let mut expect = orig.clone();
+		let sp = expect.pop().unwrap();
+		expect.insert(0, sp);
+		assert_eq!(hosts(&srvs), expect, "slowpoke first");
+
+		// Already first, no change
+		let mut srvs = srvs_by_pri(test_servers()).into_iter()
+				.flatten().collect();
+		prefer(&mut srvs, &orig[0]);
+		assert_eq!(hosts(&srvs), orig, "first stays first");
+
+		// A host that's not in the SRV list anymore is ignored
+		let mut srvs = srvs_by_pri(test_servers()).into_iter()
+				.flatten().collect();
+		prefer(&mut srvs, "gone.example.org");
+		assert_eq!(hosts(&srvs), orig, "unknown hint ignored");
+	}
+
+
+	#[test]
+	fn pinned()
+	{
+		let hosts = |srvs: &Vec<Server>| -> Vec<String> {
+			srvs.iter().map(|s| s.host.clone()).collect()
+		};
+		let base = || -> Vec<Server> { srvs_by_pri(test_servers())
+				.into_iter().flatten().collect() };
+		let orig = hosts(&base());
+
+		// One we know about moves up front, like a hint
+		let mut srvs = base();
+		pin(&mut srvs, "slowpoke");
+		let mut expect = orig.clone();
+		let sp = expect.pop().unwrap();
+		expect.insert(0, sp);
+		assert_eq!(hosts(&srvs), expect, "slowpoke first");
+
+		// One we don't gets added, ahead of everything else
+		let mut srvs = base();
+		pin(&mut srvs, "mirror.example.org");
+		let mut expect = orig.clone();
+		expect.insert(0, "mirror.example.org".to_string());
+		assert_eq!(hosts(&srvs), expect, "unknown pin added");
+
+		// And it beats the state's hint
+		let mut srvs = base();
+		prefer(&mut srvs, "slowpoke");
+		pin(&mut srvs, "mirror.example.org");
+		assert_eq!(hosts(&srvs)[..2], ["mirror.example.org", "slowpoke"]);
+	}
 }
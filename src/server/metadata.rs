@@ -35,22 +35,41 @@ impl super::Server
 
 		// Now parse it out
 		let idx = MetadataIdx::parse(&idxbytes)?;
+		self.cache.rawtidx = Some(idxbytes);
 
 		// Return
 		Ok(idx)
 	}
 
 
+	/// Load up the metadata index for some other tag than the one we
+	/// found the server with (e.g., a past patch level).  None if the
+	/// server doesn't have it; they don't keep old ones forever.
+	pub(crate) fn get_metadata_idx_at(&mut self, tidx: &str)
+			-> Result<Option<MetadataIdx>, anyhow::Error>
+	{
+		let burl = self.cache.burl()?.join("t/")?.join(tidx)?;
+		let idxbytes = match self.get_bytes(&burl) {
+			Ok(b) => b,
+			Err(e) if super::http::is_not_found(&e) => return Ok(None),
+			Err(e) => return Err(e),
+		};
+
+		use crate::util::hash;
+		hash::check_sha256(&idxbytes, tidx, "metadata index")?;
+		Ok(Some(MetadataIdx::parse(&idxbytes)?))
+	}
+
+
 	/// Fetch down metadata files
-	pub(crate) fn fetch_metafiles(&self, files: Vec<String>)
+	pub(crate) fn fetch_metafiles(&mut self, files: Vec<String>)
 			-> Result<u32, anyhow::Error>
 	{
-		// We just need to know the src URL and the dst dir, the rest is
+		// We just need to know the src dir and the dst dir, the rest is
 		// common.
-		let mburl = self.cache.burl()?.join("m/")?;
-		let fdir  = self.cache.filesdir()?.to_path_buf();
+		let fdir = self.cache.filesdir()?.to_path_buf();
 
 		// And let the lower level do the work
-		self.fetch_files_from_to(mburl, files, fdir)
+		self.fetch_files_from_to("m/", files, fdir)
 	}
 }
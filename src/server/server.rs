@@ -43,8 +43,8 @@ pub(in crate::server) struct ServerCache
 	/// The base URL for doing requests from this server
 	pub(in crate::server) burl: Option<url::Url>,
 
-	/// A stashed up ureq agent
-	pub(in crate::server) agent: Option<ureq::Agent>,
+	/// A stashed up HTTP agent (or whatever's standing in for one)
+	pub(in crate::server) agent: Option<super::Client>,
 
 	/// The public key/tag info
 	pub(in crate::server) keytag: Option<KeyTag>,
@@ -52,61 +52,213 @@ pub(in crate::server) struct ServerCache
 	/// The `files/` dir, where we put downloaded bits from the server
 	/// (i.e., usually `/var/db/freebsd-update/files/`).
 	pub(in crate::server) filesdir: Option<std::path::PathBuf>,
+
+	/// Raw public key, signed tag, and metadata index, as fetched.
+	pub(in crate::server) rawkey: Option<Vec<u8>>,
+	pub(in crate::server) rawtag: Option<Vec<u8>>,
+	pub(in crate::server) rawtidx: Option<Vec<u8>>,
+
+	/// Other servers we could fail over to, in the order we'd have
+	/// tried them, and what we verified this one against, to hold them
+	/// to the same.
+	pub(in crate::server) fallback: Vec<Server>,
+	pub(in crate::server) verified: Option<(crate::info::AVersion, String)>,
+
+	/// How the fetching's gone, by host; this carries over when we fail
+	/// over, so it covers everybody we've used.
+	pub(in crate::server) stats: crate::core::pool::fetch::Stats,
+
+	/// Where big downloads that got cut off are kept to pick up from
+	pub(in crate::server) resume: Option<crate::core::pool::fetch::Resume>,
 }
 
 
 impl Server
 {
-	/// Find a valid server under a given name.
-	pub(crate) fn find(name: &str, version: &crate::info::AVersion,
-			keyprint: &str)
+	/// Find a server, trying the one cached in the state first (or
+	/// PreferServer ahead of that), and updating the cache with what we
+	/// end up with.  The caller is responsible for saving the state.
+	pub(crate) fn find_cached(config: &crate::config::Config,
+			version: &crate::info::AVersion,
+			state: &mut crate::state::State, quiet: bool)
+			-> Result<Server, anyhow::Error>
+	{
+		let name = &config.servername;
+		let prefer = state.server_hint(name, config.server_cache_ttl);
+		let srv = Self::find_inner(name, version, &config.keyprint,
+				prefer, config.prefer_server.as_deref(), quiet)?;
+		state.set_server_hint(name, &srv);
+		Ok(srv)
+	}
+
+
+	/// find_cached() for commands that don't otherwise deal with the
+	/// state.  Updating the cache is best-effort; if the state can't be
+	/// loaded or saved, we just find a server the long way.
+	pub(crate) fn find_cached_rt(config: &crate::config::Config,
+			version: &crate::info::AVersion,
+			rtdirs: &crate::core::RtDirs, quiet: bool)
 			-> Result<Server, anyhow::Error>
 	{
-		Self::find_inner(name, version, keyprint, false)
+		let mut state = match rtdirs.state_load_brief() {
+			Ok(s) => s,
+			Err(_) => return Self::find_inner(&config.servername, version,
+					&config.keyprint, None, config.prefer_server.as_deref(),
+					quiet),
+		};
+		let srv = Self::find_cached(config, version, &mut state, quiet)?;
+		let _ = rtdirs.state_save(&state);
+		Ok(srv)
 	}
 
 
-	/// Inner impl of server finding
+	/// Inner impl of server finding.  prefer is the hint about what
+	/// worked last time, pin is what the user wants tried before even
+	/// that.
 	pub(crate) fn find_inner(name: &str, version: &crate::info::AVersion,
-			keyprint: &str, quiet: bool)
+			keyprint: &str, prefer: Option<&str>, pin: Option<&str>,
+			quiet: bool)
 			-> Result<Server, anyhow::Error>
 	{
-		use std::io::{stdout, Write as _};
+		// First, look up from that list, and put the one we liked last
+		// time at the front, and anything pinned ahead of that.  The
+		// rest are still there to fall back on.
+		let mut servers = super::lookup::servers(&name, quiet)?;
+		if let Some(p) = prefer { super::lookup::prefer(&mut servers, p); }
+		if let Some(p) = pin { super::lookup::pin(&mut servers, p); }
+
+		// Find the first one that's useful.  The rest get kept around
+		// in case it falls over on us later.
+		let width = crate::core::pool::jobs_net().min(PROBE_WIDTH) as usize;
+		let (vers, kp) = (version.clone(), keyprint.to_string());
+		let found = probe(servers, width, quiet,
+				move |s| s.get_key_tag(&vers, &kp));
+		match found {
+			Ok((mut srv, rest)) => {
+				if let Some(p) = pin.filter(|p| !quiet
+						&& !srv.host.eq_ignore_ascii_case(p))
+				{
+					println!("Preferred server {p} isn't usable, going \
+							with {} instead.", srv.host);
+				}
+				crate::core::session::note(format_args!("server {} ({} \
+						fallbacks)", srv.host, rest.len()));
+				srv.cache.fallback = rest;
+				srv.cache.verified = Some((version.clone(),
+						keyprint.to_string()));
+				Ok(srv)
+			},
 
-		// First, look up from that list
-		let servers = super::lookup::servers(&name)?;
+			// Whelp, no, that's probably fatal...
+			Err(fails) => anyhow::bail!("{}", out_of_servers(&fails, keyprint)),
+		}
+	}
+
+	pub(crate) fn name(&self) -> &str { &self.host }
 
-		// Find the first one that's useful.
-		for mut srv in servers
+
+	/// Switch over to the next server we can, when this one has started
+	/// failing on us.  It gets held to the same key, and has to be
+	/// offering the same patch level and metadata index we've been
+	/// working from.  Returns whether we found one.
+	pub(crate) fn failover(&mut self) -> bool
+	{
+		let Some((vers, kp)) = self.cache.verified.clone()
+			else { return false };
+		self.failover_with(|s| s.get_key_tag(&vers, &kp))
+	}
+
+	/// Backend for failover(), with the key/tag check split out so tests
+	/// can fake it.
+	pub(in crate::server) fn failover_with(&mut self,
+			verify: impl Fn(&mut Server) -> Result<(), super::KeyTagError>)
+			-> bool
+	{
+		let (patch, tidx) = match &self.cache.keytag {
+			Some(kt) => (kt.patch, kt.tidx.clone()),
+			None => return false,
+		};
+
+		while self.cache.fallback.len() > 0
 		{
-			if !quiet { print!("Trying server {}...", srv.name()); }
-			stdout().flush()?;
-			let sret = srv.get_key_tag(version, keyprint);
-			match sret {
-				Ok(_) => {
-					if !quiet { println!("   OK."); }
-					return Ok(srv);
-				},
-				Err(e) => {
-					if !quiet { println!("\nFailed: {}", e); }
-					// FALLTHRU
-				},
-			};
+			let mut srv = self.cache.fallback.remove(0);
+			println!("Server {} is failing, trying {}...", self.host,
+					srv.host);
+			if let Err(e) = verify(&mut srv)
+			{
+				println!("Failed: {e}");
+				continue;
+			}
+			let same = srv.keytag_patchnum() == patch
+					&& srv.keytag_tidx() == Some(tidx.as_str());
+			if !same
+			{
+				println!("Not offering the same update we started with, \
+						skipping.");
+				continue;
+			}
+
+			// Good, it takes over from here.  What we've already
+			// fetched is all content-addressed, so it's still good.
+			println!("Switched to server {}.", srv.host);
+			let c = &mut self.cache;
+			srv.cache.filesdir = c.filesdir.take();
+			srv.cache.rawtidx  = c.rawtidx.take();
+			srv.cache.fallback = std::mem::take(&mut c.fallback);
+			srv.cache.verified = c.verified.take();
+			srv.cache.stats    = std::mem::take(&mut c.stats);
+			srv.cache.resume   = c.resume.take();
+			*self = srv;
+			return true;
 		}
 
-		// Whelp, no, that's probably fatal...
-		anyhow::bail!("Out of servers, giving up.");
+		false
 	}
 
-	pub(crate) fn name(&self) -> &str { &self.host }
-
 	pub(crate) fn set_filesdir(&mut self, d: std::path::PathBuf)
 	{ self.cache.filesdir = Some(d); }
 
+	/// Keep big downloads that get cut off in d, to pick up from next
+	/// time, rather than starting them over.  Anything that's been
+	/// sitting there too long gets cleaned out.
+	pub(crate) fn set_partialdir(&mut self, d: std::path::PathBuf)
+	{
+		use crate::core::pool::fetch::{Resume, RESUME_MAX_AGE};
+
+		let resume = Resume::new(d);
+		if let Err(e) = resume.prune(RESUME_MAX_AGE)
+		{
+			eprintln!("Warning: couldn't clean out old partial downloads \
+					in {}: {e}", resume.dir.display());
+		}
+		self.cache.resume = Some(resume);
+	}
+
+	/// Where we're keeping cut off downloads, if we are
+	pub(crate) fn resume(&self) -> Option<&crate::core::pool::fetch::Resume>
+	{ self.cache.resume.as_ref() }
+
+	/// How each server we've fetched from has done so far
+	pub(crate) fn stats(&self) -> Vec<crate::core::pool::fetch::HostStats>
+	{ self.cache.stats.snapshot() }
+
+	/// Count in fetching the privsep worker did for us
+	pub(crate) fn add_stats(&self, hs: &[crate::core::pool::fetch::HostStats])
+	{ self.cache.stats.merge(hs) }
+
+	/// Tally into the same stats as another Server, for a run that uses
+	/// more than one (upgrade's old and new releases).
+	pub(crate) fn share_stats(&mut self, other: &Server)
+	{ self.cache.stats = other.cache.stats.clone() }
+
 	/// Show the patch number in our keytag
 	pub(crate) fn keytag_patchnum(&self) -> Option<u32>
 	{ self.cache.keytag.as_ref()?.patch }
 
+	/// Show the metadata index hash in our keytag
+	pub(crate) fn keytag_tidx(&self) -> Option<&str>
+	{ Some(self.cache.keytag.as_ref()?.tidx()) }
+
 
 	/// Build up EOL warning info, if applicable
 	pub(crate) fn eol_warning(&self, vers: &crate::info::version::Version)
@@ -150,7 +302,7 @@ macro_rules! mk_cache_getter {
 	};
 }
 mk_cache_getter!(burl,   url::Url);
-mk_cache_getter!(agent,  ureq::Agent);
+mk_cache_getter!(agent,  super::Client);
 mk_cache_getter!(keytag, KeyTag);
 mk_cache_getter!(filesdir, std::path::PathBuf);
 
@@ -162,6 +314,123 @@ mk_cache_getter!(filesdir, std::path::PathBuf);
  * The rest of this is just internal implementation details of the
  * external entries above.
  */
+
+/// How many servers we'll probe at once, at most.
+const PROBE_WIDTH: u32 = 4;
+
+/// Go through servers in order, looking for the first one check is happy
+/// with.  A few get checked at once (up to width), so a run of dead ones
+/// costs us about one timeout, not one each.
+///
+/// Order still wins over speed: one further down the list that answers
+/// first has to wait on everything ahead of it to fail, so SRV priority
+/// (and our preference) holds.  As soon as that's settled, we're done,
+/// without waiting on any stragglers; they time out on their own.
+///
+/// Returns the winner and what's after it in the list, or how each one
+/// failed.
+fn probe<F>(mut servers: Vec<Server>, width: usize, quiet: bool, check: F)
+		-> Result<(Server, Vec<Server>), Vec<(String, super::KeyTagError)>>
+where
+	F: Fn(&mut Server) -> Result<(), super::KeyTagError>
+			+ Send + Sync + 'static,
+{
+	use std::sync::{Arc, mpsc};
+
+	let check = Arc::new(check);
+	let width = width.max(1);
+	let (tx, rx) = mpsc::channel();
+
+	let nsrv = servers.len();
+	let mut results: Vec<Option<Result<Server, _>>> = Vec::new();
+	results.resize_with(nsrv, || None);
+	let mut next = 0;   // Next to start probing
+	let mut front = 0;  // First one that hasn't failed yet
+	let mut fails = Vec::new();
+	loop
+	{
+		// Settle what we can off the front
+		while front < nsrv
+		{
+			let host = &servers[front].host;
+			match results[front].take() {
+				None => break,
+				Some(Ok(srv)) => {
+					if !quiet { println!("Trying server {host}...   OK."); }
+					let rest = servers.split_off(front + 1);
+					return Ok((srv, rest));
+				},
+				Some(Err(e)) => {
+					if !quiet
+					{ println!("Trying server {host}...\nFailed: {e}"); }
+					fails.push((host.clone(), e));
+					front += 1;
+				},
+			}
+		}
+		if front == nsrv { return Err(fails); }
+
+		// Keep the window full.  Each probe gets a fresh copy, so the
+		// list stays ours.
+		while next < nsrv && next < front + width
+		{
+			let s = &servers[next];
+			let mut srv = Server { pri: s.pri, weight: s.weight,
+					host: s.host.clone(), ..Server::default() };
+			let (idx, tx, check) = (next, tx.clone(), check.clone());
+			std::thread::spawn(move || {
+				let res = check(&mut srv).map(|_| srv);
+				let _ = tx.send((idx, res));
+			});
+			next += 1;
+		}
+
+		// And wait for somebody to answer
+		let (idx, res) = rx.recv().expect("we hold a sender");
+		results[idx] = Some(res);
+	}
+}
+
+
+/// Explain why none of the servers worked out.
+///
+/// If they all gave us a key that doesn't match our KeyPrint, that's
+/// almost certainly a typo in the config, not a network problem, so say
+/// so.  If they failed in different ways, list them all.
+fn out_of_servers(fails: &[(String, super::KeyTagError)], keyprint: &str)
+		-> String
+{
+	use super::KeyTagError as KTE;
+	use std::mem::discriminant;
+
+	let giveup = "Out of servers, giving up.";
+	let first = match fails.first() {
+		Some((_, e)) => e,
+		None => return giveup.to_string(),
+	};
+	let same = fails.iter()
+			.all(|(_, e)| discriminant(e) == discriminant(first));
+
+	match (same, first) {
+		(true, KTE::KeyPrint(_)) => {
+			let kp: String = keyprint.chars().take(16).collect();
+			let n = fails.len();
+			format!("keytag signature did not match configured KeyPrint \
+					({kp}...) on {n} server{} -- check the KeyPrint in your \
+					config", crate::util::plural(n))
+		},
+		(true, _) => giveup.to_string(),
+		(false, _) => {
+			let width = fails.iter().map(|(h, _)| h.len()).max().unwrap_or(0);
+			let mut ret = format!("{giveup}  Servers failed with:");
+			for (h, e) in fails
+			{ ret.push_str(&format!("\n  {h:width$}  {e}")); }
+			ret
+		},
+	}
+}
+
+
 fn eol_warning_be(now: chrono::DateTime<chrono::Local>,
 		eol: chrono::DateTime<chrono::Local>,
 		vers: &crate::info::version::Version)
@@ -322,4 +591,206 @@ pub(super) mod tests
 		assert!(ew.contains("END-OF-LIFE"));
 
 	}
+
+	/// A listener on localhost that waits delay and answers every request
+	/// with body, or with None, never answers at all.  Returns its
+	/// host:port.
+	fn listen(delay: std::time::Duration, body: Option<&'static str>)
+			-> String
+	{
+		use std::io::{BufRead as _, BufReader, Write as _};
+
+		let lst = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = lst.local_addr().unwrap().to_string();
+		std::thread::spawn(move || {
+			for conn in lst.incoming()
+			{
+				let Ok(mut conn) = conn else { break };
+				std::thread::spawn(move || {
+					let mut rdr = BufReader::new(conn.try_clone().unwrap());
+					let mut line = String::new();
+					while rdr.read_line(&mut line).unwrap_or(0) > 2
+					{ line.clear(); }
+
+					std::thread::sleep(delay);
+					let Some(body) = body else {
+						std::thread::sleep(std::time::Duration::from_secs(30));
+						return;
+					};
+					let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: \
+							{}\r\n\r\n{body}", body.len());
+					let _ = conn.write_all(resp.as_bytes());
+				});
+			}
+		});
+		addr
+	}
+
+	/// Somewhere nobody's listening
+	fn refused() -> String
+	{
+		let lst = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		lst.local_addr().unwrap().to_string()
+	}
+
+	/// Stand-in for the key/tag check: it's good if it says "ok".
+	const PROBE_T: std::time::Duration = std::time::Duration::from_millis(800);
+	fn check_ok(srv: &mut Server) -> Result<(), crate::server::KeyTagError>
+	{
+		use crate::server::{http, KeyTagError as KTE};
+
+		let agent = http::mk_probe_agent(PROBE_T);
+		let url = url::Url::parse(&format!("http://{}/", srv.host)).unwrap();
+		let body = http::get_bytes(&agent, &url).map_err(KTE::Fetch)?;
+		match body == b"ok" {
+			true  => Ok(()),
+			false => Err(KTE::Tag(anyhow::anyhow!("said {body:?}"))),
+		}
+	}
+
+	fn srvs(hosts: &[&String]) -> Vec<Server>
+	{
+		hosts.iter().map(|h| Server { host: h.to_string(),
+				..Server::default() }).collect()
+	}
+
+	#[test]
+	fn probe_latency()
+	{
+		use std::time::{Duration, Instant};
+
+		// A couple that never answer, then one that does.  One at a
+		// time, that's 2 timeouts; all at once, about 1.
+		let dead1 = listen(Duration::ZERO, None);
+		let dead2 = listen(Duration::ZERO, None);
+		let good = listen(Duration::ZERO, Some("ok"));
+		let start = Instant::now();
+		let (srv, rest) = super::probe(srvs(&[&dead1, &dead2, &good]), 4,
+				true, check_ok).unwrap();
+		let took = start.elapsed();
+		assert_eq!(srv.host, good);
+		assert!(rest.is_empty());
+		assert!(took >= PROBE_T, "waited on the dead ones: {took:?}");
+		assert!(took < PROBE_T * 2, "in parallel: {took:?}");
+	}
+
+	#[test]
+	fn probe_priority()
+	{
+		use std::time::Duration;
+
+		// The first answers slower, but it's first, so it wins, and the
+		// rest are left to fall back on.
+		let slow = listen(Duration::from_millis(300), Some("ok"));
+		let fast = listen(Duration::ZERO, Some("ok"));
+		let (srv, rest) = super::probe(srvs(&[&slow, &fast]), 4, true,
+				check_ok).unwrap();
+		assert_eq!(srv.host, slow);
+		assert_eq!(rest.len(), 1);
+		assert_eq!(rest[0].host, fast);
+
+		// Once what's ahead of it fails, the next in line is it, even
+		// if one further back answered first.
+		let gone = refused();
+		let (srv, rest) = super::probe(srvs(&[&gone, &slow, &fast]), 4,
+				true, check_ok).unwrap();
+		assert_eq!(srv.host, slow);
+		assert_eq!(rest[0].host, fast);
+
+		// Width 1 is one at a time, same answer.
+		let (srv, _) = super::probe(srvs(&[&gone, &slow, &fast]), 1, true,
+				check_ok).unwrap();
+		assert_eq!(srv.host, slow);
+	}
+
+	#[test]
+	fn probe_pinned()
+	{
+		use std::time::{Duration, Instant};
+		use crate::server::lookup::pin;
+
+		// A pinned host that's refusing, or never answers, gets passed
+		// over for the rest of the list, in about a timeout; no hanging
+		// on it.
+		let good = listen(Duration::ZERO, Some("ok"));
+		for bad in [refused(), listen(Duration::ZERO, None)]
+		{
+			let mut servers = srvs(&[&good]);
+			pin(&mut servers, &bad);
+			assert_eq!(servers[0].host, bad);
+			let start = Instant::now();
+			let (srv, rest) = super::probe(servers, 4, true, check_ok)
+					.unwrap();
+			let took = start.elapsed();
+			assert_eq!(srv.host, good);
+			assert!(rest.is_empty());
+			assert!(took < PROBE_T * 2, "fell back quick: {took:?}");
+		}
+
+		// When it's fine, it wins, even answering slower and not being
+		// in the list to start with.
+		let mirror = listen(Duration::from_millis(200), Some("ok"));
+		let mut servers = srvs(&[&good]);
+		pin(&mut servers, &mirror);
+		let (srv, rest) = super::probe(servers, 4, true, check_ok).unwrap();
+		assert_eq!(srv.host, mirror);
+		assert_eq!(rest[0].host, good);
+	}
+
+	#[test]
+	fn probe_fails()
+	{
+		use std::time::Duration;
+
+		// Everything fails, and we hear how, in order.
+		let gone = refused();
+		let wrong = listen(Duration::ZERO, Some("nope"));
+		let fails = super::probe(srvs(&[&wrong, &gone]), 4, true, check_ok)
+				.unwrap_err();
+		use crate::server::KeyTagError as KTE;
+		assert_eq!(fails.len(), 2);
+		assert!(matches!(&fails[0], (h, KTE::Tag(_)) if *h == wrong));
+		assert!(matches!(&fails[1], (h, KTE::Fetch(_)) if *h == gone));
+
+		// Nothing at all to try
+		let fails = super::probe(Vec::new(), 4, true, check_ok).unwrap_err();
+		assert!(fails.is_empty());
+	}
+
+	#[test]
+	fn out_of_servers()
+	{
+		use super::out_of_servers;
+		use crate::server::KeyTagError as KTE;
+		use anyhow::anyhow;
+
+		let kp = "800651ef4b4c71c27e60786d7b487188970f4b4169cc055596e2a8d5be9dd0cb";
+		let badkey = |h: &str| (h.to_string(),
+				KTE::KeyPrint(anyhow!("Bad public key hash")));
+		let nonet = |h: &str| (h.to_string(),
+				KTE::Fetch(anyhow!("Connection refused")));
+
+		// Nothing to try at all
+		assert_eq!(out_of_servers(&[], kp), "Out of servers, giving up.");
+
+		// Every one's key mismatched: blame the config
+		let fails = [badkey("update1"), badkey("update2"), badkey("update4")];
+		assert_eq!(out_of_servers(&fails, kp), "keytag signature did not \
+				match configured KeyPrint (800651ef4b4c71c2...) on 3 servers \
+				-- check the KeyPrint in your config");
+		assert!(out_of_servers(&fails[..1], kp).contains("on 1 server --"));
+
+		// All the same other problem; they've already seen it for each
+		let fails = [nonet("update1"), nonet("update2")];
+		assert_eq!(out_of_servers(&fails, kp), "Out of servers, giving up.");
+
+		// Mixed bag; show them what happened where
+		let fails = [badkey("update1"), nonet("upd2"),
+				("update4".to_string(), KTE::Tag(anyhow!("Expected arch")))];
+		assert_eq!(out_of_servers(&fails, kp), "Out of servers, giving up.  \
+				Servers failed with:\n\
+				\x20 update1  Bad public key hash\n\
+				\x20 upd2     Connection refused\n\
+				\x20 update4  Bad tag: Expected arch");
+	}
 }
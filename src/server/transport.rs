@@ -0,0 +1,173 @@
+//! Where our HTTP requests actually go.
+//!
+//! Everything that talks to a server does it through a Client, which is
+//! normally just a ureq Agent doing real HTTP.  But it can also be a
+//! recorded session being played back (see crate::core::session), or a
+//! test's fake, without anything above here knowing the difference.
+use std::io::Read;
+use std::sync::Arc;
+
+use url::Url;
+
+use crate::core::pool::fetch::GetErr;
+
+
+/// Something we can GET things from.
+pub(crate) trait Transport: std::fmt::Debug + Send + Sync
+{
+	/// GET a URL, and give back a reader for the body.  Anything but a
+	/// 2xx comes back as a GetErr::Status.
+	fn get(&self, url: &Url) -> Result<Box<dyn Read + Send>, GetErr>;
+
+	/// GET a URL starting `from` bytes in.  The bool says whether that's
+	/// what we got; if not, it's the whole thing from the start, same as
+	/// get().  Not everything can do ranges, so by default nothing does.
+	fn get_from(&self, url: &Url, _from: u64)
+			-> Result<(Box<dyn Read + Send>, bool), GetErr>
+	{
+		Ok((self.get(url)?, false))
+	}
+}
+
+/// What everybody holds onto and clones around.
+pub(crate) type Client = Arc<dyn Transport>;
+
+
+/// The real thing.
+#[derive(Debug)]
+pub(crate) struct Http(pub(crate) ureq::Agent);
+
+impl Transport for Http
+{
+	fn get(&self, url: &Url) -> Result<Box<dyn Read + Send>, GetErr>
+	{
+		match self.0.request_url("GET", url).call() {
+			Ok(r) => Ok(Box::new(r.into_reader())),
+			Err(e) => Err(status_err(e, url)),
+		}
+	}
+
+	fn get_from(&self, url: &Url, from: u64)
+			-> Result<(Box<dyn Read + Send>, bool), GetErr>
+	{
+		let req = self.0.request_url("GET", url)
+				.set("Range", &format!("bytes={from}-"));
+		let r = req.call().map_err(|e| status_err(e, url))?;
+
+		// Only a 206 for the range we asked for is the rest of it;
+		// anything else (usually a 200, from something that doesn't do
+		// ranges) is the whole body.
+		let want = format!("bytes {from}-");
+		let ranged = r.status() == 206 && r.header("Content-Range")
+				.is_some_and(|cr| cr.starts_with(&want));
+		match (ranged, r.status()) {
+			(true, _)    => Ok((Box::new(r.into_reader()), true)),
+			(false, 206) => {
+				drain(r);
+				Ok((self.get(url)?, false))
+			},
+			(false, _)   => Ok((Box::new(r.into_reader()), false)),
+		}
+	}
+}
+
+
+/// Turn a ureq error into ours.  Missing files are routine when we're
+/// looking for patches, so read out the error body, so the connection
+/// goes back into the agent's pool rather than being dropped; otherwise
+/// each of those costs us a new connection for the next request.
+fn status_err(e: ureq::Error, url: &Url) -> GetErr
+{
+	match e {
+		ureq::Error::Status(code, r) => {
+			drain(r);
+			GetErr::Status(url.clone(), code)
+		},
+		e => e.into(),
+	}
+}
+
+/// Read out (a reasonable amount of) a body we don't want.
+fn drain(r: ureq::Response)
+{
+	let mut rdr = r.into_reader().take(64 * 1024);
+	let _ = std::io::copy(&mut rdr, &mut std::io::sink());
+}
+
+
+
+#[cfg(test)]
+pub(crate) mod tests
+{
+	use std::collections::HashMap;
+	use std::io::Read;
+	use std::sync::Mutex;
+
+	use url::Url;
+
+	use crate::core::pool::fetch::GetErr;
+
+	/// A server that's just a map of paths to bodies, and remembers
+	/// what got asked for.
+	#[derive(Debug, Default)]
+	pub(crate) struct Fake
+	{
+		pub(crate) files: HashMap<String, Vec<u8>>,
+		pub(crate) asked: Mutex<Vec<String>>,
+	}
+
+	impl Fake
+	{
+		pub(crate) fn new(files: &[(&str, &[u8])]) -> Self
+		{
+			let files = files.iter()
+					.map(|(p, b)| (p.to_string(), b.to_vec())).collect();
+			Self { files, ..Default::default() }
+		}
+	}
+
+	impl super::Transport for Fake
+	{
+		fn get(&self, url: &Url) -> Result<Box<dyn Read + Send>, GetErr>
+		{
+			let path = url.path().trim_start_matches('/').to_string();
+			self.asked.lock().unwrap().push(path.clone());
+			match self.files.get(&path) {
+				Some(b) => Ok(Box::new(std::io::Cursor::new(b.clone()))),
+				None => Err(GetErr::Status(url.clone(), 404)),
+			}
+		}
+	}
+
+
+	#[test]
+	fn fake_server()
+	{
+		use std::sync::Arc;
+		use crate::util::hash;
+
+		// A Server that's found its keytag, with everything after that
+		// coming from the fake.
+		let tidx = format!("INDEX-ALL|{}\n", "ab".repeat(32));
+		let tidxh = hash::sha256_reader(&mut tidx.as_bytes()).unwrap()
+				.to_buf().to_string();
+		let fake = Arc::new(Fake::new(&[
+			(format!("14.2-RELEASE/amd64/t/{tidxh}").as_str(),
+					tidx.as_bytes()),
+		]));
+
+		let mut srv = crate::server::Server::default();
+		srv.cache.burl = Some(Url::parse("http://fake/14.2-RELEASE/amd64/")
+				.unwrap());
+		srv.cache.agent = Some(fake.clone() as super::Client);
+		srv.cache.keytag = Some(crate::server::keytag::KeyTag { patch: Some(1),
+				tidx: tidxh.clone(), eoltime: 0 });
+
+		let idx = srv.get_metadata_idx().unwrap();
+		assert_eq!(idx.get_matching(&["all"]).len(), 1);
+		assert!(srv.get_metadata_idx_at("abcd").unwrap().is_none());
+		assert_eq!(fake.asked.lock().unwrap().as_slice(),
+				[format!("14.2-RELEASE/amd64/t/{tidxh}"),
+				"14.2-RELEASE/amd64/t/abcd".to_string()]);
+	}
+}
@@ -5,6 +5,8 @@ use std::collections::HashMap;
 use crate::metadata::{self, MetadataIdx, Metadata};
 use crate::info::version::AVersion;
 use crate::core::merge;
+use crate::core::filter::SkippedUpdate;
+use crate::core::risk::{Risks, RiskSummary};
 
 use thiserror::Error;
 
@@ -15,6 +17,24 @@ use thiserror::Error;
 /// _write_ into it...
 const STATEFILE: &str = "freebsd_rustdate_state.json";
 
+/// The manifest lives off in its own file alongside.  With a full
+/// upgrade's worth of metadata it's most of the bulk, and a lot of
+/// things only care about the rest of the state (or a summary).
+const MANIFESTFILE: &str = "freebsd_rustdate_manifest.json";
+
+/// The statefile from before the last save, in case the current one
+/// gets mangled somehow.  x-ref `doctor`.
+const STATEFILE_BAK: &str = "freebsd_rustdate_state.json.bak";
+
+/// And the manifest that went with it, if there was one; the two only
+/// make sense together.
+const MANIFESTFILE_BAK: &str = "freebsd_rustdate_manifest.json.bak";
+
+/// What shape the statefile is in, for tools outside poking at it.
+/// Everything so far has come in with defaults, so older statefiles
+/// still read fine; this only goes up when that stops being true.
+pub(crate) const STATE_SCHEMA: u32 = 1;
+
 
 /// The current state of something.  Since doing an upgrade involves
 /// multiple invocations, this is where we keep track of what we've done
@@ -27,11 +47,245 @@ pub(crate) struct State
 	/// used to...   ....   TBD
 	pub(crate) meta_idx: Option<MetadataIdx>,
 
+	/// The version meta_idx is for.  The signed tag it came from gets
+	/// stashed in RtDirs::signed_dir().
+	pub(crate) meta_idx_vers: Option<AVersion>,
+
+	/// The KeyPrint that tag checked out against.  If that's not the one
+	/// configured now, meta_idx isn't vouched for anymore; x-ref
+	/// State::keyprint_check().
+	#[serde(default)]
+	pub(crate) meta_idx_keyprint: Option<String>,
+
 	/// A prep'd up manifest for an upgrade of some sort.
+	///
+	/// This gets written out into MANIFESTFILE, not the statefile, but
+	/// older statefiles had it inline, so we still read it from there.
+	#[serde(default, skip_serializing)]
 	pub(crate) manifest: Option<Manifest>,
 
-	// XXX Will have stuff about cleaning up shared libs etc when we get
-	// that far.
+	/// Summary of the manifest, for when we don't load it.  This is
+	/// regenerated from the manifest on every save (x-ref SavedState).
+	#[serde(default, skip_serializing)]
+	pub(crate) manifest_brief: Option<ManifestBrief>,
+
+	/// Did we skip loading the manifest?  If so, we leave MANIFESTFILE
+	/// alone when saving.
+	#[serde(skip)]
+	pub(crate) brief: bool,
+
+	/// Old shared libs intentionally left behind by finishing an upgrade
+	/// with `install --skip-lib-cleanup`.
+	#[serde(default)]
+	pub(crate) kept_libs: Vec<PathBuf>,
+
+	/// Dirs upstream removed that install left alone, because they still
+	/// had local files in them.  Remembered so later installs don't keep
+	/// telling you about them.
+	#[serde(default)]
+	pub(crate) retained_dirs: Vec<PathBuf>,
+
+	/// The last server we successfully talked to, so we can try it first
+	/// next time instead of walking the whole SRV list.
+	pub(crate) server_hint: Option<ServerHint>,
+
+	/// Boot envs and kernel backups install has made, newest last, so we
+	/// can tell people how to get back to before.
+	#[serde(default)]
+	pub(crate) recovery: Vec<RecoveryPoint>,
+
+	/// Installs we've finished, newest last.
+	#[serde(default)]
+	pub(crate) history: Vec<HistoryEntry>,
+
+	/// Post-install helpers an in-progress install couldn't run, as the
+	/// commands to run by hand; they go into the HistoryEntry at the
+	/// end.
+	#[serde(default)]
+	pub(crate) deferred_helpers: Vec<String>,
+
+	/// Dirs the last install to finish (or pending one to be cleared)
+	/// touched, so crash residue from it can still be found after the
+	/// manifest's gone; x-ref crate::core::install::residue.
+	#[serde(default)]
+	pub(crate) last_install_dirs: Vec<PathBuf>,
+
+	/// Everything fetch and upgrade have downloaded and stored, over all
+	/// runs; x-ref crate::util::bytecount.
+	#[serde(default)]
+	pub(crate) bytes: crate::util::bytecount::Bytes,
+
+	/// How each server did over the last fetch or upgrade
+	#[serde(default)]
+	pub(crate) server_stats: Vec<crate::core::pool::fetch::HostStats>,
+}
+
+
+/// How many HistoryEntry's we hang onto
+const HISTORY_KEEP: usize = 10;
+
+
+/// A finished install.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct HistoryEntry
+{
+	/// fetch or upgrade
+	pub(crate) mtype: String,
+
+	/// What version we went from, and to
+	pub(crate) from: String,
+	pub(crate) to: String,
+
+	/// When it finished (unix time)
+	pub(crate) when: i64,
+
+	/// The closing audit from install --verify, if there was one
+	pub(crate) audit: Option<Audit>,
+
+	/// Post-install helpers that didn't get run, as the commands to run
+	#[serde(default)]
+	pub(crate) deferred: Vec<String>,
+
+	/// How much it changed.  Older entries don't have it.
+	#[serde(default)]
+	pub(crate) counts: Option<InstallCounts>,
+
+	/// File flags the basedir couldn't keep, if any; a later check-sys
+	/// of wherever the result ended up would show them as differing.
+	#[serde(default)]
+	pub(crate) flags_lost: Option<crate::core::fsprobe::FlagsLost>,
+}
+
+/// The counts out of a ManifestBrief, for remembering in a HistoryEntry
+/// after the manifest's gone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct InstallCounts
+{
+	pub(crate) added: usize,
+	pub(crate) removed: usize,
+	pub(crate) updated: usize,
+	pub(crate) type_changes: usize,
+	pub(crate) merged: usize,
+	pub(crate) old_libs: usize,
+	pub(crate) kept_metadata: usize,
+}
+
+impl From<&ManifestBrief> for InstallCounts
+{
+	fn from(b: &ManifestBrief) -> Self
+	{
+		Self { added: b.added, removed: b.removed, updated: b.updated,
+				type_changes: b.type_changes, merged: b.merge_clean,
+				old_libs: b.old_libs, kept_metadata: b.kept_metadata }
+	}
+}
+
+/// How the closing audit of an upgrade went.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Audit
+{
+	/// How many paths were checked
+	pub(crate) checked: usize,
+
+	/// What didn't match, as (path, what, detail); x-ref
+	/// crate::core::install::Mismatch.
+	pub(crate) mismatches: Vec<(PathBuf, String, String)>,
+}
+
+impl Audit
+{
+	pub(crate) fn passed(&self) -> bool { self.mismatches.is_empty() }
+}
+
+
+/// How many RecoveryPoint's we hang onto
+const RECOVERY_KEEP: usize = 5;
+
+
+/// Something install left behind that you could roll back to by hand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct RecoveryPoint
+{
+	/// What sort of thing it is
+	pub(crate) kind: RecoveryKind,
+
+	/// The BE name, or the kernel backup dir
+	pub(crate) name: String,
+
+	/// When we made it (unix time)
+	pub(crate) when: i64,
+
+	/// The version it holds; i.e., what we were installing from.
+	pub(crate) version: String,
+
+	/// Did we see it there after making it?
+	pub(crate) verified: bool,
+}
+
+/// Sorts of RecoveryPoint's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) enum RecoveryKind
+{
+	/// A boot environment, from bectl
+	BootEnv,
+
+	/// A kernel.old-style copy of the kernel dir
+	KernelBackup,
+}
+
+impl RecoveryPoint
+{
+	/// Make a new one, as of now.
+	pub(crate) fn new(kind: RecoveryKind, name: String, version: String,
+			verified: bool) -> Self
+	{
+		let when = chrono::Utc::now().timestamp();
+		Self { kind, name, when, version, verified }
+	}
+
+
+	/// How to go back to it.
+	pub(crate) fn describe(&self) -> String
+	{
+		use chrono::{DateTime, Local};
+		let when: DateTime<Local> = match DateTime::from_timestamp(self.when, 0)
+		{
+			Some(w) => w.into(),
+			None => Local::now(),
+		};
+		let when = when.format("%Y-%m-%d %H:%M");
+
+		let Self { name, version, .. } = self;
+		let mut ret = match self.kind {
+			RecoveryKind::BootEnv => format!("boot environment {name} \
+					contains the {version} system (from {when})"),
+			RecoveryKind::KernelBackup => format!("{name} contains the \
+					{version} kernel (from {when})"),
+		};
+		if !self.verified { ret.push_str(" [unverified]"); }
+		ret
+	}
+}
+
+
+/// A remembered good server.
+#[derive(Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct ServerHint
+{
+	/// The ServerName config it was found under.
+	pub(crate) servername: String,
+
+	/// The host that worked.
+	pub(crate) host: String,
+
+	/// When we last got a keytag from it (unix time).
+	pub(crate) when: i64,
 }
 
 
@@ -71,6 +325,50 @@ pub(crate) struct ManiFetch
 
 	/// What we think the new version will be.
 	vers: AVersion,
+
+	/// What the system was running when this was made.  Older manifests
+	/// don't have it.
+	#[serde(default)]
+	from: Option<AVersion>,
+
+	/// Anything unusual about how this was put together (e.g., one-off
+	/// path filters), to remind the user later.
+	#[serde(default)]
+	note: Option<String>,
+
+	/// The KeyPrint everything in here was checked against.  Older
+	/// manifests don't have it; x-ref Manifest::keyprint_mismatch().
+	#[serde(default)]
+	keyprint: Option<String>,
+
+	/// Upstream changes UpdateIfUnmodified kept out, to remind the user
+	/// later.
+	#[serde(default)]
+	skipped_updates: Vec<SkippedUpdate>,
+
+	/// Where KeepModifiedMetadata kept local perms over upstream's, to
+	/// show later.
+	#[serde(default)]
+	kept_metadata: Vec<metadata::MetaKept>,
+
+	/// Files we'd replace with the AnnotateXattr extattr on them
+	#[serde(default)]
+	annotated: Option<Annotated>,
+
+	/// How much install will write out, decompressed; x-ref
+	/// Manifest::size_install().
+	#[serde(default)]
+	install_bytes: Option<u64>,
+
+	/// The mtime to give what we install, if PreserveTimestamps said to
+	/// pin it.
+	#[serde(default)]
+	install_mtime: Option<i64>,
+
+	/// Paths that failed to install under `install --keep-going`; the
+	/// next install only retries these.
+	#[serde(default)]
+	install_failed: Vec<PathBuf>,
 }
 
 
@@ -104,16 +402,195 @@ pub(crate) struct ManiUpgrade
 	/// What we think the new version will be.
 	vers: AVersion,
 
+	/// What the system was running when this was made; x-ref ManiFetch.
+	#[serde(default)]
+	from: Option<AVersion>,
+
+	/// Anything unusual about how this was put together (e.g., one-off
+	/// path filters), to remind the user later.
+	#[serde(default)]
+	note: Option<String>,
+
+	/// x-ref ManiFetch
+	#[serde(default)]
+	keyprint: Option<String>,
+
+	/// x-ref ManiFetch
+	#[serde(default)]
+	pub(crate) kept_metadata: Vec<metadata::MetaKept>,
+
+	/// x-ref ManiFetch
+	#[serde(default)]
+	annotated: Option<Annotated>,
+
+	/// x-ref ManiFetch
+	#[serde(default)]
+	install_mtime: Option<i64>,
+
+	/// x-ref ManiFetch
+	#[serde(default)]
+	install_failed: Vec<PathBuf>,
+
 	/// Info about files that were successfully merged; this means the
 	/// 'new' entries above aren't the pristine upstream new, but a merge
 	/// of our previous state.  This may be important for the user to
 	/// see.
+	#[serde(serialize_with = "crate::util::sorted::map")]
 	pub(crate) merge_clean: HashMap<PathBuf, merge::Clean>,
 
 	/// Files that were not successfully merged, but have conflicts that
 	/// need to be resolved.  This needs to be emptied out before we can
 	/// install this pending upgrade.
+	#[serde(serialize_with = "crate::util::sorted::map")]
 	pub(crate) merge_conflict: HashMap<PathBuf, merge::Conflict>,
+
+	/// Old shared libraries whose removal is deferred until after the
+	/// world is installed (and the user has rebuilt things against the
+	/// new ones).
+	#[serde(default)]
+	pub(crate) old_libs: Vec<PathBuf>,
+
+	/// Locally modified files that UpdateIfUnmodified kept upstream's
+	/// changes from.  This is also what Manifest::skipped_updates() gives
+	/// for an upgrade.
+	#[serde(default, serialize_with = "crate::util::sorted::map")]
+	pub(crate) skipped: HashMap<PathBuf, merge::Skipped>,
+
+	/// How much the kernel and world steps will install, for guessing
+	/// how long they'll take.  Worked out at the end of upgrade, so
+	/// show-install doesn't have to go poking through the files dir.
+	#[serde(default)]
+	pub(crate) sizes: Option<StepSizes>,
+}
+
+
+/// How much an upgrade's install step will write out.
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct StepSize
+{
+	/// Number of files
+	pub(crate) files: usize,
+
+	/// Their total (decompressed) size
+	pub(crate) bytes: u64,
+}
+
+/// StepSize's for the kernel and world steps of an upgrade.
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct StepSizes
+{
+	pub(crate) kernel: StepSize,
+	pub(crate) world: StepSize,
+}
+
+impl StepSizes
+{
+	/// Rough seconds to install a step at a given MB/s.  Very rough; it
+	/// doesn't know anything about the disk, and doesn't count the
+	/// reboot.
+	pub(crate) fn est_secs(step: &StepSize, mbps: u32) -> u64
+	{
+		let bps = u64::from(mbps.max(1)) * 1024 * 1024;
+		step.bytes.div_ceil(bps)
+	}
+
+	/// Lines describing the steps and how long they might take, for
+	/// printing out.
+	pub(crate) fn describe(&self, mbps: u32) -> Vec<String>
+	{
+		use crate::util::{human_bytes, plural};
+		let steps = [("Kernel", &self.kernel), ("World", &self.world)];
+		steps.iter().map(|(name, st)| {
+			let secs = Self::est_secs(st, mbps);
+			format!("{name}: {} file{}, {}; roughly {} at {mbps} MB/s",
+					st.files, plural(st.files), human_bytes(st.bytes),
+					human_secs(secs))
+		}).collect()
+	}
+}
+
+/// A handful of seconds, in a human-ish way
+fn human_secs(secs: u64) -> String
+{
+	match secs {
+		0..=59 => format!("{secs}s"),
+		60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+		_ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+	}
+}
+
+
+/// The summary-level info about a Manifest, saved in the statefile.
+/// This is enough for a show-install that's not being verbose.
+#[derive(Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct ManifestBrief
+{
+	/// Manifest::mtype()
+	pub(crate) mtype: String,
+
+	/// Manifest::version()
+	pub(crate) version: AVersion,
+
+	/// Manifest::state()
+	pub(crate) state: String,
+
+	/// Counts of files added/removed/updated
+	pub(crate) added: usize,
+	pub(crate) removed: usize,
+	pub(crate) updated: usize,
+
+	/// Number of paths that change type
+	pub(crate) type_changes: usize,
+
+	/// Merge counts, and old libs pending removal (upgrade only)
+	pub(crate) merge_clean: usize,
+	pub(crate) merge_conflict: usize,
+	pub(crate) old_libs: usize,
+
+	/// Manifest::note()
+	#[serde(default)]
+	pub(crate) note: Option<String>,
+
+	/// Manifest::skipped_updates()
+	#[serde(default)]
+	pub(crate) skipped_updates: Vec<SkippedUpdate>,
+
+	/// How many Manifest::kept_metadata()
+	#[serde(default)]
+	pub(crate) kept_metadata: usize,
+
+	/// Manifest::annotated()
+	#[serde(default)]
+	pub(crate) annotated: Option<Annotated>,
+
+	/// Manifest::risks(), boiled down
+	#[serde(default)]
+	pub(crate) risk: RiskSummary,
+
+	/// Kernel/world install sizes (upgrade only)
+	#[serde(default)]
+	pub(crate) sizes: Option<StepSizes>,
+
+	/// Manifest::install_bytes()
+	#[serde(default)]
+	pub(crate) install_bytes: Option<u64>,
+}
+
+
+/// Files the pending install replaces or removes that carry the
+/// AnnotateXattr extattr.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Annotated
+{
+	/// The extattr, e.g. user.managed_by
+	pub(crate) attr: String,
+
+	/// Which paths have it
+	pub(crate) paths: Vec<PathBuf>,
 }
 
 
@@ -135,6 +612,99 @@ pub(crate) struct ManifestSummary
 
 impl State
 {
+	/// The cached server to try first, if there is one for this
+	/// servername and it's not older than ttl seconds.
+	pub(crate) fn server_hint(&self, servername: &str, ttl: u64)
+			-> Option<&str>
+	{
+		let hint = self.server_hint.as_ref()?;
+		if hint.servername != servername { return None; }
+
+		let now = chrono::Utc::now().timestamp();
+		let age = now.saturating_sub(hint.when);
+		if age < 0 || age as u64 >= ttl { return None; }
+
+		Some(&hint.host)
+	}
+
+
+	/// Remember a server that worked.
+	pub(crate) fn set_server_hint(&mut self, servername: &str,
+			srv: &crate::server::Server)
+	{
+		let servername = servername.to_string();
+		let host = srv.name().to_string();
+		let when = chrono::Utc::now().timestamp();
+		self.server_hint = Some(ServerHint { servername, host, when });
+	}
+
+	/// Forget any cached metadata index that wasn't verified against
+	/// the KeyPrint configured now.  That's what a rotated signing key
+	/// looks like, and nothing the old key vouched for gets trusted until
+	/// the new one vouches for it too, which means going back to the
+	/// server for the tag.  Ones from before we kept track go the same
+	/// way, since there's no telling which key they were under.
+	///
+	/// The downloaded files stay; they're stored by hash, so anything the
+	/// new index lists is still good.  Gives back something to tell the
+	/// user, if anything went.
+	pub(crate) fn keyprint_check(&mut self, keyprint: &str)
+			-> Option<String>
+	{
+		let stale = |kp: Option<&str>| kp != Some(keyprint);
+
+		let mut gone = Vec::new();
+		let mkp = self.meta_idx_keyprint.as_deref();
+		let tracked = mkp.is_some();
+		if self.meta_idx.is_some() && stale(mkp)
+		{
+			self.meta_idx = None;
+			self.meta_idx_vers = None;
+			self.meta_idx_keyprint = None;
+			gone.push("saved metadata index");
+		}
+		if gone.is_empty() { return None; }
+
+		let kp: String = keyprint.chars().take(16).collect();
+		let gone = gone.join(" and the ");
+		let (was, is) = match gone.contains(" and ") {
+			false => ("wasn't", "is"),
+			true  => ("weren't", "are"),
+		};
+		let why = match tracked {
+			true  => format!("The {gone} {was} verified against the \
+					configured KeyPrint ({kp}...); maybe the signing key \
+					was rotated."),
+			false => format!("The {gone} {is} from before the KeyPrint \
+					it was checked against got recorded, so it can't be \
+					matched up with the configured one ({kp}...)."),
+		};
+		Some(format!("{why}  Nothing in there gets trusted until it's \
+				been re-fetched and checked against the configured key; \
+				already downloaded files are kept."))
+	}
+
+
+	/// Note a new RecoveryPoint, forgetting the oldest if we've got too
+	/// many.
+	pub(crate) fn add_recovery(&mut self, rp: RecoveryPoint)
+	{
+		self.recovery.push(rp);
+		let extra = self.recovery.len().saturating_sub(RECOVERY_KEEP);
+		self.recovery.drain(..extra);
+	}
+
+
+	/// Note a finished install, forgetting the oldest if we've got too
+	/// many.
+	pub(crate) fn add_history(&mut self, he: HistoryEntry)
+	{
+		self.history.push(he);
+		let extra = self.history.len().saturating_sub(HISTORY_KEEP);
+		self.history.drain(..extra);
+	}
+
+
 	/// Is an 'upgrade' (the specific command, not the general concept)
 	/// currently in-progress?
 	///
@@ -166,7 +736,11 @@ impl Manifest
 	pub(crate) fn new_fetch(cur: Metadata, new: Metadata, vers: AVersion)
 			-> Self
 	{
-		let mf = ManiFetch { cur, new, vers };
+		let mf = ManiFetch { cur, new, vers, from: None, note: None,
+				keyprint: None,
+				skipped_updates: Vec::new(), kept_metadata: Vec::new(),
+				annotated: None, install_bytes: None, install_mtime: None,
+				install_failed: Vec::new() };
 		Self::Fetch(mf)
 	}
 
@@ -181,8 +755,15 @@ impl Manifest
 	{
 		let kernel = false;
 		let world = false;
-		let mu = ManiUpgrade { kernel, world,
-				cur, new, vers, merge_clean, merge_conflict };
+		let old_libs = Vec::new();
+		let mut mu = ManiUpgrade { kernel, world,
+				cur, new, vers, merge_clean, merge_conflict, old_libs,
+				skipped: HashMap::new(),
+				from: None, note: None, keyprint: None, sizes: None,
+				kept_metadata: Vec::new(),
+				annotated: None, install_mtime: None,
+				install_failed: Vec::new() };
+		mu.old_libs = mu.find_old_libs();
 		Self::Upgrade(mu)
 	}
 
@@ -229,6 +810,182 @@ impl Manifest
 	}
 
 
+	/// Everything change_summary() covers, sorted into risk buckets.
+	pub(crate) fn risks(&self) -> Risks
+	{
+		let sum = self.change_summary();
+		let (cur, new) = match self {
+			Self::Fetch(f)   => (&f.cur, &f.new),
+			Self::Upgrade(u) => (&u.cur, &u.new),
+		};
+		let all = sum.added.iter().chain(&sum.removed).chain(&sum.updated);
+		crate::core::risk::categorize(all, cur, new)
+	}
+
+
+	/// Build up the ManifestBrief for this.
+	pub(crate) fn brief(&self) -> ManifestBrief
+	{
+		let sum = self.change_summary();
+		let (merge_clean, merge_conflict, old_libs) = match self {
+			Self::Fetch(_)   => (0, 0, 0),
+			Self::Upgrade(u) => (u.num_clean(), u.num_conflict(),
+					u.old_libs.len()),
+		};
+		ManifestBrief {
+			mtype: self.mtype().to_string(),
+			version: self.version().clone(),
+			state: self.state().to_string(),
+			added: sum.added.len(),
+			removed: sum.removed.len(),
+			updated: sum.updated.len(),
+			type_changes: self.type_changes().len(),
+			merge_clean, merge_conflict, old_libs,
+			note: self.note().map(|n| n.to_string()),
+			skipped_updates: self.skipped_updates(),
+			kept_metadata: self.kept_metadata().len(),
+			annotated: self.annotated().cloned(),
+			risk: self.risks().summary(),
+			sizes: match self {
+				Self::Fetch(_)   => None,
+				Self::Upgrade(u) => u.sizes,
+			},
+			install_bytes: self.install_bytes(),
+		}
+	}
+
+
+	/// How much the install will write into the basedir, decompressed,
+	/// if it's been sized up.
+	pub(crate) fn install_bytes(&self) -> Option<u64>
+	{
+		match self {
+			Self::Fetch(f)   => f.install_bytes,
+			Self::Upgrade(u) => u.sizes.map(|s| s.kernel.bytes + s.world.bytes),
+		}
+	}
+
+	/// Lines about what a run moved around, what install will write out,
+	/// and what `clean --files` could free up, for the end of fetch and
+	/// upgrade.
+	pub(crate) fn bytes_summary(&self, rtdirs: &crate::core::RtDirs,
+			run: &crate::util::bytecount::Bytes) -> Vec<String>
+	{
+		use crate::util::{human_bytes, plural};
+
+		let mut ret = vec![format!("This run: {}.", run.describe())];
+		if let Some(ib) = self.install_bytes()
+		{
+			ret.push(format!("Install will write {} into the basedir.",
+					human_bytes(ib)));
+		}
+		match rtdirs.unneeded_files(&self.install_hashes()) {
+			Ok(un) if un.is_empty() => (),
+			Ok(un) => {
+				let n = un.len();
+				let b: u64 = un.iter().map(|(_, b)| b).sum();
+				ret.push(format!("`{} clean --files` would free {} ({n} \
+						file{}).", crate::util::cmdname(), human_bytes(b),
+						plural(n)));
+			},
+			Err(e) => ret.push(format!("Couldn't look over the files dir: \
+					{e}")),
+		}
+		ret
+	}
+
+	/// Size up a fetch's install_bytes() from the .gz's in the files
+	/// dir, so they all need to be there.  Upgrades get it from their
+	/// step_sizes() instead.
+	pub(crate) fn size_install(&mut self, rtdirs: &crate::core::RtDirs)
+			-> Result<(), std::io::Error>
+	{
+		use crate::util::compress::gz_isize;
+
+		let f = match self {
+			Self::Fetch(f)   => f,
+			Self::Upgrade(_) => return Ok(()),
+		};
+		let mut bytes = 0;
+		for mf in f.new.files.values()
+		{
+			let hf = rtdirs.hashfile(&mf.sha256.to_buf());
+			bytes += gz_isize(&hf).map_err(|e| {
+				std::io::Error::new(e.kind(),
+						format!("{}: {e}", hf.display()))
+			})?;
+		}
+		f.install_bytes = Some(bytes);
+		Ok(())
+	}
+
+
+	/// The hashfiles that need to be in the files dir to install this.
+	/// Like f-u.sh install_verify(), that's for both sides, not just
+	/// what we're installing.
+	pub(crate) fn install_hashes(&self)
+			-> std::collections::HashSet<crate::util::hash::Sha256Hash>
+	{
+		let (cur, new) = match self {
+			Self::Fetch(f)   => (&f.cur, &f.new),
+			Self::Upgrade(u) => (&u.cur, &u.new),
+		};
+		cur.files.values().chain(new.files.values()).map(|f| f.sha256)
+				.collect()
+	}
+
+
+	/// Everything in the files dir this still refers to: what install
+	/// needs, plus the merge inputs/results resolve-merges and
+	/// show-merges go back to.  What MaxWorkdirSize mustn't evict.
+	pub(crate) fn cache_hashes(&self)
+			-> std::collections::HashSet<crate::util::hash::Sha256Hash>
+	{
+		use crate::util::hash::Sha256Hash;
+
+		let mut ret = self.install_hashes();
+		if let Self::Upgrade(u) = self
+		{
+			let clean = u.merge_clean.values()
+					.flat_map(|c| [&c.old, &c.new, &c.cur, &c.res]);
+			let conflict = u.merge_conflict.values()
+					.flat_map(|c| [&c.old, &c.new, &c.cur, &c.res]);
+			let skipped = u.skipped.values().flat_map(|s| [&s.cur, &s.new]);
+			ret.extend(clean.chain(conflict).chain(skipped)
+					.map(Sha256Hash::from));
+		}
+		ret
+	}
+
+
+	/// The dirs this installs things into (or removes them from); the
+	/// parents of everything on either side.
+	pub(crate) fn install_dirs(&self) -> Vec<PathBuf>
+	{
+		let (cur, new) = match self {
+			Self::Fetch(f)   => (&f.cur, &f.new),
+			Self::Upgrade(u) => (&u.cur, &u.new),
+		};
+		let mut ret: Vec<PathBuf> = cur.allpaths_hashset_nodash().into_iter()
+				.chain(new.allpaths_hashset_nodash())
+				.filter_map(|p| p.parent()).map(|p| p.to_path_buf())
+				.collect();
+		ret.sort_unstable();
+		ret.dedup();
+		ret
+	}
+
+
+	/// The current and new sides of it
+	pub(crate) fn cur_new(&self) -> (&Metadata, &Metadata)
+	{
+		match self {
+			Self::Fetch(f)   => (&f.cur, &f.new),
+			Self::Upgrade(u) => (&u.cur, &u.new),
+		}
+	}
+
+
 	/// Show the type changes of a pending <whatever>
 	pub(crate) fn type_changes(&self) -> HashMap<PathBuf, metadata::MetaChange>
 	{
@@ -248,6 +1005,210 @@ impl Manifest
 		}
 	}
 
+	/// Any note about how this was put together
+	pub(crate) fn note(&self) -> Option<&str>
+	{
+		match self {
+			Self::Fetch(f)   => f.note.as_deref(),
+			Self::Upgrade(u) => u.note.as_deref(),
+		}
+	}
+
+	/// Attach a note to this
+	pub(crate) fn set_note(&mut self, note: Option<String>)
+	{
+		match self {
+			Self::Fetch(f)   => f.note = note,
+			Self::Upgrade(u) => u.note = note,
+		}
+	}
+
+	/// Upstream changes UpdateIfUnmodified kept out, sorted
+	pub(crate) fn skipped_updates(&self) -> Vec<SkippedUpdate>
+	{
+		match self {
+			Self::Fetch(f)   => f.skipped_updates.clone(),
+			Self::Upgrade(u) => {
+				let mut sk: Vec<_> = u.skipped.iter()
+						.map(|(p, s)| SkippedUpdate { path: p.clone(),
+							security: s.security })
+						.collect();
+				sk.sort_unstable();
+				sk
+			},
+		}
+	}
+
+	/// Note the upstream changes UpdateIfUnmodified kept out of a fetch.
+	/// An upgrade keeps them in its ManiUpgrade::skipped.
+	pub(crate) fn set_skipped_updates(&mut self, sk: Vec<SkippedUpdate>)
+	{
+		if let Self::Fetch(f) = self { f.skipped_updates = sk; }
+	}
+
+	/// Where KeepModifiedMetadata kept local perms over upstream's
+	pub(crate) fn kept_metadata(&self) -> &[metadata::MetaKept]
+	{
+		match self {
+			Self::Fetch(f)   => &f.kept_metadata,
+			Self::Upgrade(u) => &u.kept_metadata,
+		}
+	}
+
+	/// Note where KeepModifiedMetadata kept local perms
+	pub(crate) fn set_kept_metadata(&mut self, km: Vec<metadata::MetaKept>)
+	{
+		match self {
+			Self::Fetch(f)   => f.kept_metadata = km,
+			Self::Upgrade(u) => u.kept_metadata = km,
+		}
+	}
+
+	/// Marked files (AnnotateXattr) this would replace or remove
+	pub(crate) fn annotated(&self) -> Option<&Annotated>
+	{
+		match self {
+			Self::Fetch(f)   => f.annotated.as_ref(),
+			Self::Upgrade(u) => u.annotated.as_ref(),
+		}
+	}
+
+	/// Note the files found with the AnnotateXattr attr.  Only the ones
+	/// we'd actually replace or remove are kept.
+	pub(crate) fn set_annotated(&mut self, attr: &str, mut paths: Vec<PathBuf>)
+	{
+		let sum = self.change_summary();
+		let touched: std::collections::HashSet<_> = sum.updated.iter()
+				.chain(&sum.removed).collect();
+		paths.retain(|p| touched.contains(p));
+		let an = match paths.is_empty() {
+			true  => None,
+			false => Some(Annotated { attr: attr.to_string(), paths }),
+		};
+		match self {
+			Self::Fetch(f)   => f.annotated = an,
+			Self::Upgrade(u) => u.annotated = an,
+		}
+	}
+
+	/// The current files this replaces, with the owner/mode/flags/hash
+	/// the scan saw, sorted.  Their contents got stashed (x-ref
+	/// core::stash::stash_current()), so this is what it'd take to put
+	/// them back exactly.
+	pub(crate) fn stashed(&self) -> Vec<&metadata::MetaFile>
+	{
+		let cur = match self {
+			Self::Fetch(f)   => &f.cur,
+			Self::Upgrade(u) => &u.cur,
+		};
+		let mut ret: Vec<_> = cur.files.values().collect();
+		ret.sort_unstable();
+		ret
+	}
+
+	/// What mtime to give installed files, if it's pinned
+	pub(crate) fn install_mtime(&self) -> Option<i64>
+	{
+		match self {
+			Self::Fetch(f)   => f.install_mtime,
+			Self::Upgrade(u) => u.install_mtime,
+		}
+	}
+
+	/// Pin (or not) the mtime of installed files
+	pub(crate) fn set_install_mtime(&mut self, mtime: Option<i64>)
+	{
+		match self {
+			Self::Fetch(f)   => f.install_mtime = mtime,
+			Self::Upgrade(u) => u.install_mtime = mtime,
+		}
+	}
+
+	/// What failed to install last time around, to retry
+	pub(crate) fn install_failed(&self) -> &[PathBuf]
+	{
+		match self {
+			Self::Fetch(f)   => &f.install_failed,
+			Self::Upgrade(u) => &u.install_failed,
+		}
+	}
+
+	/// Remember what failed to install (or that nothing did)
+	pub(crate) fn set_install_failed(&mut self, paths: Vec<PathBuf>)
+	{
+		match self {
+			Self::Fetch(f)   => f.install_failed = paths,
+			Self::Upgrade(u) => u.install_failed = paths,
+		}
+	}
+
+	/// What the system was running when this was made, if we know
+	pub(crate) fn from(&self) -> Option<&AVersion>
+	{
+		match self {
+			Self::Fetch(f)   => f.from.as_ref(),
+			Self::Upgrade(u) => u.from.as_ref(),
+		}
+	}
+
+	/// Note what the system is running as this is made
+	pub(crate) fn set_from(&mut self, from: AVersion)
+	{
+		match self {
+			Self::Fetch(f)   => f.from = Some(from),
+			Self::Upgrade(u) => u.from = Some(from),
+		}
+	}
+
+	/// Note the KeyPrint what went into this was checked against
+	pub(crate) fn set_keyprint(&mut self, keyprint: &str)
+	{
+		let kp = Some(keyprint.to_string());
+		match self {
+			Self::Fetch(f)   => f.keyprint = kp,
+			Self::Upgrade(u) => u.keyprint = kp,
+		}
+	}
+
+	/// If this wasn't made under `keyprint`, what was it made under?
+	/// `Some(None)` is one from before we kept track, which can't say.
+	pub(crate) fn keyprint_mismatch(&self, keyprint: &str)
+			-> Option<Option<&str>>
+	{
+		let kp = match self {
+			Self::Fetch(f)   => f.keyprint.as_deref(),
+			Self::Upgrade(u) => u.keyprint.as_deref(),
+		};
+		match kp == Some(keyprint) {
+			true  => None,
+			false => Some(kp),
+		}
+	}
+
+	/// If the system isn't running what this was made against anymore
+	/// (say, it got upgraded some other way in the meantime), what is
+	/// it running?  Installing then could put older files over newer
+	/// ones.
+	///
+	/// Partway through an upgrade, once the kernel's been installed,
+	/// we may be running the new version already, which is fine.
+	pub(crate) fn source_mismatch<'a>(&self, running: &'a AVersion)
+			-> Option<&'a AVersion>
+	{
+		// Can't tell with old manifests
+		let from = self.from()?;
+		if running == from { return None; }
+
+		if let Self::Upgrade(u) = self
+		{
+			let to = &u.vers;
+			let rebooted = running.release == to.release
+					&& running.reltype == to.reltype;
+			if u.kernel && rebooted { return None; }
+		}
+		Some(running)
+	}
+
 	/// Stringy type
 	pub(crate) fn mtype(&self) -> &'static str
 	{
@@ -287,6 +1248,91 @@ impl ManiUpgrade
 	pub(crate) fn num_conflict(&self) -> usize { self.merge_conflict.len() }
 
 
+	/// Find the shared libs that are going away in this upgrade.  These
+	/// get held back from removal until the very end.
+	pub(crate) fn find_old_libs(&self) -> Vec<PathBuf>
+	{
+		let shlib = crate::core::install::re_so_file();
+		let newpaths = self.new.allpaths_hashset_nodash();
+
+		let mut ret: Vec<_> = self.cur.allpaths_hashset_nodash().into_iter()
+				.filter(|p| !newpaths.contains(p))
+				.filter(|p| {
+					let pstr = p.to_string_lossy();
+					shlib.is_match(&pstr)
+				})
+				.map(|p| p.to_path_buf())
+				.collect();
+		ret.sort_unstable();
+		ret
+	}
+
+
+	/// What was already up to date, so wasn't part of the upgrade proper,
+	/// out of the target's whole metadata (`all`, filtered the same way
+	/// it was for the upgrade).  That's whatever the upgrade doesn't
+	/// cover one way or another.  Locally modified UpdateIfUnmodified
+	/// files got left out without a trace, so anything matching `uium`
+	/// goes too, like where KeepModifiedMetadata kept ours.
+	pub(crate) fn unchanged(&self, mut all: Metadata,
+			uium: &[regex_lite::Regex]) -> Metadata
+	{
+		use std::collections::HashSet;
+		use crate::metadata::PathMatcher;
+
+		let mut covered: HashSet<PathBuf> = HashSet::new();
+		for md in [&self.new, &self.cur]
+		{
+			covered.extend(md.allpaths().into_iter()
+					.map(|p| p.to_path_buf()));
+		}
+		covered.extend(self.skipped.keys().cloned());
+		covered.extend(self.kept_metadata.iter().map(|k| k.path.clone()));
+		all.remove_paths(&covered);
+		all.remove_paths_matcher(&PathMatcher::new(uium));
+		all
+	}
+
+
+	/// Work out how many files, and how many bytes, the kernel and world
+	/// steps will install.  Sizes come from the .gz's in the files dir,
+	/// so they all need to be there.
+	pub(crate) fn step_sizes(&self, rtdirs: &crate::core::RtDirs)
+			-> Result<StepSizes, std::io::Error>
+	{
+		use crate::metadata::MetadataLine as ML;
+		use crate::util::is_kernel_dir;
+		use crate::util::compress::gz_isize;
+
+		// Added and updated is everything in new; same list install
+		// works from.
+		let ipaths: Vec<_> = self.new.allpaths_hashset_nodash().into_iter()
+				.map(|p| p.to_path_buf()).collect();
+
+		let mut ret = StepSizes::default();
+		for (p, ml) in self.get_from_paths(ipaths)
+		{
+			let f = match ml {
+				ML::File(f) => f,
+				_ => continue,
+			};
+			let hf = rtdirs.hashfile(&f.sha256.to_buf());
+			let bytes = gz_isize(&hf).map_err(|e| {
+				std::io::Error::new(e.kind(),
+						format!("{}: {e}", hf.display()))
+			})?;
+
+			let step = match is_kernel_dir(&p) {
+				true  => &mut ret.kernel,
+				false => &mut ret.world,
+			};
+			step.files += 1;
+			step.bytes += bytes;
+		}
+		Ok(ret)
+	}
+
+
 	/// For upgrades, since there may be merges, getting the info about
 	/// what to install for a set of paths requires checking the merges
 	/// as well as the new's.
@@ -349,9 +1395,66 @@ pub(crate) enum StateLoadErr
 }
 
 
+/// What actually gets written to the statefile: the State, with the
+/// brief filled in from the manifest we're writing alongside.
+#[derive(serde::Serialize)]
+struct SavedState<'a>
+{
+	#[serde(flatten)]
+	state: &'a State,
+
+	manifest_brief: Option<ManifestBrief>,
+}
+
+
 /// Load current state from a statedir.  Mostly you'll be using this via
 /// Config::state_load() instead.
 pub(crate) fn load_from_dir(dir: &std::path::Path) -> Result<State, StateLoadErr>
+{
+	let mut state = load_statefile(dir)?;
+
+	// Older statefiles had the manifest inline, in which case we've
+	// already got it.  Otherwise, it's in its own file, if there is one.
+	let manifile = dir.join(MANIFESTFILE);
+	if state.manifest.is_none() && manifile.is_file()
+	{
+		let mstr = std::fs::read_to_string(&manifile)?;
+		state.manifest = Some(serde_json::from_str(&mstr)?);
+	}
+
+	Ok(state)
+}
+
+
+/// Load current state from a statedir, without the manifest; just the
+/// manifest_brief.  Mostly you'll be using this via
+/// Config::state_load_brief() instead.
+///
+/// Saving a state loaded this way leaves the manifest as it was.
+pub(crate) fn load_brief_from_dir(dir: &std::path::Path)
+		-> Result<State, StateLoadErr>
+{
+	let mut state = load_statefile(dir)?;
+
+	// Statefiles from before we split things up have the full manifest
+	// inline, and no brief; we've paid for loading it already, so just
+	// summarize it.  And a missing brief with a manifest off on its own
+	// shouldn't happen, but if it does, go ahead and load it up.
+	if state.manifest_brief.is_none()
+	{
+		if state.manifest.is_none() && dir.join(MANIFESTFILE).is_file()
+		{ return load_from_dir(dir); }
+		state.manifest_brief = state.manifest.as_ref().map(|m| m.brief());
+	}
+
+	state.manifest = None;
+	state.brief = true;
+	Ok(state)
+}
+
+
+/// Read in the statefile itself.
+fn load_statefile(dir: &std::path::Path) -> Result<State, StateLoadErr>
 {
 	use StateLoadErr as SLE;
 
@@ -369,8 +1472,62 @@ pub(crate) fn load_from_dir(dir: &std::path::Path) -> Result<State, StateLoadErr
 }
 
 
+/// Check just the statefile loads, without the manifest; for telling a
+/// broken statefile from a broken manifest.
+pub(crate) fn check_statefile(dir: &std::path::Path)
+		-> Result<(), StateLoadErr>
+{
+	load_statefile(dir).map(|_| ())
+}
+
+
+/// Is there a backup statefile (from before the last save) that loads,
+/// along with its manifest, if it had one?
+pub(crate) fn backup_loads(dir: &std::path::Path) -> bool
+{
+	let state = std::fs::read_to_string(dir.join(STATEFILE_BAK)).ok()
+			.map_or(false, |s| serde_json::from_str::<State>(&s).is_ok());
+	let mani = match std::fs::read_to_string(dir.join(MANIFESTFILE_BAK)) {
+		Ok(s) => serde_json::from_str::<Manifest>(&s).is_ok(),
+		Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+	};
+	state && mani
+}
+
+
+/// Put the backup statefile and its manifest back as the current ones.
+/// The backups stay around.
+pub(crate) fn restore_backup(dir: &std::path::Path)
+		-> Result<(), StateLoadErr>
+{
+	let restore = |bak: &str, cur: &str| -> Result<(), std::io::Error> {
+		let new = dir.join(format!("{cur}.new"));
+		std::fs::copy(dir.join(bak), &new)?;
+		std::fs::rename(&new, dir.join(cur))
+	};
+
+	// Manifest first, like saving, so the statefile never describes one
+	// that isn't there.  No backup manifest means there wasn't one.
+	match dir.join(MANIFESTFILE_BAK).is_file() {
+		true  => restore(MANIFESTFILE_BAK, MANIFESTFILE)?,
+		false => match std::fs::remove_file(dir.join(MANIFESTFILE)) {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+			_ => (),
+		},
+	}
+	restore(STATEFILE_BAK, STATEFILE)?;
+	Ok(())
+}
+
+
 /// Write state out into a statedir.  Mostly you'll be using this via
 /// Config::state_save() instead.
+///
+/// What gets written depends only on what's in the State: the hashed
+/// collections are written out sorted (x-ref util::sorted), and the
+/// only timestamps are the unix-time `when` fields (plus the server's
+/// `eoltime`).  So two runs over the same inputs give byte-identical
+/// files, aside from those, and you can diff them across machines.
 pub(crate) fn save_to_dir(dir: &std::path::Path, state: &State)
 		-> Result<(), StateLoadErr>
 {
@@ -379,20 +1536,633 @@ pub(crate) fn save_to_dir(dir: &std::path::Path, state: &State)
 	// Statedir better exist
 	if !dir.is_dir() { Err(SLE::NoDir(dir.to_path_buf()))? }
 
-	// Open up the statefile
-	// XXX Maybe someday we should worry about atomic updates.  Of
-	// course, then we should probably be smarter than "blat a bit of
-	// JSON" too, so...
-	let statefile = dir.join(STATEFILE);
-
 	// x-ref string vs reader stuff in load_from_dir above; it applies
 	// here too.
-	use std::io::Write as _;
-	let mut sfwrite = std::fs::File::create(&statefile)?;
-	let stjson = serde_json::to_string(state)?;
-	sfwrite.write_all(stjson.as_ref())?;
-	sfwrite.sync_all()?;
+	fn write(file: &std::path::Path, json: String) -> Result<(), StateLoadErr>
+	{
+		use std::io::Write as _;
+		let mut fwrite = std::fs::File::create(file)?;
+		fwrite.write_all(json.as_ref())?;
+		fwrite.sync_all()?;
+		Ok(())
+	}
+
+	// Whatever's there now hangs around as the _BAK's, the statefile
+	// and manifest together, so they can be put back as a pair.
+	// Hardlinks, so it costs nothing; which means the new ones have to
+	// be renamed into place rather than written over.
+	let manifile = dir.join(MANIFESTFILE);
+	let statefile = dir.join(STATEFILE);
+	if statefile.is_file()
+	{
+		for (cur, bak) in [(STATEFILE, STATEFILE_BAK),
+				(MANIFESTFILE, MANIFESTFILE_BAK)]
+		{
+			let bak = dir.join(bak);
+			match std::fs::remove_file(&bak) {
+				Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+				_ => (),
+			}
+			let cur = dir.join(cur);
+			if cur.is_file() { std::fs::hard_link(&cur, &bak)?; }
+		}
+	}
+
+	// The manifest goes first, so the statefile's brief never describes
+	// a manifest that isn't there yet.  If we only loaded the brief,
+	// there's nothing new to write, and the brief we have is still
+	// right.
+	let brief = match (state.brief, &state.manifest) {
+		(true, _) => state.manifest_brief.clone(),
+		(false, Some(m)) => {
+			let newfile = dir.join(format!("{MANIFESTFILE}.new"));
+			write(&newfile, serde_json::to_string(m)?)?;
+			std::fs::rename(&newfile, &manifile)?;
+			Some(m.brief())
+		},
+		(false, None) => None,
+	};
+
+	// Now the statefile.  It gets written off to the side and renamed
+	// into place too, so a crash partway doesn't leave half of one.
+	let newfile = dir.join(format!("{STATEFILE}.new"));
+	let saved = SavedState { state, manifest_brief: brief };
+	write(&newfile, serde_json::to_string(&saved)?)?;
+	std::fs::rename(&newfile, &statefile)?;
+
+	// And if there's no manifest anymore, clean up any old one.
+	if !state.brief && state.manifest.is_none()
+	{
+		match std::fs::remove_file(&manifile) {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+			_ => (),
+		}
+	}
 
 	// Alright then
 	Ok(())
 }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::{Path, PathBuf};
+	use crate::metadata::{Metadata, MetaFile};
+	use super::{State, Manifest, STATEFILE, MANIFESTFILE};
+	use super::{save_to_dir, load_from_dir, load_brief_from_dir};
+
+	/// A state with a small pending fetch, adding one file.
+	fn mkstate() -> State
+	{
+		let vers = "14.2-RELEASE-p1".parse().unwrap();
+		let mut new = Metadata::default();
+		let f = MetaFile { path: "/bin/x".into(), ..Default::default() };
+		new.files.insert("/bin/x".into(), f);
+
+		let mut manifest = Manifest::new_fetch(Metadata::default(), new,
+				vers);
+		manifest.set_note(Some("filtered".to_string()));
+		State { manifest: Some(manifest), ..Default::default() }
+	}
+
+	fn read_json(dir: &Path) -> serde_json::Value
+	{
+		let s = std::fs::read_to_string(dir.join(STATEFILE)).unwrap();
+		serde_json::from_str(&s).unwrap()
+	}
+
+	#[test]
+	fn split_manifest()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+		save_to_dir(dir, &mkstate()).unwrap();
+
+		// The manifest's off on its own, with just the brief inline
+		assert!(dir.join(MANIFESTFILE).is_file());
+		let sj = read_json(dir);
+		assert!(sj.get("manifest").is_none(), "{sj}");
+		assert_eq!(sj["manifest_brief"]["added"], 1);
+
+		let full = load_from_dir(dir).unwrap();
+		assert!(full.manifest.is_some());
+		assert!(!full.brief);
+
+		// Loading brief never looks at the manifest file
+		std::fs::write(dir.join(MANIFESTFILE), "garbage").unwrap();
+		let brief = load_brief_from_dir(dir).unwrap();
+		assert!(brief.manifest.is_none());
+		assert!(brief.brief);
+		let mb = brief.manifest_brief.as_ref().unwrap();
+		assert_eq!((mb.mtype.as_str(), mb.added, mb.removed), ("fetch", 1, 0));
+		assert_eq!(mb.version.to_string(), "14.2-RELEASE-p1");
+		assert_eq!(mb.note.as_deref(), Some("filtered"));
+		assert!(load_from_dir(dir).is_err());
+	}
+
+	#[test]
+	fn brief_save()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+		save_to_dir(dir, &mkstate()).unwrap();
+
+		// Saving a brief state leaves the manifest (and brief) alone
+		let mut brief = load_brief_from_dir(dir).unwrap();
+		brief.kept_libs.push("/lib/libold.so.1".into());
+		save_to_dir(dir, &brief).unwrap();
+
+		let full = load_from_dir(dir).unwrap();
+		assert!(full.manifest.is_some());
+		assert_eq!(full.kept_libs.len(), 1);
+		assert_eq!(read_json(dir)["manifest_brief"]["added"], 1);
+
+		// And saving a full one without a manifest cleans it all up
+		let mut full = full;
+		full.manifest = None;
+		save_to_dir(dir, &full).unwrap();
+		assert!(!dir.join(MANIFESTFILE).exists());
+		assert!(read_json(dir)["manifest_brief"].is_null());
+		assert!(load_brief_from_dir(dir).unwrap().manifest_brief.is_none());
+	}
+
+	#[test]
+	fn old_statefile()
+	{
+		// Statefiles from before the split have the manifest inline and
+		// no brief.
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+		let state = mkstate();
+		save_to_dir(dir, &state).unwrap();
+		std::fs::remove_file(dir.join(MANIFESTFILE)).unwrap();
+		let mut sj = read_json(dir);
+		let mj = serde_json::to_value(state.manifest.as_ref().unwrap())
+				.unwrap();
+		sj["manifest"] = mj;
+		sj.as_object_mut().unwrap().remove("manifest_brief");
+		std::fs::write(dir.join(STATEFILE), sj.to_string()).unwrap();
+
+		let full = load_from_dir(dir).unwrap();
+		assert!(full.manifest.is_some());
+
+		let brief = load_brief_from_dir(dir).unwrap();
+		assert!(brief.manifest.is_none());
+		assert_eq!(brief.manifest_brief.unwrap().added, 1);
+
+		// Saving it back moves to the new layout
+		save_to_dir(dir, &full).unwrap();
+		assert!(dir.join(MANIFESTFILE).is_file());
+		assert!(read_json(dir).get("manifest").is_none());
+	}
+
+	#[test]
+	fn backup()
+	{
+		use super::{backup_loads, restore_backup, check_statefile};
+
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+
+		// The first save has nothing to back up
+		let mut state = mkstate();
+		save_to_dir(dir, &state).unwrap();
+		assert!(!backup_loads(dir));
+
+		// The second keeps the first
+		state.kept_libs = vec!["/lib/libold.so.1".into()];
+		save_to_dir(dir, &state).unwrap();
+		assert!(backup_loads(dir));
+		assert!(!dir.join(format!("{STATEFILE}.new")).exists());
+
+		// Mangle it, and go back
+		std::fs::write(dir.join(STATEFILE), b"{\"meta_idx\": nu").unwrap();
+		assert!(check_statefile(dir).is_err());
+		load_from_dir(dir).expect_err("mangled");
+		restore_backup(dir).unwrap();
+		check_statefile(dir).unwrap();
+		let back = load_from_dir(dir).unwrap();
+		assert!(back.kept_libs.is_empty());
+		assert!(back.manifest.is_some());
+		assert!(backup_loads(dir));
+
+		// The manifest goes back along with the statefile it was saved
+		// with, not whatever's there now.
+		save_to_dir(dir, &state).unwrap();
+		let mut other = mkstate();
+		other.manifest.as_mut().unwrap().set_note(Some("other".into()));
+		save_to_dir(dir, &other).unwrap();
+		restore_backup(dir).unwrap();
+		let back = load_from_dir(dir).unwrap();
+		assert_eq!(back.kept_libs, state.kept_libs);
+		assert_eq!(back.manifest.unwrap().note(), Some("filtered"));
+
+		// And if there wasn't one then, there isn't one after.
+		save_to_dir(dir, &State::default()).unwrap();
+		save_to_dir(dir, &other).unwrap();
+		assert!(dir.join(MANIFESTFILE).is_file());
+		restore_backup(dir).unwrap();
+		assert!(!dir.join(MANIFESTFILE).exists());
+		assert!(load_from_dir(dir).unwrap().manifest.is_none());
+
+		// A mangled manifest backup means no good backup
+		save_to_dir(dir, &other).unwrap();
+		save_to_dir(dir, &other).unwrap();
+		assert!(backup_loads(dir));
+		let mbak = dir.join(super::MANIFESTFILE_BAK);
+		std::fs::remove_file(&mbak).unwrap();
+		std::fs::write(&mbak, "nope").unwrap();
+		assert!(!backup_loads(dir));
+	}
+
+	#[test]
+	fn server_hint_ttl()
+	{
+		use super::ServerHint;
+
+		let now = chrono::Utc::now().timestamp();
+		let mut state = State::default();
+		let hint = |when| Some(ServerHint { servername: "update.example"
+				.into(), host: "update2.example".into(), when });
+
+		// Nothing remembered, nothing to say
+		assert_eq!(state.server_hint("update.example", 3600), None);
+
+		// Fresh enough, for the right ServerName
+		state.server_hint = hint(now - 60);
+		assert_eq!(state.server_hint("update.example", 3600),
+				Some("update2.example"));
+		assert_eq!(state.server_hint("other.example", 3600), None);
+
+		// Too old, or a TTL of 0 meaning don't bother
+		assert_eq!(state.server_hint("update.example", 30), None);
+		assert_eq!(state.server_hint("update.example", 0), None);
+
+		// From the future means the clock's confused; don't trust it
+		state.server_hint = hint(now + 3600);
+		assert_eq!(state.server_hint("update.example", 86400), None);
+	}
+
+	#[test]
+	fn keyprint_change()
+	{
+		use crate::metadata::MetadataIdx;
+
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+		let (kp1, kp2) = ("1a".repeat(32), "2b".repeat(32));
+		let idx = || MetadataIdx::parse(format!("INDEX-ALL|{}\n",
+				"ab".repeat(32)).as_bytes()).unwrap();
+		let vers: crate::info::AVersion = "14.2-RELEASE-p1".parse().unwrap();
+
+		// A fetch under the first key
+		let mut state = mkstate();
+		state.meta_idx = Some(idx());
+		state.meta_idx_vers = Some(vers.clone());
+		state.meta_idx_keyprint = Some(kp1.clone());
+		save_to_dir(dir, &state).unwrap();
+
+		// Next run, same key; nothing to say
+		let mut state = load_from_dir(dir).unwrap();
+		assert_eq!(state.keyprint_check(&kp1), None);
+		assert!(state.meta_idx.is_some());
+
+		// Then the key gets rotated
+		let mut state = load_brief_from_dir(dir).unwrap();
+		let note = state.keyprint_check(&kp2).expect("should say");
+		assert!(note.starts_with("The saved metadata index wasn't"),
+				"{note}");
+		assert!(note.contains("(2b2b2b2b2b2b2b2b...)"), "{note}");
+		assert!(state.meta_idx.is_none() && state.meta_idx_vers.is_none());
+		save_to_dir(dir, &state).unwrap();
+
+		// And stays gone, rather than coming back under the new key
+		let mut state = load_from_dir(dir).unwrap();
+		assert_eq!(state.keyprint_check(&kp2), None);
+		assert!(state.manifest.is_some(), "only the indices go");
+
+		// A fresh fetch under the new one sticks
+		state.meta_idx = Some(idx());
+		state.meta_idx_keyprint = Some(kp2.clone());
+		save_to_dir(dir, &state).unwrap();
+		let mut state = load_from_dir(dir).unwrap();
+		assert_eq!(state.keyprint_check(&kp2), None);
+		assert!(state.meta_idx.is_some());
+
+		// Statefiles from before we kept track can't say what they were
+		// checked against.
+		let mut sj = read_json(dir);
+		sj.as_object_mut().unwrap().remove("meta_idx_keyprint");
+		std::fs::write(dir.join(STATEFILE), sj.to_string()).unwrap();
+		let mut state = load_from_dir(dir).unwrap();
+		let note = state.keyprint_check(&kp2).expect("should say");
+		assert!(note.starts_with("The saved metadata index is from before"),
+				"{note}");
+		assert!(!note.contains("rotated"), "{note}");
+		assert!(state.meta_idx.is_none());
+	}
+
+
+	#[test]
+	fn manifest_keyprint()
+	{
+		let (kp1, kp2) = ("1a".repeat(32), "2b".repeat(32));
+		let vers = "14.2-RELEASE-p2".parse().unwrap();
+		let mut mani = Manifest::new_fetch(Metadata::default(),
+				Metadata::default(), vers);
+
+		// Older ones can't say
+		assert_eq!(mani.keyprint_mismatch(&kp1), Some(None));
+
+		mani.set_keyprint(&kp1);
+		assert_eq!(mani.keyprint_mismatch(&kp1), None);
+		assert_eq!(mani.keyprint_mismatch(&kp2), Some(Some(kp1.as_str())));
+
+		// And it survives a save/load
+		let td = tempfile::tempdir().unwrap();
+		let state = State { manifest: Some(mani), ..Default::default() };
+		save_to_dir(td.path(), &state).unwrap();
+		let loaded = load_from_dir(td.path()).unwrap().manifest.unwrap();
+		assert_eq!(loaded.keyprint_mismatch(&kp1), None);
+	}
+
+	#[test]
+	fn recovery()
+	{
+		use super::{RecoveryPoint, RecoveryKind as RK, RECOVERY_KEEP};
+
+		let td = tempfile::tempdir().unwrap();
+		let dir = td.path();
+
+		// Older statefiles don't have any
+		let mut state = mkstate();
+		save_to_dir(dir, &state).unwrap();
+		let mut sj = read_json(dir);
+		sj.as_object_mut().unwrap().remove("recovery");
+		std::fs::write(dir.join(STATEFILE), sj.to_string()).unwrap();
+		assert!(load_brief_from_dir(dir).unwrap().recovery.is_empty());
+
+		// Add more than we keep; the oldest fall off
+		for i in 0..(RECOVERY_KEEP + 2)
+		{
+			let rp = RecoveryPoint::new(RK::BootEnv,
+					format!("14.1-RELEASE-p{i}_be"),
+					format!("14.1-RELEASE-p{i}"), true);
+			state.add_recovery(rp);
+		}
+		let kb = RecoveryPoint::new(RK::KernelBackup,
+				"/boot/kernel.old".to_string(), "14.1-RELEASE-p9".to_string(),
+				false);
+		state.add_recovery(kb.clone());
+		assert_eq!(state.recovery.len(), RECOVERY_KEEP);
+		assert_eq!(state.recovery[0].name, "14.1-RELEASE-p3_be");
+
+		// And they make it through a brief save/load
+		save_to_dir(dir, &state).unwrap();
+		let mut brief = load_brief_from_dir(dir).unwrap();
+		assert_eq!(brief.recovery, state.recovery);
+		brief.recovery.pop();
+		save_to_dir(dir, &brief).unwrap();
+		let full = load_from_dir(dir).unwrap();
+		assert_eq!(full.recovery.len(), RECOVERY_KEEP - 1);
+		assert!(full.manifest.is_some());
+
+		let desc = kb.describe();
+		assert!(desc.starts_with("/boot/kernel.old contains the \
+				14.1-RELEASE-p9 kernel (from "), "{desc}");
+		assert!(desc.ends_with(" [unverified]"), "{desc}");
+		assert!(!state.recovery[0].describe().contains("unverified"));
+	}
+
+
+	#[test]
+	fn source_mismatch()
+	{
+		use crate::info::version::AVersion;
+		let av = |s: &str| -> AVersion { s.parse().unwrap() };
+		let p6 = av("14.1-RELEASE-p6");
+
+		// Old manifests don't know, so can't complain
+		let mut mani = Manifest::new_fetch(Metadata::default(),
+				Metadata::default(), av("14.1-RELEASE-p7"));
+		assert_eq!(mani.source_mismatch(&av("14.2-RELEASE")), None);
+
+		// Fetch has to be on exactly what it was made from
+		mani.set_from(p6.clone());
+		assert_eq!(mani.from(), Some(&p6));
+		assert_eq!(mani.source_mismatch(&p6), None);
+		assert_eq!(mani.source_mismatch(&av("14.2-RELEASE")),
+				Some(&av("14.2-RELEASE")));
+		assert!(mani.source_mismatch(&av("14.1-RELEASE-p5")).is_some());
+
+		// Upgrades can be on the new version too, once the kernel's in
+		let mut mani = Manifest::new_upgrade(Metadata::default(),
+				Metadata::default(), av("14.2-RELEASE-p1"),
+				Default::default(), Default::default());
+		mani.set_from(p6.clone());
+		let after = av("14.2-RELEASE");
+		assert_eq!(mani.source_mismatch(&p6), None);
+		assert!(mani.source_mismatch(&after).is_some());
+		if let Manifest::Upgrade(u) = &mut mani { u.kernel = true; }
+		assert_eq!(mani.source_mismatch(&p6), None);
+		assert_eq!(mani.source_mismatch(&after), None);
+		assert!(mani.source_mismatch(&av("15.0-RELEASE")).is_some());
+
+		// And it survives a save/load
+		let td = tempfile::tempdir().unwrap();
+		let state = State { manifest: Some(mani), ..Default::default() };
+		save_to_dir(td.path(), &state).unwrap();
+		let loaded = load_from_dir(td.path()).unwrap();
+		assert_eq!(loaded.manifest.unwrap().from(), Some(&p6));
+	}
+
+
+	#[test]
+	fn upgrade_unchanged()
+	{
+		use crate::metadata::{MetaFile, MetaKept, MetaPerms};
+		use crate::core::merge::Skipped;
+
+		let md = |paths: &[&str]| {
+			let mut md = Metadata::default();
+			for p in paths
+			{
+				let f = MetaFile { path: p.into(), ..Default::default() };
+				md.files.insert(p.into(), f);
+			}
+			md
+		};
+
+		// Updating sh, removing csh
+		let vers = "15.0-RELEASE".parse().unwrap();
+		let mut mani = Manifest::new_upgrade(md(&["/bin/sh", "/bin/csh"]),
+				md(&["/bin/sh"]), vers, Default::default(),
+				Default::default());
+		let mup = match &mut mani {
+			Manifest::Upgrade(u) => u,
+			_ => unreachable!(),
+		};
+		let skip = Skipped { cur: Default::default(), new: Default::default(),
+				security: false };
+		mup.skipped.insert("/etc/rc.conf".into(), skip);
+		let perms = MetaPerms { uid: 0, gid: 0, mode: 0o600, flags: 0 };
+		mup.kept_metadata.push(MetaKept { path: "/etc/motd".into(),
+				upstream: perms, local: perms });
+
+		let all = md(&["/bin/sh", "/bin/csh", "/bin/ls", "/etc/rc.conf",
+				"/etc/motd", "/etc/hosts", "/etc/group"]);
+		let uium = [regex_lite::Regex::new("^/etc/hosts").unwrap()];
+		let unch = mup.unchanged(all, &uium);
+		let mut got: Vec<_> = unch.allpaths();
+		got.sort_unstable();
+		assert_eq!(got, ["/bin/ls", "/etc/group"].map(std::path::Path::new));
+	}
+
+
+	#[test]
+	fn reproducible()
+	{
+		use std::collections::HashMap;
+		use crate::metadata::{MetaDir, MetaSymLink};
+		use crate::core::merge::{Clean, Conflict, Skipped};
+
+		// Same contents, put together in opposite orders, so the maps
+		// have every chance to iterate differently.
+		let build = |rev: bool| -> Vec<u8> {
+			let mut idxs: Vec<u32> = (0..200).collect();
+			if rev { idxs.reverse(); }
+
+			let (mut cur, mut new) = (Metadata::default(), Metadata::default());
+			let (mut mclean, mut mconf) = (HashMap::new(), HashMap::new());
+			let mut skipped = HashMap::new();
+			for &i in &idxs
+			{
+				let f = |p: String| MetaFile { path: p.clone().into(),
+						uid: i, ..Default::default() };
+				let fp = format!("/usr/lib/libx{i}.so.{}", i % 3);
+				cur.files.insert(fp.clone().into(), f(fp));
+				let np = format!("/usr/share/f{i}");
+				new.files.insert(np.clone().into(), f(np));
+
+				let dp = format!("/usr/share/d{i}");
+				new.dirs.insert(dp.clone().into(),
+						MetaDir { path: dp.into(), ..Default::default() });
+				let sp = format!("/usr/bin/s{i}");
+				new.symlinks.insert(sp.clone().into(), MetaSymLink {
+						path: sp.into(), target: "x".into(),
+						..Default::default() });
+				new.dashes.insert(format!("/usr/old/{i}").into());
+
+				let ep: PathBuf = format!("/etc/f{i}.conf").into();
+				match i % 3 {
+					0 => { mclean.insert(ep, Clean { old: Default::default(),
+							new: Default::default(), cur: Default::default(),
+							res: Default::default(), resolved: false,
+							normalized: false }); },
+					1 => { mconf.insert(ep, Conflict { old: Default::default(),
+							new: Default::default(), cur: Default::default(),
+							res: Default::default() }); },
+					_ => { skipped.insert(ep, Skipped {
+							cur: Default::default(),
+							new: Default::default(), security: false }); },
+				}
+			}
+
+			let vers = "14.2-RELEASE".parse().unwrap();
+			let mut mani = Manifest::new_upgrade(cur, new, vers, mclean,
+					mconf);
+			if let Manifest::Upgrade(u) = &mut mani { u.skipped = skipped; }
+
+			let td = tempfile::tempdir().unwrap();
+			let state = State { manifest: Some(mani), ..Default::default() };
+			save_to_dir(td.path(), &state).unwrap();
+			let mut ret = std::fs::read(td.path().join(MANIFESTFILE)).unwrap();
+			ret.extend(std::fs::read(td.path().join(STATEFILE)).unwrap());
+			ret
+		};
+
+		let first = build(false);
+		assert!(first == build(false), "same order differs");
+		assert!(first == build(true), "reverse order differs");
+	}
+
+
+	#[test]
+	fn step_sizes()
+	{
+		use super::{StepSize, StepSizes, human_secs};
+
+		let mb = 1024 * 1024;
+		let kernel = StepSize { files: 800, bytes: 150 * mb };
+		let world = StepSize { files: 1, bytes: 1 };
+		let sz = StepSizes { kernel, world };
+
+		assert_eq!(StepSizes::est_secs(&kernel, 50), 3);
+		assert_eq!(StepSizes::est_secs(&world, 50), 1);
+		assert_eq!(StepSizes::est_secs(&StepSize::default(), 50), 0);
+		assert_eq!(human_secs(3), "3s");
+		assert_eq!(human_secs(125), "2m05s");
+		assert_eq!(human_secs(7260), "2h01m");
+
+		let lines = sz.describe(50);
+		assert_eq!(lines[0], "Kernel: 800 files, 150.0M; roughly 3s at 50 MB/s");
+		assert!(lines[1].starts_with("World: 1 file,"), "{}", lines[1]);
+	}
+
+
+	#[test]
+	fn stashed()
+	{
+		let mf = |i: u32| MetaFile { path: format!("/usr/bin/f{i}").into(),
+				sha256: [i as u8; 32].into(), uid: 0, gid: 5,
+				mode: 0o4555, flags: 0x20000 };
+		let mut cur = Metadata::default();
+		cur.files = (0..20).rev().map(|i| (mf(i).path, mf(i))).collect();
+
+		let m = mkstate().manifest.unwrap();
+		assert!(m.stashed().is_empty());
+
+		// It's what's in cur, with all the metadata the scan saw
+		let vers = "14.2-RELEASE-p1".parse().unwrap();
+		let m = Manifest::new_fetch(cur, Metadata::default(), vers);
+		let mj = serde_json::to_value(&m).unwrap();
+		let back: Manifest = serde_json::from_value(mj).unwrap();
+		let st = back.stashed();
+		assert_eq!(st.len(), 20);
+		assert!(st.windows(2).all(|w| w[0].path < w[1].path), "sorted");
+		assert_eq!(*st[0], mf(0));
+		assert_eq!((st[3].gid, st[3].mode, st[3].flags), (5, 0o4555, 0x20000));
+	}
+
+
+	#[test]
+	fn annotated()
+	{
+		let mf = |p: &str, h: u8| MetaFile { path: p.into(),
+				sha256: [h; 32].into(), ..Default::default() };
+		let mut cur = Metadata::default();
+		let mut new = Metadata::default();
+		cur.files.insert("/etc/upd".into(), mf("/etc/upd", 1));
+		new.files.insert("/etc/upd".into(), mf("/etc/upd", 2));
+		cur.files.insert("/etc/gone".into(), mf("/etc/gone", 1));
+		new.files.insert("/etc/added".into(), mf("/etc/added", 2));
+		let vers = "14.2-RELEASE-p1".parse().unwrap();
+		let mut m = Manifest::new_fetch(cur, new, vers);
+
+		// Only what we're replacing or removing sticks
+		let found: Vec<PathBuf> = ["/etc/gone", "/etc/upd", "/etc/untouched"]
+				.iter().map(PathBuf::from).collect();
+		m.set_annotated("user.managed_by", found);
+		let an = m.annotated().unwrap();
+		assert_eq!(an.attr, "user.managed_by");
+		assert_eq!(an.paths, [Path::new("/etc/gone"), Path::new("/etc/upd")]);
+		assert_eq!(m.brief().annotated.as_ref(), Some(an));
+
+		// Nothing relevant means nothing at all
+		m.set_annotated("user.managed_by",
+				vec!["/etc/untouched".into()]);
+		assert!(m.annotated().is_none());
+	}
+}
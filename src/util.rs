@@ -12,10 +12,36 @@ pub(crate) mod bspatch;
 /// Boot envs
 pub(crate) mod bectl;
 
+/// Running jails
+pub(crate) mod jail;
+
+/// User-facing output that doesn't die on a closed stdout
+pub(crate) mod out;
+
+/// Timing phases of commands
+pub(crate) mod timings;
+
+/// Status file for watching running commands
+pub(crate) mod status;
+
+/// Byte counts for a run
+pub(crate) mod bytecount;
+
+/// Extended attributes and ACLs
+pub(crate) mod xattr;
+
+/// Reading tar archives
+pub(crate) mod tar;
+
+/// Sorted serialization of hashed collections
+pub(crate) mod sorted;
+
 /// Filesystem stuff (mostly flags related)
 mod fs;
-pub(crate) use fs::{lchflags, unschg_file};
+pub(crate) use fs::{lchflags, lchmod, lutimes, unschg_file};
 pub(crate) use fs::{lstat, LstatErr};
+pub(crate) use fs::{too_long, PATH_MAX};
+pub(crate) use fs::{fstype, securelevel};
 
 
 
@@ -119,6 +145,38 @@ pub(crate) fn plural(n: usize) -> &'static str
 }
 
 
+/// Ask the user a yes/no question.  Only if there's a tty to ask on;
+/// otherwise, it's a no.
+pub(crate) fn confirm(prompt: &str) -> Result<bool, std::io::Error>
+{
+	use std::io::{self, IsTerminal as _, Write as _};
+	if !io::stdin().is_terminal() { return Ok(false); }
+
+	print!("{prompt} [y/N] ");
+	io::stdout().flush()?;
+	let mut inline = String::new();
+	io::stdin().read_line(&mut inline)?;
+	Ok(matches!(inline.trim(), "y" | "Y" | "yes"))
+}
+
+
+/// Human-ish size, like "1.5M"
+pub(crate) fn human_bytes(n: u64) -> String
+{
+	let units = ["K", "M", "G", "T"];
+	if n < 1024 { return format!("{n}B"); }
+
+	let mut v = n as f64 / 1024.0;
+	let mut u = 0;
+	while v >= 1024.0 && u < units.len() - 1
+	{
+		v /= 1024.0;
+		u += 1;
+	}
+	format!("{v:.1}{}", units[u])
+}
+
+
 /// Is a given path kernel-y?  Used in the install process for Upgrades,
 /// as we split the install into multiple steps.
 pub(crate) fn is_kernel_dir(p: &impl AsRef<Path>) -> bool
@@ -36,3 +36,17 @@ pub(crate) fn create(name: &str) -> Result<(), anyhow::Error>
 
 	Ok(())
 }
+
+
+/// Is there a BE by this name?  Going by `bectl list`.
+pub(crate) fn exists(name: &str) -> Result<bool, anyhow::Error>
+{
+	let bret = std::process::Command::new(BECTL)
+			.args(["list", "-H"]).output()?;
+	if !bret.status.success()
+	{ anyhow::bail!("bectl failed: {:?}", bret); }
+
+	// Tab-separated, name first
+	let out = String::from_utf8_lossy(&bret.stdout);
+	Ok(out.lines().any(|l| l.split('\t').next() == Some(name)))
+}
@@ -0,0 +1,122 @@
+//! Counting the bytes a run moves around.
+//!
+//! How much got downloaded, and how much landed in the files dir (and
+//! from where), for people trying to plan how much space the workdir
+//! needs.  The pools report as their results come in; like the status
+//! file, that's all on the thread running the command, so the counts
+//! are per-thread too.
+use std::cell::Cell;
+
+
+/// Byte counts, for a run or accumulated over many.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Bytes
+{
+	/// Pulled down from the server, whatever it was for
+	#[serde(default)]
+	pub(crate) downloaded: u64,
+
+	/// Written into the files dir from the installed system (stash)
+	#[serde(default)]
+	pub(crate) stashed: u64,
+
+	/// Written into the files dir from what we downloaded
+	#[serde(default)]
+	pub(crate) fetched: u64,
+}
+
+impl Bytes
+{
+	/// Nothing counted?
+	pub(crate) fn is_empty(&self) -> bool { *self == Self::default() }
+
+	/// Everything that went into the files dir
+	pub(crate) fn stored(&self) -> u64 { self.stashed + self.fetched }
+
+	/// Add another set in
+	pub(crate) fn add(&mut self, o: &Bytes)
+	{
+		self.downloaded += o.downloaded;
+		self.stashed += o.stashed;
+		self.fetched += o.fetched;
+	}
+
+	/// A line about it
+	pub(crate) fn describe(&self) -> String
+	{
+		use crate::util::human_bytes as hb;
+		format!("{} downloaded; {} into the files dir ({} stashed, {} \
+				fetched)", hb(self.downloaded), hb(self.stored()),
+				hb(self.stashed), hb(self.fetched))
+	}
+}
+
+
+thread_local! {
+	static COUNTS: Cell<Bytes> = const { Cell::new(Bytes {
+			downloaded: 0, stashed: 0, fetched: 0 }) };
+}
+
+/// Tweak the counts, and let the status file know.
+fn update(f: impl FnOnce(&mut Bytes))
+{
+	let mut b = COUNTS.get();
+	f(&mut b);
+	COUNTS.set(b);
+	crate::util::status::run_bytes(b);
+}
+
+/// Downloaded some
+pub(crate) fn downloaded(n: u64) { update(|b| b.downloaded += n) }
+
+/// Stashed some into files/
+pub(crate) fn stashed(n: u64) { update(|b| b.stashed += n) }
+
+/// Fetched some into files/
+pub(crate) fn fetched(n: u64) { update(|b| b.fetched += n) }
+
+/// What this run's done so far
+pub(crate) fn get() -> Bytes { COUNTS.get() }
+
+/// Start over
+#[cfg(test)]
+pub(crate) fn reset() { COUNTS.set(Bytes::default()) }
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::Bytes;
+
+	#[test]
+	fn counting()
+	{
+		super::reset();
+		assert!(super::get().is_empty());
+
+		super::downloaded(100);
+		super::fetched(90);
+		super::stashed(40);
+		super::downloaded(5);
+		let b = super::get();
+		assert_eq!(b, Bytes { downloaded: 105, stashed: 40, fetched: 90 });
+		assert_eq!(b.stored(), 130);
+
+		let mut tot = Bytes { downloaded: 1, ..Default::default() };
+		tot.add(&b);
+		tot.add(&b);
+		assert_eq!(tot, Bytes { downloaded: 211, stashed: 80, fetched: 180 });
+
+		assert_eq!(b.describe(), "105B downloaded; 130B into the files dir \
+				(40B stashed, 90B fetched)");
+
+		// Older saved ones without some of the fields still load
+		let old: Bytes = serde_json::from_str(r#"{"downloaded":3}"#).unwrap();
+		assert_eq!(old, Bytes { downloaded: 3, ..Default::default() });
+
+		super::reset();
+		assert!(super::get().is_empty());
+	}
+}
@@ -16,9 +16,15 @@ pub(crate) fn decompress_gz_write(src: &Path, dst: &mut impl Write)
 
 	// Hook the input file up to the unzipper
 	let gzfh = File::open(src).with_context(|| ctx())?;
-	let mut gzd = flate2::read::GzDecoder::new(gzfh);
+	decompress_gz_fh_write(&gzfh, dst)?;
+	Ok(())
+}
 
-	// Copy bits, sync it all, and return
+/// Decompress an already open .gz file into a `Writer`er.
+pub(crate) fn decompress_gz_fh_write(src: &std::fs::File,
+		dst: &mut impl Write) -> Result<(), std::io::Error>
+{
+	let mut gzd = flate2::read::GzDecoder::new(src);
 	std::io::copy(&mut gzd, dst)?;
 	Ok(())
 }
@@ -26,23 +32,70 @@ pub(crate) fn decompress_gz_write(src: &Path, dst: &mut impl Write)
 /// Decompress a .gz file into a named output location.
 pub(crate) fn decompress_gz_file(src: &Path, dst: &Path)
 		-> Result<(), anyhow::Error>
+{
+	Ok(decompress_gz_file_sides(src, dst)?)
+}
+
+
+/// Which side of a decompression went wrong.
+///
+/// Something wrong on the read side means the .gz is bad (or gone);
+/// something wrong writing it out is our problem (full disk, etc), and
+/// says nothing about the .gz.
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+pub(crate) enum GzErr
+{
+	/// Couldn't open the .gz
+	#[error("opening {}: {1}", .0.display())]
+	Open(PathBuf, std::io::Error),
+
+	/// Bad data in the .gz (corrupt, truncated, etc)
+	#[error("decompressing {}: {1}", .0.display())]
+	Read(PathBuf, std::io::Error),
+
+	/// Couldn't write out the result
+	#[error("writing {}: {1}", .0.display())]
+	Write(PathBuf, std::io::Error),
+}
+
+
+/// Decompress a .gz file into a named output location, keeping track of
+/// which side any failure came from.  On failure, the partial output is
+/// removed.
+pub(crate) fn decompress_gz_file_sides(src: &Path, dst: &Path)
+		-> Result<(), GzErr>
 {
 	use std::fs::File;
+	use std::io::{BufWriter, Read as _, ErrorKind as EK};
+	use crate::util::FILE_BUFSZ;
 
-	// Open up the output
-	let outfh = File::create(dst)?;
+	let gzfh = File::open(src).map_err(|e| GzErr::Open(src.into(), e))?;
+	let mut gzd = flate2::read::GzDecoder::new(gzfh);
+
+	let outfh = File::create(dst).map_err(|e| GzErr::Write(dst.into(), e))?;
+	let mut bw = BufWriter::with_capacity(FILE_BUFSZ, outfh);
+
+	// io::copy() would be simpler, but then we can't tell which end
+	// blew up.
+	let mut buf = vec![0u8; FILE_BUFSZ];
+	let ret = loop {
+		let n = match gzd.read(&mut buf) {
+			Ok(0) => break bw.flush().map_err(|e| GzErr::Write(dst.into(), e)),
+			Ok(n) => n,
+			Err(e) if e.kind() == EK::Interrupted => continue,
+			Err(e) => break Err(GzErr::Read(src.into(), e)),
+		};
+		if let Err(e) = bw.write_all(&buf[..n])
+		{ break Err(GzErr::Write(dst.into(), e)); }
+	};
 
-	// Wrap up some buffering, and copy
+	if ret.is_err()
 	{
-		use std::io::BufWriter;
-		use crate::util::FILE_BUFSZ;
-		let mut bw = BufWriter::with_capacity(FILE_BUFSZ, outfh);
-		decompress_gz_write(src, &mut bw)?;
-		bw.flush()?;
+		drop(bw);
+		let _ = std::fs::remove_file(dst);
 	}
-
-	// There we go
-	Ok(())
+	ret
 }
 
 /// Decompress a named gz file.  This expects a file of "something.gz",
@@ -115,3 +168,110 @@ pub(crate) fn compress_gz(src: &Path, dst: &Path) -> Result<(), std::io::Error>
 	dstf.sync_all()?;
 	Ok(())
 }
+
+
+/// Write something out as gzip'd JSON, and sync it.
+pub(crate) fn write_json_gz(dst: &Path, what: &impl serde::Serialize)
+		-> Result<(), anyhow::Error>
+{
+	use flate2::{write::GzEncoder, Compression};
+
+	let dstf = std::fs::File::create(dst)?;
+	let mut gzc = GzEncoder::new(dstf, Compression::best());
+	serde_json::to_writer(&mut gzc, what)?;
+	gzc.finish()?.sync_all()?;
+	Ok(())
+}
+
+
+/// Decompressed size of a .gz, from the ISIZE field in its trailer,
+/// without actually decompressing it.
+///
+/// That's only the size mod 2^32, and only of the last member if it's
+/// a multi-member gzip.  So something that's 5 GiB uncompressed reads as
+/// 1 GiB here; there's no telling from the trailer alone.  Neither's a
+/// thing for the files we deal with (even the biggest debug files are a
+/// fraction of that), and it's only used for estimates and display
+/// anyway.
+pub(crate) fn gz_isize(src: &Path) -> Result<u64, std::io::Error>
+{
+	use std::fs::File;
+	use std::io::{Read as _, Seek as _, SeekFrom};
+
+	let mut fh = File::open(src)?;
+	fh.seek(SeekFrom::End(-4))?;
+	let mut isz = [0u8; 4];
+	fh.read_exact(&mut isz)?;
+	Ok(u32::from_le_bytes(isz).into())
+}
+
+
+/// gzip some bytes in memory.  For tests, making up hashfiles and the
+/// like.
+#[cfg(test)]
+pub(crate) fn gz_bytes(content: &[u8]) -> Vec<u8>
+{
+	use std::io::Write as _;
+	use flate2::{write::GzEncoder, Compression};
+
+	let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+	gz.write_all(content).unwrap();
+	gz.finish().unwrap()
+}
+
+
+/// Put content in dir as a `<sha256>.gz` hashfile, the way the files dir
+/// holds them, and return the hash.  For tests.
+#[cfg(test)]
+pub(crate) fn write_hashfile(dir: &Path, content: &[u8])
+		-> crate::util::hash::Sha256Hash
+{
+	let hash = crate::util::hash::sha256_reader(&mut &content[..]).unwrap();
+	std::fs::write(dir.join(format!("{hash}.gz")), gz_bytes(content))
+			.unwrap();
+	hash
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn gz_isize()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let src = td.path().join("plain");
+		let dst = td.path().join("plain.gz");
+
+		let data: Vec<u8> = (0..100_000u32).map(|n| (n % 7) as u8).collect();
+		std::fs::write(&src, &data).unwrap();
+		super::compress_gz(&src, &dst).unwrap();
+		assert!(dst.metadata().unwrap().len() < data.len() as u64);
+		assert_eq!(super::gz_isize(&dst).unwrap(), data.len() as u64);
+
+		// Too short to have a trailer
+		std::fs::write(&dst, b"gz").unwrap();
+		super::gz_isize(&dst).expect_err("no trailer");
+
+		// Past 4 GiB, it wraps.  Nobody's going to compress that much
+		// in a test, but only the trailer gets looked at anyway.
+		let big: u64 = 5 << 30;
+		let mut fake = b"\x1f\x8b not really deflate".to_vec();
+		fake.extend_from_slice(&0u32.to_le_bytes());  // CRC
+		fake.extend_from_slice(&(big as u32).to_le_bytes());
+		std::fs::write(&dst, &fake).unwrap();
+		assert_eq!(super::gz_isize(&dst).unwrap(), 1 << 30);
+	}
+
+	#[test]
+	fn write_hashfile()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let hash = super::write_hashfile(td.path(), b"echo hi\n");
+		let hf = td.path().join(format!("{hash}.gz"));
+		assert_eq!(super::decompress_to_vec(&hf).unwrap(), b"echo hi\n");
+		assert_eq!(hash, crate::util::hash::sha256_reader(
+				&mut &b"echo hi\n"[..]).unwrap());
+	}
+}
@@ -50,8 +50,7 @@ fn flag_twiddle_inner(file: &Path, flag: u64, set: bool, curflag: Option<u64>)
 		-> Result<u64, anyhow::Error>
 {
 	// We'll need the CString file
-	let fnbytes = file.as_os_str().as_encoded_bytes();
-	let f = CString::new(fnbytes)?;
+	let f = cpath(file)?;
 
 	// Get current flags
 	let cur = match curflag {
@@ -82,6 +81,24 @@ fn flag_twiddle_inner(file: &Path, flag: u64, set: bool, curflag: Option<u64>)
  * Lower-level bits
  */
 
+/// Longest path the kernel will take, counting the trailing NUL.
+pub(crate) const PATH_MAX: usize = libc::PATH_MAX as usize;
+
+/// Is a path too long to hand to the kernel?
+pub(crate) fn too_long(path: &Path) -> bool
+{
+	path.as_os_str().len() >= PATH_MAX
+}
+
+/// Make a C-ish string of a filename, if it can be one.
+fn cpath(file: &Path) -> Result<CString, LstatErr>
+{
+	if too_long(file) { return Err(LstatErr::TooLong(file.to_path_buf())); }
+	let fnbytes = file.as_os_str().as_encoded_bytes();
+	Ok(CString::new(fnbytes)?)
+}
+
+
 /// My stat(2) (lstat(2)) return, broken out rustily
 #[derive(Debug, Default)]
 pub(crate) struct Stat
@@ -115,6 +132,10 @@ pub(crate) enum LstatErr
 	#[error("File not found: {0}")]
 	Nonexistent(PathBuf),
 
+	/// Path's too long to even ask about
+	#[error("Path too long ({} bytes): {}", .0.as_os_str().len(), .0.display())]
+	TooLong(PathBuf),
+
 	/// Unknown stat(2) error
 	#[error("libc stat(2): error {0}: {1}")]
 	Lstat(i32, String),
@@ -129,10 +150,7 @@ pub(crate) enum LstatErr
 /// (for uses that go more directly into it).
 pub(crate) fn lstat(file: &Path) -> Result<(Stat, libc::stat), LstatErr>
 {
-	// Make a C-ish string of the filename.
-	let fnbytes = file.as_os_str().as_encoded_bytes();
-	let f = CString::new(fnbytes)?;
-
+	let f = cpath(file)?;
 	lstat_inner(file, &f)
 }
 
@@ -152,7 +170,7 @@ fn lstat_inner(file: &Path, f: &CString) -> Result<(Stat, libc::stat), LstatErr>
 	};
 
 	// errno != 0 means some failure.
-	use libc::{ENOENT, ENOTDIR};
+	use libc::{ENOENT, ENOTDIR, ENAMETOOLONG};
 	use LstatErr as LE;
 	match errno {
 		0 => {
@@ -174,6 +192,10 @@ fn lstat_inner(file: &Path, f: &CString) -> Result<(Stat, libc::stat), LstatErr>
 			// as such.
 			Err(LE::Nonexistent(file.to_path_buf()))
 		},
+		ENAMETOOLONG => {
+			// Whole path fit, but some component didn't
+			Err(LE::TooLong(file.to_path_buf()))
+		},
 		_ => {
 			// Anything else, whoTF knows...  this is probably really
 			// an Io, but since we're a long way from std::io...
@@ -195,13 +217,7 @@ fn lstat_inner(file: &Path, f: &CString) -> Result<(Stat, libc::stat), LstatErr>
 pub(crate) fn lchflags(file: &Path, flags: u64)
 		-> Result<(), anyhow::Error>
 {
-	// Make a C-ish string of the filename.
-	let fnbytes = file.as_os_str().as_encoded_bytes();
-	let f = match CString::new(fnbytes) {
-		Ok(s) => s,
-		Err(e) => anyhow::bail!("CString error: {e}"),
-	};
-
+	let f = cpath(file)?;
 	lchflags_inner(file, &f, flags)
 }
 
@@ -221,3 +237,171 @@ fn lchflags_inner(file: &Path, f: &CString, flags: u64)
 		_ => anyhow::bail!("lchflags({}): errno {err}", file.display()),
 	}
 }
+
+
+/// kern.securelevel
+pub(crate) fn securelevel() -> Result<i32, anyhow::Error>
+{
+	use sysctl::{Ctl, Sysctl as _};
+	let ctl = "kern.securelevel";
+	let sv = Ctl::new(ctl)?.value()?;
+	sv.as_int().copied()
+			.ok_or_else(|| anyhow::anyhow!("{ctl} not int?  {sv:?}"))
+}
+
+
+/// What kind of filesystem is a dir on?
+pub(crate) fn fstype(dir: &Path) -> Result<String, std::io::Error>
+{
+	use std::os::unix::ffi::OsStrExt as _;
+	let cdir = CString::new(dir.as_os_str().as_bytes())?;
+
+	// SAFETY: valid C string, and statfs fills in the zeroed struct
+	let sfs = unsafe {
+		let mut sfs: libc::statfs = std::mem::zeroed();
+		match libc::statfs(cdir.as_ptr(), &mut sfs) {
+			0 => sfs,
+			_ => return Err(std::io::Error::last_os_error()),
+		}
+	};
+	// SAFETY: the kernel NUL-terminates it
+	let name = unsafe { ffi::CStr::from_ptr(sfs.f_fstypename.as_ptr()) };
+	Ok(name.to_string_lossy().into_owned())
+}
+
+
+/// Set a path's atime and mtime, not following it if it's a symlink;
+/// i.e., lutimes(2), by way of utimensat(2).
+pub(crate) fn lutimes(file: &Path, secs: i64) -> Result<(), std::io::Error>
+{
+	use std::io::{Error, ErrorKind};
+	let f = cpath(file)
+			.map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+	let ts = libc::timespec { tv_sec: secs as libc::time_t, tv_nsec: 0 };
+	let times = [ts, ts];
+	let ret = unsafe {
+		libc::utimensat(libc::AT_FDCWD, f.as_ptr(), times.as_ptr(),
+				libc::AT_SYMLINK_NOFOLLOW)
+	};
+
+	match ret {
+		0 => Ok(()),
+		_ => Err(Error::last_os_error()),
+	}
+}
+
+
+
+/// chmod() a path without following it if it's a symlink; FreeBSD
+/// symlinks have their own mode.
+pub(crate) fn lchmod(file: &Path, mode: u32) -> Result<(), std::io::Error>
+{
+	use std::io::{Error, ErrorKind};
+	let f = cpath(file)
+			.map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+	let ret = unsafe {
+		libc::fchmodat(libc::AT_FDCWD, f.as_ptr(), mode as libc::mode_t,
+				libc::AT_SYMLINK_NOFOLLOW)
+	};
+
+	match ret {
+		0 => Ok(()),
+		_ => Err(Error::last_os_error()),
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::path::PathBuf;
+	use super::{PATH_MAX, LstatErr as LE};
+
+	/// A path of exactly len bytes, in plausible-looking components.
+	fn deep(len: usize) -> PathBuf
+	{
+		let mut s = "/node_modules".repeat(len / 13 + 1);
+		s.truncate(len);
+		s.into()
+	}
+
+	#[test]
+	fn too_long()
+	{
+		use super::too_long;
+
+		assert!(!too_long(&deep(100)));
+		assert!(!too_long(&deep(PATH_MAX - 1)), "room for the NUL");
+		assert!(too_long(&deep(PATH_MAX)));
+		assert!(too_long(&deep(PATH_MAX * 4)));
+
+		// Caught before it gets anywhere near the kernel
+		let p = deep(PATH_MAX + 10);
+		match super::lstat(&p) {
+			Err(LE::TooLong(tp)) => assert_eq!(tp, p),
+			Err(e) => panic!("Expected TooLong, got {e}"),
+			Ok(_) => panic!("Expected TooLong, got a stat"),
+		}
+		let e = super::lchflags(&p, 0).expect_err("too long");
+		assert!(e.to_string().contains("too long"), "{e}");
+	}
+
+	#[test]
+	fn nul()
+	{
+		use std::os::unix::ffi::OsStrExt as _;
+		let p = std::ffi::OsStr::from_bytes(b"/foo\0bar");
+		match super::lstat(p.as_ref()) {
+			Err(LE::CString(_)) => (),
+			Err(e) => panic!("Expected CString, got {e}"),
+			Ok(_) => panic!("Expected CString, got a stat"),
+		}
+	}
+
+	#[test]
+	fn lutimes()
+	{
+		use std::os::unix::fs::MetadataExt as _;
+
+		let td = tempfile::tempdir().unwrap();
+		let file = td.path().join("file");
+		let link = td.path().join("link");
+		std::fs::write(&file, b"x").unwrap();
+		std::os::unix::fs::symlink("file", &link).unwrap();
+		let fmt = file.metadata().unwrap().mtime();
+
+		// The link itself, not the file behind it
+		super::lutimes(&link, 1_000_000_000).unwrap();
+		assert_eq!(link.symlink_metadata().unwrap().mtime(), 1_000_000_000);
+		assert_eq!(file.metadata().unwrap().mtime(), fmt);
+
+		super::lutimes(&file, 1_000_000_001).unwrap();
+		assert_eq!(file.metadata().unwrap().mtime(), 1_000_000_001);
+
+		super::lutimes(&td.path().join("nope"), 1).expect_err("not there");
+	}
+
+	#[test]
+	fn lchmod()
+	{
+		use std::os::unix::fs::MetadataExt as _;
+
+		let td = tempfile::tempdir().unwrap();
+		let file = td.path().join("file");
+		let link = td.path().join("link");
+		std::fs::write(&file, b"x").unwrap();
+		std::os::unix::fs::symlink("file", &link).unwrap();
+		let fmode = file.metadata().unwrap().mode() & 0o7777;
+
+		// The link itself, not the file behind it
+		super::lchmod(&link, 0o700).unwrap();
+		assert_eq!(link.symlink_metadata().unwrap().mode() & 0o7777, 0o700);
+		assert_eq!(file.metadata().unwrap().mode() & 0o7777, fmode);
+
+		super::lchmod(&file, 0o600).unwrap();
+		assert_eq!(file.metadata().unwrap().mode() & 0o7777, 0o600);
+
+		super::lchmod(&td.path().join("nope"), 0o644).expect_err("not there");
+	}
+}
@@ -234,12 +234,135 @@ pub(crate) fn sha256_reader<T: std::io::Read>(rdr: &mut T)
 }
 
 
+/*
+ * Hashing files.  This is where check-sys and friends spend most of
+ * their time.
+ *
+ * The compression function itself is already about as fast as we'll get
+ * it; sha2 checks at runtime for SHA-NI (or the ARMv8 SHA2 extensions)
+ * and uses them when they're there.  x-ref accel().  So what's left is
+ * how we feed it.
+ */
+
+/// Buffer size for reading files to hash.  io::copy() uses 8k, which
+/// means a lot of syscalls for not much hashing on a fast CPU.
+const READ_BUF: usize = 256 << 10;
+
+/// Files at least this big get mmap'd instead, if that's on.  Below
+/// this, setting up the mapping costs more than it saves.
+const MMAP_MIN: u64 = 1 << 20;
+
+use std::sync::atomic::{self, AtomicBool};
+
+/// Hash big files via mmap?  x-ref set_mmap().
+static MMAP: AtomicBool = AtomicBool::new(false);
+
+/// Turn mmap'ing big files to hash them on or off.
+///
+/// It's off by default, since a file getting truncated while it's
+/// mapped gets us a SIGBUS instead of just a wrong hash, and on a live
+/// system that's not impossible.
+pub(crate) fn set_mmap(s: bool) { MMAP.store(s, atomic::Ordering::Relaxed) }
+
+fn mmap_on() -> bool { MMAP.load(atomic::Ordering::Relaxed) }
+
+
+/// Is the hardware-accelerated SHA256 there on this CPU?  Just for
+/// saying so; sha2 does its own detection.
+pub(crate) fn accel() -> bool
+{
+	#[cfg(any(target_arch = "x86_64", target_arch = "x86"))]
+	let ret = std::arch::is_x86_feature_detected!("sha");
+	#[cfg(target_arch = "aarch64")]
+	let ret = std::arch::is_aarch64_feature_detected!("sha2");
+	#[cfg(not(any(target_arch = "x86_64", target_arch = "x86",
+			target_arch = "aarch64")))]
+	let ret = false;
+	ret
+}
+
+
 /// Calculate the SHA256 of a file
 pub(crate) fn sha256_file(file: &std::path::Path)
 		-> Result<Sha256Hash, Sha256ReaderErr>
 {
-	let mut fh = std::fs::File::open(file)?;
-	sha256_reader(&mut fh)
+	sha256_file_with(file, mmap_on())
+}
+
+
+/// sha256_file(), with the mmap decision made explicitly.
+pub(crate) fn sha256_file_with(file: &std::path::Path, mmap: bool)
+		-> Result<Sha256Hash, Sha256ReaderErr>
+{
+	let fh = std::fs::File::open(file)?;
+	if mmap
+	{
+		let len = fh.metadata()?.len();
+		if len >= MMAP_MIN
+		{
+			if let Some(h) = sha256_mmap(&fh, len)? { return Ok(h); }
+		}
+	}
+	sha256_read(fh)
+}
+
+
+/// Hash a file by reading it through our big buffer.  Reads to EOF,
+/// however long that turns out to be, like sha256_reader().
+fn sha256_read(mut fh: std::fs::File) -> Result<Sha256Hash, Sha256ReaderErr>
+{
+	use sha2::{Sha256, Digest};
+	use std::io::{Read as _, ErrorKind as EK};
+
+	let mut hasher = Sha256::new();
+	let mut buf = vec![0u8; READ_BUF];
+	loop
+	{
+		match fh.read(&mut buf) {
+			Ok(0) => break,
+			Ok(n) => hasher.update(&buf[..n]),
+			Err(e) if e.kind() == EK::Interrupted => continue,
+			Err(e) => return Err(e.into()),
+		}
+	}
+	Ok(Sha256Hash(hasher.finalize().into()))
+}
+
+
+/// Hash a file via mmap.  None if we couldn't map it, or it changed
+/// size while we were at it; either way the caller should just read it
+/// instead, so what we get is the same as a read would have.
+fn sha256_mmap(fh: &std::fs::File, len: u64)
+		-> Result<Option<Sha256Hash>, Sha256ReaderErr>
+{
+	use sha2::{Sha256, Digest};
+	use std::os::fd::AsRawFd as _;
+
+	let mlen = match usize::try_from(len) {
+		Ok(l) => l,
+		Err(_) => return Ok(None),
+	};
+	let fd = fh.as_raw_fd();
+
+	// SAFETY: fresh mapping of an open fd, read-only, and we only touch
+	// it within mlen before unmapping.
+	let map = unsafe { libc::mmap(std::ptr::null_mut(), mlen,
+			libc::PROT_READ, libc::MAP_SHARED, fd, 0) };
+	if map == libc::MAP_FAILED { return Ok(None); }
+
+	let mut hasher = Sha256::new();
+	// SAFETY: map is mlen bytes long and stays mapped until below.
+	unsafe {
+		libc::madvise(map, mlen, libc::MADV_SEQUENTIAL);
+		hasher.update(std::slice::from_raw_parts(map as *const u8, mlen));
+		libc::munmap(map, mlen);
+	}
+
+	// If it grew, a read would have seen more
+	match fh.metadata()?.len() == len {
+		true  => Ok(Some(Sha256Hash(hasher.finalize().into()))),
+		false => Ok(None),
+	}
 }
 
 
@@ -266,8 +389,17 @@ pub(crate) fn check_sha256_reader<T: std::io::Read>(rdr: &mut T, expect: &str)
 pub(crate) fn check_sha256_file(file: &std::path::Path, expect: &str)
 		-> Result<(), Sha256ReaderErr>
 {
-	let mut fh = std::fs::File::open(file)?;
-	check_sha256_reader(&mut fh, expect)
+	use Sha256ReaderErr as ERR;
+
+	let xhash: Sha256Hash = expect.parse()
+			.map_err(|e| ERR::Expected(e))?;
+	let gothash = sha256_file(file)?;
+
+	if xhash != gothash
+	{
+		return Err(ERR::Hash(xhash.to_string(), gothash.to_string()));
+	}
+	Ok(())
 }
 
 
@@ -295,4 +427,43 @@ mod tests
 		let expect = expect_at_the_beginning();
 		super::check_sha256_reader(&mut buf, &expect).unwrap();
 	}
+
+	#[test]
+	fn sha256_file()
+	{
+		use sha2::{Sha256, Digest};
+		use super::{sha256_file_with, Sha256Hash, Sha256ReaderErr as HE};
+
+		let td = tempfile::tempdir().unwrap();
+		let f = td.path().join("f");
+		let digest = |b: &[u8]| Sha256Hash(Sha256::digest(b).into());
+
+		// Empty's the same either way
+		std::fs::write(&f, b"").unwrap();
+		let empty: Sha256Hash = "e3b0c44298fc1c149afbf4c8996fb924\
+				27ae41e4649b934ca495991b7852b855".parse().unwrap();
+		assert_eq!(sha256_file_with(&f, false).unwrap(), empty);
+		assert_eq!(sha256_file_with(&f, true).unwrap(), empty);
+
+		// Bigger than both the read buffer and the mmap cutoff, and
+		// not a multiple of either.
+		let big: Vec<u8> = (0..(super::MMAP_MIN as usize * 2 + 12345))
+				.map(|i| (i % 251) as u8).collect();
+		std::fs::write(&f, &big).unwrap();
+		let want = digest(&big);
+		assert_eq!(sha256_file_with(&f, false).unwrap(), want);
+		assert_eq!(sha256_file_with(&f, true).unwrap(), want);
+		super::check_sha256_file(&f, &want.to_string()).unwrap();
+
+		// Not there is an I/O error, either way
+		let nope = td.path().join("nope");
+		for m in [false, true]
+		{
+			match sha256_file_with(&nope, m) {
+				Err(HE::IO(e)) => assert_eq!(e.kind(),
+						std::io::ErrorKind::NotFound),
+				x => panic!("Expected I/O error, got {x:?}"),
+			}
+		}
+	}
 }
@@ -0,0 +1,106 @@
+//! jls(8)/jexec(8) handling for installing into running jails.
+//!
+//! Everything in here is best-effort; if the tools aren't there or don't
+//! work, we just act like there's no jail.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+static JLS: &str = "/usr/sbin/jls";
+static JEXEC: &str = "/usr/sbin/jexec";
+static SERVICE: &str = "/usr/sbin/service";
+
+
+/// A running jail.
+#[derive(Debug, Clone)]
+pub(crate) struct Jail
+{
+	pub(crate) jid: u32,
+	pub(crate) name: String,
+	pub(crate) path: PathBuf,
+}
+
+
+impl std::fmt::Display for Jail
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "{} (jid {})", self.name, self.jid)
+	}
+}
+
+
+/// Find the running jail whose root is the given basedir, if any.
+pub(crate) fn find(basedir: &Path) -> Option<Jail>
+{
+	// The host root is never a jail.
+	let bd = basedir.canonicalize().ok()?;
+	if bd == Path::new("/") { return None; }
+
+	let out = Command::new(JLS).args(["-h", "path", "jid", "name"])
+			.output().ok()?;
+	if !out.status.success() { return None; }
+	let jls = String::from_utf8_lossy(&out.stdout);
+
+	parse_jls(&jls).into_iter().find(|j| {
+		match j.path.canonicalize() {
+			Ok(p) => p == bd,
+			Err(_) => false,
+		}
+	})
+}
+
+
+/// Parse the `jls -h path jid name` output.  The first line is the
+/// header.
+fn parse_jls(jls: &str) -> Vec<Jail>
+{
+	jls.lines().skip(1).filter_map(|l| {
+		let mut flds = l.split_whitespace();
+		let path = PathBuf::from(flds.next()?);
+		let jid = flds.next()?.parse().ok()?;
+		let name = flds.next()?.to_string();
+		Some(Jail { jid, name, path })
+	}).collect()
+}
+
+
+impl Jail
+{
+	/// Run a command inside the jail.
+	pub(crate) fn exec(&self, args: &[&str]) -> Result<ExitStatus, std::io::Error>
+	{
+		Command::new(JEXEC).arg(self.jid.to_string()).args(args).status()
+	}
+
+
+	/// Restart the whole jail via the jail rc.d script.
+	pub(crate) fn restart(&self) -> Result<(), anyhow::Error>
+	{
+		let cret = Command::new(SERVICE)
+				.args(["jail", "restart", &self.name]).status()?;
+		if !cret.success()
+		{ anyhow::bail!("service jail restart {} failed: {cret:?}", self.name); }
+		Ok(())
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	#[test]
+	fn parse_jls()
+	{
+		let jls = "path jid name\n\
+				/jails/foo 3 foo\n\
+				/jails/bar 12 bar\n\
+				garbage\n";
+		let jails = super::parse_jls(jls);
+		assert_eq!(jails.len(), 2, "two jails");
+		assert_eq!(jails[1].jid, 12);
+		assert_eq!(jails[1].name, "bar");
+		assert_eq!(jails[0].path, std::path::Path::new("/jails/foo"));
+	}
+}
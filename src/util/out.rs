@@ -0,0 +1,133 @@
+//! User-facing output.
+//!
+//! stdout may well be a pipe to something that goes away on us; head(1),
+//! a pager somebody quit out of, a cron mailer that died.  For commands
+//! that just show stuff, that's a fine reason to stop, so they get the
+//! default SIGPIPE handling and go away quietly like any other unix
+//! tool.
+//!
+//! For commands doing real work (install), dying halfway through because
+//! a progress message couldn't be written would be much worse than the
+//! message going nowhere.  So they print via say!() and sayln!() here,
+//! which drop write errors on the floor.
+use std::io::Write;
+
+
+/// Go back to the default SIGPIPE handling (i.e., die), rather than the
+/// ignoring the rust runtime sets up.  Only sensible for commands that
+/// don't do anything but output.
+pub(crate) fn sigpipe_default()
+{
+	// SAFETY: Just resetting a signal disposition to the default,
+	// before we've started any threads.
+	unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL); }
+}
+
+
+/// Write something out, ignoring any errors.
+pub(crate) fn say_to(w: &mut (impl Write + ?Sized), args: std::fmt::Arguments)
+{
+	w.write_fmt(args).ok();
+}
+
+
+/// Write something to stdout, ignoring any errors.  Generally you want
+/// say!() or sayln!() instead.
+pub(crate) fn say_fmt(args: std::fmt::Arguments)
+{
+	#[cfg(test)]
+	if SINK.with(|s| s.borrow_mut().as_mut().map(|w| say_to(w, args)))
+			.is_some() { return; }
+
+	say_to(&mut std::io::stdout(), args)
+}
+
+
+/// Flush stdout, ignoring any errors.
+pub(crate) fn flush()
+{
+	#[cfg(test)]
+	if SINK.with(|s| s.borrow_mut().as_mut().map(|w| w.flush().ok()))
+			.is_some() { return; }
+
+	std::io::stdout().flush().ok();
+}
+
+
+/// print!(), but not caring if it fails
+macro_rules! say {
+	($($arg:tt)*) => {
+		$crate::util::out::say_fmt(format_args!($($arg)*))
+	};
+}
+pub(crate) use say;
+
+/// println!(), but not caring if it fails
+macro_rules! sayln {
+	() => {
+		$crate::util::out::say_fmt(format_args!("\n"))
+	};
+	($($arg:tt)*) => {
+		$crate::util::out::say_fmt(format_args!("{}\n", format_args!($($arg)*)))
+	};
+}
+pub(crate) use sayln;
+
+
+
+// For tests, so we can point output somewhere other than the real
+// stdout.  Per-thread, so it doesn't stomp on other tests.
+#[cfg(test)]
+thread_local! {
+	static SINK: std::cell::RefCell<Option<Box<dyn Write>>>
+			= const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(test)]
+pub(crate) fn set_sink(w: Option<Box<dyn Write>>)
+{
+	SINK.with(|s| *s.borrow_mut() = w);
+}
+
+/// A writer whose other end is already gone, so every write gets EPIPE.
+#[cfg(test)]
+pub(crate) fn closed_pipe() -> std::fs::File
+{
+	use std::os::fd::FromRawFd as _;
+	let mut fds = [0; 2];
+	// SAFETY: pipe() fills in two fresh fds; we close one, and hand
+	// sole ownership of the other to the File.
+	unsafe {
+		assert_eq!(libc::pipe(fds.as_mut_ptr()), 0, "pipe() failed");
+		libc::close(fds[0]);
+		std::fs::File::from_raw_fd(fds[1])
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::io::Write as _;
+
+	#[test]
+	fn closed_pipe()
+	{
+		// Sanity: it really is broken
+		let mut cp = super::closed_pipe();
+		let err = cp.write_all(b"hi\n").expect_err("write should fail");
+		assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+		// But we don't care
+		super::say_to(&mut cp, format_args!("hi {}\n", 1));
+
+		// And through the macros
+		super::set_sink(Some(Box::new(cp)));
+		super::say!("Doing something...  ");
+		super::flush();
+		super::sayln!("Done.");
+		super::sayln!();
+		super::set_sink(None);
+	}
+}
@@ -0,0 +1,75 @@
+//! Serializing hashed collections in sorted order.
+//!
+//! HashMap/HashSet iteration order changes from run to run, so anything
+//! we write out from them would too.  Using these via
+//! `#[serde(serialize_with = ...)]` writes them out sorted instead, so
+//! the same contents always give the same bytes.  Reading back in is
+//! unaffected.
+use std::collections::{HashMap, HashSet};
+
+use serde::Serializer;
+
+
+/// Serialize a HashMap as a map sorted by key.
+pub(crate) fn map<S, K, V>(m: &HashMap<K, V>, s: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+	K: serde::Serialize + Ord,
+	V: serde::Serialize,
+{
+	let sorted: std::collections::BTreeMap<_, _> = m.iter().collect();
+	s.collect_map(sorted)
+}
+
+
+/// Serialize a HashSet as a sorted sequence.
+pub(crate) fn set<S, T>(m: &HashSet<T>, s: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+	T: serde::Serialize + Ord,
+{
+	let mut sorted: Vec<_> = m.iter().collect();
+	sorted.sort_unstable();
+	s.collect_seq(sorted)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::{HashMap, HashSet};
+
+	#[derive(serde::Serialize)]
+	struct Both
+	{
+		#[serde(serialize_with = "super::map")]
+		m: HashMap<String, u32>,
+		#[serde(serialize_with = "super::set")]
+		s: HashSet<String>,
+	}
+
+	#[test]
+	fn sorted()
+	{
+		let keys: Vec<_> = (0..50).map(|i| format!("k{i:02}")).collect();
+		let m = keys.iter().rev().cloned().zip(0..).collect();
+		let s = keys.iter().cloned().collect();
+		let json = serde_json::to_string(&Both { m, s }).unwrap();
+
+		// Both come out in key order, not whatever the hasher felt like.
+		let mstart = json.find("\"m\"").unwrap();
+		let sstart = json.find("\"s\"").unwrap();
+		let mjson = &json[mstart..sstart];
+		let mut last = 0;
+		for k in &keys
+		{
+			let p = mjson.find(&format!("\"{k}\"")).unwrap();
+			assert!(p > last, "{k} out of order in {mjson}");
+			last = p;
+		}
+		let sjson = &json[sstart..];
+		let want: Vec<_> = keys.iter().map(|k| format!("\"{k}\"")).collect();
+		assert_eq!(sjson, format!("\"s\":[{}]}}", want.join(",")));
+	}
+}
@@ -0,0 +1,344 @@
+//! Machine-readable progress of a running command.
+//!
+//! While the long commands run, we keep a little JSON file in the state
+//! dir saying what we're up to, for dashboards and such to watch.  It
+//! gets updated as phases start and progress bars move (at most every
+//! INTERVAL), and goes away when the command's done.
+//!
+//! None of this is allowed to get in the way of the actual work; if the
+//! file can't be written, we say so once and stop trying.
+//!
+//! The writer is per-thread; phases and progress bars all get driven
+//! from the thread running the command (pools hand their results back
+//! to it), and it keeps tests from seeing each other's updates.
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+
+/// Don't write more often than this.
+const INTERVAL: Duration = Duration::from_secs(2);
+
+
+/// What we write out.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Status
+{
+	/// Command that's running (fetch, upgrade, etc)
+	pub(crate) command: String,
+
+	/// Its pid
+	pub(crate) pid: u32,
+
+	/// What it's doing now
+	pub(crate) phase: String,
+
+	/// Items done in this phase, and how many there are, if it's that
+	/// kind of phase.
+	pub(crate) done: u64,
+	pub(crate) total: Option<u64>,
+
+	/// Bytes done in this phase, where we know
+	pub(crate) bytes: Option<u64>,
+
+	/// Bytes downloaded and stored so far this run, over all phases
+	#[serde(default)]
+	pub(crate) run_bytes: crate::util::bytecount::Bytes,
+
+	/// When the command started, and when this was written (unix time)
+	pub(crate) started: i64,
+	pub(crate) updated: i64,
+}
+
+impl Status
+{
+	/// Is the process that wrote this still around?  If not, it crashed
+	/// (or got killed) without cleaning up after itself.
+	pub(crate) fn alive(&self) -> bool
+	{
+		let pid = match libc::pid_t::try_from(self.pid) {
+			Ok(p) if p > 0 => p,
+			_ => return false,
+		};
+
+		// SAFETY: signal 0 just checks, it doesn't send anything.
+		let ret = unsafe { libc::kill(pid, 0) };
+		// EPERM means it's there, just not ours.
+		ret == 0 || std::io::Error::last_os_error().raw_os_error()
+				== Some(libc::EPERM)
+	}
+
+
+	/// Human-ish lines describing it.
+	pub(crate) fn describe(&self) -> Vec<String>
+	{
+		use crate::util::human_bytes;
+
+		let mut ret = vec![format!("{} (pid {}): {}", self.command,
+				self.pid, self.phase)];
+
+		let mut prog = match self.total {
+			Some(t) if t > 0 => {
+				let pct = self.done as f64 * 100.0 / t as f64;
+				format!("{} of {t} ({pct:.1}%)", self.done)
+			},
+			Some(_) => String::new(),
+			None if self.done > 0 => format!("{} done", self.done),
+			None => String::new(),
+		};
+		if let Some(b) = self.bytes
+		{
+			if !prog.is_empty() { prog.push_str(", "); }
+			prog.push_str(&human_bytes(b));
+		}
+		if !prog.is_empty() { ret.push(format!("  {prog}")); }
+		if !self.run_bytes.is_empty()
+		{ ret.push(format!("  so far: {}", self.run_bytes.describe())); }
+
+		let ago = |t: i64| {
+			let secs = (chrono::Utc::now().timestamp() - t).max(0);
+			format!("{secs}s ago")
+		};
+		ret.push(format!("  started {}, updated {}", ago(self.started),
+				ago(self.updated)));
+		ret
+	}
+}
+
+
+/// Where we're writing, and what
+#[derive(Debug)]
+struct Writer
+{
+	path: PathBuf,
+	st: Status,
+	last: Option<Instant>,
+}
+
+impl Writer
+{
+	/// Write it out, via a temp file so nobody sees a partial one.
+	fn write(&mut self) -> Result<(), std::io::Error>
+	{
+		self.st.updated = chrono::Utc::now().timestamp();
+		self.last = Some(Instant::now());
+
+		let tmp = self.path.with_extension("json.tmp");
+		let json = serde_json::to_vec(&self.st)?;
+		let ret = std::fs::write(&tmp, json)
+				.and_then(|_| std::fs::rename(&tmp, &self.path));
+		if ret.is_err() { let _ = std::fs::remove_file(&tmp); }
+		ret
+	}
+}
+
+thread_local! {
+	static WRITER: RefCell<Option<Writer>> = const { RefCell::new(None) };
+}
+
+
+/// Keeps the status file around while it's in scope, and cleans it up
+/// after.
+#[derive(Debug)]
+pub(crate) struct Guard(());
+
+impl Drop for Guard
+{
+	fn drop(&mut self) { finish() }
+}
+
+
+/// Start keeping a status file at path for a command.
+pub(crate) fn start(path: PathBuf, command: &str) -> Guard
+{
+	let now = chrono::Utc::now().timestamp();
+	let st = Status {
+		command: command.to_string(),
+		pid: std::process::id(),
+		phase: "Starting".to_string(),
+		started: now,
+		..Default::default()
+	};
+	WRITER.with_borrow_mut(|w| *w = Some(Writer { path, st, last: None }));
+	update(true, |_| ());
+	Guard(())
+}
+
+
+/// Starting a new phase.
+pub(crate) fn phase(name: &str)
+{
+	update(true, |st| {
+		st.phase = name.to_string();
+		st.done = 0;
+		st.total = None;
+		st.bytes = None;
+	});
+}
+
+/// Got some items done
+pub(crate) fn items(done: u64, total: Option<u64>)
+{
+	update(false, |st| {
+		st.done = done;
+		st.total = total;
+	});
+}
+
+/// Got some bytes done
+pub(crate) fn bytes(n: u64)
+{
+	update(false, |st| *st.bytes.get_or_insert(0) += n);
+}
+
+
+/// The run's byte counts changed
+pub(crate) fn run_bytes(b: crate::util::bytecount::Bytes)
+{
+	update(false, |st| st.run_bytes = b);
+}
+
+
+/// Done; clean up the file.
+fn finish()
+{
+	if let Some(w) = WRITER.take()
+	{ let _ = std::fs::remove_file(&w.path); }
+}
+
+
+/// Tweak the status, and write it out if it's time (or forced).  If we
+/// can't, give up on it for the rest of the run.
+fn update(force: bool, f: impl FnOnce(&mut Status))
+{
+	WRITER.with_borrow_mut(|wl| update_inner(wl, force, f));
+}
+
+fn update_inner(wl: &mut Option<Writer>, force: bool,
+		f: impl FnOnce(&mut Status))
+{
+	let w = match wl.as_mut() {
+		Some(w) => w,
+		None => return,
+	};
+
+	f(&mut w.st);
+	let due = match w.last {
+		Some(l) => l.elapsed() >= INTERVAL,
+		None => true,
+	};
+	if !(force || due) { return; }
+
+	if let Err(e) = w.write()
+	{
+		eprintln!("WARNING: can't write status file {}: {e}; giving up \
+				on it.", w.path.display());
+		let _ = std::fs::remove_file(&w.path);
+		*wl = None;
+	}
+}
+
+
+/// Load up a status file, if there is one.
+pub(crate) fn load(path: &Path) -> Result<Option<Status>, anyhow::Error>
+{
+	let json = match std::fs::read(path) {
+		Ok(j) => j,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+		Err(e) => Err(e)?,
+	};
+	Ok(Some(serde_json::from_slice(&json)?))
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use super::Status;
+
+	#[test]
+	fn lifecycle()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let path = td.path().join("status.json");
+		let get = || super::load(&path).unwrap().unwrap();
+
+		// Nothing yet
+		assert!(super::load(&path).unwrap().is_none());
+
+		let guard = super::start(path.clone(), "fetch");
+		let st = get();
+		assert_eq!((st.command.as_str(), st.phase.as_str()),
+				("fetch", "Starting"));
+		assert_eq!(st.pid, std::process::id());
+		assert!(st.alive());
+
+		// Phases always get written; progress only every so often
+		super::phase("Fetching files");
+		super::items(12, Some(100));
+		super::bytes(4096);
+		let st = get();
+		assert_eq!(st.phase, "Fetching files");
+		assert_eq!((st.done, st.total, st.bytes), (0, None, None));
+
+		// Next phase resets the counts
+		super::phase("Stashing");
+		let st = get();
+		assert_eq!((st.done, st.total, st.bytes), (0, None, None));
+
+		// And cleaned up at the end
+		drop(guard);
+		assert!(!path.exists());
+
+		// Without a writer, updates go nowhere
+		super::phase("Nowhere");
+		assert!(!path.exists());
+
+		// Can't write there; not fatal, we just stop trying
+		let bad = td.path().join("nonexistent/status.json");
+		let guard = super::start(bad.clone(), "fetch");
+		super::phase("Still going");
+		assert!(!bad.exists());
+		drop(guard);
+	}
+
+	#[test]
+	fn stale()
+	{
+		// We're definitely alive; pid 0 and absurd pids aren't.
+		let mut st = Status { pid: std::process::id(), ..Default::default() };
+		assert!(st.alive());
+		st.pid = 0;
+		assert!(!st.alive());
+		st.pid = u32::MAX;
+		assert!(!st.alive());
+	}
+
+	#[test]
+	fn describe()
+	{
+		let now = chrono::Utc::now().timestamp();
+		let mut st = Status { command: "fetch".into(), pid: 42,
+				phase: "Fetching files".into(), done: 12403,
+				total: Some(18234), bytes: None,
+				run_bytes: Default::default(), started: now, updated: now };
+		let lines = st.describe();
+		assert_eq!(lines[0], "fetch (pid 42): Fetching files");
+		assert_eq!(lines[1], "  12403 of 18234 (68.0%)");
+
+		st.bytes = Some(3 * 1024 * 1024);
+		assert_eq!(st.describe()[1], "  12403 of 18234 (68.0%), 3.0M");
+
+		st.run_bytes.downloaded = 2048;
+		assert_eq!(st.describe()[2], "  so far: 2.0K downloaded; 0B into \
+				the files dir (0B stashed, 0B fetched)");
+
+		st.total = None;
+		st.done = 0;
+		st.bytes = None;
+		st.run_bytes = Default::default();
+		assert_eq!(st.describe().len(), 2);
+	}
+}
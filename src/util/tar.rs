@@ -0,0 +1,534 @@
+//! Minimal tar reading (and writing).
+//!
+//! Just enough to walk through the release distribution sets, which are
+//! bsdtar-made pax archives.  We only ever read forward through a
+//! stream (e.g., out of xz -d), one entry at a time, so this never holds
+//! more than a block plus whatever pax headers in memory.
+//!
+//! Writing is even more minimal: plain files with short names, which is
+//! all export-pending needs.
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+
+const BLOCK: u64 = 512;
+
+
+/// What sort of thing an entry is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Kind
+{
+	File,
+	Dir,
+	Symlink(PathBuf),
+	Hardlink(PathBuf),
+
+	/// Devices, fifos, whatever else; we don't care about these.
+	Other(u8),
+}
+
+
+/// An entry header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry
+{
+	pub(crate) path: PathBuf,
+	pub(crate) kind: Kind,
+	pub(crate) mode: u32,
+	pub(crate) uid:  u32,
+	pub(crate) gid:  u32,
+	pub(crate) size: u64,
+
+	/// File flags, as in the pax SCHILY.fflags header (e.g. "schg")
+	pub(crate) fflags: Option<String>,
+}
+
+
+/// Reading through a tar stream.
+#[derive(Debug)]
+pub(crate) struct Reader<R>
+{
+	rdr: R,
+
+	/// Unread data left in the current entry, and the padding after it.
+	left: u64,
+	pad: u64,
+}
+
+
+impl<R: Read> Reader<R>
+{
+	pub(crate) fn new(rdr: R) -> Self
+	{
+		Self { rdr, left: 0, pad: 0 }
+	}
+
+
+	/// Move on to the next entry, skipping whatever's left of the
+	/// current one.  None at the end of the archive.
+	pub(crate) fn next(&mut self) -> Result<Option<Entry>, io::Error>
+	{
+		self.skip()?;
+
+		// Extended headers that apply to the next real entry
+		let mut pax: HashMap<String, String> = HashMap::new();
+		let mut longname = None;
+		let mut longlink = None;
+
+		loop
+		{
+			let mut hdr = [0u8; BLOCK as usize];
+			if !self.read_block(&mut hdr)? { return Ok(None); }
+			if hdr.iter().all(|b| *b == 0) { return Ok(None); }
+			check_sum(&hdr)?;
+
+			let size = num(&hdr[124..136])?;
+			let tflag = hdr[156];
+
+			// The special headers just modify the following entry
+			match tflag
+			{
+				b'x' => {
+					let data = self.read_data(size)?;
+					pax.extend(parse_pax(&data)?);
+					continue;
+				},
+				b'L' => {
+					longname = Some(cstr(&self.read_data(size)?).to_vec());
+					continue;
+				},
+				b'K' => {
+					longlink = Some(cstr(&self.read_data(size)?).to_vec());
+					continue;
+				},
+				b'g' => {
+					self.read_data(size)?;
+					continue;
+				},
+				_ => (),
+			}
+
+			// A real entry.  Figure the name, starting with the ustar
+			// bits, and overriding with anything better.
+			let mut name = cstr(&hdr[0..100]).to_vec();
+			let prefix = cstr(&hdr[345..500]);
+			if &hdr[257..263] == b"ustar\0" && !prefix.is_empty()
+			{
+				let mut p = prefix.to_vec();
+				p.push(b'/');
+				p.extend(name);
+				name = p;
+			}
+			let mut link = cstr(&hdr[157..257]).to_vec();
+			if let Some(n) = longname.take() { name = n; }
+			if let Some(l) = longlink.take() { link = l; }
+			if let Some(n) = pax.remove("path") { name = n.into_bytes(); }
+			if let Some(l) = pax.remove("linkpath") { link = l.into_bytes(); }
+
+			let pnum = |k: &str, dfl: u64| -> Result<u64, io::Error> {
+				match pax.get(k) {
+					Some(v) => v.parse().map_err(|e| bad(format!("pax {k}: {e}"))),
+					None => Ok(dfl),
+				}
+			};
+			let size = pnum("size", size)?;
+			let uid  = pnum("uid", num(&hdr[108..116])?)? as u32;
+			let gid  = pnum("gid", num(&hdr[116..124])?)? as u32;
+			let mode = num(&hdr[100..108])? as u32 & 0o7777;
+			let fflags = pax.remove("SCHILY.fflags");
+
+			let kind = match tflag {
+				b'0' | b'\0' | b'7' => Kind::File,
+				b'5' => Kind::Dir,
+				b'2' => Kind::Symlink(pathbuf(link)),
+				b'1' => Kind::Hardlink(pathbuf(link)),
+				t    => Kind::Other(t),
+			};
+
+			self.left = size;
+			self.pad = padding(size);
+			let path = pathbuf(name);
+			return Ok(Some(Entry { path, kind, mode, uid, gid, size, fflags }));
+		}
+	}
+
+
+	/// Copy out the current entry's data.
+	pub(crate) fn data(&mut self, w: &mut (impl Write + ?Sized))
+			-> Result<u64, io::Error>
+	{
+		let n = io::copy(&mut (&mut self.rdr).take(self.left), w)?;
+		if n != self.left
+		{ return Err(io::Error::from(io::ErrorKind::UnexpectedEof)); }
+		self.left = 0;
+		Ok(n)
+	}
+
+
+	/// Skip over whatever's left of the current entry.
+	fn skip(&mut self) -> Result<(), io::Error>
+	{
+		let want = self.left + self.pad;
+		let n = io::copy(&mut (&mut self.rdr).take(want), &mut io::sink())?;
+		if n != want
+		{ return Err(io::Error::from(io::ErrorKind::UnexpectedEof)); }
+		(self.left, self.pad) = (0, 0);
+		Ok(())
+	}
+
+
+	/// Read a header block.  false if we hit a clean EOF instead.
+	fn read_block(&mut self, buf: &mut [u8]) -> Result<bool, io::Error>
+	{
+		let mut got = 0;
+		while got < buf.len()
+		{
+			match self.rdr.read(&mut buf[got..]) {
+				Ok(0) if got == 0 => return Ok(false),
+				Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+				Ok(n) => got += n,
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+				Err(e) => return Err(e),
+			}
+		}
+		Ok(true)
+	}
+
+
+	/// Read in a (small) special entry's data, and its padding.
+	fn read_data(&mut self, size: u64) -> Result<Vec<u8>, io::Error>
+	{
+		// These are just names and such; anything huge is garbage.
+		if size > 1024 * 1024
+		{ return Err(bad(format!("{size} byte extended header?"))); }
+
+		let mut ret = vec![0u8; size as usize];
+		self.rdr.read_exact(&mut ret)?;
+		(self.left, self.pad) = (0, padding(size));
+		self.skip()?;
+		Ok(ret)
+	}
+}
+
+
+/// Writing out a tar stream.
+#[derive(Debug)]
+pub(crate) struct Writer<W>
+{
+	wtr: W,
+}
+
+
+impl<W: Write> Writer<W>
+{
+	pub(crate) fn new(wtr: W) -> Self
+	{
+		Self { wtr }
+	}
+
+
+	/// Add a plain file, with `size` bytes of contents from a reader.
+	/// Names are limited to what fits in the basic header.
+	pub(crate) fn file(&mut self, name: &str, mode: u32, size: u64,
+			data: &mut impl Read) -> Result<(), io::Error>
+	{
+		if name.len() >= 100
+		{ return Err(bad(format!("{name}: name too long"))); }
+
+		let mut h = [0u8; BLOCK as usize];
+		let mut put = |off: usize, s: &[u8]| h[off..off + s.len()]
+				.copy_from_slice(s);
+		put(0, name.as_bytes());
+		put(100, format!("{:07o}\0", mode & 0o7777).as_bytes());
+		put(108, b"0000000\0");
+		put(116, b"0000000\0");
+		put(124, format!("{size:011o}\0").as_bytes());
+		put(136, b"00000000000\0");
+		put(148, b"        ");
+		put(156, b"0");
+		put(257, b"ustar\0");
+		put(263, b"00");
+
+		let sum: u32 = h.iter().map(|b| *b as u32).sum();
+		h[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+		self.wtr.write_all(&h)?;
+
+		let n = io::copy(&mut data.take(size), &mut self.wtr)?;
+		if n != size
+		{ return Err(io::Error::from(io::ErrorKind::UnexpectedEof)); }
+		let pad = [0u8; BLOCK as usize];
+		self.wtr.write_all(&pad[..padding(size) as usize])?;
+		Ok(())
+	}
+
+
+	/// Finish off the archive, and hand back the writer.
+	pub(crate) fn finish(mut self) -> Result<W, io::Error>
+	{
+		self.wtr.write_all(&[0u8; 2 * BLOCK as usize])?;
+		self.wtr.flush()?;
+		Ok(self.wtr)
+	}
+}
+
+
+
+fn bad(s: String) -> io::Error
+{
+	io::Error::new(io::ErrorKind::InvalidData, s)
+}
+
+fn padding(size: u64) -> u64
+{
+	(BLOCK - size % BLOCK) % BLOCK
+}
+
+fn pathbuf(b: Vec<u8>) -> PathBuf
+{
+	use std::os::unix::ffi::OsStringExt as _;
+	std::ffi::OsString::from_vec(b).into()
+}
+
+/// A NUL-terminated (or not, if it fills the field) string field
+fn cstr(f: &[u8]) -> &[u8]
+{
+	match f.iter().position(|b| *b == 0) {
+		Some(n) => &f[..n],
+		None => f,
+	}
+}
+
+
+/// A numeric field; octal, or big-endian binary if the high bit is set
+/// (GNU-style, for things too big for the octal).
+fn num(f: &[u8]) -> Result<u64, io::Error>
+{
+	if f[0] & 0x80 != 0
+	{
+		let mut ret = (f[0] & 0x7f) as u64;
+		for b in &f[1..] { ret = (ret << 8) | *b as u64; }
+		return Ok(ret);
+	}
+
+	let s = std::str::from_utf8(cstr(f)).map_err(|e| bad(e.to_string()))?;
+	let s = s.trim_matches(|c| c == ' ' || c == '\0');
+	if s.is_empty() { return Ok(0); }
+	u64::from_str_radix(s, 8).map_err(|e| bad(format!("bad number '{s}': {e}")))
+}
+
+
+/// Make sure the header checksum is right, so we don't go wandering off
+/// into garbage.
+fn check_sum(hdr: &[u8]) -> Result<(), io::Error>
+{
+	let want = num(&hdr[148..156])?;
+	let got: u64 = hdr.iter().enumerate()
+			.map(|(i, b)| match i { 148..=155 => b' ' as u64, _ => *b as u64 })
+			.sum();
+	match got == want {
+		true  => Ok(()),
+		false => Err(bad(format!("header checksum {got} expected {want}"))),
+	}
+}
+
+
+/// Parse out pax extended header records; "<len> <key>=<value>\n".
+fn parse_pax(mut data: &[u8]) -> Result<Vec<(String, String)>, io::Error>
+{
+	let mut ret = Vec::new();
+	while !data.is_empty()
+	{
+		let sp = data.iter().position(|b| *b == b' ')
+				.ok_or_else(|| bad("pax record without length".to_string()))?;
+		let len: usize = std::str::from_utf8(&data[..sp]).ok()
+				.and_then(|l| l.parse().ok())
+				.filter(|l| *l > sp && *l <= data.len())
+				.ok_or_else(|| bad("bad pax record length".to_string()))?;
+		let rec = &data[sp + 1..len];
+		let rec = rec.strip_suffix(b"\n").unwrap_or(rec);
+		let rec = String::from_utf8_lossy(rec);
+		if let Some((k, v)) = rec.split_once('=')
+		{ ret.push((k.to_string(), v.to_string())); }
+		data = &data[len..];
+	}
+	Ok(ret)
+}
+
+
+
+/// Building up tars, for tests here and elsewhere.
+#[cfg(test)]
+pub(crate) mod tests
+{
+	use super::{Kind, Reader, BLOCK};
+
+	/// Tack a header block onto a tar.
+	fn header(tar: &mut Vec<u8>, name: &str, tflag: u8, mode: u32, size: u64,
+			link: &str)
+	{
+		let mut h = [0u8; BLOCK as usize];
+		let mut put = |off: usize, s: &[u8]| h[off..off + s.len()]
+				.copy_from_slice(s);
+		put(0, name.as_bytes());
+		put(100, format!("{mode:07o}\0").as_bytes());
+		put(108, b"0000000\0");
+		put(116, b"0000005\0");
+		put(124, format!("{size:011o}\0").as_bytes());
+		put(136, b"00000000000\0");
+		put(148, b"        ");
+		put(156, &[tflag]);
+		put(157, link.as_bytes());
+		put(257, b"ustar\0");
+		put(263, b"00");
+
+		let sum: u32 = h.iter().map(|b| *b as u32).sum();
+		h[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+		tar.extend(h);
+	}
+
+	fn data(tar: &mut Vec<u8>, d: &[u8])
+	{
+		tar.extend(d);
+		tar.extend(vec![0u8; super::padding(d.len() as u64) as usize]);
+	}
+
+	/// An entry for a test tar
+	pub(crate) enum T<'a>
+	{
+		Dir(&'a str, u32),
+		File(&'a str, u32, &'a str),
+		Sym(&'a str, &'a str),
+		Hard(&'a str, &'a str),
+
+		/// pax headers for the following entry
+		Pax(&'a [(&'a str, &'a str)]),
+	}
+
+	/// Build a tar
+	pub(crate) fn mk_tar(ents: &[T]) -> Vec<u8>
+	{
+		let mut tar = Vec::new();
+		for e in ents
+		{
+			match e {
+				T::Dir(n, m) => header(&mut tar, n, b'5', *m, 0, ""),
+				T::File(n, m, c) => {
+					header(&mut tar, n, b'0', *m, c.len() as u64, "");
+					data(&mut tar, c.as_bytes());
+				},
+				T::Sym(n, l) => header(&mut tar, n, b'2', 0o755, 0, l),
+				T::Hard(n, l) => header(&mut tar, n, b'1', 0o555, 0, l),
+				T::Pax(recs) => {
+					let mut d = Vec::new();
+					for (k, v) in *recs
+					{
+						// Length includes itself, so find the fixed point
+						let rest = format!(" {k}={v}\n").len();
+						let mut len = rest + 1;
+						while format!("{len}").len() + rest != len { len += 1; }
+						d.extend(format!("{len} {k}={v}\n").into_bytes());
+					}
+					header(&mut tar, "PaxHeader", b'x', 0o644, d.len() as u64,
+							"");
+					data(&mut tar, &d);
+				},
+			}
+		}
+		tar.extend([0u8; 2 * BLOCK as usize]);
+		tar
+	}
+
+	#[test]
+	fn read()
+	{
+		let long = format!("./usr/share/{}", "x".repeat(150));
+		let tar = mk_tar(&[
+			T::Dir("./bin/", 0o755),
+			T::Pax(&[("SCHILY.fflags", "schg"), ("uid", "12345")]),
+			T::File("./bin/sh", 0o555, "echo hi\n"),
+			T::Hard("./bin/rsh", "./bin/sh"),
+			T::Sym("./bin/ksh", "sh"),
+			T::Pax(&[("path", long.as_str())]),
+			T::File("./bin/short", 0o444, &"y".repeat(1000)),
+			T::File("./bin/empty", 0o444, ""),
+		]);
+
+		let mut rdr = Reader::new(&tar[..]);
+		let mut ents = Vec::new();
+		while let Some(e) = rdr.next().unwrap()
+		{
+			// Pull out the content of one, skip the rest
+			let mut d = Vec::new();
+			if e.path.to_str() == Some("./bin/sh")
+			{ rdr.data(&mut d).unwrap(); }
+			ents.push((e, d));
+		}
+
+		let got: Vec<_> = ents.iter().map(|(e, _)| (e.path.to_str().unwrap(),
+				&e.kind)).collect();
+		assert_eq!(got, [
+			("./bin/", &Kind::Dir),
+			("./bin/sh", &Kind::File),
+			("./bin/rsh", &Kind::Hardlink("./bin/sh".into())),
+			("./bin/ksh", &Kind::Symlink("sh".into())),
+			(long.as_str(), &Kind::File),
+			("./bin/empty", &Kind::File),
+		]);
+
+		let (sh, shd) = &ents[1];
+		assert_eq!((sh.mode, sh.uid, sh.gid, sh.size), (0o555, 12345, 5, 8));
+		assert_eq!(sh.fflags.as_deref(), Some("schg"));
+		assert_eq!(shd, b"echo hi\n");
+
+		// pax headers only apply to the one entry
+		assert_eq!((ents[2].0.uid, ents[2].0.fflags.as_deref()), (0, None));
+		assert_eq!(ents[4].0.size, 1000);
+	}
+
+	#[test]
+	fn write()
+	{
+		let mut tw = super::Writer::new(Vec::new());
+		tw.file("a", 0o644, 5, &mut &b"hello"[..]).unwrap();
+		tw.file("files/b.gz", 0o600, 0, &mut &b""[..]).unwrap();
+		tw.file("c", 0o644, 1000, &mut "z".repeat(1000).as_bytes()).unwrap();
+		assert!(tw.file(&"x".repeat(100), 0o644, 0, &mut &b""[..]).is_err());
+		let tar = tw.finish().unwrap();
+		assert_eq!(tar.len() % BLOCK as usize, 0);
+
+		let mut rdr = Reader::new(&tar[..]);
+		let mut got = Vec::new();
+		while let Some(e) = rdr.next().unwrap()
+		{
+			let mut d = Vec::new();
+			rdr.data(&mut d).unwrap();
+			assert_eq!(e.kind, Kind::File);
+			got.push((e.path.to_str().unwrap().to_string(), e.mode, d));
+		}
+		assert_eq!(got, [
+			("a".to_string(), 0o644, b"hello".to_vec()),
+			("files/b.gz".to_string(), 0o600, vec![]),
+			("c".to_string(), 0o644, "z".repeat(1000).into_bytes()),
+		]);
+
+		// Short data is an error, not a broken archive
+		let mut tw = super::Writer::new(Vec::new());
+		assert!(tw.file("a", 0o644, 10, &mut &b"hello"[..]).is_err());
+	}
+
+	#[test]
+	fn corrupt()
+	{
+		let mut tar = mk_tar(&[T::File("./bin/sh", 0o555, "echo hi\n")]);
+		tar[10] ^= 0x20;
+		let err = Reader::new(&tar[..]).next().unwrap_err();
+		assert!(err.to_string().contains("checksum"), "{err}");
+
+		// Cut off mid-data
+		let tar = mk_tar(&[T::File("./bin/sh", 0o555, &"z".repeat(2000))]);
+		let mut rdr = Reader::new(&tar[..1024]);
+		rdr.next().unwrap().unwrap();
+		assert!(rdr.data(&mut Vec::new()).is_err());
+	}
+}
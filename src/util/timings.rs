@@ -0,0 +1,161 @@
+//! Timing phases of a command.
+//!
+//! Long-running commands bracket their major steps with phase() calls,
+//! and at the end, report() shows where the time went.  Timing is always
+//! on (it's just grabbing an Instant now and then), but the table only
+//! gets shown if it's been a long run, or somebody asked for it.
+use std::time::{Duration, Instant};
+
+
+/// Runs at least this long always show the timings.
+const REPORT_THRESHOLD: Duration = Duration::from_secs(5 * 60);
+
+
+/// Accumulated phase timings.
+#[derive(Debug)]
+pub(crate) struct Timings
+{
+	/// Finished phases, in the order they first started.  A phase that
+	/// gets started again accumulates into its existing entry.
+	phases: Vec<(String, Duration)>,
+
+	/// The one we're in now, if any.
+	cur: Option<(String, Instant)>,
+}
+
+
+impl Timings
+{
+	pub(crate) fn new() -> Self
+	{
+		Self { phases: Vec::new(), cur: None }
+	}
+
+
+	/// Start timing a new phase, ending whatever phase we were in.
+	/// This is also what the status file shows as the phase.
+	pub(crate) fn phase(&mut self, name: impl Into<String>)
+	{
+		self.end();
+		let name = name.into();
+		crate::util::status::phase(&name);
+		self.cur = Some((name, Instant::now()));
+	}
+
+
+	/// End the current phase, without starting a new one.
+	pub(crate) fn end(&mut self)
+	{
+		if let Some((name, start)) = self.cur.take()
+		{ self.add(name, start.elapsed()); }
+	}
+
+
+	/// Add some time to a phase
+	fn add(&mut self, name: String, dur: Duration)
+	{
+		match self.phases.iter_mut().find(|(n, _)| *n == name) {
+			Some((_, d)) => *d += dur,
+			None => self.phases.push((name, dur)),
+		}
+	}
+
+
+	/// Total time of all the phases.
+	pub(crate) fn total(&self) -> Duration
+	{
+		self.phases.iter().map(|(_, d)| *d).sum()
+	}
+
+
+	/// The summary table.
+	pub(crate) fn table(&self) -> String
+	{
+		let total = ("Total".to_string(), self.total());
+		let width = self.phases.iter().chain([&total])
+				.map(|(n, _)| n.len()).max().unwrap_or(0) + 1;
+
+		let mut ret = String::from("Timings:\n");
+		for (n, d) in self.phases.iter().chain([&total])
+		{
+			let n = format!("{n}:");
+			ret.push_str(&format!("  {n:width$} {:>9}\n", fmt_dur(*d)));
+		}
+		ret
+	}
+
+
+	/// Wrap up, and show the table if we ran long enough, or were asked
+	/// to.
+	pub(crate) fn report(&mut self, force: bool)
+	{
+		self.end();
+		if force || self.total() >= REPORT_THRESHOLD
+		{ crate::util::out::sayln!("\n{}", self.table()); }
+	}
+}
+
+
+/// Show a Duration in a human-friendly way.
+pub(crate) fn fmt_dur(d: Duration) -> String
+{
+	let secs = d.as_secs();
+	let (h, m, s) = (secs / 3600, (secs / 60) % 60, secs % 60);
+	match (h, m) {
+		(0, 0) => format!("{:.1}s", d.as_secs_f64()),
+		(0, _) => format!("{m}m{s:02}s"),
+		_      => format!("{h}h{m:02}m{s:02}s"),
+	}
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::time::Duration;
+	use super::{Timings, fmt_dur};
+
+	#[test]
+	fn durations()
+	{
+		assert_eq!(fmt_dur(Duration::from_millis(420)), "0.4s");
+		assert_eq!(fmt_dur(Duration::from_secs(59)), "59.0s");
+		assert_eq!(fmt_dur(Duration::from_secs(6 * 60 + 12)), "6m12s");
+		assert_eq!(fmt_dur(Duration::from_secs(3600 + 2 * 60 + 3)),
+				"1h02m03s");
+	}
+
+	#[test]
+	fn accumulate()
+	{
+		let mut tm = Timings::new();
+		tm.add("Scanning".to_string(), Duration::from_secs(372));
+		tm.add("Fetching files".to_string(), Duration::from_secs(1323));
+		tm.add("Scanning".to_string(), Duration::from_secs(8));
+		assert_eq!(tm.total(), Duration::from_secs(1703));
+
+		let expect = "\
+Timings:
+  Scanning:           6m20s
+  Fetching files:    22m03s
+  Total:             28m23s
+";
+		assert_eq!(tm.table(), expect);
+	}
+
+	#[test]
+	fn phases()
+	{
+		let mut tm = Timings::new();
+		tm.phase("one");
+		tm.phase("two");
+		tm.phase("one");
+		tm.end();
+		tm.end();
+		let names: Vec<_> = tm.phases.iter().map(|(n, _)| n.as_str())
+				.collect();
+		assert_eq!(names, ["one", "two"]);
+		assert!(tm.cur.is_none());
+	}
+}
@@ -0,0 +1,400 @@
+//! Extended attributes and ACLs.
+//!
+//! Our metadata only knows about owner/group/mode/flags, so anything
+//! else hung on a file (an ACL somebody added, extattrs some tool uses)
+//! would silently vanish when install replaces it with a new file.  This
+//! is for finding those, and copying them over to the replacement.
+use std::ffi::{CString, c_int, c_void};
+use std::io;
+use std::path::Path;
+
+
+/*
+ * libc has the extattr bits, but not the acl(3) ones.
+ */
+#[allow(non_camel_case_types)]
+type acl_t = *mut c_void;
+
+const ACL_TYPE_ACCESS: c_int = 0x2;
+const ACL_TYPE_NFS4: c_int = 0x4;
+
+extern "C" {
+	fn acl_get_link_np(path: *const libc::c_char, atype: c_int) -> acl_t;
+	fn acl_set_link_np(path: *const libc::c_char, atype: c_int, acl: acl_t)
+			-> c_int;
+	fn acl_is_trivial_np(acl: acl_t, trivialp: *mut c_int) -> c_int;
+	fn acl_free(obj: *mut c_void) -> c_int;
+}
+
+
+/// An ACL we got off a file, which we free when done with.
+#[derive(Debug)]
+struct Acl
+{
+	acl: acl_t,
+	atype: c_int,
+}
+
+impl Drop for Acl
+{
+	fn drop(&mut self)
+	{
+		// SAFETY: we got it from acl_get_link_np(), and nobody else has
+		// it.
+		unsafe { acl_free(self.acl); }
+	}
+}
+
+
+/// Extended attribute namespaces we look at, and their names.
+const NAMESPACES: [(c_int, &str); 2] = [
+	(libc::EXTATTR_NAMESPACE_USER, "user"),
+	(libc::EXTATTR_NAMESPACE_SYSTEM, "system"),
+];
+
+/// UFS keeps ACLs in system extattrs; those get handled as ACLs, not
+/// copied as raw attrs.
+const ACL_ATTRS: [&str; 3] = ["posix1e.acl_access", "posix1e.acl_default",
+		"nfs4.acl"];
+
+
+/// The stuff on a file we don't otherwise track.
+#[derive(Debug, Default)]
+pub(crate) struct Extras
+{
+	/// Extended attributes; (namespace, name, value)
+	attrs: Vec<(c_int, CString, Vec<u8>)>,
+
+	/// A non-trivial ACL
+	acl: Option<Acl>,
+}
+
+impl Extras
+{
+	/// Is there anything here?
+	pub(crate) fn is_empty(&self) -> bool
+	{ self.attrs.is_empty() && self.acl.is_none() }
+
+
+	/// Short description of what's here, e.g. "ACL, 2 extattrs"
+	pub(crate) fn describe(&self) -> String
+	{
+		let mut ret = Vec::new();
+		if self.acl.is_some() { ret.push("ACL".to_string()); }
+		match self.attrs.len() {
+			0 => (),
+			1 => ret.push("1 extattr".to_string()),
+			n => ret.push(format!("{n} extattrs")),
+		}
+		ret.join(", ")
+	}
+
+
+	/// Put all this onto another file.
+	pub(crate) fn apply(&self, path: &Path) -> Result<(), io::Error>
+	{
+		let p = cpath(path)?;
+
+		if let Some(acl) = &self.acl
+		{
+			// SAFETY: valid C string and acl_t.
+			let ret = unsafe { acl_set_link_np(p.as_ptr(), acl.atype,
+					acl.acl) };
+			if ret != 0 { return Err(io::Error::last_os_error()); }
+		}
+
+		for (ns, name, val) in &self.attrs
+		{
+			// SAFETY: valid C strings, and the buffer is what we say it
+			// is.
+			let ret = unsafe { libc::extattr_set_link(p.as_ptr(), *ns,
+					name.as_ptr(), val.as_ptr() as *const c_void,
+					val.len()) };
+			if ret < 0 { return Err(io::Error::last_os_error()); }
+		}
+
+		Ok(())
+	}
+}
+
+
+/// One particular extattr, like `user.managed_by`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AttrName
+{
+	ns: c_int,
+	name: CString,
+}
+
+impl AttrName
+{
+	/// Parse a namespace.name string.  None if it's not one.
+	pub(crate) fn parse(s: &str) -> Option<Self>
+	{
+		let (nsstr, name) = s.split_once('.')?;
+		let ns = NAMESPACES.iter().find(|(_, n)| *n == nsstr)?.0;
+		if name.is_empty() { return None; }
+		let name = CString::new(name).ok()?;
+		Some(Self { ns, name })
+	}
+
+	/// Is it on this path?  Anything going wrong asking (no such
+	/// file, no extattr support, not allowed) counts as no.  This is
+	/// just the one syscall, so it's cheap enough to do while scanning.
+	pub(crate) fn on(&self, path: &Path) -> bool
+	{
+		let Ok(p) = cpath(path) else { return false };
+		// SAFETY: NULL buffer just asks for the size.
+		let sz = unsafe { libc::extattr_get_link(p.as_ptr(), self.ns,
+				self.name.as_ptr(), std::ptr::null_mut(), 0) };
+		sz >= 0
+	}
+}
+
+
+/// Find any extattrs or non-trivial ACL on a path.
+///
+/// A nonexistent file has nothing on it, as does one on a filesystem
+/// that doesn't do these things.  Extattr namespaces we can't read (the
+/// system one, when we're not root) are skipped.
+pub(crate) fn get(path: &Path) -> Result<Extras, io::Error>
+{
+	let p = cpath(path)?;
+	let mut ret = Extras::default();
+
+	ret.acl = match get_acl(&p) {
+		Err(e) if e.raw_os_error() == Some(libc::ENOENT) => return Ok(ret),
+		r => r?,
+	};
+
+	for (ns, nsname) in NAMESPACES
+	{
+		let names = match list_attrs(&p, ns) {
+			Ok(n) => n,
+			Err(e) if unsupported(&e) => continue,
+			Err(e) => return Err(e),
+		};
+		for name in names
+		{
+			if ns == libc::EXTATTR_NAMESPACE_SYSTEM
+					&& ACL_ATTRS.iter().any(|a| a.as_bytes() == name.as_bytes())
+			{ continue; }
+
+			let val = get_attr(&p, ns, &name).map_err(|e| {
+				let n = name.to_string_lossy();
+				io::Error::new(e.kind(), format!("{nsname}.{n}: {e}"))
+			})?;
+			ret.attrs.push((ns, name, val));
+		}
+	}
+
+	Ok(ret)
+}
+
+
+/// Errors that mean "can't do that here", rather than something going
+/// wrong.
+fn unsupported(e: &io::Error) -> bool
+{
+	use libc::{EOPNOTSUPP, EINVAL, EPERM, EACCES};
+	matches!(e.raw_os_error(), Some(EOPNOTSUPP | EINVAL | EPERM | EACCES))
+}
+
+
+fn cpath(path: &Path) -> Result<CString, io::Error>
+{
+	CString::new(path.as_os_str().as_encoded_bytes())
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+
+/// Get the ACL, if it's something more than the mode bits.  We try
+/// NFSv4 first, then POSIX.1e; a filesystem does one or the other (or
+/// neither).
+fn get_acl(p: &CString) -> Result<Option<Acl>, io::Error>
+{
+	for atype in [ACL_TYPE_NFS4, ACL_TYPE_ACCESS]
+	{
+		// SAFETY: valid C string.
+		let acl = unsafe { acl_get_link_np(p.as_ptr(), atype) };
+		if acl.is_null()
+		{
+			let e = io::Error::last_os_error();
+			if unsupported(&e) { continue; }
+			return Err(e);
+		}
+		let acl = Acl { acl, atype };
+
+		let mut trivial: c_int = 0;
+		// SAFETY: valid acl_t, and somewhere to put the answer.
+		let ret = unsafe { acl_is_trivial_np(acl.acl, &mut trivial) };
+		if ret != 0 { return Err(io::Error::last_os_error()); }
+
+		return Ok(match trivial {
+			0 => Some(acl),
+			_ => None,
+		});
+	}
+
+	Ok(None)
+}
+
+
+/// List the names of the extattrs in a namespace.
+fn list_attrs(p: &CString, ns: c_int) -> Result<Vec<CString>, io::Error>
+{
+	// SAFETY: NULL buffer just asks for the size.
+	let sz = unsafe { libc::extattr_list_link(p.as_ptr(), ns,
+			std::ptr::null_mut(), 0) };
+	if sz < 0 { return Err(io::Error::last_os_error()); }
+	if sz == 0 { return Ok(Vec::new()); }
+
+	let mut buf = vec![0u8; sz as usize];
+	// SAFETY: buffer's as big as we say.
+	let sz = unsafe { libc::extattr_list_link(p.as_ptr(), ns,
+			buf.as_mut_ptr() as *mut c_void, buf.len()) };
+	if sz < 0 { return Err(io::Error::last_os_error()); }
+	buf.truncate(sz as usize);
+
+	Ok(parse_list(&buf))
+}
+
+
+/// extattr_list_*(2) gives us a series of length-byte-prefixed names.
+fn parse_list(mut buf: &[u8]) -> Vec<CString>
+{
+	let mut ret = Vec::new();
+	while let Some((&len, rest)) = buf.split_first()
+	{
+		let len = (len as usize).min(rest.len());
+		let (name, rest) = rest.split_at(len);
+		if let Ok(n) = CString::new(name) { ret.push(n); }
+		buf = rest;
+	}
+	ret
+}
+
+
+/// Get the value of one extattr.
+fn get_attr(p: &CString, ns: c_int, name: &CString)
+		-> Result<Vec<u8>, io::Error>
+{
+	// SAFETY: NULL buffer just asks for the size.
+	let sz = unsafe { libc::extattr_get_link(p.as_ptr(), ns, name.as_ptr(),
+			std::ptr::null_mut(), 0) };
+	if sz < 0 { return Err(io::Error::last_os_error()); }
+
+	let mut buf = vec![0u8; sz as usize];
+	// SAFETY: buffer's as big as we say.
+	let sz = unsafe { libc::extattr_get_link(p.as_ptr(), ns, name.as_ptr(),
+			buf.as_mut_ptr() as *mut c_void, buf.len()) };
+	if sz < 0 { return Err(io::Error::last_os_error()); }
+	buf.truncate(sz as usize);
+
+	Ok(buf)
+}
+
+
+
+#[cfg(test)]
+mod tests
+{
+	use std::ffi::CString;
+
+	/// Try setting a user extattr; false if the filesystem won't.
+	fn set_user(path: &std::path::Path, name: &str, val: &[u8]) -> bool
+	{
+		let p = super::cpath(path).unwrap();
+		let n = CString::new(name).unwrap();
+		let ret = unsafe { libc::extattr_set_link(p.as_ptr(),
+				libc::EXTATTR_NAMESPACE_USER, n.as_ptr(),
+				val.as_ptr() as *const libc::c_void, val.len()) };
+		ret >= 0
+	}
+
+	#[test]
+	fn parse_list()
+	{
+		let got = super::parse_list(b"\x03foo\x06barbaz\x00\x01q");
+		let got: Vec<_> = got.iter().map(|n| n.to_str().unwrap()).collect();
+		assert_eq!(got, ["foo", "barbaz", "", "q"]);
+
+		// Truncated; take what's there
+		let got = super::parse_list(b"\x03foo\x09ba");
+		assert_eq!(got.len(), 2);
+	}
+
+	#[test]
+	fn plain()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let f = td.path().join("plain");
+		std::fs::write(&f, "hi\n").unwrap();
+
+		// A freshly made file has nothing interesting
+		let x = super::get(&f).unwrap();
+		assert!(x.is_empty(), "{x:?}");
+		assert_eq!(x.describe(), "");
+
+		// And a missing one has even less
+		let x = super::get(&td.path().join("nonexistent")).unwrap();
+		assert!(x.is_empty(), "{x:?}");
+	}
+
+	#[test]
+	fn attr_name()
+	{
+		use super::AttrName;
+
+		assert!(AttrName::parse("user.managed_by").is_some());
+		assert!(AttrName::parse("system.thing").is_some());
+		assert!(AttrName::parse("managed_by").is_none());
+		assert!(AttrName::parse("user.").is_none());
+		assert!(AttrName::parse("bogus.managed_by").is_none());
+
+		let td = tempfile::tempdir().unwrap();
+		let f = td.path().join("f");
+		std::fs::write(&f, "hi\n").unwrap();
+		let an = AttrName::parse("user.rdtest.managed").unwrap();
+		assert!(!an.on(&f));
+		assert!(!an.on(&td.path().join("nonexistent")));
+
+		if !set_user(&f, "rdtest.managed", b"ansible")
+		{
+			eprintln!("No extattr support on tempdir filesystem, skipping");
+			return;
+		}
+		assert!(an.on(&f));
+		assert!(!AttrName::parse("user.rdtest.other").unwrap().on(&f));
+	}
+
+	#[test]
+	fn copy_attrs()
+	{
+		let td = tempfile::tempdir().unwrap();
+		let src = td.path().join("src");
+		let dst = td.path().join("dst");
+		std::fs::write(&src, "old\n").unwrap();
+		std::fs::write(&dst, "new\n").unwrap();
+
+		if !set_user(&src, "rdtest.one", b"1")
+		{
+			eprintln!("No extattr support on tempdir filesystem, skipping");
+			return;
+		}
+		assert!(set_user(&src, "rdtest.two", b"second"));
+
+		let x = super::get(&src).unwrap();
+		assert!(!x.is_empty());
+		assert_eq!(x.describe(), "2 extattrs");
+
+		x.apply(&dst).unwrap();
+		let y = super::get(&dst).unwrap();
+		let mut got: Vec<_> = y.attrs.iter()
+				.map(|(_, n, v)| (n.to_str().unwrap(), v.as_slice()))
+				.collect();
+		got.sort();
+		assert_eq!(got, [("rdtest.one", &b"1"[..]),
+				("rdtest.two", &b"second"[..])]);
+	}
+}